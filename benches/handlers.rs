@@ -0,0 +1,259 @@
+//! Handler throughput and reservation lookup latency.
+//!
+//! Every iteration runs one message per synthetic client, so the reported
+//! throughput is messages per second across the whole population. The
+//! population size defaults to 10,000 and can be changed with
+//! `SHADOWDHCP_BENCH_CLIENTS`, e.g. to see how lookups behave at a million
+//! reservations:
+//!
+//! ```sh
+//! SHADOWDHCP_BENCH_CLIENTS=1000000 cargo bench --bench handlers
+//! ```
+//!
+//! The `encode` group compares encoding replies with `to_vec`, a fresh
+//! allocation per reply, against [`shadowdhcp::encode_into`] reusing one
+//! buffer the way the workers do.
+
+use std::hint::black_box;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use advmac::MacAddr6;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use ipnet::Ipv6Net;
+use shadowdhcp::dhcproto::{v4, v6, Encodable};
+use shadowdhcp::testutil;
+use shadowdhcp::types::{Duid, Option82, Reservation, V4Subnet};
+use shadowdhcp::{
+    encode_into, handle_v4_message, handle_v6_message, Config, DhcpV4Response, DhcpV6Response,
+    Opt82Cache, ReservationDb,
+};
+
+const RELAY_V4: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+struct Client {
+    mac: MacAddr6,
+    duid: Duid,
+    remote: Option82,
+}
+
+fn client_count() -> usize {
+    std::env::var("SHADOWDHCP_BENCH_CLIENTS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(10_000)
+}
+
+/// Client `i` has MAC 02:00:xx:xx:xx:xx and DUID-LL of that MAC. Even
+/// clients are reserved by MAC/DUID and odd ones by Option 82 remote-id, so
+/// both lookup paths get exercised.
+fn synthesize(n: usize) -> (Config, ReservationDb, Vec<Client>) {
+    let config = Config {
+        v4_server_id: RELAY_V4,
+        subnets_v4: vec![V4Subnet {
+            net: "10.0.0.0/8".parse().unwrap(),
+            gateway: RELAY_V4,
+            reply_prefix_len: None,
+            authoritative: false,
+            ranges: vec![],
+        }]
+        .into(),
+        v6_server_id: Duid::from(vec![0, 1, 2, 3]),
+        option82_extractors: shadowdhcp::v4::extractors::get_all_extractors()
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+
+    let reservations = ReservationDb::new();
+    let mut clients = Vec::with_capacity(n);
+    for i in 0..n as u32 {
+        let [a, b, c, d] = i.to_be_bytes();
+        let mac = MacAddr6::new([0x02, 0x00, a, b, c, d]);
+        let duid = Duid::from(testutil::duid_ll(mac));
+        let remote = Option82 {
+            circuit: None,
+            remote: Some(format!("olt1:{i}").into()),
+            subscriber: None,
+        };
+        let by_mac = i % 2 == 0;
+        reservations.insert(Reservation {
+            ipv4: Some(Ipv4Addr::from(0x0a00_0000 + 2 + i)),
+            ipv6_na: Some(Ipv6Addr::from((0x2001_0db8_u128 << 96) | i as u128)),
+            ipv6_pd: Ipv6Net::new(
+                Ipv6Addr::from((0x2001_0db9_u128 << 96) | ((i as u128) << 72)),
+                56,
+            )
+            .unwrap(),
+            mac: by_mac.then_some(mac),
+            duid: by_mac.then(|| duid.clone()),
+            client_id: None,
+            option82: (!by_mac).then(|| remote.clone()),
+            option1837: None,
+            svlan: None,
+            cvlan: None,
+            subscriber_id: None,
+            static_routes: vec![],
+            boot: None,
+            profile: None,
+            activate_at: None,
+            deactivate_at: None,
+            suspended: false,
+        });
+        clients.push(Client { mac, duid, remote });
+    }
+    (config, reservations, clients)
+}
+
+fn discover(client: &Client, xid: u32) -> v4::Message {
+    let mut msg = testutil::discover(client.mac, xid, RELAY_V4);
+    let remote = client.remote.remote.as_ref().unwrap().as_bytes();
+    if let Some(opt) = testutil::relay_agent_information(None, Some(remote), None) {
+        msg.opts_mut().insert(opt);
+    }
+    msg
+}
+
+fn solicit(client: &Client) -> (v6::Message, v6::RelayMessage) {
+    let msg = testutil::solicit(&client.duid.bytes);
+    let mut relay_msg =
+        testutil::relay_forw(&msg, "2001:db8::1".parse().unwrap(), Ipv6Addr::UNSPECIFIED);
+    relay_msg
+        .opts
+        .insert(v6::DhcpOption::ClientLinklayerAddress(
+            v6::ClientLinklayerAddress {
+                address_type: 1,
+                address: client.mac.to_array().to_vec(),
+            },
+        ));
+    (msg, relay_msg)
+}
+
+fn handlers(c: &mut Criterion) {
+    let n = client_count();
+    let (config, reservations, clients) = synthesize(n);
+
+    let mut group = c.benchmark_group("handle_message");
+    group.throughput(Throughput::Elements(n as u64));
+
+    let discovers: Vec<_> = (0u32..)
+        .zip(&clients)
+        .map(|(xid, client)| discover(client, xid))
+        .collect();
+    group.bench_function("v4_discover", |b| {
+        // Fresh cache each iteration so every Discover takes the same path
+        // instead of later iterations hitting bindings the first one made
+        b.iter_batched_ref(
+            Opt82Cache::new,
+            |leases| {
+                for msg in &discovers {
+                    black_box(handle_v4_message(&config, &reservations, leases, msg));
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let solicits: Vec<_> = clients.iter().map(solicit).collect();
+    group.bench_function("v6_solicit", |b| {
+        b.iter_batched_ref(
+            Opt82Cache::new,
+            |leases| {
+                for (msg, relay_msg) in &solicits {
+                    black_box(handle_v6_message(
+                        &config,
+                        &reservations,
+                        leases,
+                        msg,
+                        relay_msg,
+                    ));
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+
+    let leases = Opt82Cache::new();
+    let offers: Vec<_> = discovers
+        .iter()
+        .filter_map(
+            |msg| match handle_v4_message(&config, &reservations, &leases, msg) {
+                DhcpV4Response::Message(resp) => Some(resp.message),
+                DhcpV4Response::NoResponse(_) => None,
+            },
+        )
+        .collect();
+    let advertises: Vec<_> = solicits
+        .iter()
+        .filter_map(|(msg, relay_msg)| {
+            match handle_v6_message(&config, &reservations, &leases, msg, relay_msg) {
+                DhcpV6Response::Message(resp) => Some(resp.message),
+                DhcpV6Response::NoResponse(_) => None,
+            }
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(n as u64));
+    group.bench_function("v4_offer_to_vec", |b| {
+        b.iter(|| {
+            for msg in &offers {
+                black_box(msg.to_vec().unwrap());
+            }
+        })
+    });
+    group.bench_function("v4_offer_reused_buffer", |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            for msg in &offers {
+                encode_into(msg, &mut buf).unwrap();
+                black_box(&buf);
+            }
+        })
+    });
+    group.bench_function("v6_advertise_to_vec", |b| {
+        b.iter(|| {
+            for msg in &advertises {
+                black_box(msg.to_vec().unwrap());
+            }
+        })
+    });
+    group.bench_function("v6_advertise_reused_buffer", |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            for msg in &advertises {
+                encode_into(msg, &mut buf).unwrap();
+                black_box(&buf);
+            }
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("reservationdb");
+    group.throughput(Throughput::Elements(n as u64));
+    group.bench_function("by_mac", |b| {
+        b.iter(|| {
+            for client in &clients {
+                black_box(reservations.by_mac(client.mac));
+            }
+        })
+    });
+    group.bench_function("by_duid", |b| {
+        b.iter(|| {
+            for client in &clients {
+                black_box(reservations.by_duid(&client.duid));
+            }
+        })
+    });
+    group.bench_function("by_opt82", |b| {
+        b.iter(|| {
+            for client in &clients {
+                black_box(reservations.by_opt82(&client.remote));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, handlers);
+criterion_main!(benches);
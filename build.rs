@@ -0,0 +1,5 @@
+fn main() {
+    // The gRPC service is generated from its .proto, which needs protoc
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/shadowdhcp.proto").expect("compile proto/shadowdhcp.proto");
+}
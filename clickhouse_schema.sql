@@ -40,17 +40,72 @@
 
 CREATE DATABASE IF NOT EXISTS dhcp;
 
+-- Tables created before reservation_subscriber_id existed keep working (the
+-- writer skips unknown fields); add the column to start recording it:
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS reservation_subscriber_id Nullable(String) AFTER reservation_option82_subscriber;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS reservation_subscriber_id Nullable(String) AFTER reservation_option1837_remote;
+--
+-- Likewise for the DHCPv4 xid and client fingerprint columns:
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS xid FixedString(8) AFTER message_type;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS client_hostname Nullable(String) AFTER option82_subscriber;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS vendor_class LowCardinality(Nullable(String)) AFTER client_hostname;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS prl_fingerprint LowCardinality(Nullable(String)) AFTER vendor_class;
+--
+-- And the DHCPv6 hint mismatch flags:
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS ipv6_na_hint_mismatch UInt8 DEFAULT 0 AFTER extractor_used;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS ipv6_pd_hint_mismatch UInt8 DEFAULT 0 AFTER ipv6_na_hint_mismatch;
+--
+-- And the lease conflict columns:
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS lease_conflict_mac Nullable(String) AFTER extractor_used;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS lease_conflict_duid Nullable(String) AFTER ipv6_pd_hint_mismatch;
+--
+-- And the timing columns:
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS handler_us Nullable(UInt32) AFTER lease_conflict_mac;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS latency_us Nullable(UInt32) AFTER handler_us;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS socket_queue_bytes Nullable(UInt32) AFTER latency_us;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS handler_us Nullable(UInt32) AFTER lease_conflict_duid;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS latency_us Nullable(UInt32) AFTER handler_us;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS socket_queue_bytes Nullable(UInt32) AFTER latency_us;
+--
+-- And the hex columns for relay identifiers that aren't UTF-8:
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS option82_circuit_hex Nullable(String) AFTER option82_subscriber;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS option82_remote_hex Nullable(String) AFTER option82_circuit_hex;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS option82_subscriber_hex Nullable(String) AFTER option82_remote_hex;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS option1837_interface_hex Nullable(String) AFTER option1837_remote;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS option1837_remote_hex Nullable(String) AFTER option1837_interface_hex;
+--
+-- And the boot id and sequence number:
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS boot_id LowCardinality(String) DEFAULT '' AFTER timestamp;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS seq UInt64 DEFAULT 0 AFTER boot_id;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS boot_id LowCardinality(String) DEFAULT '' AFTER timestamp;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS seq UInt64 DEFAULT 0 AFTER boot_id;
+--   ALTER TABLE dhcp.alerts ADD COLUMN IF NOT EXISTS boot_id LowCardinality(String) DEFAULT '' AFTER timestamp;
+--   ALTER TABLE dhcp.alerts ADD COLUMN IF NOT EXISTS seq UInt64 DEFAULT 0 AFTER boot_id;
+--
+-- And the device class labels:
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS device_class LowCardinality(Nullable(String)) AFTER prl_fingerprint;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS device_class LowCardinality(Nullable(String)) AFTER requested_ipv6_pd_length;
+--
+-- And the matched key columns:
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS match_key Nullable(String) AFTER extractor_used;
+--   ALTER TABLE dhcp.events_v4 ADD COLUMN IF NOT EXISTS match_priority Nullable(UInt8) AFTER match_key;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS match_key Nullable(String) AFTER extractor_used;
+--   ALTER TABLE dhcp.events_v6 ADD COLUMN IF NOT EXISTS match_priority Nullable(UInt8) AFTER match_key;
+
 -- DHCPv4 events table
 CREATE TABLE IF NOT EXISTS dhcp.events_v4
 (
     -- Timing
     timestamp DateTime64(3),
+    boot_id LowCardinality(String) DEFAULT '',  -- random for each run of the server
+    seq UInt64 DEFAULT 0,                       -- counts up within a boot_id
 
     -- Server identification
     host_name LowCardinality(String) DEFAULT '',
 
     -- Message info
     message_type LowCardinality(Nullable(String)),
+    xid FixedString(8),  -- DHCPv4 transaction id is 4 bytes; writer emits 8-char hex
     relay_addr IPv4,
 
     -- Request data (from client/relay)
@@ -58,6 +113,13 @@ CREATE TABLE IF NOT EXISTS dhcp.events_v4
     option82_circuit Nullable(String),
     option82_remote Nullable(String),
     option82_subscriber Nullable(String),
+    option82_circuit_hex Nullable(String),     -- set instead of the text column when not UTF-8
+    option82_remote_hex Nullable(String),
+    option82_subscriber_hex Nullable(String),
+    client_hostname Nullable(String),                   -- option 12
+    vendor_class LowCardinality(Nullable(String)),      -- option 60
+    prl_fingerprint LowCardinality(Nullable(String)),   -- option 55 codes, e.g. '1,3,6,15'
+    device_class LowCardinality(Nullable(String)),      -- hash of options 55 and 60
 
     -- Reservation data (what matched)
     reservation_ipv4 Nullable(IPv4),
@@ -65,10 +127,19 @@ CREATE TABLE IF NOT EXISTS dhcp.events_v4
     reservation_option82_circuit Nullable(String),
     reservation_option82_remote Nullable(String),
     reservation_option82_subscriber Nullable(String),
+    reservation_subscriber_id Nullable(String),
 
     -- Match info (how was reservation found)
-    match_method LowCardinality(Nullable(String)),  -- 'mac', 'option82'
+    match_method LowCardinality(Nullable(String)),  -- 'client_id', 'mac', 'vlans', 'option82', 'pool'
     extractor_used LowCardinality(Nullable(String)),  -- extractor name (e.g., 'chaddr' for mac, or option82 extractor name)
+    match_key Nullable(String),  -- the client's MAC, client ID or VLANs, or the extracted Option 82 as JSON
+    match_priority Nullable(UInt8),  -- 1 client_id, 2 mac, 3 vlans, 4 option82/pool
+    lease_conflict_mac Nullable(String),  -- another client held this address within the lease time
+
+    -- Timing
+    handler_us Nullable(UInt32),  -- time in the packet handler
+    latency_us Nullable(UInt32),  -- socket read to send
+    socket_queue_bytes Nullable(UInt32),  -- receive queue when read (Linux only)
 
     -- Result
     success UInt8,
@@ -81,7 +152,8 @@ CREATE TABLE IF NOT EXISTS dhcp.events_v4
     -- failure_reason; query them directly without a skip index.
     INDEX idx_host host_name TYPE bloom_filter GRANULARITY 4,
     INDEX idx_mac mac_address TYPE bloom_filter GRANULARITY 4,
-    INDEX idx_reservation_ipv4 reservation_ipv4 TYPE bloom_filter GRANULARITY 4
+    INDEX idx_reservation_ipv4 reservation_ipv4 TYPE bloom_filter GRANULARITY 4,
+    INDEX idx_subscriber_id reservation_subscriber_id TYPE bloom_filter GRANULARITY 4
 )
 ENGINE = MergeTree()
 PARTITION BY toYYYYMM(timestamp)
@@ -94,6 +166,8 @@ CREATE TABLE IF NOT EXISTS dhcp.events_v6
 (
     -- Timing
     timestamp DateTime64(3),
+    boot_id LowCardinality(String) DEFAULT '',  -- random for each run of the server
+    seq UInt64 DEFAULT 0,                       -- counts up within a boot_id
 
     -- Server identification
     host_name LowCardinality(String) DEFAULT '',
@@ -110,9 +184,12 @@ CREATE TABLE IF NOT EXISTS dhcp.events_v6
     client_id Nullable(String),
     option1837_interface Nullable(String),
     option1837_remote Nullable(String),
+    option1837_interface_hex Nullable(String),  -- set instead of the text column when not UTF-8
+    option1837_remote_hex Nullable(String),
     requested_ipv6_na Nullable(IPv6),
     requested_ipv6_pd_prefix Nullable(IPv6),
     requested_ipv6_pd_length Nullable(UInt8),
+    device_class LowCardinality(Nullable(String)),  -- hash of options 6 and 16
 
     -- Reservation data (what matched)
     reservation_ipv6_na Nullable(IPv6),
@@ -123,10 +200,21 @@ CREATE TABLE IF NOT EXISTS dhcp.events_v6
     reservation_duid Nullable(String),
     reservation_option1837_interface Nullable(String),
     reservation_option1837_remote Nullable(String),
+    reservation_subscriber_id Nullable(String),
 
     -- Match info (how was reservation found)
-    match_method LowCardinality(Nullable(String)),  -- 'mac', 'duid', 'option1837', 'option82'
+    match_method LowCardinality(Nullable(String)),  -- 'mac', 'duid', 'option1837', 'option82', 'pool'
     extractor_used LowCardinality(Nullable(String)),  -- extractor name (mac: 'client_linklayer_address', 'peer_addr_eui64', 'duid'; option1837/option82: extractor name)
+    match_key Nullable(String),  -- the client's DUID or MAC, or the extracted Option 18/37 or Option 82 as JSON
+    match_priority Nullable(UInt8),  -- 1 duid, 2 option1837, 3 duid_binding, 4 mac, 5 lease_fallback
+    ipv6_na_hint_mismatch UInt8,  -- client's IA_NA/IA_PD hint differs from the reservation
+    ipv6_pd_hint_mismatch UInt8,
+    lease_conflict_duid Nullable(String),
+
+    -- Timing
+    handler_us Nullable(UInt32),  -- time in the packet handler
+    latency_us Nullable(UInt32),  -- socket read to send
+    socket_queue_bytes Nullable(UInt32),  -- receive queue when read (Linux only)
 
     -- Result
     success UInt8,
@@ -137,7 +225,8 @@ CREATE TABLE IF NOT EXISTS dhcp.events_v6
     INDEX idx_host host_name TYPE bloom_filter GRANULARITY 4,
     INDEX idx_mac mac_address TYPE bloom_filter GRANULARITY 4,
     INDEX idx_client_id client_id TYPE bloom_filter GRANULARITY 4,
-    INDEX idx_reservation_ipv6_na reservation_ipv6_na TYPE bloom_filter GRANULARITY 4
+    INDEX idx_reservation_ipv6_na reservation_ipv6_na TYPE bloom_filter GRANULARITY 4,
+    INDEX idx_subscriber_id reservation_subscriber_id TYPE bloom_filter GRANULARITY 4
 )
 ENGINE = MergeTree()
 PARTITION BY toYYYYMM(timestamp)
@@ -145,6 +234,56 @@ ORDER BY (host_name, relay_addr, timestamp)
 TTL timestamp + INTERVAL 90 DAY
 SETTINGS index_granularity = 8192;
 
+-- Capacity alerts from the `alerts` config, raised and cleared. A handful a
+-- day at most, so no indices.
+CREATE TABLE IF NOT EXISTS dhcp.alerts
+(
+    timestamp DateTime64(3),
+    boot_id LowCardinality(String) DEFAULT '',
+    seq UInt64 DEFAULT 0,
+    host_name LowCardinality(String) DEFAULT '',
+    alert LowCardinality(String),   -- subnet_lease_percent, no_reservation_per_minute
+    state LowCardinality(String),   -- raised, cleared
+    subnet Nullable(String),        -- subnets_v4 entry, for subnet_lease_percent
+    value UInt64,
+    threshold UInt64
+)
+ENGINE = MergeTree()
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (host_name, alert, timestamp)
+TTL timestamp + INTERVAL 365 DAY;
+
+-- Leases that ran out without being renewed, one row per client session:
+-- expired_at - leased_at is how long it held the address.
+CREATE TABLE IF NOT EXISTS dhcp.lease_expirations
+(
+    timestamp DateTime64(3),
+    boot_id LowCardinality(String) DEFAULT '',
+    seq UInt64 DEFAULT 0,
+    host_name LowCardinality(String) DEFAULT '',
+    protocol LowCardinality(String),   -- v4, v6
+    ipv4 Nullable(IPv4),
+    ipv6_na Nullable(IPv6),
+    mac_address Nullable(String),
+    client_id Nullable(String),        -- DUID
+    option82_circuit Nullable(String),
+    option82_remote Nullable(String),
+    option82_subscriber Nullable(String),
+    option1837_interface Nullable(String),
+    option1837_remote Nullable(String),
+    leased_at DateTime64(3),           -- first grant, renewals since included
+    renewed_at DateTime64(3),
+    expired_at DateTime64(3),
+    lease_secs UInt32,
+
+    INDEX idx_mac mac_address TYPE bloom_filter GRANULARITY 4,
+    INDEX idx_client_id client_id TYPE bloom_filter GRANULARITY 4
+)
+ENGINE = MergeTree()
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (host_name, protocol, timestamp)
+TTL timestamp + INTERVAL 90 DAY;
+
 -- Materialized views below all aggregate by `message_type` so operators can
 -- separate Discover/Request/Renew rates from one another. The source events
 -- tables expire at 90 days, but MVs are independent tables that grow until
@@ -228,7 +367,7 @@ GROUP BY host_name, relay_addr, message_type, date;
 -- SELECT mac_address, sum(request_count) as total FROM dhcp.frequent_clients_v4_mv GROUP BY mac_address ORDER BY total DESC LIMIT 10;
 
 -- Clients that tried to get an address without a reservation
--- SELECT * FROM dhcp.events_v4 WHERE success = 0 AND failure_reason = 'NoReservation' ORDER BY timestamp DESC LIMIT 100;
+-- SELECT * FROM dhcp.events_v4 WHERE success = 0 AND failure_reason IN ('NoReservation', 'NotAuthoritative') ORDER BY timestamp DESC LIMIT 100;
 -- SELECT * FROM dhcp.events_v6 WHERE success = 0 AND failure_reason = 'NoReservation' ORDER BY timestamp DESC LIMIT 100;
 
 -- Total successful requests today
@@ -241,6 +380,12 @@ GROUP BY host_name, relay_addr, message_type, date;
 -- LEFT JOIN dhcp.events_v6 e6 ON e4.mac_address = e6.mac_address AND e6.success = 1
 -- WHERE e4.success = 1 AND e4.mac_address IS NOT NULL AND e6.mac_address IS NULL;
 
+-- Dual-stack view of one customer, joined on the reservation's subscriber_id
+-- SELECT 'v4' AS family, timestamp, message_type, success FROM dhcp.events_v4 WHERE reservation_subscriber_id = 'acct-100234'
+-- UNION ALL
+-- SELECT 'v6' AS family, timestamp, message_type, success FROM dhcp.events_v6 WHERE reservation_subscriber_id = 'acct-100234'
+-- ORDER BY timestamp DESC LIMIT 100;
+
 -- Requests by relay
 -- SELECT relay_addr, sum(request_count) as total FROM dhcp.relay_stats_v4_mv GROUP BY relay_addr ORDER BY total DESC;
 
@@ -259,9 +404,25 @@ GROUP BY host_name, relay_addr, message_type, date;
 -- SELECT extractor_used, count() as total FROM dhcp.events_v6 WHERE match_method = 'mac' GROUP BY extractor_used;
 -- Possible values: 'client_linklayer_address' (RFC 6939), 'peer_addr_eui64', 'duid'
 
+-- Clients matched by MAC on one day and by Option 82 on another, e.g. a
+-- CPE moved to another port while its MAC reservation was removed
+-- SELECT mac_address, groupUniqArray(match_method) AS methods, groupUniqArray(match_key) AS keys
+-- FROM dhcp.events_v4 WHERE success = 1 AND timestamp > now() - INTERVAL 7 DAY
+-- GROUP BY mac_address HAVING length(methods) > 1;
+
 -- Events from specific server
 -- SELECT * FROM dhcp.events_v4 WHERE host_name = 'dhcp-server-01' ORDER BY timestamp DESC LIMIT 100;
 
+-- How long DHCPv4 clients kept their address over the last week
+-- SELECT quantiles(0.5, 0.9, 0.99)(dateDiff('second', leased_at, expired_at)) FROM dhcp.lease_expirations WHERE protocol = 'v4' AND timestamp >= now() - INTERVAL 7 DAY;
+
+-- Capacity alerts raised in the last week
+-- SELECT timestamp, host_name, alert, subnet, value, threshold FROM dhcp.alerts WHERE state = 'raised' AND timestamp >= now() - INTERVAL 7 DAY ORDER BY timestamp DESC;
+
+-- A server's events in the order it sent them, whatever its clock did, each
+-- once even if a batch was retried after it had already been written
+-- SELECT * FROM dhcp.events_v4 WHERE host_name = 'dhcp-server-01' AND timestamp >= now() - INTERVAL 1 DAY ORDER BY boot_id, seq LIMIT 1 BY boot_id, seq;
+
 -- Request count per server
 -- SELECT host_name, sum(request_count) as total FROM dhcp.relay_stats_v4_mv GROUP BY host_name;
 
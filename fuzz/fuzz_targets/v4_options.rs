@@ -0,0 +1,184 @@
+//! DHCPv4 messages from the harness relay, built from combinations of the
+//! options the handlers read. The header is always well formed and the
+//! client is usually one with a reservation, so inputs get past decoding
+//! and the lookup into building the reply.
+
+#![no_main]
+
+use std::sync::OnceLock;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use shadowdhcp::fuzz::{self, Harness};
+
+static HARNESS: OnceLock<Harness> = OnceLock::new();
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    message_type: MessageType,
+    client: Client,
+    xid: u32,
+    broadcast: bool,
+    ciaddr: Addr,
+    /// Straight from a client rather than through the relay
+    unrelayed: bool,
+    options: Vec<Opt>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum MessageType {
+    Discover,
+    Request,
+    Decline,
+    Release,
+    Inform,
+    Other(u8),
+    Missing,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Client {
+    Reserved,
+    Other([u8; 6]),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Addr {
+    Unspecified,
+    Reserved,
+    Gateway,
+    ServerId,
+    Other([u8; 4]),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Opt {
+    RequestedIp(Addr),
+    ServerId(Addr),
+    ParameterRequestList(Vec<u8>),
+    MaxMessageSize(u16),
+    ClientId(Vec<u8>),
+    Hostname(Vec<u8>),
+    VendorClass(Vec<u8>),
+    Overload(u8),
+    RelayAgentInformation(Vec<SubOpt>),
+    Raw(u8, Vec<u8>),
+}
+
+#[derive(Arbitrary, Debug)]
+enum SubOpt {
+    CircuitId(Vec<u8>),
+    RemoteId(Option<Vec<u8>>),
+    SubscriberId(Vec<u8>),
+    LinkSelection(Addr),
+    ServerIdOverride(Addr),
+    Raw(u8, Vec<u8>),
+}
+
+impl Addr {
+    fn octets(&self) -> [u8; 4] {
+        match self {
+            Addr::Unspecified => [0; 4],
+            Addr::Reserved => [100, 64, 0, 10],
+            Addr::Gateway => fuzz::RELAY_V4.octets(),
+            Addr::ServerId => fuzz::V4_SERVER_ID.octets(),
+            Addr::Other(octets) => *octets,
+        }
+    }
+}
+
+/// Code, length and as much of `data` as the length byte can describe
+fn tlv(out: &mut Vec<u8>, code: u8, data: &[u8]) {
+    let data = &data[..data.len().min(255)];
+    out.push(code);
+    out.push(data.len() as u8);
+    out.extend_from_slice(data);
+}
+
+impl Opt {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Opt::RequestedIp(addr) => tlv(out, 50, &addr.octets()),
+            Opt::ServerId(addr) => tlv(out, 54, &addr.octets()),
+            Opt::ParameterRequestList(codes) => tlv(out, 55, codes),
+            Opt::MaxMessageSize(size) => tlv(out, 57, &size.to_be_bytes()),
+            Opt::ClientId(id) => tlv(out, 61, id),
+            Opt::Hostname(name) => tlv(out, 12, name),
+            Opt::VendorClass(class) => tlv(out, 60, class),
+            Opt::Overload(value) => tlv(out, 52, &[*value]),
+            Opt::RelayAgentInformation(sub_opts) => {
+                let mut data = Vec::new();
+                for sub_opt in sub_opts {
+                    sub_opt.encode(&mut data);
+                }
+                tlv(out, 82, &data);
+            }
+            Opt::Raw(code, data) => tlv(out, *code, data),
+        }
+    }
+}
+
+impl SubOpt {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            SubOpt::CircuitId(id) => tlv(out, 1, id),
+            SubOpt::RemoteId(id) => tlv(out, 2, id.as_deref().unwrap_or(fuzz::RESERVED_REMOTE_ID)),
+            SubOpt::SubscriberId(id) => tlv(out, 6, id),
+            SubOpt::LinkSelection(addr) => tlv(out, 5, &addr.octets()),
+            SubOpt::ServerIdOverride(addr) => tlv(out, 11, &addr.octets()),
+            SubOpt::Raw(code, data) => tlv(out, *code, data),
+        }
+    }
+}
+
+impl Input {
+    fn packet(&self) -> Vec<u8> {
+        let chaddr = match &self.client {
+            Client::Reserved => fuzz::RESERVED_MAC.to_array(),
+            Client::Other(mac) => *mac,
+        };
+        let giaddr = if self.unrelayed {
+            [0; 4]
+        } else {
+            fuzz::RELAY_V4.octets()
+        };
+        let flags: u16 = if self.broadcast { 0x8000 } else { 0 };
+
+        // op, htype, hlen, hops
+        let mut out = vec![1, 1, 6, u8::from(!self.unrelayed)];
+        out.extend_from_slice(&self.xid.to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&flags.to_be_bytes());
+        out.extend_from_slice(&self.ciaddr.octets());
+        // yiaddr, siaddr
+        out.extend_from_slice(&[0; 8]);
+        out.extend_from_slice(&giaddr);
+        out.extend_from_slice(&chaddr);
+        out.extend_from_slice(&[0; 10]);
+        // sname, file
+        out.extend_from_slice(&[0; 64 + 128]);
+        out.extend_from_slice(&[99, 130, 83, 99]);
+
+        let message_type = match self.message_type {
+            MessageType::Discover => Some(1),
+            MessageType::Request => Some(3),
+            MessageType::Decline => Some(4),
+            MessageType::Release => Some(7),
+            MessageType::Inform => Some(8),
+            MessageType::Other(t) => Some(t),
+            MessageType::Missing => None,
+        };
+        if let Some(t) = message_type {
+            tlv(&mut out, 53, &[t]);
+        }
+        for opt in &self.options {
+            opt.encode(&mut out);
+        }
+        out.push(255);
+        out
+    }
+}
+
+fuzz_target!(|input: Input| {
+    HARNESS.get_or_init(Harness::new).v4(&input.packet());
+});
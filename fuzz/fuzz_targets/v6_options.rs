@@ -0,0 +1,232 @@
+//! DHCPv6 Relay-forwards from the harness relay, built from combinations of
+//! the relay and client options the handlers read, IA_NA and IA_PD with
+//! their addresses and prefixes included. The framing is always well formed
+//! and the client is usually one with a reservation, so inputs get past
+//! decoding and the lookup into building the reply.
+
+#![no_main]
+
+use std::net::Ipv6Addr;
+use std::sync::OnceLock;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use shadowdhcp::fuzz::{self, Harness};
+
+static HARNESS: OnceLock<Harness> = OnceLock::new();
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    message_type: MessageType,
+    xid: [u8; 3],
+    hop_count: u8,
+    peer_addr: Addr,
+    /// Wrap the Relay-forward in a second one, as a relay chain would
+    nested: bool,
+    relay_options: Vec<RelayOpt>,
+    options: Vec<Opt>,
+}
+
+#[derive(Arbitrary, Debug)]
+enum MessageType {
+    Solicit,
+    Request,
+    Confirm,
+    Renew,
+    Rebind,
+    Release,
+    Decline,
+    InformationRequest,
+    Other(u8),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Addr {
+    Unspecified,
+    /// Link-local EUI-64 of the reserved MAC
+    Eui64,
+    ReservedNa,
+    Other([u8; 16]),
+}
+
+#[derive(Arbitrary, Debug)]
+enum RelayOpt {
+    InterfaceId(Option<Vec<u8>>),
+    RemoteId(u32, Vec<u8>),
+    ClientLinklayerAddress(u16, Option<Vec<u8>>),
+    RelayPort(u16),
+    EchoRequest(Vec<u16>),
+    SubscriberId(Vec<u8>),
+    Raw(u16, Vec<u8>),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Opt {
+    ClientId(Option<Vec<u8>>),
+    ServerId(Option<Vec<u8>>),
+    IaNa(u32, u32, u32, Vec<IaAddr>),
+    IaPd(u32, u32, u32, Vec<IaPrefix>),
+    OptionRequest(Vec<u16>),
+    ElapsedTime(u16),
+    RapidCommit,
+    ReconfigureAccept,
+    Raw(u16, Vec<u8>),
+}
+
+#[derive(Arbitrary, Debug)]
+struct IaAddr {
+    addr: Addr,
+    preferred: u32,
+    valid: u32,
+}
+
+#[derive(Arbitrary, Debug)]
+struct IaPrefix {
+    reserved: bool,
+    prefix: [u8; 16],
+    prefix_len: u8,
+    preferred: u32,
+    valid: u32,
+}
+
+impl Addr {
+    fn octets(&self) -> [u8; 16] {
+        match self {
+            Addr::Unspecified => [0; 16],
+            Addr::Eui64 => {
+                let [a, b, c, d, e, f] = fuzz::RESERVED_MAC.to_array();
+                let mut octets = [0; 16];
+                octets[..2].copy_from_slice(&[0xfe, 0x80]);
+                octets[8..].copy_from_slice(&[a ^ 0x02, b, c, 0xff, 0xfe, d, e, f]);
+                octets
+            }
+            Addr::ReservedNa => "2001:db8:1::1".parse::<Ipv6Addr>().unwrap().octets(),
+            Addr::Other(octets) => *octets,
+        }
+    }
+}
+
+/// Code, length and as much of `data` as the length field can describe
+fn tlv(out: &mut Vec<u8>, code: u16, data: &[u8]) {
+    let data = &data[..data.len().min(usize::from(u16::MAX))];
+    out.extend_from_slice(&code.to_be_bytes());
+    out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn codes(codes: &[u16]) -> Vec<u8> {
+    codes.iter().flat_map(|code| code.to_be_bytes()).collect()
+}
+
+impl RelayOpt {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            RelayOpt::InterfaceId(id) => tlv(
+                out,
+                18,
+                id.as_deref().unwrap_or(fuzz::RESERVED_INTERFACE_ID),
+            ),
+            RelayOpt::RemoteId(enterprise, id) => {
+                tlv(out, 37, &[&enterprise.to_be_bytes()[..], &id[..]].concat())
+            }
+            RelayOpt::ClientLinklayerAddress(hw_type, addr) => {
+                let reserved = fuzz::RESERVED_MAC.to_array();
+                let addr = addr.as_deref().unwrap_or(&reserved);
+                tlv(out, 79, &[&hw_type.to_be_bytes()[..], addr].concat())
+            }
+            RelayOpt::RelayPort(port) => tlv(out, 135, &port.to_be_bytes()),
+            RelayOpt::EchoRequest(requested) => tlv(out, 43, &codes(requested)),
+            RelayOpt::SubscriberId(id) => tlv(out, 38, id),
+            RelayOpt::Raw(code, data) => tlv(out, *code, data),
+        }
+    }
+}
+
+impl Opt {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Opt::ClientId(duid) => tlv(out, 1, duid.as_deref().unwrap_or(&fuzz::RESERVED_DUID)),
+            Opt::ServerId(duid) => tlv(out, 2, duid.as_deref().unwrap_or(&fuzz::V6_SERVER_ID)),
+            Opt::IaNa(iaid, t1, t2, addrs) => {
+                let mut data = [iaid, t1, t2].map(|n| n.to_be_bytes()).concat();
+                for addr in addrs {
+                    let body = [
+                        &addr.addr.octets()[..],
+                        &addr.preferred.to_be_bytes()[..],
+                        &addr.valid.to_be_bytes()[..],
+                    ]
+                    .concat();
+                    tlv(&mut data, 5, &body);
+                }
+                tlv(out, 3, &data);
+            }
+            Opt::IaPd(iaid, t1, t2, prefixes) => {
+                let mut data = [iaid, t1, t2].map(|n| n.to_be_bytes()).concat();
+                for prefix in prefixes {
+                    let (octets, len) = if prefix.reserved {
+                        let reserved: Ipv6Addr = "2001:db8:100::".parse().unwrap();
+                        (reserved.octets(), 56)
+                    } else {
+                        (prefix.prefix, prefix.prefix_len)
+                    };
+                    let body = [
+                        &prefix.preferred.to_be_bytes()[..],
+                        &prefix.valid.to_be_bytes()[..],
+                        &[len][..],
+                        &octets[..],
+                    ]
+                    .concat();
+                    tlv(&mut data, 26, &body);
+                }
+                tlv(out, 25, &data);
+            }
+            Opt::OptionRequest(requested) => tlv(out, 6, &codes(requested)),
+            Opt::ElapsedTime(time) => tlv(out, 8, &time.to_be_bytes()),
+            Opt::RapidCommit => tlv(out, 14, &[]),
+            Opt::ReconfigureAccept => tlv(out, 20, &[]),
+            Opt::Raw(code, data) => tlv(out, *code, data),
+        }
+    }
+}
+
+impl Input {
+    fn packet(&self) -> Vec<u8> {
+        let message_type = match self.message_type {
+            MessageType::Solicit => 1,
+            MessageType::Request => 3,
+            MessageType::Confirm => 4,
+            MessageType::Renew => 5,
+            MessageType::Rebind => 6,
+            MessageType::Release => 8,
+            MessageType::Decline => 9,
+            MessageType::InformationRequest => 11,
+            MessageType::Other(t) => t,
+        };
+        let mut client = vec![message_type];
+        client.extend_from_slice(&self.xid);
+        for opt in &self.options {
+            opt.encode(&mut client);
+        }
+
+        let mut relay_forw = vec![12, self.hop_count];
+        // link-addr
+        relay_forw.extend_from_slice(&[0; 16]);
+        relay_forw.extend_from_slice(&self.peer_addr.octets());
+        for opt in &self.relay_options {
+            opt.encode(&mut relay_forw);
+        }
+        tlv(&mut relay_forw, 9, &client);
+
+        if !self.nested {
+            return relay_forw;
+        }
+        let mut outer = vec![12, self.hop_count.wrapping_add(1)];
+        outer.extend_from_slice(&[0; 32]);
+        tlv(&mut outer, 9, &relay_forw);
+        outer
+    }
+}
+
+fuzz_target!(|input: Input| {
+    HARNESS.get_or_init(Harness::new).v6(&input.packet());
+});
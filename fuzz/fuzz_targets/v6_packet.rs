@@ -0,0 +1,14 @@
+//! Arbitrary bytes as a datagram on the DHCPv6 socket
+
+#![no_main]
+
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use shadowdhcp::fuzz::Harness;
+
+static HARNESS: OnceLock<Harness> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+    HARNESS.get_or_init(Harness::new).v6(data);
+});
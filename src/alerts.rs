@@ -0,0 +1,255 @@
+//! Capacity alerts from the `alerts` config. Once a minute the monitor works
+//! out how many of each subnet's reservations have a running lease and how
+//! many clients went unanswered for want of a reservation. When either
+//! reaches its threshold it logs at `warn` and sends an alert event, and
+//! reports again, at `info`, once the value drops back under.
+
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use ipnet::Ipv4Net;
+use tracing::{info, warn};
+
+use crate::analytics::events::{boot_id, now, AlertEvent, DhcpEvent};
+use crate::analytics::EventSenders;
+use crate::config::Config;
+use crate::health::Health;
+use crate::opt82_cache::{LeaseV4, Opt82Cache};
+use crate::outcome::NoResponse;
+use crate::reservationdb::ReservationDb;
+use crate::shutdown::Shutdown;
+use crate::subnets::V4Subnets;
+use crate::types::Reservation;
+
+/// How often the thresholds are checked. `no_reservation_per_minute` counts
+/// the failures between two checks.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubnetUptake {
+    pub subnet: Ipv4Net,
+    /// Reserved addresses in the subnet
+    pub reserved: usize,
+    /// Those of them with a running lease
+    pub leased: usize,
+}
+
+impl SubnetUptake {
+    /// Rounded down, and 0 for a subnet without reservations
+    pub fn percent(&self) -> u64 {
+        (self.leased * 100).checked_div(self.reserved).unwrap_or(0) as u64
+    }
+}
+
+/// The reserved addresses in each of `subnets` and how many of them are in
+/// `leases`. An address counts towards the entry that answers it, the one
+/// with the longest matching prefix, and its `ranges` count with its `net`.
+pub fn subnet_uptake(
+    subnets: &V4Subnets,
+    reservations: &[Reservation],
+    leases: &[LeaseV4],
+) -> Vec<SubnetUptake> {
+    let leased: HashSet<Ipv4Addr> = leases.iter().map(|lease| lease.ipv4).collect();
+    let reserved: HashSet<Ipv4Addr> = reservations.iter().filter_map(|r| r.ipv4).collect();
+    let mut uptake: Vec<_> = subnets
+        .iter()
+        .map(|subnet| SubnetUptake {
+            subnet: subnet.net,
+            reserved: 0,
+            leased: 0,
+        })
+        .collect();
+    for ipv4 in reserved {
+        if let Some((index, _)) = subnets.find_index(ipv4) {
+            uptake[index].reserved += 1;
+            uptake[index].leased += usize::from(leased.contains(&ipv4));
+        }
+    }
+    uptake
+}
+
+/// The thresholds currently reached, so each crossing is reported once
+#[derive(Default)]
+pub struct Watermarks {
+    raised: HashSet<(&'static str, Option<Ipv4Net>)>,
+}
+
+impl Watermarks {
+    /// The alert for `value` if it reached `threshold`, or dropped back
+    /// under it, since the last check
+    pub fn check(
+        &mut self,
+        alert: &'static str,
+        subnet: Option<Ipv4Net>,
+        value: u64,
+        threshold: u64,
+    ) -> Option<AlertEvent> {
+        let key = (alert, subnet);
+        let state = if value >= threshold {
+            self.raised.insert(key).then_some("raised")?
+        } else {
+            self.raised.remove(&key).then_some("cleared")?
+        };
+        Some(AlertEvent {
+            timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
+            alert,
+            state,
+            subnet,
+            value,
+            threshold,
+        })
+    }
+}
+
+/// DHCPv4 and DHCPv6 messages left unanswered because no reservation
+/// matched, since startup
+fn unknown_clients(health: &Health) -> u64 {
+    [NoResponse::NoReservation, NoResponse::NotAuthoritative]
+        .iter()
+        .map(|reason| {
+            health.v4.outcome_count(reason.as_str()) + health.v6.outcome_count(reason.as_str())
+        })
+        .sum()
+}
+
+/// Every [`CHECK_INTERVAL`], check the `alerts` thresholds until shutdown
+pub fn monitor(
+    config: Arc<ArcSwap<Config>>,
+    db: Arc<ArcSwap<ReservationDb>>,
+    leases: Arc<Opt82Cache>,
+    health: Arc<Health>,
+    events: Option<EventSenders>,
+    shutdown: Shutdown,
+) {
+    let mut watermarks = Watermarks::default();
+    let mut last_unknown = unknown_clients(&health);
+    while !shutdown.wait_timeout(CHECK_INTERVAL) {
+        let loaded = config.load();
+        let Some(thresholds) = &loaded.alerts else {
+            return;
+        };
+        let mut alerts = Vec::new();
+
+        if let Some(threshold) = thresholds.subnet_lease_percent {
+            let snapshot = leases.snapshot();
+            let reservations = db.load().reservations();
+            for uptake in subnet_uptake(&loaded.subnets_v4, &reservations, &snapshot.v4) {
+                let percent = uptake.percent();
+                if let Some(alert) = watermarks.check(
+                    "subnet_lease_percent",
+                    Some(uptake.subnet),
+                    percent,
+                    threshold.into(),
+                ) {
+                    if alert.state == "raised" {
+                        warn!(
+                            subnet = %uptake.subnet,
+                            leased = uptake.leased,
+                            reserved = uptake.reserved,
+                            "{percent}% of reservations leased, subnet_lease_percent is {threshold}"
+                        );
+                    } else {
+                        info!(subnet = %uptake.subnet, "Back to {percent}% of reservations leased");
+                    }
+                    alerts.push(alert);
+                }
+            }
+        }
+
+        let unknown = unknown_clients(&health);
+        if let Some(threshold) = thresholds.no_reservation_per_minute {
+            let per_minute = unknown.saturating_sub(last_unknown);
+            if let Some(alert) =
+                watermarks.check("no_reservation_per_minute", None, per_minute, threshold)
+            {
+                if alert.state == "raised" {
+                    warn!(
+                        "{per_minute} messages without a reservation in the last minute, \
+                         no_reservation_per_minute is {threshold}"
+                    );
+                } else {
+                    info!("Back to {per_minute} messages without a reservation a minute");
+                }
+                alerts.push(alert);
+            }
+        }
+        last_unknown = unknown;
+
+        if let Some(events) = &events {
+            for alert in alerts {
+                events.send(DhcpEvent::Alert(alert));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::V4Subnet;
+    use advmac::MacAddr6;
+
+    #[test]
+    fn uptake_crosses_the_watermark_once_each_way() {
+        let subnets: Vec<V4Subnet> = serde_json::from_str(
+            r#"[{"net": "100.64.0.0/24", "gateway": "100.64.0.1"},
+                {"net": "100.64.1.0/24", "gateway": "100.64.1.1"}]"#,
+        )
+        .unwrap();
+        let subnets = V4Subnets::new(subnets);
+        let reservations: Vec<Reservation> = serde_json::from_str(
+            r#"[{"ipv4": "100.64.0.2", "ipv6_pd": "2001:db8:1::/56", "mac": "00-11-22-33-44-01"},
+                {"ipv4": "100.64.0.3", "ipv6_pd": "2001:db8:2::/56", "mac": "00-11-22-33-44-02"},
+                {"ipv4": "100.64.0.4", "ipv6_pd": "2001:db8:3::/56", "mac": "00-11-22-33-44-03"},
+                {"ipv4": "100.64.0.5", "ipv6_pd": "2001:db8:4::/56", "mac": "00-11-22-33-44-04"},
+                {"ipv6_pd": "2001:db8:5::/56", "duid": "00:03:00:01:00:11:22:33:44:05"}]"#,
+        )
+        .unwrap();
+        let lease = |ipv4: &str| LeaseV4 {
+            ipv4: ipv4.parse().unwrap(),
+            mac: MacAddr6::new([0, 0x11, 0x22, 0x33, 0x44, 0x01]),
+            option82: None,
+            subscriber_id: None,
+            leased_at: 0,
+            expires_at: 0,
+        };
+        let mut leases: Vec<_> = ["100.64.0.2", "100.64.0.3", "100.64.0.4"]
+            .into_iter()
+            .map(lease)
+            .collect();
+
+        let uptake = subnet_uptake(&subnets, &reservations, &leases);
+        assert_eq!((uptake[0].reserved, uptake[0].leased), (4, 3));
+        assert_eq!((uptake[0].percent(), uptake[1].percent()), (75, 0));
+
+        let mut watermarks = Watermarks::default();
+        let subnet = Some(uptake[0].subnet);
+        let alert = watermarks.check("subnet_lease_percent", subnet, 75, 75);
+        assert_eq!(alert.map(|alert| alert.state), Some("raised"));
+        assert_eq!(
+            watermarks.check("subnet_lease_percent", subnet, 100, 75),
+            None
+        );
+        // Another subnet is tracked on its own
+        assert_eq!(watermarks.check("subnet_lease_percent", None, 0, 75), None);
+
+        leases.pop();
+        let uptake = subnet_uptake(&subnets, &reservations, &leases);
+        let alert = watermarks
+            .check("subnet_lease_percent", subnet, uptake[0].percent(), 75)
+            .unwrap();
+        assert_eq!(
+            (alert.state, alert.value, alert.threshold),
+            ("cleared", 50, 75)
+        );
+        assert_eq!(
+            watermarks.check("subnet_lease_percent", subnet, 50, 75),
+            None
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use serde::Serialize;
 use std::net::Ipv6Addr;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::time::Duration;
@@ -8,10 +8,9 @@ use tracing::{info, warn};
 use ureq::Agent;
 
 use crate::analytics::batch::{run, BatchConfig, BatchSink};
-use crate::analytics::clickhouse_http::{
-    basic_auth_header, build_agent, post, read_hostname, PostOutcome,
-};
+use crate::analytics::clickhouse_http::{basic_auth_header, build_agent, post, PostOutcome};
 use crate::analytics::events::{DhcpEvent, DhcpEventV6};
+use crate::analytics::read_hostname;
 use crate::config::ClickHouseConfig;
 use crate::shutdown::Shutdown;
 
@@ -52,24 +51,36 @@ struct ChEventsSink {
     base_url: String,
     url_v4: String,
     url_v6: String,
+    url_alerts: String,
+    url_expirations: String,
     auth: String,
     host_name: String,
     body_v4: Vec<u8>,
     body_v6: Vec<u8>,
+    body_alerts: Vec<u8>,
+    body_expirations: Vec<u8>,
     count_v4: usize,
     count_v6: usize,
+    count_alerts: usize,
+    count_expirations: usize,
     dropped: Arc<AtomicU64>,
+    queued: Arc<AtomicUsize>,
 }
 
 impl BatchSink<DhcpEvent> for ChEventsSink {
     fn reset(&mut self) {
         self.body_v4.clear();
         self.body_v6.clear();
+        self.body_alerts.clear();
+        self.body_expirations.clear();
         self.count_v4 = 0;
         self.count_v6 = 0;
+        self.count_alerts = 0;
+        self.count_expirations = 0;
     }
 
     fn push(&mut self, event: DhcpEvent) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
         match event {
             DhcpEvent::V4(v4) => {
                 let row = HostRow {
@@ -95,67 +106,79 @@ impl BatchSink<DhcpEvent> for ChEventsSink {
                     self.count_v6 += 1;
                 }
             }
+            DhcpEvent::Alert(alert) => {
+                let row = HostRow {
+                    host_name: &self.host_name,
+                    inner: &alert,
+                };
+                if serde_json::to_writer(&mut self.body_alerts, &row).is_ok() {
+                    self.body_alerts.push(b'\n');
+                    self.count_alerts += 1;
+                }
+            }
+            DhcpEvent::LeaseExpired(expired) => {
+                let row = HostRow {
+                    host_name: &self.host_name,
+                    inner: &expired,
+                };
+                if serde_json::to_writer(&mut self.body_expirations, &row).is_ok() {
+                    self.body_expirations.push(b'\n');
+                    self.count_expirations += 1;
+                }
+            }
         }
     }
 
     fn item_count(&self) -> usize {
-        self.count_v4 + self.count_v6
+        self.count_v4 + self.count_v6 + self.count_alerts + self.count_expirations
     }
 
-    /// POST v4 then v6.
+    /// POST v4, then v6, then alerts, then lease expirations.
     ///
     /// Per-sub-batch outcome:
     /// * `Ok` — clear the buffer.
     /// * `Permanent` (4xx other than 408/429) — drop the sub-batch with a warn
     ///   so a single poisoned row can't wedge the writer forever. Don't
-    ///   propagate; the other sub-batch may still be transient.
+    ///   propagate; the other sub-batches may still be transient.
     /// * `Transient` (5xx, network, 408/429) — leave the sub-batch buffered so
     ///   the runner retries it.
     ///
     /// Returns `Err` only if any sub-batch was transient.
     fn flush(&mut self) -> Result<(), ()> {
-        let mut overall = Ok(());
-        if self.count_v4 > 0 {
-            match post(&self.agent, &self.url_v4, &self.auth, &self.body_v4) {
-                PostOutcome::Ok => {
-                    self.body_v4.clear();
-                    self.count_v4 = 0;
-                }
-                PostOutcome::Permanent(status) => {
-                    warn!(
-                        "ClickHouse v4 dropped batch of {} after permanent HTTP {status}",
-                        self.count_v4
-                    );
-                    self.body_v4.clear();
-                    self.count_v4 = 0;
-                }
-                PostOutcome::Transient(msg) => {
-                    warn!("ClickHouse v4 batch of {} retrying: {msg}", self.count_v4);
-                    overall = Err(());
-                }
-            }
-        }
-        if self.count_v6 > 0 {
-            match post(&self.agent, &self.url_v6, &self.auth, &self.body_v6) {
-                PostOutcome::Ok => {
-                    self.body_v6.clear();
-                    self.count_v6 = 0;
-                }
-                PostOutcome::Permanent(status) => {
-                    warn!(
-                        "ClickHouse v6 dropped batch of {} after permanent HTTP {status}",
-                        self.count_v6
-                    );
-                    self.body_v6.clear();
-                    self.count_v6 = 0;
-                }
-                PostOutcome::Transient(msg) => {
-                    warn!("ClickHouse v6 batch of {} retrying: {msg}", self.count_v6);
-                    overall = Err(());
-                }
-            }
-        }
-        overall
+        let (agent, auth) = (&self.agent, self.auth.as_str());
+        let v4 = flush_rows(
+            agent,
+            auth,
+            "v4",
+            &self.url_v4,
+            &mut self.body_v4,
+            &mut self.count_v4,
+        );
+        let v6 = flush_rows(
+            agent,
+            auth,
+            "v6",
+            &self.url_v6,
+            &mut self.body_v6,
+            &mut self.count_v6,
+        );
+        let alerts = flush_rows(
+            agent,
+            auth,
+            "alerts",
+            &self.url_alerts,
+            &mut self.body_alerts,
+            &mut self.count_alerts,
+        );
+        let expirations = flush_rows(
+            agent,
+            auth,
+            "lease_expirations",
+            &self.url_expirations,
+            &mut self.body_expirations,
+            &mut self.count_expirations,
+        );
+        v4.and(v6).and(alerts).and(expirations)
     }
 
     fn on_start(&mut self) {
@@ -173,17 +196,45 @@ impl BatchSink<DhcpEvent> for ChEventsSink {
     }
 
     fn on_giveup(&mut self) {
-        let total = self.count_v4 + self.count_v6;
+        let total = self.count_v4 + self.count_v6 + self.count_alerts + self.count_expirations;
         if total > 0 {
             warn!("ClickHouse dropped batch of {total} after exhausted retries");
         }
     }
 }
 
+/// POST one table's rows, clearing them unless the failure was transient
+fn flush_rows(
+    agent: &Agent,
+    auth: &str,
+    table: &str,
+    url: &str,
+    body: &mut Vec<u8>,
+    count: &mut usize,
+) -> Result<(), ()> {
+    if *count == 0 {
+        return Ok(());
+    }
+    match post(agent, url, auth, body) {
+        PostOutcome::Ok => {}
+        PostOutcome::Permanent(status) => {
+            warn!("ClickHouse {table} dropped batch of {count} after permanent HTTP {status}");
+        }
+        PostOutcome::Transient(msg) => {
+            warn!("ClickHouse {table} batch of {count} retrying: {msg}");
+            return Err(());
+        }
+    }
+    body.clear();
+    *count = 0;
+    Ok(())
+}
+
 pub fn clickhouse_writer(
     cfg: ClickHouseConfig,
     rx: mpsc::Receiver<DhcpEvent>,
     dropped: Arc<AtomicU64>,
+    queued: Arc<AtomicUsize>,
     shutdown: Shutdown,
 ) {
     let base_url = cfg.url.trim_end_matches('/').to_string();
@@ -200,18 +251,34 @@ pub fn clickhouse_writer(
         db = cfg.database,
     );
 
+    let url_alerts = format!(
+        "{base_url}/?database={db}&input_format_skip_unknown_fields=1&query=INSERT+INTO+alerts+FORMAT+JSONEachRow",
+        db = cfg.database,
+    );
+    let url_expirations = format!(
+        "{base_url}/?database={db}&input_format_skip_unknown_fields=1&query=INSERT+INTO+lease_expirations+FORMAT+JSONEachRow",
+        db = cfg.database,
+    );
+
     let mut sink = ChEventsSink {
         agent: build_agent(),
         base_url,
         url_v4,
         url_v6,
+        url_alerts,
+        url_expirations,
         auth: basic_auth_header(&cfg.user, &cfg.password),
         host_name: cfg.hostname.unwrap_or_else(read_hostname),
         body_v4: Vec::with_capacity(512 * 1024),
         body_v6: Vec::with_capacity(512 * 1024),
+        body_alerts: Vec::new(),
+        body_expirations: Vec::new(),
         count_v4: 0,
         count_v6: 0,
+        count_alerts: 0,
+        count_expirations: 0,
         dropped,
+        queued,
     };
 
     run(
@@ -64,14 +64,6 @@ pub fn basic_auth_header(user: &str, password: &str) -> String {
     format!("Basic {encoded}")
 }
 
-/// /etc/hostname is a one-liner on Linux/BSD. Empty on macOS/Windows is fine
-/// — the ClickHouse schema defaults `host_name` to `''`.
-pub fn read_hostname() -> String {
-    std::fs::read_to_string("/etc/hostname")
-        .map(|s| s.trim().to_string())
-        .unwrap_or_default()
-}
-
 /// Build the shared ureq agent used by both writers.
 pub fn build_agent() -> Agent {
     Agent::config_builder()
@@ -1,23 +1,37 @@
-use crate::types::{Duid, Reservation};
+use crate::types::{Duid, Option1837, Option82, Reservation};
 use advmac::MacAddr6;
-use compact_str::CompactString;
+use compact_str::{CompactString, ToCompactString};
 use dhcproto::v4;
 use dhcproto::v6::{self, MessageType};
-use ipnet::Ipv6Net;
+use ipnet::{Ipv4Net, Ipv6Net};
 use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
 use std::net::{Ipv4Addr, Ipv6Addr};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::capture::to_hex;
+use crate::outcome::NoResponse;
 use crate::v4::extensions::{RelayAgentInformationExt, ShadowMessageExtV4};
 use crate::v6::extensions::{ShadowMessageExtV6, ShadowRelayMessageExtV6};
 
 /// Metadata about how a reservation was matched
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct ReservationMatch {
-    /// The method used to find the reservation: "mac", "duid", "option82", "option1837"
+    /// The method used to find the reservation: "mac", "duid", "client_id", "vlans",
+    /// "option82", "option1837", "pool"
     pub method: &'static str,
     /// The extractor function name that succeeded (for option82/option1837 matches)
     pub extractor: Option<&'static str>,
+    /// The value the reservation was found by: the MAC, the DUID or client
+    /// ID as colon separated hex, the VLAN tags, or the extracted relay
+    /// option as JSON
+    pub key: Option<CompactString>,
+    /// Position of the lookup in the order they are tried, 1 being the first,
+    /// so a match on a weaker identifier stands out
+    pub priority: Option<u8>,
 }
 
 impl ReservationMatch {
@@ -25,6 +39,8 @@ impl ReservationMatch {
         Self {
             method: "mac",
             extractor: Some(extractor),
+            key: None,
+            priority: None,
         }
     }
 
@@ -32,6 +48,26 @@ impl ReservationMatch {
         Self {
             method: "duid",
             extractor: None,
+            key: None,
+            priority: None,
+        }
+    }
+
+    pub fn client_id() -> Self {
+        Self {
+            method: "client_id",
+            extractor: None,
+            key: None,
+            priority: None,
+        }
+    }
+
+    pub fn vlans() -> Self {
+        Self {
+            method: "vlans",
+            extractor: None,
+            key: None,
+            priority: None,
         }
     }
 
@@ -39,6 +75,8 @@ impl ReservationMatch {
         Self {
             method: "option82",
             extractor: Some(extractor),
+            key: None,
+            priority: None,
         }
     }
 
@@ -46,8 +84,36 @@ impl ReservationMatch {
         Self {
             method: "option1837",
             extractor: Some(extractor),
+            key: None,
+            priority: None,
         }
     }
+
+    pub fn pool(extractor: &'static str) -> Self {
+        Self {
+            method: "pool",
+            extractor: Some(extractor),
+            key: None,
+            priority: None,
+        }
+    }
+
+    pub fn with_key(mut self, key: impl std::fmt::Display) -> Self {
+        self.key = Some(key.to_compact_string());
+        self
+    }
+
+    /// Key for the relay options a reservation was found by, written the
+    /// way reservations spell them
+    pub fn with_relay_key(mut self, key: &impl Serialize) -> Self {
+        self.key = serde_json::to_string(key).ok().map(CompactString::from);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority);
+        self
+    }
 }
 
 #[derive(Clone, Serialize)]
@@ -57,6 +123,161 @@ pub enum DhcpEvent {
     V6(DhcpEventV6),
     #[serde(rename = "v4")]
     V4(DhcpEventV4),
+    #[serde(rename = "alert")]
+    Alert(AlertEvent),
+    #[serde(rename = "lease_expired")]
+    LeaseExpired(LeaseExpiredEvent),
+}
+
+impl DhcpEvent {
+    /// False for alerts and expiries, so they pass `events.filter` as
+    /// failures do
+    pub fn success(&self) -> bool {
+        match self {
+            DhcpEvent::V4(event) => event.success,
+            DhcpEvent::V6(event) => event.success,
+            DhcpEvent::Alert(_) | DhcpEvent::LeaseExpired(_) => false,
+        }
+    }
+
+    /// A DHCPv4 Request renewing or rebinding a lease the client holds, or
+    /// a DHCPv6 Renew or Rebind
+    pub fn is_renewal(&self) -> bool {
+        match self {
+            DhcpEvent::V4(event) => event.renewal,
+            DhcpEvent::V6(event) => matches!(event.message_type, "Renew" | "Rebind"),
+            DhcpEvent::Alert(_) | DhcpEvent::LeaseExpired(_) => false,
+        }
+    }
+
+    /// Number the event as it is handed to the sinks. The wall clock can
+    /// step under NTP, so `(boot_id, seq)` is what orders the events of a
+    /// run and tells a resent event from a new one; events the filter turns
+    /// away aren't numbered, so a gap means one was dropped.
+    pub fn set_seq(&mut self) {
+        let seq = SEQ.fetch_add(1, Ordering::Relaxed) + 1;
+        match self {
+            DhcpEvent::V4(event) => event.seq = seq,
+            DhcpEvent::V6(event) => event.seq = seq,
+            DhcpEvent::Alert(event) => event.seq = seq,
+            DhcpEvent::LeaseExpired(event) => event.seq = seq,
+        }
+    }
+}
+
+/// A threshold from the `alerts` config crossed, in either direction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AlertEvent {
+    pub timestamp: u64,
+    pub boot_id: &'static str,
+    pub seq: u64,
+    /// The setting crossed: `subnet_lease_percent` or
+    /// `no_reservation_per_minute`
+    pub alert: &'static str,
+    /// `raised` on going over the threshold, `cleared` on going back under
+    pub state: &'static str,
+    /// For `subnet_lease_percent`, the subnet in `subnets_v4`
+    pub subnet: Option<Ipv4Net>,
+    pub value: u64,
+    pub threshold: u64,
+}
+
+/// A lease that ran out without being renewed, found by
+/// [`Opt82Cache::expire_leases`](crate::opt82_cache::Opt82Cache::expire_leases).
+/// The client held the address from `leased_at` until `expired_at`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LeaseExpiredEvent {
+    pub timestamp: u64,
+    pub boot_id: &'static str,
+    pub seq: u64,
+    /// `v4` or `v6`
+    pub protocol: &'static str,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6_na: Option<Ipv6Addr>,
+    /// DHCPv4 chaddr
+    pub mac_address: Option<MacAddr6>,
+    /// DHCPv6 client DUID, as in v6 events
+    pub client_id: Option<String>,
+    /// The client's Option 82 or 18/37 when it was last leased the address
+    pub option82_circuit: Option<String>,
+    pub option82_remote: Option<String>,
+    pub option82_subscriber: Option<String>,
+    pub option1837_interface: Option<String>,
+    pub option1837_remote: Option<String>,
+    /// Unix milliseconds of the first ACK/Reply for the address, renewals
+    /// since then included
+    pub leased_at: u64,
+    /// Unix milliseconds of the last ACK/Reply
+    pub renewed_at: u64,
+    /// Unix milliseconds the lease ran out: `renewed_at` plus `lease_secs`
+    pub expired_at: u64,
+    /// Lease time (v4) or valid lifetime (v6) of the last ACK/Reply
+    pub lease_secs: u32,
+}
+
+impl LeaseExpiredEvent {
+    /// `leased_at` and `renewed_at` in Unix milliseconds
+    pub fn v4(
+        ipv4: Ipv4Addr,
+        mac: MacAddr6,
+        option82: Option<&Option82>,
+        leased_at: u64,
+        renewed_at: u64,
+        lease: Duration,
+    ) -> Self {
+        let text = |field: Option<&CompactString>| field.map(|s| s.to_string());
+        Self {
+            protocol: "v4",
+            ipv4: Some(ipv4),
+            mac_address: Some(mac),
+            option82_circuit: text(option82.and_then(|o| o.circuit.as_ref())),
+            option82_remote: text(option82.and_then(|o| o.remote.as_ref())),
+            option82_subscriber: text(option82.and_then(|o| o.subscriber.as_ref())),
+            ..Self::new(leased_at, renewed_at, lease)
+        }
+    }
+
+    /// DHCPv6 counterpart of [`Self::v4`], for an IA_NA address
+    pub fn v6(
+        ipv6_na: Ipv6Addr,
+        duid: &Duid,
+        option1837: Option<&Option1837>,
+        leased_at: u64,
+        renewed_at: u64,
+        lease: Duration,
+    ) -> Self {
+        let text = |field: Option<&CompactString>| field.map(|s| s.to_string());
+        Self {
+            protocol: "v6",
+            ipv6_na: Some(ipv6_na),
+            client_id: Some(duid.to_string()),
+            option1837_interface: text(option1837.and_then(|o| o.interface.as_ref())),
+            option1837_remote: text(option1837.and_then(|o| o.remote.as_ref())),
+            ..Self::new(leased_at, renewed_at, lease)
+        }
+    }
+
+    fn new(leased_at: u64, renewed_at: u64, lease: Duration) -> Self {
+        Self {
+            timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
+            protocol: "",
+            ipv4: None,
+            ipv6_na: None,
+            mac_address: None,
+            client_id: None,
+            option82_circuit: None,
+            option82_remote: None,
+            option82_subscriber: None,
+            option1837_interface: None,
+            option1837_remote: None,
+            leased_at,
+            renewed_at,
+            expired_at: renewed_at + lease.as_millis() as u64,
+            lease_secs: u32::try_from(lease.as_secs()).unwrap_or(u32::MAX),
+        }
+    }
 }
 
 /// DHCPv4 event for analytics - enables v4/v6 correlation via mac_address
@@ -65,7 +286,14 @@ pub struct DhcpEventV4 {
     /// Unix milliseconds. ClickHouse parses the integer directly into a
     /// `DateTime64(3)` column.
     pub timestamp: u64,
+    /// Random for each run of the server, see [`boot_id`]
+    pub boot_id: &'static str,
+    /// Counts up from 1 across every event this run sends, see
+    /// [`DhcpEvent::set_seq`]
+    pub seq: u64,
     pub message_type: Option<&'static str>,
+    /// Transaction ID from the client (hex string)
+    pub xid: String,
     pub relay_addr: Ipv4Addr,
 
     // === Request data (from client/relay) ===
@@ -73,6 +301,19 @@ pub struct DhcpEventV4 {
     pub option82_circuit: Option<CompactString>,
     pub option82_remote: Option<CompactString>,
     pub option82_subscriber: Option<CompactString>,
+    /// The sub-options that aren't UTF-8 text, hex encoded. The matching
+    /// text field is null when one of these is set.
+    pub option82_circuit_hex: Option<String>,
+    pub option82_remote_hex: Option<String>,
+    pub option82_subscriber_hex: Option<String>,
+    /// Client hostname (option 12)
+    pub client_hostname: Option<CompactString>,
+    /// Vendor class identifier (option 60)
+    pub vendor_class: Option<CompactString>,
+    /// Parameter Request List (option 55) codes, comma separated
+    pub prl_fingerprint: Option<CompactString>,
+    /// Hash of options 55 and 60, see [`crate::fingerprint`]
+    pub device_class: Option<CompactString>,
 
     // === Reservation data (what matched) ===
     pub reservation_ipv4: Option<Ipv4Addr>,
@@ -80,19 +321,42 @@ pub struct DhcpEventV4 {
     pub reservation_option82_circuit: Option<CompactString>,
     pub reservation_option82_remote: Option<CompactString>,
     pub reservation_option82_subscriber: Option<CompactString>,
+    /// `subscriber_id` of the matched reservation, for joining v4 and v6
+    pub reservation_subscriber_id: Option<CompactString>,
 
     // === Match metadata ===
-    /// How the reservation was matched: "mac", "option82"
+    /// How the reservation was matched: "client_id", "mac", "option82"
     pub match_method: Option<&'static str>,
     /// Which extractor function was used (for option82 matches)
     pub extractor_used: Option<&'static str>,
+    /// The client's value that matched, see [`ReservationMatch::key`]
+    pub match_key: Option<CompactString>,
+    /// See [`ReservationMatch::priority`]
+    pub match_priority: Option<u8>,
+    /// Another client was leased this address within the lease time
+    pub lease_conflict_mac: Option<MacAddr6>,
+
+    // === Timing (set by the worker, see `with_timing`) ===
+    /// Microseconds spent in the packet handler building the answer
+    pub handler_us: Option<u32>,
+    /// Microseconds from reading the datagram off the socket until the
+    /// answer was sent, or until the event was raised if none was
+    pub latency_us: Option<u32>,
+    /// Bytes waiting in the socket receive queue when this datagram was
+    /// read. Linux only.
+    pub socket_queue_bytes: Option<u32>,
 
     pub success: bool,
     pub failure_reason: Option<&'static str>,
+
+    /// A Request from a client that already holds its lease (ciaddr set),
+    /// for [`events.filter`](crate::config::EventFilterConfig). Not sent.
+    #[serde(skip)]
+    pub renewal: bool,
 }
 
 impl DhcpEventV4 {
-    fn message_type_str(msg_type: &v4::MessageType) -> &'static str {
+    pub(crate) fn message_type_str(msg_type: &v4::MessageType) -> &'static str {
         match msg_type {
             v4::MessageType::Ack => "Ack",
             v4::MessageType::Discover => "Discover",
@@ -105,6 +369,11 @@ impl DhcpEventV4 {
         }
     }
 
+    fn is_renewal(msg: &v4::Message) -> bool {
+        matches!(msg.message_type(), Some(v4::MessageType::Request))
+            && !msg.ciaddr().is_unspecified()
+    }
+
     fn bytes_to_compact_string(bytes: &[u8]) -> Option<CompactString> {
         CompactString::from_utf8(bytes).ok()
     }
@@ -113,70 +382,106 @@ impl DhcpEventV4 {
         msg: &v4::Message,
         relay_addr: Ipv4Addr,
         reservation: Option<&Reservation>,
-        reservation_match: Option<ReservationMatch>,
+        reservation_match: Option<&ReservationMatch>,
     ) -> Self {
         // Extract option82 from the request message
         let relay_info = msg.relay_agent_information();
+        let (option82_circuit, option82_circuit_hex) =
+            text_or_hex(relay_info.and_then(|r| r.circuit_id()));
+        let (option82_remote, option82_remote_hex) =
+            text_or_hex(relay_info.and_then(|r| r.remote_id()));
+        let (option82_subscriber, option82_subscriber_hex) =
+            text_or_hex(relay_info.and_then(|r| r.subscriber_id()));
         let res_option82 = reservation.and_then(|r| r.option82.as_ref());
 
         Self {
             timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
             message_type: msg.message_type().map(Self::message_type_str),
+            xid: format!("{:08x}", msg.xid()),
             relay_addr,
             // Request data
             mac_address: MacAddr6::try_from(msg.chaddr()).ok(),
-            option82_circuit: relay_info
-                .and_then(|r| r.circuit_id())
-                .and_then(|b| Self::bytes_to_compact_string(&b)),
-            option82_remote: relay_info
-                .and_then(|r| r.remote_id())
-                .and_then(|b| Self::bytes_to_compact_string(&b)),
-            option82_subscriber: relay_info
-                .and_then(|r| r.subscriber_id())
-                .and_then(|b| Self::bytes_to_compact_string(&b)),
+            option82_circuit,
+            option82_remote,
+            option82_subscriber,
+            option82_circuit_hex,
+            option82_remote_hex,
+            option82_subscriber_hex,
+            client_hostname: msg.hostname().map(CompactString::from),
+            vendor_class: msg.vendor_class().and_then(Self::bytes_to_compact_string),
+            prl_fingerprint: msg.prl_fingerprint().map(CompactString::from),
+            device_class: crate::fingerprint::v4(msg),
             // Reservation data
-            reservation_ipv4: reservation.map(|r| r.ipv4),
+            reservation_ipv4: reservation.and_then(|r| r.ipv4),
             reservation_mac: reservation.and_then(|r| r.mac),
             reservation_option82_circuit: res_option82.and_then(|o| o.circuit.clone()),
             reservation_option82_remote: res_option82.and_then(|o| o.remote.clone()),
             reservation_option82_subscriber: res_option82.and_then(|o| o.subscriber.clone()),
+            reservation_subscriber_id: reservation.and_then(|r| r.subscriber_id.clone()),
             // Match metadata
             match_method: reservation_match.map(|m| m.method),
             extractor_used: reservation_match.and_then(|m| m.extractor),
+            match_key: reservation_match.and_then(|m| m.key.clone()),
+            match_priority: reservation_match.and_then(|m| m.priority),
+            lease_conflict_mac: None,
+            handler_us: None,
+            latency_us: None,
+            socket_queue_bytes: None,
             success: true,
             failure_reason: None,
+            renewal: Self::is_renewal(msg),
         }
     }
 
-    pub fn failed(msg: &v4::Message, relay_addr: Ipv4Addr, reason: &'static str) -> Self {
+    pub fn failed(msg: &v4::Message, relay_addr: Ipv4Addr, reason: NoResponse) -> Self {
         let relay_info = msg.relay_agent_information();
+        let (option82_circuit, option82_circuit_hex) =
+            text_or_hex(relay_info.and_then(|r| r.circuit_id()));
+        let (option82_remote, option82_remote_hex) =
+            text_or_hex(relay_info.and_then(|r| r.remote_id()));
+        let (option82_subscriber, option82_subscriber_hex) =
+            text_or_hex(relay_info.and_then(|r| r.subscriber_id()));
 
         Self {
             timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
             message_type: msg.message_type().map(Self::message_type_str),
+            xid: format!("{:08x}", msg.xid()),
             relay_addr,
             // Request data
             mac_address: MacAddr6::try_from(msg.chaddr()).ok(),
-            option82_circuit: relay_info
-                .and_then(|r| r.circuit_id())
-                .and_then(|b| Self::bytes_to_compact_string(&b)),
-            option82_remote: relay_info
-                .and_then(|r| r.remote_id())
-                .and_then(|b| Self::bytes_to_compact_string(&b)),
-            option82_subscriber: relay_info
-                .and_then(|r| r.subscriber_id())
-                .and_then(|b| Self::bytes_to_compact_string(&b)),
+            option82_circuit,
+            option82_remote,
+            option82_subscriber,
+            option82_circuit_hex,
+            option82_remote_hex,
+            option82_subscriber_hex,
+            client_hostname: msg.hostname().map(CompactString::from),
+            vendor_class: msg.vendor_class().and_then(Self::bytes_to_compact_string),
+            prl_fingerprint: msg.prl_fingerprint().map(CompactString::from),
+            device_class: crate::fingerprint::v4(msg),
             // No reservation
             reservation_ipv4: None,
             reservation_mac: None,
             reservation_option82_circuit: None,
             reservation_option82_remote: None,
             reservation_option82_subscriber: None,
+            reservation_subscriber_id: None,
             // No match
             match_method: None,
             extractor_used: None,
+            match_key: None,
+            match_priority: None,
+            lease_conflict_mac: None,
+            handler_us: None,
+            latency_us: None,
+            socket_queue_bytes: None,
             success: false,
-            failure_reason: Some(reason),
+            failure_reason: Some(reason.as_str()),
+            renewal: Self::is_renewal(msg),
         }
     }
 
@@ -186,21 +491,39 @@ impl DhcpEventV4 {
     pub fn parse_error(relay_addr: Ipv4Addr) -> Self {
         Self {
             timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
             message_type: None,
+            xid: String::new(),
             relay_addr,
             mac_address: None,
             option82_circuit: None,
             option82_remote: None,
             option82_subscriber: None,
+            option82_circuit_hex: None,
+            option82_remote_hex: None,
+            option82_subscriber_hex: None,
+            client_hostname: None,
+            vendor_class: None,
+            prl_fingerprint: None,
+            device_class: None,
             reservation_ipv4: None,
             reservation_mac: None,
             reservation_option82_circuit: None,
             reservation_option82_remote: None,
             reservation_option82_subscriber: None,
+            reservation_subscriber_id: None,
             match_method: None,
             extractor_used: None,
+            match_key: None,
+            match_priority: None,
+            lease_conflict_mac: None,
+            handler_us: None,
+            latency_us: None,
+            socket_queue_bytes: None,
             success: false,
             failure_reason: Some("ParseError"),
+            renewal: false,
         }
     }
 
@@ -211,7 +534,7 @@ impl DhcpEventV4 {
         msg: &v4::Message,
         relay_addr: Ipv4Addr,
         reservation: Option<&Reservation>,
-        reservation_match: Option<ReservationMatch>,
+        reservation_match: Option<&ReservationMatch>,
         reason: &'static str,
     ) -> Self {
         let mut event = Self::success(msg, relay_addr, reservation, reservation_match);
@@ -219,6 +542,15 @@ impl DhcpEventV4 {
         event.failure_reason = Some(reason);
         event
     }
+
+    /// Fill in the timing fields. Called as the event is sent, so
+    /// `latency_us` covers everything the worker did with the datagram.
+    pub fn with_timing(mut self, timing: &PacketTiming) -> Self {
+        self.handler_us = timing.handler.map(micros);
+        self.latency_us = Some(micros(timing.received.elapsed()));
+        self.socket_queue_bytes = timing.socket_queue_bytes;
+        self
+    }
 }
 
 /// DHCPv6 event for analytics
@@ -232,6 +564,11 @@ pub struct DhcpEventV6 {
     /// Unix milliseconds. ClickHouse parses the integer directly into a
     /// `DateTime64(3)` column.
     pub timestamp: u64,
+    /// Random for each run of the server, see [`boot_id`]
+    pub boot_id: &'static str,
+    /// Counts up from 1 across every event this run sends, see
+    /// [`DhcpEvent::set_seq`]
+    pub seq: u64,
     pub message_type: &'static str,
     /// Transaction ID from the client (hex string)
     pub xid: String,
@@ -248,8 +585,14 @@ pub struct DhcpEventV6 {
     pub option1837_interface: Option<String>,
     /// Option 37: Remote-ID from relay agent
     pub option1837_remote: Option<String>,
+    /// Interface-ID and Remote-ID that aren't UTF-8 text, hex encoded. The
+    /// matching text field is null when one of these is set.
+    pub option1837_interface_hex: Option<String>,
+    pub option1837_remote_hex: Option<String>,
     pub requested_ipv6_na: Option<Ipv6Addr>,
     pub requested_ipv6_pd: Option<Ipv6Net>,
+    /// Hash of options 6 and 16, see [`crate::fingerprint`]
+    pub device_class: Option<CompactString>,
 
     // === Reservation data (what matched) ===
     pub reservation_ipv6_na: Option<Ipv6Addr>,
@@ -259,19 +602,42 @@ pub struct DhcpEventV6 {
     pub reservation_duid: Option<String>,
     pub reservation_option1837_interface: Option<String>,
     pub reservation_option1837_remote: Option<String>,
+    /// `subscriber_id` of the matched reservation, for joining v4 and v6
+    pub reservation_subscriber_id: Option<String>,
 
     // === Match metadata ===
     /// How the reservation was matched: "mac", "duid", "option1837"
     pub match_method: Option<&'static str>,
     /// Which extractor function was used (for option1837 matches)
     pub extractor_used: Option<&'static str>,
+    /// The client's value that matched, see [`ReservationMatch::key`]
+    pub match_key: Option<CompactString>,
+    /// See [`ReservationMatch::priority`]
+    pub match_priority: Option<u8>,
+    /// Client hinted at an IA_NA address other than the reserved one
+    pub ipv6_na_hint_mismatch: bool,
+    /// Client hinted at an IA_PD prefix, or prefix length, other than the
+    /// reserved one
+    pub ipv6_pd_hint_mismatch: bool,
+    /// DUID of another client leased this address within the lease time
+    pub lease_conflict_duid: Option<String>,
+
+    // === Timing (set by the worker, see `with_timing`) ===
+    /// Microseconds spent in the packet handler building the answer
+    pub handler_us: Option<u32>,
+    /// Microseconds from reading the datagram off the socket until the
+    /// answer was sent, or until the event was raised if none was
+    pub latency_us: Option<u32>,
+    /// Bytes waiting in the socket receive queue when this datagram was
+    /// read. Linux only.
+    pub socket_queue_bytes: Option<u32>,
 
     pub success: bool,
     pub failure_reason: Option<&'static str>,
 }
 
 impl DhcpEventV6 {
-    fn message_type_str(msg_type: MessageType) -> &'static str {
+    pub(crate) fn message_type_str(msg_type: MessageType) -> &'static str {
         match msg_type {
             MessageType::Solicit => "Solicit",
             MessageType::Advertise => "Advertise",
@@ -295,13 +661,16 @@ impl DhcpEventV6 {
         relay_msg: &v6::RelayMessage,
         relay_addr: Ipv6Addr,
         reservation: Option<&Reservation>,
-        reservation_match: Option<ReservationMatch>,
+        reservation_match: Option<&ReservationMatch>,
     ) -> Self {
         let option1837 = relay_msg.option1837();
+        let (option1837_interface_hex, option1837_remote_hex) = option1837_hex(relay_msg);
         let res_option1837 = reservation.and_then(|r| r.option1837.as_ref());
 
         DhcpEventV6 {
             timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
             message_type: Self::message_type_str(input_msg.msg_type()),
             xid: format!(
                 "{:02x}{:02x}{:02x}",
@@ -324,12 +693,15 @@ impl DhcpEventV6 {
             option1837_remote: option1837
                 .as_ref()
                 .and_then(|o| o.remote.as_ref().map(|s| s.to_string())),
+            option1837_interface_hex,
+            option1837_remote_hex,
             requested_ipv6_na: input_msg.ia_na_address(),
             requested_ipv6_pd: input_msg.ia_pd_prefix(),
+            device_class: crate::fingerprint::v6(input_msg),
             // Reservation data
-            reservation_ipv6_na: reservation.map(|r| r.ipv6_na),
+            reservation_ipv6_na: reservation.and_then(|r| r.ipv6_na),
             reservation_ipv6_pd: reservation.map(|r| r.ipv6_pd),
-            reservation_ipv4: reservation.map(|r| r.ipv4),
+            reservation_ipv4: reservation.and_then(|r| r.ipv4),
             reservation_mac: reservation.and_then(|r| r.mac),
             reservation_duid: reservation
                 .and_then(|r| r.duid.as_ref())
@@ -338,9 +710,23 @@ impl DhcpEventV6 {
                 .and_then(|o| o.interface.as_ref().map(|s| s.to_string())),
             reservation_option1837_remote: res_option1837
                 .and_then(|o| o.remote.as_ref().map(|s| s.to_string())),
+            reservation_subscriber_id: reservation
+                .and_then(|r| r.subscriber_id.as_ref())
+                .map(|s| s.to_string()),
             // Match metadata
             match_method: reservation_match.map(|m| m.method),
             extractor_used: reservation_match.and_then(|m| m.extractor),
+            match_key: reservation_match.and_then(|m| m.key.clone()),
+            match_priority: reservation_match.and_then(|m| m.priority),
+            ipv6_na_hint_mismatch: reservation
+                .and_then(|r| r.ipv6_na)
+                .is_some_and(|na| input_msg.ia_na_hint_differs(na)),
+            ipv6_pd_hint_mismatch: reservation
+                .is_some_and(|r| input_msg.ia_pd_hint_differs(r.ipv6_pd)),
+            lease_conflict_duid: None,
+            handler_us: None,
+            latency_us: None,
+            socket_queue_bytes: None,
             success: true,
             failure_reason: None,
         }
@@ -350,12 +736,15 @@ impl DhcpEventV6 {
         input_msg: &v6::Message,
         relay_msg: &v6::RelayMessage,
         relay_addr: Ipv6Addr,
-        reason: &'static str,
+        reason: NoResponse,
     ) -> DhcpEventV6 {
         let option1837 = relay_msg.option1837();
+        let (option1837_interface_hex, option1837_remote_hex) = option1837_hex(relay_msg);
 
         DhcpEventV6 {
             timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
             message_type: Self::message_type_str(input_msg.msg_type()),
             xid: format!(
                 "{:02x}{:02x}{:02x}",
@@ -378,8 +767,11 @@ impl DhcpEventV6 {
             option1837_remote: option1837
                 .as_ref()
                 .and_then(|o| o.remote.as_ref().map(|s| s.to_string())),
+            option1837_interface_hex,
+            option1837_remote_hex,
             requested_ipv6_na: input_msg.ia_na_address(),
             requested_ipv6_pd: input_msg.ia_pd_prefix(),
+            device_class: crate::fingerprint::v6(input_msg),
             // No reservation
             reservation_ipv6_na: None,
             reservation_ipv6_pd: None,
@@ -388,11 +780,20 @@ impl DhcpEventV6 {
             reservation_duid: None,
             reservation_option1837_interface: None,
             reservation_option1837_remote: None,
+            reservation_subscriber_id: None,
             // No match
             match_method: None,
             extractor_used: None,
+            match_key: None,
+            match_priority: None,
+            ipv6_na_hint_mismatch: false,
+            ipv6_pd_hint_mismatch: false,
+            lease_conflict_duid: None,
+            handler_us: None,
+            latency_us: None,
+            socket_queue_bytes: None,
             success: false,
-            failure_reason: Some(reason),
+            failure_reason: Some(reason.as_str()),
         }
     }
 
@@ -402,6 +803,8 @@ impl DhcpEventV6 {
     pub fn parse_error(relay_addr: Ipv6Addr) -> Self {
         Self {
             timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
             message_type: "Unknown",
             xid: String::new(),
             relay_addr,
@@ -411,8 +814,11 @@ impl DhcpEventV6 {
             client_id: None,
             option1837_interface: None,
             option1837_remote: None,
+            option1837_interface_hex: None,
+            option1837_remote_hex: None,
             requested_ipv6_na: None,
             requested_ipv6_pd: None,
+            device_class: None,
             reservation_ipv6_na: None,
             reservation_ipv6_pd: None,
             reservation_ipv4: None,
@@ -420,8 +826,17 @@ impl DhcpEventV6 {
             reservation_duid: None,
             reservation_option1837_interface: None,
             reservation_option1837_remote: None,
+            reservation_subscriber_id: None,
             match_method: None,
             extractor_used: None,
+            match_key: None,
+            match_priority: None,
+            ipv6_na_hint_mismatch: false,
+            ipv6_pd_hint_mismatch: false,
+            lease_conflict_duid: None,
+            handler_us: None,
+            latency_us: None,
+            socket_queue_bytes: None,
             success: false,
             failure_reason: Some("ParseError"),
         }
@@ -437,9 +852,12 @@ impl DhcpEventV6 {
         reason: &'static str,
     ) -> Self {
         let option1837 = relay_msg.option1837();
+        let (option1837_interface_hex, option1837_remote_hex) = option1837_hex(relay_msg);
 
         Self {
             timestamp: now(),
+            boot_id: boot_id(),
+            seq: 0,
             message_type: "Unknown",
             xid: String::new(),
             relay_addr,
@@ -453,8 +871,11 @@ impl DhcpEventV6 {
             option1837_remote: option1837
                 .as_ref()
                 .and_then(|o| o.remote.as_ref().map(|s| s.to_string())),
+            option1837_interface_hex,
+            option1837_remote_hex,
             requested_ipv6_na: None,
             requested_ipv6_pd: None,
+            device_class: None,
             reservation_ipv6_na: None,
             reservation_ipv6_pd: None,
             reservation_ipv4: None,
@@ -462,8 +883,17 @@ impl DhcpEventV6 {
             reservation_duid: None,
             reservation_option1837_interface: None,
             reservation_option1837_remote: None,
+            reservation_subscriber_id: None,
             match_method: None,
             extractor_used: None,
+            match_key: None,
+            match_priority: None,
+            ipv6_na_hint_mismatch: false,
+            ipv6_pd_hint_mismatch: false,
+            lease_conflict_duid: None,
+            handler_us: None,
+            latency_us: None,
+            socket_queue_bytes: None,
             success: false,
             failure_reason: Some(reason),
         }
@@ -476,7 +906,7 @@ impl DhcpEventV6 {
         relay_msg: &v6::RelayMessage,
         relay_addr: Ipv6Addr,
         reservation: Option<&Reservation>,
-        reservation_match: Option<ReservationMatch>,
+        reservation_match: Option<&ReservationMatch>,
         reason: &'static str,
     ) -> Self {
         let mut event = Self::success(
@@ -490,9 +920,95 @@ impl DhcpEventV6 {
         event.failure_reason = Some(reason);
         event
     }
+
+    /// Fill in the timing fields. Called as the event is sent, so
+    /// `latency_us` covers everything the worker did with the datagram.
+    pub fn with_timing(mut self, timing: &PacketTiming) -> Self {
+        self.handler_us = timing.handler.map(micros);
+        self.latency_us = Some(micros(timing.received.elapsed()));
+        self.socket_queue_bytes = timing.socket_queue_bytes;
+        self
+    }
+}
+
+/// A relay sub-option as text if it is UTF-8, otherwise hex encoded, so
+/// binary identifiers still reach analytics
+fn text_or_hex(bytes: Option<Vec<u8>>) -> (Option<CompactString>, Option<String>) {
+    let Some(bytes) = bytes else {
+        return (None, None);
+    };
+    match CompactString::from_utf8(&bytes) {
+        Ok(text) => (Some(text), None),
+        Err(_) => (None, Some(to_hex(&bytes))),
+    }
+}
+
+/// Hex of the Interface-ID and Remote-ID in `relay_msg` that
+/// [`ShadowRelayMessageExtV6::option1837`] leaves out for not being UTF-8
+fn option1837_hex(relay_msg: &v6::RelayMessage) -> (Option<String>, Option<String>) {
+    let hex = |bytes: &[u8]| std::str::from_utf8(bytes).is_err().then(|| to_hex(bytes));
+    let mut interface = None;
+    let mut remote = None;
+    for opt in relay_msg.opts().iter() {
+        match opt {
+            v6::DhcpOption::InterfaceId(id) => interface = hex(id),
+            v6::DhcpOption::RemoteId(remote_id) => remote = hex(&remote_id.id),
+            _ => (),
+        }
+    }
+    (interface, remote)
+}
+
+/// Timing the worker records for one datagram, copied into its event by
+/// `with_timing`.
+#[derive(Debug, Clone, Copy)]
+pub struct PacketTiming {
+    received: Instant,
+    handler: Option<Duration>,
+    socket_queue_bytes: Option<u32>,
+}
+
+impl PacketTiming {
+    /// Start timing a datagram that has just been read off the socket
+    pub fn received(socket_queue_bytes: Option<u32>) -> Self {
+        Self {
+            received: Instant::now(),
+            handler: None,
+            socket_queue_bytes,
+        }
+    }
+
+    /// Run the packet handler, recording how long it took
+    pub fn handle<T>(&mut self, handler: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let response = handler();
+        self.handler = Some(start.elapsed());
+        response
+    }
+}
+
+fn micros(duration: Duration) -> u32 {
+    u32::try_from(duration.as_micros()).unwrap_or(u32::MAX)
+}
+
+/// Last `seq` handed out
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// 32 hex digits, picked at startup and sent with every event so a restart,
+/// which starts `seq` over, can't be mistaken for the run before it
+pub(crate) fn boot_id() -> &'static str {
+    static BOOT_ID: OnceLock<String> = OnceLock::new();
+    BOOT_ID.get_or_init(|| {
+        // std seeds each RandomState from the OS; mixing in the time and pid
+        // keeps two ids apart even if the seeds were to repeat
+        let seed = (now(), std::process::id());
+        let high = RandomState::new().hash_one(seed);
+        let low = RandomState::new().hash_one(seed);
+        format!("{high:016x}{low:016x}")
+    })
 }
 
-fn now() -> u64 {
+pub(crate) fn now() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
@@ -1,5 +1,7 @@
 mod batch;
 pub mod events;
+pub mod syslog;
+pub mod watch;
 pub mod writer;
 
 #[cfg(feature = "clickhouse")]
@@ -7,54 +9,279 @@ pub mod clickhouse;
 #[cfg(feature = "clickhouse")]
 mod clickhouse_http;
 
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 
 use crate::analytics::events::DhcpEvent;
+use crate::config::EventFilterConfig;
+
+/// Bytes allocated to the socket's receive queue, from `SO_MEMINFO`. This
+/// counts every queued datagram including kernel overhead, unlike
+/// `FIONREAD` which only reports the next datagram on a UDP socket.
+#[cfg(target_os = "linux")]
+pub fn socket_queue_bytes(socket: &UdpSocket) -> Option<u32> {
+    use std::os::fd::AsRawFd;
+
+    // SK_MEMINFO_VARS entries; the kernel copies as many as fit
+    let mut meminfo = [0u32; 9];
+    let mut len = std::mem::size_of_val(&meminfo) as libc::socklen_t;
+    // SAFETY: meminfo and len outlive the call and len is meminfo's size
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MEMINFO,
+            meminfo.as_mut_ptr().cast(),
+            &mut len,
+        )
+    };
+    // SK_MEMINFO_RMEM_ALLOC
+    (ret == 0).then_some(meminfo[0])
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn socket_queue_bytes(_socket: &UdpSocket) -> Option<u32> {
+    None
+}
+
+/// /etc/hostname is a one-liner on Linux/BSD. Empty on macOS/Windows is fine
+/// — the ClickHouse schema defaults `host_name` to `''`, and syslog sends `-`.
+pub fn read_hostname() -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
+}
 
 /// Per-sink fan-out target: the bounded channel and a drop counter shared
 /// with the writer thread so it can report producer-side drops on each cycle.
+/// `queued` counts events in the channel; the writer takes one off for each
+/// event it receives.
 #[derive(Clone)]
 struct EventSinkChannel {
     tx: mpsc::SyncSender<DhcpEvent>,
     dropped: Arc<AtomicU64>,
+    queued: Arc<AtomicUsize>,
 }
 
 /// Fan-out to every enabled event sink. Channels are bounded; a full queue
 /// drops the event at the producer rather than back-pressuring the DHCP hot
 /// path. Each drop is counted in the sink's shared `dropped` counter, which
-/// the writer thread reads and logs once per flush cycle.
+/// the writer thread reads and logs once per flush cycle. Events the filter
+/// turns away never reach a channel.
 #[derive(Clone, Default)]
-pub struct EventSenders(Vec<EventSinkChannel>);
+pub struct EventSenders {
+    sinks: Vec<EventSinkChannel>,
+    filter: Option<Arc<EventFilter>>,
+}
 
 impl EventSenders {
     pub fn new() -> Self {
-        Self(Vec::new())
+        Self::default()
+    }
+
+    pub fn with_filter(mut self, config: &EventFilterConfig) -> Self {
+        self.filter = Some(Arc::new(EventFilter::new(config)));
+        self
     }
 
-    pub fn push(&mut self, tx: mpsc::SyncSender<DhcpEvent>, dropped: Arc<AtomicU64>) {
-        self.0.push(EventSinkChannel { tx, dropped });
+    pub fn push(
+        &mut self,
+        tx: mpsc::SyncSender<DhcpEvent>,
+        dropped: Arc<AtomicU64>,
+        queued: Arc<AtomicUsize>,
+    ) {
+        self.sinks.push(EventSinkChannel {
+            tx,
+            dropped,
+            queued,
+        });
+    }
+
+    pub fn queue_depth(&self) -> QueueDepth {
+        QueueDepth(self.sinks.iter().map(|sink| sink.queued.clone()).collect())
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.sinks.is_empty()
     }
 
-    pub fn send(&self, event: DhcpEvent) {
+    pub fn send(&self, mut event: DhcpEvent) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !filter.keep(&event))
+        {
+            return;
+        }
+        event.set_seq();
         // Move `event` into the last sink instead of cloning, so the common
         // single-sink case doesn't clone at all.
-        let Some((last, rest)) = self.0.split_last() else {
+        let Some((last, rest)) = self.sinks.split_last() else {
             return;
         };
 
         for sink in rest {
-            if sink.tx.try_send(event.clone()).is_err() {
-                sink.dropped.fetch_add(1, Ordering::Relaxed);
-            }
+            sink.send(event.clone());
+        }
+        last.send(event);
+    }
+}
+
+impl EventSinkChannel {
+    /// Counted as queued before sending, so the writer can't take it off
+    /// the count first
+    fn send(&self, event: DhcpEvent) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        if self.tx.try_send(event).is_err() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// `events.filter`, shared by every worker so renewals are sampled across
+/// all of them
+struct EventFilter {
+    successes: bool,
+    renewal_sample: u32,
+    renewals: AtomicU64,
+}
+
+impl EventFilter {
+    fn new(config: &EventFilterConfig) -> Self {
+        Self {
+            successes: config.successes,
+            renewal_sample: config.renewal_sample,
+            renewals: AtomicU64::new(0),
+        }
+    }
+
+    /// Failures always pass. Successful renewals are sampled, one in every
+    /// `renewal_sample`, and other successes pass if `successes` is set.
+    fn keep(&self, event: &DhcpEvent) -> bool {
+        if !event.success() {
+            return true;
+        }
+        if !self.successes {
+            return false;
+        }
+        if !event.is_renewal() {
+            return true;
         }
-        if last.tx.try_send(event).is_err() {
-            last.dropped.fetch_add(1, Ordering::Relaxed);
+        match self.renewal_sample {
+            0 => false,
+            1 => true,
+            n => self.renewals.fetch_add(1, Ordering::Relaxed) % u64::from(n) == 0,
         }
     }
 }
+
+/// Events waiting in the sink channels, summed over every sink
+#[derive(Clone, Default)]
+pub struct QueueDepth(Vec<Arc<AtomicUsize>>);
+
+impl QueueDepth {
+    pub fn get(&self) -> usize {
+        self.0
+            .iter()
+            .map(|queued| queued.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::events::DhcpEventV4;
+    use crate::outcome::NoResponse;
+    use dhcproto::v4;
+    use std::net::Ipv4Addr;
+
+    fn request(ciaddr: Ipv4Addr) -> DhcpEvent {
+        let mut msg = v4::Message::default();
+        msg.set_ciaddr(ciaddr);
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Request));
+        DhcpEvent::V4(DhcpEventV4::success(
+            &msg,
+            Ipv4Addr::new(192, 0, 2, 1),
+            None,
+            None,
+        ))
+    }
+
+    #[test]
+    fn filter_samples_renewals_and_keeps_failures() {
+        let filter = |successes, renewal_sample| {
+            let (tx, rx) = mpsc::sync_channel(16);
+            let mut senders = EventSenders::new().with_filter(&EventFilterConfig {
+                successes,
+                renewal_sample,
+            });
+            senders.push(tx, Arc::default(), Arc::default());
+            (senders, rx)
+        };
+        let renewal = || request(Ipv4Addr::new(100, 64, 0, 1));
+        let first_lease = || request(Ipv4Addr::UNSPECIFIED);
+        let failure = || {
+            let msg = v4::Message::default();
+            let relay = Ipv4Addr::new(192, 0, 2, 1);
+            DhcpEvent::V4(DhcpEventV4::failed(&msg, relay, NoResponse::NoReservation))
+        };
+        assert!(renewal().is_renewal());
+        assert!(!first_lease().is_renewal());
+
+        let (senders, rx) = filter(true, 3);
+        for _ in 0..6 {
+            senders.send(renewal());
+        }
+        senders.send(first_lease());
+        assert_eq!(
+            rx.try_iter().count(),
+            3,
+            "2 of 6 renewals and the first lease"
+        );
+
+        let (senders, rx) = filter(true, 0);
+        senders.send(renewal());
+        senders.send(first_lease());
+        senders.send(failure());
+        assert_eq!(rx.try_iter().count(), 2);
+
+        let (senders, rx) = filter(false, 1);
+        senders.send(renewal());
+        senders.send(first_lease());
+        senders.send(failure());
+        let sent: Vec<_> = rx.try_iter().collect();
+        assert_eq!(sent.len(), 1);
+        assert!(!sent[0].success());
+    }
+
+    #[test]
+    fn every_sink_sees_the_same_sequence() {
+        let mut senders = EventSenders::new();
+        let (first_tx, first) = mpsc::sync_channel(4);
+        let (second_tx, second) = mpsc::sync_channel(4);
+        senders.push(first_tx, Arc::default(), Arc::default());
+        senders.push(second_tx, Arc::default(), Arc::default());
+        senders.send(request(Ipv4Addr::UNSPECIFIED));
+        senders.send(request(Ipv4Addr::UNSPECIFIED));
+
+        let numbered = |rx: mpsc::Receiver<DhcpEvent>| -> Vec<(&'static str, u64)> {
+            rx.try_iter()
+                .map(|event| match event {
+                    DhcpEvent::V4(event) => (event.boot_id, event.seq),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        let first = numbered(first);
+        assert_eq!(first, numbered(second));
+        // Other tests send events too, so only the order is certain
+        assert!(first[0].1 > 0 && first[1].1 > first[0].1);
+        assert_eq!(first[0].0, first[1].0);
+        assert_eq!(first[0].0.len(), 32);
+    }
+}
@@ -0,0 +1,478 @@
+//! Lease events as RFC 5424 syslog messages, from `logging.syslog`, for
+//! collectors that take nothing else.
+//!
+//! Each DHCPv4 or DHCPv6 event becomes one message: the client's message
+//! type as MSGID, a structured data element with the lease fields, and a
+//! one-line summary for people reading it. Grants and releases are sent at
+//! `info`, denials at `notice`. Alert events aren't leases and aren't sent,
+//! nor are lease expiries, which no client message stands behind.
+//!
+//! UDP sends a datagram per message (RFC 5426). TCP frames each message
+//! with its length (RFC 6587 octet counting) and reconnects with backoff; a
+//! batch cut short by a dropped connection is sent again in full, so the
+//! collector may see some messages twice. The event's `boot_id` and `seq`
+//! are in the structured data to tell them apart.
+
+use std::fmt::Write as _;
+use std::io::{self, BufWriter, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use compact_str::CompactString;
+use tracing::{info, warn};
+
+use crate::analytics::batch::{run, BatchConfig, BatchSink};
+use crate::analytics::events::{DhcpEvent, DhcpEventV4, DhcpEventV6};
+use crate::analytics::read_hostname;
+use crate::config::{SyslogConfig, SyslogProtocol};
+use crate::shutdown::Shutdown;
+use crate::types::Timestamp;
+
+const MAX_BATCH: usize = 256;
+/// Kept short: syslog collectors expect messages as they happen
+const MAX_BATCH_LATENCY: Duration = Duration::from_secs(1);
+const RETRY_SLEEP: Duration = Duration::from_secs(3);
+const MAX_RETRIES: u32 = 100;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+const APP_NAME: &str = "shadowdhcp";
+const SEVERITY_NOTICE: u8 = 5;
+const SEVERITY_INFO: u8 = 6;
+
+/// The parts of the header that are the same for every message
+pub struct Header {
+    pub facility: u8,
+    /// `-` when unknown, as RFC 5424 has it
+    pub hostname: String,
+    pub procid: u32,
+    pub sd_id: String,
+}
+
+impl Header {
+    pub fn new(cfg: &SyslogConfig) -> Self {
+        let hostname = cfg.hostname.clone().unwrap_or_else(read_hostname);
+        Self {
+            facility: cfg.facility.code(),
+            hostname: header_field(&hostname, 255),
+            procid: std::process::id(),
+            sd_id: cfg.sd_id.clone(),
+        }
+    }
+}
+
+/// A header field is printable ASCII without spaces, or `-` for none
+fn header_field(value: &str, max_len: usize) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    if value.is_empty() {
+        "-".to_string()
+    } else {
+        value
+    }
+}
+
+/// Grant, release or deny, from the event's outcome and message type
+fn result(event: &DhcpEvent) -> &'static str {
+    let message_type = match event {
+        DhcpEvent::V4(event) => event.message_type.unwrap_or("Unknown"),
+        DhcpEvent::V6(event) => event.message_type,
+        DhcpEvent::Alert(_) => "Alert",
+        DhcpEvent::LeaseExpired(_) => "LeaseExpired",
+    };
+    if !event.success() {
+        "deny"
+    } else if matches!(message_type, "Release" | "Decline") {
+        "release"
+    } else {
+        "grant"
+    }
+}
+
+/// The RFC 5424 message for `event`, without framing. `None` for alerts and
+/// lease expiries.
+pub fn format(header: &Header, event: &DhcpEvent) -> Option<String> {
+    let (timestamp, message_type, params, summary) = match event {
+        DhcpEvent::V4(v4) => (
+            v4.timestamp,
+            v4.message_type.unwrap_or("Unknown"),
+            params_v4(v4),
+            summary_v4(v4, result(event)),
+        ),
+        DhcpEvent::V6(v6) => (
+            v6.timestamp,
+            v6.message_type,
+            params_v6(v6),
+            summary_v6(v6, result(event)),
+        ),
+        DhcpEvent::Alert(_) | DhcpEvent::LeaseExpired(_) => return None,
+    };
+    let severity = if event.success() {
+        SEVERITY_INFO
+    } else {
+        SEVERITY_NOTICE
+    };
+    let mut message = format!(
+        "<{}>1 {} {} {APP_NAME} {} {} [{}",
+        header.facility * 8 + severity,
+        Timestamp::from_unix_millis(timestamp),
+        header.hostname,
+        header.procid,
+        header_field(message_type, 32),
+        header.sd_id,
+    );
+    let result = result(event).to_string();
+    for (name, value) in [("result", Some(result))].into_iter().chain(params) {
+        if let Some(value) = value {
+            let _ = write!(message, " {name}=\"{}\"", escape_param(&value));
+        }
+    }
+    message.push_str("] ");
+    message.push_str(&summary);
+    Some(message)
+}
+
+/// `"`, `\` and `]` are escaped in a PARAM-VALUE (RFC 5424 §6.3.3)
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+type Params = Vec<(&'static str, Option<String>)>;
+
+fn params_v4(event: &DhcpEventV4) -> Params {
+    let text = |value: &Option<CompactString>| value.as_ref().map(|v| v.to_string());
+    vec![
+        ("ip_version", Some("v4".to_string())),
+        ("xid", Some(event.xid.clone()).filter(|xid| !xid.is_empty())),
+        ("mac", event.mac_address.map(|mac| mac.to_string())),
+        ("ipv4", event.reservation_ipv4.map(|ip| ip.to_string())),
+        ("relay", Some(event.relay_addr.to_string())),
+        (
+            "circuit_id",
+            text(&event.option82_circuit).or(event.option82_circuit_hex.clone()),
+        ),
+        (
+            "remote_id",
+            text(&event.option82_remote).or(event.option82_remote_hex.clone()),
+        ),
+        ("subscriber_id", text(&event.reservation_subscriber_id)),
+        ("hostname", text(&event.client_hostname)),
+        ("reason", event.failure_reason.map(str::to_string)),
+        ("boot_id", Some(event.boot_id.to_string())),
+        ("seq", Some(event.seq.to_string())),
+    ]
+}
+
+fn params_v6(event: &DhcpEventV6) -> Params {
+    vec![
+        ("ip_version", Some("v6".to_string())),
+        ("xid", Some(event.xid.clone()).filter(|xid| !xid.is_empty())),
+        ("mac", event.mac_address.map(|mac| mac.to_string())),
+        ("client_id", event.client_id.clone()),
+        (
+            "ipv6_na",
+            event.reservation_ipv6_na.map(|ip| ip.to_string()),
+        ),
+        (
+            "ipv6_pd",
+            event.reservation_ipv6_pd.map(|pd| pd.to_string()),
+        ),
+        ("relay", Some(event.relay_addr.to_string())),
+        (
+            "circuit_id",
+            event
+                .option1837_interface
+                .clone()
+                .or(event.option1837_interface_hex.clone()),
+        ),
+        (
+            "remote_id",
+            event
+                .option1837_remote
+                .clone()
+                .or(event.option1837_remote_hex.clone()),
+        ),
+        ("subscriber_id", event.reservation_subscriber_id.clone()),
+        ("reason", event.failure_reason.map(str::to_string)),
+        ("boot_id", Some(event.boot_id.to_string())),
+        ("seq", Some(event.seq.to_string())),
+    ]
+}
+
+fn summary_v4(event: &DhcpEventV4, result: &str) -> String {
+    let client = event
+        .mac_address
+        .map_or_else(|| "unknown client".to_string(), |mac| mac.to_string());
+    let mut summary = format!(
+        "DHCPv4 {} from {client} via {}",
+        event.message_type.unwrap_or("Unknown"),
+        event.relay_addr
+    );
+    outcome(
+        &mut summary,
+        result,
+        event.reservation_ipv4.map(|ip: Ipv4Addr| ip.to_string()),
+        event.failure_reason,
+    );
+    summary
+}
+
+fn summary_v6(event: &DhcpEventV6, result: &str) -> String {
+    let client = event
+        .client_id
+        .clone()
+        .or(event.mac_address.map(|mac| mac.to_string()))
+        .unwrap_or_else(|| "unknown client".to_string());
+    let mut summary = format!(
+        "DHCPv6 {} from {client} via {}",
+        event.message_type, event.relay_addr
+    );
+    let leased: Vec<String> = [
+        event.reservation_ipv6_na.map(|ip: Ipv6Addr| ip.to_string()),
+        event.reservation_ipv6_pd.map(|pd| pd.to_string()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let leased = (!leased.is_empty()).then(|| leased.join(" and "));
+    outcome(&mut summary, result, leased, event.failure_reason);
+    summary
+}
+
+fn outcome(summary: &mut String, result: &str, leased: Option<String>, reason: Option<&str>) {
+    match (result, leased) {
+        ("deny", _) => {
+            let _ = write!(summary, " denied: {}", reason.unwrap_or("unknown"));
+        }
+        ("release", _) => summary.push_str(" acknowledged"),
+        (_, Some(leased)) => {
+            let _ = write!(summary, " granted {leased}");
+        }
+        (_, None) => summary.push_str(" answered"),
+    }
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(BufWriter<TcpStream>),
+}
+
+impl Connection {
+    fn connect(address: &str, protocol: SyslogProtocol) -> io::Result<Self> {
+        let addr: SocketAddr = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "address resolved to nothing")
+        })?;
+        match protocol {
+            SyslogProtocol::Udp => {
+                let bind: SocketAddr = match addr {
+                    SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                    SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+                };
+                let socket = UdpSocket::bind(bind)?;
+                socket.connect(addr)?;
+                Ok(Connection::Udp(socket))
+            }
+            SyslogProtocol::Tcp => {
+                let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                Ok(Connection::Tcp(BufWriter::new(stream)))
+            }
+        }
+    }
+
+    fn send(&mut self, messages: &[String]) -> io::Result<()> {
+        match self {
+            Connection::Udp(socket) => {
+                for message in messages {
+                    socket.send(message.as_bytes())?;
+                }
+                Ok(())
+            }
+            Connection::Tcp(writer) => {
+                for message in messages {
+                    write!(writer, "{} {message}", message.len())?;
+                }
+                writer.flush()
+            }
+        }
+    }
+}
+
+struct SyslogSink {
+    address: String,
+    protocol: SyslogProtocol,
+    header: Header,
+    connection: Option<Connection>,
+    batch: Vec<String>,
+    dropped: Arc<AtomicU64>,
+    queued: Arc<AtomicUsize>,
+    backoff: Duration,
+    next_connect: Instant,
+}
+
+impl BatchSink<DhcpEvent> for SyslogSink {
+    fn reset(&mut self) {
+        self.batch.clear();
+    }
+
+    fn push(&mut self, ev: DhcpEvent) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.batch.extend(format(&self.header, &ev));
+    }
+
+    fn item_count(&self) -> usize {
+        self.batch.len()
+    }
+
+    /// Connect if needed and the backoff allows it, then send the batch.
+    /// Any failure drops the connection.
+    fn flush(&mut self) -> Result<(), ()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        if self.connection.is_none() {
+            if Instant::now() < self.next_connect {
+                return Err(());
+            }
+            match Connection::connect(&self.address, self.protocol) {
+                Ok(connection) => {
+                    if self.backoff > RECONNECT_BACKOFF_MIN {
+                        info!("Reconnected to syslog collector {}", self.address);
+                    }
+                    self.backoff = RECONNECT_BACKOFF_MIN;
+                    self.connection = Some(connection);
+                }
+                Err(e) => {
+                    if self.backoff == RECONNECT_BACKOFF_MIN {
+                        warn!(
+                            "Syslog collector {} unreachable, retrying with backoff: {e}",
+                            self.address
+                        );
+                    }
+                    self.next_connect = Instant::now() + self.backoff;
+                    self.backoff = (self.backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                    return Err(());
+                }
+            }
+        }
+        let connection = self.connection.as_mut().expect("connected above");
+        if let Err(e) = connection.send(&self.batch) {
+            warn!("Failed to send to syslog collector {}: {e}", self.address);
+            self.connection = None;
+            return Err(());
+        }
+        Ok(())
+    }
+
+    fn on_start(&mut self) {
+        info!(
+            "Starting syslog writer sending to {} over {:?}",
+            self.address, self.protocol
+        );
+    }
+
+    fn on_cycle_complete(&mut self) {
+        let n = self.dropped.swap(0, Ordering::Relaxed);
+        if n > 0 {
+            warn!("Dropped {n} syslog messages at sender (channel full)");
+        }
+    }
+
+    fn on_giveup(&mut self) {
+        if !self.batch.is_empty() {
+            warn!(
+                "Syslog writer dropped batch of {} after exhausted retries",
+                self.batch.len()
+            );
+        }
+    }
+}
+
+pub fn syslog_writer(
+    cfg: SyslogConfig,
+    rx: mpsc::Receiver<DhcpEvent>,
+    dropped: Arc<AtomicU64>,
+    queued: Arc<AtomicUsize>,
+    shutdown: Shutdown,
+) {
+    let mut sink = SyslogSink {
+        header: Header::new(&cfg),
+        address: cfg.address,
+        protocol: cfg.protocol,
+        connection: None,
+        batch: Vec::with_capacity(MAX_BATCH),
+        dropped,
+        queued,
+        backoff: RECONNECT_BACKOFF_MIN,
+        next_connect: Instant::now(),
+    };
+    run(
+        rx,
+        &mut sink,
+        BatchConfig {
+            max_batch: MAX_BATCH,
+            max_latency: MAX_BATCH_LATENCY,
+            retry_sleep: RETRY_SLEEP,
+            max_retries: MAX_RETRIES,
+        },
+        &shutdown,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outcome::NoResponse;
+    use dhcproto::v4;
+
+    #[test]
+    fn lease_events_as_rfc5424() {
+        let header = Header {
+            facility: 16,
+            hostname: header_field("dhcp 01", 255),
+            procid: 4321,
+            sd_id: "lease@32473".to_string(),
+        };
+        let relay = Ipv4Addr::new(192, 0, 2, 1);
+        let mut msg = v4::Message::new(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            relay,
+            &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55],
+        );
+        msg.set_xid(0x1234);
+        msg.opts_mut()
+            .insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        let mut event = DhcpEventV4::failed(&msg, relay, NoResponse::NoReservation);
+        event.timestamp = 1_704_067_200_123;
+        event.seq = 7;
+        event.client_hostname = Some("cpe \"lab\"]".into());
+
+        let message = format(&header, &DhcpEvent::V4(event)).unwrap();
+        let expected = format!(
+            "<133>1 2024-01-01T00:00:00.123Z dhcp01 shadowdhcp 4321 Discover \
+             [lease@32473 result=\"deny\" ip_version=\"v4\" xid=\"00001234\" \
+             mac=\"00-11-22-33-44-55\" relay=\"192.0.2.1\" hostname=\"cpe \\\"lab\\\"\\]\" \
+             reason=\"NoReservation\" boot_id=\"{}\" seq=\"7\"] \
+             DHCPv4 Discover from 00-11-22-33-44-55 via 192.0.2.1 denied: NoReservation",
+            crate::analytics::events::boot_id()
+        );
+        assert_eq!(message, expected);
+    }
+}
@@ -0,0 +1,281 @@
+//! Live subscribers to the event stream, for management clients that follow
+//! events as they happen rather than reading them from events.tcp or
+//! ClickHouse.
+//!
+//! The watchers are one more event sink: a thread takes each event off the
+//! sink channel and hands it to every current subscriber. A subscriber that
+//! falls behind misses events instead of holding up the others, and one that
+//! has gone away is dropped on the next event.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use advmac::MacAddr6;
+use ipnet::IpNet;
+use tracing::{debug, warn};
+
+use crate::analytics::events::DhcpEvent;
+use crate::types::parse_mac;
+
+/// Events buffered for each subscriber before it starts missing them
+const SUBSCRIBER_QUEUE: usize = 1024;
+/// How often missed events are logged
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Default)]
+pub struct EventWatchers {
+    subscribers: Arc<Mutex<Vec<mpsc::SyncSender<Arc<DhcpEvent>>>>>,
+}
+
+impl EventWatchers {
+    /// Every event from now on, until the receiver is dropped
+    pub fn subscribe(&self) -> mpsc::Receiver<Arc<DhcpEvent>> {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        self.subscribers
+            .lock()
+            .expect("watchers mutex poisoned")
+            .push(tx);
+        rx
+    }
+
+    /// Subscribers, counting any that have gone away since the last event
+    pub fn len(&self) -> usize {
+        self.subscribers
+            .lock()
+            .expect("watchers mutex poisoned")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hand each event from the sink channel to the subscribers, until every
+    /// worker has dropped its sender. Events a subscriber had no room for
+    /// are counted in `dropped` along with the sink's own, and logged every
+    /// [`REPORT_INTERVAL`].
+    pub fn forward(
+        &self,
+        rx: mpsc::Receiver<DhcpEvent>,
+        dropped: Arc<AtomicU64>,
+        queued: Arc<AtomicUsize>,
+    ) {
+        let mut last_report = Instant::now();
+        loop {
+            match rx.recv_timeout(REPORT_INTERVAL) {
+                Ok(event) => {
+                    queued.fetch_sub(1, Ordering::Relaxed);
+                    self.publish(Arc::new(event), &dropped);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => (),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            if last_report.elapsed() >= REPORT_INTERVAL {
+                let missed = dropped.swap(0, Ordering::Relaxed);
+                if missed > 0 {
+                    warn!(missed, "event watchers fell behind and missed events");
+                }
+                last_report = Instant::now();
+            }
+        }
+    }
+
+    fn publish(&self, event: Arc<DhcpEvent>, dropped: &AtomicU64) {
+        let mut subscribers = self.subscribers.lock().expect("watchers mutex poisoned");
+        subscribers.retain(|tx| match tx.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(mpsc::TrySendError::Full(_)) => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                debug!("event watcher went away");
+                false
+            }
+        });
+    }
+}
+
+/// The events a subscriber asked for, from the query string of its request
+#[derive(Debug, Default, PartialEq)]
+pub struct WatchFilter {
+    /// The client's MAC address, or its reservation's
+    pub mac: Option<MacAddr6>,
+    /// Holds the relay's address or the reserved address or prefix
+    pub subnet: Option<IpNet>,
+    pub failures_only: bool,
+}
+
+impl WatchFilter {
+    /// Parse `mac=..&subnet=..&failures_only=true`, percent-encoded or not.
+    /// A bare address for `subnet` is that one address.
+    pub fn from_query(query: &str) -> Result<Self, String> {
+        let mut filter = WatchFilter::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value =
+                percent_decode(value).ok_or_else(|| format!("Invalid encoding in `{pair}`"))?;
+            match key {
+                "mac" => {
+                    filter.mac =
+                        Some(parse_mac(&value).ok_or_else(|| format!("Invalid mac `{value}`"))?)
+                }
+                "subnet" => {
+                    let subnet = value
+                        .parse::<IpNet>()
+                        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+                        .map_err(|_| format!("Invalid subnet `{value}`"))?;
+                    filter.subnet = Some(subnet);
+                }
+                "failures_only" => {
+                    filter.failures_only = match value.as_str() {
+                        "" | "true" | "1" => true,
+                        "false" | "0" => false,
+                        _ => return Err(format!("Invalid failures_only `{value}`")),
+                    }
+                }
+                _ => return Err(format!("Unknown parameter `{key}`")),
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn keep(&self, event: &DhcpEvent) -> bool {
+        if self.failures_only && event.success() {
+            return false;
+        }
+        if let Some(mac) = self.mac {
+            let (client, reserved) = match event {
+                DhcpEvent::V4(event) => (event.mac_address, event.reservation_mac),
+                DhcpEvent::V6(event) => (event.mac_address, event.reservation_mac),
+                DhcpEvent::Alert(_) => (None, None),
+                DhcpEvent::LeaseExpired(event) => (event.mac_address, None),
+            };
+            if client != Some(mac) && reserved != Some(mac) {
+                return false;
+            }
+        }
+        if let Some(subnet) = self.subnet {
+            let addresses: Vec<IpAddr> = match event {
+                DhcpEvent::V4(event) => [Some(event.relay_addr), event.reservation_ipv4]
+                    .into_iter()
+                    .flatten()
+                    .map(IpAddr::V4)
+                    .collect(),
+                DhcpEvent::V6(event) => [
+                    Some(event.relay_addr),
+                    Some(event.relay_link_addr),
+                    event.reservation_ipv6_na,
+                    event.reservation_ipv6_pd.map(|pd| pd.network()),
+                ]
+                .into_iter()
+                .flatten()
+                .map(IpAddr::V6)
+                .collect(),
+                DhcpEvent::Alert(alert) => alert
+                    .subnet
+                    .map(|subnet| IpAddr::V4(subnet.network()))
+                    .into_iter()
+                    .collect(),
+                DhcpEvent::LeaseExpired(event) => {
+                    [event.ipv4.map(IpAddr::V4), event.ipv6_na.map(IpAddr::V6)]
+                        .into_iter()
+                        .flatten()
+                        .collect()
+                }
+            };
+            if !addresses.iter().any(|addr| subnet.contains(addr)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = value.get(i + 1..i + 3)?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::events::DhcpEventV4;
+    use crate::outcome::NoResponse;
+    use dhcproto::v4;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn events_reach_every_subscriber_until_it_goes_away() {
+        let watchers = EventWatchers::default();
+        let first = watchers.subscribe();
+        let second = watchers.subscribe();
+        drop(second);
+
+        let (tx, rx) = mpsc::sync_channel(4);
+        let queued = Arc::new(AtomicUsize::new(1));
+        let event = DhcpEventV4::failed(
+            &v4::Message::default(),
+            Ipv4Addr::new(192, 0, 2, 1),
+            NoResponse::NoReservation,
+        );
+        tx.send(DhcpEvent::V4(event)).unwrap();
+        drop(tx);
+        watchers.forward(rx, Arc::default(), queued.clone());
+
+        assert!(first.try_recv().is_ok_and(|event| !event.success()));
+        assert_eq!(queued.load(Ordering::Relaxed), 0);
+        assert_eq!(watchers.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn filter_by_mac_subnet_and_failures() {
+        let relay = Ipv4Addr::new(192, 0, 2, 1);
+        let msg = v4::Message::new(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            relay,
+            &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55],
+        );
+        let failure = DhcpEvent::V4(DhcpEventV4::failed(&msg, relay, NoResponse::NoReservation));
+        let success = DhcpEvent::V4(DhcpEventV4::success(&msg, relay, None, None));
+
+        let filter =
+            WatchFilter::from_query("mac=00%3A11%3A22%3A33%3A44%3A55&subnet=192.0.2.0%2F24")
+                .unwrap();
+        assert!(filter.keep(&failure) && filter.keep(&success));
+
+        let filter = WatchFilter::from_query("failures_only=true&subnet=192.0.2.1").unwrap();
+        assert!(filter.keep(&failure) && !filter.keep(&success));
+
+        for query in ["mac=00-11-22-33-44-56", "subnet=198.51.100.0/24"] {
+            let filter = WatchFilter::from_query(query).unwrap();
+            assert!(!filter.keep(&failure), "{query}");
+        }
+        assert_eq!(WatchFilter::from_query("").unwrap(), WatchFilter::default());
+        assert!(WatchFilter::from_query("mac=bogus").is_err());
+        assert!(WatchFilter::from_query("vlan=100").is_err());
+    }
+}
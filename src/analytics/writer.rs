@@ -1,17 +1,29 @@
 use crate::analytics::batch::{run, BatchConfig, BatchSink};
 use crate::analytics::events::DhcpEvent;
 use crate::shutdown::Shutdown;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::{
     fmt::Debug,
-    io::{BufWriter, Write},
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
     net::{TcpStream, ToSocketAddrs},
-    time::Duration,
+    path::PathBuf,
+    time::{Duration, Instant},
 };
 use tracing::{info, warn};
 
+use crate::config::TcpSpoolConfig;
+
+#[cfg(feature = "tcp-tls")]
+use crate::config::TcpTlsConfig;
+#[cfg(feature = "tcp-tls")]
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+
 const MAX_BATCH: usize = 256;
 const MAX_BATCH_LATENCY: Duration = Duration::from_secs(3);
 const RECONNECT_TIMEOUT: Duration = Duration::from_secs(3);
@@ -19,20 +31,111 @@ const RECONNECT_TIMEOUT: Duration = Duration::from_secs(3);
 /// covers short downstream maintenance windows without dropping the
 /// in-flight batch.
 const MAX_RETRIES: u32 = 100;
+/// Connect attempts back off from `RECONNECT_BACKOFF_MIN`, doubling after
+/// each failure, so a collector that is down for hours isn't hammered every
+/// few seconds.
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// How the TCP sink connects to the collector, and where it keeps events
+/// while it can't
+#[derive(Clone, Default)]
+pub struct TcpOptions {
+    #[cfg(feature = "tcp-tls")]
+    pub tls: Option<TlsConnector>,
+    /// Sent as `{"token": ...}` on the first line of each connection
+    pub token: Option<String>,
+    pub spool: Option<TcpSpoolConfig>,
+}
+
+/// Client side of the TCP sink's TLS, built once at startup so a bad CA
+/// file or server name stops the server instead of every reconnect failing.
+#[cfg(feature = "tcp-tls")]
+#[derive(Clone)]
+pub struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+#[cfg(feature = "tcp-tls")]
+impl TlsConnector {
+    pub fn new(cfg: &TcpTlsConfig) -> Result<Self, String> {
+        let server_name = ServerName::try_from(cfg.server_name.clone())
+            .map_err(|e| format!("invalid server_name {:?}: {e}", cfg.server_name))?;
+
+        let mut roots = RootCertStore::empty();
+        match &cfg.ca_file {
+            Some(path) => {
+                let certs = CertificateDer::pem_file_iter(path)
+                    .map_err(|e| format!("{}: {e}", path.display()))?;
+                for cert in certs {
+                    let cert = cert.map_err(|e| format!("{}: {e}", path.display()))?;
+                    roots
+                        .add(cert)
+                        .map_err(|e| format!("{}: {e}", path.display()))?;
+                }
+                if roots.is_empty() {
+                    return Err(format!("{}: no certificates found", path.display()));
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|e| e.to_string())?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+
+        Ok(Self {
+            config: Arc::new(config),
+            server_name,
+        })
+    }
+
+    fn wrap(&self, stream: TcpStream) -> std::io::Result<StreamOwned<ClientConnection, TcpStream>> {
+        let conn = ClientConnection::new(self.config.clone(), self.server_name.clone())
+            .map_err(std::io::Error::other)?;
+        let mut tls = StreamOwned::new(conn, stream);
+        // Handshake here so a certificate the collector can't back up fails
+        // the connect, not the first batch
+        while tls.conn.is_handshaking() {
+            tls.conn.complete_io(&mut tls.sock)?;
+        }
+        Ok(tls)
+    }
+}
 
 struct Writer {
-    writer: BufWriter<TcpStream>,
+    writer: BufWriter<Box<dyn Write + Send>>,
 }
 
 impl Writer {
-    fn connect<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+    fn connect<A: ToSocketAddrs>(addr: A, options: &TcpOptions) -> std::io::Result<Self> {
         let stream = TcpStream::connect(addr)?;
         stream.set_nodelay(true)?;
         stream.set_write_timeout(Some(Duration::from_secs(2)))?;
 
-        Ok(Self {
-            writer: BufWriter::new(stream),
-        })
+        #[cfg(feature = "tcp-tls")]
+        let stream: Box<dyn Write + Send> = match &options.tls {
+            Some(tls) => {
+                // The handshake reads, so bound it like the writes
+                stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+                Box::new(tls.wrap(stream)?)
+            }
+            None => Box::new(stream),
+        };
+        #[cfg(not(feature = "tcp-tls"))]
+        let stream: Box<dyn Write + Send> = Box::new(stream);
+
+        let mut writer = BufWriter::new(stream);
+        if let Some(token) = &options.token {
+            serde_json::to_writer(&mut writer, &serde_json::json!({ "token": token }))?;
+            writer.write_all(b"\n")?;
+        }
+
+        Ok(Self { writer })
     }
 
     fn send_batch(&mut self, batch: &[DhcpEvent]) -> std::io::Result<()> {
@@ -46,11 +149,129 @@ impl Writer {
     }
 }
 
+/// JSON lines file holding events the collector couldn't be sent. Survives
+/// restarts: whatever a previous run left behind is replayed first.
+struct Spool {
+    path: PathBuf,
+    max_bytes: u64,
+    len: u64,
+    /// Events dropped because the spool was full, reported per cycle
+    dropped: u64,
+}
+
+impl Spool {
+    fn open(cfg: &TcpSpoolConfig) -> Self {
+        let len = fs::metadata(&cfg.path).map(|m| m.len()).unwrap_or(0);
+        if len > 0 {
+            info!(
+                "{} holds {len} bytes of events from a previous run, replaying once connected",
+                cfg.path.display()
+            );
+        }
+        Self {
+            path: cfg.path.clone(),
+            max_bytes: cfg.max_bytes,
+            len,
+            dropped: 0,
+        }
+    }
+
+    /// Append as much of `batch` as fits under `max_bytes`
+    fn append(&mut self, batch: &[DhcpEvent]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut kept = 0;
+        for ev in batch {
+            let start = buf.len();
+            serde_json::to_writer(&mut buf, ev)?;
+            buf.push(b'\n');
+            if self.len + buf.len() as u64 > self.max_bytes {
+                buf.truncate(start);
+                break;
+            }
+            kept += 1;
+        }
+        self.dropped += (batch.len() - kept) as u64;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&buf));
+        // A failed write may have appended part of the buffer
+        self.len = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        result
+    }
+
+    /// Send everything spooled ahead of new events, then empty the file.
+    /// A replay cut short is repeated in full on the next connection, so
+    /// the collector may see some events twice.
+    fn replay(&mut self, writer: &mut impl Write) -> io::Result<()> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        let sent = io::copy(&mut File::open(&self.path)?, writer)?;
+        writer.flush()?;
+        File::create(&self.path)?;
+        self.len = 0;
+        info!("Replayed {sent} bytes of spooled events");
+        Ok(())
+    }
+}
+
 struct TcpSink<A: ToSocketAddrs + Debug> {
     address: A,
+    options: TcpOptions,
     writer: Option<Writer>,
     batch: Vec<DhcpEvent>,
     dropped: Arc<AtomicU64>,
+    queued: Arc<AtomicUsize>,
+    spool: Option<Spool>,
+    backoff: Duration,
+    next_connect: Instant,
+}
+
+impl<A: ToSocketAddrs + Debug> TcpSink<A> {
+    /// Connect if needed and the backoff allows it, replay the spool, then
+    /// send the batch. Any failure drops the connection.
+    fn send(&mut self) -> io::Result<()> {
+        if self.writer.is_none() {
+            if Instant::now() < self.next_connect {
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            match Writer::connect(&self.address, &self.options) {
+                Ok(w) => {
+                    if self.backoff > RECONNECT_BACKOFF_MIN {
+                        info!("Reconnected to events collector {:?}", self.address);
+                    }
+                    self.backoff = RECONNECT_BACKOFF_MIN;
+                    self.writer = Some(w);
+                }
+                Err(e) => {
+                    if self.backoff == RECONNECT_BACKOFF_MIN {
+                        warn!(
+                            "Events collector {:?} unreachable, retrying with backoff: {e}",
+                            self.address
+                        );
+                    }
+                    self.next_connect = Instant::now() + self.backoff;
+                    self.backoff = (self.backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                    return Err(e);
+                }
+            }
+        }
+        let w = self.writer.as_mut().expect("writer present after connect");
+        let result = match self.spool.as_mut() {
+            Some(spool) => spool.replay(&mut w.writer),
+            None => Ok(()),
+        }
+        .and_then(|()| w.send_batch(&self.batch));
+        if result.is_err() {
+            self.writer = None;
+        }
+        result
+    }
 }
 
 impl<A: ToSocketAddrs + Debug> BatchSink<DhcpEvent> for TcpSink<A> {
@@ -59,6 +280,7 @@ impl<A: ToSocketAddrs + Debug> BatchSink<DhcpEvent> for TcpSink<A> {
     }
 
     fn push(&mut self, ev: DhcpEvent) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
         self.batch.push(ev);
     }
 
@@ -66,21 +288,22 @@ impl<A: ToSocketAddrs + Debug> BatchSink<DhcpEvent> for TcpSink<A> {
         self.batch.len()
     }
 
+    /// With a spool, a batch that can't be sent is written to disk and
+    /// counts as flushed, so the runner never retries or gives up on it.
     fn flush(&mut self) -> Result<(), ()> {
         if self.batch.is_empty() {
             return Ok(());
         }
-        if self.writer.is_none() {
-            match Writer::connect(&self.address) {
-                Ok(w) => self.writer = Some(w),
-                Err(_) => return Err(()),
-            }
+        if self.send().is_ok() {
+            return Ok(());
         }
-        let w = self.writer.as_mut().expect("writer present after connect");
-        match w.send_batch(&self.batch) {
+        let Some(spool) = self.spool.as_mut() else {
+            return Err(());
+        };
+        match spool.append(&self.batch) {
             Ok(()) => Ok(()),
-            Err(_) => {
-                self.writer = None;
+            Err(e) => {
+                warn!("Failed to spool events to {}: {e}", spool.path.display());
                 Err(())
             }
         }
@@ -95,6 +318,14 @@ impl<A: ToSocketAddrs + Debug> BatchSink<DhcpEvent> for TcpSink<A> {
         if n > 0 {
             warn!("Dropped {n} DHCP events at sender (channel full)");
         }
+        if let Some(spool) = self.spool.as_mut().filter(|spool| spool.dropped > 0) {
+            warn!(
+                "Dropped {} DHCP events, {} is full",
+                spool.dropped,
+                spool.path.display()
+            );
+            spool.dropped = 0;
+        }
     }
 
     fn on_giveup(&mut self) {
@@ -109,15 +340,23 @@ impl<A: ToSocketAddrs + Debug> BatchSink<DhcpEvent> for TcpSink<A> {
 
 pub fn tcp_writer<A: ToSocketAddrs + Debug>(
     address: A,
+    options: TcpOptions,
     rx: mpsc::Receiver<DhcpEvent>,
     dropped: Arc<AtomicU64>,
+    queued: Arc<AtomicUsize>,
     shutdown: Shutdown,
 ) {
+    let spool = options.spool.as_ref().map(Spool::open);
     let mut sink = TcpSink {
         address,
+        options,
         writer: None,
         batch: Vec::with_capacity(MAX_BATCH),
         dropped,
+        queued,
+        spool,
+        backoff: RECONNECT_BACKOFF_MIN,
+        next_connect: Instant::now(),
     };
     run(
         rx,
@@ -0,0 +1,377 @@
+//! Append-only audit trail of address assignments.
+//!
+//! Every DHCPv4 ACK and every DHCPv6 Reply that binds an address or prefix
+//! is written as one JSON line, so "who had this IP at that time" can be
+//! answered from the box itself without the analytics pipeline. The file is
+//! only ever appended to; when it reaches `max_bytes` it is renamed with a
+//! millisecond timestamp suffix and a fresh file is started.
+//!
+//! Unlike the event sinks the queue never drops: a worker blocks on a full
+//! queue rather than hand out an address that isn't recorded. Write errors
+//! (disk full, file removed underneath us) are logged and the record lost,
+//! since stalling DHCP wouldn't get it on disk either.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use advmac::MacAddr6;
+use compact_str::CompactString;
+use dhcproto::{v4, v6};
+use ipnet::Ipv6Net;
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::analytics::events::now;
+use crate::config::AuditConfig;
+use crate::types::{Duid, Reservation};
+use crate::v4::extensions::{RelayAgentInformationExt, ShadowMessageExtV4};
+use crate::v6::extensions::{ShadowMessageExtV6, ShadowRelayMessageExtV6};
+
+/// Records buffered between the workers and the writer thread
+pub const AUDIT_QUEUE: usize = 4096;
+
+/// One address assignment as it went out on the wire
+#[derive(Debug, Serialize)]
+pub struct AuditRecord {
+    /// Unix milliseconds
+    pub timestamp: u64,
+    pub ip_version: &'static str,
+    pub message_type: &'static str,
+    pub relay_addr: IpAddr,
+    pub mac_address: Option<MacAddr6>,
+    /// DHCPv6 client DUID
+    pub client_id: Option<String>,
+    pub subscriber_id: Option<CompactString>,
+    /// Option 82 circuit-id (v4) or Option 18 interface-id (v6)
+    pub circuit_id: Option<String>,
+    /// Option 82 remote-id (v4) or Option 37 remote-id (v6)
+    pub remote_id: Option<String>,
+    /// DHCPv4 client hostname (option 12)
+    pub hostname: Option<String>,
+    /// DHCPv4 vendor class identifier (option 60)
+    pub vendor_class: Option<String>,
+    /// DHCPv4 Parameter Request List codes, comma separated
+    pub prl_fingerprint: Option<String>,
+    /// Hash of what the client requests, see [`crate::fingerprint`]
+    pub device_class: Option<CompactString>,
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6_na: Option<Ipv6Addr>,
+    pub ipv6_pd: Option<Ipv6Net>,
+    /// Lease time (v4) or valid lifetime (v6) granted, seconds
+    pub lease_secs: u32,
+}
+
+impl AuditRecord {
+    /// Record for a DHCPv4 ACK, `None` for any other reply
+    pub fn v4(
+        msg: &v4::Message,
+        reply: &v4::Message,
+        relay_addr: IpAddr,
+        reservation: Option<&Reservation>,
+    ) -> Option<Self> {
+        if reply.message_type() != Some(&v4::MessageType::Ack) {
+            return None;
+        }
+        let relay_info = msg.relay_agent_information();
+        let lease_secs = reply
+            .opts()
+            .iter()
+            .find_map(|(_, opt)| match opt {
+                v4::DhcpOption::AddressLeaseTime(secs) => Some(*secs),
+                _ => None,
+            })
+            .unwrap_or(0);
+        Some(Self {
+            timestamp: now(),
+            ip_version: "v4",
+            message_type: "Ack",
+            relay_addr,
+            mac_address: MacAddr6::try_from(msg.chaddr()).ok(),
+            client_id: None,
+            subscriber_id: reservation.and_then(|r| r.subscriber_id.clone()),
+            circuit_id: relay_info
+                .and_then(|r| r.circuit_id())
+                .map(|b| String::from_utf8_lossy(&b).into_owned()),
+            remote_id: relay_info
+                .and_then(|r| r.remote_id())
+                .map(|b| String::from_utf8_lossy(&b).into_owned()),
+            hostname: msg.hostname().map(str::to_owned),
+            vendor_class: msg
+                .vendor_class()
+                .map(|b| String::from_utf8_lossy(b).into_owned()),
+            prl_fingerprint: msg.prl_fingerprint(),
+            device_class: crate::fingerprint::v4(msg),
+            ipv4: Some(reply.yiaddr()),
+            ipv6_na: None,
+            ipv6_pd: None,
+            lease_secs,
+        })
+    }
+
+    /// Record for a DHCPv6 Reply, `None` if it binds nothing (NoBinding,
+    /// or any other reply without a non-zero lifetime)
+    pub fn v6(
+        msg: &v6::Message,
+        relay_msg: &v6::RelayMessage,
+        reply: &v6::Message,
+        relay_addr: IpAddr,
+        reservation: Option<&Reservation>,
+    ) -> Option<Self> {
+        if reply.msg_type() != v6::MessageType::Reply {
+            return None;
+        }
+        // A Renew for a stale address gets it back with zero lifetimes next
+        // to the reserved one; only the live binding is an assignment.
+        let na = reply.ia_na().and_then(|na| {
+            na.opts.iter().find_map(|opt| match opt {
+                v6::DhcpOption::IAAddr(ia) if ia.valid_life != 0 => Some((ia.addr, ia.valid_life)),
+                _ => None,
+            })
+        });
+        let pd = reply.ia_pd().and_then(|pd| {
+            pd.opts.iter().find_map(|opt| match opt {
+                v6::DhcpOption::IAPrefix(ia) if ia.valid_lifetime != 0 => {
+                    Ipv6Net::new(ia.prefix_ip, ia.prefix_len)
+                        .ok()
+                        .map(|net| (net, ia.valid_lifetime))
+                }
+                _ => None,
+            })
+        });
+        if na.is_none() && pd.is_none() {
+            return None;
+        }
+        let option1837 = relay_msg.option1837();
+        Some(Self {
+            timestamp: now(),
+            ip_version: "v6",
+            message_type: "Reply",
+            relay_addr,
+            mac_address: relay_msg.hw_addr(),
+            client_id: msg
+                .client_id()
+                .and_then(|bytes| Duid::new(bytes.to_vec()))
+                .map(|d| d.to_string()),
+            subscriber_id: reservation.and_then(|r| r.subscriber_id.clone()),
+            circuit_id: option1837
+                .as_ref()
+                .and_then(|o| o.interface.as_ref().map(|s| s.to_string())),
+            remote_id: option1837
+                .as_ref()
+                .and_then(|o| o.remote.as_ref().map(|s| s.to_string())),
+            hostname: None,
+            vendor_class: None,
+            prl_fingerprint: None,
+            device_class: crate::fingerprint::v6(msg),
+            ipv4: None,
+            ipv6_na: na.map(|(addr, _)| addr),
+            ipv6_pd: pd.map(|(prefix, _)| prefix),
+            lease_secs: na
+                .map(|(_, life)| life)
+                .or(pd.map(|(_, life)| life))
+                .unwrap_or(0),
+        })
+    }
+}
+
+/// Queue a record for the writer. Blocks if the writer is behind. Callers
+/// only build the record when `audit` is set.
+pub fn record(audit: &Option<mpsc::SyncSender<AuditRecord>>, record: Option<AuditRecord>) {
+    if let (Some(tx), Some(record)) = (audit, record) {
+        if tx.send(record).is_err() {
+            error!("Audit writer is gone, assignment not recorded");
+        }
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: BufWriter<File>,
+    written: u64,
+}
+
+impl AuditLog {
+    pub fn open(cfg: &AuditConfig) -> io::Result<Self> {
+        let file = open_append(&cfg.path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: cfg.path.clone(),
+            max_bytes: cfg.max_bytes,
+            max_files: cfg.max_files,
+            file: BufWriter::new(file),
+            written,
+        })
+    }
+
+    fn append(&mut self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let len = line.len() as u64;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(&line)?;
+        self.written += len;
+        Ok(())
+    }
+
+    /// Flush buffered lines and ask the OS to put them on disk
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sync()?;
+        // Never overwrite an earlier rotation, even if two land in one ms
+        let mut stamp = now();
+        let mut rotated = rotated_path(&self.path, stamp);
+        while rotated.exists() {
+            stamp += 1;
+            rotated = rotated_path(&self.path, stamp);
+        }
+        std::fs::rename(&self.path, &rotated)?;
+        self.file = BufWriter::new(open_append(&self.path)?);
+        self.written = 0;
+        info!("Rotated audit log to {}", rotated.display());
+
+        if self.max_files > 0 {
+            if let Err(e) = self.prune() {
+                error!("Unable to remove old audit logs: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the oldest rotated files beyond `max_files`
+    fn prune(&self) -> io::Result<()> {
+        let mut rotated = rotated_files(&self.path)?;
+        if rotated.len() <= self.max_files {
+            return Ok(());
+        }
+        rotated.sort_unstable_by_key(|(stamp, _)| *stamp);
+        let excess = rotated.len() - self.max_files;
+        for (_, path) in rotated.into_iter().take(excess) {
+            std::fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+pub(crate) fn rotated_path(path: &Path, stamp: u64) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{stamp}"));
+    PathBuf::from(name)
+}
+
+/// Rotated siblings of `path` with their timestamp suffix
+pub(crate) fn rotated_files(path: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let dir = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let Some(base) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let mut rotated = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let stamp = name
+            .to_str()
+            .and_then(|n| n.strip_prefix(base))
+            .and_then(|n| n.strip_prefix('.'))
+            .and_then(|n| n.parse::<u64>().ok());
+        if let Some(stamp) = stamp {
+            rotated.push((stamp, entry.path()));
+        }
+    }
+    Ok(rotated)
+}
+
+/// Writer thread. Runs until every worker has dropped its sender, then
+/// syncs what is left and returns.
+pub fn writer(mut log: AuditLog, rx: mpsc::Receiver<AuditRecord>) {
+    while let Ok(record) = rx.recv() {
+        if let Err(e) = log.append(&record) {
+            error!("Unable to write audit record: {e}");
+        }
+        // Batch up whatever else is queued before paying for the sync
+        while let Ok(record) = rx.try_recv() {
+            if let Err(e) = log.append(&record) {
+                error!("Unable to write audit record: {e}");
+            }
+        }
+        if let Err(e) = log.sync() {
+            error!("Unable to sync audit log: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_record(n: u8) -> AuditRecord {
+        AuditRecord {
+            timestamp: 1704067200000,
+            ip_version: "v4",
+            message_type: "Ack",
+            relay_addr: Ipv4Addr::new(10, 0, 0, 1).into(),
+            mac_address: Some(MacAddr6::new([0, 1, 2, 3, 4, n])),
+            client_id: None,
+            subscriber_id: None,
+            circuit_id: None,
+            remote_id: None,
+            hostname: None,
+            vendor_class: None,
+            prl_fingerprint: None,
+            device_class: None,
+            ipv4: Some(Ipv4Addr::new(100, 64, 0, n)),
+            ipv6_na: None,
+            ipv6_pd: None,
+            lease_secs: 3600,
+        }
+    }
+
+    #[test]
+    fn rotates_at_max_bytes_and_prunes() {
+        let dir = std::env::temp_dir().join(format!("shadowdhcp-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let line_len = serde_json::to_vec(&test_record(1)).unwrap().len() as u64 + 1;
+        let mut log = AuditLog::open(&AuditConfig {
+            path: path.clone(),
+            // two records per file
+            max_bytes: line_len * 2,
+            max_files: 2,
+        })
+        .unwrap();
+
+        for n in 0..7 {
+            log.append(&test_record(n)).unwrap();
+        }
+        log.sync().unwrap();
+
+        // 7 records: three full files rotated, the oldest pruned
+        let rotated = rotated_files(&path).unwrap();
+        assert_eq!(rotated.len(), 2);
+        let current = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.contains("100.64.0.6"));
+        for (_, file) in &rotated {
+            assert_eq!(std::fs::read_to_string(file).unwrap().lines().count(), 2);
+        }
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,423 @@
+//! Test client that plays the relay agent in front of a running shadowdhcp,
+//! so the whole socket path can be exercised from a lab box or CI without a
+//! real CPE or switch.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use advmac::MacAddr6;
+use shadowdhcp::dhcproto::{
+    v4,
+    v6::{self, RelayMessage, RelayMessageData},
+    Decodable, Encodable,
+};
+use shadowdhcp::testutil;
+use shadowdhcp::types::Duid;
+use shadowdhcp::v4::extensions::ShadowMessageExtV4;
+use shadowdhcp::v6::extensions::ShadowMessageExtV6;
+
+const HELP: &str = "\
+shadowdhcp-client
+
+Acts as a DHCP relay agent and runs a full exchange against a shadowdhcp
+server, printing each step. Exits non-zero if the server doesn't answer or
+answers with something other than an address.
+
+USAGE:
+  shadowdhcp-client v4 --server ADDR --relay ADDR --mac MAC [OPTIONS]
+  shadowdhcp-client v6 --server ADDR --relay ADDR --mac MAC [OPTIONS]
+
+  v4 runs DISCOVER/OFFER/REQUEST/ACK with giaddr set to --relay.
+  v6 runs Solicit/Advertise/Request/Reply inside Relay-forward messages.
+
+  --relay must be an address on this host: the server answers the relay on
+  port 67 (v4) or 547 (v6), so those ports must be free and this usually
+  needs root.
+
+COMMON OPTIONS:
+  --server ADDR                 Server address
+  --relay ADDR                  Relay address to send from and put in giaddr/link-address
+  --mac MAC                     Client MAC address, e.g. 00:11:22:33:44:55
+  --remote-id TEXT              Option 82 remote-id (v4) or Option 37 (v6)
+  --timeout SECS                How long to wait for each answer (default: 3)
+
+V4 OPTIONS:
+  --circuit-id TEXT             Option 82 circuit-id
+  --subscriber-id TEXT          Option 82 subscriber-id
+
+V6 OPTIONS:
+  --duid DUID                   Client DUID (default: DUID-LL from --mac)
+  --interface-id TEXT           Option 18 interface-id
+  --enterprise-number N         Enterprise number for Option 37 (default: 0)
+  --renew                       Follow the Reply with a Renew and check the binding
+";
+
+const V4_SERVER_PORT: u16 = 67;
+const V6_SERVER_PORT: u16 = 547;
+
+struct CommonArgs {
+    server: IpAddr,
+    relay: IpAddr,
+    mac: MacAddr6,
+    remote_id: Option<String>,
+    timeout: Duration,
+}
+
+struct V4Args {
+    circuit_id: Option<String>,
+    subscriber_id: Option<String>,
+}
+
+struct V6Args {
+    duid: Option<Duid>,
+    interface_id: Option<String>,
+    enterprise_number: u32,
+    renew: bool,
+}
+
+fn main() -> ExitCode {
+    let mut args = pico_args::Arguments::from_env();
+    if args.contains(["-h", "--help"]) {
+        print!("{HELP}");
+        return ExitCode::SUCCESS;
+    }
+
+    let result = match args.subcommand() {
+        Ok(Some(cmd)) if cmd == "v4" => parse_common(&mut args).and_then(|common| {
+            let v4_args = V4Args {
+                circuit_id: args.opt_value_from_str("--circuit-id")?,
+                subscriber_id: args.opt_value_from_str("--subscriber-id")?,
+            };
+            finish(args)?;
+            Ok(run_v4(&common, &v4_args))
+        }),
+        Ok(Some(cmd)) if cmd == "v6" => parse_common(&mut args).and_then(|common| {
+            let v6_args = V6Args {
+                duid: args.opt_value_from_fn("--duid", |s: &str| Duid::try_from(s))?,
+                interface_id: args.opt_value_from_str("--interface-id")?,
+                enterprise_number: args.opt_value_from_str("--enterprise-number")?.unwrap_or(0),
+                renew: args.contains("--renew"),
+            };
+            finish(args)?;
+            Ok(run_v6(&common, &v6_args))
+        }),
+        Ok(_) => Err(pico_args::Error::MissingArgument),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(Ok(())) => ExitCode::SUCCESS,
+        Ok(Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("{e}\n Run `shadowdhcp-client --help` for usage");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn parse_common(args: &mut pico_args::Arguments) -> Result<CommonArgs, pico_args::Error> {
+    Ok(CommonArgs {
+        server: args.value_from_str("--server")?,
+        relay: args.value_from_str("--relay")?,
+        mac: args.value_from_str("--mac")?,
+        remote_id: args.opt_value_from_str("--remote-id")?,
+        timeout: Duration::from_secs(args.opt_value_from_str("--timeout")?.unwrap_or(3)),
+    })
+}
+
+fn finish(args: pico_args::Arguments) -> Result<(), pico_args::Error> {
+    match args.finish().first() {
+        Some(arg) => Err(pico_args::Error::ArgumentParsingFailed {
+            cause: format!("unexpected argument {arg:?}"),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn run_v4(common: &CommonArgs, args: &V4Args) -> Result<(), String> {
+    let (IpAddr::V4(server), IpAddr::V4(relay)) = (common.server, common.relay) else {
+        return Err("v4 needs IPv4 --server and --relay addresses".into());
+    };
+    let socket = bind((relay, V4_SERVER_PORT).into(), common.timeout)?;
+    let server = SocketAddr::from((server, V4_SERVER_PORT));
+    let xid = rand_u32();
+
+    let relay_info = testutil::relay_agent_information(
+        args.circuit_id.as_deref().map(str::as_bytes),
+        common.remote_id.as_deref().map(str::as_bytes),
+        args.subscriber_id.as_deref().map(str::as_bytes),
+    );
+
+    let mut discover = testutil::discover(common.mac, xid, relay);
+    if let Some(info) = &relay_info {
+        discover.opts_mut().insert(info.clone());
+    }
+    let offer = exchange_v4(&socket, server, &discover, common.timeout)?;
+    expect_v4(&offer, v4::MessageType::Offer)?;
+    let Some(&server_id) = offer.server_id() else {
+        return Err("OFFER has no server identifier".into());
+    };
+    println!("DISCOVER -> OFFER {} from {server_id}", offer.yiaddr());
+
+    let mut request =
+        testutil::request_selecting(common.mac, xid, relay, server_id, offer.yiaddr());
+    if let Some(info) = relay_info {
+        request.opts_mut().insert(info);
+    }
+    let ack = exchange_v4(&socket, server, &request, common.timeout)?;
+    expect_v4(&ack, v4::MessageType::Ack)?;
+    if ack.yiaddr() != offer.yiaddr() {
+        return Err(format!(
+            "ACK is for {} but OFFER was for {}",
+            ack.yiaddr(),
+            offer.yiaddr()
+        ));
+    }
+    println!("REQUEST -> ACK {}", ack.yiaddr());
+    Ok(())
+}
+
+fn exchange_v4(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    msg: &v4::Message,
+    timeout: Duration,
+) -> Result<v4::Message, String> {
+    let bytes = msg.to_vec().map_err(|e| format!("Encoding message: {e}"))?;
+    socket
+        .send_to(&bytes, server)
+        .map_err(|e| format!("Sending to {server}: {e}"))?;
+    recv_matching(socket, timeout, |buf| {
+        v4::Message::from_bytes(buf)
+            .ok()
+            .filter(|reply| reply.xid() == msg.xid())
+    })
+}
+
+fn expect_v4(reply: &v4::Message, expected: v4::MessageType) -> Result<(), String> {
+    match reply.message_type() {
+        Some(t) if *t == expected => Ok(()),
+        other => Err(format!("Expected {expected:?}, got {other:?}")),
+    }
+}
+
+fn run_v6(common: &CommonArgs, args: &V6Args) -> Result<(), String> {
+    let (IpAddr::V6(server), IpAddr::V6(relay)) = (common.server, common.relay) else {
+        return Err("v6 needs IPv6 --server and --relay addresses".into());
+    };
+    let socket = bind((relay, V6_SERVER_PORT).into(), common.timeout)?;
+    let server = SocketAddr::from((server, V6_SERVER_PORT));
+    let client_id = args
+        .duid
+        .clone()
+        .map(|d| d.bytes)
+        .unwrap_or_else(|| testutil::duid_ll(common.mac));
+
+    let solicit = testutil::solicit(&client_id);
+    let advertise = exchange_v6(&socket, server, common, args, relay, &solicit)?;
+    expect_v6(&advertise, v6::MessageType::Advertise)?;
+    let Some(server_id) = advertise.server_id().map(<[u8]>::to_vec) else {
+        return Err("Advertise has no server identifier".into());
+    };
+    println!("Solicit -> Advertise {}", describe_ias(&advertise));
+
+    let reply = request_with_ias(
+        &socket,
+        server,
+        common,
+        args,
+        relay,
+        v6::MessageType::Request,
+        &client_id,
+        &server_id,
+        &advertise,
+    )?;
+    println!("Request -> Reply {}", describe_ias(&reply));
+
+    if args.renew {
+        let renewed = request_with_ias(
+            &socket,
+            server,
+            common,
+            args,
+            relay,
+            v6::MessageType::Renew,
+            &client_id,
+            &server_id,
+            &reply,
+        )?;
+        if (renewed.ia_na_address(), renewed.ia_pd_prefix())
+            != (reply.ia_na_address(), reply.ia_pd_prefix())
+        {
+            return Err(format!(
+                "Renew Reply changed the binding to {}",
+                describe_ias(&renewed)
+            ));
+        }
+        println!("Renew -> Reply {}", describe_ias(&renewed));
+    }
+    Ok(())
+}
+
+/// Send `msg_type` carrying the IAs from `previous`, as a client does for
+/// Request after Advertise and Renew after Reply
+#[allow(clippy::too_many_arguments)]
+fn request_with_ias(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    common: &CommonArgs,
+    args: &V6Args,
+    relay: Ipv6Addr,
+    msg_type: v6::MessageType,
+    client_id: &[u8],
+    server_id: &[u8],
+    previous: &v6::Message,
+) -> Result<v6::Message, String> {
+    let mut msg = v6::Message::new(msg_type);
+    let opts = msg.opts_mut();
+    opts.insert(v6::DhcpOption::ClientId(client_id.to_vec()));
+    opts.insert(v6::DhcpOption::ServerId(server_id.to_vec()));
+    if let Some(iana) = previous.ia_na() {
+        opts.insert(v6::DhcpOption::IANA(iana.clone()));
+    }
+    if let Some(iapd) = previous.ia_pd() {
+        opts.insert(v6::DhcpOption::IAPD(iapd.clone()));
+    }
+    let reply = exchange_v6(socket, server, common, args, relay, &msg)?;
+    expect_v6(&reply, v6::MessageType::Reply)?;
+    if reply.ia_na_address().is_none() && reply.ia_pd_prefix().is_none() {
+        return Err("Reply has no address or prefix".into());
+    }
+    Ok(reply)
+}
+
+fn exchange_v6(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    common: &CommonArgs,
+    args: &V6Args,
+    relay: Ipv6Addr,
+    msg: &v6::Message,
+) -> Result<v6::Message, String> {
+    let mut relay_forw = testutil::relay_forw(msg, relay, testutil::link_local_eui64(common.mac));
+    let relay_opts = &mut relay_forw.opts;
+    relay_opts.insert(v6::DhcpOption::ClientLinklayerAddress(
+        v6::ClientLinklayerAddress {
+            address_type: 1,
+            address: common.mac.to_array().to_vec(),
+        },
+    ));
+    if let Some(interface) = &args.interface_id {
+        relay_opts.insert(v6::DhcpOption::InterfaceId(interface.clone().into_bytes()));
+    }
+    if let Some(remote) = &common.remote_id {
+        relay_opts.insert(v6::DhcpOption::RemoteId(v6::RemoteId {
+            enterprise_number: args.enterprise_number,
+            id: remote.clone().into_bytes(),
+        }));
+    }
+
+    let bytes = relay_forw
+        .to_vec()
+        .map_err(|e| format!("Encoding message: {e}"))?;
+    socket
+        .send_to(&bytes, server)
+        .map_err(|e| format!("Sending to {server}: {e}"))?;
+    recv_matching(socket, common.timeout, |buf| {
+        let relay_repl = RelayMessage::from_bytes(buf).ok()?;
+        relay_repl.opts().iter().find_map(|opt| match opt {
+            v6::DhcpOption::RelayMsg(RelayMessageData::Message(reply))
+                if reply.xid() == msg.xid() =>
+            {
+                Some(reply.clone())
+            }
+            _ => None,
+        })
+    })
+}
+
+fn expect_v6(reply: &v6::Message, expected: v6::MessageType) -> Result<(), String> {
+    let status = reply.opts().iter().find_map(|opt| match opt {
+        v6::DhcpOption::StatusCode(sc) => Some(sc),
+        _ => None,
+    });
+    if let Some(sc) = status {
+        if sc.status != v6::Status::Success {
+            return Err(format!(
+                "{:?}: {:?} {}",
+                reply.msg_type(),
+                sc.status,
+                sc.msg
+            ));
+        }
+    }
+    if reply.msg_type() != expected {
+        return Err(format!("Expected {expected:?}, got {:?}", reply.msg_type()));
+    }
+    Ok(())
+}
+
+fn describe_ias(msg: &v6::Message) -> String {
+    let na = msg
+        .ia_na_address()
+        .map_or_else(|| "-".to_string(), |a| a.to_string());
+    let pd = msg
+        .ia_pd_prefix()
+        .map_or_else(|| "-".to_string(), |p| p.to_string());
+    format!("na={na} pd={pd}")
+}
+
+fn bind(addr: SocketAddr, timeout: Duration) -> Result<UdpSocket, String> {
+    let socket = UdpSocket::bind(addr).map_err(|e| format!("Binding {addr}: {e}"))?;
+    socket
+        .set_read_timeout(Some(timeout))
+        .map_err(|e| format!("Setting read timeout: {e}"))?;
+    Ok(socket)
+}
+
+/// Read datagrams until `decode` accepts one or `timeout` runs out. Anything
+/// else arriving on the relay port, such as answers to an earlier attempt,
+/// is skipped.
+fn recv_matching<T>(
+    socket: &UdpSocket,
+    timeout: Duration,
+    mut decode: impl FnMut(&[u8]) -> Option<T>,
+) -> Result<T, String> {
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    while Instant::now() < deadline {
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                if let Some(reply) = decode(&buf[..len]) {
+                    return Ok(reply);
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                break
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("Receiving: {e}")),
+        }
+    }
+    Err(format!("No answer within {}s", timeout.as_secs()))
+}
+
+/// Transaction IDs only need to differ between runs, not be unpredictable
+fn rand_u32() -> u32 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    hasher.finish() as u32
+}
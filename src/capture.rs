@@ -0,0 +1,169 @@
+//! Per-client packet capture for diagnosing CPE interop problems.
+//!
+//! Capture is switched on per client MAC, from `capture.macs` in the config
+//! or with the `capture_start` management command. For a watched client the
+//! workers keep the last `depth` datagrams received from and sent to its
+//! relay, hex encoded, so the exact bytes on the wire can be pulled with
+//! `capture_dump` and decoded offline without running tcpdump on the box.
+//!
+//! DHCPv4 clients are keyed by chaddr, read before the packet is parsed so
+//! malformed packets are kept as well. DHCPv6 clients are keyed by the first
+//! MAC the configured `mac_extractors` find, and the whole Relay-forward and
+//! Relay-reply datagrams are kept, not just the client message inside.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+use advmac::MacAddr6;
+use dashmap::DashMap;
+use serde::Serialize;
+
+use crate::analytics::events::now;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Received from the relay
+    Rx,
+    /// Sent to the relay
+    Tx,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedPacket {
+    /// Unix milliseconds
+    pub timestamp: u64,
+    pub direction: Direction,
+    pub peer: SocketAddr,
+    /// UDP payload, lowercase hex
+    pub hex: String,
+}
+
+pub struct PacketCapture {
+    depth: usize,
+    clients: DashMap<MacAddr6, VecDeque<CapturedPacket>>,
+}
+
+impl PacketCapture {
+    pub fn new(depth: usize, macs: &[MacAddr6]) -> Self {
+        let capture = Self {
+            depth,
+            clients: DashMap::new(),
+        };
+        for mac in macs {
+            capture.start(*mac);
+        }
+        capture
+    }
+
+    /// Whether any client is watched, so workers can skip finding the key
+    pub fn is_active(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
+    /// Start watching `mac`. Packets already captured for it are kept.
+    pub fn start(&self, mac: MacAddr6) {
+        self.clients.entry(mac).or_default();
+    }
+
+    /// Stop watching `mac` and discard its packets. Returns false if it
+    /// wasn't being watched.
+    pub fn stop(&self, mac: MacAddr6) -> bool {
+        self.clients.remove(&mac).is_some()
+    }
+
+    /// Packets captured for `mac`, oldest first, or `None` if it isn't
+    /// being watched
+    pub fn dump(&self, mac: MacAddr6) -> Option<Vec<CapturedPacket>> {
+        self.clients
+            .get(&mac)
+            .map(|ring| ring.iter().cloned().collect())
+    }
+
+    pub fn record(
+        &self,
+        mac: Option<MacAddr6>,
+        direction: Direction,
+        peer: SocketAddr,
+        bytes: &[u8],
+    ) {
+        let Some(mut ring) = mac.and_then(|mac| self.clients.get_mut(&mac)) else {
+            return;
+        };
+        if ring.len() >= self.depth {
+            ring.pop_front();
+        }
+        ring.push_back(CapturedPacket {
+            timestamp: now(),
+            direction,
+            peer,
+            hex: to_hex(bytes),
+        });
+    }
+}
+
+/// Ethernet chaddr of a raw DHCPv4 packet, read from its fixed BOOTP offset
+/// so packets that fail to parse can still be matched to a client
+pub fn chaddr(packet: &[u8]) -> Option<MacAddr6> {
+    const HTYPE_ETHERNET: u8 = 1;
+    match packet.get(..34) {
+        Some(header) if header[1] == HTYPE_ETHERNET && header[2] == 6 => {
+            let mut mac = [0u8; 6];
+            mac.copy_from_slice(&header[28..34]);
+            Some(MacAddr6::new(mac))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(hex, "{b:02x}");
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: MacAddr6 = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+    #[test]
+    fn keeps_last_packets_for_watched_clients_only() {
+        let capture = PacketCapture::new(2, &[MAC]);
+        let peer: SocketAddr = "192.168.1.254:67".parse().unwrap();
+        let other = MacAddr6::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+
+        capture.record(Some(MAC), Direction::Rx, peer, &[0x01]);
+        capture.record(Some(MAC), Direction::Tx, peer, &[0x02, 0xab]);
+        capture.record(Some(MAC), Direction::Rx, peer, &[0x03]);
+        capture.record(Some(other), Direction::Rx, peer, &[0x04]);
+        capture.record(None, Direction::Rx, peer, &[0x05]);
+
+        let packets = capture.dump(MAC).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].direction, Direction::Tx);
+        assert_eq!(packets[0].hex, "02ab");
+        assert_eq!(packets[1].hex, "03");
+        assert!(capture.dump(other).is_none());
+
+        assert!(capture.stop(MAC));
+        assert!(!capture.is_active());
+    }
+
+    #[test]
+    fn chaddr_read_from_raw_packet() {
+        let mut packet = vec![0u8; 240];
+        packet[0] = 1; // BOOTREQUEST
+        packet[1] = 1; // Ethernet
+        packet[2] = 6;
+        packet[28..34].copy_from_slice(&MAC.to_array());
+        assert_eq!(chaddr(&packet), Some(MAC));
+        assert_eq!(chaddr(&packet[..20]), None);
+        packet[2] = 16;
+        assert_eq!(chaddr(&packet), None);
+    }
+}
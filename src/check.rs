@@ -0,0 +1,318 @@
+//! `shadowdhcp --check`: everything startup would reject, plus mistakes in
+//! reservations.json that startup accepts silently but that leave a client
+//! unreachable or handing out someone else's address. Meant for CI in the
+//! repositories that generate reservations.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use ipnet::{Ipv4Net, Ipv6Net};
+
+use crate::config::Config;
+use crate::reservationdb::{keys_of, ReservationDb, ReservationKey};
+use crate::types::Reservation;
+
+pub struct Report {
+    pub reservations: usize,
+    /// Reservations whose IPv4 address falls in each of `subnets_v4`
+    pub per_subnet: Vec<(Ipv4Net, usize)>,
+    pub problems: Vec<String>,
+}
+
+/// Check `reservations` against each other and against `config`
+pub fn check(config: &Config, mut reservations: Vec<Reservation>) -> Report {
+    let mut problems = Vec::new();
+
+    let db = ReservationDb::with_na_template(config.v6_na_template.clone());
+    for reservation in reservations.iter_mut() {
+        if let Err(e) = db.derive_ipv6_na(reservation) {
+            problems.push(e);
+        }
+    }
+
+    let mut per_subnet: Vec<_> = config.subnets_v4.iter().map(|s| (s.net, 0)).collect();
+    let mut keys: HashMap<ReservationKey, String> = HashMap::new();
+    let mut ipv4s: HashMap<Ipv4Addr, usize> = HashMap::new();
+    let mut ipv6_nas: HashMap<Ipv6Addr, String> = HashMap::new();
+    let (mut by_option82, mut by_option1837) = (0, 0);
+
+    for reservation in &reservations {
+        let name = reservation.name();
+        // v6-only reservations are never answered over DHCPv4
+        if let Some(ipv4) = reservation.ipv4 {
+            match config.subnets_v4.find_index(ipv4) {
+                Some((index, _)) => per_subnet[index].1 += 1,
+                None => problems.push(format!(
+                    "Reservation for {ipv4} is outside every subnets_v4 entry and would never be answered"
+                )),
+            }
+            *ipv4s.entry(ipv4).or_default() += 1;
+        }
+        // `auto` addresses that couldn't be derived were reported above
+        if let Some(na) = reservation.ipv6_na.filter(|na| !na.is_unspecified()) {
+            if let Some(other) = ipv6_nas.insert(na, name.clone()) {
+                problems.push(format!(
+                    "ipv6_na {na} is reserved for both {other} and {name}"
+                ));
+            }
+        }
+
+        if let (Some(activate), Some(deactivate)) =
+            (reservation.activate_at, reservation.deactivate_at)
+        {
+            if deactivate <= activate {
+                problems.push(format!(
+                    "Reservation for {name} deactivates at {deactivate}, no later than it activates at {activate}, and would never be answered"
+                ));
+            }
+        }
+
+        if reservation.cvlan.is_some() && reservation.svlan.is_none() {
+            problems.push(format!(
+                "Reservation for {name} has a cvlan but no svlan, so it isn't matched on VLANs"
+            ));
+        }
+        if [reservation.svlan, reservation.cvlan]
+            .into_iter()
+            .flatten()
+            .any(|id| id > 4095)
+        {
+            problems.push(format!(
+                "Reservation for {name} has a VLAN ID above 4095, which no relay can report"
+            ));
+        }
+
+        if let Some(profile) = &reservation.profile {
+            if !config.reservation_profiles.contains_key(profile.as_str()) {
+                problems.push(format!(
+                    "Reservation for {name} uses profile `{profile}`, which isn't in reservation_profiles"
+                ));
+            }
+        }
+
+        let reservation_keys = keys_of(reservation);
+        if reservation_keys.is_empty() {
+            problems.push(format!(
+                "Reservation for {name} has no mac, duid, client_id, option82, option1837 or svlan and can never match"
+            ));
+        }
+        for key in reservation_keys {
+            match key {
+                ReservationKey::Opt82(_) => by_option82 += 1,
+                ReservationKey::Opt1837(_) => by_option1837 += 1,
+                _ => (),
+            }
+            let described = describe(&key);
+            if let Some(other) = keys.insert(key, name.clone()) {
+                problems.push(format!(
+                    "{described} is used by the reservations for both {other} and {name}"
+                ));
+            }
+        }
+    }
+
+    let mut duplicate_ipv4s: Vec<_> = ipv4s.into_iter().filter(|(_, n)| *n > 1).collect();
+    duplicate_ipv4s.sort_unstable();
+    for (ipv4, n) in duplicate_ipv4s {
+        problems.push(format!("ipv4 {ipv4} is used by {n} reservations"));
+    }
+
+    let mut pds: Vec<_> = reservations.iter().map(|r| (r.ipv6_pd, r.name())).collect();
+    problems.extend(overlapping_prefixes(&mut pds));
+
+    for pool in &config.option82_pools {
+        for reservation in &reservations {
+            let overlaps = reservation
+                .ipv4
+                .is_some_and(|ipv4| pool.ipv4.contains(&ipv4))
+                || reservation
+                    .ipv6_na
+                    .is_some_and(|na| pool.ipv6_na.contains(&na))
+                || pool.ipv6_pd.contains(&reservation.ipv6_pd.network())
+                || reservation.ipv6_pd.contains(&pool.ipv6_pd.network());
+            if overlaps {
+                problems.push(format!(
+                    "Reservation for {} overlaps option82 pool `{}`",
+                    reservation.name(),
+                    pool.name
+                ));
+            }
+        }
+    }
+
+    if by_option82 > 0 && config.option82_extractors.is_empty() {
+        problems.push(format!(
+            "{by_option82} reservations are keyed on option82 but option82_extractors is empty"
+        ));
+    }
+    if by_option1837 > 0 && config.option1837_extractors.is_empty() {
+        problems.push(format!(
+            "{by_option1837} reservations are keyed on option1837 but option1837_extractors is empty"
+        ));
+    }
+
+    Report {
+        reservations: reservations.len(),
+        per_subnet,
+        problems,
+    }
+}
+
+/// Delegated prefixes that contain one another. Sorted by start address, a
+/// prefix can only overlap the widest one seen so far.
+fn overlapping_prefixes(pds: &mut [(Ipv6Net, String)]) -> Vec<String> {
+    pds.sort_unstable_by_key(|(pd, _)| (pd.network(), pd.prefix_len()));
+    let mut problems = Vec::new();
+    let mut widest: Option<(Ipv6Net, &str)> = None;
+    for (pd, name) in pds.iter() {
+        if let Some((outer, outer_name)) = widest {
+            if outer.contains(&pd.network()) {
+                problems.push(format!(
+                    "ipv6_pd {pd} of {name} overlaps {outer} of {outer_name}"
+                ));
+            }
+        }
+        if widest.is_none_or(|(outer, _)| pd.broadcast() > outer.broadcast()) {
+            widest = Some((*pd, name.as_str()));
+        }
+    }
+    problems
+}
+
+fn describe(key: &ReservationKey) -> String {
+    match key {
+        ReservationKey::Mac(mac) => format!("mac {mac}"),
+        ReservationKey::Duid(duid) => format!("duid {duid}"),
+        ReservationKey::ClientId(client_id) => format!("client_id {client_id}"),
+        ReservationKey::Opt82(opt) => {
+            format!(
+                "option82 {}",
+                serde_json::to_string(opt).unwrap_or_default()
+            )
+        }
+        ReservationKey::Opt1837(opt) => {
+            format!(
+                "option1837 {}",
+                serde_json::to_string(opt).unwrap_or_default()
+            )
+        }
+        ReservationKey::Vlans(vlans) => format!("vlans {vlans}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Option82, V4Subnet};
+
+    fn reservation(ipv4: &str, pd: &str, remote: &str) -> Reservation {
+        Reservation {
+            ipv4: Some(ipv4.parse().unwrap()),
+            ipv6_na: None,
+            ipv6_pd: pd.parse().unwrap(),
+            mac: None,
+            duid: None,
+            client_id: None,
+            option82: Some(Option82 {
+                circuit: None,
+                remote: Some(remote.into()),
+                subscriber: None,
+            }),
+            option1837: None,
+            svlan: None,
+            cvlan: None,
+            subscriber_id: None,
+            static_routes: vec![],
+            boot: None,
+            profile: None,
+            activate_at: None,
+            deactivate_at: None,
+            suspended: false,
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            subnets_v4: vec![V4Subnet {
+                net: "100.64.0.0/24".parse().unwrap(),
+                gateway: "100.64.0.1".parse().unwrap(),
+                reply_prefix_len: None,
+                authoritative: false,
+                ranges: vec![],
+            }]
+            .into(),
+            option82_extractors: crate::v4::extractors::get_all_extractors()
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn clean_reservations_have_no_problems() {
+        let report = check(
+            &config(),
+            vec![
+                reservation("100.64.0.2", "2001:db8:100::/56", "olt1:1"),
+                reservation("100.64.0.3", "2001:db8:200::/56", "olt1:2"),
+            ],
+        );
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert_eq!(report.reservations, 2);
+        assert_eq!(report.per_subnet[0].1, 2);
+    }
+
+    #[test]
+    fn duplicates_and_overlaps_reported() {
+        let report = check(
+            &config(),
+            vec![
+                reservation("100.64.0.2", "2001:db8:100::/48", "olt1:1"),
+                reservation("100.64.0.2", "2001:db8:100:200::/56", "olt1:1"),
+                reservation("10.0.0.1", "2001:db8:300::/56", "olt1:3"),
+            ],
+        );
+        let problems = report.problems.join("\n");
+        assert!(problems.contains("option82"), "{problems}");
+        assert!(
+            problems.contains("ipv4 100.64.0.2 is used by 2"),
+            "{problems}"
+        );
+        assert!(
+            problems.contains("overlaps 2001:db8:100::/48"),
+            "{problems}"
+        );
+        assert!(problems.contains("10.0.0.1 is outside"), "{problems}");
+        assert_eq!(report.problems.len(), 4);
+    }
+
+    #[test]
+    fn unknown_profile_reported() {
+        let mut config = config();
+        config
+            .reservation_profiles
+            .insert("residential-1g".into(), Default::default());
+        let mut known = reservation("100.64.0.2", "2001:db8:100::/56", "olt1:1");
+        known.profile = Some("residential-1g".into());
+        let mut unknown = reservation("100.64.0.3", "2001:db8:200::/56", "olt1:2");
+        unknown.profile = Some("residential-10g".into());
+
+        let report = check(&config, vec![known, unknown]);
+        assert_eq!(report.problems.len(), 1, "{:?}", report.problems);
+        assert!(report.problems[0].contains("`residential-10g`"));
+    }
+
+    #[test]
+    fn empty_activation_window_reported() {
+        let mut churned = reservation("100.64.0.2", "2001:db8:100::/56", "olt1:1");
+        churned.activate_at = "2025-06-01T00:00:00Z".parse().ok();
+        churned.deactivate_at = "2026-06-01T00:00:00Z".parse().ok();
+        let mut inverted = reservation("100.64.0.3", "2001:db8:200::/56", "olt1:2");
+        inverted.activate_at = "2025-06-01T00:00:00Z".parse().ok();
+        inverted.deactivate_at = inverted.activate_at;
+
+        let report = check(&config(), vec![churned, inverted]);
+        assert_eq!(report.problems.len(), 1, "{:?}", report.problems);
+        assert!(report.problems[0].starts_with("Reservation for 100.64.0.3 deactivates"));
+    }
+}
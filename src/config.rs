@@ -1,23 +1,52 @@
-use serde::Deserialize;
+use advmac::MacAddr6;
+use ipnet::{Ipv4Net, Ipv6Net};
+use serde::{de::DeserializeOwned, Deserialize};
 use std::{
+    collections::BTreeMap,
     fmt,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
     path::{Path, PathBuf},
     str::FromStr,
 };
+use tracing_subscriber::filter::LevelFilter;
 
-use crate::types::{Duid, V4Subnet};
+use crate::fingerprint;
+use crate::server_id;
+use crate::subnets::{self, V4Subnets};
+use crate::types::{BootParams, Duid, Reservation, StaticRoute, V4Subnet, MAX_DUID_LEN};
 use crate::v4::extractors::{self as v4_extractors, NamedOption82Extractor};
 use crate::v6::extractors::{self as v6_extractors, NamedOption1837Extractor};
 use crate::v6::mac_extractors::MacExtractor;
 
 /// Server wide configuration
 pub struct Config {
+    /// Run the DHCPv4 workers. When off nothing is bound on the DHCPv4
+    /// port and ids.json needn't have a `v4` server id.
+    pub enable_v4: bool,
+    /// Run the DHCPv6 workers, likewise
+    pub enable_v6: bool,
+    /// `0.0.0.0` when DHCPv4 is disabled and ids.json has none
     pub v4_server_id: Ipv4Addr,
     pub dns_v4: Vec<Ipv4Addr>,
     pub dns_v6: Vec<Ipv6Addr>,
-    pub subnets_v4: Vec<V4Subnet>,
+    /// DHCPv6 domain search list (option 24)
+    pub domain_search_v6: Vec<String>,
+    /// DHCPv6 SNTP servers (option 31)
+    pub sntp_v6: Vec<Ipv6Addr>,
+    /// DHCPv6 NTP servers (option 56)
+    pub ntp_v6: Vec<Ipv6Addr>,
+    /// DHCPv4 NTP servers (option 42)
+    pub ntp_v4: Vec<Ipv4Addr>,
+    /// DHCPv4 interface MTU (option 26)
+    pub interface_mtu: Option<u16>,
+    /// DHCPv4 domain name (option 15)
+    pub domain_name: Option<String>,
+    pub subnets_v4: V4Subnets,
+    /// Empty when DHCPv6 is disabled and ids.json has none
     pub v6_server_id: Duid,
+    /// There was no ids.json, so the server ids were generated. The server
+    /// writes them out at startup.
+    pub generated_server_ids: bool,
     pub option82_extractors: Vec<NamedOption82Extractor>,
     pub option1837_extractors: Vec<NamedOption1837Extractor>,
     pub mac_extractors: Vec<MacExtractor>,
@@ -25,8 +54,96 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub events: EventsConfig,
     pub mgmt_address: Option<SocketAddr>,
-    pub v4_bind_address: SocketAddrV4,
-    pub v6_bind_address: SocketAddrV6,
+    /// Where the gRPC management service listens, when built with the
+    /// `grpc` feature
+    pub grpc_address: Option<SocketAddr>,
+    /// Addresses the DHCPv4 workers receive on, from `SHADOW_DHCP4_BIND`
+    /// if it is set
+    pub v4_bind_addresses: Vec<SocketAddrV4>,
+    /// Addresses the DHCPv6 workers receive on, from `SHADOW_DHCP6_BIND`
+    /// if it is set
+    pub v6_bind_addresses: Vec<SocketAddrV6>,
+    /// Source address for DHCPv4 replies, instead of the one the kernel
+    /// picks for the wildcard socket. Linux only.
+    pub v4_reply_source: Option<Ipv4Addr>,
+    /// Source address for DHCPv6 replies. Linux only.
+    pub v6_reply_source: Option<Ipv6Addr>,
+    /// Address sent in the Server Unicast option, which lets clients send
+    /// Request, Renew, Release and Decline straight to the server instead
+    /// of through their relay
+    pub v6_server_unicast: Option<Ipv6Addr>,
+    /// Interface the DHCPv4 sockets are bound to (`SO_BINDTODEVICE`)
+    pub v4_bind_interface: Option<String>,
+    /// Interface the DHCPv6 sockets are bound to, and the link a
+    /// link-local `v6_reply_source` is on
+    pub v6_bind_interface: Option<String>,
+    pub reservation_source: Option<ReservationSourceConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub ha: Option<HaConfig>,
+    pub conflict_detection: Option<ConflictDetectionConfig>,
+    pub alerts: Option<AlertsConfig>,
+    pub static_routes: Vec<StaticRoute>,
+    /// Answer a Solicit from a client without a reservation with a
+    /// NoAddrsAvail/NoPrefixAvail Advertise instead of staying silent
+    pub v6_advertise_unknown_clients: bool,
+    /// Put a Success status note in each Advertised IA whose address or
+    /// prefix differs from the client's hint
+    pub v6_hint_status: bool,
+    /// Send DNS, domain search, SNTP and NTP options in every Advertise and
+    /// Reply, whether or not the client's Option Request lists them
+    pub v6_always_send_options: bool,
+    /// Answer a Solicit carrying Rapid Commit with a committed Reply. When
+    /// off, such clients get an Advertise and go through the four-message
+    /// exchange
+    pub rapid_commit_enabled: bool,
+    /// Preference option (RFC 8415 §21.8) for Advertise. Left out when
+    /// unset, so clients keep collecting Advertises from other servers.
+    pub v6_preference: Option<u8>,
+    /// Don't answer a client claiming a reserved address another client was
+    /// leased within the lease time, instead of only reporting it
+    pub refuse_lease_conflicts: bool,
+    /// Resend the reply to a Discover or Solicit retransmitted within this
+    /// many milliseconds instead of handling it again. 0 turns it off.
+    pub retransmit_window_ms: u64,
+    /// Option 82 values whose extractor match is remembered, so repeat
+    /// packets from a subscriber skip the extractors. 0 turns it off.
+    pub relay_cache_size: usize,
+    pub audit: Option<AuditConfig>,
+    /// Actions run as delegated prefixes are bound, move and expire
+    pub pd_routes: Option<PdRoutesConfig>,
+    pub capture: Option<CaptureConfig>,
+    /// Transactions kept per client for the `history` management command.
+    /// 0 keeps none
+    pub lease_history: usize,
+    /// BOOTP `sname`/`siaddr`/`file` for every DHCPv4 reply, unless the
+    /// reservation sets its own
+    pub boot: BootParams,
+    /// Vendor class identifier (option 60) prefixes of clients that are
+    /// never sent an `sname`
+    pub omit_sname_vendor_classes: Vec<String>,
+    /// Answer BOOTREQUESTs without a DHCP Message Type from clients with a
+    /// MAC reservation
+    pub bootp_enabled: bool,
+    /// How reservations with `"ipv6_na": "auto"` get their address
+    pub v6_na_template: Option<V6NaTemplate>,
+    /// Pools that Option 82 keys without a reservation are mapped into
+    pub option82_pools: Vec<Option82PoolConfig>,
+    /// Number of DHCPv4 and of DHCPv6 worker threads, each with its own
+    /// socket on the shared port
+    pub workers: usize,
+    /// Settings for the clients behind particular relays, see
+    /// [`Config::profile_v4`]
+    pub subscriber_networks: Vec<SubscriberNetwork>,
+    /// Settings shared by the reservations that name them, see
+    /// [`Profile::with_reservation`]
+    pub reservation_profiles: BTreeMap<String, ReservationProfile>,
+    /// Named CPE firmware, see [`crate::fingerprint`] and
+    /// [`Config::device_class`]
+    pub device_classes: BTreeMap<String, DeviceClass>,
+    /// Where suspended DHCPv4 customers are put instead of being refused
+    pub walled_garden: Option<WalledGarden>,
+    /// Server that answers the clients without a reservation
+    pub fallback: Option<FallbackConfig>,
 }
 
 /// Default DHCPv4 lease time (seconds). RFC 2131 §4.4.5 implicitly assumes
@@ -72,6 +189,23 @@ impl LeaseTimes {
     }
 }
 
+impl LeaseTimes {
+    /// These times with a profile's base values in place of the ones it
+    /// sets. Setting only the v4 lease gives the v6 lease the default
+    /// multiple of it, as at the top level.
+    pub fn with_overrides(&self, v4_lease: Option<u32>, v6_valid: Option<u32>) -> Self {
+        if v4_lease.is_none() && v6_valid.is_none() {
+            return *self;
+        }
+        let v6_valid = match (v6_valid, v4_lease) {
+            (Some(v6_valid), _) => v6_valid,
+            (None, Some(v4_lease)) => v4_lease.saturating_mul(DEFAULT_V6_MULTIPLE),
+            (None, None) => self.v6_valid,
+        };
+        Self::from_base(v4_lease.unwrap_or(self.v4_lease), v6_valid)
+    }
+}
+
 impl Default for LeaseTimes {
     fn default() -> Self {
         Self::from_base(
@@ -81,13 +215,53 @@ impl Default for LeaseTimes {
     }
 }
 
+/// Environment variables that take precedence over `v4_bind_address` and
+/// `v6_bind_address`, as comma-separated addresses
+pub const V4_BIND_ENV: &str = "SHADOW_DHCP4_BIND";
+pub const V6_BIND_ENV: &str = "SHADOW_DHCP6_BIND";
+
+/// A single value or a list of them, so options that grew to take several
+/// still accept the one they used to
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
 /// Server wide configuration, used to deserialize the config.json file before
 /// transforming to `Config`
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 struct ServerConfig {
+    #[serde(default = "default_protocol_enabled")]
+    enable_v4: bool,
+    #[serde(default = "default_protocol_enabled")]
+    enable_v6: bool,
+    #[serde(default)]
     dns_v4: Vec<Ipv4Addr>,
+    #[serde(default)]
     dns_v6: Vec<Ipv6Addr>,
+    #[serde(default)]
+    domain_search_v6: Vec<String>,
+    #[serde(default)]
+    sntp_v6: Vec<Ipv6Addr>,
+    #[serde(default)]
+    ntp_v6: Vec<Ipv6Addr>,
+    #[serde(default)]
+    ntp_v4: Vec<Ipv4Addr>,
+    interface_mtu: Option<u16>,
+    domain_name: Option<String>,
+    #[serde(default)]
     subnets_v4: Vec<V4Subnet>,
     #[serde(default)]
     option82_extractors: Vec<String>,
@@ -100,27 +274,167 @@ struct ServerConfig {
     #[serde(default)]
     events: EventsConfig,
     mgmt_address: Option<SocketAddr>,
-    v4_bind_address: Option<SocketAddrV4>,
-    v6_bind_address: Option<SocketAddrV6>,
+    grpc_address: Option<SocketAddr>,
+    v4_bind_address: Option<OneOrMany<SocketAddrV4>>,
+    v6_bind_address: Option<OneOrMany<SocketAddrV6>>,
+    v4_reply_source: Option<Ipv4Addr>,
+    v6_reply_source: Option<Ipv6Addr>,
+    v6_server_unicast: Option<Ipv6Addr>,
+    v4_bind_interface: Option<String>,
+    v6_bind_interface: Option<String>,
+    reservation_source: Option<ReservationSourceConfig>,
+    rate_limit: Option<RateLimitConfig>,
+    ha: Option<HaConfig>,
+    conflict_detection: Option<ConflictDetectionConfig>,
+    alerts: Option<AlertsConfig>,
+    #[serde(default)]
+    static_routes: Vec<StaticRoute>,
+    #[serde(default)]
+    v6_advertise_unknown_clients: bool,
+    #[serde(default)]
+    v6_hint_status: bool,
+    #[serde(default)]
+    v6_always_send_options: bool,
+    #[serde(default = "default_rapid_commit_enabled")]
+    rapid_commit_enabled: bool,
+    v6_preference: Option<u8>,
+    #[serde(default)]
+    refuse_lease_conflicts: bool,
+    #[serde(default)]
+    retransmit_window_ms: u64,
+    #[serde(default = "default_relay_cache_size")]
+    relay_cache_size: usize,
+    audit: Option<AuditConfig>,
+    pd_routes: Option<PdRoutesConfig>,
+    capture: Option<CaptureConfig>,
+    #[serde(default = "default_lease_history")]
+    lease_history: usize,
+    #[serde(default)]
+    boot: BootParams,
+    #[serde(default)]
+    omit_sname_vendor_classes: Vec<String>,
+    #[serde(default)]
+    bootp_enabled: bool,
+    v6_na_template: Option<V6NaTemplate>,
+    #[serde(default)]
+    option82_pools: Vec<Option82PoolConfig>,
+    workers: Option<usize>,
+    #[serde(default)]
+    subscriber_networks: Vec<SubscriberNetwork>,
+    #[serde(default)]
+    reservation_profiles: BTreeMap<String, ReservationProfile>,
+    #[serde(default)]
+    device_classes: BTreeMap<String, DeviceClass>,
+    walled_garden: Option<WalledGarden>,
+    fallback: Option<FallbackConfig>,
+}
+
+fn default_protocol_enabled() -> bool {
+    true
+}
+
+fn default_rapid_commit_enabled() -> bool {
+    true
+}
+
+fn default_lease_history() -> usize {
+    4
+}
+
+fn default_relay_cache_size() -> usize {
+    16384
 }
 
-/// Server IDs stored in separate file that may be auto generated in the future
+/// Server IDs stored in a separate file, generated when it is missing.
+/// Each is only required while its protocol is enabled.
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 struct ServerIds {
-    v4: Ipv4Addr,
-    v6: Duid,
+    v4: Option<Ipv4Addr>,
+    v6: Option<Duid>,
+}
+
+/// Ids for a config directory without ids.json: a fresh DUID, and for
+/// DHCPv4 the address replies are sent from, if the config pins one down
+fn generated_server_ids_for(config: &ServerConfig) -> ServerIds {
+    let v4_bind_address = match &config.v4_bind_address {
+        Some(OneOrMany::One(addr)) => Some(*addr.ip()),
+        Some(OneOrMany::Many(addrs)) if addrs.len() == 1 => Some(*addrs[0].ip()),
+        _ => None,
+    };
+    ServerIds {
+        v4: config
+            .v4_reply_source
+            .or(v4_bind_address.filter(|ip| !ip.is_unspecified())),
+        v6: Some(server_id::generate_duid(
+            config.v6_bind_interface.as_deref(),
+        )),
+    }
+}
+
+/// `--enable-v4` and `--enable-v6`, which take precedence over `enable_v4`
+/// and `enable_v6` in config.json, and the [`V4_BIND_ENV`] and
+/// [`V6_BIND_ENV`] values, which take precedence over the bind addresses.
+/// The binary reads these, so loading the config only reads its files.
+#[derive(Debug, Clone, Default)]
+pub struct ProtocolOverrides {
+    pub enable_v4: Option<bool>,
+    pub enable_v6: Option<bool>,
+    /// Comma-separated addresses, as in [`V4_BIND_ENV`]
+    pub v4_bind: Option<String>,
+    /// Comma-separated addresses, as in [`V6_BIND_ENV`]
+    pub v6_bind: Option<String>,
 }
 
 /// DHCP event sinks. Each sink is enabled by its presence. `queue_size` is
 /// applied independently to each sink's bounded channel.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct EventsConfig {
     #[serde(default = "default_events_queue_size")]
     pub queue_size: usize,
     pub tcp: Option<SocketAddr>,
+    /// Encrypt the TCP sink. Needs the `tcp-tls` cargo feature
+    #[serde(default)]
+    pub tcp_tls: Option<TcpTlsConfig>,
+    /// Sent as the first line of every TCP sink connection so the collector
+    /// can reject unknown senders
+    #[serde(default)]
+    pub tcp_token: Option<String>,
+    /// Keep TCP sink events on disk while the collector is unreachable
+    #[serde(default)]
+    pub tcp_spool: Option<TcpSpoolConfig>,
     pub clickhouse: Option<ClickHouseConfig>,
+    /// Which events the workers send at all
+    #[serde(default)]
+    pub filter: EventFilterConfig,
+}
+
+impl std::fmt::Debug for EventsConfig {
+    /// Debug print without the TCP token
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventsConfig")
+            .field("queue_size", &self.queue_size)
+            .field("tcp", &self.tcp)
+            .field("tcp_tls", &self.tcp_tls)
+            .field("tcp_token", &self.tcp_token.as_ref().map(|_| "<redacted>"))
+            .field("tcp_spool", &self.tcp_spool)
+            .field("clickhouse", &self.clickhouse)
+            .field("filter", &self.filter)
+            .finish()
+    }
+}
+
+/// TLS for the events TCP sink. The collector's certificate is checked
+/// against `ca_file`, or against the bundled Mozilla roots when unset.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpTlsConfig {
+    /// Name the collector's certificate must be valid for
+    pub server_name: String,
+    /// PEM file of CA certificates to trust instead of the public roots
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
 }
 
 fn default_events_queue_size() -> usize {
@@ -132,11 +446,59 @@ impl Default for EventsConfig {
         Self {
             queue_size: default_events_queue_size(),
             tcp: None,
+            tcp_tls: None,
+            tcp_token: None,
+            tcp_spool: None,
             clickhouse: None,
+            filter: EventFilterConfig::default(),
+        }
+    }
+}
+
+/// Cuts routine events on busy servers. Failures are always sent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventFilterConfig {
+    /// Send events for messages that were answered, not only failures
+    #[serde(default = "default_event_successes")]
+    pub successes: bool,
+    /// Send one in this many successful renewals and rebinds, 0 for none
+    #[serde(default = "default_renewal_sample")]
+    pub renewal_sample: u32,
+}
+
+fn default_event_successes() -> bool {
+    true
+}
+
+fn default_renewal_sample() -> u32 {
+    1
+}
+
+impl Default for EventFilterConfig {
+    fn default() -> Self {
+        Self {
+            successes: default_event_successes(),
+            renewal_sample: default_renewal_sample(),
         }
     }
 }
 
+/// On-disk buffer for the events TCP sink. Events that can't be sent are
+/// appended here and replayed, oldest first, once the collector is back.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpSpoolConfig {
+    pub path: PathBuf,
+    /// Events that would grow the file past this are dropped
+    #[serde(default = "default_tcp_spool_max_bytes")]
+    pub max_bytes: u64,
+}
+
+fn default_tcp_spool_max_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
 /// Connection details for the self-hosted ClickHouse server
 #[derive(Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -169,150 +531,1038 @@ fn default_database() -> String {
     "dhcp".to_string()
 }
 
-/// Top-level `logging` block from `config.json`.
-#[derive(Deserialize)]
+/// Remote provisioning endpoint polled for the full reservation list
+#[derive(Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
-struct ServerLoggingConfig {
-    /// Log verbosity. One of: `trace`, `debug`, `info`, `warn`, `error`.
-    /// Resolved by the config loader; this field is here so it can sit
-    /// alongside the sinks it controls. Default: `info`.
-    pub level: Option<String>,
-    pub stdout: Option<bool>,
-    pub file: Option<FileLogConfig>,
+pub struct ReservationSourceConfig {
+    /// URL returning the reservations as a JSON array, same format as
+    /// reservations.json
+    pub url: String,
+    /// Seconds between polls, defaults to 300
+    #[serde(default = "default_reservation_source_interval")]
+    pub interval_secs: u64,
+    /// Optional value sent verbatim in the `Authorization` header
+    #[serde(default)]
+    pub authorization: Option<String>,
 }
 
-pub struct LoggingConfig {
-    pub level: tracing::Level,
-    pub stdout: bool,
-    pub file: Option<FileLogConfig>,
+impl std::fmt::Debug for ReservationSourceConfig {
+    /// Debug print without the authorization header
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReservationSourceConfig")
+            .field("url", &self.url)
+            .field("interval_secs", &self.interval_secs)
+            .finish()
+    }
 }
 
-impl Default for LoggingConfig {
-    fn default() -> Self {
-        Self {
-            level: tracing::Level::INFO,
-            stdout: true,
-            file: None,
+fn default_reservation_source_interval() -> u64 {
+    300
+}
+
+/// Per-client (and optionally per-relay) token-bucket limits applied by the
+/// DHCP workers before a packet is handled
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Bucket per client, keyed by chaddr MAC (v4) or client DUID (v6)
+    #[serde(default = "default_client_bucket")]
+    pub client: BucketConfig,
+    /// Bucket per relay address, disabled if not set
+    #[serde(default)]
+    pub relay: Option<BucketConfig>,
+    /// Consecutive dropped packets before a client is banned, 0 disables bans
+    #[serde(default = "default_ban_threshold")]
+    pub ban_threshold: u32,
+    /// Length of a client ban in seconds
+    #[serde(default = "default_ban_secs")]
+    pub ban_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    /// Sustained packets per second
+    pub per_second: f64,
+    /// Packets allowed back to back before the rate applies
+    pub burst: u32,
+}
+
+impl BucketConfig {
+    fn validate(&self) -> Result<(), &'static str> {
+        if !(self.per_second.is_finite() && self.per_second > 0.0) {
+            return Err("per_second must be greater than 0");
+        }
+        if self.burst == 0 {
+            return Err("burst must be at least 1");
         }
+        Ok(())
     }
 }
 
-impl TryFrom<ServerLoggingConfig> for LoggingConfig {
-    type Error = ConfigError;
-
-    fn try_from(c: ServerLoggingConfig) -> Result<Self, Self::Error> {
-        let level = match c.level.as_deref().filter(|s| !s.is_empty()) {
-            Some(s) => {
-                tracing::Level::from_str(s).map_err(|_| ConfigError::LogLevel(s.to_string()))?
-            }
-            None => tracing::Level::INFO,
-        };
-        Ok(Self {
-            level,
-            stdout: c.stdout.unwrap_or(true),
-            file: c.file,
-        })
+fn default_client_bucket() -> BucketConfig {
+    BucketConfig {
+        per_second: 1.0,
+        burst: 10,
     }
 }
 
+fn default_ban_threshold() -> u32 {
+    50
+}
+
+fn default_ban_secs() -> u64 {
+    300
+}
+
+/// Active/standby pair. Both peers replicate their option82/18/37 bindings
+/// and lease holders to each other; the standby only answers DHCP while the
+/// primary is silent.
 #[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct FileLogConfig {
+pub struct HaConfig {
+    /// Address to accept the peer's connection on
+    pub listen: SocketAddr,
+    /// The peer's `listen` address
+    pub peer: SocketAddr,
+    #[serde(default)]
+    pub role: HaRole,
+    /// Seconds between heartbeats sent to the peer
+    #[serde(default = "default_heartbeat_secs")]
+    pub heartbeat_secs: u64,
+    /// Seconds without hearing from the primary before the standby answers
+    #[serde(default = "default_failover_secs")]
+    pub failover_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HaRole {
+    #[default]
+    Primary,
+    Standby,
+}
+
+fn default_heartbeat_secs() -> u64 {
+    1
+}
+
+fn default_failover_secs() -> u64 {
+    5
+}
+
+/// ICMP echo probe of the reserved address before a DHCPv4 OFFER, to catch
+/// hosts squatting on it with a static configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConflictDetectionConfig {
+    /// How long to wait for an echo reply. The v4 worker blocks for this
+    /// long on every DISCOVER whose address doesn't answer.
+    #[serde(default = "default_probe_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Seconds to stop offering an address after a conflict, 0 disables
+    #[serde(default)]
+    pub quarantine_secs: u64,
+}
+
+fn default_probe_timeout_ms() -> u64 {
+    300
+}
+
+/// Capacity thresholds, checked once a minute. Crossing one either way is
+/// logged and sent as an alert event.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertsConfig {
+    /// Percentage of a subnet's reservations with a running lease
+    #[serde(default)]
+    pub subnet_lease_percent: Option<u8>,
+    /// NoReservation failures a minute, DHCPv4 and DHCPv6 together
+    #[serde(default)]
+    pub no_reservation_per_minute: Option<u64>,
+}
+
+/// Probes block the v4 worker, so keep them short.
+const MAX_PROBE_TIMEOUT_MS: u64 = 2000;
+/// Longer than a client waits before retransmitting, and short enough that a
+/// reservation change is picked up by the client's next attempt
+const MAX_RETRANSMIT_WINDOW_MS: u64 = 10_000;
+
+/// Append-only record of every address handed out, kept apart from the
+/// analytics events so it doesn't depend on a collector being up
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
     pub path: PathBuf,
-    #[serde(default = "default_max_files")]
+    /// Size at which the file is rotated
+    #[serde(default = "default_audit_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotated files to keep, 0 keeps all of them
+    #[serde(default)]
     pub max_files: usize,
 }
 
-fn default_max_files() -> usize {
-    3
+fn default_audit_max_bytes() -> u64 {
+    100 * 1024 * 1024
 }
 
-#[derive(Debug)]
-pub enum ConfigError {
-    UnknownOption82Extractor(String),
-    UnknownOption1837Extractor(String),
-    Parsing {
-        err: serde_json::Error,
-        path: PathBuf,
-    },
-    Io {
-        err: std::io::Error,
-        path: PathBuf,
+/// Where routes for delegated prefixes are sent, see [`crate::pd_routes`].
+/// Any combination of the three may be set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PdRoutesConfig {
+    /// Run as `exec add|change|delete PREFIX NEXT_HOP`
+    pub exec: Option<PathBuf>,
+    /// ExaBGP's API named pipe, written `announce`/`withdraw route` commands
+    pub exabgp_pipe: Option<PathBuf>,
+    /// Rewritten with a BIRD static route for each live prefix
+    pub route_file: Option<PathBuf>,
+}
+
+impl PdRoutesConfig {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.exec.is_none() && self.exabgp_pipe.is_none() && self.route_file.is_none() {
+            return Err("set at least one of exec, exabgp_pipe and route_file");
+        }
+        Ok(())
+    }
+}
+
+/// Rule for filling in `"ipv6_na": "auto"` reservations when they are
+/// loaded. Either way the address is `interface_id` within a /64, so the
+/// same reservation always gets the same address.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "from", rename_all = "snake_case", deny_unknown_fields)]
+pub enum V6NaTemplate {
+    /// The `subnet`th /64 of the reservation's own delegated prefix
+    Pd {
+        #[serde(default)]
+        subnet: u64,
+        #[serde(default = "default_na_interface_id")]
+        interface_id: u64,
     },
-    LogLevel(String),
-    InvalidSubnet {
-        subnet: String,
-        reason: &'static str,
+    /// The /64 numbered by the reservation's numeric `subscriber_id`,
+    /// counting from the start of `prefix`
+    SubscriberId {
+        prefix: Ipv6Net,
+        #[serde(default = "default_na_interface_id")]
+        interface_id: u64,
     },
-    EmptyDnsV4,
-    EmptyDnsV6,
-    MgmtNotLoopback(SocketAddr),
 }
 
-trait PathContext<T> {
-    fn context<P: AsRef<Path>>(self, path: P) -> Result<T, ConfigError>;
+fn default_na_interface_id() -> u64 {
+    1
 }
 
-impl<T> PathContext<T> for serde_json::Result<T> {
-    fn context<P: AsRef<Path>>(self, path: P) -> Result<T, ConfigError> {
-        self.map_err(|e| ConfigError::Parsing {
-            err: e,
-            path: path.as_ref().to_path_buf(),
-        })
+impl V6NaTemplate {
+    fn validate(&self) -> Result<(), &'static str> {
+        let interface_id = match self {
+            V6NaTemplate::Pd { interface_id, .. } => *interface_id,
+            V6NaTemplate::SubscriberId {
+                prefix,
+                interface_id,
+            } => {
+                if prefix.prefix_len() > 64 {
+                    return Err("prefix must be a /64 or shorter");
+                }
+                *interface_id
+            }
+        };
+        if interface_id == 0 {
+            return Err("interface_id must not be 0");
+        }
+        Ok(())
+    }
+
+    /// Address for `reservation`, whose `ipv6_pd` and `subscriber_id` are
+    /// the only inputs
+    pub fn derive(&self, reservation: &Reservation) -> Result<Ipv6Addr, String> {
+        match self {
+            V6NaTemplate::Pd {
+                subnet,
+                interface_id,
+            } => nth_64(reservation.ipv6_pd, *subnet, *interface_id).ok_or_else(|| {
+                format!("ipv6_pd {} has no /64 number {subnet}", reservation.ipv6_pd)
+            }),
+            V6NaTemplate::SubscriberId {
+                prefix,
+                interface_id,
+            } => {
+                let id = reservation
+                    .subscriber_id
+                    .as_deref()
+                    .ok_or("no subscriber_id")?;
+                let n = id
+                    .parse::<u64>()
+                    .map_err(|_| format!("subscriber_id `{id}` is not a number"))?;
+                nth_64(*prefix, n, *interface_id)
+                    .ok_or_else(|| format!("subscriber_id {n} is past the end of {prefix}"))
+            }
+        }
     }
 }
 
-impl<T> PathContext<T> for std::io::Result<T> {
-    fn context<P: AsRef<Path>>(self, path: P) -> Result<T, ConfigError> {
-        self.map_err(|e| ConfigError::Io {
-            err: e,
-            path: path.as_ref().to_path_buf(),
-        })
+/// `interface_id` in the `n`th /64 of `net`, if `net` has that many
+pub(crate) fn nth_64(net: Ipv6Net, n: u64, interface_id: u64) -> Option<Ipv6Addr> {
+    let bits = 64u32.checked_sub(net.prefix_len() as u32)?;
+    if bits < 64 && n >> bits != 0 {
+        return None;
     }
+    let addr = u128::from(net.network()) | ((n as u128) << 64) | interface_id as u128;
+    Some(Ipv6Addr::from(addr))
 }
 
-impl fmt::Display for ConfigError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ConfigError::UnknownOption82Extractor(name) => {
-                writeln!(f, "Unknown Option82 extractor: `{name}`")?;
-                write!(
-                    f,
-                    "Run `shadowdhcp --available-extractors` to see valid options"
-                )
-            }
-            ConfigError::UnknownOption1837Extractor(name) => {
-                writeln!(f, "Unknown Option18/37 extractor: `{name}`")?;
-                write!(
-                    f,
-                    "Run `shadowdhcp --available-extractors` to see valid options"
-                )
-            }
-            ConfigError::Parsing { err, path } => {
-                writeln!(f, "Failed to parse `{}`:", path.to_string_lossy())?;
-                writeln!(f, "  {err}")?;
-                write!(
-                    f,
-                    "Run `shadowdhcp --help-config` for configuration file format"
-                )
-            }
-            ConfigError::Io { err, path } => {
-                writeln!(f, "Cannot read `{}`: {err}", path.to_string_lossy())?;
-                write!(f, "Check the file exists, or run with --configdir to specify the directory containing config.json and ids.json")
-            }
-            ConfigError::LogLevel(value) => {
-                writeln!(f, "Invalid logging.level: `{value}`")?;
-                write!(f, "Expected one of: trace, debug, info, warn, error")
-            }
-            ConfigError::InvalidSubnet { subnet, reason } => {
-                write!(f, "Invalid subnet `{subnet}`: {reason}")
-            }
-            ConfigError::EmptyDnsV4 => {
-                write!(f, "`dns_v4` must contain at least one IPv4 address.")
+/// Addresses for Option 82 keys that match the pool but have no reservation
+/// yet, so a new subscriber comes online before provisioning catches up.
+/// The key produced by `extractor` is hashed to a slot, and slot `n` gets the
+/// `n`th address of `ipv4`, the `n`th /64 of `ipv6_na` and the `n`th
+/// `/pd_len` of `ipv6_pd`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Option82PoolConfig {
+    /// Identifies the pool in the persisted assignments
+    pub name: String,
+    /// One of the Option 82 extractor names
+    pub extractor: String,
+    /// Only keys whose circuit-id starts with this belong to the pool
+    pub circuit_prefix: Option<String>,
+    /// Only keys whose remote-id starts with this belong to the pool
+    pub remote_prefix: Option<String>,
+    pub ipv4: ipnet::Ipv4Net,
+    pub ipv6_na: Ipv6Net,
+    pub ipv6_pd: Ipv6Net,
+    pub pd_len: u8,
+}
+
+impl Option82PoolConfig {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.name.is_empty() {
+            return Err("name must not be empty");
+        }
+        if self.ipv4.prefix_len() > 30 {
+            return Err("ipv4 must be a /30 or shorter");
+        }
+        if self.ipv6_na.prefix_len() > 64 {
+            return Err("ipv6_na must be a /64 or shorter");
+        }
+        if self.pd_len < self.ipv6_pd.prefix_len() || self.pd_len > 64 {
+            return Err("pd_len must be between the ipv6_pd length and 64");
+        }
+        Ok(())
+    }
+}
+
+/// Overrides for the clients behind some of the relays, such as a business
+/// network that wants its own resolvers and shorter leases. A network is
+/// picked by the relay's giaddr (DHCPv4) or link-address (DHCPv6); anything
+/// it leaves out comes from the top level.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriberNetwork {
+    pub name: String,
+    #[serde(default)]
+    pub relays_v4: Vec<Ipv4Net>,
+    #[serde(default)]
+    pub relays_v6: Vec<Ipv6Net>,
+    pub dns_v4: Option<Vec<Ipv4Addr>>,
+    pub dns_v6: Option<Vec<Ipv6Addr>>,
+    pub domain_search_v6: Option<Vec<String>>,
+    pub sntp_v6: Option<Vec<Ipv6Addr>>,
+    pub ntp_v6: Option<Vec<Ipv6Addr>>,
+    pub ntp_v4: Option<Vec<Ipv4Addr>>,
+    pub interface_mtu: Option<u16>,
+    pub domain_name: Option<String>,
+    pub static_routes: Option<Vec<StaticRoute>>,
+    pub v4_lease_time: Option<u32>,
+    /// Defaults to 12 times this network's `v4_lease_time` when that is
+    /// set, the top-level v6 lease otherwise
+    pub v6_lease_time: Option<u32>,
+    /// NAK DHCPv4 clients without a reservation, in place of the
+    /// `authoritative` flag of the subnet the relay is in
+    pub authoritative: Option<bool>,
+    pub v6_advertise_unknown_clients: Option<bool>,
+}
+
+impl SubscriberNetwork {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.relays_v4.is_empty() && self.relays_v6.is_empty() {
+            return Err("needs at least one prefix in relays_v4 or relays_v6");
+        }
+        if self.dns_v4.as_ref().is_some_and(Vec::is_empty) {
+            return Err("dns_v4 must contain at least one IPv4 address");
+        }
+        if self.dns_v6.as_ref().is_some_and(Vec::is_empty) {
+            return Err("dns_v6 must contain at least one IPv6 address");
+        }
+        Ok(())
+    }
+}
+
+/// The settings a reply is built from: the top-level ones with the
+/// overrides of the relay's subscriber network, if it is in one
+#[derive(Debug, Clone, Copy)]
+pub struct Profile<'a> {
+    /// `None` when no subscriber network matched
+    pub network: Option<&'a str>,
+    pub dns_v4: &'a [Ipv4Addr],
+    pub dns_v6: &'a [Ipv6Addr],
+    pub domain_search_v6: &'a [String],
+    pub sntp_v6: &'a [Ipv6Addr],
+    pub ntp_v6: &'a [Ipv6Addr],
+    pub ntp_v4: &'a [Ipv4Addr],
+    pub interface_mtu: Option<u16>,
+    pub domain_name: Option<&'a str>,
+    pub static_routes: &'a [StaticRoute],
+    pub lease_times: LeaseTimes,
+    /// DHCPv4: NAK clients without a reservation. DHCPv6: Advertise
+    /// NoAddrsAvail to them. Otherwise they are ignored.
+    pub answer_unknown_clients: bool,
+    /// BOOTP fields of the reservation's profile, which the reservation's
+    /// own override and which override the config-level `boot`
+    pub boot: Option<&'a BootParams>,
+    /// Settings of the client's device class, applied after the
+    /// reservation's profile. See [`Profile::with_device_class`].
+    pub device: Option<&'a ReservationProfile>,
+}
+
+impl<'a> Profile<'a> {
+    /// These settings with the overrides of the reservation's
+    /// `reservation_profiles` entry, then those of the client's device
+    /// class. An unknown profile name is logged and ignored, since
+    /// reservations aren't checked against the config when they are loaded.
+    pub fn with_reservation(self, config: &'a Config, reservation: &Reservation) -> Profile<'a> {
+        let profile = match &reservation.profile {
+            Some(name) => match config.reservation_profiles.get(name.as_str()) {
+                Some(profile) => self.overridden_by(profile),
+                None => {
+                    tracing::warn!(
+                        reservation = %reservation.name(),
+                        "reservation uses unknown profile `{name}`, ignoring it"
+                    );
+                    self
+                }
+            },
+            None => self,
+        };
+        match self.device {
+            Some(device) => profile.overridden_by(device),
+            None => profile,
+        }
+    }
+
+    /// Remember the settings of the `device_classes` entry listing
+    /// `label`, the client's [fingerprint](crate::fingerprint), for
+    /// [`with_reservation`](Self::with_reservation). They come last so a
+    /// class can hold back what its firmware mishandles whatever the
+    /// customer's product.
+    pub fn with_device_class(self, config: &'a Config, label: Option<&str>) -> Profile<'a> {
+        let device = label
+            .and_then(|label| config.device_class(label))
+            .and_then(|(_, class)| class.profile.as_deref())
+            .and_then(|name| config.reservation_profiles.get(name));
+        Profile { device, ..self }
+    }
+
+    fn overridden_by(self, profile: &'a ReservationProfile) -> Profile<'a> {
+        Profile {
+            dns_v4: profile.dns_v4.as_deref().unwrap_or(self.dns_v4),
+            dns_v6: profile.dns_v6.as_deref().unwrap_or(self.dns_v6),
+            domain_search_v6: profile
+                .domain_search_v6
+                .as_deref()
+                .unwrap_or(self.domain_search_v6),
+            sntp_v6: profile.sntp_v6.as_deref().unwrap_or(self.sntp_v6),
+            ntp_v6: profile.ntp_v6.as_deref().unwrap_or(self.ntp_v6),
+            ntp_v4: profile.ntp_v4.as_deref().unwrap_or(self.ntp_v4),
+            interface_mtu: profile.interface_mtu.or(self.interface_mtu),
+            domain_name: profile.domain_name.as_deref().or(self.domain_name),
+            static_routes: profile
+                .static_routes
+                .as_deref()
+                .unwrap_or(self.static_routes),
+            lease_times: self
+                .lease_times
+                .with_overrides(profile.v4_lease_time, profile.v6_lease_time),
+            boot: profile.boot.as_ref().or(self.boot),
+            ..self
+        }
+    }
+}
+
+/// Settings shared by a group of reservations, such as every customer on
+/// one product, named by the reservations' `profile`. Applied on top of the
+/// relay's subscriber network; the reservation's own `static_routes` and
+/// `boot` still come last.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReservationProfile {
+    pub dns_v4: Option<Vec<Ipv4Addr>>,
+    pub dns_v6: Option<Vec<Ipv6Addr>>,
+    pub domain_search_v6: Option<Vec<String>>,
+    pub sntp_v6: Option<Vec<Ipv6Addr>>,
+    pub ntp_v6: Option<Vec<Ipv6Addr>>,
+    pub ntp_v4: Option<Vec<Ipv4Addr>>,
+    pub interface_mtu: Option<u16>,
+    pub domain_name: Option<String>,
+    pub static_routes: Option<Vec<StaticRoute>>,
+    pub v4_lease_time: Option<u32>,
+    pub v6_lease_time: Option<u32>,
+    pub boot: Option<BootParams>,
+}
+
+impl ReservationProfile {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.dns_v4.as_ref().is_some_and(Vec::is_empty) {
+            return Err("dns_v4 must contain at least one IPv4 address");
+        }
+        if self.dns_v6.as_ref().is_some_and(Vec::is_empty) {
+            return Err("dns_v6 must contain at least one IPv6 address");
+        }
+        if let Some(boot) = &self.boot {
+            boot.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// CPE whose DHCP clients send the same requests, named by their
+/// `device_classes` key. See [`crate::fingerprint`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceClass {
+    /// The `device_class` labels events report for this firmware
+    pub fingerprints: Vec<String>,
+    /// `reservation_profiles` entry with the settings for this class
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+/// A subnet for suspended customers, whose resolvers send every name to a
+/// payment or notice page. A suspended reservation is offered the address
+/// with its own `ipv4`'s host bits in `net`, so each customer keeps a
+/// stable address there, with these resolvers and a short lease so it
+/// moves back soon after being resumed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WalledGarden {
+    pub net: Ipv4Net,
+    pub gateway: Ipv4Addr,
+    pub dns_v4: Vec<Ipv4Addr>,
+    #[serde(default = "default_walled_garden_lease_time")]
+    pub lease_time: u32,
+}
+
+fn default_walled_garden_lease_time() -> u32 {
+    300
+}
+
+impl WalledGarden {
+    fn validate(&self, subnets: &[V4Subnet]) -> Result<(), &'static str> {
+        if self.net.prefix_len() > 30 {
+            return Err("net must be a /30 or shorter");
+        }
+        if !self.net.contains(&self.gateway)
+            || self.gateway == self.net.network()
+            || self.gateway == self.net.broadcast()
+        {
+            return Err("gateway must be a host address inside net");
+        }
+        if self.dns_v4.is_empty() {
+            return Err("dns_v4 must contain at least one IPv4 address");
+        }
+        if self.lease_time == 0 {
+            return Err("lease_time must be greater than 0");
+        }
+        if subnets
+            .iter()
+            .flat_map(V4Subnet::prefixes)
+            .any(|net| net.contains(&self.net) || self.net.contains(&net))
+        {
+            return Err("net must not overlap subnets_v4");
+        }
+        Ok(())
+    }
+
+    /// The garden address for a reservation of `ipv4`. `None` when its host
+    /// bits land on the garden's network, broadcast or gateway address.
+    pub fn address_for(&self, ipv4: Ipv4Addr) -> Option<Ipv4Addr> {
+        let host = u32::from(ipv4) & u32::from(self.net.hostmask());
+        let address = Ipv4Addr::from(u32::from(self.net.network()) | host);
+        (address != self.net.network()
+            && address != self.net.broadcast()
+            && address != self.gateway)
+            .then_some(address)
+    }
+
+    /// `base` with the garden's resolvers and lease, and without the routes
+    /// and boot parameters meant for the customer's own network
+    pub fn profile<'a>(&'a self, base: Profile<'a>) -> Profile<'a> {
+        Profile {
+            dns_v4: &self.dns_v4,
+            static_routes: &[],
+            lease_times: base.lease_times.with_overrides(Some(self.lease_time), None),
+            boot: None,
+            ..base
+        }
+    }
+}
+
+/// The server being migrated from, `address:port` for each protocol. See
+/// [`crate::fallback`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FallbackConfig {
+    pub v4: Option<SocketAddrV4>,
+    pub v6: Option<SocketAddrV6>,
+}
+
+impl FallbackConfig {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.v4.is_none() && self.v6.is_none() {
+            return Err("set at least one of v4 and v6");
+        }
+        if self
+            .v4
+            .is_some_and(|v4| v4.ip().is_unspecified() || v4.port() == 0)
+            || self
+                .v6
+                .is_some_and(|v6| v6.ip().is_unspecified() || v6.port() == 0)
+        {
+            return Err("addresses must name a host and port");
+        }
+        Ok(())
+    }
+}
+
+/// Clients to capture packets for from startup. Capture can also be
+/// switched on and off per client over the management interface.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    #[serde(default)]
+    pub macs: Vec<MacAddr6>,
+    /// Packets kept per client, both directions together
+    #[serde(default = "default_capture_depth")]
+    pub depth: usize,
+}
+
+pub fn default_capture_depth() -> usize {
+    64
+}
+
+/// Top-level `logging` block from `config.json`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServerLoggingConfig {
+    /// Log verbosity. One of: `trace`, `debug`, `info`, `warn`, `error`.
+    /// Resolved by the config loader; this field is here so it can sit
+    /// alongside the sinks it controls. Default: `info`.
+    pub level: Option<String>,
+    /// Module path prefix → level, overriding `level` for those targets
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+    #[serde(default)]
+    pub format: LogFormat,
+    pub stdout: Option<bool>,
+    pub file: Option<FileLogConfig>,
+    pub syslog: Option<SyslogConfig>,
+}
+
+pub struct LoggingConfig {
+    pub level: tracing::Level,
+    /// Per-module overrides of `level`. `off` silences a module entirely.
+    pub modules: Vec<(String, LevelFilter)>,
+    pub format: LogFormat,
+    pub stdout: bool,
+    pub file: Option<FileLogConfig>,
+    /// Lease events sent on to a syslog collector, see
+    /// [`crate::analytics::syslog`]
+    pub syslog: Option<SyslogConfig>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: tracing::Level::INFO,
+            modules: Vec::new(),
+            format: LogFormat::default(),
+            stdout: true,
+            file: None,
+            syslog: None,
+        }
+    }
+}
+
+impl TryFrom<ServerLoggingConfig> for LoggingConfig {
+    type Error = ConfigError;
+
+    fn try_from(c: ServerLoggingConfig) -> Result<Self, Self::Error> {
+        let level = match c.level.as_deref().filter(|s| !s.is_empty()) {
+            Some(s) => {
+                tracing::Level::from_str(s).map_err(|_| ConfigError::LogLevel(s.to_string()))?
+            }
+            None => tracing::Level::INFO,
+        };
+        let modules = c
+            .modules
+            .into_iter()
+            .map(|(module, value)| match LevelFilter::from_str(&value) {
+                Ok(filter) => Ok((module, filter)),
+                Err(_) => Err(ConfigError::ModuleLogLevel { module, value }),
+            })
+            .collect::<Result<_, _>>()?;
+        if let Some(file) = &c.file {
+            if file.rotation == LogRotation::Size && file.max_bytes == 0 {
+                return Err(ConfigError::LogFileMaxBytes);
+            }
+        }
+        if let Some(syslog) = &c.syslog {
+            syslog.validate().map_err(ConfigError::InvalidSyslog)?;
+        }
+        Ok(Self {
+            level,
+            modules,
+            format: c.format,
+            stdout: c.stdout.unwrap_or(true),
+            file: c.file,
+            syslog: c.syslog,
+        })
+    }
+}
+
+/// Line format for every log sink. `auto` keeps the historical behaviour:
+/// stdout is human-readable on a terminal and JSON when piped, the file is
+/// always JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Auto,
+    Json,
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    #[default]
+    Daily,
+    Hourly,
+    /// Roll over once the file reaches `max_bytes`
+    Size,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileLogConfig {
+    pub path: PathBuf,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// Only used with `"rotation": "size"`
+    #[serde(default = "default_log_max_bytes")]
+    pub max_bytes: u64,
+}
+
+fn default_max_files() -> usize {
+    3
+}
+
+fn default_log_max_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyslogConfig {
+    /// Collector `host:port`
+    pub address: String,
+    #[serde(default)]
+    pub protocol: SyslogProtocol,
+    #[serde(default)]
+    pub facility: SyslogFacility,
+    /// SD-ID of the structured data element holding the lease fields
+    #[serde(default = "default_syslog_sd_id")]
+    pub sd_id: String,
+    /// Override the system's hostname in the HOSTNAME field
+    pub hostname: Option<String>,
+}
+
+/// `32473` is the enterprise number RFC 5612 reserves for documentation;
+/// sites with their own can put it in `sd_id`
+fn default_syslog_sd_id() -> String {
+    "lease@32473".to_string()
+}
+
+impl SyslogConfig {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.address.is_empty() {
+            return Err("address must be a host:port");
+        }
+        // SD-NAME characters (RFC 5424 §6.3.2), and an SD-ID without an
+        // enterprise number is reserved for IANA
+        let name_char = |c: char| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"');
+        let valid = self.sd_id.len() <= 32
+            && self.sd_id.chars().all(name_char)
+            && self.sd_id.split_once('@').is_some_and(|(name, number)| {
+                !name.is_empty()
+                    && !number.is_empty()
+                    && number.chars().all(|c| c.is_ascii_digit() || c == '.')
+            });
+        if !valid {
+            return Err("sd_id must be NAME@ENTERPRISE_NUMBER, at most 32 characters");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogProtocol {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    #[default]
+    Daemon,
+    Auth,
+    Authpriv,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Facility number from RFC 5424 §6.2.1
+    pub fn code(self) -> u8 {
+        match self {
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Auth => 4,
+            SyslogFacility::Authpriv => 10,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownOption82Extractor(String),
+    UnknownOption1837Extractor(String),
+    Parsing {
+        err: serde_json::Error,
+        path: PathBuf,
+        /// The line the error points at, for context
+        line: Option<String>,
+    },
+    Io {
+        err: std::io::Error,
+        path: PathBuf,
+    },
+    LogLevel(String),
+    ModuleLogLevel {
+        module: String,
+        value: String,
+    },
+    LogFileMaxBytes,
+    InvalidSyslog(&'static str),
+    InvalidSubnet {
+        subnet: String,
+        reason: &'static str,
+    },
+    /// A prefix in two `subnets_v4` entries, the first of which is `first`
+    DuplicateSubnet {
+        prefix: Ipv4Net,
+        first: Ipv4Net,
+    },
+    EmptyDnsV4,
+    EmptyDnsV6,
+    NoProtocolEnabled,
+    /// ids.json has no server id for an enabled protocol: `v4` or `v6`
+    MissingServerId(&'static str),
+    InvalidDomainSearch(String),
+    InvalidDomainName(String),
+    InvalidInterfaceMtu(u16),
+    MgmtNotLoopback(SocketAddr),
+    GrpcNotLoopback(SocketAddr),
+    ReservationSourceInterval,
+    InvalidRateLimit(&'static str),
+    InvalidHa(&'static str),
+    ConflictProbeTimeout,
+    InvalidAlerts(&'static str),
+    RetransmitWindow,
+    InvalidStaticRoute(ipnet::Ipv4Net),
+    AuditMaxBytes,
+    InvalidPdRoutes(&'static str),
+    TcpSpoolMaxBytes,
+    CaptureDepth,
+    InvalidBoot(&'static str),
+    InvalidNaTemplate(&'static str),
+    InvalidPool {
+        pool: String,
+        reason: &'static str,
+    },
+    InvalidSubscriberNetwork {
+        network: String,
+        reason: &'static str,
+    },
+    InvalidReservationProfile {
+        profile: String,
+        reason: &'static str,
+    },
+    InvalidDeviceClass {
+        class: String,
+        reason: &'static str,
+    },
+    InvalidWalledGarden(&'static str),
+    InvalidFallback(&'static str),
+    Workers,
+    InvalidServerDuid(usize),
+    InvalidReplySource(&'static str),
+    InvalidServerUnicast(&'static str),
+    InvalidBindEnv {
+        var: &'static str,
+        value: String,
+    },
+    InvalidBindAddress {
+        option: &'static str,
+        reason: String,
+    },
+    /// Every problem found in `path`, when there is more than one
+    Multiple {
+        path: PathBuf,
+        errors: Vec<ConfigError>,
+    },
+}
+
+trait PathContext<T> {
+    fn context<P: AsRef<Path>>(self, path: P) -> Result<T, ConfigError>;
+}
+
+impl<T> PathContext<T> for std::io::Result<T> {
+    fn context<P: AsRef<Path>>(self, path: P) -> Result<T, ConfigError> {
+        self.map_err(|e| ConfigError::Io {
+            err: e,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownOption82Extractor(name) => {
+                writeln!(f, "Unknown Option82 extractor: `{name}`")?;
+                write!(
+                    f,
+                    "Run `shadowdhcp --available-extractors` to see valid options"
+                )
+            }
+            ConfigError::UnknownOption1837Extractor(name) => {
+                writeln!(f, "Unknown Option18/37 extractor: `{name}`")?;
+                write!(
+                    f,
+                    "Run `shadowdhcp --available-extractors` to see valid options"
+                )
+            }
+            ConfigError::Parsing { err, path, line } => {
+                writeln!(
+                    f,
+                    "Failed to parse `{}` at line {}, column {}:",
+                    path.to_string_lossy(),
+                    err.line(),
+                    err.column()
+                )?;
+                writeln!(f, "  {err}")?;
+                if let Some(line) = line {
+                    writeln!(f, "  | {line}")?;
+                    writeln!(f, "  | {:>width$}", "^", width = err.column().max(1))?;
+                }
+                write!(
+                    f,
+                    "Run `shadowdhcp --help-config` for configuration file format"
+                )
+            }
+            ConfigError::Io { err, path } => {
+                writeln!(f, "Cannot read `{}`: {err}", path.to_string_lossy())?;
+                write!(f, "Check the file exists, or run with --configdir to specify the directory containing config.json and ids.json")
+            }
+            ConfigError::LogLevel(value) => {
+                writeln!(f, "Invalid logging.level: `{value}`")?;
+                write!(f, "Expected one of: trace, debug, info, warn, error")
+            }
+            ConfigError::ModuleLogLevel { module, value } => {
+                writeln!(f, "Invalid logging.modules level for `{module}`: `{value}`")?;
+                write!(f, "Expected one of: off, trace, debug, info, warn, error")
+            }
+            ConfigError::LogFileMaxBytes => {
+                write!(f, "`logging.file.max_bytes` must be greater than 0.")
+            }
+            ConfigError::InvalidSyslog(reason) => {
+                write!(f, "Invalid logging.syslog: {reason}")
+            }
+            ConfigError::InvalidSubnet { subnet, reason } => {
+                write!(f, "Invalid subnet `{subnet}`: {reason}")
+            }
+            ConfigError::DuplicateSubnet { prefix, first } => {
+                write!(
+                    f,
+                    "Subnet `{prefix}` is already in subnets_v4 entry `{first}`"
+                )
+            }
+            ConfigError::EmptyDnsV4 => {
+                write!(f, "`dns_v4` must contain at least one IPv4 address.")
             }
             ConfigError::EmptyDnsV6 => {
                 write!(f, "`dns_v6` must contain at least one IPv6 address.")
             }
+            ConfigError::NoProtocolEnabled => {
+                write!(f, "`enable_v4` and `enable_v6` are both false; at least one protocol must be enabled.")
+            }
+            ConfigError::MissingServerId(protocol) => {
+                write!(
+                    f,
+                    "ids.json has no `{protocol}` server id, which is needed while `enable_{protocol}` is true."
+                )?;
+                if *protocol == "v4" {
+                    write!(f, "\nWithout ids.json it is taken from `v4_reply_source`, or a single `v4_bind_address` that isn't 0.0.0.0.")?;
+                }
+                Ok(())
+            }
+            ConfigError::InvalidDomainSearch(domain) => {
+                write!(
+                    f,
+                    "Invalid `domain_search_v6` entry `{domain}`: labels must be 1-63 bytes and the name at most 253 bytes."
+                )
+            }
+            ConfigError::InvalidDomainName(domain) => {
+                write!(
+                    f,
+                    "Invalid `domain_name` `{domain}`: labels must be 1-63 bytes and the name at most 253 bytes."
+                )
+            }
+            ConfigError::InvalidInterfaceMtu(mtu) => {
+                write!(
+                    f,
+                    "Invalid `interface_mtu` {mtu}: must be at least {MIN_INTERFACE_MTU} (RFC 2132 §5.1)."
+                )
+            }
             ConfigError::MgmtNotLoopback(addr) => {
                 writeln!(
                     f,
@@ -323,20 +1573,329 @@ impl fmt::Display for ConfigError {
                     "The management interface has no authentication and full write access to reservations; management clients are expected to run on the same machine."
                 )
             }
+            ConfigError::GrpcNotLoopback(addr) => {
+                writeln!(
+                    f,
+                    "grpc_address `{addr}` must be a loopback address (e.g. 127.0.0.1 or [::1])."
+                )?;
+                write!(
+                    f,
+                    "Like the management interface, the gRPC service has no authentication; put a proxy in front of it to reach it from other machines."
+                )
+            }
+            ConfigError::ReservationSourceInterval => {
+                write!(
+                    f,
+                    "`reservation_source.interval_secs` must be greater than 0."
+                )
+            }
+            ConfigError::InvalidRateLimit(reason) => {
+                write!(f, "Invalid rate_limit: {reason}")
+            }
+            ConfigError::InvalidHa(reason) => {
+                write!(f, "Invalid ha: {reason}")
+            }
+            ConfigError::InvalidAlerts(reason) => {
+                write!(f, "Invalid alerts: {reason}")
+            }
+            ConfigError::ConflictProbeTimeout => {
+                write!(
+                    f,
+                    "`conflict_detection.timeout_ms` must be between 1 and {MAX_PROBE_TIMEOUT_MS}."
+                )
+            }
+            ConfigError::RetransmitWindow => {
+                write!(
+                    f,
+                    "`retransmit_window_ms` must be at most {MAX_RETRANSMIT_WINDOW_MS}."
+                )
+            }
+            ConfigError::InvalidStaticRoute(destination) => {
+                write!(
+                    f,
+                    "Static route destination `{destination}` has host bits set, did you mean `{}`?",
+                    destination.trunc()
+                )
+            }
+            ConfigError::AuditMaxBytes => {
+                write!(f, "`audit.max_bytes` must be greater than 0.")
+            }
+            ConfigError::InvalidPdRoutes(reason) => {
+                write!(f, "Invalid pd_routes: {reason}")
+            }
+            ConfigError::TcpSpoolMaxBytes => {
+                write!(f, "`events.tcp_spool.max_bytes` must be greater than 0.")
+            }
+            ConfigError::CaptureDepth => {
+                write!(f, "`capture.depth` must be greater than 0.")
+            }
+            ConfigError::InvalidBoot(reason) => {
+                write!(f, "Invalid boot: {reason}")
+            }
+            ConfigError::InvalidNaTemplate(reason) => {
+                write!(f, "Invalid v6_na_template: {reason}")
+            }
+            ConfigError::InvalidPool { pool, reason } => {
+                write!(f, "Invalid option82_pools entry `{pool}`: {reason}")
+            }
+            ConfigError::InvalidSubscriberNetwork { network, reason } => {
+                write!(f, "Invalid subscriber_networks entry `{network}`: {reason}")
+            }
+            ConfigError::InvalidReservationProfile { profile, reason } => {
+                write!(
+                    f,
+                    "Invalid reservation_profiles entry `{profile}`: {reason}"
+                )
+            }
+            ConfigError::InvalidDeviceClass { class, reason } => {
+                write!(f, "Invalid device_classes entry `{class}`: {reason}")
+            }
+            ConfigError::InvalidWalledGarden(reason) => {
+                write!(f, "Invalid walled_garden: {reason}")
+            }
+            ConfigError::InvalidFallback(reason) => {
+                write!(f, "Invalid fallback: {reason}")
+            }
+            ConfigError::Workers => {
+                write!(f, "`workers` must be greater than 0.")
+            }
+            ConfigError::InvalidReplySource(reason) => {
+                write!(f, "Invalid reply source: {reason}")
+            }
+            ConfigError::InvalidServerUnicast(reason) => {
+                write!(f, "Invalid `v6_server_unicast`: {reason}")
+            }
+            ConfigError::InvalidBindEnv { var, value } => {
+                write!(
+                    f,
+                    "Invalid {var}={value:?}: expected comma-separated address:port pairs"
+                )
+            }
+            ConfigError::InvalidBindAddress { option, reason } => {
+                write!(f, "Invalid `{option}`: {reason}")
+            }
+            ConfigError::InvalidServerDuid(len) => {
+                write!(
+                    f,
+                    "ids.json `v6` is {len} bytes; a DUID is a 2-byte type followed by at least one byte, at most {MAX_DUID_LEN} bytes in all."
+                )
+            }
+            ConfigError::Multiple { path, errors } => {
+                write!(
+                    f,
+                    "Found {} problems in `{}`:",
+                    errors.len(),
+                    path.to_string_lossy()
+                )?;
+                for error in errors {
+                    let error = error.to_string();
+                    let mut lines = error.lines();
+                    if let Some(first) = lines.next() {
+                        write!(f, "\n  - {first}")?;
+                    }
+                    for line in lines {
+                        write!(f, "\n    {line}")?;
+                    }
+                }
+                Ok(())
+            }
         }
     }
 }
 
 impl std::error::Error for ConfigError {}
 
+/// Read and parse one JSON config file, keeping the offending line of a
+/// parse error for the message
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let text = std::fs::read_to_string(path).context(path)?;
+    serde_json::from_str(&text).map_err(|err| ConfigError::Parsing {
+        line: err
+            .line()
+            .checked_sub(1)
+            .and_then(|n| text.lines().nth(n))
+            .map(str::to_string),
+        err,
+        path: path.to_path_buf(),
+    })
+}
+
+/// Problems with the reply source and bind interface options
+fn validate_reply_source(config: &ServerConfig) -> Vec<&'static str> {
+    let mut problems = Vec::new();
+    let any_set = config.v4_reply_source.is_some()
+        || config.v6_reply_source.is_some()
+        || config.v4_bind_interface.is_some()
+        || config.v6_bind_interface.is_some();
+    if any_set && !cfg!(target_os = "linux") {
+        problems.push(
+            "`v4_reply_source`, `v6_reply_source`, `v4_bind_interface` and `v6_bind_interface` need Linux",
+        );
+    }
+    if let Some(addr) = config.v4_reply_source {
+        if addr.is_unspecified() || addr.is_broadcast() || addr.is_multicast() {
+            problems.push("`v4_reply_source` must be a unicast address");
+        }
+    }
+    if let Some(addr) = config.v6_reply_source {
+        if addr.is_unspecified() || addr.is_multicast() {
+            problems.push("`v6_reply_source` must be a unicast address");
+        } else if addr.is_unicast_link_local() && config.v6_bind_interface.is_none() {
+            problems
+                .push("`v6_reply_source` is link-local, so `v6_bind_interface` must name its link");
+        }
+    }
+    problems
+}
+
+/// Problems with `v6_server_unicast`, which clients have to be able to reach
+/// and the DHCPv6 sockets have to receive on
+fn validate_server_unicast(
+    config: &ServerConfig,
+    v6_bind_addresses: &[SocketAddrV6],
+) -> Option<&'static str> {
+    let addr = config.v6_server_unicast?;
+    if addr.is_unspecified()
+        || addr.is_multicast()
+        || addr.is_loopback()
+        || addr.is_unicast_link_local()
+    {
+        return Some("must be a global or unique local unicast address");
+    }
+    if v6_bind_addresses
+        .iter()
+        .any(|bind| bind.ip().is_unspecified() || *bind.ip() == addr)
+    {
+        return None;
+    }
+    Some("`v6_bind_address` is bound to other addresses, so unicast messages wouldn't be received")
+}
+
+/// Addresses to bind for one protocol: those in `env`, the value the
+/// environment variable `var` was set to, otherwise those configured as
+/// `option`, otherwise `default`
+fn bind_addresses<T>(
+    var: &'static str,
+    env: Option<String>,
+    option: &'static str,
+    configured: Option<OneOrMany<T>>,
+    default: T,
+    errors: &mut Vec<ConfigError>,
+) -> Vec<T>
+where
+    T: FromStr + Copy + PartialEq + Into<SocketAddr>,
+{
+    let (source, addresses) = match env {
+        Some(value) => {
+            let parsed: Result<Vec<T>, _> = value
+                .split(',')
+                .map(str::trim)
+                .filter(|addr| !addr.is_empty())
+                .map(str::parse)
+                .collect();
+            match parsed {
+                Ok(addresses) if !addresses.is_empty() => (var, addresses),
+                _ => {
+                    errors.push(ConfigError::InvalidBindEnv { var, value });
+                    return vec![default];
+                }
+            }
+        }
+        None => (
+            option,
+            configured.map_or_else(|| vec![default], OneOrMany::into_vec),
+        ),
+    };
+    if let Err(reason) = validate_bind_addresses(&addresses) {
+        errors.push(ConfigError::InvalidBindAddress {
+            option: source,
+            reason,
+        });
+    }
+    addresses
+}
+
+/// Every address must be bindable alongside the others: listed once, and
+/// not sharing a port with the wildcard address, which would take it
+fn validate_bind_addresses<T: Copy + PartialEq + Into<SocketAddr>>(
+    addresses: &[T],
+) -> Result<(), String> {
+    if addresses.is_empty() {
+        return Err("lists no addresses".into());
+    }
+    for (i, addr) in addresses.iter().enumerate() {
+        let addr: SocketAddr = (*addr).into();
+        for other in &addresses[..i] {
+            let other: SocketAddr = (*other).into();
+            if other == addr {
+                return Err(format!("lists {addr} twice"));
+            }
+            if other.port() == addr.port()
+                && (other.ip().is_unspecified() || addr.ip().is_unspecified())
+            {
+                return Err(format!(
+                    "{other} and {addr} share a port, and the wildcard address already covers the other"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One worker per core where SO_REUSEPORT spreads the load across sockets
+fn default_workers() -> usize {
+    if cfg!(target_os = "linux") {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        1
+    }
+}
+
+/// Whether `domain` can be sent as an uncompressed DNS name (RFC 1035
+/// §2.3.4). A trailing dot is accepted.
+fn is_valid_domain(domain: &str) -> bool {
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    !domain.is_empty()
+        && domain.len() <= 253
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+}
+
+/// Smallest MTU option 26 may carry (RFC 2132 §5.1)
+const MIN_INTERFACE_MTU: u16 = 68;
+
+/// `interface_mtu` and `domain_name`, wherever they are set
+fn validate_v4_options(
+    interface_mtu: Option<u16>,
+    domain_name: Option<&str>,
+    errors: &mut Vec<ConfigError>,
+) {
+    if let Some(mtu) = interface_mtu.filter(|mtu| *mtu < MIN_INTERFACE_MTU) {
+        errors.push(ConfigError::InvalidInterfaceMtu(mtu));
+    }
+    if let Some(domain) = domain_name.filter(|domain| !is_valid_domain(domain)) {
+        errors.push(ConfigError::InvalidDomainName(domain.to_owned()));
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
+            enable_v4: true,
+            enable_v6: true,
             v4_server_id: Ipv4Addr::UNSPECIFIED,
             dns_v4: vec![],
             dns_v6: vec![],
-            subnets_v4: vec![],
+            domain_search_v6: vec![],
+            sntp_v6: vec![],
+            ntp_v6: vec![],
+            ntp_v4: vec![],
+            interface_mtu: None,
+            domain_name: None,
+            subnets_v4: V4Subnets::default(),
             v6_server_id: Duid::default(),
+            generated_server_ids: false,
             option82_extractors: vec![],
             option1837_extractors: vec![],
             mac_extractors: vec![MacExtractor::ClientLinklayerAddress],
@@ -344,8 +1903,43 @@ impl Default for Config {
             logging: LoggingConfig::default(),
             events: EventsConfig::default(),
             mgmt_address: None,
-            v4_bind_address: "0.0.0.0:67".parse().unwrap(),
-            v6_bind_address: "[::]:547".parse().unwrap(),
+            grpc_address: None,
+            v4_bind_addresses: vec!["0.0.0.0:67".parse().unwrap()],
+            v6_bind_addresses: vec!["[::]:547".parse().unwrap()],
+            v4_reply_source: None,
+            v6_reply_source: None,
+            v6_server_unicast: None,
+            v4_bind_interface: None,
+            v6_bind_interface: None,
+            reservation_source: None,
+            rate_limit: None,
+            ha: None,
+            conflict_detection: None,
+            alerts: None,
+            static_routes: vec![],
+            v6_advertise_unknown_clients: false,
+            v6_hint_status: false,
+            v6_always_send_options: false,
+            rapid_commit_enabled: true,
+            v6_preference: None,
+            refuse_lease_conflicts: false,
+            retransmit_window_ms: 0,
+            relay_cache_size: default_relay_cache_size(),
+            audit: None,
+            pd_routes: None,
+            capture: None,
+            lease_history: default_lease_history(),
+            boot: BootParams::default(),
+            omit_sname_vendor_classes: vec![],
+            bootp_enabled: false,
+            v6_na_template: None,
+            option82_pools: vec![],
+            workers: 1,
+            subscriber_networks: vec![],
+            reservation_profiles: BTreeMap::new(),
+            device_classes: BTreeMap::new(),
+            walled_garden: None,
+            fallback: None,
         }
     }
 }
@@ -353,61 +1947,377 @@ impl Default for Config {
 impl Config {
     /// Load server config from `config.json` and `ids.json` in the current directory
     pub fn load_from_files<P: AsRef<Path>>(config_dir: P) -> Result<Config, ConfigError> {
+        Self::load_with_overrides(config_dir, ProtocolOverrides::default())
+    }
+
+    /// [`Config::load_from_files`], with the protocols enabled or disabled
+    /// from the command line
+    pub fn load_with_overrides<P: AsRef<Path>>(
+        config_dir: P,
+        overrides: ProtocolOverrides,
+    ) -> Result<Config, ConfigError> {
         let server_config_path = config_dir.as_ref().join("config.json");
-        let server_config: ServerConfig = serde_json::from_reader(
-            std::fs::File::open(&server_config_path).context(&server_config_path)?,
-        )
-        .context(&server_config_path)?;
+        let mut server_config: ServerConfig = read_json(&server_config_path)?;
+        let enable_v4 = overrides.enable_v4.unwrap_or(server_config.enable_v4);
+        let enable_v6 = overrides.enable_v6.unwrap_or(server_config.enable_v6);
+        if !enable_v4 && !enable_v6 {
+            return Err(ConfigError::NoProtocolEnabled);
+        }
 
         let server_ids_path = config_dir.as_ref().join("ids.json");
-        let server_ids: ServerIds = serde_json::from_reader(
-            std::fs::File::open(&server_ids_path).context(&server_ids_path)?,
-        )
-        .context(&server_ids_path)?;
+        let generated_server_ids = !server_ids_path.try_exists().context(&server_ids_path)?;
+        let server_ids: ServerIds = if generated_server_ids {
+            generated_server_ids_for(&server_config)
+        } else {
+            read_json(&server_ids_path)?
+        };
+        let v4_server_id = match server_ids.v4 {
+            Some(id) => id,
+            None if enable_v4 => return Err(ConfigError::MissingServerId("v4")),
+            None => Ipv4Addr::UNSPECIFIED,
+        };
+        let v6_server_id = match server_ids.v6 {
+            Some(id) => id,
+            None if enable_v6 => return Err(ConfigError::MissingServerId("v6")),
+            None => Duid::default(),
+        };
+        let duid_len = v6_server_id.bytes.len();
+        if enable_v6 && !(3..=MAX_DUID_LEN).contains(&duid_len) {
+            return Err(ConfigError::InvalidServerDuid(duid_len));
+        }
+
+        // Everything below checks config.json. Problems are collected rather
+        // than returned one at a time so they can all be fixed in one go.
+        let mut errors = Vec::new();
 
         let option82_extractors_map = v4_extractors::get_all_extractors();
         let mut option82_extractors = Vec::with_capacity(server_config.option82_extractors.len());
-        for extractor_str in server_config.option82_extractors {
+        for extractor_str in &server_config.option82_extractors {
             match option82_extractors_map.get_key_value(extractor_str.as_str()) {
                 Some((&name, &extractor)) => option82_extractors.push((name, extractor)),
-                None => return Err(ConfigError::UnknownOption82Extractor(extractor_str)),
+                None => errors.push(ConfigError::UnknownOption82Extractor(extractor_str.clone())),
             }
         }
 
         let option1837_extractors_map = v6_extractors::get_all_extractors();
         let mut option1837_extractors =
             Vec::with_capacity(server_config.option1837_extractors.len());
-        for extractor_str in server_config.option1837_extractors {
+        for extractor_str in &server_config.option1837_extractors {
             match option1837_extractors_map.get_key_value(extractor_str.as_str()) {
                 Some((&name, &extractor)) => option1837_extractors.push((name, extractor)),
-                None => return Err(ConfigError::UnknownOption1837Extractor(extractor_str)),
+                None => errors.push(ConfigError::UnknownOption1837Extractor(
+                    extractor_str.clone(),
+                )),
+            }
+        }
+
+        // Validate subnet configurations
+        for subnet in &server_config.subnets_v4 {
+            if let Err(reason) = subnet.validate() {
+                errors.push(ConfigError::InvalidSubnet {
+                    subnet: subnet.net.to_string(),
+                    reason,
+                });
+            }
+        }
+        for (prefix, first) in subnets::duplicates(&server_config.subnets_v4) {
+            errors.push(ConfigError::DuplicateSubnet { prefix, first });
+        }
+
+        if enable_v4 && server_config.dns_v4.is_empty() {
+            errors.push(ConfigError::EmptyDnsV4);
+        }
+        if enable_v6 && server_config.dns_v6.is_empty() {
+            errors.push(ConfigError::EmptyDnsV6);
+        }
+        for domain in &server_config.domain_search_v6 {
+            if !is_valid_domain(domain) {
+                errors.push(ConfigError::InvalidDomainSearch(domain.clone()));
+            }
+        }
+        validate_v4_options(
+            server_config.interface_mtu,
+            server_config.domain_name.as_deref(),
+            &mut errors,
+        );
+
+        // The management interface has full write access to reservations and
+        // no authentication, so exposing it beyond loopback is refused
+        // outright.
+        if let Some(addr) = server_config.mgmt_address {
+            if !addr.ip().is_loopback() {
+                errors.push(ConfigError::MgmtNotLoopback(addr));
+            }
+        }
+        if let Some(addr) = server_config.grpc_address {
+            if !addr.ip().is_loopback() {
+                errors.push(ConfigError::GrpcNotLoopback(addr));
+            }
+        }
+
+        // A disabled protocol binds nothing, so no workers are started for it
+        let v4_bind_addresses = if enable_v4 {
+            bind_addresses(
+                V4_BIND_ENV,
+                overrides.v4_bind,
+                "v4_bind_address",
+                server_config.v4_bind_address.take(),
+                SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 67),
+                &mut errors,
+            )
+        } else {
+            Vec::new()
+        };
+        let v6_bind_addresses = if enable_v6 {
+            bind_addresses(
+                V6_BIND_ENV,
+                overrides.v6_bind,
+                "v6_bind_address",
+                server_config.v6_bind_address.take(),
+                SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 547, 0, 0),
+                &mut errors,
+            )
+        } else {
+            Vec::new()
+        };
+
+        errors.extend(
+            validate_reply_source(&server_config)
+                .into_iter()
+                .map(ConfigError::InvalidReplySource),
+        );
+        errors.extend(
+            validate_server_unicast(&server_config, &v6_bind_addresses)
+                .map(ConfigError::InvalidServerUnicast),
+        );
+
+        if let Some(source) = &server_config.reservation_source {
+            if source.interval_secs == 0 {
+                errors.push(ConfigError::ReservationSourceInterval);
+            }
+        }
+
+        if let Some(rate_limit) = &server_config.rate_limit {
+            if let Err(reason) = rate_limit.client.validate() {
+                errors.push(ConfigError::InvalidRateLimit(reason));
+            }
+            if let Some(Err(reason)) = rate_limit.relay.as_ref().map(|relay| relay.validate()) {
+                errors.push(ConfigError::InvalidRateLimit(reason));
+            }
+        }
+
+        if let Some(ha) = &server_config.ha {
+            if ha.heartbeat_secs == 0 {
+                errors.push(ConfigError::InvalidHa(
+                    "heartbeat_secs must be greater than 0",
+                ));
+            } else if ha.failover_secs <= ha.heartbeat_secs {
+                errors.push(ConfigError::InvalidHa(
+                    "failover_secs must be greater than heartbeat_secs",
+                ));
+            }
+        }
+
+        if let Some(cd) = &server_config.conflict_detection {
+            if cd.timeout_ms == 0 || cd.timeout_ms > MAX_PROBE_TIMEOUT_MS {
+                errors.push(ConfigError::ConflictProbeTimeout);
+            }
+        }
+
+        if let Some(alerts) = &server_config.alerts {
+            if alerts
+                .subnet_lease_percent
+                .is_some_and(|percent| !(1..=100).contains(&percent))
+            {
+                errors.push(ConfigError::InvalidAlerts(
+                    "subnet_lease_percent must be between 1 and 100",
+                ));
+            }
+            if alerts.no_reservation_per_minute == Some(0) {
+                errors.push(ConfigError::InvalidAlerts(
+                    "no_reservation_per_minute must be greater than 0",
+                ));
+            }
+        }
+
+        if server_config.retransmit_window_ms > MAX_RETRANSMIT_WINDOW_MS {
+            errors.push(ConfigError::RetransmitWindow);
+        }
+
+        for route in &server_config.static_routes {
+            if route.destination.trunc() != route.destination {
+                errors.push(ConfigError::InvalidStaticRoute(route.destination));
+            }
+        }
+
+        if let Some(audit) = &server_config.audit {
+            if audit.max_bytes == 0 {
+                errors.push(ConfigError::AuditMaxBytes);
+            }
+        }
+
+        if let Some(Err(reason)) = server_config.pd_routes.as_ref().map(|p| p.validate()) {
+            errors.push(ConfigError::InvalidPdRoutes(reason));
+        }
+
+        if let Some(spool) = &server_config.events.tcp_spool {
+            if spool.max_bytes == 0 {
+                errors.push(ConfigError::TcpSpoolMaxBytes);
+            }
+        }
+
+        if let Some(capture) = &server_config.capture {
+            if capture.depth == 0 {
+                errors.push(ConfigError::CaptureDepth);
+            }
+        }
+
+        if let Err(reason) = server_config.boot.validate() {
+            errors.push(ConfigError::InvalidBoot(reason));
+        }
+        if server_config
+            .omit_sname_vendor_classes
+            .iter()
+            .any(String::is_empty)
+        {
+            errors.push(ConfigError::InvalidBoot(
+                "omit_sname_vendor_classes entries must not be empty",
+            ));
+        }
+
+        for (i, pool) in server_config.option82_pools.iter().enumerate() {
+            if !option82_extractors_map.contains_key(pool.extractor.as_str()) {
+                errors.push(ConfigError::UnknownOption82Extractor(
+                    pool.extractor.clone(),
+                ));
+            }
+            if let Err(reason) = pool.validate() {
+                errors.push(ConfigError::InvalidPool {
+                    pool: pool.name.clone(),
+                    reason,
+                });
+            }
+            if server_config.option82_pools[..i]
+                .iter()
+                .any(|other| other.name == pool.name)
+            {
+                errors.push(ConfigError::InvalidPool {
+                    pool: pool.name.clone(),
+                    reason: "name is used by another pool",
+                });
+            }
+        }
+
+        for (i, network) in server_config.subscriber_networks.iter().enumerate() {
+            if let Err(reason) = network.validate() {
+                errors.push(ConfigError::InvalidSubscriberNetwork {
+                    network: network.name.clone(),
+                    reason,
+                });
+            }
+            if server_config.subscriber_networks[..i]
+                .iter()
+                .any(|other| other.name == network.name)
+            {
+                errors.push(ConfigError::InvalidSubscriberNetwork {
+                    network: network.name.clone(),
+                    reason: "name is used by another subscriber network",
+                });
+            }
+            for domain in network.domain_search_v6.iter().flatten() {
+                if !is_valid_domain(domain) {
+                    errors.push(ConfigError::InvalidDomainSearch(domain.clone()));
+                }
+            }
+            validate_v4_options(
+                network.interface_mtu,
+                network.domain_name.as_deref(),
+                &mut errors,
+            );
+            for route in network.static_routes.iter().flatten() {
+                if route.destination.trunc() != route.destination {
+                    errors.push(ConfigError::InvalidStaticRoute(route.destination));
+                }
+            }
+        }
+
+        for (name, profile) in &server_config.reservation_profiles {
+            if let Err(reason) = profile.validate() {
+                errors.push(ConfigError::InvalidReservationProfile {
+                    profile: name.clone(),
+                    reason,
+                });
+            }
+            for domain in profile.domain_search_v6.iter().flatten() {
+                if !is_valid_domain(domain) {
+                    errors.push(ConfigError::InvalidDomainSearch(domain.clone()));
+                }
+            }
+            validate_v4_options(
+                profile.interface_mtu,
+                profile.domain_name.as_deref(),
+                &mut errors,
+            );
+            for route in profile.static_routes.iter().flatten() {
+                if route.destination.trunc() != route.destination {
+                    errors.push(ConfigError::InvalidStaticRoute(route.destination));
+                }
+            }
+        }
+
+        let mut labels = std::collections::HashSet::new();
+        for (name, class) in &server_config.device_classes {
+            let mut invalid = |reason| {
+                errors.push(ConfigError::InvalidDeviceClass {
+                    class: name.clone(),
+                    reason,
+                })
+            };
+            if class.fingerprints.is_empty() {
+                invalid("fingerprints must list at least one label");
+            }
+            if !class
+                .fingerprints
+                .iter()
+                .all(|fp| fingerprint::is_label(fp))
+            {
+                invalid("fingerprints must be 16 lowercase hex digits, as events report them");
+            }
+            if !class
+                .fingerprints
+                .iter()
+                .all(|fp| labels.insert(fp.as_str()))
+            {
+                invalid("a fingerprint is already listed by another class");
+            }
+            if class
+                .profile
+                .as_ref()
+                .is_some_and(|profile| !server_config.reservation_profiles.contains_key(profile))
+            {
+                invalid("profile is not a reservation_profiles entry");
             }
         }
 
-        // Validate subnet configurations
-        for subnet in &server_config.subnets_v4 {
-            subnet
-                .validate()
-                .map_err(|reason| ConfigError::InvalidSubnet {
-                    subnet: subnet.net.to_string(),
-                    reason,
-                })?;
+        if let Some(Err(reason)) = server_config
+            .walled_garden
+            .as_ref()
+            .map(|garden| garden.validate(&server_config.subnets_v4))
+        {
+            errors.push(ConfigError::InvalidWalledGarden(reason));
         }
 
-        if server_config.dns_v4.is_empty() {
-            return Err(ConfigError::EmptyDnsV4);
+        if let Some(Err(reason)) = server_config.fallback.as_ref().map(|f| f.validate()) {
+            errors.push(ConfigError::InvalidFallback(reason));
         }
-        if server_config.dns_v6.is_empty() {
-            return Err(ConfigError::EmptyDnsV6);
+
+        if let Some(Err(reason)) = server_config.v6_na_template.as_ref().map(|t| t.validate()) {
+            errors.push(ConfigError::InvalidNaTemplate(reason));
         }
 
-        // The management interface has full write access to reservations and
-        // no authentication, so exposing it beyond loopback is refused
-        // outright.
-        if let Some(addr) = server_config.mgmt_address {
-            if !addr.ip().is_loopback() {
-                return Err(ConfigError::MgmtNotLoopback(addr));
-            }
+        let workers = server_config.workers.unwrap_or_else(default_workers);
+        if workers == 0 {
+            errors.push(ConfigError::Workers);
         }
 
         // Default to ClientLinklayerAddress if no extractors configured
@@ -416,11 +2326,25 @@ impl Config {
             .filter(|v| !v.is_empty())
             .unwrap_or_else(|| vec![MacExtractor::ClientLinklayerAddress]);
 
-        let logging = server_config
-            .logging
-            .map(LoggingConfig::try_from)
-            .transpose()?
-            .unwrap_or_default();
+        let logging = match server_config.logging.map(LoggingConfig::try_from) {
+            Some(Ok(logging)) => logging,
+            Some(Err(e)) => {
+                errors.push(e);
+                LoggingConfig::default()
+            }
+            None => LoggingConfig::default(),
+        };
+
+        match errors.len() {
+            0 => (),
+            1 => return Err(errors.remove(0)),
+            _ => {
+                return Err(ConfigError::Multiple {
+                    path: server_config_path,
+                    errors,
+                })
+            }
+        }
 
         let v4_lease = server_config.v4_lease_time.unwrap_or(DEFAULT_V4_LEASE);
         let v6_valid = server_config
@@ -429,11 +2353,20 @@ impl Config {
         let lease_times = LeaseTimes::from_base(v4_lease, v6_valid);
 
         Ok(Config {
+            enable_v4,
+            enable_v6,
             dns_v4: server_config.dns_v4,
             dns_v6: server_config.dns_v6,
-            v4_server_id: server_ids.v4,
-            subnets_v4: server_config.subnets_v4,
-            v6_server_id: server_ids.v6,
+            domain_search_v6: server_config.domain_search_v6,
+            sntp_v6: server_config.sntp_v6,
+            ntp_v6: server_config.ntp_v6,
+            ntp_v4: server_config.ntp_v4,
+            interface_mtu: server_config.interface_mtu,
+            domain_name: server_config.domain_name,
+            v4_server_id,
+            subnets_v4: V4Subnets::new(server_config.subnets_v4),
+            v6_server_id,
+            generated_server_ids,
             option82_extractors,
             option1837_extractors,
             mac_extractors,
@@ -441,14 +2374,134 @@ impl Config {
             logging,
             events: server_config.events,
             mgmt_address: server_config.mgmt_address,
-            v4_bind_address: server_config
-                .v4_bind_address
-                .unwrap_or_else(|| "0.0.0.0:67".parse().unwrap()),
-            v6_bind_address: server_config
-                .v6_bind_address
-                .unwrap_or_else(|| "[::]:547".parse().unwrap()),
+            grpc_address: server_config.grpc_address,
+            v4_bind_addresses,
+            v6_bind_addresses,
+            v4_reply_source: server_config.v4_reply_source,
+            v6_reply_source: server_config.v6_reply_source,
+            v6_server_unicast: server_config.v6_server_unicast,
+            v4_bind_interface: server_config.v4_bind_interface,
+            v6_bind_interface: server_config.v6_bind_interface,
+            reservation_source: server_config.reservation_source,
+            rate_limit: server_config.rate_limit,
+            ha: server_config.ha,
+            conflict_detection: server_config.conflict_detection,
+            alerts: server_config.alerts,
+            static_routes: server_config.static_routes,
+            v6_advertise_unknown_clients: server_config.v6_advertise_unknown_clients,
+            v6_hint_status: server_config.v6_hint_status,
+            v6_always_send_options: server_config.v6_always_send_options,
+            rapid_commit_enabled: server_config.rapid_commit_enabled,
+            v6_preference: server_config.v6_preference,
+            refuse_lease_conflicts: server_config.refuse_lease_conflicts,
+            retransmit_window_ms: server_config.retransmit_window_ms,
+            relay_cache_size: server_config.relay_cache_size,
+            audit: server_config.audit,
+            pd_routes: server_config.pd_routes,
+            capture: server_config.capture,
+            lease_history: server_config.lease_history,
+            boot: server_config.boot,
+            omit_sname_vendor_classes: server_config.omit_sname_vendor_classes,
+            bootp_enabled: server_config.bootp_enabled,
+            v6_na_template: server_config.v6_na_template,
+            option82_pools: server_config.option82_pools,
+            workers,
+            subscriber_networks: server_config.subscriber_networks,
+            reservation_profiles: server_config.reservation_profiles,
+            device_classes: server_config.device_classes,
+            walled_garden: server_config.walled_garden,
+            fallback: server_config.fallback,
         })
     }
+
+    /// Settings for a DHCPv4 message relayed from `giaddr`, or with that Link
+    /// Selection address: those of the first subscriber network with a
+    /// `relays_v4` prefix containing it
+    pub fn profile_v4(&self, giaddr: Ipv4Addr) -> Profile<'_> {
+        let network = self
+            .subscriber_networks
+            .iter()
+            .find(|network| network.relays_v4.iter().any(|net| net.contains(&giaddr)));
+        let authoritative = network.and_then(|network| network.authoritative);
+        let answer_unknown_clients = authoritative.unwrap_or_else(|| {
+            self.subnets_v4
+                .find(giaddr)
+                .is_some_and(|(subnet, _)| subnet.authoritative)
+        });
+        self.profile(network, answer_unknown_clients)
+    }
+
+    /// Settings for a DHCPv6 message relayed with `link_addr`: those of the
+    /// first subscriber network with a `relays_v6` prefix containing it
+    pub fn profile_v6(&self, link_addr: Ipv6Addr) -> Profile<'_> {
+        let network = self
+            .subscriber_networks
+            .iter()
+            .find(|network| network.relays_v6.iter().any(|net| net.contains(&link_addr)));
+        let answer_unknown_clients = network
+            .and_then(|network| network.v6_advertise_unknown_clients)
+            .unwrap_or(self.v6_advertise_unknown_clients);
+        self.profile(network, answer_unknown_clients)
+    }
+
+    fn profile<'a>(
+        &'a self,
+        network: Option<&'a SubscriberNetwork>,
+        answer_unknown_clients: bool,
+    ) -> Profile<'a> {
+        let top_level = Profile {
+            network: None,
+            dns_v4: &self.dns_v4,
+            dns_v6: &self.dns_v6,
+            domain_search_v6: &self.domain_search_v6,
+            sntp_v6: &self.sntp_v6,
+            ntp_v6: &self.ntp_v6,
+            ntp_v4: &self.ntp_v4,
+            interface_mtu: self.interface_mtu,
+            domain_name: self.domain_name.as_deref(),
+            static_routes: &self.static_routes,
+            lease_times: self.lease_times,
+            answer_unknown_clients,
+            boot: None,
+            device: None,
+        };
+        let Some(network) = network else {
+            return top_level;
+        };
+        let lease_times = self
+            .lease_times
+            .with_overrides(network.v4_lease_time, network.v6_lease_time);
+        Profile {
+            network: Some(network.name.as_str()),
+            dns_v4: network.dns_v4.as_deref().unwrap_or(top_level.dns_v4),
+            dns_v6: network.dns_v6.as_deref().unwrap_or(top_level.dns_v6),
+            domain_search_v6: network
+                .domain_search_v6
+                .as_deref()
+                .unwrap_or(top_level.domain_search_v6),
+            sntp_v6: network.sntp_v6.as_deref().unwrap_or(top_level.sntp_v6),
+            ntp_v6: network.ntp_v6.as_deref().unwrap_or(top_level.ntp_v6),
+            ntp_v4: network.ntp_v4.as_deref().unwrap_or(top_level.ntp_v4),
+            interface_mtu: network.interface_mtu.or(top_level.interface_mtu),
+            domain_name: network.domain_name.as_deref().or(top_level.domain_name),
+            static_routes: network
+                .static_routes
+                .as_deref()
+                .unwrap_or(top_level.static_routes),
+            lease_times,
+            answer_unknown_clients,
+            boot: None,
+            device: None,
+        }
+    }
+
+    /// The name and entry of the `device_classes` class listing `label`
+    pub fn device_class(&self, label: &str) -> Option<(&str, &DeviceClass)> {
+        self.device_classes
+            .iter()
+            .find(|(_, class)| class.fingerprints.iter().any(|fp| fp == label))
+            .map(|(name, class)| (name.as_str(), class))
+    }
 }
 
 #[cfg(test)]
@@ -497,6 +2550,45 @@ mod tests {
         assert!(matches!(res, Err(ConfigError::EmptyDnsV6)));
     }
 
+    #[test]
+    fn rapid_commit_enabled_by_default() {
+        let dir =
+            write_test_config(r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[]}"#);
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(config.unwrap().rapid_commit_enabled);
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"rapid_commit_enabled":false}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(!config.unwrap().rapid_commit_enabled);
+    }
+
+    #[test]
+    fn v6_preference_is_optional_and_one_byte() {
+        let dir =
+            write_test_config(r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[]}"#);
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(config.unwrap().v6_preference, None);
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v6_preference":128}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(config.unwrap().v6_preference, Some(128));
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v6_preference":256}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(config.is_err());
+    }
+
     #[test]
     fn non_loopback_mgmt_rejected() {
         for addr in ["0.0.0.0:8547", "192.0.2.10:8547", "[2001:db8::1]:8547"] {
@@ -512,6 +2604,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn non_loopback_grpc_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"grpc_address":"0.0.0.0:50051"}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::GrpcNotLoopback(_))));
+    }
+
     #[test]
     fn loopback_mgmt_accepted() {
         for addr in ["127.0.0.1:8547", "127.0.0.53:8547", "[::1]:8547"] {
@@ -524,6 +2626,596 @@ mod tests {
         }
     }
 
+    #[test]
+    fn events_tcp_token_not_in_debug_output() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"events":{"tcp":"10.0.0.5:9000","tcp_tls":{"server_name":"collector.example.com"},"tcp_token":"s3cret-token"}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let config = res.unwrap();
+        assert_eq!(config.events.tcp_token.as_deref(), Some("s3cret-token"));
+        let debug = format!("{:?}", config.events);
+        assert!(debug.contains("collector.example.com"));
+        assert!(!debug.contains("s3cret-token"));
+    }
+
+    #[test]
+    fn zero_reservation_source_interval_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"reservation_source":{"url":"http://127.0.0.1/r","interval_secs":0}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::ReservationSourceInterval)));
+    }
+
+    #[test]
+    fn zero_rate_limit_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"rate_limit":{"client":{"per_second":0,"burst":5}}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidRateLimit(_))));
+    }
+
+    #[test]
+    fn alert_thresholds_validated() {
+        for alerts in [
+            r#"{"subnet_lease_percent":0}"#,
+            r#"{"subnet_lease_percent":101}"#,
+            r#"{"no_reservation_per_minute":0}"#,
+        ] {
+            let dir = write_test_config(&format!(
+                r#"{{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"alerts":{alerts}}}"#,
+            ));
+            let res = Config::load_from_files(&dir);
+            std::fs::remove_dir_all(&dir).ok();
+            assert!(
+                matches!(res, Err(ConfigError::InvalidAlerts(_))),
+                "{alerts}"
+            );
+        }
+    }
+
+    #[test]
+    fn disabled_protocol_needs_no_server_id() {
+        let dir = write_test_config(r#"{"dns_v4":["8.8.8.8"],"enable_v6":false}"#);
+        std::fs::write(dir.join("ids.json"), r#"{"v4":"10.0.0.1"}"#).unwrap();
+        let config = Config::load_from_files(&dir).unwrap();
+        assert!(config.enable_v4 && !config.enable_v6);
+        assert!(config.v6_bind_addresses.is_empty());
+        assert_eq!(config.v4_bind_addresses.len(), 1);
+
+        // The command line takes precedence over config.json
+        let overrides = |enable_v4, enable_v6| ProtocolOverrides {
+            enable_v4,
+            enable_v6,
+            ..Default::default()
+        };
+        let res = Config::load_with_overrides(&dir, overrides(None, Some(true)));
+        assert!(matches!(res, Err(ConfigError::MissingServerId("v6"))));
+        let res = Config::load_with_overrides(&dir, overrides(Some(false), None));
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::NoProtocolEnabled)));
+    }
+
+    #[test]
+    fn missing_ids_json_is_generated() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"v4_bind_address":"192.0.2.1:67"}"#,
+        );
+        std::fs::remove_file(dir.join("ids.json")).unwrap();
+        let config = Config::load_from_files(&dir).unwrap();
+        assert!(config.generated_server_ids);
+        assert_eq!(config.v4_server_id, Ipv4Addr::new(192, 0, 2, 1));
+        assert!(matches!(
+            config.v6_server_id.duid_type(),
+            Some(crate::types::DuidType::Llt | crate::types::DuidType::Uuid)
+        ));
+
+        // Nothing names an address of the server to use for DHCPv4
+        std::fs::write(
+            dir.join("config.json"),
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"]}"#,
+        )
+        .unwrap();
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::MissingServerId("v4"))));
+    }
+
+    #[test]
+    fn retransmit_window_bounded() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"retransmit_window_ms":2000}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(config.unwrap().retransmit_window_ms, 2000);
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"retransmit_window_ms":60000}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::RetransmitWindow)));
+    }
+
+    #[test]
+    fn static_route_with_host_bits_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"static_routes":[{"destination":"10.1.2.3/16","gateway":"192.168.1.1"}]}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidStaticRoute(_))));
+    }
+
+    #[test]
+    fn audit_zero_max_bytes_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"audit":{"path":"/var/log/shadowdhcp/audit.jsonl","max_bytes":0}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::AuditMaxBytes)));
+    }
+
+    #[test]
+    fn tcp_spool_zero_max_bytes_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"events":{"tcp":"10.0.0.5:9000","tcp_spool":{"path":"/var/spool/shadowdhcp/events.jsonl","max_bytes":0}}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::TcpSpoolMaxBytes)));
+    }
+
+    #[test]
+    fn option82_pool_pd_len_shorter_than_pool_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"option82_pools":[{"name":"olt1","extractor":"remote_only","ipv4":"100.64.0.0/20","ipv6_na":"2001:db8:ff00::/48","ipv6_pd":"2001:db8:8000::/40","pd_len":32}]}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidPool { .. })));
+    }
+
+    #[test]
+    fn link_local_reply_source_needs_interface() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v6_reply_source":"fe80::1"}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidReplySource(_))));
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v6_reply_source":"fe80::1","v6_bind_interface":"eth0"}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        if cfg!(target_os = "linux") {
+            let config = res.unwrap();
+            assert_eq!(config.v6_bind_interface.as_deref(), Some("eth0"));
+        } else {
+            assert!(matches!(res, Err(ConfigError::InvalidReplySource(_))));
+        }
+    }
+
+    #[test]
+    fn server_unicast_must_be_reachable() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v6_server_unicast":"2001:db8::547"}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(
+            config.unwrap().v6_server_unicast,
+            Some("2001:db8::547".parse().unwrap())
+        );
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v6_server_unicast":"fe80::1"}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidServerUnicast(_))));
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v6_server_unicast":"2001:db8::547","v6_bind_address":"[2001:db8::1]:547"}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidServerUnicast(_))));
+    }
+
+    #[test]
+    fn bind_addresses_from_config_or_env() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v4_bind_address":["192.0.2.1:67","198.51.100.1:67"]}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let config = config.unwrap();
+        assert_eq!(
+            config.v4_bind_addresses,
+            [
+                "192.0.2.1:67".parse::<SocketAddrV4>().unwrap(),
+                "198.51.100.1:67".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            config.v6_bind_addresses,
+            Config::default().v6_bind_addresses
+        );
+
+        let default = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 67);
+        let configured = || Some(OneOrMany::One("192.0.2.1:67".parse().unwrap()));
+        let mut errors = Vec::new();
+        let env = Some("192.0.2.2:67, 192.0.2.3:6767".to_string());
+        let addresses = bind_addresses(
+            V4_BIND_ENV,
+            env,
+            "v4_bind_address",
+            configured(),
+            default,
+            &mut errors,
+        );
+        assert_eq!(
+            addresses,
+            [
+                "192.0.2.2:67".parse::<SocketAddrV4>().unwrap(),
+                "192.0.2.3:6767".parse().unwrap()
+            ]
+        );
+        assert!(errors.is_empty());
+
+        let env = Some("192.0.2.2".to_string());
+        bind_addresses(
+            V4_BIND_ENV,
+            env,
+            "v4_bind_address",
+            configured(),
+            default,
+            &mut errors,
+        );
+        assert!(matches!(
+            errors.pop(),
+            Some(ConfigError::InvalidBindEnv {
+                var: V4_BIND_ENV,
+                ..
+            })
+        ));
+
+        // the wildcard address already receives for 192.0.2.1:67
+        let configured = Some(OneOrMany::Many(vec![
+            default,
+            "192.0.2.1:67".parse().unwrap(),
+        ]));
+        bind_addresses(
+            V4_BIND_ENV,
+            None,
+            "v4_bind_address",
+            configured,
+            default,
+            &mut errors,
+        );
+        assert!(matches!(
+            errors.pop(),
+            Some(ConfigError::InvalidBindAddress {
+                option: "v4_bind_address",
+                ..
+            })
+        ));
+        assert!(validate_bind_addresses(&[default, default]).is_err());
+        assert!(
+            validate_bind_addresses(&[default, SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 68)])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn subscriber_network_profile() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"v4_lease_time":3600,
+                "subscriber_networks":[{"name":"business","relays_v4":["100.64.8.0/24"],"dns_v4":["192.0.2.53"],"v4_lease_time":600}]}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let config = res.unwrap();
+
+        let profile = config.profile_v4("100.64.8.1".parse().unwrap());
+        assert_eq!(profile.network, Some("business"));
+        assert_eq!(profile.dns_v4, ["192.0.2.53".parse::<Ipv4Addr>().unwrap()]);
+        assert_eq!(profile.lease_times.v4_lease, 600);
+        assert_eq!(profile.lease_times.v6_valid, 600 * DEFAULT_V6_MULTIPLE);
+        assert_eq!(profile.dns_v6, config.dns_v6);
+
+        let profile = config.profile_v4("100.64.9.1".parse().unwrap());
+        assert_eq!(profile.network, None);
+        assert_eq!(profile.lease_times.v4_lease, 3600);
+        assert_eq!(
+            config.profile_v6("2001:db8::1".parse().unwrap()).network,
+            None
+        );
+    }
+
+    #[test]
+    fn subscriber_network_without_relays_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],
+                "subscriber_networks":[{"name":"business","dns_v4":["192.0.2.53"]}]}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(
+            res,
+            Err(ConfigError::InvalidSubscriberNetwork { network, .. }) if network == "business"
+        ));
+    }
+
+    #[test]
+    fn zero_workers_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"workers":0}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::Workers)));
+    }
+
+    #[test]
+    fn all_problems_reported_together() {
+        let dir = write_test_config(
+            r#"{"dns_v4":[],"dns_v6":["2001:db8::1"],"subnets_v4":[{"net":"192.168.1.0/24","gateway":"192.168.2.1"}],"option82_extractors":["no_such_extractor"],"workers":0}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        match res {
+            Err(ConfigError::Multiple { errors, .. }) => {
+                assert_eq!(errors.len(), 4);
+                assert!(matches!(
+                    errors[0],
+                    ConfigError::UnknownOption82Extractor(_)
+                ));
+                assert!(matches!(errors[1], ConfigError::InvalidSubnet { .. }));
+                assert!(matches!(errors[2], ConfigError::EmptyDnsV4));
+                assert!(matches!(errors[3], ConfigError::Workers));
+            }
+            _ => panic!("expected every problem to be reported"),
+        }
+    }
+
+    #[test]
+    fn parse_error_shows_offending_line() {
+        let dir = write_test_config("{\n  \"dns_v4\": [\"8.8.8\"]\n}");
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let err = res.err().expect("invalid address rejected");
+        assert!(
+            matches!(err, ConfigError::Parsing { line: Some(ref l), .. } if l.contains("8.8.8"))
+        );
+        assert!(err.to_string().contains("at line 2"));
+    }
+
+    #[test]
+    fn subnet_reply_prefix_len_out_of_range_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[{"net":"192.168.1.0/24","gateway":"192.168.1.1","reply_prefix_len":33}]}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidSubnet { .. })));
+    }
+
+    #[test]
+    fn domain_search_empty_label_rejected() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"domain_search_v6":["example..net"]}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidDomainSearch(_))));
+    }
+
+    #[test]
+    fn v4_options_validated() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"ntp_v4":["192.0.2.123"],"interface_mtu":1500,"domain_name":"example.net"}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let config = config.unwrap();
+        assert_eq!(config.ntp_v4, [Ipv4Addr::new(192, 0, 2, 123)]);
+        assert_eq!(config.interface_mtu, Some(1500));
+        assert_eq!(config.domain_name.as_deref(), Some("example.net"));
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"interface_mtu":67,"reservation_profiles":{"fiber":{"domain_name":"example..net"}}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        match res {
+            Err(ConfigError::Multiple { errors, .. }) => {
+                assert!(matches!(errors[0], ConfigError::InvalidInterfaceMtu(67)));
+                assert!(matches!(errors[1], ConfigError::InvalidDomainName(_)));
+            }
+            _ => panic!("expected both errors"),
+        }
+    }
+
+    #[test]
+    fn boot_long_sname_rejected() {
+        let sname = "a".repeat(64);
+        let dir = write_test_config(&format!(
+            r#"{{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"boot":{{"sname":"{sname}"}}}}"#
+        ));
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidBoot(_))));
+    }
+
+    #[test]
+    fn pd_routes_needs_an_action() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"pd_routes":{}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(res, Err(ConfigError::InvalidPdRoutes(_))));
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"pd_routes":{"route_file":"/run/bird/pd.conf"}}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let pd_routes = config.unwrap().pd_routes.unwrap();
+        assert_eq!(
+            pd_routes.route_file.as_deref(),
+            Some(Path::new("/run/bird/pd.conf"))
+        );
+        assert!(pd_routes.exec.is_none());
+    }
+
+    #[test]
+    fn walled_garden_addresses() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[{"net":"100.64.0.0/16","gateway":"100.64.0.1"}],"walled_garden":{"net":"10.99.0.0/16","gateway":"10.99.0.1","dns_v4":["10.99.0.53"]}}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let config = config.unwrap();
+        let garden = config.walled_garden.as_ref().unwrap();
+        assert_eq!(garden.lease_time, 300);
+        assert_eq!(
+            garden.address_for(Ipv4Addr::new(100, 64, 7, 9)),
+            Some(Ipv4Addr::new(10, 99, 7, 9))
+        );
+        assert_eq!(garden.address_for(Ipv4Addr::new(100, 64, 0, 1)), None);
+        assert_eq!(garden.address_for(Ipv4Addr::new(100, 64, 255, 255)), None);
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[{"net":"100.64.0.0/16","gateway":"100.64.0.1"}],"walled_garden":{"net":"100.64.128.0/24","gateway":"100.64.128.1","dns_v4":["10.99.0.53"]}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(
+            res,
+            Err(ConfigError::InvalidWalledGarden(
+                "net must not overlap subnets_v4"
+            ))
+        ));
+    }
+
+    #[test]
+    fn device_classes_validated() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"reservation_profiles":{"legacy-ont":{"dns_v4":["198.51.100.53"]}},"device_classes":{"acme-ont-1.0":{"fingerprints":["0123456789abcdef"],"profile":"legacy-ont"}}}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let config = config.unwrap();
+        let (name, class) = config.device_class("0123456789abcdef").unwrap();
+        assert_eq!(name, "acme-ont-1.0");
+        assert_eq!(class.profile.as_deref(), Some("legacy-ont"));
+        assert!(config.device_class("fedcba9876543210").is_none());
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"device_classes":{"a":{"fingerprints":["0123456789ABCDEF"]},"b":{"fingerprints":["0123456789abcdef"],"profile":"missing"},"c":{"fingerprints":["0123456789abcdef"]}}}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        match res {
+            Err(ConfigError::Multiple { errors, .. }) => {
+                let reasons: Vec<_> = errors
+                    .iter()
+                    .map(|e| match e {
+                        ConfigError::InvalidDeviceClass { class, reason } => {
+                            (class.as_str(), *reason)
+                        }
+                        e => panic!("unexpected {e}"),
+                    })
+                    .collect();
+                assert_eq!(
+                    reasons,
+                    [
+                        (
+                            "a",
+                            "fingerprints must be 16 lowercase hex digits, as events report them"
+                        ),
+                        ("b", "profile is not a reservation_profiles entry"),
+                        ("c", "a fingerprint is already listed by another class"),
+                    ]
+                );
+            }
+            _ => panic!("expected three errors"),
+        }
+    }
+
+    #[test]
+    fn supernets_load_but_repeated_prefixes_do_not() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[{"net":"100.64.0.0/10","gateway":"100.64.0.1","authoritative":true},{"net":"100.64.8.0/24","gateway":"100.64.8.1","ranges":["198.51.100.0/24"]}]}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let config = config.unwrap();
+        assert!(
+            config
+                .profile_v4(Ipv4Addr::new(100, 64, 0, 254))
+                .answer_unknown_clients
+        );
+        // The /24 inside the authoritative supernet isn't, nor its range
+        assert!(
+            !config
+                .profile_v4(Ipv4Addr::new(100, 64, 8, 254))
+                .answer_unknown_clients
+        );
+        assert!(
+            !config
+                .profile_v4(Ipv4Addr::new(198, 51, 100, 254))
+                .answer_unknown_clients
+        );
+
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[{"net":"100.64.0.0/24","gateway":"100.64.0.1","ranges":["198.51.100.0/24"]},{"net":"198.51.100.0/24","gateway":"198.51.100.1"}]}"#,
+        );
+        let res = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let Err(ConfigError::DuplicateSubnet { prefix, first }) = res else {
+            panic!("expected a duplicate subnet error");
+        };
+        assert_eq!(prefix.to_string(), "198.51.100.0/24");
+        assert_eq!(first.to_string(), "100.64.0.0/24");
+    }
+
+    #[test]
+    fn fallback_needs_an_address() {
+        let dir = write_test_config(
+            r#"{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"fallback":{"v6":"[2001:db8::53]:547"}}"#,
+        );
+        let config = Config::load_from_files(&dir);
+        std::fs::remove_dir_all(&dir).ok();
+        let fallback = config.unwrap().fallback.unwrap();
+        assert_eq!(fallback.v6.map(|v6| v6.port()), Some(547));
+        assert!(fallback.v4.is_none());
+
+        for fallback in [r#"{}"#, r#"{"v4":"0.0.0.0:67"}"#] {
+            let dir = write_test_config(&format!(
+                r#"{{"dns_v4":["8.8.8.8"],"dns_v6":["2001:db8::1"],"subnets_v4":[],"fallback":{fallback}}}"#
+            ));
+            let res = Config::load_from_files(&dir);
+            std::fs::remove_dir_all(&dir).ok();
+            assert!(
+                matches!(res, Err(ConfigError::InvalidFallback(_))),
+                "{fallback}"
+            );
+        }
+    }
+
     #[test]
     fn invalid_log_level_errors() {
         let json = r#"{"level": "inf"}"#;
@@ -532,4 +3224,48 @@ mod tests {
 
         assert!(matches!(config, Err(ConfigError::LogLevel(_))));
     }
+
+    #[test]
+    fn module_log_levels() {
+        let json = r#"{"level": "warn", "modules": {"shadowdhcp::v6": "debug", "shadowdhcp::analytics": "off"}}"#;
+        let config: ServerLoggingConfig = serde_json::from_str(json).unwrap();
+        let config = LoggingConfig::try_from(config).unwrap();
+        assert_eq!(
+            config.modules,
+            vec![
+                ("shadowdhcp::analytics".to_string(), LevelFilter::OFF),
+                ("shadowdhcp::v6".to_string(), LevelFilter::DEBUG),
+            ]
+        );
+
+        let json = r#"{"modules": {"shadowdhcp::v6": "loud"}}"#;
+        let config: ServerLoggingConfig = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            LoggingConfig::try_from(config),
+            Err(ConfigError::ModuleLogLevel { .. })
+        ));
+    }
+
+    #[test]
+    fn syslog_target() {
+        let json = r#"{"syslog": {"address": "syslog.example.net:6514", "protocol": "tcp", "facility": "local3"}}"#;
+        let config: ServerLoggingConfig = serde_json::from_str(json).unwrap();
+        let syslog = LoggingConfig::try_from(config).unwrap().syslog.unwrap();
+        assert_eq!(syslog.protocol, SyslogProtocol::Tcp);
+        assert_eq!(syslog.facility.code(), 19);
+        assert_eq!(syslog.sd_id, "lease@32473");
+
+        for sd_id in ["lease", "lease@", "lease lease@32473", "lease@acme"] {
+            let json =
+                format!(r#"{{"syslog": {{"address": "127.0.0.1:514", "sd_id": "{sd_id}"}}}}"#);
+            let config: ServerLoggingConfig = serde_json::from_str(&json).unwrap();
+            assert!(
+                matches!(
+                    LoggingConfig::try_from(config),
+                    Err(ConfigError::InvalidSyslog(_))
+                ),
+                "{sd_id}"
+            );
+        }
+    }
 }
@@ -0,0 +1,433 @@
+//! Ping-before-offer conflict detection for DHCPv4.
+//!
+//! Before an OFFER goes out, the reserved address is sent an ICMP echo. A
+//! reply means some host already uses it, typically a customer with a stale
+//! static configuration, and handing it out would leave two hosts fighting
+//! over one address. The offer is withheld and, if configured, the address
+//! is quarantined so the squatter isn't pinged on every DISCOVER retry.
+//!
+//! Probes run on a thread of their own, so the worker never waits on one.
+//! A DISCOVER whose OFFER needs a probe is parked; once the probe finishes
+//! the worker handles the packet again and finds the result waiting. Only
+//! OFFERs are probed; a REQUEST for an address the client was already
+//! offered or holds is answered as before.
+//!
+//! An unprivileged ICMP datagram socket is preferred (Linux, with the gid in
+//! `net.ipv4.ping_group_range`), falling back to a raw socket, which needs
+//! CAP_NET_RAW. If neither can be opened the detector logs once and lets
+//! every offer through.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{debug, error, info};
+
+use crate::config::ConflictDetectionConfig;
+
+const ECHO_REQUEST: u8 = 8;
+const ECHO_REPLY: u8 = 0;
+const ECHO_PAYLOAD: &[u8] = b"shadowdh";
+/// Longest the prober waits on the ICMP socket before checking for new
+/// addresses to probe
+const PROBER_POLL: Duration = Duration::from_millis(10);
+/// Packets parked at once. Beyond this a DISCOVER needing a probe is
+/// dropped, and the client's retransmission tries again.
+const MAX_PARKED: usize = 1024;
+/// How long a finished probe waits for its parked packet to be handled again
+const RESULT_TTL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    Free,
+    /// The address answered the echo
+    InUse,
+    /// The address answered a recent probe and is not being offered
+    Quarantined,
+    /// A probe is in flight and the packet is parked until it finishes
+    Pending,
+}
+
+impl Probe {
+    /// Value used as the event `failure_reason`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Probe::Free => "Free",
+            Probe::InUse => "AddressConflict",
+            Probe::Quarantined => "AddressQuarantined",
+            Probe::Pending => "ProbePending",
+        }
+    }
+}
+
+struct IcmpSocket {
+    socket: UdpSocket,
+    /// Raw sockets see the IP header and every echo reply on the host;
+    /// datagram sockets get only their own replies, without the header.
+    raw: bool,
+}
+
+/// A worker's side of conflict detection: parks packets whose OFFER waits
+/// on a probe and hands them back once the prober thread has answered.
+pub struct ConflictDetector {
+    /// Addresses for the prober thread. None without an ICMP socket.
+    probes: Option<Sender<Ipv4Addr>>,
+    /// Finished probes, true if the address answered
+    results: Receiver<(Ipv4Addr, bool)>,
+    quarantine: Duration,
+    quarantined: HashMap<Ipv4Addr, Instant>,
+    /// Finished probes not yet picked up by the packet that asked for them
+    finished: HashMap<Ipv4Addr, (Probe, Instant)>,
+    /// The packet waiting on each address being probed, and its source
+    parked: HashMap<Ipv4Addr, (Vec<u8>, SocketAddr)>,
+    ready: VecDeque<(Vec<u8>, SocketAddr)>,
+}
+
+impl ConflictDetector {
+    pub fn new(cfg: &ConflictDetectionConfig) -> Self {
+        let (result_tx, results) = mpsc::channel();
+        let probes = match open_icmp_socket() {
+            Ok(icmp) => {
+                info!(
+                    raw = icmp.raw,
+                    timeout_ms = cfg.timeout_ms,
+                    "DHCPv4 conflict detection enabled"
+                );
+                let (probe_tx, probe_rx) = mpsc::channel();
+                let timeout = Duration::from_millis(cfg.timeout_ms);
+                let spawned = std::thread::Builder::new()
+                    .name("v4-conflict-probe".to_string())
+                    .spawn(move || prober(icmp, timeout, probe_rx, result_tx));
+                match spawned {
+                    Ok(_) => Some(probe_tx),
+                    Err(e) => {
+                        error!("Unable to start conflict prober, conflict detection disabled: {e}");
+                        None
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Unable to open ICMP socket, conflict detection disabled: {e}");
+                None
+            }
+        };
+        Self::with_prober(probes, results, Duration::from_secs(cfg.quarantine_secs))
+    }
+
+    fn with_prober(
+        probes: Option<Sender<Ipv4Addr>>,
+        results: Receiver<(Ipv4Addr, bool)>,
+        quarantine: Duration,
+    ) -> Self {
+        Self {
+            probes,
+            results,
+            quarantine,
+            quarantined: HashMap::new(),
+            finished: HashMap::new(),
+            parked: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Check `ip` before offering it. Without a finished probe for it, one
+    /// is started, `packet` from `src` is parked and `Pending` returned;
+    /// [`Self::next_ready`] hands the packet back once the probe is done.
+    /// A broken ICMP path never stops the server from answering: probe
+    /// errors are logged and count as free.
+    pub fn check(&mut self, ip: Ipv4Addr, packet: &[u8], src: SocketAddr) -> Probe {
+        let now = Instant::now();
+        if let Some((probe, _)) = self.finished.remove(&ip) {
+            if probe == Probe::InUse && !self.quarantine.is_zero() {
+                self.quarantined.insert(ip, now + self.quarantine);
+            }
+            return probe;
+        }
+        self.quarantined.retain(|_, until| now < *until);
+        if self.quarantined.contains_key(&ip) {
+            return Probe::Quarantined;
+        }
+        let Some(probes) = &self.probes else {
+            return Probe::Free;
+        };
+
+        // A retransmission while the probe is in flight replaces the
+        // parked packet without probing again
+        if let Some(parked) = self.parked.get_mut(&ip) {
+            *parked = (packet.to_vec(), src);
+            return Probe::Pending;
+        }
+        if self.parked.len() >= MAX_PARKED {
+            debug!(%ip, "too many packets waiting on conflict probes, dropping");
+            return Probe::Pending;
+        }
+        if probes.send(ip).is_err() {
+            error!("Conflict prober stopped, offering {ip} unprobed");
+            self.probes = None;
+            return Probe::Free;
+        }
+        self.parked.insert(ip, (packet.to_vec(), src));
+        Probe::Pending
+    }
+
+    /// True while packets are parked, so the worker should poll
+    /// [`Self::next_ready`] often rather than block on its socket
+    pub fn is_waiting(&self) -> bool {
+        !self.parked.is_empty() || !self.ready.is_empty()
+    }
+
+    /// A parked packet whose probe has finished, to be handled again
+    pub fn next_ready(&mut self) -> Option<(Vec<u8>, SocketAddr)> {
+        let now = Instant::now();
+        self.finished
+            .retain(|_, (_, at)| now.duration_since(*at) < RESULT_TTL);
+        loop {
+            match self.results.try_recv() {
+                Ok((ip, in_use)) => {
+                    let probe = if in_use { Probe::InUse } else { Probe::Free };
+                    self.finished.insert(ip, (probe, now));
+                    if let Some(parked) = self.parked.remove(&ip) {
+                        self.ready.push_back(parked);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                // The prober is gone; let everything parked through unprobed
+                Err(TryRecvError::Disconnected) => {
+                    if self.probes.take().is_some() {
+                        error!("Conflict prober stopped, offering unprobed");
+                    }
+                    for (ip, parked) in self.parked.drain() {
+                        self.finished.insert(ip, (Probe::Free, now));
+                        self.ready.push_back(parked);
+                    }
+                    break;
+                }
+            }
+        }
+        self.ready.pop_front()
+    }
+}
+
+/// Probe the addresses sent on `requests`, several at a time, until the
+/// detector is dropped. Each gets one echo request and `timeout` for the
+/// reply.
+fn prober(
+    icmp: IcmpSocket,
+    timeout: Duration,
+    requests: Receiver<Ipv4Addr>,
+    results: Sender<(Ipv4Addr, bool)>,
+) {
+    let ident = std::process::id() as u16;
+    let mut seq: u16 = 0;
+    let mut in_flight: HashMap<u16, (Ipv4Addr, Instant)> = HashMap::new();
+    let mut buf = [0u8; 1500];
+    loop {
+        // Block only when idle; otherwise pick up new addresses in between
+        // waiting for replies
+        let first = if in_flight.is_empty() {
+            match requests.recv() {
+                Ok(ip) => Some(ip),
+                Err(_) => return,
+            }
+        } else {
+            None
+        };
+        let mut pending: Vec<Ipv4Addr> = first.into_iter().collect();
+        loop {
+            match requests.try_recv() {
+                Ok(ip) => pending.push(ip),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        for ip in pending {
+            seq = seq.wrapping_add(1);
+            match icmp
+                .socket
+                .send_to(&echo_request(ident, seq), SocketAddrV4::new(ip, 0))
+            {
+                Ok(_) => {
+                    in_flight.insert(seq, (ip, Instant::now() + timeout));
+                }
+                Err(e) => {
+                    error!("Conflict probe of {ip} failed: {e}");
+                    if results.send((ip, false)).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let wait = in_flight
+            .values()
+            .map(|(_, deadline)| deadline.saturating_duration_since(now))
+            .min()
+            .unwrap_or(PROBER_POLL)
+            .clamp(Duration::from_millis(1), PROBER_POLL);
+        if let Err(e) = icmp.socket.set_read_timeout(Some(wait)) {
+            error!("Unable to set conflict probe timeout: {e}");
+        }
+        match icmp.socket.recv_from(&mut buf) {
+            Ok((amount, SocketAddr::V4(from))) => {
+                let answered = echo_reply_seq(&buf[..amount], icmp.raw, ident)
+                    .filter(|seq| in_flight.get(seq).is_some_and(|(ip, _)| ip == from.ip()));
+                if let Some(seq) = answered {
+                    let (ip, _) = in_flight.remove(&seq).expect("checked by the filter");
+                    if results.send((ip, true)).is_err() {
+                        return;
+                    }
+                }
+            }
+            // Replies to other pingers, or other traffic on a raw socket
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(e) => {
+                error!("Conflict probe receive failed: {e}");
+                std::thread::sleep(PROBER_POLL);
+            }
+        }
+
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        in_flight.retain(|_, (ip, deadline)| {
+            let keep = now < *deadline;
+            if !keep {
+                expired.push(*ip);
+            }
+            keep
+        });
+        for ip in expired {
+            if results.send((ip, false)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn open_icmp_socket() -> io::Result<IcmpSocket> {
+    let (socket, raw) = match Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4)) {
+        Ok(socket) => (socket, false),
+        Err(_) => (
+            Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?,
+            true,
+        ),
+    };
+    // Both socket types are message oriented, so std's UdpSocket
+    // send_to/recv_from work on them unchanged.
+    Ok(IcmpSocket {
+        socket: socket.into(),
+        raw,
+    })
+}
+
+fn echo_request(ident: u16, seq: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(8 + ECHO_PAYLOAD.len());
+    packet.extend_from_slice(&[ECHO_REQUEST, 0, 0, 0]);
+    packet.extend_from_slice(&ident.to_be_bytes());
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(ECHO_PAYLOAD);
+    let checksum = internet_checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
+/// Sequence number of an echo reply to `ident`. Datagram sockets have the
+/// identifier rewritten by the kernel, which also filters replies per
+/// socket, so only raw sockets check it.
+fn echo_reply_seq(packet: &[u8], raw: bool, ident: u16) -> Option<u16> {
+    let icmp = if raw {
+        let header_len = usize::from(packet.first()? & 0x0f) * 4;
+        packet.get(header_len..)?
+    } else {
+        packet
+    };
+    if icmp.len() < 8 || icmp[0] != ECHO_REPLY {
+        return None;
+    }
+    let reply_ident = u16::from_be_bytes([icmp[4], icmp[5]]);
+    (!raw || reply_ident == ident).then_some(u16::from_be_bytes([icmp[6], icmp[7]]))
+}
+
+/// RFC 1071 one's complement checksum
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u32::from(u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_request_checksum_verifies() {
+        let packet = echo_request(0x1234, 7);
+        assert_eq!(packet[0], ECHO_REQUEST);
+        // a packet including its own checksum sums to zero
+        assert_eq!(internet_checksum(&packet), 0);
+    }
+
+    #[test]
+    fn matches_only_own_echo_reply() {
+        let mut reply = echo_request(0x1234, 7);
+        reply[0] = ECHO_REPLY;
+        assert_eq!(echo_reply_seq(&reply, false, 0x1234), Some(7));
+        assert_eq!(
+            echo_reply_seq(&echo_request(0x1234, 7), false, 0x1234),
+            None
+        );
+
+        // raw sockets deliver the IP header, and replies to other pingers
+        let mut with_header = vec![0x45];
+        with_header.extend_from_slice(&[0; 19]);
+        with_header.extend_from_slice(&reply);
+        assert_eq!(echo_reply_seq(&with_header, true, 0x1234), Some(7));
+        assert_eq!(echo_reply_seq(&with_header, true, 0x4321), None);
+    }
+
+    #[test]
+    fn offers_wait_on_the_probe_thread() {
+        let (probe_tx, probe_rx) = mpsc::channel();
+        let (result_tx, result_rx) = mpsc::channel();
+        let mut detector =
+            ConflictDetector::with_prober(Some(probe_tx), result_rx, Duration::from_secs(60));
+        let src: SocketAddr = "192.0.2.1:67".parse().unwrap();
+        let (free, taken) = (Ipv4Addr::new(100, 64, 0, 1), Ipv4Addr::new(100, 64, 0, 2));
+
+        assert_eq!(detector.check(free, b"first", src), Probe::Pending);
+        // a retransmission replaces the parked packet without a second probe
+        assert_eq!(detector.check(free, b"retry", src), Probe::Pending);
+        assert_eq!(detector.check(taken, b"other", src), Probe::Pending);
+        assert_eq!(probe_rx.try_iter().collect::<Vec<_>>(), [free, taken]);
+        assert!(detector.is_waiting());
+        assert!(detector.next_ready().is_none());
+
+        result_tx.send((free, false)).unwrap();
+        result_tx.send((taken, true)).unwrap();
+        assert_eq!(detector.next_ready(), Some((b"retry".to_vec(), src)));
+        assert_eq!(detector.next_ready(), Some((b"other".to_vec(), src)));
+        assert!(!detector.is_waiting());
+
+        // handled again, each packet picks up its result
+        assert_eq!(detector.check(free, b"retry", src), Probe::Free);
+        assert_eq!(detector.check(taken, b"other", src), Probe::InUse);
+        assert_eq!(detector.check(taken, b"again", src), Probe::Quarantined);
+        // results are used once; the next offer is probed afresh
+        assert_eq!(detector.check(free, b"later", src), Probe::Pending);
+    }
+}
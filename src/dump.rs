@@ -0,0 +1,355 @@
+//! dhcpdump-style rendering of messages for the workers' trace logging.
+//!
+//! Each header field and option gets a line of its own, options by number
+//! and name with their decoded values, and options inside IA_NA, IA_PD and
+//! Relay-forward/reply messages indented under them. Options without a
+//! decoder here are shown as hex. The `Debug` output these replace put a
+//! whole message on one very long line, or several hundred short ones.
+
+use std::fmt::{self, Display, Formatter};
+
+use dhcproto::v4::relay::RelayAgentInformation;
+use dhcproto::v4::{self, Flags};
+use dhcproto::v6::{self, RelayMessageData};
+use dhcproto::Encodable;
+
+use crate::analytics::events::{DhcpEventV4, DhcpEventV6};
+use crate::v4::extensions::RelayAgentInformationExt;
+
+/// A DHCPv4 message, one field or option per line
+pub struct V4Dump<'a>(pub &'a v4::Message);
+
+/// A DHCPv6 client or server message, one option per line
+pub struct V6Dump<'a>(pub &'a v6::Message);
+
+/// A DHCPv6 Relay-forward or Relay-reply and the message it carries
+pub struct V6RelayDump<'a>(pub &'a v6::RelayMessage);
+
+impl Display for V4Dump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let msg = self.0;
+        let flags = if Flags::broadcast(&msg.flags()) {
+            "broadcast"
+        } else {
+            "unicast"
+        };
+        writeln!(f, "  op      {:?}", msg.opcode())?;
+        writeln!(f, "  xid     {:08x}", msg.xid())?;
+        writeln!(f, "  secs    {}", msg.secs())?;
+        writeln!(f, "  flags   {flags}")?;
+        writeln!(f, "  ciaddr  {}", msg.ciaddr())?;
+        writeln!(f, "  yiaddr  {}", msg.yiaddr())?;
+        writeln!(f, "  siaddr  {}", msg.siaddr())?;
+        writeln!(f, "  giaddr  {}", msg.giaddr())?;
+        write!(f, "  chaddr  ")?;
+        hex(f, msg.chaddr())?;
+        writeln!(f)?;
+        if let Some(sname) = msg.sname() {
+            write!(f, "  sname   ")?;
+            bytes(f, sname)?;
+            writeln!(f)?;
+        }
+        if let Some(file) = msg.fname() {
+            write!(f, "  file    ")?;
+            bytes(f, file)?;
+            writeln!(f)?;
+        }
+        for (code, opt) in msg.opts().iter() {
+            write!(
+                f,
+                "  option {:>3} {:<24} ",
+                u8::from(*code),
+                format!("{code:?}")
+            )?;
+            v4_value(f, opt)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+fn v4_value(f: &mut Formatter<'_>, opt: &v4::DhcpOption) -> fmt::Result {
+    match opt {
+        v4::DhcpOption::MessageType(msg_type) => {
+            f.write_str(DhcpEventV4::message_type_str(msg_type))
+        }
+        v4::DhcpOption::ServerIdentifier(addr)
+        | v4::DhcpOption::RequestedIpAddress(addr)
+        | v4::DhcpOption::SubnetMask(addr) => {
+            write!(f, "{addr}")
+        }
+        v4::DhcpOption::Router(addrs)
+        | v4::DhcpOption::DomainNameServer(addrs)
+        | v4::DhcpOption::NtpServers(addrs) => list(f, addrs),
+        v4::DhcpOption::AddressLeaseTime(secs)
+        | v4::DhcpOption::Renewal(secs)
+        | v4::DhcpOption::Rebinding(secs) => write!(f, "{secs}s"),
+        v4::DhcpOption::InterfaceMtu(n) | v4::DhcpOption::MaxMessageSize(n) => write!(f, "{n}"),
+        v4::DhcpOption::Hostname(name) | v4::DhcpOption::DomainName(name) => write!(f, "{name:?}"),
+        v4::DhcpOption::ClientIdentifier(id) | v4::DhcpOption::ClassIdentifier(id) => bytes(f, id),
+        v4::DhcpOption::ParameterRequestList(codes) => {
+            let codes: Vec<u8> = codes.iter().map(|code| u8::from(*code)).collect();
+            list(f, &codes)
+        }
+        v4::DhcpOption::RelayAgentInformation(info) => relay_info(f, info),
+        other => match other.to_vec() {
+            // Code and length byte, then the value
+            Ok(encoded) => hex(f, encoded.get(2..).unwrap_or_default()),
+            Err(_) => f.write_str("(unencodable)"),
+        },
+    }
+}
+
+fn relay_info(f: &mut Formatter<'_>, info: &RelayAgentInformation) -> fmt::Result {
+    let sub_options = [
+        (1, "circuit-id", info.circuit_id()),
+        (2, "remote-id", info.remote_id()),
+        (6, "subscriber-id", info.subscriber_id()),
+    ];
+    for (code, name, value) in sub_options {
+        if let Some(value) = value {
+            write!(f, "\n    sub-option {code:>2} {name:<20} ")?;
+            bytes(f, &value)?;
+        }
+    }
+    if let Some(addr) = info.link_selection() {
+        write!(f, "\n    sub-option  5 {:<20} {addr}", "link-selection")?;
+    }
+    if let Some(addr) = info.server_id_override() {
+        write!(f, "\n    sub-option 11 {:<20} {addr}", "server-id-override")?;
+    }
+    Ok(())
+}
+
+impl Display for V6Dump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        v6_message(f, self.0, 1)
+    }
+}
+
+impl Display for V6RelayDump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        v6_relay(f, self.0, 1)
+    }
+}
+
+fn v6_message(f: &mut Formatter<'_>, msg: &v6::Message, depth: usize) -> fmt::Result {
+    let xid = msg.xid();
+    writeln!(
+        f,
+        "{:indent$}{} xid {:02x}{:02x}{:02x}",
+        "",
+        DhcpEventV6::message_type_str(msg.msg_type()),
+        xid[0],
+        xid[1],
+        xid[2],
+        indent = depth * 2
+    )?;
+    v6_options(f, msg.opts(), depth)
+}
+
+fn v6_relay(f: &mut Formatter<'_>, msg: &v6::RelayMessage, depth: usize) -> fmt::Result {
+    writeln!(
+        f,
+        "{:indent$}{} hop-count {} link-addr {} peer-addr {}",
+        "",
+        DhcpEventV6::message_type_str(msg.msg_type),
+        msg.hop_count,
+        msg.link_addr,
+        msg.peer_addr,
+        indent = depth * 2
+    )?;
+    v6_options(f, msg.opts(), depth)
+}
+
+fn v6_options(f: &mut Formatter<'_>, opts: &v6::DhcpOptions, depth: usize) -> fmt::Result {
+    for opt in opts.iter() {
+        let code = v6::OptionCode::from(opt);
+        write!(
+            f,
+            "{:indent$}option {:>3} {:<24} ",
+            "",
+            u16::from(code),
+            format!("{code:?}"),
+            indent = depth * 2
+        )?;
+        match opt {
+            v6::DhcpOption::ClientId(id) | v6::DhcpOption::ServerId(id) => hex(f, id)?,
+            v6::DhcpOption::IANA(ia) => {
+                writeln!(f, "iaid {} t1 {}s t2 {}s", ia.id, ia.t1, ia.t2)?;
+                v6_options(f, &ia.opts, depth + 1)?;
+                continue;
+            }
+            v6::DhcpOption::IAPD(ia) => {
+                writeln!(f, "iaid {} t1 {}s t2 {}s", ia.id, ia.t1, ia.t2)?;
+                v6_options(f, &ia.opts, depth + 1)?;
+                continue;
+            }
+            v6::DhcpOption::IAAddr(addr) => {
+                writeln!(
+                    f,
+                    "{} preferred {}s valid {}s",
+                    addr.addr, addr.preferred_life, addr.valid_life
+                )?;
+                v6_options(f, &addr.opts, depth + 1)?;
+                continue;
+            }
+            v6::DhcpOption::IAPrefix(prefix) => {
+                writeln!(
+                    f,
+                    "{}/{} preferred {}s valid {}s",
+                    prefix.prefix_ip,
+                    prefix.prefix_len,
+                    prefix.preferred_lifetime,
+                    prefix.valid_lifetime
+                )?;
+                v6_options(f, &prefix.opts, depth + 1)?;
+                continue;
+            }
+            v6::DhcpOption::StatusCode(status) => {
+                write!(f, "{:?} {:?}", status.status, status.msg)?
+            }
+            v6::DhcpOption::Preference(pref) => write!(f, "{pref}")?,
+            v6::DhcpOption::RapidCommit => (),
+            v6::DhcpOption::Unicast(addr) => write!(f, "{addr}")?,
+            v6::DhcpOption::DomainNameServers(addrs) => list(f, addrs)?,
+            v6::DhcpOption::InterfaceId(id) => bytes(f, id)?,
+            v6::DhcpOption::RemoteId(remote) => {
+                write!(f, "enterprise {} ", remote.enterprise_number)?;
+                bytes(f, &remote.id)?;
+            }
+            v6::DhcpOption::ClientLinklayerAddress(lla) => {
+                write!(f, "type {} ", lla.address_type)?;
+                hex(f, &lla.address)?;
+            }
+            v6::DhcpOption::RelayMsg(RelayMessageData::Message(msg)) => {
+                writeln!(f)?;
+                v6_message(f, msg, depth + 1)?;
+                continue;
+            }
+            v6::DhcpOption::RelayMsg(RelayMessageData::Relay(relay)) => {
+                writeln!(f)?;
+                v6_relay(f, relay, depth + 1)?;
+                continue;
+            }
+            other => match other.to_vec() {
+                // Two-byte code and two-byte length, then the value
+                Ok(encoded) => hex(f, encoded.get(4..).unwrap_or_default())?,
+                Err(_) => f.write_str("(unencodable)")?,
+            },
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+fn list<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{item}")?;
+    }
+    Ok(())
+}
+
+/// Printable text quoted, anything else as hex
+fn bytes(f: &mut Formatter<'_>, value: &[u8]) -> fmt::Result {
+    match std::str::from_utf8(value) {
+        Ok(text) if !text.is_empty() && text.chars().all(|c| c.is_ascii_graphic() || c == ' ') => {
+            write!(f, "{text:?}")
+        }
+        _ => hex(f, value),
+    }
+}
+
+/// Colon-separated hex, as MAC addresses and DUIDs are usually written
+fn hex(f: &mut Formatter<'_>, value: &[u8]) -> fmt::Result {
+    for (i, b) in value.iter().enumerate() {
+        if i > 0 {
+            f.write_str(":")?;
+        }
+        write!(f, "{b:02x}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dhcproto::v4::relay::RelayInfo;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn options_shown_by_name_with_decoded_values() {
+        let mut msg = v4::Message::new_with_id(
+            0x1234abcd,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::new(192, 0, 2, 1),
+            &[0x00, 0x11, 0x22, 0x33, 0x44, 0x55],
+        );
+        let mut relay_info = RelayAgentInformation::default();
+        relay_info.insert(RelayInfo::AgentCircuitId(b"eth 1/1/1:100".to_vec()));
+        relay_info.insert(RelayInfo::AgentRemoteId(vec![0xde, 0xad]));
+        let opts = msg.opts_mut();
+        opts.insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        opts.insert(v4::DhcpOption::RelayAgentInformation(relay_info));
+        let dump = V4Dump(&msg).to_string();
+        assert!(dump.contains("  xid     1234abcd\n"), "{dump}");
+        assert!(dump.contains("  giaddr  192.0.2.1\n"), "{dump}");
+        assert!(dump.contains("  chaddr  00:11:22:33:44:55"), "{dump}");
+        assert!(dump.contains(" 53 MessageType"), "{dump}");
+        assert!(dump.contains("Discover\n"), "{dump}");
+        assert!(
+            dump.contains(r#"circuit-id           "eth 1/1/1:100""#),
+            "{dump}"
+        );
+        assert!(dump.contains("remote-id            de:ad"), "{dump}");
+
+        let mut solicit = v6::Message::new_with_id(v6::MessageType::Solicit, [1, 2, 3]);
+        solicit.opts_mut().insert(v6::DhcpOption::IANA(v6::IANA {
+            id: 7,
+            t1: 0,
+            t2: 0,
+            opts: {
+                let mut opts = v6::DhcpOptions::new();
+                opts.insert(v6::DhcpOption::IAAddr(v6::IAAddr {
+                    addr: "2001:db8::1".parse().unwrap(),
+                    preferred_life: 100,
+                    valid_life: 200,
+                    opts: v6::DhcpOptions::new(),
+                }));
+                opts
+            },
+        }));
+        let mut relay_opts = v6::DhcpOptions::new();
+        relay_opts.insert(v6::DhcpOption::InterfaceId(b"eth0".to_vec()));
+        relay_opts.insert(v6::DhcpOption::RelayMsg(RelayMessageData::Message(solicit)));
+        let relay = v6::RelayMessage {
+            msg_type: v6::MessageType::RelayForw,
+            hop_count: 0,
+            link_addr: "2001:db8::fe".parse().unwrap(),
+            peer_addr: Ipv6Addr::UNSPECIFIED,
+            opts: relay_opts,
+        };
+        let dump = V6RelayDump(&relay).to_string();
+        assert!(
+            dump.starts_with("  RelayForw hop-count 0 link-addr 2001:db8::fe peer-addr ::\n"),
+            "{dump}"
+        );
+        assert!(
+            dump.contains(r#"InterfaceId              "eth0""#),
+            "{dump}"
+        );
+        assert!(dump.contains("\n    Solicit xid 010203\n"), "{dump}");
+        assert!(dump.contains("iaid 7 t1 0s t2 0s\n"), "{dump}");
+        assert!(
+            dump.contains(
+                "      option   5 IAAddr                   2001:db8::1 preferred 100s valid 200s\n"
+            ),
+            "{dump}"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Passing messages without a reservation on to the `fallback` server, so
+//! shadowdhcp can sit in front of the server it is replacing while
+//! customers are moved over.
+//!
+//! DHCPv4 messages are sent on unchanged, as a relay with a second helper
+//! address would. The relay's giaddr stays in place, so the fallback picks
+//! the subnet from it and answers the relay directly.
+//!
+//! DHCPv6 messages are relayed once more (RFC 8415 §19.1.2): the relay's
+//! Relay-forward goes inside one from this server, which the fallback
+//! answers here. The worker takes the relay's Relay-reply back out and sends
+//! it to the relay on port 547.
+
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use dhcproto::v6::{self, DhcpOption, DhcpOptions, RelayMessage, RelayMessageData};
+use dhcproto::Decodable;
+use tracing::{debug, error, warn};
+
+use crate::config::Config;
+use crate::outcome::NoResponse;
+
+/// Relay agents listen for Relay-reply on the server port
+const RELAY_PORT: u16 = 547;
+
+/// Clients without a reservation, and those answering or renewing with
+/// another server, which during a migration is the fallback
+fn forwards(reason: NoResponse) -> bool {
+    reason.is_unknown_client() || reason == NoResponse::WrongServerId
+}
+
+/// Send a DHCPv4 message left unanswered for `reason` to the fallback, if
+/// one is configured and it is [one the fallback answers](forwards). The
+/// outcome is [`NoResponse::Forwarded`] once it is on its way.
+pub fn forward_v4(
+    socket: &UdpSocket,
+    config: &Config,
+    reason: NoResponse,
+    packet: &[u8],
+) -> NoResponse {
+    let Some(fallback) = config.fallback.as_ref().and_then(|f| f.v4) else {
+        return reason;
+    };
+    if !forwards(reason) {
+        return reason;
+    }
+    match socket.send_to(packet, fallback) {
+        Ok(_) => NoResponse::Forwarded,
+        Err(e) => {
+            error!("Failed to forward DHCPv4 message to fallback {fallback}: {e}");
+            reason
+        }
+    }
+}
+
+/// Relay a DHCPv6 Relay-forward left unanswered for `reason`, received from
+/// the relay at `src`, to the fallback, as [`forward_v4`] does for DHCPv4.
+/// `buf` is reused to encode it.
+pub fn forward_v6(
+    socket: &UdpSocket,
+    config: &Config,
+    reason: NoResponse,
+    msg: &RelayMessage,
+    src: SocketAddr,
+    buf: &mut Vec<u8>,
+) -> NoResponse {
+    let Some(fallback) = config.fallback.as_ref().and_then(|f| f.v6) else {
+        return reason;
+    };
+    let SocketAddr::V6(relay) = src else {
+        return reason;
+    };
+    if !forwards(reason) {
+        return reason;
+    }
+    let sent = crate::encode_into(&relay_forw(msg, *relay.ip()), buf)
+        .map_err(io::Error::other)
+        .and_then(|()| socket.send_to(buf, fallback));
+    match sent {
+        Ok(_) => NoResponse::Forwarded,
+        Err(e) => {
+            error!("Failed to forward DHCPv6 message to fallback {fallback}: {e}");
+            reason
+        }
+    }
+}
+
+/// A Relay-reply from the fallback, answering [`forward_v6`]
+pub fn is_reply_v6(config: &Config, src: SocketAddr, packet: &[u8]) -> bool {
+    config
+        .fallback
+        .as_ref()
+        .and_then(|f| f.v6)
+        .is_some_and(|fallback| src.ip() == IpAddr::V6(*fallback.ip()))
+        && packet.first() == Some(&u8::from(v6::MessageType::RelayRepl))
+}
+
+/// Pass the relay's Relay-reply in the fallback's answer `packet` on to it
+pub fn return_v6(socket: &UdpSocket, packet: &[u8], buf: &mut Vec<u8>) {
+    let answer = match RelayMessage::from_bytes(packet) {
+        Ok(answer) => answer,
+        Err(e) => {
+            warn!("Undecodable DHCPv6 answer from the fallback: {e}");
+            return;
+        }
+    };
+    let Some((reply, dest)) = unwrap_reply(&answer) else {
+        warn!("DHCPv6 answer from the fallback carries no Relay-reply");
+        return;
+    };
+    let sent = crate::encode_into(reply, buf)
+        .map_err(io::Error::other)
+        .and_then(|()| socket.send_to(buf, dest));
+    match sent {
+        Ok(sent) => debug!("returned fallback reply to {dest} with {sent} bytes"),
+        Err(e) => error!("Failed to return fallback reply to {dest}: {e}"),
+    }
+}
+
+/// `msg` from the relay at `relay`, inside a Relay-forward from this server
+pub fn relay_forw(msg: &RelayMessage, relay: Ipv6Addr) -> RelayMessage {
+    let mut opts = DhcpOptions::new();
+    opts.insert(DhcpOption::RelayMsg(RelayMessageData::Relay(msg.clone())));
+    RelayMessage {
+        msg_type: v6::MessageType::RelayForw,
+        hop_count: msg.hop_count().saturating_add(1),
+        // Unspecified: the message came from a relay, not a client link
+        link_addr: Ipv6Addr::UNSPECIFIED,
+        peer_addr: relay,
+        opts,
+    }
+}
+
+/// The relay's Relay-reply inside the fallback's answer to [`relay_forw`],
+/// and where to send it
+pub fn unwrap_reply(reply: &RelayMessage) -> Option<(&RelayMessage, SocketAddr)> {
+    if reply.msg_type() != v6::MessageType::RelayRepl {
+        return None;
+    }
+    let inner = reply.opts().iter().find_map(|opt| match opt {
+        DhcpOption::RelayMsg(RelayMessageData::Relay(inner)) => Some(inner),
+        _ => None,
+    })?;
+    Some((inner, SocketAddr::new(reply.peer_addr().into(), RELAY_PORT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dhcproto::Encodable;
+
+    #[test]
+    fn relay_chain_round_trip() {
+        let relay: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let client = v6::Message::new(v6::MessageType::Solicit);
+        let mut opts = DhcpOptions::new();
+        opts.insert(DhcpOption::RelayMsg(RelayMessageData::Message(client)));
+        let from_relay = RelayMessage {
+            msg_type: v6::MessageType::RelayForw,
+            hop_count: 0,
+            link_addr: "2001:db8:100::1".parse().unwrap(),
+            peer_addr: "fe80::1".parse().unwrap(),
+            opts,
+        };
+
+        let forwarded = relay_forw(&from_relay, relay);
+        let decoded = RelayMessage::from_bytes(&forwarded.to_vec().unwrap()).unwrap();
+        assert_eq!(decoded.hop_count(), 1);
+        assert_eq!(decoded.peer_addr(), relay);
+        assert_eq!(decoded.link_addr(), Ipv6Addr::UNSPECIFIED);
+
+        // The fallback answers with a Relay-reply for each hop
+        let to_relay = crate::v6::handlers::relay_reply(
+            &from_relay,
+            v6::Message::new(v6::MessageType::Advertise),
+        );
+        let mut opts = DhcpOptions::new();
+        opts.insert(DhcpOption::RelayMsg(RelayMessageData::Relay(
+            to_relay.clone(),
+        )));
+        let answer = RelayMessage {
+            msg_type: v6::MessageType::RelayRepl,
+            opts,
+            ..decoded
+        };
+        let answer = RelayMessage::from_bytes(&answer.to_vec().unwrap()).unwrap();
+        let (inner, dest) = unwrap_reply(&answer).unwrap();
+        assert_eq!(inner, &to_relay);
+        assert_eq!(dest, "[2001:db8::1]:547".parse().unwrap());
+
+        assert!(unwrap_reply(&from_relay).is_none());
+    }
+}
@@ -0,0 +1,168 @@
+//! Device classes: telling CPE models and firmware apart by what they ask
+//! for.
+//!
+//! Which options a client requests, in which order, and the vendor class it
+//! sends are fixed by its DHCP client, so they differ between models and
+//! firmware releases but not between two boxes running the same image. The
+//! DHCPv4 Parameter Request List and vendor class identifier (options 55
+//! and 60), or the DHCPv6 Option Request and Vendor Class options (6 and
+//! 16), are hashed into a short label, 16 hex digits, that events and the
+//! audit trail carry as `device_class`. Counting events by it shows which
+//! firmware is in the field; `device_classes` in the config gives the ones
+//! that need it a name and the settings they get.
+
+use compact_str::CompactString;
+use dhcproto::v4::{self, DhcpOption as V4Option};
+use dhcproto::v6::{self, DhcpOption as V6Option, OptionCode};
+use dhcproto::Encodable;
+
+use crate::capture::to_hex;
+
+/// Option Request (RFC 8415 §21.7)
+const OPTION_ORO: u16 = 6;
+/// Vendor Class (RFC 8415 §21.16)
+const OPTION_VENDOR_CLASS: u16 = 16;
+
+/// Hex digits in a label
+pub const LABEL_LEN: usize = 16;
+
+/// Label for a DHCPv4 client, `None` when it sends neither a Parameter
+/// Request List nor a vendor class
+pub fn v4(msg: &v4::Message) -> Option<CompactString> {
+    let mut prl = None;
+    let mut vendor = None;
+    for (_, opt) in msg.opts().iter() {
+        match opt {
+            V4Option::ParameterRequestList(codes) => {
+                prl = Some(codes.iter().map(|code| u8::from(*code)).collect::<Vec<_>>())
+            }
+            V4Option::ClassIdentifier(class) => vendor = Some(class.as_slice()),
+            _ => (),
+        }
+    }
+    if prl.is_none() && vendor.is_none() {
+        return None;
+    }
+    Some(label(
+        4,
+        &prl.unwrap_or_default(),
+        vendor.unwrap_or_default(),
+    ))
+}
+
+/// Label for a DHCPv6 client, `None` when it sends neither an Option
+/// Request nor a Vendor Class option
+pub fn v6(msg: &v6::Message) -> Option<CompactString> {
+    let mut oro = None;
+    let mut vendor = None;
+    for opt in msg.opts().iter() {
+        let slot = match u16::from(OptionCode::from(opt)) {
+            OPTION_ORO => &mut oro,
+            OPTION_VENDOR_CLASS => &mut vendor,
+            _ => continue,
+        };
+        // Two-byte code and two-byte length, then the value
+        *slot = opt
+            .to_vec()
+            .ok()
+            .map(|encoded| encoded.get(4..).unwrap_or_default().to_vec());
+    }
+    if oro.is_none() && vendor.is_none() {
+        return None;
+    }
+    Some(label(
+        6,
+        &oro.unwrap_or_default(),
+        &vendor.unwrap_or_default(),
+    ))
+}
+
+/// The first 8 bytes of a SHA-1 over the IP version and the two fields,
+/// each after its length so no two inputs run together
+fn label(version: u8, requested: &[u8], vendor: &[u8]) -> CompactString {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(&[version]);
+    for field in [requested, vendor] {
+        hasher.update(&(field.len() as u32).to_be_bytes());
+        hasher.update(field);
+    }
+    let digest = hasher.digest().bytes();
+    CompactString::from(to_hex(&digest[..LABEL_LEN / 2]))
+}
+
+/// Whether `s` has the form of a label, for checking `device_classes`
+pub fn is_label(s: &str) -> bool {
+    s.len() == LABEL_LEN && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn discover(opts: Vec<V4Option>) -> v4::Message {
+        let mut msg = v4::Message::new(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            &[0, 1, 2, 3, 4, 5],
+        );
+        for opt in opts {
+            msg.opts_mut().insert(opt);
+        }
+        msg
+    }
+
+    #[test]
+    fn v4_label_follows_request_order_and_vendor() {
+        let prl = |codes: &[u8]| {
+            V4Option::ParameterRequestList(codes.iter().map(|c| (*c).into()).collect())
+        };
+        let vendor = || V4Option::ClassIdentifier(b"udhcp 1.36.1".to_vec());
+
+        let label = v4(&discover(vec![prl(&[1, 3, 6, 15]), vendor()])).unwrap();
+        assert!(is_label(&label), "{label}");
+        // Another box with the same firmware
+        assert_eq!(
+            v4(&discover(vec![vendor(), prl(&[1, 3, 6, 15])])),
+            Some(label.clone())
+        );
+        assert_ne!(
+            v4(&discover(vec![prl(&[1, 3, 15, 6]), vendor()])),
+            Some(label.clone())
+        );
+        assert_ne!(v4(&discover(vec![prl(&[1, 3, 6, 15])])), Some(label));
+        assert_eq!(v4(&discover(vec![])), None);
+    }
+
+    #[test]
+    fn v6_label_from_option_request_and_vendor_class() {
+        let option = |code: u16, data: &[u8]| {
+            V6Option::Unknown(v6::UnknownOption::new(
+                OptionCode::from(code),
+                data.to_vec(),
+            ))
+        };
+        let solicit = |opts: Vec<V6Option>| {
+            let mut msg = v6::Message::new(v6::MessageType::Solicit);
+            for opt in opts {
+                msg.opts_mut().insert(opt);
+            }
+            msg
+        };
+        let oro = || option(OPTION_ORO, &[0, 23, 0, 24]);
+        let vendor = || {
+            option(
+                OPTION_VENDOR_CLASS,
+                &[0, 0, 0x0d, 0xe9, 0, 3, b'o', b'n', b't'],
+            )
+        };
+
+        let label = v6(&solicit(vec![oro(), vendor()])).unwrap();
+        assert!(is_label(&label), "{label}");
+        assert_eq!(v6(&solicit(vec![oro(), vendor()])), Some(label.clone()));
+        assert_ne!(v6(&solicit(vec![oro()])), Some(label));
+        assert_eq!(v6(&solicit(vec![])), None);
+    }
+}
@@ -0,0 +1,268 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`.
+//!
+//! [`Harness`] takes a datagram through everything a worker does with it
+//! between `recv_from` and `send_to`: decoding, the relay inventory, the
+//! trace dumps, the handlers, the analytics event and encoding the reply.
+//! It calls the same steps in `v4::worker` and `v6::worker` that the
+//! workers do, so the two can't drift apart. Nothing is sent. The only thing checked is that no input panics, since
+//! a panic in a worker drops every packet queued behind it.
+//!
+//! The reservations are fixed so that generated messages can name a client
+//! the server knows: by MAC/DUID, by Option 82 remote-id and by Option 18
+//! Interface-ID. A fresh lease cache and relay inventory are used for each
+//! input, so a crash reproduces from its input alone.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use advmac::MacAddr6;
+use dhcproto::v6::RelayMessage;
+use dhcproto::Decodable;
+
+use crate::analytics::events::{DhcpEventV4, DhcpEventV6};
+use crate::config::Config;
+use crate::dump::{V4Dump, V6Dump, V6RelayDump};
+use crate::opt82_cache::Opt82Cache;
+use crate::relays::RelayInventory;
+use crate::reservationdb::ReservationDb;
+use crate::types::{Duid, Reservation, V4Subnet};
+use crate::v4::handlers::DhcpV4Response;
+use crate::v4::worker as v4_worker;
+use crate::v6::handlers::DhcpV6Response;
+use crate::v6::mac_extractors::MacExtractor;
+use crate::v6::worker as v6_worker;
+
+/// giaddr of the relay the DHCPv4 clients are behind
+pub const RELAY_V4: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 1);
+/// Source of the DHCPv6 Relay-forwards
+pub const RELAY_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+pub const V4_SERVER_ID: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+pub const V6_SERVER_ID: [u8; 4] = [0, 1, 2, 3];
+
+/// Reserved by MAC, and by DUID-LL of the same MAC for DHCPv6
+pub const RESERVED_MAC: MacAddr6 = MacAddr6::new([0x02, 0x00, 0x00, 0x00, 0x00, 0x01]);
+pub const RESERVED_DUID: [u8; 10] = [0, 3, 0, 1, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+/// Option 82 Agent Remote-ID of the second reservation
+pub const RESERVED_REMOTE_ID: &[u8] = b"olt1:fuzz";
+/// Option 18 Interface-ID of the third reservation
+pub const RESERVED_INTERFACE_ID: &[u8] = b"eth0:fuzz";
+
+const RESERVATIONS: &str = r#"[
+    {"ipv4": "100.64.0.10", "ipv6_na": "2001:db8:1::1", "ipv6_pd": "2001:db8:100::/56",
+     "mac": "02:00:00:00:00:01", "duid": "00:03:00:01:02:00:00:00:00:01"},
+    {"ipv4": "100.64.0.11", "ipv6_pd": "2001:db8:200::/56",
+     "option82": {"remote": "olt1:fuzz"}},
+    {"ipv6_na": "2001:db8:1::3", "ipv6_pd": "2001:db8:300::/56",
+     "option1837": {"interface": "eth0:fuzz"}}
+]"#;
+
+pub struct Harness {
+    config: Config,
+    reservations: ReservationDb,
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        let config = Config {
+            v4_server_id: V4_SERVER_ID,
+            subnets_v4: vec![V4Subnet {
+                net: "100.64.0.0/24".parse().expect("valid prefix"),
+                gateway: RELAY_V4,
+                reply_prefix_len: None,
+                authoritative: true,
+                ranges: vec![],
+            }]
+            .into(),
+            v6_server_id: Duid::from(V6_SERVER_ID.to_vec()),
+            dns_v6: vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53)],
+            option82_extractors: crate::v4::extractors::get_all_extractors()
+                .into_iter()
+                .collect(),
+            option1837_extractors: crate::v6::extractors::get_all_extractors()
+                .into_iter()
+                .collect(),
+            mac_extractors: vec![
+                MacExtractor::ClientLinklayerAddress,
+                MacExtractor::PeerAddrEui64,
+                MacExtractor::Duid,
+            ],
+            ..Default::default()
+        };
+        let reservations = ReservationDb::new();
+        let list: Vec<Reservation> =
+            serde_json::from_str(RESERVATIONS).expect("valid reservations");
+        reservations
+            .load_reservations(list)
+            .expect("no duplicate reservations");
+        Self {
+            config,
+            reservations,
+        }
+    }
+
+    /// A datagram received on the DHCPv4 socket from [`RELAY_V4`]
+    pub fn v4(&self, packet: &[u8]) {
+        // Packet capture reads the MAC before the message is decoded
+        let _ = crate::capture::chaddr(packet);
+        let src = SocketAddr::new(IpAddr::V4(RELAY_V4), 67);
+        let Ok(msg) = v4_worker::decode(packet, src, Some(&RelayInventory::default())) else {
+            return;
+        };
+        // No subscriber is installed, so format what the worker traces
+        let _ = V4Dump(&msg).to_string();
+
+        let leases = Opt82Cache::new();
+        let event = match crate::v4::handlers::handle_message(
+            &self.reservations,
+            &leases,
+            &self.config,
+            &msg,
+        ) {
+            DhcpV4Response::Message(mut resp) => {
+                let conflict_holder = v4_worker::lease_conflict(&leases, &self.config, &msg, &resp);
+                let mut buf = Vec::new();
+                if v4_worker::encode_reply(&msg, &mut resp.message, &mut buf).is_ok() {
+                    let _ = V4Dump(&resp.message).to_string();
+                }
+                v4_worker::reply_event(&msg, RELAY_V4, &resp, conflict_holder)
+            }
+            DhcpV4Response::NoResponse(reason) => DhcpEventV4::failed(&msg, RELAY_V4, reason),
+        };
+        let _ = serde_json::to_string(&event);
+    }
+
+    /// A datagram received on the DHCPv6 socket from [`RELAY_V6`]. Anything
+    /// but a Relay-forward is taken as a client unicasting to the server.
+    pub fn v6(&self, packet: &[u8]) {
+        let src = SocketAddr::new(IpAddr::V6(RELAY_V6), 547);
+        let (direct, parsed) = v6_worker::decode(packet, src, true, &RelayInventory::default());
+        if let Ok(relay_msg) = parsed {
+            self.v6_relay(&relay_msg, direct, src);
+        }
+        // Answers from a fallback server are decoded on the same socket
+        if let Ok(answer) = RelayMessage::from_bytes(packet) {
+            let _ = crate::fallback::unwrap_reply(&answer);
+        }
+    }
+
+    fn v6_relay(&self, relay_msg: &RelayMessage, direct: bool, src: SocketAddr) {
+        let _ = V6RelayDump(relay_msg).to_string();
+        let msg = match v6_worker::relayed_message(relay_msg) {
+            Ok(msg) => msg,
+            Err(reason) => {
+                let event = DhcpEventV6::relay_failed(relay_msg, RELAY_V6, reason);
+                let _ = serde_json::to_string(&event);
+                return;
+            }
+        };
+        for extractor in &self.config.mac_extractors {
+            let _ = extractor.extract(relay_msg, msg);
+        }
+
+        let mut buf = Vec::new();
+        let leases = Opt82Cache::new();
+        let event = match crate::v6::handlers::handle_message(
+            &self.config,
+            &self.reservations,
+            &leases,
+            msg,
+            relay_msg,
+        ) {
+            DhcpV6Response::Message(resp) => {
+                let conflict_holder = v6_worker::lease_conflict(&leases, &self.config, msg, &resp);
+                let reply_type = resp.message.msg_type();
+                let _ = V6Dump(&resp.message).to_string();
+                let _ = v6_worker::encode_reply(relay_msg, resp.message, direct, src, &mut buf);
+                v6_worker::reply_event(
+                    msg,
+                    relay_msg,
+                    RELAY_V6,
+                    reply_type,
+                    resp.reservation.as_deref(),
+                    resp.reservation_match.as_ref(),
+                    conflict_holder.as_ref(),
+                )
+            }
+            DhcpV6Response::NoResponse(reason) => {
+                // What a fallback server would be sent
+                if !direct {
+                    let forwarded = crate::fallback::relay_forw(relay_msg, RELAY_V6);
+                    let _ = crate::encode_into(&forwarded, &mut buf);
+                }
+                DhcpEventV6::failed(msg, relay_msg, RELAY_V6, reason)
+            }
+        };
+        let _ = serde_json::to_string(&event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dhcproto::v4::relay::{RelayAgentInformation, RelayInfo};
+    use dhcproto::v6::{DhcpOption, RelayMessageData};
+    use dhcproto::Encodable;
+    use dhcproto::{v4, v6};
+
+    /// Every truncation of `packet`, and `packet` with each byte inverted
+    fn mangled(packet: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let truncated = (0..packet.len()).map(|len| packet[..len].to_vec());
+        let flipped = (0..packet.len()).map(|i| {
+            let mut p = packet.to_vec();
+            p[i] ^= 0xff;
+            p
+        });
+        truncated.chain(flipped)
+    }
+
+    #[test]
+    fn mangled_packets_do_not_panic() {
+        let harness = Harness::new();
+
+        let mut discover = v4::Message::new_with_id(
+            1,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            RELAY_V4,
+            &RESERVED_MAC.to_array(),
+        );
+        discover.set_opcode(v4::Opcode::BootRequest);
+        let opts = discover.opts_mut();
+        opts.insert(v4::DhcpOption::MessageType(v4::MessageType::Discover));
+        let mut relay_info = RelayAgentInformation::default();
+        relay_info.insert(RelayInfo::AgentRemoteId(RESERVED_REMOTE_ID.to_vec()));
+        opts.insert(v4::DhcpOption::RelayAgentInformation(relay_info));
+        for packet in mangled(&discover.to_vec().unwrap()) {
+            harness.v4(&packet);
+        }
+
+        let mut solicit = v6::Message::new_with_id(v6::MessageType::Solicit, [1, 2, 3]);
+        let opts = solicit.opts_mut();
+        opts.insert(DhcpOption::ClientId(RESERVED_DUID.to_vec()));
+        opts.insert(DhcpOption::IANA(v6::IANA {
+            id: 1,
+            t1: 0,
+            t2: 0,
+            opts: v6::DhcpOptions::new(),
+        }));
+        let mut relay_opts = v6::DhcpOptions::new();
+        relay_opts.insert(DhcpOption::InterfaceId(RESERVED_INTERFACE_ID.to_vec()));
+        relay_opts.insert(DhcpOption::RelayMsg(RelayMessageData::Message(solicit)));
+        let relay_forw = v6::RelayMessage {
+            msg_type: v6::MessageType::RelayForw,
+            hop_count: 0,
+            link_addr: Ipv6Addr::UNSPECIFIED,
+            peer_addr: "fe80::1".parse().unwrap(),
+            opts: relay_opts,
+        };
+        for packet in mangled(&relay_forw.to_vec().unwrap()) {
+            harness.v6(&packet);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! gRPC management service on `grpc_address`, for OSS integrations that
+//! would rather generate a client from proto/shadowdhcp.proto than speak the
+//! newline-delimited JSON interface in [`crate::mgmt`].
+//!
+//! It covers provisioning and lease lookups: the same add and remove as the
+//! `add` and `remove` commands, the `leases` snapshot, and a stream of the
+//! events sent to events.tcp. Like the management listener it has no
+//! authentication, so the config loader only allows a loopback address.
+//!
+//! The rest of the server is plain threads; the service gets a
+//! single-threaded tokio runtime of its own on a detached thread.
+
+use std::net::{IpAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::analytics::watch::EventWatchers;
+use crate::mgmt::{atomic_write_reservations, lock_reservations, matching};
+use crate::opt82_cache::{LeaseSnapshot, LeaseV4, LeaseV6, Opt82Cache};
+use crate::reservationdb::ReservationDb;
+use crate::types::{parse_mac, Duid, Option1837, Option82, Reservation};
+
+pub mod proto {
+    tonic::include_proto!("shadowdhcp.v1");
+}
+
+use proto::management_server::{Management, ManagementServer};
+
+/// Events buffered between a subscriber and its gRPC stream
+const STREAM_BUFFER: usize = 64;
+/// How long a stream with no events goes before checking its client is
+/// still there
+const IDLE_CHECK: Duration = Duration::from_secs(1);
+
+pub struct ManagementService {
+    pub reservations: Arc<ArcSwap<ReservationDb>>,
+    pub leases: Arc<Opt82Cache>,
+    pub config_dir: PathBuf,
+    pub watchers: EventWatchers,
+}
+
+/// Serve `service` on `listener` until the process exits. Runs on a detached
+/// thread, like the management listener.
+pub fn serve(listener: TcpListener, service: ManagementService) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            warn!(%e, "failed to start the gRPC runtime");
+            return;
+        }
+    };
+    let result = runtime.block_on(async move {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        tonic::transport::Server::builder()
+            .add_service(ManagementServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+            .map_err(std::io::Error::other)
+    });
+    if let Err(e) = result {
+        warn!(%e, "gRPC service stopped");
+    }
+}
+
+impl ManagementService {
+    /// Apply `change` to the served reservations and write them back to
+    /// reservations.json when `persist`, holding the reservations lock
+    /// throughout. The lock and the write wait out the management listener
+    /// and the reservation source, so this runs on the blocking pool rather
+    /// than the runtime's only thread. Returns what `change` returned and the
+    /// number of reservations now served.
+    async fn update<T, F>(&self, persist: bool, change: F) -> Result<(T, usize), Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ReservationDb) -> Result<T, Status> + Send + 'static,
+    {
+        let reservations = self.reservations.clone();
+        let config_dir = self.config_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let _lock = lock_reservations();
+            let db = reservations.load();
+            let changed = change(&db)?;
+            if persist {
+                atomic_write_reservations(&config_dir, &db.reservations()).map_err(|e| {
+                    warn!(%e, "failed to persist reservations to disk");
+                    Status::internal(format!(
+                        "Applied in memory but failed to write reservations: {e}"
+                    ))
+                })?;
+            }
+            Ok((changed, db.len()))
+        })
+        .await
+        .unwrap_or_else(|e| Err(Status::internal(e.to_string())))
+    }
+
+    fn snapshot(&self) -> LeaseSnapshot {
+        self.leases.snapshot()
+    }
+}
+
+#[tonic::async_trait]
+impl Management for ManagementService {
+    async fn get_lease(
+        &self,
+        request: Request<proto::GetLeaseRequest>,
+    ) -> Result<Response<proto::Lease>, Status> {
+        let address: IpAddr = request
+            .into_inner()
+            .address
+            .parse()
+            .map_err(|e| Status::invalid_argument(format!("Invalid address: {e}")))?;
+        let snapshot = self.snapshot();
+        let lease = match address {
+            IpAddr::V4(ipv4) => snapshot
+                .v4
+                .into_iter()
+                .find(|lease| lease.ipv4 == ipv4)
+                .map(proto::Lease::from),
+            IpAddr::V6(ipv6) => snapshot
+                .v6
+                .into_iter()
+                .find(|lease| lease.ipv6_na == ipv6)
+                .map(proto::Lease::from),
+        };
+        lease
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found(format!("No running lease on {address}")))
+    }
+
+    async fn list_leases(
+        &self,
+        _request: Request<proto::ListLeasesRequest>,
+    ) -> Result<Response<proto::ListLeasesResponse>, Status> {
+        let snapshot = self.snapshot();
+        let v4 = snapshot.v4.into_iter().map(proto::Lease::from);
+        let v6 = snapshot.v6.into_iter().map(proto::Lease::from);
+        let leases = v4.chain(v6).collect();
+        Ok(Response::new(proto::ListLeasesResponse { leases }))
+    }
+
+    async fn upsert_reservation(
+        &self,
+        request: Request<proto::UpsertReservationRequest>,
+    ) -> Result<Response<proto::UpsertReservationResponse>, Status> {
+        let request = request.into_inner();
+        let persist = request.persist.unwrap_or(true);
+        let mut reservation: Reservation = serde_json::from_str(&request.reservation)
+            .map_err(|e| Status::invalid_argument(format!("Invalid reservation: {e}")))?;
+        let ((), count) = self
+            .update(persist, move |db| {
+                db.derive_ipv6_na(&mut reservation)
+                    .map_err(Status::invalid_argument)?;
+                db.upsert(reservation);
+                Ok(())
+            })
+            .await?;
+        info!(persist, "added reservation via gRPC");
+        Ok(Response::new(proto::UpsertReservationResponse {
+            reservation_count: count as u64,
+        }))
+    }
+
+    async fn delete_reservation(
+        &self,
+        request: Request<proto::DeleteReservationRequest>,
+    ) -> Result<Response<proto::DeleteReservationResponse>, Status> {
+        let request = request.into_inner();
+        let persist = request.persist.unwrap_or(true);
+        let mac = match request.mac.as_str() {
+            "" => None,
+            mac => Some(
+                parse_mac(mac)
+                    .ok_or_else(|| Status::invalid_argument(format!("Invalid mac `{mac}`")))?,
+            ),
+        };
+        let duid = match request.duid.as_str() {
+            "" => None,
+            duid => Some(
+                Duid::try_from(duid)
+                    .map_err(|e| Status::invalid_argument(format!("Invalid duid: {e}")))?,
+            ),
+        };
+        let subscriber_id = Some(request.subscriber_id)
+            .filter(|id| !id.is_empty())
+            .map(Into::into);
+
+        let option82 = request.option82.map(Option82::from);
+        let option1837 = request.option1837.map(Option1837::from);
+
+        let (removed, count) = self
+            .update(persist, move |db| {
+                let matched = matching(db, mac, duid, option82, option1837, subscriber_id);
+                if matched.is_empty() {
+                    return Err(Status::not_found("No matching reservation"));
+                }
+                for reservation in &matched {
+                    db.remove(reservation);
+                }
+                Ok(matched.len())
+            })
+            .await?;
+        info!(persist, "removed reservation via gRPC");
+        Ok(Response::new(proto::DeleteReservationResponse {
+            removed: removed as u64,
+            reservation_count: count as u64,
+        }))
+    }
+
+    type WatchEventsStream = ReceiverStream<Result<proto::Event, Status>>;
+
+    async fn watch_events(
+        &self,
+        _request: Request<proto::WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        let events = self.watchers.subscribe();
+        let (tx, rx) = tokio::sync::mpsc::channel(STREAM_BUFFER);
+        // The subscription is a std channel, so it's read on a blocking
+        // thread; dropping it when the client goes away unsubscribes
+        tokio::task::spawn_blocking(move || {
+            while !tx.is_closed() {
+                let event = match events.recv_timeout(IDLE_CHECK) {
+                    Ok(event) => event,
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
+                };
+                let json = match serde_json::to_string(&*event) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        warn!(%e, "failed to serialize event for gRPC");
+                        continue;
+                    }
+                };
+                if tx.blocking_send(Ok(proto::Event { json })).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+impl From<LeaseV4> for proto::Lease {
+    fn from(lease: LeaseV4) -> Self {
+        proto::Lease {
+            address: lease.ipv4.to_string(),
+            mac: lease.mac.to_string(),
+            option82: lease.option82.map(Into::into),
+            subscriber_id: lease.subscriber_id.map(Into::into),
+            leased_at: lease.leased_at,
+            expires_at: lease.expires_at,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<LeaseV6> for proto::Lease {
+    fn from(lease: LeaseV6) -> Self {
+        proto::Lease {
+            address: lease.ipv6_na.to_string(),
+            duid: lease.duid.to_string(),
+            option1837: lease.option1837.map(Into::into),
+            subscriber_id: lease.subscriber_id.map(Into::into),
+            leased_at: lease.leased_at,
+            expires_at: lease.expires_at,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Option82> for proto::Option82 {
+    fn from(opt: Option82) -> Self {
+        proto::Option82 {
+            circuit: opt.circuit.map(Into::into),
+            remote: opt.remote.map(Into::into),
+            subscriber: opt.subscriber.map(Into::into),
+        }
+    }
+}
+
+impl From<proto::Option82> for Option82 {
+    fn from(opt: proto::Option82) -> Self {
+        Option82 {
+            circuit: opt.circuit.map(Into::into),
+            remote: opt.remote.map(Into::into),
+            subscriber: opt.subscriber.map(Into::into),
+        }
+    }
+}
+
+impl From<Option1837> for proto::Option1837 {
+    fn from(opt: Option1837) -> Self {
+        proto::Option1837 {
+            interface: opt.interface.map(Into::into),
+            remote: opt.remote.map(Into::into),
+            enterprise_number: opt.enterprise_number,
+        }
+    }
+}
+
+impl From<proto::Option1837> for Option1837 {
+    fn from(opt: proto::Option1837) -> Self {
+        Option1837 {
+            interface: opt.interface.map(Into::into),
+            remote: opt.remote.map(Into::into),
+            enterprise_number: opt.enterprise_number,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(db: ReservationDb) -> ManagementService {
+        ManagementService {
+            reservations: Arc::new(ArcSwap::from_pointee(db)),
+            leases: Arc::new(Opt82Cache::new()),
+            config_dir: PathBuf::new(),
+            watchers: EventWatchers::default(),
+        }
+    }
+
+    #[test]
+    fn upsert_then_delete_by_option82() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let service = service(ReservationDb::new());
+
+        let upserted = runtime
+            .block_on(service.upsert_reservation(Request::new(
+                proto::UpsertReservationRequest {
+                    reservation: r#"{"ipv4":"100.64.0.1","ipv6_pd":"2001:db8:1::/56","option82":{"remote":"olt1:1"}}"#.into(),
+                    persist: Some(false),
+                },
+            )))
+            .unwrap();
+        assert_eq!(upserted.into_inner().reservation_count, 1);
+
+        let request = || proto::DeleteReservationRequest {
+            option82: Some(proto::Option82 {
+                remote: Some("olt1:1".into()),
+                ..Default::default()
+            }),
+            persist: Some(false),
+            ..Default::default()
+        };
+        let deleted = runtime
+            .block_on(service.delete_reservation(Request::new(request())))
+            .unwrap()
+            .into_inner();
+        assert_eq!((deleted.removed, deleted.reservation_count), (1, 0));
+
+        let status = runtime
+            .block_on(service.delete_reservation(Request::new(request())))
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+}
@@ -0,0 +1,296 @@
+//! Active/standby high availability between two shadowdhcp instances.
+//!
+//! Each peer forwards the runtime state it learns from its own traffic to
+//! the other over a TCP JSON-lines connection, and sends a heartbeat
+//! whenever it has nothing else to send. That is the opt82 cache: mac ->
+//! option82 bindings learned from DHCPv4 ACKs (needed to serve opt82-only
+//! reservations over DHCPv6), DUID -> option18/37 bindings, and which client
+//! holds each reserved address, so the standby carries on spotting lease
+//! conflicts after a failover. The reservations themselves aren't sent;
+//! both peers load them from their own `reservations.json`. Transaction
+//! history stays local.
+//!
+//! Both peers run the same code; the role only decides who answers. The
+//! primary always answers. The standby ignores DHCP traffic until it hasn't
+//! heard anything from the primary for `failover_secs`, then answers until
+//! the primary is heard from again.
+//!
+//! The peer link has no authentication: only connections from the
+//! configured peer IP are accepted, and it should run over a private
+//! network.
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use advmac::MacAddr6;
+use compact_str::CompactString;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::config::{HaConfig, HaRole};
+use crate::opt82_cache::Opt82Cache;
+use crate::shutdown::Shutdown;
+use crate::types::{Duid, Option1837, Option82};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Capacity of the replication queue between the workers and the sender.
+pub const REPLICATION_QUEUE: usize = 4096;
+
+/// One line on the peer link
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PeerMessage {
+    Heartbeat,
+    Binding {
+        mac: MacAddr6,
+        option82: Option82,
+    },
+    DuidBinding {
+        duid: Duid,
+        option1837: Option1837,
+    },
+    /// `mac` was granted `ipv4` for `lease_secs`
+    HolderV4 {
+        ipv4: Ipv4Addr,
+        mac: MacAddr6,
+        subscriber_id: Option<CompactString>,
+        lease_secs: u64,
+    },
+    /// `duid` was granted `ipv6_na` for `lease_secs`
+    HolderV6 {
+        ipv6_na: Ipv6Addr,
+        duid: Duid,
+        subscriber_id: Option<CompactString>,
+        lease_secs: u64,
+    },
+    /// `duid` released `ipv6_na`
+    ReleaseV6 {
+        ipv6_na: Ipv6Addr,
+        duid: Duid,
+    },
+}
+
+/// Tracks when the peer was last heard from and decides whether this
+/// instance should answer DHCP.
+pub struct PeerMonitor {
+    role: HaRole,
+    failover: Duration,
+    start: Instant,
+    /// Milliseconds after `start` the peer was last heard from. Startup
+    /// counts as hearing from it, so a standby waits a full failover
+    /// period before answering.
+    last_seen_ms: AtomicU64,
+    active: AtomicBool,
+}
+
+impl PeerMonitor {
+    pub fn new(cfg: &HaConfig) -> Self {
+        Self {
+            role: cfg.role,
+            failover: Duration::from_secs(cfg.failover_secs),
+            start: Instant::now(),
+            last_seen_ms: AtomicU64::new(0),
+            active: AtomicBool::new(cfg.role == HaRole::Primary),
+        }
+    }
+
+    fn heard(&self) {
+        self.last_seen_ms
+            .store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Always true on the primary. On the standby, true only while the
+    /// primary has been silent for at least `failover_secs`.
+    pub fn should_answer(&self) -> bool {
+        if self.role == HaRole::Primary {
+            return true;
+        }
+        let last_seen = Duration::from_millis(self.last_seen_ms.load(Ordering::Relaxed));
+        let silent = self.start.elapsed().saturating_sub(last_seen);
+        let active = silent >= self.failover;
+        // Both workers call this; the swap makes sure only one logs.
+        if self.active.swap(active, Ordering::Relaxed) != active {
+            if active {
+                warn!(
+                    silent_secs = silent.as_secs(),
+                    "HA primary unreachable, standby taking over"
+                );
+            } else {
+                info!("HA primary is back, standby standing down");
+            }
+        }
+        active
+    }
+}
+
+/// Forward locally learned state to the peer, heartbeating while idle.
+/// Reconnects at most once per heartbeat interval; updates produced while
+/// the peer is unreachable are dropped, since the peer relearns them from
+/// its own traffic once it is up.
+pub fn sender(
+    peer: SocketAddr,
+    rx: mpsc::Receiver<PeerMessage>,
+    heartbeat: Duration,
+    shutdown: Shutdown,
+) {
+    info!(%peer, "Starting HA peer sender");
+    let mut conn: Option<BufWriter<TcpStream>> = None;
+    let mut next_connect = Instant::now();
+
+    loop {
+        if shutdown.is_signalled() {
+            return;
+        }
+        let msg = match rx.recv_timeout(heartbeat) {
+            Ok(msg) => msg,
+            Err(mpsc::RecvTimeoutError::Timeout) => PeerMessage::Heartbeat,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+
+        if conn.is_none() && Instant::now() >= next_connect {
+            next_connect = Instant::now() + heartbeat;
+            match connect(peer) {
+                Ok(stream) => {
+                    info!(%peer, "connected to HA peer");
+                    conn = Some(BufWriter::new(stream));
+                }
+                Err(e) => debug!(%peer, %e, "HA peer connect failed"),
+            }
+        }
+        if let Some(w) = conn.as_mut() {
+            if let Err(e) = write_message(w, &msg) {
+                warn!(%peer, %e, "lost connection to HA peer");
+                conn = None;
+            }
+        }
+    }
+}
+
+fn connect(peer: SocketAddr) -> std::io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&peer, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    Ok(stream)
+}
+
+fn write_message(w: &mut BufWriter<TcpStream>, msg: &PeerMessage) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *w, msg)?;
+    w.write_all(b"\n")?;
+    w.flush()
+}
+
+/// Accept the peer's connection and apply what it sends. Runs detached like
+/// the management listener: it blocks in accept() and dies with the process.
+pub fn listener(
+    listener: TcpListener,
+    peer: IpAddr,
+    cache: Arc<Opt82Cache>,
+    monitor: Arc<PeerMonitor>,
+    read_timeout: Duration,
+) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                match stream.peer_addr() {
+                    Ok(addr) if addr.ip() == peer => {}
+                    other => {
+                        warn!(addr = ?other.ok(), "rejected HA connection from unexpected address");
+                        continue;
+                    }
+                }
+                // A restarted peer reconnects while the old connection may
+                // still be blocked in read, so each connection gets its own
+                // thread; the read timeout retires half-open ones.
+                let (cache, monitor) = (cache.clone(), monitor.clone());
+                if let Err(e) = std::thread::Builder::new()
+                    .name("ha-peer".to_string())
+                    .spawn(move || read_peer(stream, &cache, &monitor, read_timeout))
+                {
+                    warn!(%e, "failed to spawn HA peer reader");
+                }
+            }
+            Err(e) => {
+                warn!(%e, "failed to accept HA connection");
+                std::thread::sleep(Duration::from_millis(250));
+            }
+        }
+    }
+}
+
+fn read_peer(stream: TcpStream, cache: &Opt82Cache, monitor: &PeerMonitor, timeout: Duration) {
+    stream.set_read_timeout(Some(timeout)).ok();
+    info!(peer = ?stream.peer_addr().ok(), "HA peer connected");
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                debug!(%e, "HA peer connection closed");
+                return;
+            }
+        };
+        monitor.heard();
+        match serde_json::from_str::<PeerMessage>(&line) {
+            Ok(PeerMessage::Heartbeat) => {}
+            Ok(msg) => cache.apply_peer(msg),
+            Err(e) => warn!(%e, "invalid message from HA peer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ha_config(role: HaRole, failover_secs: u64) -> HaConfig {
+        HaConfig {
+            listen: "127.0.0.1:8548".parse().unwrap(),
+            peer: "127.0.0.2:8548".parse().unwrap(),
+            role,
+            heartbeat_secs: 1,
+            failover_secs,
+        }
+    }
+
+    #[test]
+    fn primary_always_answers() {
+        let m = PeerMonitor::new(&ha_config(HaRole::Primary, 5));
+        assert!(m.should_answer());
+    }
+
+    #[test]
+    fn standby_answers_only_after_primary_silent() {
+        let m = PeerMonitor::new(&ha_config(HaRole::Standby, 5));
+        assert!(!m.should_answer());
+
+        let m = PeerMonitor::new(&ha_config(HaRole::Standby, 0));
+        assert!(m.should_answer());
+    }
+
+    #[test]
+    fn peer_message_wire_format() {
+        let msg: PeerMessage = serde_json::from_str(
+            r#"{"type":"binding","mac":"00-11-22-33-44-55","option82":{"circuit":null,"remote":"r1","subscriber":null}}"#,
+        )
+        .unwrap();
+        assert!(matches!(msg, PeerMessage::Binding { .. }));
+        let msg: PeerMessage = serde_json::from_str(
+            r#"{"type":"holder_v4","ipv4":"10.0.0.1","mac":"00-11-22-33-44-55","subscriber_id":null,"lease_secs":3600}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            msg,
+            PeerMessage::HolderV4 {
+                lease_secs: 3600,
+                ..
+            }
+        ));
+        assert_eq!(
+            serde_json::to_string(&PeerMessage::Heartbeat).unwrap(),
+            r#"{"type":"heartbeat"}"#
+        );
+    }
+}
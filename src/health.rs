@@ -0,0 +1,416 @@
+//! What `/healthz` and `/readyz` on the management address report. Workers
+//! note each packet they receive and count themselves running for as long as
+//! their loop lives, so a worker killed by a panic shows up until its
+//! supervisor has started it again.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::analytics::{events::ReservationMatch, QueueDepth};
+use crate::config::Config;
+use crate::opt82_cache::CacheStats;
+use crate::relays::RelayInventory;
+use crate::reservationdb::LookupCount;
+
+/// Shared between the workers, which update it, and the management listener
+#[derive(Default)]
+pub struct Health {
+    pub v4: ProtocolHealth,
+    pub v6: ProtocolHealth,
+    pub events_queued: QueueDepth,
+    /// Not part of the report; served by the `relays` management command
+    pub relays: RelayInventory,
+}
+
+#[derive(Default)]
+pub struct ProtocolHealth {
+    sockets: AtomicUsize,
+    running: AtomicUsize,
+    panics: AtomicU64,
+    restarts: AtomicU64,
+    recv_errors: AtomicU64,
+    rebinds: AtomicU64,
+    packets: AtomicU64,
+    /// Unix milliseconds, 0 before the first packet
+    last_packet: AtomicU64,
+    /// Replies sent per match method and extractor
+    matches: Mutex<BTreeMap<(&'static str, Option<&'static str>), u64>>,
+    /// Messages handled per reply type or reason for not replying
+    outcomes: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl ProtocolHealth {
+    /// Sockets bound for this protocol, one per worker
+    pub fn set_sockets(&self, sockets: usize) {
+        self.sockets.store(sockets, Ordering::Relaxed);
+    }
+
+    pub fn record_packet(&self) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.last_packet.store(now, Ordering::Relaxed);
+    }
+
+    /// Count the calling worker as running until the guard is dropped
+    pub fn worker_running(&self) -> RunningWorker<'_> {
+        self.running.fetch_add(1, Ordering::Relaxed);
+        RunningWorker(self)
+    }
+
+    /// A panicked worker was started again
+    pub fn record_restart(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A reply was sent for a reservation found by `found`
+    pub fn record_match(&self, found: &ReservationMatch) {
+        let mut matches = self.matches.lock().unwrap_or_else(|e| e.into_inner());
+        *matches.entry((found.method, found.extractor)).or_default() += 1;
+    }
+
+    /// A message was answered with `outcome`, the reply's message type, or
+    /// left unanswered for that reason, as in the transaction history
+    pub fn record_outcome(&self, outcome: &'static str) {
+        let mut outcomes = self.outcomes.lock().unwrap_or_else(|e| e.into_inner());
+        *outcomes.entry(outcome).or_default() += 1;
+    }
+
+    /// Messages handled with `outcome` since startup
+    pub fn outcome_count(&self, outcome: &str) -> u64 {
+        let outcomes = self.outcomes.lock().unwrap_or_else(|e| e.into_inner());
+        outcomes.get(outcome).copied().unwrap_or_default()
+    }
+
+    /// The match counts, with a zero for each of the `configured`
+    /// (method, extractor) pairs that hasn't matched yet so rules that never
+    /// fire stand out
+    fn matches(
+        &self,
+        configured: impl IntoIterator<Item = (&'static str, &'static str)>,
+    ) -> Vec<MatchCount> {
+        let mut matches = self
+            .matches
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for (method, extractor) in configured {
+            matches.entry((method, Some(extractor))).or_default();
+        }
+        matches
+            .into_iter()
+            .map(|((method, extractor), count)| MatchCount {
+                method,
+                extractor,
+                count,
+            })
+            .collect()
+    }
+
+    /// A worker's socket was replaced after [`RecvAction::Rebind`]
+    pub fn record_rebind(&self) {
+        self.rebinds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// What a worker should do about a failed `recv_from`. `consecutive`
+    /// counts the failures since the last packet; the worker resets it.
+    pub fn recv_failed(&self, err: &io::Error, consecutive: &mut u32) -> RecvAction {
+        /// About 10 seconds of errors at the longest backoff
+        const MAX_CONSECUTIVE: u32 = 16;
+        const MAX_BACKOFF_MS: u64 = 1000;
+
+        match err.kind() {
+            // Read-timeout expiry: WouldBlock on Unix, TimedOut on Windows
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => return RecvAction::Retry,
+            _ => self.recv_errors.fetch_add(1, Ordering::Relaxed),
+        };
+        match err.kind() {
+            // An earlier reply met ICMP unreachable, or a signal arrived;
+            // the socket itself is fine
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted => RecvAction::Retry,
+            // The address or interface the socket is bound to went away
+            io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::NotConnected => RecvAction::Rebind,
+            _ => {
+                *consecutive = consecutive.saturating_add(1);
+                if *consecutive > MAX_CONSECUTIVE {
+                    RecvAction::Rebind
+                } else {
+                    // Exponential so a persistent error doesn't spin the CPU
+                    RecvAction::Backoff(Duration::from_millis(std::cmp::min(
+                        10_u64.saturating_mul(2_u64.saturating_pow(*consecutive)),
+                        MAX_BACKOFF_MS,
+                    )))
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> ProtocolStatus {
+        let last_packet = self.last_packet.load(Ordering::Relaxed);
+        ProtocolStatus {
+            sockets: self.sockets.load(Ordering::Relaxed),
+            workers_running: self.running.load(Ordering::Relaxed),
+            worker_panics: self.panics.load(Ordering::Relaxed),
+            worker_restarts: self.restarts.load(Ordering::Relaxed),
+            recv_errors: self.recv_errors.load(Ordering::Relaxed),
+            socket_rebinds: self.rebinds.load(Ordering::Relaxed),
+            packets_received: self.packets.load(Ordering::Relaxed),
+            last_packet_at: (last_packet != 0).then_some(last_packet),
+            matches: Vec::new(),
+            outcomes: self
+                .outcomes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        }
+    }
+}
+
+/// How a worker handles a receive error
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecvAction {
+    /// Transient; try again straight away
+    Retry,
+    /// Sleep before trying again
+    Backoff(Duration),
+    /// The socket is unusable; return it to the supervisor to bind a fresh one
+    Rebind,
+}
+
+/// Dropped when the worker returns or unwinds
+pub struct RunningWorker<'a>(&'a ProtocolHealth);
+
+impl Drop for RunningWorker<'_> {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::Relaxed);
+        if std::thread::panicking() {
+            self.0.panics.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProtocolStatus {
+    pub sockets: usize,
+    pub workers_running: usize,
+    pub worker_panics: u64,
+    pub worker_restarts: u64,
+    /// `recv_from` failures other than the read timeout
+    pub recv_errors: u64,
+    /// Sockets replaced after persistent or fatal receive errors
+    pub socket_rebinds: u64,
+    pub packets_received: u64,
+    /// Unix milliseconds of the last packet received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_packet_at: Option<u64>,
+    pub matches: Vec<MatchCount>,
+    /// Messages handled by reply type or failure reason
+    pub outcomes: BTreeMap<&'static str, u64>,
+}
+
+/// Replies sent for reservations found one way, as in the events'
+/// `match_method` and `extractor_used`
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct MatchCount {
+    pub method: &'static str,
+    pub extractor: Option<&'static str>,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// `ok`; `degraded` from a worker panicking until it is restarted;
+    /// `unavailable` while any other worker isn't running, such as during
+    /// startup and shutdown
+    pub status: &'static str,
+    pub v4: ProtocolStatus,
+    pub v6: ProtocolStatus,
+    pub reservations: usize,
+    /// Filled in by the caller, which has the database at hand
+    pub reservation_lookups: Vec<LookupCount>,
+    pub leases: CacheStats,
+    pub events_queued: usize,
+}
+
+impl Report {
+    /// `/healthz`: fails while a worker is down after a panic
+    pub fn healthy(&self) -> bool {
+        self.status != "degraded"
+    }
+
+    /// `/readyz`: also fails until every worker is running
+    pub fn ready(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+impl Health {
+    pub fn report(&self, config: &Config, reservations: usize, leases: CacheStats) -> Report {
+        let (mut v4, mut v6) = (self.v4.status(), self.v6.status());
+        v4.matches = self.v4.matches(
+            config
+                .option82_extractors
+                .iter()
+                .map(|(name, _)| ("option82", *name)),
+        );
+        v6.matches = self.v6.matches(
+            config
+                .option1837_extractors
+                .iter()
+                .map(|(name, _)| ("option1837", *name))
+                .chain(config.mac_extractors.iter().map(|e| ("mac", e.name()))),
+        );
+        let status =
+            if v4.worker_panics > v4.worker_restarts || v6.worker_panics > v6.worker_restarts {
+                "degraded"
+            } else if v4.workers_running < v4.sockets || v6.workers_running < v6.sockets {
+                "unavailable"
+            } else {
+                "ok"
+            };
+        Report {
+            status,
+            v4,
+            v6,
+            reservations,
+            reservation_lookups: Vec::new(),
+            leases,
+            events_queued: self.events_queued.get(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recv_errors_retry_back_off_then_rebind() {
+        let health = ProtocolHealth::default();
+        let mut consecutive = 0;
+        let timeout = io::Error::from(io::ErrorKind::WouldBlock);
+        assert_eq!(
+            health.recv_failed(&timeout, &mut consecutive),
+            RecvAction::Retry
+        );
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            health.recv_failed(&reset, &mut consecutive),
+            RecvAction::Retry
+        );
+        let gone = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        assert_eq!(
+            health.recv_failed(&gone, &mut consecutive),
+            RecvAction::Rebind
+        );
+        assert_eq!(consecutive, 0);
+
+        let other = io::Error::other("ENOBUFS");
+        assert_eq!(
+            health.recv_failed(&other, &mut consecutive),
+            RecvAction::Backoff(Duration::from_millis(20))
+        );
+        while health.recv_failed(&other, &mut consecutive) != RecvAction::Rebind {}
+        assert_eq!(consecutive, 17);
+
+        let status = health.status();
+        assert_eq!(status.recv_errors, 19);
+        assert_eq!(status.socket_rebinds, 0);
+    }
+
+    #[test]
+    fn panicked_worker_degrades_health_until_restarted() {
+        let health = Health::default();
+        health.v4.set_sockets(1);
+        health.v6.set_sockets(1);
+        let _v6 = health.v6.worker_running();
+        assert_eq!(
+            health
+                .report(&Config::default(), 0, CacheStats::default())
+                .status,
+            "unavailable"
+        );
+
+        std::thread::scope(|s| {
+            let v4 = s.spawn(|| {
+                let _running = health.v4.worker_running();
+                health.v4.record_packet();
+                assert!(health
+                    .report(&Config::default(), 0, CacheStats::default())
+                    .ready());
+                panic!("worker bug");
+            });
+            assert!(v4.join().is_err());
+        });
+
+        let report = health.report(&Config::default(), 0, CacheStats::default());
+        assert_eq!(report.status, "degraded");
+        assert!(!report.healthy());
+        assert_eq!(report.v4.workers_running, 0);
+        assert_eq!(report.v4.worker_panics, 1);
+        assert!(report.v4.last_packet_at.is_some());
+        assert!(report.v6.last_packet_at.is_none());
+
+        health.v4.record_restart();
+        let _v4 = health.v4.worker_running();
+        let report = health.report(&Config::default(), 0, CacheStats::default());
+        assert!(report.ready());
+        assert_eq!(report.v4.worker_restarts, 1);
+    }
+
+    #[test]
+    fn match_counts_include_configured_extractors() {
+        let health = Health::default();
+        let config = Config {
+            option82_extractors: crate::v4::extractors::get_all_extractors()
+                .into_iter()
+                .filter(|(name, _)| matches!(*name, "remote_only" | "circuit_only"))
+                .collect(),
+            ..Default::default()
+        };
+        health.v4.record_match(&ReservationMatch::mac("chaddr"));
+        health
+            .v4
+            .record_match(&ReservationMatch::option82("remote_only"));
+        health
+            .v4
+            .record_match(&ReservationMatch::option82("remote_only"));
+
+        let report = health.report(&config, 0, CacheStats::default());
+        let count = |method, extractor| MatchCount {
+            method,
+            extractor: Some(extractor),
+            count: 0,
+        };
+        assert_eq!(
+            report.v4.matches,
+            [
+                MatchCount {
+                    count: 1,
+                    ..count("mac", "chaddr")
+                },
+                count("option82", "circuit_only"),
+                MatchCount {
+                    count: 2,
+                    ..count("option82", "remote_only")
+                },
+            ]
+        );
+        // The default config's v6 MAC extractor, not matched yet
+        assert_eq!(
+            report.v6.matches,
+            [count("mac", "client_linklayer_address")]
+        );
+    }
+}
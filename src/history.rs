@@ -0,0 +1,206 @@
+//! The last few transactions with each client, kept in memory so a client
+//! that can't get an address can be diagnosed from the management port
+//! without searching the analytics store.
+//!
+//! Every message the workers receive from a client is recorded with what the
+//! server did about it: the reply it sent, or why it sent none. DHCPv4
+//! clients are keyed by chaddr and DHCPv6 clients by DUID. Each client keeps
+//! its last `lease_history` transactions, and clients that haven't been heard
+//! from for a day are forgotten along with the Option 82/18/37 bindings.
+
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use advmac::MacAddr6;
+use compact_str::CompactString;
+use dashmap::DashMap;
+use dhcproto::{v4, v6};
+use serde::Serialize;
+
+use crate::analytics::events::{now, DhcpEventV4, DhcpEventV6, ReservationMatch};
+use crate::types::{Duid, Option1837, Option82};
+use crate::v4::extensions::{RelayAgentInformationExt, ShadowMessageExtV4};
+use crate::v6::extensions::ShadowRelayMessageExtV6;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Transaction {
+    /// Unix milliseconds
+    pub timestamp: u64,
+    pub message_type: &'static str,
+    pub xid: String,
+    /// Address the relay sent the message from
+    pub relay: IpAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub option82: Option<Option82>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub option1837: Option<Option1837>,
+    /// Message type of the reply, or why there was none, as in events'
+    /// `failure_reason`
+    pub outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub match_method: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extractor_used: Option<&'static str>,
+}
+
+impl Transaction {
+    pub fn v4(
+        msg: &v4::Message,
+        relay: IpAddr,
+        outcome: &'static str,
+        found: Option<&ReservationMatch>,
+    ) -> Self {
+        let option82 = msg.relay_agent_information().map(|relay| Option82 {
+            circuit: relay
+                .circuit_id()
+                .and_then(|v| CompactString::from_utf8(v).ok()),
+            remote: relay
+                .remote_id()
+                .and_then(|v| CompactString::from_utf8(v).ok()),
+            subscriber: relay
+                .subscriber_id()
+                .and_then(|v| CompactString::from_utf8(v).ok()),
+        });
+        Self {
+            timestamp: now(),
+            message_type: msg
+                .message_type()
+                .map_or("Unknown", DhcpEventV4::message_type_str),
+            xid: format!("{:08x}", msg.xid()),
+            relay,
+            option82,
+            option1837: None,
+            outcome,
+            match_method: found.map(|m| m.method),
+            extractor_used: found.and_then(|m| m.extractor),
+        }
+    }
+
+    pub fn v6(
+        msg: &v6::Message,
+        relay_msg: &v6::RelayMessage,
+        relay: IpAddr,
+        outcome: &'static str,
+        found: Option<&ReservationMatch>,
+    ) -> Self {
+        let xid = msg.xid();
+        Self {
+            timestamp: now(),
+            message_type: DhcpEventV6::message_type_str(msg.msg_type()),
+            xid: format!("{:02x}{:02x}{:02x}", xid[0], xid[1], xid[2]),
+            relay,
+            option82: None,
+            option1837: relay_msg.option1837(),
+            outcome,
+            match_method: found.map(|m| m.method),
+            extractor_used: found.and_then(|m| m.extractor),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct LeaseHistory {
+    v4: DashMap<MacAddr6, VecDeque<Transaction>>,
+    v6: DashMap<Duid, VecDeque<Transaction>>,
+}
+
+impl LeaseHistory {
+    /// Keep `transaction` as one of the last `depth` for `mac`. A depth of
+    /// 0 keeps nothing.
+    pub fn record_v4(&self, depth: usize, mac: MacAddr6, transaction: Transaction) {
+        record(&self.v4, depth, mac, transaction);
+    }
+
+    /// DHCPv6 counterpart of [`Self::record_v4`]
+    pub fn record_v6(&self, depth: usize, duid: &Duid, transaction: Transaction) {
+        record(&self.v6, depth, duid.clone(), transaction);
+    }
+
+    /// Transactions with `mac`, oldest first
+    pub fn v4(&self, mac: MacAddr6) -> Vec<Transaction> {
+        self.v4
+            .get(&mac)
+            .map(|ring| ring.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Transactions with `duid`, oldest first
+    pub fn v6(&self, duid: &Duid) -> Vec<Transaction> {
+        self.v6
+            .get(duid)
+            .map(|ring| ring.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forget clients whose last transaction is older than `max_age`
+    pub fn evict_expired(&self, max_age: Duration) {
+        let cutoff = now().saturating_sub(max_age.as_millis() as u64);
+        let recent =
+            |ring: &VecDeque<Transaction>| ring.back().is_some_and(|t| t.timestamp >= cutoff);
+        self.v4.retain(|_, ring| recent(ring));
+        self.v6.retain(|_, ring| recent(ring));
+    }
+
+    pub fn clients(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+}
+
+fn record<K: Hash + Eq>(
+    clients: &DashMap<K, VecDeque<Transaction>>,
+    depth: usize,
+    key: K,
+    transaction: Transaction,
+) {
+    if depth == 0 {
+        return;
+    }
+    let mut ring = clients.entry(key).or_default();
+    while ring.len() >= depth {
+        ring.pop_front();
+    }
+    ring.push_back(transaction);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn transaction(outcome: &'static str) -> Transaction {
+        Transaction {
+            timestamp: now(),
+            message_type: "Discover",
+            xid: "00000001".into(),
+            relay: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            option82: None,
+            option1837: None,
+            outcome,
+            match_method: None,
+            extractor_used: None,
+        }
+    }
+
+    #[test]
+    fn keeps_last_depth_transactions_per_client() {
+        let history = LeaseHistory::default();
+        let mac = MacAddr6::new([0, 1, 2, 3, 4, 5]);
+        for outcome in ["NoReservation", "Offer", "Ack"] {
+            history.record_v4(2, mac, transaction(outcome));
+        }
+        let outcomes: Vec<_> = history.v4(mac).iter().map(|t| t.outcome).collect();
+        assert_eq!(outcomes, ["Offer", "Ack"]);
+        assert!(history.v4(MacAddr6::new([0, 1, 2, 3, 4, 6])).is_empty());
+
+        history.record_v4(0, MacAddr6::new([0, 1, 2, 3, 4, 7]), transaction("Ack"));
+        assert_eq!(history.clients(), 1, "depth 0 records nothing");
+
+        history.evict_expired(Duration::from_secs(60));
+        assert_eq!(history.clients(), 1);
+        std::thread::sleep(Duration::from_millis(10));
+        history.evict_expired(Duration::from_millis(5));
+        assert_eq!(history.clients(), 0);
+    }
+}
@@ -0,0 +1,162 @@
+//! Reservation lookup and DHCP packet handling behind the shadowdhcp server.
+//!
+//! The `shadowdhcp` binary is a thin runtime around this crate: it binds the
+//! sockets, spawns the workers and writers, and feeds each decoded packet to
+//! [`handle_v4_message`] or [`handle_v6_message`]. Other tooling can do the
+//! same without any of that runtime, for example to replay captured packets
+//! against a reservations file and see what the server would have answered:
+//!
+//! ```no_run
+//! use shadowdhcp::{Config, Opt82Cache, ReservationDb};
+//!
+//! let config = Config::load_from_files("/etc/shadowdhcp").unwrap();
+//! let reservations = ReservationDb::new();
+//! let leases = Opt82Cache::new();
+//! # let msg: shadowdhcp::dhcproto::v4::Message = unimplemented!();
+//! let response = shadowdhcp::handle_v4_message(&config, &reservations, &leases, &msg);
+//! ```
+//!
+//! Both functions are pure apart from the MAC/DUID bindings they record in
+//! the [`Opt82Cache`]; they send nothing and log through `tracing`.
+//!
+//! With the `testutil` feature, [`testutil`] builds the DHCPv4 and DHCPv6
+//! messages a relayed client sends, for integration tests against either.
+//!
+//! Only the modules documented here are a supported API. The rest are the
+//! server runtime, public so the binary can use them, and may change in any
+//! release.
+
+pub mod config;
+pub mod opt82_cache;
+pub mod outcome;
+pub mod reservationdb;
+#[cfg(any(test, feature = "testutil"))]
+pub mod testutil;
+pub mod types;
+pub mod v4;
+pub mod v6;
+
+#[doc(hidden)]
+pub mod alerts;
+#[doc(hidden)]
+pub mod analytics;
+#[doc(hidden)]
+pub mod audit;
+#[doc(hidden)]
+pub mod capture;
+#[doc(hidden)]
+pub mod check;
+#[doc(hidden)]
+pub mod conflict;
+#[doc(hidden)]
+pub mod dump;
+#[doc(hidden)]
+pub mod fallback;
+#[doc(hidden)]
+pub mod fingerprint;
+#[cfg(any(test, feature = "fuzzing"))]
+#[doc(hidden)]
+pub mod fuzz;
+#[cfg(feature = "grpc")]
+#[doc(hidden)]
+pub mod grpc;
+#[doc(hidden)]
+pub mod ha;
+#[doc(hidden)]
+pub mod health;
+#[doc(hidden)]
+pub mod history;
+#[doc(hidden)]
+pub mod logging;
+#[doc(hidden)]
+pub mod lookup;
+#[doc(hidden)]
+pub mod mgmt;
+#[doc(hidden)]
+pub mod pd_routes;
+#[doc(hidden)]
+pub mod pool;
+#[doc(hidden)]
+pub mod rate_limit;
+#[doc(hidden)]
+pub mod relays;
+#[doc(hidden)]
+pub mod replay;
+#[doc(hidden)]
+pub mod reply_source;
+#[doc(hidden)]
+pub mod reservation_diff;
+#[cfg(feature = "reservation-source")]
+#[doc(hidden)]
+pub mod reservation_source;
+#[doc(hidden)]
+pub mod retransmit;
+#[doc(hidden)]
+pub mod schedule;
+#[doc(hidden)]
+pub mod server_id;
+#[doc(hidden)]
+pub mod shutdown;
+#[cfg(unix)]
+#[doc(hidden)]
+pub mod signal;
+#[doc(hidden)]
+pub mod snmp;
+#[doc(hidden)]
+pub mod subnets;
+#[doc(hidden)]
+pub mod websocket;
+
+pub use dhcproto;
+
+pub use crate::config::Config;
+pub use crate::opt82_cache::Opt82Cache;
+pub use crate::outcome::NoResponse;
+pub use crate::reservationdb::ReservationDb;
+pub use crate::types::Reservation;
+pub use crate::v4::handlers::DhcpV4Response;
+pub use crate::v6::handlers::DhcpV6Response;
+
+/// Work out the answer to a DHCPv4 message that arrived through a relay.
+///
+/// This is exactly what the v4 worker does with each packet after decoding
+/// it: relay checks, reservation lookup and building the Offer/Ack/Nak. The
+/// caller is left to encode and send [`DhcpV4Response::Message`].
+pub fn handle_v4_message(
+    config: &Config,
+    reservations: &ReservationDb,
+    leases: &Opt82Cache,
+    msg: &dhcproto::v4::Message,
+) -> DhcpV4Response {
+    v4::handlers::handle_message(reservations, leases, config, msg)
+}
+
+/// Work out the answer to a DHCPv6 client message and the Relay-forward it
+/// arrived in.
+///
+/// `msg` is the client message from the innermost Relay-forward in
+/// `relay_msg`. The response holds the bare Advertise/Reply; wrapping it in
+/// a Relay-reply is left to the caller, as it is in the v6 worker.
+pub fn handle_v6_message(
+    config: &Config,
+    reservations: &ReservationDb,
+    leases: &Opt82Cache,
+    msg: &dhcproto::v6::Message,
+    relay_msg: &dhcproto::v6::RelayMessage,
+) -> DhcpV6Response {
+    v6::handlers::handle_message(config, reservations, leases, msg, relay_msg)
+}
+
+/// Encode `msg` into `buf`, replacing what was there.
+///
+/// The workers keep one buffer each and encode every reply into it, so once
+/// the buffer has grown to fit the largest reply no further allocation is
+/// needed to put a reply on the wire. `Encodable::to_vec` allocates a fresh
+/// `Vec` every time.
+pub fn encode_into<T: dhcproto::Encodable>(
+    msg: &T,
+    buf: &mut Vec<u8>,
+) -> Result<(), dhcproto::error::EncodeError> {
+    buf.clear();
+    msg.encode(&mut dhcproto::Encoder::new(buf))
+}
@@ -1,11 +1,20 @@
-use std::io::IsTerminal;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
 
-use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::Rotation;
 use tracing_subscriber::{
-    filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt, Layer, Registry,
+    filter::{LevelFilter, Targets},
+    fmt,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer, Registry,
 };
 
-use crate::config::{FileLogConfig, LoggingConfig};
+use crate::analytics::events::now;
+use crate::audit::{open_append, rotated_files, rotated_path};
+use crate::config::{FileLogConfig, LogFormat, LogRotation, LoggingConfig};
 
 type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync + 'static>;
 
@@ -22,20 +31,22 @@ pub struct LogGuards {
 /// If no sink resolves to enabled, falls back to stdout so the process isn't
 /// silently deaf.
 pub fn init(cfg: &LoggingConfig) -> LogGuards {
-    let filter = LevelFilter::from_level(cfg.level);
+    let filter = Targets::new()
+        .with_default(LevelFilter::from_level(cfg.level))
+        .with_targets(cfg.modules.iter().cloned());
 
     let mut layers: Vec<BoxedLayer> = Vec::new();
     let mut stdout_guard: Option<WorkerGuard> = None;
     let mut file_guard: Option<WorkerGuard> = None;
 
     if cfg.stdout {
-        let (layer, guard) = stdout_layer(filter);
+        let (layer, guard) = stdout_layer(cfg.format, filter.clone());
         layers.push(layer);
         stdout_guard = Some(guard);
     }
 
     if let Some(file_cfg) = cfg.file.as_ref() {
-        match build_file_layer(file_cfg, filter) {
+        match build_file_layer(file_cfg, cfg.format, filter.clone()) {
             Ok((layer, guard)) => {
                 layers.push(layer);
                 file_guard = Some(guard);
@@ -51,7 +62,7 @@ pub fn init(cfg: &LoggingConfig) -> LogGuards {
 
     if layers.is_empty() {
         eprintln!("logging: no sinks enabled in `logging` block; falling back to stdout");
-        let (layer, guard) = stdout_layer(filter);
+        let (layer, guard) = stdout_layer(cfg.format, filter);
         layers.push(layer);
         stdout_guard = Some(guard);
     }
@@ -64,14 +75,38 @@ pub fn init(cfg: &LoggingConfig) -> LogGuards {
     }
 }
 
-/// TTY-aware stdout layer: pretty when attached to a terminal, JSON when piped.
+/// Stdout layer. With `auto` it is pretty when attached to a terminal and
+/// JSON when piped.
 ///
 /// The writer is non-blocking (and lossy past its buffer) so a stalled pipe —
 /// `| less` left paged, a dead supervisor — can't freeze DHCP responses.
-fn stdout_layer(filter: LevelFilter) -> (BoxedLayer, WorkerGuard) {
+fn stdout_layer(format: LogFormat, filter: Targets) -> (BoxedLayer, WorkerGuard) {
     let (writer, guard) = tracing_appender::non_blocking(std::io::stdout());
-    let layer = if std::io::stdout().is_terminal() {
+    let json = match format {
+        LogFormat::Auto => !std::io::stdout().is_terminal(),
+        LogFormat::Json => true,
+        LogFormat::Text => false,
+    };
+    (fmt_layer(writer, json, filter), guard)
+}
+
+fn build_file_layer(
+    cfg: &FileLogConfig,
+    format: LogFormat,
+    filter: Targets,
+) -> io::Result<(BoxedLayer, WorkerGuard)> {
+    let (writer, guard) = match cfg.rotation {
+        LogRotation::Daily => tracing_appender::non_blocking(rolling(cfg, Rotation::DAILY)?),
+        LogRotation::Hourly => tracing_appender::non_blocking(rolling(cfg, Rotation::HOURLY)?),
+        LogRotation::Size => tracing_appender::non_blocking(SizeRollingFile::open(cfg)?),
+    };
+    Ok((fmt_layer(writer, format != LogFormat::Text, filter), guard))
+}
+
+fn fmt_layer(writer: NonBlocking, json: bool, filter: Targets) -> BoxedLayer {
+    if json {
         fmt::layer()
+            .json()
             .with_writer(writer)
             .with_target(true)
             .with_thread_names(true)
@@ -79,20 +114,19 @@ fn stdout_layer(filter: LevelFilter) -> (BoxedLayer, WorkerGuard) {
             .boxed()
     } else {
         fmt::layer()
-            .json()
             .with_writer(writer)
             .with_target(true)
             .with_thread_names(true)
             .with_filter(filter)
             .boxed()
-    };
-    (layer, guard)
+    }
 }
 
-fn build_file_layer(
+/// Time-based rotation: the date (and hour) is appended to each file name
+fn rolling(
     cfg: &FileLogConfig,
-    filter: LevelFilter,
-) -> std::io::Result<(BoxedLayer, WorkerGuard)> {
+    rotation: Rotation,
+) -> io::Result<tracing_appender::rolling::RollingFileAppender> {
     let dir = cfg
         .path
         .parent()
@@ -101,22 +135,118 @@ fn build_file_layer(
     let file_name = cfg
         .path
         .file_name()
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "missing filename"))?;
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing filename"))?;
 
-    let appender = tracing_appender::rolling::Builder::new()
-        .rotation(tracing_appender::rolling::Rotation::DAILY)
+    tracing_appender::rolling::Builder::new()
+        .rotation(rotation)
         .filename_prefix(file_name.to_string_lossy().as_ref())
         .max_log_files(cfg.max_files)
         .build(dir)
-        .map_err(std::io::Error::other)?;
-
-    let (writer, guard) = tracing_appender::non_blocking(appender);
-    let layer = fmt::layer()
-        .json()
-        .with_writer(writer)
-        .with_target(true)
-        .with_thread_names(true)
-        .with_filter(filter)
-        .boxed();
-    Ok((layer, guard))
+        .map_err(io::Error::other)
+}
+
+/// Size-based rotation, named like the audit log: the active file keeps the
+/// configured path and a full one is renamed with a millisecond timestamp
+/// suffix. Runs on the non-blocking worker thread, so it reports problems on
+/// stderr rather than through `tracing`.
+struct SizeRollingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRollingFile {
+    fn open(cfg: &FileLogConfig) -> io::Result<Self> {
+        let file = open_append(&cfg.path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: cfg.path.clone(),
+            max_bytes: cfg.max_bytes,
+            max_files: cfg.max_files,
+            file,
+            written,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut stamp = now();
+        let mut rotated = rotated_path(&self.path, stamp);
+        while rotated.exists() {
+            stamp += 1;
+            rotated = rotated_path(&self.path, stamp);
+        }
+        std::fs::rename(&self.path, &rotated)?;
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        if let Err(e) = self.prune() {
+            eprintln!("logging: unable to remove old log files: {e}");
+        }
+        Ok(())
+    }
+
+    /// `max_files` counts the active file, as it does for time rotation
+    fn prune(&self) -> io::Result<()> {
+        let keep = self.max_files.saturating_sub(1);
+        let mut rotated = rotated_files(&self.path)?;
+        if rotated.len() <= keep {
+            return Ok(());
+        }
+        rotated.sort_unstable_by_key(|(stamp, _)| *stamp);
+        let excess = rotated.len() - keep;
+        for (_, path) in rotated.into_iter().take(excess) {
+            std::fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            if let Err(e) = self.rotate() {
+                eprintln!("logging: unable to rotate {}: {e}", self.path.display());
+            }
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_rotation_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("shadowdhcp-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shadowdhcp.log");
+        let line = b"0123456789abcdef\n";
+        let mut file = SizeRollingFile::open(&FileLogConfig {
+            path: path.clone(),
+            max_files: 3,
+            rotation: LogRotation::Size,
+            // two lines per file
+            max_bytes: line.len() as u64 * 2,
+        })
+        .unwrap();
+
+        for _ in 0..9 {
+            file.write_all(line).unwrap();
+        }
+
+        // 9 lines: four full files rotated, all but the newest two pruned
+        assert_eq!(rotated_files(&path).unwrap().len(), 2);
+        let current = std::fs::read(&path).unwrap();
+        assert_eq!(current.len(), line.len());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,201 @@
+//! `shadowdhcp lookup`: which reservation a client would get, worked out
+//! from the files on disk. Each lookup the workers would try is listed in
+//! order with its result, so it shows not only what matched but why the
+//! earlier steps didn't.
+
+use std::sync::Arc;
+
+use advmac::MacAddr6;
+
+use crate::analytics::events::ReservationMatch;
+use crate::config::Config;
+use crate::pool::PoolAllocator;
+use crate::reservationdb::ReservationDb;
+use crate::types::{Duid, Option82, Reservation};
+use crate::v4::extractors::CircuitId;
+
+/// What is known about the client
+#[derive(Default)]
+pub struct Query {
+    pub mac: Option<MacAddr6>,
+    pub duid: Option<Duid>,
+    pub option82: Option<Option82>,
+}
+
+/// One lookup the worker would try
+pub struct Step {
+    pub description: String,
+    pub found: Option<Arc<Reservation>>,
+}
+
+pub struct Outcome {
+    pub steps: Vec<Step>,
+    pub matched: Option<(Arc<Reservation>, ReservationMatch)>,
+}
+
+impl Outcome {
+    fn new() -> Self {
+        Self {
+            steps: Vec::new(),
+            matched: None,
+        }
+    }
+
+    /// Record a step, keeping the first match
+    fn step(
+        &mut self,
+        description: String,
+        found: Option<Arc<Reservation>>,
+        method: ReservationMatch,
+    ) {
+        if let (None, Some(res)) = (&self.matched, &found) {
+            self.matched = Some((res.clone(), method));
+        }
+        self.steps.push(Step { description, found });
+    }
+}
+
+/// DHCPv4 order: MAC from chaddr, then the VLAN tags in the Circuit-ID, then
+/// each Option 82 extractor, then the Option 82 pools. Pools are only peeked at, nothing is assigned.
+pub fn lookup_v4(
+    config: &Config,
+    reservations: &ReservationDb,
+    pools: Option<&PoolAllocator>,
+    query: &Query,
+) -> Outcome {
+    let mut outcome = Outcome::new();
+    if let Some(mac) = query.mac {
+        outcome.step(
+            format!("mac {mac}"),
+            reservations.by_mac(mac),
+            ReservationMatch::mac("chaddr"),
+        );
+    }
+    let Some(option) = &query.option82 else {
+        return outcome;
+    };
+    if let Some(vlans) = option
+        .circuit
+        .as_deref()
+        .and_then(CircuitId::parse)
+        .and_then(|circuit| circuit.vlans)
+    {
+        outcome.step(
+            format!("vlans {vlans}"),
+            reservations.by_vlans(vlans),
+            ReservationMatch::vlans(),
+        );
+    }
+    for &(name, extractor) in &config.option82_extractors {
+        match extractor(option) {
+            Some(key) => outcome.step(
+                format!("option82 extractor {name}: {}", describe(&key)),
+                reservations.by_opt82(&key),
+                ReservationMatch::option82(name),
+            ),
+            None => outcome.steps.push(Step {
+                description: format!("option82 extractor {name}: no key"),
+                found: None,
+            }),
+        }
+    }
+    if let Some((pool, extractor, assigned)) = pools.and_then(|pools| pools.peek(option)) {
+        let description = match assigned {
+            Some(_) => format!("option82 pool `{pool}`: already assigned"),
+            None => format!("option82 pool `{pool}`: would assign new addresses"),
+        };
+        outcome.step(description, assigned, ReservationMatch::pool(extractor));
+    }
+    outcome
+}
+
+/// DHCPv6 order for what can be known offline: DUID, then MAC. Option 18/37
+/// keys and the bindings learned from earlier leases only exist at runtime.
+pub fn lookup_v6(reservations: &ReservationDb, query: &Query) -> Outcome {
+    let mut outcome = Outcome::new();
+    if let Some(duid) = &query.duid {
+        outcome.step(
+            format!("duid {duid}"),
+            reservations.by_duid(duid),
+            ReservationMatch::duid(),
+        );
+    }
+    if let Some(mac) = query.mac {
+        outcome.step(
+            format!("mac {mac}"),
+            reservations.by_mac(mac),
+            ReservationMatch::mac("mac_extractors"),
+        );
+    }
+    outcome
+}
+
+fn describe(key: &Option82) -> String {
+    serde_json::to_string(key).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn reservation(ipv4: Ipv4Addr, mac: Option<MacAddr6>, remote: Option<&str>) -> Reservation {
+        Reservation {
+            ipv4: Some(ipv4),
+            ipv6_na: Some("2001:db8::1".parse().unwrap()),
+            ipv6_pd: "2001:db8:100::/56".parse().unwrap(),
+            mac,
+            duid: None,
+            client_id: None,
+            option82: remote.map(|remote| Option82 {
+                circuit: None,
+                remote: Some(remote.into()),
+                subscriber: None,
+            }),
+            option1837: None,
+            svlan: None,
+            cvlan: None,
+            subscriber_id: None,
+            static_routes: vec![],
+            boot: None,
+            profile: None,
+            activate_at: None,
+            deactivate_at: None,
+            suspended: false,
+        }
+    }
+
+    #[test]
+    fn v4_falls_through_mac_to_option82_extractor() {
+        let config = Config {
+            option82_extractors: vec![(
+                "remote_only",
+                crate::v4::extractors::remote_only as crate::v4::extractors::Option82ExtractorFn,
+            )],
+            ..Default::default()
+        };
+        let db = ReservationDb::new();
+        db.insert(reservation(
+            Ipv4Addr::new(100, 64, 0, 2),
+            None,
+            Some("olt1:1"),
+        ));
+        let query = Query {
+            mac: Some(MacAddr6::new([0x02, 0, 0, 0, 0, 1])),
+            duid: None,
+            option82: Some(Option82 {
+                circuit: Some("eth0/1".into()),
+                remote: Some("olt1:1".into()),
+                subscriber: None,
+            }),
+        };
+
+        let outcome = lookup_v4(&config, &db, None, &query);
+        assert_eq!(outcome.steps.len(), 2);
+        assert!(outcome.steps[0].found.is_none());
+        let (res, method) = outcome.matched.unwrap();
+        assert_eq!(res.ipv4, Some(Ipv4Addr::new(100, 64, 0, 2)));
+        assert_eq!(method.method, "option82");
+        assert_eq!(method.extractor, Some("remote_only"));
+    }
+}
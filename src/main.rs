@@ -1,39 +1,51 @@
 use std::{
-    io,
-    net::{SocketAddr, TcpListener, UdpSocket},
-    path::PathBuf,
-    sync::{atomic::AtomicU64, mpsc, Arc},
+    io::{self, BufRead, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize},
+        mpsc, Arc,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use arc_swap::ArcSwap;
+use compact_str::CompactString;
 
-use crate::analytics::EventSenders;
-use crate::config::Config;
-use crate::opt82_cache::Opt82Cache;
-use crate::reservationdb::ReservationDb;
-use crate::v4::extractors;
-use crate::{analytics::events::DhcpEvent, types::Reservation};
-
-mod analytics;
-mod config;
-mod logging;
-mod mgmt;
-mod opt82_cache;
-mod reservationdb;
-mod shutdown;
+use shadowdhcp::analytics::watch::EventWatchers;
+use shadowdhcp::analytics::EventSenders;
+use shadowdhcp::audit::{AuditLog, AuditRecord};
+use shadowdhcp::capture::PacketCapture;
+use shadowdhcp::config::{Config, ProtocolOverrides, V4_BIND_ENV, V6_BIND_ENV};
+#[cfg(feature = "grpc")]
+use shadowdhcp::grpc;
+use shadowdhcp::health::{Health, ProtocolHealth};
+use shadowdhcp::opt82_cache::Opt82Cache;
+use shadowdhcp::pd_routes::PdUpdate;
+use shadowdhcp::reply_source::ReplySource;
+#[cfg(feature = "reservation-source")]
+use shadowdhcp::reservation_source;
+use shadowdhcp::reservationdb::ReservationDb;
 #[cfg(unix)]
-mod signal;
-mod types;
-mod v4;
-mod v6;
+use shadowdhcp::signal;
+use shadowdhcp::v4::extractors;
+use shadowdhcp::{
+    alerts, analytics, audit, check, config, ha, logging, lookup, mgmt, opt82_cache, pd_routes,
+    pool, replay, reply_source, reservation_diff, schedule, server_id, shutdown, snmp, v4, v6,
+};
+use shadowdhcp::{
+    analytics::events::DhcpEvent,
+    types::{parse_mac, Duid, Option82, Reservation},
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const GITHUB_SHA: Option<&str> = option_env!("GITHUB_SHA");
 
 fn main() {
     let mut args = pico_args::Arguments::from_env();
+    let subcommand = args.subcommand().expect("Parsing subcommand");
     if args.contains(["-h", "--help"]) {
         print!("{}", HELP);
         return;
@@ -65,10 +77,73 @@ fn main() {
         return;
     }
 
+    let check_only = args.contains("--check");
     let config_dir: PathBuf = args
         .opt_value_from_str("--configdir")
         .expect("Parsing option --configdir")
         .unwrap_or_else(|| PathBuf::from("."));
+    let overrides = ProtocolOverrides {
+        enable_v4: args
+            .opt_value_from_str("--enable-v4")
+            .expect("Parsing option --enable-v4"),
+        enable_v6: args
+            .opt_value_from_str("--enable-v6")
+            .expect("Parsing option --enable-v6"),
+        v4_bind: env_value(V4_BIND_ENV),
+        v6_bind: env_value(V6_BIND_ENV),
+    };
+
+    let command = match subcommand.as_deref() {
+        None => None,
+        Some("lookup") => match lookup_query(&mut args) {
+            Ok(query) => Some(Command::Lookup(query)),
+            Err(e) => {
+                eprintln!("{e}\n Run `shadowdhcp --help` for usage");
+                std::process::exit(1);
+            }
+        },
+        Some("leases") => Some(Command::Leases {
+            csv: args.contains("--csv"),
+        }),
+        Some("snmp") => {
+            let base = args
+                .opt_value_from_str::<_, String>("--base-oid")
+                .expect("Parsing option --base-oid")
+                .unwrap_or_else(|| snmp::DEFAULT_BASE.to_string());
+            match snmp::parse_oid(&base) {
+                Some(base) => Some(Command::Snmp { base }),
+                None => {
+                    eprintln!("Invalid --base-oid `{base}`\n Run `shadowdhcp --help` for usage");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some("diff-reservations") => {
+            match (
+                args.free_from_str::<PathBuf>(),
+                args.free_from_str::<PathBuf>(),
+            ) {
+                (Ok(old), Ok(new)) => Some(Command::DiffReservations { old, new }),
+                _ => {
+                    eprintln!(
+                        "diff-reservations needs an old and a new reservations file\n Run `shadowdhcp --help` for usage"
+                    );
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some("replay") => match args.free_from_str::<PathBuf>() {
+            Ok(path) => Some(Command::Replay(path)),
+            Err(_) => {
+                eprintln!("replay needs a pcap file\n Run `shadowdhcp --help` for usage");
+                std::process::exit(1);
+            }
+        },
+        Some(other) => {
+            eprintln!("Unknown command `{other}`\n Run `shadowdhcp --help` for usage");
+            std::process::exit(1);
+        }
+    };
 
     // Check for any remaining unused arguments
     let remaining = args.finish();
@@ -80,9 +155,23 @@ fn main() {
         std::process::exit(1);
     }
 
+    if check_only {
+        std::process::exit(run_check(&config_dir, overrides));
+    }
+    match command {
+        Some(Command::Lookup(query)) => std::process::exit(run_lookup(&config_dir, &query)),
+        Some(Command::Replay(path)) => std::process::exit(run_replay(&config_dir, &path)),
+        Some(Command::Leases { csv }) => std::process::exit(run_leases(&config_dir, csv)),
+        Some(Command::Snmp { base }) => std::process::exit(run_snmp(&config_dir, &base)),
+        Some(Command::DiffReservations { old, new }) => {
+            std::process::exit(run_diff_reservations(&config_dir, &old, &new))
+        }
+        None => (),
+    }
+
     let shutdown = shutdown::Shutdown::new();
 
-    let config = match Config::load_from_files(&config_dir) {
+    let config = match Config::load_with_overrides(&config_dir, overrides) {
         Ok(c) => c,
         Err(e) => {
             eprintln!("Unable to load config file: {e}");
@@ -91,6 +180,24 @@ fn main() {
     };
     // The guards flush buffered file logs when they drop at the end of main.
     let _log_guards = logging::init(&config.logging);
+    if config.generated_server_ids {
+        let v4 = (!config.v4_server_id.is_unspecified()).then_some(config.v4_server_id);
+        let v6 = &config.v6_server_id;
+        match server_id::write_ids(&config_dir, v4, v6) {
+            Ok(()) => tracing::info!(
+                v4 = ?v4,
+                %v6,
+                "No ids.json found, generated server ids and saved them to {}",
+                config_dir.join("ids.json").display()
+            ),
+            Err(e) => tracing::warn!(
+                v4 = ?v4,
+                %v6,
+                "No ids.json found and the generated server ids couldn't be saved, \
+                 so they will change on restart: {e}"
+            ),
+        }
+    }
     let config = Arc::new(ArcSwap::from_pointee(config));
 
     let reservations_path = config_dir.join("reservations.json");
@@ -113,15 +220,72 @@ fn main() {
     };
     tracing::info!("Loaded {} reservations", reservations.len());
 
-    let db = ReservationDb::new();
-    db.load_reservations(reservations);
+    let option82_pools = config.load().option82_pools.clone();
+    let pools = (!option82_pools.is_empty()).then(|| {
+        let path = config_dir.join("pool_assignments.json");
+        match pool::PoolAllocator::open(&option82_pools, path.clone()) {
+            Ok(pools) => Arc::new(pools),
+            Err(e) => {
+                eprintln!("Failed to load {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let db = ReservationDb::with_na_template(config.load().v6_na_template.clone())
+        .with_pools(pools.clone())
+        .with_relay_cache(config.load().relay_cache_size);
+    if let Err(e) = db.load_reservations(reservations) {
+        eprintln!("Failed to load {}: {e}", reservations_path.display());
+        std::process::exit(1);
+    }
     let db = Arc::new(ArcSwap::from_pointee(db));
-    let leases = Arc::new(Opt82Cache::new());
+
+    let ha_config = config.load().ha.clone();
+    let (leases, ha_rx) = match &ha_config {
+        Some(_) => {
+            let (tx, rx) = mpsc::sync_channel(ha::REPLICATION_QUEUE);
+            (Opt82Cache::with_replication(tx), Some(rx))
+        }
+        None => (Opt82Cache::new(), None),
+    };
+    let leases = Arc::new(leases);
+    let ha_monitor = ha_config
+        .as_ref()
+        .map(|cfg| Arc::new(ha::PeerMonitor::new(cfg)));
 
     let loaded_config = config.load();
     let events_address = loaded_config.events.tcp;
+    let tcp_options = analytics::writer::TcpOptions {
+        #[cfg(feature = "tcp-tls")]
+        tls: loaded_config.events.tcp_tls.as_ref().map(|cfg| {
+            analytics::writer::TlsConnector::new(cfg).unwrap_or_else(|e| {
+                eprintln!("Invalid events.tcp_tls: {e}");
+                std::process::exit(1);
+            })
+        }),
+        token: loaded_config.events.tcp_token.clone(),
+        spool: loaded_config.events.tcp_spool.clone(),
+    };
     let mgmt_address = loaded_config.mgmt_address;
+    #[cfg(feature = "grpc")]
+    let grpc_address = loaded_config.grpc_address;
+    #[cfg(not(feature = "grpc"))]
+    if loaded_config.grpc_address.is_some() {
+        tracing::warn!(
+            "grpc_address is configured but this binary was built without the \
+             `grpc` feature; the gRPC service will not be started"
+        );
+    }
     let events_queue_size = loaded_config.events.queue_size;
+    let events_filter = loaded_config.events.filter.clone();
+    let syslog_config = loaded_config.logging.syslog.clone();
+    let audit_config = loaded_config.audit.clone();
+    let pd_routes_config = loaded_config.pd_routes.clone();
+    let capture = Arc::new(match &loaded_config.capture {
+        Some(cfg) => PacketCapture::new(cfg.depth, &cfg.macs),
+        None => PacketCapture::new(config::default_capture_depth(), &[]),
+    });
 
     #[cfg(feature = "clickhouse")]
     let clickhouse_config = loaded_config.events.clickhouse.clone();
@@ -132,38 +296,172 @@ fn main() {
              `clickhouse` feature; events will not be shipped to ClickHouse"
         );
     }
+
+    // Falling back to plaintext would put the events, and the token, on the
+    // wire unencrypted
+    #[cfg(not(feature = "tcp-tls"))]
+    let events_address = match events_address {
+        Some(_) if loaded_config.events.tcp_tls.is_some() => {
+            tracing::warn!(
+                "events.tcp_tls is configured but this binary was built without the \
+                 `tcp-tls` feature; events will not be sent to events.tcp"
+            );
+            None
+        }
+        address => address,
+    };
+
+    #[cfg(feature = "reservation-source")]
+    let reservation_source = loaded_config.reservation_source.clone();
+    #[cfg(not(feature = "reservation-source"))]
+    if loaded_config.reservation_source.is_some() {
+        tracing::warn!(
+            "reservation_source is configured but this binary was built without the \
+             `reservation-source` feature; reservations will only be read from disk"
+        );
+    }
     drop(loaded_config);
 
-    let mut senders = EventSenders::new();
-    let tcp_rx: Option<(mpsc::Receiver<DhcpEvent>, Arc<AtomicU64>)> = events_address.map(|_| {
+    let mut senders = EventSenders::new().with_filter(&events_filter);
+    let tcp_rx = events_address.map(|_| {
         let (tx, rx) = mpsc::sync_channel::<DhcpEvent>(events_queue_size);
-        let dropped = Arc::new(AtomicU64::new(0));
-        senders.push(tx, dropped.clone());
-        (rx, dropped)
+        let (dropped, queued) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicUsize::new(0)));
+        senders.push(tx, dropped.clone(), queued.clone());
+        (rx, dropped, queued)
     });
     #[cfg(feature = "clickhouse")]
-    let clickhouse_rx: Option<(mpsc::Receiver<DhcpEvent>, Arc<AtomicU64>)> =
-        clickhouse_config.as_ref().map(|_| {
-            let (tx, rx) = mpsc::sync_channel::<DhcpEvent>(events_queue_size);
-            let dropped = Arc::new(AtomicU64::new(0));
-            senders.push(tx, dropped.clone());
-            (rx, dropped)
-        });
+    let clickhouse_rx = clickhouse_config.as_ref().map(|_| {
+        let (tx, rx) = mpsc::sync_channel::<DhcpEvent>(events_queue_size);
+        let (dropped, queued) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicUsize::new(0)));
+        senders.push(tx, dropped.clone(), queued.clone());
+        (rx, dropped, queued)
+    });
+    let syslog_rx = syslog_config.as_ref().map(|_| {
+        let (tx, rx) = mpsc::sync_channel::<DhcpEvent>(events_queue_size);
+        let (dropped, queued) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicUsize::new(0)));
+        senders.push(tx, dropped.clone(), queued.clone());
+        (rx, dropped, queued)
+    });
+    // Only fed while the management listener or gRPC service can subscribe
+    #[cfg(feature = "grpc")]
+    let watched = mgmt_address.is_some() || grpc_address.is_some();
+    #[cfg(not(feature = "grpc"))]
+    let watched = mgmt_address.is_some();
+    let watchers = watched.then(|| {
+        let (tx, rx) = mpsc::sync_channel::<DhcpEvent>(events_queue_size);
+        let (dropped, queued) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicUsize::new(0)));
+        senders.push(tx, dropped.clone(), queued.clone());
+        (EventWatchers::default(), rx, dropped, queued)
+    });
+    let health = Arc::new(Health {
+        events_queued: senders.queue_depth(),
+        ..Default::default()
+    });
     let senders = if senders.is_empty() {
         None
     } else {
         Some(senders)
     };
 
+    // Open the audit log up front: running without the record regulators
+    // asked for is worse than not starting.
+    let audit_log = audit_config.map(|cfg| match AuditLog::open(&cfg) {
+        Ok(log) => {
+            tracing::info!("Writing audit log to {}", cfg.path.display());
+            log
+        }
+        Err(e) => {
+            eprintln!("Failed to open audit log {}: {e}", cfg.path.display());
+            std::process::exit(1);
+        }
+    });
+    let (audit_tx, audit_rx) = match &audit_log {
+        Some(_) => {
+            let (tx, rx) = mpsc::sync_channel::<AuditRecord>(audit::AUDIT_QUEUE);
+            (Some(tx), Some(rx))
+        }
+        None => (None, None),
+    };
+    let (pd_routes_tx, pd_routes_rx) = match &pd_routes_config {
+        Some(_) => {
+            let (tx, rx) = mpsc::sync_channel::<PdUpdate>(pd_routes::PD_ROUTES_QUEUE);
+            (Some(tx), Some(rx))
+        }
+        None => (None, None),
+    };
+
+    #[cfg(unix)]
+    let workers = config.load().workers;
+    #[cfg(not(unix))]
+    let workers = match config.load().workers {
+        1 => 1,
+        _ => {
+            tracing::warn!("workers > 1 needs SO_REUSEPORT, which this platform lacks; using 1");
+            1
+        }
+    };
+
     // Bind sockets before spawning threads - fail fast if any fails
-    let v4_socket = bind_udp_socket(config.load().v4_bind_address, "DHCPv4");
-    let v6_socket = bind_udp_socket(config.load().v6_bind_address, "DHCPv6");
+    let v4_sockets = bind_udp_sockets(&config.load().v4_bind_addresses, "DHCPv4", workers);
+    let v6_sockets = bind_udp_sockets(&config.load().v6_bind_addresses, "DHCPv6", workers);
+    health.v4.set_sockets(v4_sockets.len());
+    health.v6.set_sockets(v6_sockets.len());
+    bind_interface(
+        &v4_sockets,
+        config.load().v4_bind_interface.as_deref(),
+        "DHCPv4",
+    );
+    bind_interface(
+        &v6_sockets,
+        config.load().v6_bind_interface.as_deref(),
+        "DHCPv6",
+    );
+    // Kept for binding a fresh socket when a worker is restarted
+    let v4_bind_interface = config.load().v4_bind_interface.clone();
+    let v6_bind_interface = config.load().v6_bind_interface.clone();
+    let v4_reply_source = config.load().v4_reply_source.map(ReplySource::V4);
+    let v6_reply_source = config.load().v6_reply_source.map(|addr| {
+        ReplySource::v6(addr, config.load().v6_bind_interface.as_deref()).unwrap_or_else(|e| {
+            eprintln!("Unable to use v6_reply_source {addr}: {e}");
+            std::process::exit(1);
+        })
+    });
     let mgmt_listener = mgmt_address.map(|addr| bind_tcp_socket(addr, "management"));
-    tracing::info!("Bound DHCPv4 to {}", config.load().v4_bind_address);
-    tracing::info!("Bound DHCPv6 to {}", config.load().v6_bind_address);
+    #[cfg(feature = "grpc")]
+    let grpc_listener = grpc_address.map(|addr| bind_tcp_socket(addr, "gRPC"));
+    let ha_listener = ha_config
+        .as_ref()
+        .map(|cfg| bind_tcp_socket(cfg.listen, "HA"));
+    for addr in &config.load().v4_bind_addresses {
+        tracing::info!("Bound DHCPv4 to {addr} ({workers} workers)");
+    }
+    for addr in &config.load().v6_bind_addresses {
+        tracing::info!("Bound DHCPv6 to {addr} ({workers} workers)");
+    }
+    if !config.load().enable_v4 {
+        tracing::info!("DHCPv4 is disabled");
+    }
+    if !config.load().enable_v6 {
+        tracing::info!("DHCPv6 is disabled");
+    }
+    for source in v4_reply_source.iter().chain(&v6_reply_source) {
+        tracing::info!("Sending replies from {source}");
+    }
     if let Some(addr) = mgmt_address {
         tracing::info!("Bound management to {}", addr);
     }
+    #[cfg(feature = "grpc")]
+    if let Some(addr) = grpc_address {
+        tracing::info!("Bound gRPC to {addr}");
+    }
+    if let Some(cfg) = &ha_config {
+        tracing::info!(
+            "Bound HA to {} ({:?}, peer {})",
+            cfg.listen,
+            cfg.role,
+            cfg.peer
+        );
+    }
 
     // Spawn signal handler (Unix only, before thread::scope; it exits on its
     // own after signalling shutdown). SIGHUP reloads reservations,
@@ -177,15 +475,48 @@ fn main() {
     // on shutdown. Safe even mid-request — reservation persistence is an
     // atomic write+rename — and keeps blocking accept, so management
     // clients see no polling latency.
-    if let Some(listener) = mgmt_listener {
-        let mgmt_db = db.clone();
+    if let (Some(listener), Some((watchers, ..))) = (mgmt_listener, &watchers) {
+        let state = mgmt::State {
+            reservations: db.clone(),
+            capture: capture.clone(),
+            leases: leases.clone(),
+            config: config.clone(),
+            health: health.clone(),
+            watchers: watchers.clone(),
+        };
         let mgmt_config_dir = config_dir.clone();
         thread::Builder::new()
             .name("mgmt".to_string())
-            .spawn(move || mgmt::listener(listener, mgmt_db, mgmt_config_dir))
+            .spawn(move || mgmt::listener(listener, state, mgmt_config_dir))
             .expect("mgmt spawn");
     }
 
+    // Detached like the management listener, and for the same reasons
+    #[cfg(feature = "grpc")]
+    if let (Some(listener), Some((watchers, ..))) = (grpc_listener, &watchers) {
+        let service = grpc::ManagementService {
+            reservations: db.clone(),
+            leases: leases.clone(),
+            config_dir: config_dir.clone(),
+            watchers: watchers.clone(),
+        };
+        thread::Builder::new()
+            .name("grpc".to_string())
+            .spawn(move || grpc::serve(listener, service))
+            .expect("grpc spawn");
+    }
+
+    // The HA listener is detached for the same reason as the management
+    // listener: it blocks in accept() and has nothing to flush on exit.
+    if let (Some(listener), Some(cfg), Some(monitor)) = (ha_listener, &ha_config, &ha_monitor) {
+        let (ha_leases, ha_monitor) = (leases.clone(), monitor.clone());
+        let (peer, read_timeout) = (cfg.peer.ip(), Duration::from_secs(cfg.failover_secs * 2));
+        thread::Builder::new()
+            .name("ha-listener".to_string())
+            .spawn(move || ha::listener(listener, peer, ha_leases, ha_monitor, read_timeout))
+            .expect("ha-listener spawn");
+    }
+
     // `thread::scope` auto-joins every spawned thread when the closure
     // returns and re-raises any panic. Every thread watches `shutdown`
     // (directly or via its channel disconnecting), so on SIGTERM the scope
@@ -193,65 +524,256 @@ fn main() {
     thread::scope(|s| {
         let cleanup_leases = leases.clone();
         let cleanup_db = db.clone();
+        let cleanup_sinks = senders.clone();
         let cleanup_shutdown = shutdown.clone();
+        let cleanup_monitor = ha_monitor.clone();
         thread::Builder::new()
             .name("opt82-cleanup".to_string())
             .spawn_scoped(s, move || {
-                while !cleanup_shutdown.wait_timeout(Duration::from_hours(1)) {
-                    cleanup_leases.evict_expired(Duration::from_hours(24), &cleanup_db.load());
+                // Expired leases are looked for every minute, so their events
+                // aren't long behind, and stale bindings every hour. A
+                // standby holds the primary's leases too, but leaves their
+                // events to the primary.
+                let mut minutes = 0u32;
+                while !cleanup_shutdown.wait_timeout(Duration::from_mins(1)) {
+                    let expired = cleanup_leases.expire_leases();
+                    let answering = cleanup_monitor.as_ref().is_none_or(|m| m.should_answer());
+                    if let (Some(sinks), true) = (&cleanup_sinks, answering) {
+                        for event in expired {
+                            sinks.send(DhcpEvent::LeaseExpired(event));
+                        }
+                    }
+                    minutes += 1;
+                    if minutes % 60 == 0 {
+                        cleanup_leases.evict_expired(Duration::from_hours(24), &cleanup_db.load());
+                    }
                 }
             })
             .expect("opt82-cleanup spawn");
 
-        let (v4db, v4leases, v4config, v4sinks, v4shutdown) = (
-            db.clone(),
-            leases.clone(),
-            config.clone(),
-            senders.clone(),
-            shutdown.clone(),
-        );
-        thread::Builder::new()
-            .name("v4worker".to_string())
-            .spawn_scoped(s, move || {
-                v4::v4_worker(v4_socket, v4db, v4leases, v4config, v4sinks, v4shutdown)
-            })
-            .expect("v4worker spawn");
-
-        let (v6db, v6leases, v6config, v6sinks, v6shutdown) = (
-            db.clone(),
-            leases.clone(),
-            config.clone(),
-            senders.clone(),
-            shutdown.clone(),
-        );
+        if let Some(pools) = pools {
+            let pool_shutdown = shutdown.clone();
+            thread::Builder::new()
+                .name("pool-persist".to_string())
+                .spawn_scoped(s, move || pool::persister(pools, pool_shutdown))
+                .expect("pool-persist spawn");
+        }
+
+        let (schedule_db, schedule_shutdown) = (db.clone(), shutdown.clone());
         thread::Builder::new()
-            .name("v6worker".to_string())
-            .spawn_scoped(s, move || {
-                v6::v6_worker(v6_socket, v6db, v6leases, v6config, v6sinks, v6shutdown)
-            })
-            .expect("v6worker spawn");
+            .name("reservation-schedule".to_string())
+            .spawn_scoped(s, move || schedule::logger(schedule_db, schedule_shutdown))
+            .expect("reservation-schedule spawn");
+
+        if config.load().alerts.is_some() {
+            let (
+                alerts_config,
+                alerts_db,
+                alerts_leases,
+                alerts_health,
+                alerts_sinks,
+                alerts_shutdown,
+            ) = (
+                config.clone(),
+                db.clone(),
+                leases.clone(),
+                health.clone(),
+                senders.clone(),
+                shutdown.clone(),
+            );
+            thread::Builder::new()
+                .name("alerts".to_string())
+                .spawn_scoped(s, move || {
+                    alerts::monitor(
+                        alerts_config,
+                        alerts_db,
+                        alerts_leases,
+                        alerts_health,
+                        alerts_sinks,
+                        alerts_shutdown,
+                    )
+                })
+                .expect("alerts spawn");
+        }
+
+        #[cfg(feature = "reservation-source")]
+        if let Some(cfg) = reservation_source {
+            let (pull_db, pull_config_dir, pull_shutdown) =
+                (db.clone(), config_dir.clone(), shutdown.clone());
+            thread::Builder::new()
+                .name("reservations-pull".to_string())
+                .spawn_scoped(s, move || {
+                    reservation_source::puller(cfg, pull_db, pull_config_dir, pull_shutdown)
+                })
+                .expect("reservations-pull spawn");
+        }
+
+        if let (Some(cfg), Some(rx)) = (&ha_config, ha_rx) {
+            let (peer, heartbeat, ha_shutdown) = (
+                cfg.peer,
+                Duration::from_secs(cfg.heartbeat_secs),
+                shutdown.clone(),
+            );
+            thread::Builder::new()
+                .name("ha-sender".to_string())
+                .spawn_scoped(s, move || ha::sender(peer, rx, heartbeat, ha_shutdown))
+                .expect("ha-sender spawn");
+        }
 
-        // Only the workers hold event senders from here on, so once they
-        // exit the writers see their channels disconnect and drain.
+        let worker_count = v4_sockets.len();
+        for (i, (v4_addr, v4_socket)) in v4_sockets.into_iter().enumerate() {
+            let (v4db, v4leases, v4config, v4sinks, v4audit, v4capture, v4ha, v4shutdown) = (
+                db.clone(),
+                leases.clone(),
+                config.clone(),
+                senders.clone(),
+                audit_tx.clone(),
+                capture.clone(),
+                ha_monitor.clone(),
+                shutdown.clone(),
+            );
+            let (v4health, v4_bind_interface) = (health.clone(), &v4_bind_interface);
+            thread::Builder::new()
+                .name(worker_name("v4worker", i, worker_count))
+                .spawn_scoped(s, move || {
+                    supervise(
+                        "DHCPv4",
+                        v4_socket,
+                        || rebind_udp_socket(v4_addr, workers > 1, v4_bind_interface.as_deref()),
+                        &v4health.v4,
+                        &v4shutdown,
+                        |socket| {
+                            v4::v4_worker(
+                                socket,
+                                v4db.clone(),
+                                v4leases.clone(),
+                                v4config.clone(),
+                                v4sinks.clone(),
+                                v4audit.clone(),
+                                v4capture.clone(),
+                                v4ha.clone(),
+                                v4_reply_source,
+                                v4health.clone(),
+                                v4shutdown.clone(),
+                            )
+                        },
+                    )
+                })
+                .expect("v4worker spawn");
+        }
+
+        let worker_count = v6_sockets.len();
+        for (i, (v6_addr, v6_socket)) in v6_sockets.into_iter().enumerate() {
+            let (v6db, v6leases, v6config, v6sinks, v6audit, v6routes, v6capture, v6ha, v6shutdown) = (
+                db.clone(),
+                leases.clone(),
+                config.clone(),
+                senders.clone(),
+                audit_tx.clone(),
+                pd_routes_tx.clone(),
+                capture.clone(),
+                ha_monitor.clone(),
+                shutdown.clone(),
+            );
+            let (v6health, v6_bind_interface) = (health.clone(), &v6_bind_interface);
+            thread::Builder::new()
+                .name(worker_name("v6worker", i, worker_count))
+                .spawn_scoped(s, move || {
+                    supervise(
+                        "DHCPv6",
+                        v6_socket,
+                        || rebind_udp_socket(v6_addr, workers > 1, v6_bind_interface.as_deref()),
+                        &v6health.v6,
+                        &v6shutdown,
+                        |socket| {
+                            v6::v6_worker(
+                                socket,
+                                v6db.clone(),
+                                v6leases.clone(),
+                                v6config.clone(),
+                                v6sinks.clone(),
+                                v6audit.clone(),
+                                v6routes.clone(),
+                                v6capture.clone(),
+                                v6ha.clone(),
+                                v6_reply_source,
+                                v6health.clone(),
+                                v6shutdown.clone(),
+                            )
+                        },
+                    )
+                })
+                .expect("v6worker spawn");
+        }
+
+        // Only the workers hold event, audit and route senders from here on,
+        // so once they exit the writers see their channels disconnect and
+        // drain.
         drop(senders);
+        drop(audit_tx);
+        drop(pd_routes_tx);
 
-        if let Some((addr, (rx, dropped))) = events_address.zip(tcp_rx) {
+        if let (Some(log), Some(rx)) = (audit_log, audit_rx) {
+            thread::Builder::new()
+                .name("audit".to_string())
+                .spawn_scoped(s, move || audit::writer(log, rx))
+                .expect("audit spawn");
+        }
+
+        if let (Some(cfg), Some(rx)) = (pd_routes_config, pd_routes_rx) {
+            thread::Builder::new()
+                .name("pd-routes".to_string())
+                .spawn_scoped(s, move || pd_routes::run(cfg, rx))
+                .expect("pd-routes spawn");
+        }
+
+        if let Some((addr, (rx, dropped, queued))) = events_address.zip(tcp_rx) {
             let writer_shutdown = shutdown.clone();
             thread::Builder::new()
                 .name("events-tcp".to_string())
                 .spawn_scoped(s, move || {
-                    analytics::writer::tcp_writer(addr, rx, dropped, writer_shutdown)
+                    analytics::writer::tcp_writer(
+                        addr,
+                        tcp_options,
+                        rx,
+                        dropped,
+                        queued,
+                        writer_shutdown,
+                    )
                 })
                 .expect("events-tcp spawn");
         }
 
+        if let Some((cfg, (rx, dropped, queued))) = syslog_config.zip(syslog_rx) {
+            let writer_shutdown = shutdown.clone();
+            thread::Builder::new()
+                .name("syslog".to_string())
+                .spawn_scoped(s, move || {
+                    analytics::syslog::syslog_writer(cfg, rx, dropped, queued, writer_shutdown)
+                })
+                .expect("syslog spawn");
+        }
+
+        if let Some((watchers, rx, dropped, queued)) = watchers {
+            thread::Builder::new()
+                .name("events-watch".to_string())
+                .spawn_scoped(s, move || watchers.forward(rx, dropped, queued))
+                .expect("events-watch spawn");
+        }
+
         #[cfg(feature = "clickhouse")]
-        if let Some((cfg, (rx, dropped))) = clickhouse_config.zip(clickhouse_rx) {
+        if let Some((cfg, (rx, dropped, queued))) = clickhouse_config.zip(clickhouse_rx) {
             let writer_shutdown = shutdown.clone();
             thread::Builder::new()
                 .name("events-ch".to_string())
                 .spawn_scoped(s, move || {
-                    analytics::clickhouse::clickhouse_writer(cfg, rx, dropped, writer_shutdown)
+                    analytics::clickhouse::clickhouse_writer(
+                        cfg,
+                        rx,
+                        dropped,
+                        queued,
+                        writer_shutdown,
+                    )
                 })
                 .expect("events-ch spawn");
         }
@@ -267,15 +789,55 @@ A reservation only DHCPv4 and DHCPv6 server designed for internet service provid
 
 USAGE:
   shadowdhcp [OPTIONS]
+  shadowdhcp lookup [--mac MAC] [--duid DUID] [--circuit ID] [--remote ID] [--subscriber ID]
+  shadowdhcp replay FILE.pcap
+  shadowdhcp leases [--csv]
+  shadowdhcp snmp [--base-oid OID]
+  shadowdhcp diff-reservations OLD.json NEW.json
 
 FLAGS:
   -h, --help                    Prints this help information
       --help-config             Configuration file help
       --help-reservations       Reservations file help
       --available-extractors    Print list of available extractors for Option82 and Option18/37
+      --check                   Validate config.json, ids.json and reservations.json, print a
+                                summary and exit non-zero on any problem
 
 OPTIONS:
   --configdir PATH              Sets the directory to read config files from
+  --enable-v4 true|false        Run DHCPv4, overriding enable_v4 in config.json
+  --enable-v6 true|false        Run DHCPv6, overriding enable_v6 in config.json
+
+LOOKUP:
+  Prints which reservation a client would get and which lookup or extractor
+  matches it, from the files in --configdir. Option82 suboptions are given as
+  the relay sends them, before any extractor runs:
+    shadowdhcp lookup --mac 00-11-22-33-44-55 --remote olt1:1/1/1
+
+REPLAY:
+  Runs the DHCPv4 and DHCPv6 requests in a pcap (not pcapng) through the
+  handlers with the files in --configdir and prints what the server would
+  have replied, or why it stayed silent, for each:
+    shadowdhcp replay --configdir /etc/shadowdhcp traffic.pcap
+
+LEASES:
+  Asks the running server, over mgmt_address from config.json, for the
+  addresses it has leased from reservations and not yet seen expire, and
+  prints them as JSON, or as CSV with --csv.
+
+DIFF-RESERVATIONS:
+  Loads two reservations files the way the server does and prints the
+  reservations NEW.json removes (-), adds (+) and changes (~), with each
+  changed field's old and new value. Reservations are paired by ipv6_pd.
+  Changes to the fields clients are matched on (mac, duid, client_id,
+  option82, option1837, svlan, cvlan) are called out as key fields. Exits 0
+  if the files hold the same reservations, 1 if they differ, 2 on errors.
+
+SNMP:
+  Serves the server's counters to snmpd with net-snmp's pass_persist
+  protocol, reading them from mgmt_address in config.json. The subtree is at
+  --base-oid, by default .1.3.6.1.4.1.8072.9999.9999.67. In snmpd.conf:
+    pass_persist .1.3.6.1.4.1.8072.9999.9999.67 /usr/bin/shadowdhcp snmp --configdir /etc/shadowdhcp
 
 RUNTIME UPDATES:
   Reservations can be reloaded at runtime via:
@@ -284,10 +846,19 @@ RUNTIME UPDATES:
     echo '{\"command\":\"reload\"}' | nc localhost 8547
     echo '{\"command\":\"replace\",\"reservations\":[...]}' | nc localhost 8547
     echo '{\"command\":\"status\"}' | nc localhost 8547
+    echo '{\"command\":\"add\",\"reservation\":{...}}' | nc localhost 8547
+    echo '{\"command\":\"remove\",\"mac\":\"00-11-22-33-44-55\"}' | nc localhost 8547
+    echo '{\"command\":\"suspend\",\"mac\":\"00-11-22-33-44-55\"}' | nc localhost 8547
+    echo '{\"command\":\"capture_start\",\"mac\":\"00-11-22-33-44-55\"}' | nc localhost 8547
+    echo '{\"command\":\"capture_dump\",\"mac\":\"00-11-22-33-44-55\"}' | nc localhost 8547
+    echo '{\"command\":\"leases\"}' | nc localhost 8547
 ";
 
 const HELP_CONFIG: &str = r#"Config files are stored in a directory specified by --configdir (defaults to current directory):
-  - ids.json contains the DHCPv4 and DHCPv6 server IDs
+  - ids.json contains the DHCPv4 and DHCPv6 server IDs, {"v4": "...", "v6": "..."}.
+    Only the enabled protocols need one. When the file is missing it is
+    generated at startup: a DUID-LLT (or DUID-UUID) for v6, and for v4 the
+    v4_reply_source or single v4_bind_address, if one is set.
   - config.json server wide configuration
   - reservations.json IP reservations, can be hot reloaded. See --help-reservations
 
@@ -316,7 +887,7 @@ config.json:
         },
         {
             "net": "100.100.2.0/24",
-            "gateway": "100.100.3.1"
+            "gateway": "100.100.2.1"
         }
     ],
     "option82_extractors": [
@@ -348,6 +919,16 @@ config.json:
 }
 
 Optional fields:
+  - enable_v4: Run the DHCPv4 workers (default: true). dns_v4 is only
+               required while it is on. --enable-v4 takes precedence.
+  - enable_v6: Run the DHCPv6 workers (default: true). dns_v6 is only
+               required while it is on. --enable-v6 takes precedence.
+  - domain_search_v6: DNS search domains for DHCPv6 clients (option 24)
+  - sntp_v6: SNTP servers for DHCPv6 clients (option 31)
+  - ntp_v6: NTP servers for DHCPv6 clients (option 56)
+  - ntp_v4: NTP servers for DHCPv4 clients (option 42)
+  - interface_mtu: MTU for DHCPv4 clients (option 26, at least 68)
+  - domain_name: Domain name for DHCPv4 clients (option 15)
   - option82_extractors: List of DHCPv4 Option82 extractor functions
   - option1837_extractors: List of DHCPv6 Option18/37 extractor functions
   - mac_extractors: List of DHCPv6 MAC extraction methods (default: ["client_linklayer_address"])
@@ -355,22 +936,179 @@ Optional fields:
   - v6_lease_time: DHCPv6 valid lifetime, seconds (default: 12 * v4_lease_time)
   - logging: Logging block. Fields:
       level      - One of [trace, debug, info, warn, error] (default: info)
+      modules    - { "module::path": level } overrides of level, e.g.
+                   { "shadowdhcp::v6": "debug" }. "off" silences a module.
+      format     - One of [auto, json, text] (default: auto, which is
+                   pretty on a terminal and JSON otherwise)
       stdout     - Write to stdout (default: true if logging block present)
-      file       - { path, max_files } for in-process rotating file sink
+      file       - { path, max_files, rotation, max_bytes } for in-process
+                   rotating file sink. rotation is one of [daily, hourly,
+                   size] (default: daily); max_bytes applies to size
+                   (default: 104857600)
+      syslog     - { address, protocol, facility, sd_id, hostname } sends
+                   every lease grant, release and denial as an RFC 5424
+                   message with structured data. protocol is one of [udp,
+                   tcp] (default: udp); facility is daemon, auth, authpriv
+                   or local0-7 (default: daemon)
   - events: Event sink block. Each sink is enabled by its presence. Fields:
       queue_size - Per-sink in-memory queue capacity (default: 16384)
       tcp        - Address:port for analytics events over TCP (JSON lines)
+      tcp_tls    - { server_name, ca_file } to send the TCP events over TLS.
+                   ca_file is a PEM bundle (default: public web roots).
+                   Needs the "tcp-tls" cargo feature (on by default).
+      tcp_token  - Sent as {"token": "..."} on the first line of each TCP
+                   connection so the collector can authenticate the server
+      tcp_spool  - { path, max_bytes } file that holds TCP events while the
+                   collector is unreachable, replayed on reconnect
+                   (max_bytes default: 67108864)
       clickhouse - ClickHouse connection (HTTPS) for inserts into
                    dhcp.events_v4 / dhcp.events_v6. Required: url, user,
                    password. Optional: database (default "dhcp"), hostname
                    (default: read from /etc/hostname). Needs the "clickhouse"
                    cargo feature (on by default).
+      filter     - { successes, renewal_sample } to send fewer events.
+                   successes false sends failures only; renewal_sample N
+                   sends one in N successful renewals, 0 none (default 1)
   - mgmt_address: Address:port for management interface (reload/replace
                   reservations). Must be a loopback address; the interface
                   has no authentication, so any local process can use it.
                   Management clients are expected to run on this machine.
-  - v4_bind_address: Address:port for DHCPv4 (default: 0.0.0.0:67)
-  - v6_bind_address: Address:port for DHCPv6 (default: [::]:547)
+                  Also answers HTTP GET /healthz and /readyz, and streams
+                  events over a WebSocket at /events.
+  - grpc_address: Address:port for the gRPC management service in
+                  proto/shadowdhcp.proto. Needs the grpc feature, and a
+                  loopback address like mgmt_address
+  - v4_bind_address: Address:port for DHCPv4, or a list of them
+      (default: 0.0.0.0:67). SHADOW_DHCP4_BIND, comma-separated,
+      takes precedence.
+  - v6_bind_address: Address:port for DHCPv6, or a list of them
+      (default: [::]:547). SHADOW_DHCP6_BIND, comma-separated,
+      takes precedence.
+  - v4_reply_source: Source address for DHCPv4 replies (Linux only)
+  - v6_reply_source: Source address for DHCPv6 replies; link-local needs
+      v6_bind_interface (Linux only)
+  - v6_server_unicast: Address sent in the DHCPv6 Server Unicast option;
+      clients then send Request, Renew, Release and Decline straight
+      to it instead of through the relay
+  - v4_bind_interface: Interface to bind the DHCPv4 sockets to (Linux only)
+  - v6_bind_interface: Interface to bind the DHCPv6 sockets to (Linux only)
+  - reservation_source: Poll an HTTP endpoint for the full reservation list.
+                        Fields: url (required), interval_secs (default 300),
+                        authorization (Authorization header value). Changes
+                        are hot-swapped and persisted to reservations.json.
+  - ha: Active/standby pair. Fields: listen, peer (addresses), role
+        (primary|standby, default primary), heartbeat_secs (default 1),
+        failover_secs (default 5). Peers replicate option82/18/37 bindings
+        and lease holders; the standby answers only while the primary is
+        silent.
+  - rate_limit: Token-bucket limits applied before handling a packet. Fields:
+      client        - { per_second, burst } per MAC/DUID (default 1/s, burst 10)
+      relay         - { per_second, burst } per relay address (default: off)
+      ban_threshold - Consecutive drops before a client is banned (default 50)
+      ban_secs      - Ban length in seconds (default 300)
+  - conflict_detection: Ping the reserved IPv4 before each OFFER and withhold
+                        the offer if it answers. Fields: timeout_ms (default
+                        300, max 2000), quarantine_secs (default 0, off).
+                        Needs an ICMP socket (ping_group_range or CAP_NET_RAW).
+  - alerts: Thresholds checked once a minute; reaching one logs a warning
+            and sends an alert event. Fields: subnet_lease_percent (1-100,
+            share of a subnet's reservations with a running lease) and
+            no_reservation_per_minute (clients unanswered for want of a
+            reservation). Both default to off.
+  - static_routes: DHCPv4 routes for every client, as a list of
+                   {"destination": "10.50.0.0/16", "gateway": "192.168.1.1"}.
+                   Sent as option 121 to clients that request it, otherwise
+                   the classful ones as option 33.
+  - v6_advertise_unknown_clients: Answer a Solicit from a client with no
+                                  reservation with an Advertise carrying
+                                  NoAddrsAvail/NoPrefixAvail instead of
+                                  staying silent (default: false).
+  - v6_hint_status: When a Solicit hints at an address or prefix other
+                    than the reserved one, add a Success status note
+                    saying so inside that IA of the Advertise
+                    (default: false). Mismatches are logged either way.
+  - v6_always_send_options: Send the DNS, domain search, SNTP and NTP
+                            options whether or not the client's Option
+                            Request lists them (default: false).
+  - rapid_commit_enabled: Answer a Solicit carrying Rapid Commit with a
+                          Reply straight away (default: true). Turn off
+                          when more than one server answers the same
+                          clients, so only the one the client picks
+                          commits; such Solicits then get an Advertise.
+  - v6_preference: Preference (0-255) sent in DHCPv6 Advertise (default:
+                   none, not sent). 255 stops clients waiting for other
+                   servers' Advertises.
+  - refuse_lease_conflicts: Don't answer a client claiming a reserved
+                            address another client was leased within the
+                            lease time (default: false, only logged and
+                            reported in events).
+  - retransmit_window_ms: Resend the reply to a Discover or Solicit
+                          retransmitted within this many milliseconds
+                          instead of handling it again, with no event
+                          (default: 0, off; at most 10000).
+  - relay_cache_size: Option 82 values whose extractor match is
+                      remembered so repeat packets skip the extractors
+                      (default: 16384; 0 turns it off).
+  - audit: Append-only JSON lines log of every DHCPv4 ACK and DHCPv6 Reply
+           that assigns an address. Fields: path (required), max_bytes
+           (rotate at this size, default 100 MiB), max_files (rotated
+           files to keep, default 0 = keep all).
+  - pd_routes: Routes for delegated prefixes, added, changed and deleted
+               as DHCPv6 leases are bound, move and expire. The next hop is
+               the client's IA_NA address, or the relay. Fields (at least
+               one): exec (run as `exec add|change|delete PREFIX NEXT_HOP`),
+               exabgp_pipe (ExaBGP API pipe), route_file (BIRD static
+               routes, rewritten on each change).
+  - capture: Keep the raw packets of selected clients in memory for the
+             capture_dump management command. Fields: macs (clients to
+             capture from startup), depth (packets per client, default 64).
+  - lease_history: Transactions kept in memory per client for the
+                   history management command (default: 4, 0 to keep
+                   none). Each is a few hundred bytes.
+  - boot: BOOTP header fields for DHCPv4 OFFER/ACK, for TFTP provisioning.
+          Fields: sname (max 63 bytes), next_server (siaddr), file (max
+          127 bytes), omit_sname (leave sname empty). Reservations can
+          override each field.
+  - omit_sname_vendor_classes: Vendor class identifier (option 60)
+          prefixes of DHCPv4 clients never sent an sname.
+  - bootp_enabled: Answer BOOTP requests (no DHCP Message Type) from
+          clients with a MAC reservation (default: false)
+  - option82_pools: Address pools for Option 82 keys without a
+          reservation. Each entry: name, extractor (an option82
+          extractor name), circuit_prefix/remote_prefix (optional key
+          filters), ipv4, ipv6_na, ipv6_pd (CIDRs), pd_len. Assignments
+          are saved to pool_assignments.json in the config directory.
+  - v6_na_template: How reservations with "ipv6_na": "auto" get their
+          address, as interface_id (default 1) within a /64. Either
+          {"from": "pd", "subnet": N} for the Nth /64 of the
+          reservation's ipv6_pd, or {"from": "subscriber_id", "prefix":
+          "2001:db8:ff00::/40"} for the /64 numbered by the numeric
+          subscriber_id within prefix.
+  - workers: DHCPv4 and DHCPv6 worker threads, each with its own
+          SO_REUSEPORT socket (default: CPU count on Linux, 1 elsewhere)
+  - subscriber_networks: Settings for the clients behind particular
+          relays. Each entry: name, relays_v4 (giaddr CIDRs), relays_v6
+          (link-address CIDRs), and any of dns_v4, dns_v6,
+          domain_search_v6, sntp_v6, ntp_v6, ntp_v4, interface_mtu,
+          domain_name, static_routes, v4_lease_time, v6_lease_time,
+          authoritative and v6_advertise_unknown_clients to replace the
+          top-level value.
+          The first entry whose prefix matches the relay is used.
+  - reservation_profiles: Named settings shared by the reservations
+          whose "profile" is that name: dns_v4, dns_v6, domain_search_v6,
+          sntp_v6, ntp_v6, ntp_v4, interface_mtu, domain_name,
+          static_routes, v4_lease_time, v6_lease_time and boot. Applied
+          over the subscriber network's settings.
+  - device_classes: Names for the device_class labels events report,
+          each with fingerprints (the labels) and an optional profile,
+          a reservation_profiles entry applied after the reservation's.
+  - walled_garden: Where suspended DHCPv4 customers go instead of
+          being NAKed: net, gateway, dns_v4 and lease_time (default 300).
+          Each gets the address in net with its reserved ipv4's host bits.
+  - fallback: DHCP server that clients without a reservation, or naming
+          another server id, are passed on to while migrating from it.
+          Fields (at least one): v4 and v6 socket addresses. DHCPv6 is
+          relayed through this server.
 
 ids.json:
 {
@@ -380,16 +1118,36 @@ ids.json:
 "#;
 
 const HELP_RESERVATIONS: &str = r#"Reservations must contain:
-  - ipv4
-  - ipv6_na
   - ipv6_pd
-  - At least one source for IPv4 and IPv6. Some sources can be used for both
+  - At least one source for IPv6, and for IPv4 if ipv4 is set. Some sources
+    can be used for both
     - mac - can be used for both
     - option82 - can be used for both. Should be formatted in all caps dash format: AA-BB-CC-DD-EE-FF
     - duid - IPv6 only
+    - client_id - IPv4 only. DHCPv4 client identifier (option 61) in hex, type byte first
+    - svlan, cvlan - IPv4 only. VLAN tags the relay reports in the Option 82
+      Circuit-ID, e.g. "svlan": 100, "cvlan": 200
+
+Optional:
+  - ipv4 - Left out, the customer only gets IPv6 and DHCPv4 treats it as
+    having no reservation
+  - ipv6_na - an address, or "auto" to derive it from v6_na_template. Left
+    out, the customer only gets prefix delegation
+  - subscriber_id - Free-form customer identifier copied into DHCP events
+  - static_routes - Extra DHCPv4 routes, added to the config-level list:
+    [{"destination": "10.50.0.0/16", "gateway": "192.168.1.1"}]
+  - boot - DHCPv4 sname/next_server/file, overriding the config-level ones:
+    {"file": "cpe/model-a.cfg"}
+  - profile - Name of a reservation_profiles entry in config.json whose
+    DNS servers, options and lease times this customer shares
+  - activate_at, deactivate_at - RFC 3339 times between which the
+    reservation is served, such as the install and churn dates:
+    "2025-06-01T00:00:00Z". Outside them the customer gets no answer
+  - suspended - true to refuse the customer with DHCPNAK and DHCPv6
+    replies without leases, keeping the reservation in place
 
 Reservations with multiple sources will be evaluated in the following order:
-IPv4: mac -> option82
+IPv4: client_id -> mac -> svlan/cvlan -> option82
 IPv6: duid -> option18/37 -> mac -> option82
 
 reservations.json:
@@ -428,6 +1186,12 @@ reservations.json:
 ]
 "#;
 
+/// The value of an environment variable that is set, even if it isn't
+/// valid Unicode, so the config reports it as it would any other mistake
+fn env_value(var: &str) -> Option<String> {
+    std::env::var_os(var).map(|value| value.to_string_lossy().into_owned())
+}
+
 fn bind_udp_socket(addr: impl Into<SocketAddr>, protocol: &str) -> UdpSocket {
     let addr = addr.into();
     match UdpSocket::bind(addr) {
@@ -439,6 +1203,582 @@ fn bind_udp_socket(addr: impl Into<SocketAddr>, protocol: &str) -> UdpSocket {
     }
 }
 
+/// reservations.json from `config_dir`, or `None` if there isn't one
+fn read_reservations(config_dir: &Path) -> Result<Option<Vec<Reservation>>, String> {
+    let path = config_dir.join("reservations.json");
+    match std::fs::File::open(&path) {
+        Ok(file) => serde_json::from_reader(file)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse {}: {e}", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to open {}: {e}", path.display())),
+    }
+}
+
+enum Command {
+    Lookup(lookup::Query),
+    Replay(PathBuf),
+    Leases { csv: bool },
+    Snmp { base: Vec<u32> },
+    DiffReservations { old: PathBuf, new: PathBuf },
+}
+
+fn lookup_query(args: &mut pico_args::Arguments) -> Result<lookup::Query, String> {
+    let mac = args
+        .opt_value_from_fn("--mac", |s| parse_mac(s).ok_or("unrecognised format"))
+        .map_err(|e| format!("Invalid --mac: {e}"))?;
+    let duid = args
+        .opt_value_from_fn("--duid", |s| Duid::try_from(s))
+        .map_err(|e| format!("Invalid --duid: {e}"))?;
+    let mut suboption = |name| {
+        args.opt_value_from_str::<_, String>(name)
+            .map(|v| v.map(CompactString::from))
+            .map_err(|e| format!("Invalid {name}: {e}"))
+    };
+    let option82 = Option82 {
+        circuit: suboption("--circuit")?,
+        remote: suboption("--remote")?,
+        subscriber: suboption("--subscriber")?,
+    };
+    let option82 =
+        (option82.circuit.is_some() || option82.remote.is_some() || option82.subscriber.is_some())
+            .then_some(option82);
+    if mac.is_none() && duid.is_none() && option82.is_none() {
+        return Err(
+            "lookup needs at least one of --mac, --duid, --circuit, --remote or --subscriber"
+                .to_string(),
+        );
+    }
+    Ok(lookup::Query {
+        mac,
+        duid,
+        option82,
+    })
+}
+
+/// `lookup`: print the lookups each protocol would try for the client and
+/// the reservation it ends up with. Exits non-zero if neither protocol
+/// finds one.
+fn run_lookup(config_dir: &Path, query: &lookup::Query) -> i32 {
+    let (config, db, pools) = match load_offline(config_dir) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+
+    let v4 = lookup::lookup_v4(&config, &db, pools.as_deref(), query);
+    let v6 = lookup::lookup_v6(&db, query);
+    let mut found = false;
+    for (protocol, outcome) in [("DHCPv4", v4), ("DHCPv6", v6)] {
+        println!("{protocol}:");
+        if outcome.steps.is_empty() {
+            println!("  nothing to look up");
+        }
+        for step in &outcome.steps {
+            match &step.found {
+                Some(res) => println!("  {}: {}", step.description, res.name()),
+                None => println!("  {}: no reservation", step.description),
+            }
+        }
+        match outcome.matched {
+            Some((res, method)) => {
+                found = true;
+                match method.extractor {
+                    Some(extractor) => println!("  => matched by {} ({extractor})", method.method),
+                    None => println!("  => matched by {}", method.method),
+                }
+                let json = serde_json::to_string_pretty(&*res).unwrap_or_default();
+                for line in json.lines() {
+                    println!("  {line}");
+                }
+            }
+            None => println!("  => no reservation"),
+        }
+    }
+    i32::from(!found)
+}
+
+/// Config, reservations and Option 82 pools as startup would load them, for
+/// the subcommands that work offline. Pool assignments are read but never
+/// written back.
+fn load_offline(
+    config_dir: &Path,
+) -> Result<(Config, ReservationDb, Option<Arc<pool::PoolAllocator>>), String> {
+    let config = Config::load_from_files(config_dir)
+        .map_err(|e| format!("Unable to load config file: {e}"))?;
+    let reservations = read_reservations(config_dir)?.unwrap_or_default();
+    let pools = if config.option82_pools.is_empty() {
+        None
+    } else {
+        let path = config_dir.join("pool_assignments.json");
+        let pools = pool::PoolAllocator::open(&config.option82_pools, path.clone())
+            .map_err(|e| format!("Failed to load {}: {e}", path.display()))?;
+        Some(Arc::new(pools))
+    };
+    let db =
+        ReservationDb::with_na_template(config.v6_na_template.clone()).with_pools(pools.clone());
+    db.load_reservations(reservations)
+        .map_err(|e| format!("Failed to load reservations.json: {e}"))?;
+    Ok((config, db, pools))
+}
+
+/// `diff-reservations`: print what `new` adds, removes and changes compared
+/// to `old`. Exits 0 when they hold the same reservations, 1 when they
+/// differ and 2 when either can't be loaded, like diff(1).
+fn run_diff_reservations(config_dir: &Path, old: &Path, new: &Path) -> i32 {
+    // Only needed for `"ipv6_na": "auto"`, so a missing config is no reason
+    // to refuse
+    let na_template = match Config::load_from_files(config_dir) {
+        Ok(config) => config.v6_na_template,
+        Err(_) => None,
+    };
+    let diff = reservation_diff::load(old, na_template.clone()).and_then(|old_reservations| {
+        let new_reservations = reservation_diff::load(new, na_template)?;
+        reservation_diff::diff(old_reservations, new_reservations)
+    });
+    let diff = match diff {
+        Ok(diff) => diff,
+        Err(e) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+
+    for reservation in &diff.removed {
+        println!("- {}", reservation.name());
+    }
+    for reservation in &diff.added {
+        println!("+ {}", reservation.name());
+    }
+    for changed in &diff.changed {
+        let keys: Vec<_> = changed.key_fields().collect();
+        if keys.is_empty() {
+            println!("~ {}", changed.new.name());
+        } else {
+            println!("~ {} (key fields: {})", changed.new.name(), keys.join(", "));
+        }
+        for (field, old, new) in &changed.fields {
+            println!("    {field}: {old} -> {new}");
+        }
+    }
+    println!(
+        "{} added, {} removed, {} changed",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len()
+    );
+    i32::from(!diff.is_empty())
+}
+
+/// `replay`: run the requests in a pcap through the handlers and print what
+/// the server would have done with each. Returns the exit code.
+fn run_replay(config_dir: &Path, path: &Path) -> i32 {
+    let (config, db, _) = match load_offline(config_dir) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+    let datagrams = match std::fs::File::open(path)
+        .and_then(|file| replay::read_pcap(io::BufReader::new(file)))
+    {
+        Ok(datagrams) => datagrams,
+        Err(e) => {
+            eprintln!("Failed to read {}: {e}", path.display());
+            return 1;
+        }
+    };
+
+    let replayed = replay::replay(&config, &db, &datagrams);
+    let mut totals: Vec<(&str, usize)> = Vec::new();
+    for packet in &replayed {
+        let mut line = format!(
+            "#{} {} {} => {}",
+            packet.number, packet.src, packet.request, packet.outcome
+        );
+        if let Some(detail) = &packet.detail {
+            line.push_str(&format!(" {detail}"));
+        }
+        println!("{line}");
+        match totals
+            .iter_mut()
+            .find(|(outcome, _)| *outcome == packet.outcome)
+        {
+            Some((_, count)) => *count += 1,
+            None => totals.push((packet.outcome, 1)),
+        }
+    }
+    println!(
+        "{} requests replayed, {} replies skipped",
+        replayed.len(),
+        datagrams.len() - replayed.len()
+    );
+    for (outcome, count) in totals {
+        println!("  {outcome}: {count}");
+    }
+    0
+}
+
+/// `leases`: ask the running server for its current leases over the
+/// management interface and print them as JSON or CSV. Returns the exit
+/// code.
+fn run_leases(config_dir: &Path, csv: bool) -> i32 {
+    let config = match Config::load_from_files(config_dir) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Unable to load config file: {e}");
+            return 1;
+        }
+    };
+    let Some(addr) = config.mgmt_address else {
+        eprintln!("leases needs mgmt_address set in config.json");
+        return 1;
+    };
+    let leases = match request_leases(addr) {
+        Ok(leases) => leases,
+        Err(e) => {
+            eprintln!("Failed to get leases from {addr}: {e}");
+            return 1;
+        }
+    };
+    if !csv {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&leases).unwrap_or_default()
+        );
+        return 0;
+    }
+
+    println!(
+        "protocol,address,client,circuit,remote,subscriber,leased_at,expires_at,subscriber_id"
+    );
+    for lease in &leases.v4 {
+        let option82 = lease.option82.as_ref();
+        print_csv_row(&[
+            "v4",
+            &lease.ipv4.to_string(),
+            &lease.mac.to_string(),
+            option82.and_then(|o| o.circuit.as_deref()).unwrap_or(""),
+            option82.and_then(|o| o.remote.as_deref()).unwrap_or(""),
+            option82.and_then(|o| o.subscriber.as_deref()).unwrap_or(""),
+            &lease.leased_at.to_string(),
+            &lease.expires_at.to_string(),
+            lease.subscriber_id.as_deref().unwrap_or(""),
+        ]);
+    }
+    // Option 18 interface-id goes in the circuit column, Option 37 in remote
+    for lease in &leases.v6 {
+        let option1837 = lease.option1837.as_ref();
+        print_csv_row(&[
+            "v6",
+            &lease.ipv6_na.to_string(),
+            &lease.duid.to_string(),
+            option1837
+                .and_then(|o| o.interface.as_deref())
+                .unwrap_or(""),
+            option1837.and_then(|o| o.remote.as_deref()).unwrap_or(""),
+            "",
+            &lease.leased_at.to_string(),
+            &lease.expires_at.to_string(),
+            lease.subscriber_id.as_deref().unwrap_or(""),
+        ]);
+    }
+    0
+}
+
+fn request_leases(addr: SocketAddr) -> Result<opt82_cache::LeaseSnapshot, String> {
+    let mut stream =
+        TcpStream::connect_timeout(&addr, Duration::from_secs(5)).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+    stream
+        .write_all(b"{\"command\":\"leases\"}\n")
+        .map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    io::BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    let mut response: serde_json::Value =
+        serde_json::from_str(&line).map_err(|e| format!("invalid response: {e}"))?;
+    if let Some(error) = response["error"].as_str() {
+        return Err(error.to_string());
+    }
+    serde_json::from_value(response["leases"].take()).map_err(|e| format!("invalid response: {e}"))
+}
+
+/// `snmp`: answer snmpd's `pass_persist` requests on stdin with the table
+/// the running server serves at `/snmp`. Returns the exit code.
+fn run_snmp(config_dir: &Path, base: &[u32]) -> i32 {
+    let config = match Config::load_from_files(config_dir) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Unable to load config file: {e}");
+            return 1;
+        }
+    };
+    let Some(addr) = config.mgmt_address else {
+        eprintln!("snmp needs mgmt_address set in config.json");
+        return 1;
+    };
+    let fetch =
+        || request_snmp_table(addr).map_err(|e| format!("failed to get counters from {addr}: {e}"));
+    match snmp::pass_persist(base, io::stdin().lock(), io::stdout().lock(), fetch) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("snmp: {e}");
+            1
+        }
+    }
+}
+
+fn request_snmp_table(addr: SocketAddr) -> Result<Vec<snmp::Object>, String> {
+    let mut stream =
+        TcpStream::connect_timeout(&addr, Duration::from_secs(5)).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+    stream
+        .write_all(b"GET /snmp HTTP/1.1\r\nHost: shadowdhcp\r\n\r\n")
+        .map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    io::Read::read_to_string(&mut stream, &mut response).map_err(|e| e.to_string())?;
+    match response.split_once("\r\n\r\n") {
+        Some((head, body)) if head.starts_with("HTTP/1.1 200 ") => snmp::parse(body),
+        Some((head, _)) => Err(head.lines().next().unwrap_or_default().to_string()),
+        None => Err("invalid response".to_string()),
+    }
+}
+
+fn print_csv_row(fields: &[&str]) {
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect();
+    println!("{}", fields.join(","));
+}
+
+/// `--check`: load everything startup loads, look for conflicts between
+/// reservations and print a summary. Returns the exit code.
+fn run_check(config_dir: &Path, overrides: ProtocolOverrides) -> i32 {
+    let config = match Config::load_with_overrides(config_dir, overrides) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Unable to load config file: {e}");
+            return 1;
+        }
+    };
+
+    let reservations = match read_reservations(config_dir) {
+        Ok(Some(reservations)) => reservations,
+        Ok(None) => {
+            println!("No reservations.json found, checking with empty reservations");
+            Vec::new()
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+
+    if config.generated_server_ids {
+        println!(
+            "No ids.json found, the server will generate one at startup (DUID {})",
+            config.v6_server_id
+        );
+    }
+
+    let report = check::check(&config, reservations);
+    println!("Reservations: {}", report.reservations);
+    for (net, count) in &report.per_subnet {
+        println!("  {net}: {count}");
+    }
+    let names = |names: Vec<&str>| {
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    println!(
+        "Option82 extractors: {}",
+        names(config.option82_extractors.iter().map(|e| e.0).collect())
+    );
+    println!(
+        "Option18/37 extractors: {}",
+        names(config.option1837_extractors.iter().map(|e| e.0).collect())
+    );
+    for pool in &config.option82_pools {
+        println!("Option82 pool `{}`: {}", pool.name, pool.ipv4);
+    }
+
+    if report.problems.is_empty() {
+        println!("No problems found");
+        0
+    } else {
+        eprintln!("Found {} problems:", report.problems.len());
+        for problem in &report.problems {
+            eprintln!("  - {problem}");
+        }
+        1
+    }
+}
+
+/// One socket per worker for each address, each paired with its address.
+/// More than one share the port with SO_REUSEPORT, and the kernel spreads
+/// incoming datagrams across them by source address, so each relay keeps
+/// landing on the same worker.
+fn bind_udp_sockets<A: Copy + Into<SocketAddr>>(
+    addrs: &[A],
+    protocol: &str,
+    count: usize,
+) -> Vec<(SocketAddr, UdpSocket)> {
+    let mut sockets = Vec::with_capacity(addrs.len() * count);
+    for addr in addrs.iter().map(|addr| (*addr).into()) {
+        if count == 1 {
+            sockets.push((addr, bind_udp_socket(addr, protocol)));
+            continue;
+        }
+        for _ in 0..count {
+            match bind_reuse_port(addr) {
+                Ok(socket) => sockets.push((addr, socket)),
+                Err(e) => {
+                    print_bind_error(addr, protocol, &e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    sockets
+}
+
+#[cfg(unix)]
+fn bind_reuse_port(addr: SocketAddr) -> io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+#[cfg(not(unix))]
+fn bind_reuse_port(addr: SocketAddr) -> io::Result<UdpSocket> {
+    UdpSocket::bind(addr)
+}
+
+/// Restrict every socket of a protocol to `interface`, if one is configured
+fn bind_interface(sockets: &[(SocketAddr, UdpSocket)], interface: Option<&str>, protocol: &str) {
+    let Some(interface) = interface else {
+        return;
+    };
+    for (_, socket) in sockets {
+        if let Err(e) = reply_source::bind_interface(socket, interface) {
+            eprintln!("Unable to bind {protocol} to interface {interface}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Run a DHCP worker on `socket`, and if it panics or gives up on its socket
+/// start it again on a fresh one from `rebind`, so one bad packet or a
+/// vanished address doesn't leave the protocol served by fewer workers (or
+/// none) for the life of the process. Returns once the worker exits
+/// normally, which it only does on shutdown.
+fn supervise(
+    protocol: &str,
+    mut socket: UdpSocket,
+    rebind: impl Fn() -> io::Result<UdpSocket>,
+    health: &ProtocolHealth,
+    shutdown: &shutdown::Shutdown,
+    mut run: impl FnMut(UdpSocket) -> io::Result<()>,
+) {
+    const MIN_BACKOFF: Duration = Duration::from_millis(100);
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let started = Instant::now();
+        // The socket is dropped as the worker returns or unwinds, which
+        // also frees the port for the rebind when SO_REUSEPORT isn't in use
+        let panicked = match std::panic::catch_unwind(AssertUnwindSafe(|| run(socket))) {
+            Ok(Ok(())) => return,
+            // The worker has logged the error
+            Ok(Err(_)) => false,
+            Err(panic) => {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown panic");
+                tracing::error!(
+                    thread = thread::current().name(),
+                    panic = message,
+                    "{protocol} worker panicked, restarting it"
+                );
+                true
+            }
+        };
+        // A worker that ran for a while before stopping hit a rare packet or
+        // outage, not a fault it will hit again straight away
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
+        }
+        socket = loop {
+            if shutdown.wait_timeout(backoff) {
+                return;
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            match rebind() {
+                Ok(socket) => break socket,
+                Err(e) => tracing::error!("Unable to rebind {protocol} worker socket: {e}"),
+            }
+        };
+        if panicked {
+            health.record_restart();
+            tracing::warn!("{protocol} worker restarted");
+        } else {
+            health.record_rebind();
+            tracing::warn!("{protocol} worker socket rebound");
+        }
+    }
+}
+
+/// A replacement for a worker's socket, bound the way startup bound the
+/// original
+fn rebind_udp_socket(
+    addr: SocketAddr,
+    reuse_port: bool,
+    interface: Option<&str>,
+) -> io::Result<UdpSocket> {
+    let socket = if reuse_port {
+        bind_reuse_port(addr)?
+    } else {
+        UdpSocket::bind(addr)?
+    };
+    if let Some(interface) = interface {
+        reply_source::bind_interface(&socket, interface)?;
+    }
+    Ok(socket)
+}
+
+/// Thread names stay `v4worker`/`v6worker` with a single worker
+fn worker_name(base: &str, index: usize, count: usize) -> String {
+    if count == 1 {
+        base.to_string()
+    } else {
+        format!("{base}-{index}")
+    }
+}
+
 fn bind_tcp_socket(addr: impl Into<SocketAddr>, protocol: &str) -> TcpListener {
     let addr = addr.into();
     match TcpListener::bind(addr) {
@@ -472,12 +1812,17 @@ fn print_bind_error(addr: SocketAddr, protocol: &str, e: &io::Error) {
             }
         }
         io::ErrorKind::AddrInUse => {
-            eprintln!(
-                "Hint: Port {} is already in use by another process.",
-                addr.port()
-            );
+            eprintln!("Hint: {addr} is already in use by another process.");
             #[cfg(unix)]
-            eprintln!("  - Check with: ss -tlnp | grep {}", addr.port());
+            {
+                // The DHCP sockets are UDP, the rest TCP
+                let flags = if protocol.starts_with("DHCP") {
+                    "-ulnp"
+                } else {
+                    "-tlnp"
+                };
+                eprintln!("  - Check with: ss {flags} | grep {}", addr.port());
+            }
             #[cfg(windows)]
             eprintln!("  - Check with: netstat -ano | findstr :{}", addr.port());
         }
@@ -1,16 +1,33 @@
 use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::{Shutdown, TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
 use std::time::Duration;
 
+use advmac::MacAddr6;
 use arc_swap::ArcSwap;
+use compact_str::CompactString;
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 
-use crate::reservationdb::ReservationDb;
+use crate::analytics::events::DhcpEvent;
+use crate::analytics::watch::{EventWatchers, WatchFilter};
+use crate::capture::{CapturedPacket, PacketCapture};
+use crate::config::Config;
+use crate::health::{Health, Report};
+use crate::history::Transaction;
+use crate::opt82_cache::{LeaseSnapshot, Opt82Cache};
+use crate::relays::RelaySummary;
+use crate::reservationdb::{ReservationDb, ReservationKey};
+use crate::snmp;
+use crate::types::{Duid, Option1837, Option82};
+use crate::websocket;
 use crate::Reservation;
 
+/// Event streams beyond this many, gRPC ones included, are refused
+const MAX_EVENT_STREAMS: usize = 32;
+
 #[derive(Deserialize)]
 #[serde(tag = "command")]
 pub enum MgmtRequest {
@@ -20,9 +37,85 @@ pub enum MgmtRequest {
     Replace { reservations: Vec<Reservation> },
     #[serde(rename = "status")]
     Status,
+    /// Add a single reservation, replacing any reservation that shares a
+    /// MAC, DUID, Option82 or Option18/37 key with it
+    #[serde(rename = "add")]
+    Add {
+        reservation: Reservation,
+        #[serde(default = "default_persist")]
+        persist: bool,
+    },
+    /// Remove the reservations matching any of the supplied keys
+    #[serde(rename = "remove")]
+    Remove {
+        #[serde(default)]
+        mac: Option<MacAddr6>,
+        #[serde(default)]
+        duid: Option<Duid>,
+        #[serde(default)]
+        option82: Option<Option82>,
+        #[serde(default)]
+        option1837: Option<Option1837>,
+        /// Removes every reservation carrying this `subscriber_id`
+        #[serde(default)]
+        subscriber_id: Option<CompactString>,
+        #[serde(default = "default_persist")]
+        persist: bool,
+    },
+    /// Suspend, or with `"suspended": false` resume, the reservations
+    /// matching any of the supplied keys
+    #[serde(rename = "suspend")]
+    Suspend {
+        #[serde(default)]
+        mac: Option<MacAddr6>,
+        #[serde(default)]
+        duid: Option<Duid>,
+        #[serde(default)]
+        option82: Option<Option82>,
+        #[serde(default)]
+        option1837: Option<Option1837>,
+        #[serde(default)]
+        subscriber_id: Option<CompactString>,
+        #[serde(default = "default_suspended")]
+        suspended: bool,
+        #[serde(default = "default_persist")]
+        persist: bool,
+    },
+    /// Start keeping the packets exchanged with a client
+    #[serde(rename = "capture_start")]
+    CaptureStart { mac: MacAddr6 },
+    /// Stop capturing for a client and discard its packets
+    #[serde(rename = "capture_stop")]
+    CaptureStop { mac: MacAddr6 },
+    /// Return the packets captured for a client
+    #[serde(rename = "capture_dump")]
+    CaptureDump { mac: MacAddr6 },
+    /// Return the leases handed out from reservations that haven't run out
+    #[serde(rename = "leases")]
+    Leases,
+    /// Return the last transactions with a DHCPv4 client, a DHCPv6 client,
+    /// or both
+    #[serde(rename = "history")]
+    History {
+        #[serde(default)]
+        mac: Option<MacAddr6>,
+        #[serde(default)]
+        duid: Option<Duid>,
+    },
+    /// Return every relay agent heard from since startup
+    #[serde(rename = "relays")]
+    Relays,
+}
+
+fn default_persist() -> bool {
+    true
 }
 
-#[derive(Serialize)]
+fn default_suspended() -> bool {
+    true
+}
+
+#[derive(Default, Serialize)]
 pub struct MgmtResponse {
     pub success: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -31,6 +124,42 @@ pub struct MgmtResponse {
     pub message: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reservation_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packets: Option<Vec<CapturedPacket>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leases: Option<LeaseSnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<Transaction>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relays: Option<Vec<RelaySummary>>,
+}
+
+impl MgmtResponse {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            success: true,
+            message: Some(message.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn err(error: impl Into<String>) -> Self {
+        Self {
+            success: false,
+            error: Some(error.into()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Shared server state the management commands read and update
+pub struct State {
+    pub reservations: Arc<ArcSwap<ReservationDb>>,
+    pub capture: Arc<PacketCapture>,
+    pub leases: Arc<Opt82Cache>,
+    pub config: Arc<ArcSwap<Config>>,
+    pub health: Arc<Health>,
+    pub watchers: EventWatchers,
 }
 
 /// Main management listener loop. Runs on a detached thread (outside the
@@ -44,16 +173,12 @@ pub struct MgmtResponse {
 /// bound to a loopback address, so any local process can manage the server —
 /// the same trust model as a world-readable unix socket, chosen so the
 /// interface works identically on Windows.
-pub fn listener(
-    listener: TcpListener,
-    reservations: Arc<ArcSwap<ReservationDb>>,
-    config_dir: PathBuf,
-) {
+pub fn listener(listener: TcpListener, state: State, config_dir: PathBuf) {
     for stream in listener.incoming() {
         match stream {
             Ok(stream) => {
                 let peer = stream.peer_addr().ok();
-                handle_client(stream, &reservations, &config_dir);
+                handle_client(stream, &state, &config_dir);
                 if let Some(addr) = peer {
                     info!(%addr, "handled management request");
                 }
@@ -67,7 +192,8 @@ pub fn listener(
     }
 }
 
-fn handle_client(stream: TcpStream, reservations: &Arc<ArcSwap<ReservationDb>>, config_dir: &Path) {
+fn handle_client(stream: TcpStream, state: &State, config_dir: &Path) {
+    let (reservations, capture) = (&state.reservations, &*state.capture);
     stream.set_read_timeout(Some(Duration::from_secs(5))).ok();
     stream.set_write_timeout(Some(Duration::from_secs(5))).ok();
 
@@ -83,63 +209,191 @@ fn handle_client(stream: TcpStream, reservations: &Arc<ArcSwap<ReservationDb>>,
         }
     }
 
+    if let Some(request) = line.strip_prefix("GET ") {
+        // Read the headers so closing doesn't reset the connection under
+        // the response
+        let mut header = String::new();
+        let mut websocket_key = None;
+        while reader.read_line(&mut header).is_ok_and(|n| n > 0) && header.trim() != "" {
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                    websocket_key = Some(value.trim().to_string());
+                }
+            }
+            header.clear();
+        }
+        let path = request.split_whitespace().next().unwrap_or_default();
+        if let Some(key) = websocket_key {
+            drop(reader);
+            watch_events(stream, path, &key, &state.watchers);
+            return;
+        }
+        let db = reservations.load();
+        let mut report = state
+            .health
+            .report(&state.config.load(), db.len(), state.leases.stats());
+        report.reservation_lookups = db.lookup_stats();
+        if let Err(e) = (&stream).write_all(http_response(path, &report).as_bytes()) {
+            warn!(%e, "failed to write health response");
+        }
+        return;
+    }
+
     let response = match serde_json::from_str::<MgmtRequest>(&line) {
         Ok(MgmtRequest::Reload) => match reload_from_disk(reservations, config_dir) {
             Ok(count) => MgmtResponse {
-                success: true,
-                error: None,
-                message: Some(format!("Reloaded {} reservations", count)),
                 reservation_count: Some(count),
+                ..MgmtResponse::ok(format!("Reloaded {} reservations", count))
             },
-            Err(e) => MgmtResponse {
-                success: false,
-                error: Some(e),
-                message: None,
-                reservation_count: None,
-            },
+            Err(e) => MgmtResponse::err(e),
         },
         Ok(MgmtRequest::Replace {
             reservations: new_res,
-        }) => match atomic_write_reservations(config_dir, &new_res) {
-            Ok(()) => {
-                let count = new_res.len();
-                let new_db = ReservationDb::new();
-                new_db.load_reservations(new_res);
-                reservations.store(Arc::new(new_db));
-                info!(count, "replaced reservations via TCP and persisted to disk");
-                MgmtResponse {
-                    success: true,
-                    error: None,
-                    message: Some(format!("Replaced with {} reservations", count)),
-                    reservation_count: Some(count),
-                }
+        }) => {
+            let _lock = lock_reservations();
+            // Load first so a set with an underivable address is rejected
+            // before it overwrites the file
+            let new_db = reservations.load().empty_like();
+            match new_db.load_reservations(new_res.clone()) {
+                Err(e) => MgmtResponse::err(e),
+                Ok(()) => match atomic_write_reservations(config_dir, &new_res) {
+                    Ok(()) => {
+                        let count = new_res.len();
+                        reservations.store(Arc::new(new_db));
+                        info!(count, "replaced reservations via TCP and persisted to disk");
+                        MgmtResponse {
+                            reservation_count: Some(count),
+                            ..MgmtResponse::ok(format!("Replaced with {} reservations", count))
+                        }
+                    }
+                    Err(e) => {
+                        warn!(%e, "failed to persist reservations to disk");
+                        MgmtResponse::err(format!("Failed to write reservations: {}", e))
+                    }
+                },
             }
-            Err(e) => {
-                warn!(%e, "failed to persist reservations to disk");
-                MgmtResponse {
-                    success: false,
-                    error: Some(format!("Failed to write reservations: {}", e)),
-                    message: None,
-                    reservation_count: None,
-                }
-            }
-        },
+        }
         Ok(MgmtRequest::Status) => {
             let db = reservations.load();
             let count = db.len();
             MgmtResponse {
-                success: true,
-                error: None,
-                message: Some("Status OK".into()),
                 reservation_count: Some(count),
+                ..MgmtResponse::ok("Status OK")
             }
         }
-        Err(e) => MgmtResponse {
-            success: false,
-            error: Some(format!("Invalid request: {}", e)),
-            message: None,
-            reservation_count: None,
+        Ok(MgmtRequest::Add {
+            mut reservation,
+            persist,
+        }) => {
+            let _lock = lock_reservations();
+            let db = reservations.load();
+            match db.derive_ipv6_na(&mut reservation) {
+                Ok(()) => {
+                    db.upsert(reservation);
+                    info!(persist, "added reservation via TCP");
+                    persist_response(&db, config_dir, persist, "Added 1 reservation")
+                }
+                Err(e) => MgmtResponse::err(e),
+            }
+        }
+        Ok(MgmtRequest::Remove {
+            mac,
+            duid,
+            option82,
+            option1837,
+            subscriber_id,
+            persist,
+        }) => {
+            let _lock = lock_reservations();
+            let db = reservations.load();
+            let matched = matching(&db, mac, duid, option82, option1837, subscriber_id);
+            if matched.is_empty() {
+                MgmtResponse::err("No matching reservation")
+            } else {
+                for reservation in &matched {
+                    db.remove(reservation);
+                }
+                info!(persist, "removed reservation via TCP");
+                persist_response(&db, config_dir, persist, "Removed reservation")
+            }
+        }
+        Ok(MgmtRequest::Suspend {
+            mac,
+            duid,
+            option82,
+            option1837,
+            subscriber_id,
+            suspended,
+            persist,
+        }) => {
+            let _lock = lock_reservations();
+            let db = reservations.load();
+            let matched = matching(&db, mac, duid, option82, option1837, subscriber_id);
+            set_suspended(&db, config_dir, &matched, suspended, persist)
+        }
+        Ok(MgmtRequest::CaptureStart { mac }) => {
+            capture.start(mac);
+            info!(%mac, "started packet capture via TCP");
+            MgmtResponse::ok(format!("Capturing packets for {mac}"))
+        }
+        Ok(MgmtRequest::CaptureStop { mac }) => {
+            if capture.stop(mac) {
+                info!(%mac, "stopped packet capture via TCP");
+                MgmtResponse::ok(format!("Stopped capturing packets for {mac}"))
+            } else {
+                MgmtResponse::err(format!("Not capturing packets for {mac}"))
+            }
+        }
+        Ok(MgmtRequest::CaptureDump { mac }) => match capture.dump(mac) {
+            Some(packets) => {
+                let message = format!("{} packets captured for {mac}", packets.len());
+                MgmtResponse {
+                    packets: Some(packets),
+                    ..MgmtResponse::ok(message)
+                }
+            }
+            None => MgmtResponse::err(format!("Not capturing packets for {mac}")),
         },
+        Ok(MgmtRequest::Leases) => {
+            let leases = state.leases.snapshot();
+            let message = format!(
+                "{} DHCPv4 and {} DHCPv6 leases",
+                leases.v4.len(),
+                leases.v6.len()
+            );
+            MgmtResponse {
+                leases: Some(leases),
+                ..MgmtResponse::ok(message)
+            }
+        }
+        Ok(MgmtRequest::History {
+            mac: None,
+            duid: None,
+        }) => MgmtResponse::err("history needs a mac or duid"),
+        Ok(MgmtRequest::History { mac, duid }) => {
+            let history = state.leases.history();
+            let mut transactions: Vec<Transaction> = mac
+                .map(|mac| history.v4(mac))
+                .into_iter()
+                .chain(duid.map(|duid| history.v6(&duid)))
+                .flatten()
+                .collect();
+            transactions.sort_by_key(|t| t.timestamp);
+            let message = format!("{} transactions", transactions.len());
+            MgmtResponse {
+                history: Some(transactions),
+                ..MgmtResponse::ok(message)
+            }
+        }
+        Ok(MgmtRequest::Relays) => {
+            let relays = state.health.relays.snapshot();
+            let message = format!("{} relays", relays.len());
+            MgmtResponse {
+                relays: Some(relays),
+                ..MgmtResponse::ok(message)
+            }
+        }
+        Err(e) => MgmtResponse::err(format!("Invalid request: {}", e)),
     };
 
     let mut writer = stream;
@@ -149,14 +403,248 @@ fn handle_client(stream: TcpStream, reservations: &Arc<ArcSwap<ReservationDb>>,
     let _ = writer.write_all(b"\n");
 }
 
+/// `GET /events` upgraded to a WebSocket: every event from now on that
+/// passes the filter in the query string, each as a JSON text message. The
+/// stream gets threads of its own so the listener goes on answering
+/// commands.
+fn watch_events(stream: TcpStream, path: &str, key: &str, watchers: &EventWatchers) {
+    let (route, query) = path.split_once('?').unwrap_or((path, ""));
+    if route != "/events" {
+        return refuse_upgrade(&stream, "404 Not Found", "");
+    }
+    let filter = match WatchFilter::from_query(query) {
+        Ok(filter) => filter,
+        Err(e) => return refuse_upgrade(&stream, "400 Bad Request", &e),
+    };
+    if watchers.len() >= MAX_EVENT_STREAMS {
+        let body = format!("Already streaming to {MAX_EVENT_STREAMS} clients");
+        return refuse_upgrade(&stream, "503 Service Unavailable", &body);
+    }
+    if let Err(e) = (&stream).write_all(websocket::handshake_response(key).as_bytes()) {
+        warn!(%e, "failed to accept event stream");
+        return;
+    }
+    let events = watchers.subscribe();
+    info!(?filter, "streaming events over WebSocket");
+    let spawned = std::thread::Builder::new()
+        .name("mgmt-events".to_string())
+        .spawn(move || stream_events(stream, events, filter));
+    if let Err(e) = spawned {
+        warn!(%e, "failed to spawn event stream");
+    }
+}
+
+fn refuse_upgrade(mut stream: &TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        warn!(%e, "failed to refuse event stream");
+    }
+}
+
+/// Send the events that pass `filter` until the client closes the stream or
+/// stops reading. A second thread reads what the client sends, answering
+/// pings and noticing when it closes.
+fn stream_events(stream: TcpStream, events: mpsc::Receiver<Arc<DhcpEvent>>, filter: WatchFilter) {
+    stream.set_read_timeout(None).ok();
+    let Ok(mut reader) = stream.try_clone() else {
+        return;
+    };
+    let writer = Arc::new(Mutex::new(stream));
+    let closed = Arc::new(AtomicBool::new(false));
+    let (reader_writer, reader_closed) = (writer.clone(), closed.clone());
+    let spawned = std::thread::Builder::new()
+        .name("mgmt-events-read".to_string())
+        .spawn(move || {
+            loop {
+                match websocket::read_frame(&mut reader) {
+                    Ok(frame) if frame.opcode == websocket::PING => {
+                        let mut stream = reader_writer.lock().expect("event stream poisoned");
+                        let _ =
+                            websocket::write_frame(&mut *stream, websocket::PONG, &frame.payload);
+                    }
+                    Ok(frame) if frame.opcode != websocket::CLOSE => (),
+                    _ => break,
+                }
+            }
+            reader_closed.store(true, Ordering::Relaxed);
+            let mut stream = reader_writer.lock().expect("event stream poisoned");
+            let _ = websocket::write_frame(&mut *stream, websocket::CLOSE, &[]);
+            let _ = stream.shutdown(Shutdown::Both);
+        });
+    if spawned.is_err() {
+        return;
+    }
+
+    while !closed.load(Ordering::Relaxed) {
+        let event = match events.recv_timeout(Duration::from_secs(1)) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        if !filter.keep(&event) {
+            continue;
+        }
+        let Ok(json) = serde_json::to_string(&*event) else {
+            continue;
+        };
+        let mut stream = writer.lock().expect("event stream poisoned");
+        if websocket::write_frame(&mut *stream, websocket::TEXT, json.as_bytes()).is_err() {
+            break;
+        }
+    }
+    // Wakes the reading thread if the client hasn't closed
+    let _ = writer
+        .lock()
+        .expect("event stream poisoned")
+        .shutdown(Shutdown::Both);
+    info!("event stream closed");
+}
+
+/// Reservations stored under any of the keys, active or not, plus every
+/// reservation tagged with `subscriber_id`
+pub(crate) fn matching(
+    db: &ReservationDb,
+    mac: Option<MacAddr6>,
+    duid: Option<Duid>,
+    option82: Option<Option82>,
+    option1837: Option<Option1837>,
+    subscriber_id: Option<CompactString>,
+) -> Vec<Arc<Reservation>> {
+    let mut matched: Vec<Arc<Reservation>> = [
+        mac.map(ReservationKey::Mac),
+        duid.map(ReservationKey::Duid),
+        option82.map(|opt| ReservationKey::Opt82(opt.with_canonical_macs())),
+        option1837.map(ReservationKey::Opt1837),
+    ]
+    .into_iter()
+    .flatten()
+    .filter_map(|key| db.stored(&key))
+    .collect();
+    // Not a lookup key, so this one is a scan
+    if let Some(id) = &subscriber_id {
+        matched.extend(
+            db.reservations()
+                .into_iter()
+                .filter(|r| r.subscriber_id.as_ref() == Some(id))
+                .map(Arc::new),
+        );
+    }
+    matched
+}
+
+/// `/healthz` and `/readyz`, for load balancers and orchestrators that probe
+/// over HTTP. Both return the full report; only the status code differs.
+/// `/snmp` is the report as the table `shadowdhcp snmp` serves to snmpd.
+fn http_response(path: &str, report: &Report) -> String {
+    let ok = match path.split('?').next() {
+        Some("/healthz") => report.healthy(),
+        Some("/readyz") => report.ready(),
+        Some("/snmp") => {
+            let body = snmp::render(&snmp::table(report));
+            return format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        }
+        _ => {
+            return "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .into()
+        }
+    };
+    let status = if ok {
+        "200 OK"
+    } else {
+        "503 Service Unavailable"
+    };
+    let body = serde_json::to_string(report).unwrap_or_default();
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Suspend or resume every reservation in `matched`. Each is upserted as a
+/// whole, so it stays reachable through all of its keys.
+fn set_suspended(
+    db: &ReservationDb,
+    config_dir: &Path,
+    matched: &[Arc<Reservation>],
+    suspended: bool,
+    persist: bool,
+) -> MgmtResponse {
+    if matched.is_empty() {
+        return MgmtResponse::err("No matching reservation");
+    }
+    for reservation in matched {
+        db.upsert(Reservation {
+            suspended,
+            ..Reservation::clone(reservation)
+        });
+    }
+    let message = if suspended {
+        "Suspended reservation"
+    } else {
+        "Resumed reservation"
+    };
+    info!(suspended, persist, "changed reservation suspension via TCP");
+    persist_response(db, config_dir, persist, message)
+}
+
+/// Build the response for a single-reservation change that has already been
+/// applied in memory, writing the full set back to disk when requested.
+fn persist_response(
+    db: &ReservationDb,
+    config_dir: &Path,
+    persist: bool,
+    message: &str,
+) -> MgmtResponse {
+    if persist {
+        if let Err(e) = atomic_write_reservations(config_dir, &db.reservations()) {
+            warn!(%e, "failed to persist reservations to disk");
+            return MgmtResponse {
+                reservation_count: Some(db.len()),
+                ..MgmtResponse::err(format!(
+                    "Applied in memory but failed to write reservations: {}",
+                    e
+                ))
+            };
+        }
+    }
+    MgmtResponse {
+        reservation_count: Some(db.len()),
+        ..MgmtResponse::ok(message)
+    }
+}
+
+/// Held while the served reservations are changed and written back, by the
+/// management listener, the gRPC service, reloads and the reservation source.
+/// Without it a change applied to the database another writer is about to
+/// replace is lost, in memory and in reservations.json.
+static UPDATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Take [`UPDATE_LOCK`] for a change to the served reservations. Taken before
+/// loading the database to change and held through its write to disk.
+pub fn lock_reservations() -> MutexGuard<'static, ()> {
+    UPDATE_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Held for the whole of [`atomic_write_reservations`]. The management
+/// listener, the reservation source puller and the gRPC service all persist
+/// through it and share the temp file, so two writes must never overlap.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
 /// Atomically write reservations to disk using write-rename pattern.
 /// This ensures the file is never corrupted even if the process is killed mid-write.
-fn atomic_write_reservations(
+pub fn atomic_write_reservations(
     config_dir: &Path,
     reservations: &[Reservation],
 ) -> std::io::Result<()> {
     use std::fs::{self, File};
 
+    let _guard = WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
     let target = config_dir.join("reservations.json");
     let temp = config_dir.join("reservations.json.tmp");
 
@@ -180,6 +668,7 @@ pub fn reload_from_disk(
     reservations: &Arc<ArcSwap<ReservationDb>>,
     config_dir: &Path,
 ) -> Result<usize, String> {
+    let _lock = lock_reservations();
     let path = config_dir.join("reservations.json");
     let file = std::fs::File::open(&path)
         .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
@@ -188,8 +677,8 @@ pub fn reload_from_disk(
         .map_err(|e| format!("Failed to parse reservations: {}", e))?;
 
     let count = new_reservations.len();
-    let new_db = ReservationDb::new();
-    new_db.load_reservations(new_reservations);
+    let new_db = reservations.load().empty_like();
+    new_db.load_reservations(new_reservations)?;
     reservations.store(Arc::new(new_db));
 
     info!(count, "reloaded reservations from disk");
@@ -210,9 +699,36 @@ mod tests {
             serde_json::from_str::<MgmtRequest>(r#"{"command":"status"}"#).unwrap(),
             MgmtRequest::Status
         ));
+        assert!(matches!(
+            serde_json::from_str::<MgmtRequest>(r#"{"command":"leases"}"#).unwrap(),
+            MgmtRequest::Leases
+        ));
+        assert!(matches!(
+            serde_json::from_str::<MgmtRequest>(r#"{"command":"relays"}"#).unwrap(),
+            MgmtRequest::Relays
+        ));
         assert!(serde_json::from_str::<MgmtRequest>(r#"{"command":"bogus"}"#).is_err());
     }
 
+    #[test]
+    fn health_endpoints_answer_over_http() {
+        use crate::opt82_cache::CacheStats;
+
+        let health = Health::default();
+        health.v4.set_sockets(1);
+        let report = health.report(&Config::default(), 3, CacheStats::default());
+
+        let ready = http_response("/readyz", &report);
+        assert!(ready.starts_with("HTTP/1.1 503 "), "{ready}");
+        assert!(ready.ends_with(r#""reservations":3,"leases":{"option82_bindings":0,"option1837_bindings":0,"v4_holders":0,"v6_holders":0},"events_queued":0}"#), "{ready}");
+        assert!(http_response("/healthz?verbose", &report).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(http_response("/", &report).starts_with("HTTP/1.1 404 "));
+        let table = http_response("/snmp", &report);
+        assert!(table.starts_with("HTTP/1.1 200 OK\r\n"), "{table}");
+        assert!(table.contains("\r\n\r\n.1.1.0 counter 0\n"), "{table}");
+        assert!(table.contains("\n.3.0 gauge 3\n"), "{table}");
+    }
+
     #[test]
     fn request_parses_replace_with_reservations() {
         let req: MgmtRequest = serde_json::from_str(
@@ -224,4 +740,149 @@ mod tests {
             _ => panic!("expected replace"),
         }
     }
+
+    #[test]
+    fn request_parses_add_and_remove() {
+        let req: MgmtRequest = serde_json::from_str(
+            r#"{"command":"add","reservation":{"ipv4":"100.64.1.1","ipv6_na":"2001:db8::1","ipv6_pd":"2001:db8:1::/56","mac":"00-11-22-33-44-55"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(req, MgmtRequest::Add { persist: true, .. }));
+
+        let req: MgmtRequest = serde_json::from_str(
+            r#"{"command":"remove","mac":"00-11-22-33-44-55","persist":false}"#,
+        )
+        .unwrap();
+        match req {
+            MgmtRequest::Remove {
+                mac: Some(_),
+                duid: None,
+                persist: false,
+                ..
+            } => {}
+            _ => panic!("expected remove by mac"),
+        }
+    }
+
+    #[test]
+    fn suspend_toggles_matching_reservations() {
+        let req: MgmtRequest = serde_json::from_str(
+            r#"{"command":"suspend","subscriber_id":"258","suspended":false}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            req,
+            MgmtRequest::Suspend {
+                subscriber_id: Some(_),
+                suspended: false,
+                persist: true,
+                ..
+            }
+        ));
+
+        let db = ReservationDb::new();
+        db.insert(
+            serde_json::from_str(
+                r#"{"ipv4":"100.64.1.1","ipv6_na":"2001:db8::1","ipv6_pd":"2001:db8:1::/56","mac":"00-11-22-33-44-55","activate_at":"2999-01-01T00:00:00Z"}"#,
+            )
+            .unwrap(),
+        );
+        let mac = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        // Not yet active, but still found to change it
+        let matched = matching(&db, Some(mac), None, None, None, None);
+        assert_eq!(matched.len(), 1);
+        assert!(!matched[0].suspended);
+        let dir = std::path::Path::new("/nonexistent");
+        let tagged = matching(&db, None, None, None, None, Some("258".into()));
+        assert!(!set_suspended(&db, dir, &tagged, true, false).success);
+
+        let reservations: Vec<Reservation> = serde_json::from_str(
+            r#"[
+                {"ipv4":"100.64.1.2","ipv6_na":"2001:db8::2","ipv6_pd":"2001:db8:2::/56",
+                 "mac":"00-11-22-33-44-56","duid":"00:03:00:01:00:11:22:33:44:56","subscriber_id":"258"},
+                {"ipv4":"100.64.1.3","ipv6_na":"2001:db8::3","ipv6_pd":"2001:db8:3::/56",
+                 "option82":{"remote":"olt1:3"},"subscriber_id":"258"}
+            ]"#,
+        )
+        .unwrap();
+        db.load_reservations(reservations.clone()).unwrap();
+        let keys = [
+            ReservationKey::Mac(reservations[0].mac.unwrap()),
+            ReservationKey::Duid(reservations[0].duid.clone().unwrap()),
+            ReservationKey::Opt82(reservations[1].option82.clone().unwrap()),
+        ];
+        for suspended in [true, false] {
+            let tagged = matching(&db, None, None, None, None, Some("258".into()));
+            let resp = set_suspended(&db, dir, &tagged, suspended, false);
+            assert!(resp.success);
+            assert_eq!(resp.reservation_count, Some(3));
+            for key in &keys {
+                let stored = db.stored(key).expect("still reachable by every key");
+                assert_eq!(stored.suspended, suspended, "{key:?}");
+            }
+            // The untagged reservation is left alone
+            assert!(!db.stored(&ReservationKey::Mac(mac)).unwrap().suspended);
+        }
+    }
+
+    #[test]
+    fn concurrent_writes_leave_a_complete_file() {
+        let dir = std::env::temp_dir().join(format!("shadowdhcp-mgmt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let reservation: Reservation = serde_json::from_str(
+            r#"{"ipv4":"100.64.1.1","ipv6_na":"2001:db8::1","ipv6_pd":"2001:db8:1::/56","mac":"00-11-22-33-44-55"}"#,
+        )
+        .unwrap();
+
+        std::thread::scope(|s| {
+            for n in 1..=8 {
+                let (dir, set) = (&dir, vec![reservation.clone(); n * 50]);
+                s.spawn(move || {
+                    for _ in 0..10 {
+                        atomic_write_reservations(dir, &set).unwrap();
+                    }
+                });
+            }
+        });
+
+        let file = std::fs::File::open(dir.join("reservations.json")).unwrap();
+        let written: Vec<Reservation> = serde_json::from_reader(file).unwrap();
+        assert_eq!(written.len() % 50, 0);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn request_parses_history() {
+        let req: MgmtRequest = serde_json::from_str(
+            r#"{"command":"history","mac":"00-11-22-33-44-55","duid":"00:03:00:01:00:11:22:33:44:55"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            req,
+            MgmtRequest::History {
+                mac: Some(_),
+                duid: Some(_)
+            }
+        ));
+        assert!(matches!(
+            serde_json::from_str::<MgmtRequest>(r#"{"command":"history"}"#).unwrap(),
+            MgmtRequest::History {
+                mac: None,
+                duid: None
+            }
+        ));
+    }
+
+    #[test]
+    fn request_parses_capture_commands() {
+        let req: MgmtRequest =
+            serde_json::from_str(r#"{"command":"capture_start","mac":"00-11-22-33-44-55"}"#)
+                .unwrap();
+        assert!(matches!(req, MgmtRequest::CaptureStart { .. }));
+        let req: MgmtRequest =
+            serde_json::from_str(r#"{"command":"capture_dump","mac":"00-11-22-33-44-55"}"#)
+                .unwrap();
+        assert!(matches!(req, MgmtRequest::CaptureDump { .. }));
+        assert!(serde_json::from_str::<MgmtRequest>(r#"{"command":"capture_stop"}"#).is_err());
+    }
 }
@@ -1,35 +1,267 @@
-use std::time::{Duration, Instant};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use advmac::MacAddr6;
+use compact_str::CompactString;
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 
+use crate::analytics::events::LeaseExpiredEvent;
+use crate::ha::PeerMessage;
+use crate::history::LeaseHistory;
 use crate::reservationdb::ReservationDb;
-use crate::types::Option82;
+use crate::types::{Duid, Option1837, Option82};
+
+/// When a binding or lease was last refreshed, on both clocks. Ages are
+/// measured on the monotonic clock, so an NTP step can't expire or revive
+/// anything; the wall clock time is what gets exported and what a restart
+/// can restore from.
+#[derive(Debug, Clone, Copy)]
+pub struct LeaseTime {
+    instant: Instant,
+    system: SystemTime,
+}
+
+impl LeaseTime {
+    pub fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+        }
+    }
+
+    /// A time read back from outside the process, e.g. a persisted lease.
+    /// Its age is taken from the wall clock once, then tracked monotonically.
+    pub fn from_system(system: SystemTime) -> Self {
+        let now = Self::now();
+        let age = now.system.duration_since(system).unwrap_or_default();
+        Self {
+            instant: now.instant.checked_sub(age).unwrap_or(now.instant),
+            system,
+        }
+    }
+
+    /// Time since this one, as of `now`
+    pub fn age(&self, now: &LeaseTime) -> Duration {
+        now.instant.saturating_duration_since(self.instant)
+    }
+
+    pub fn system_time(&self) -> SystemTime {
+        self.system
+    }
+
+    /// Unix milliseconds, the format events and exports use
+    pub fn unix_millis(&self) -> u64 {
+        self.system
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
 
 /// Wrapper for Option82 with timestamp for expiry tracking.
 #[derive(Clone)]
 struct Opt82Entry {
     opt82: Option82,
-    last_seen: Instant,
+    last_seen: LeaseTime,
+}
+
+/// Wrapper for Option1837 with timestamp for expiry tracking.
+#[derive(Clone)]
+struct Opt1837Entry {
+    opt1837: Option1837,
+    last_seen: LeaseTime,
+}
+
+/// Client last handed a reserved address, for spotting a second client
+/// claiming it while the first one's lease is still running.
+#[derive(Clone)]
+struct HolderEntry<T> {
+    client: T,
+    last_seen: LeaseTime,
+    /// First grant to `client`, kept through its renewals
+    since: LeaseTime,
+    /// Lease time of the last grant
+    lease: Duration,
+    /// `subscriber_id` of the reservation the address was granted from
+    subscriber_id: Option<CompactString>,
+}
+
+impl<T> HolderEntry<T> {
+    fn expired(&self, now: &LeaseTime) -> bool {
+        self.last_seen.age(now) >= self.lease
+    }
+}
+
+/// A DHCPv4 client last ACKed a reserved address
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaseV4 {
+    pub ipv4: Ipv4Addr,
+    pub mac: MacAddr6,
+    /// The client's last seen Option 82, if it came through a relay that
+    /// adds one
+    pub option82: Option<Option82>,
+    /// `subscriber_id` of the reservation the address came from
+    #[serde(default)]
+    pub subscriber_id: Option<CompactString>,
+    /// Unix milliseconds of the last ACK
+    pub leased_at: u64,
+    /// Unix milliseconds the lease runs out unless renewed
+    pub expires_at: u64,
+}
+
+/// A DHCPv6 client last leased a reserved IA_NA address
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaseV6 {
+    pub ipv6_na: Ipv6Addr,
+    pub duid: Duid,
+    pub option1837: Option<Option1837>,
+    /// `subscriber_id` of the reservation the address came from
+    #[serde(default)]
+    pub subscriber_id: Option<CompactString>,
+    /// Unix milliseconds of the last Reply
+    pub leased_at: u64,
+    /// Unix milliseconds the valid lifetime runs out unless renewed
+    pub expires_at: u64,
+}
+
+/// Leases still running at the time of [`Opt82Cache::snapshot`], sorted by
+/// address
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LeaseSnapshot {
+    pub v4: Vec<LeaseV4>,
+    pub v6: Vec<LeaseV6>,
+}
+
+/// Entry counts of an [`Opt82Cache`], for health reports. Holders are
+/// counted until [`Opt82Cache::expire_leases`] finds their lease has run
+/// out.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CacheStats {
+    pub option82_bindings: usize,
+    pub option1837_bindings: usize,
+    pub v4_holders: usize,
+    pub v6_holders: usize,
 }
 
 /// Cache mapping client MAC addresses to the most recently observed Option82
 /// value. Bridges DHCPv4 option82 context into v6 reservation matching when
 /// the same router does both protocols and only the MAC is shared.
+///
+/// Also maps client DUIDs to the Option 18/37 value of the reservation they
+/// were last leased, so a v6-only client keeps matching when a later message
+/// arrives without the relay options (e.g. a Rebind through another relay).
+///
+/// And records which client was last leased each reserved address, so a
+/// second client taking over an Option 82/18/37 reservation before the
+/// first one's lease runs out can be reported.
+///
+/// And keeps each client's last few transactions for the `history`
+/// management command.
 #[derive(Clone)]
 pub struct Opt82Cache {
     mac_to_opt82: DashMap<MacAddr6, Opt82Entry>,
+    duid_to_opt1837: DashMap<Duid, Opt1837Entry>,
+    ipv4_holders: DashMap<Ipv4Addr, HolderEntry<MacAddr6>>,
+    ipv6_holders: DashMap<Ipv6Addr, HolderEntry<Duid>>,
+    history: LeaseHistory,
+    /// Local bindings and claims are forwarded here for the HA peer, if
+    /// configured. Bounded; a full queue drops the update rather than
+    /// blocking the worker.
+    replicate: Option<mpsc::SyncSender<PeerMessage>>,
+}
+
+impl Default for Opt82Cache {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Opt82Cache {
     pub fn new() -> Self {
         Self {
             mac_to_opt82: DashMap::new(),
+            duid_to_opt1837: DashMap::new(),
+            ipv4_holders: DashMap::new(),
+            ipv6_holders: DashMap::new(),
+            history: LeaseHistory::default(),
+            replicate: None,
+        }
+    }
+
+    pub fn with_replication(tx: mpsc::SyncSender<PeerMessage>) -> Self {
+        Self {
+            mac_to_opt82: DashMap::new(),
+            duid_to_opt1837: DashMap::new(),
+            ipv4_holders: DashMap::new(),
+            ipv6_holders: DashMap::new(),
+            history: LeaseHistory::default(),
+            replicate: Some(tx),
         }
     }
 
     pub fn insert_mac_option82_binding(&self, mac: &MacAddr6, opt: &Option82) {
+        self.insert(mac, opt);
+        self.replicate(|| PeerMessage::Binding {
+            mac: *mac,
+            option82: opt.clone(),
+        });
+    }
+
+    /// Apply an update received from the HA peer. Not replicated back.
+    pub fn apply_peer(&self, msg: PeerMessage) {
+        match msg {
+            PeerMessage::Heartbeat => {}
+            PeerMessage::Binding { mac, option82 } => self.insert(&mac, &option82),
+            PeerMessage::DuidBinding { duid, option1837 } => self.insert_duid(&duid, &option1837),
+            // The peer already decided the client gets the address
+            PeerMessage::HolderV4 {
+                ipv4,
+                mac,
+                subscriber_id,
+                lease_secs,
+            } => {
+                let lease = Duration::from_secs(lease_secs);
+                claim(
+                    &self.ipv4_holders,
+                    ipv4,
+                    mac,
+                    subscriber_id.as_ref(),
+                    lease,
+                    true,
+                    LeaseTime::now(),
+                );
+            }
+            PeerMessage::HolderV6 {
+                ipv6_na,
+                duid,
+                subscriber_id,
+                lease_secs,
+            } => {
+                let lease = Duration::from_secs(lease_secs);
+                claim(
+                    &self.ipv6_holders,
+                    ipv6_na,
+                    duid,
+                    subscriber_id.as_ref(),
+                    lease,
+                    true,
+                    LeaseTime::now(),
+                );
+            }
+            PeerMessage::ReleaseV6 { ipv6_na, duid } => self.release(ipv6_na, &duid),
+        }
+    }
+
+    fn replicate(&self, msg: impl FnOnce() -> PeerMessage) {
+        if let Some(tx) = &self.replicate {
+            let _ = tx.try_send(msg());
+        }
+    }
+
+    fn insert(&self, mac: &MacAddr6, opt: &Option82) {
         self.mac_to_opt82
             .entry(*mac)
             .and_modify(|entry| {
@@ -37,13 +269,13 @@ impl Opt82Cache {
                     debug!(%mac, old = ?entry.opt82, new = ?opt, "updated mac -> option82 binding");
                     entry.opt82 = opt.clone();
                 }
-                entry.last_seen = Instant::now();
+                entry.last_seen = LeaseTime::now();
             })
             .or_insert_with(|| {
                 debug!(%mac, option82 = ?opt, "added mac -> option82 binding");
                 Opt82Entry {
                     opt82: opt.clone(),
-                    last_seen: Instant::now(),
+                    last_seen: LeaseTime::now(),
                 }
             });
     }
@@ -54,23 +286,207 @@ impl Opt82Cache {
             .map(|entry| entry.opt82.clone())
     }
 
-    /// Remove expired and orphaned mac -> option82 bindings.
+    /// Bind a client DUID to the Option 18/37 value of its reservation
+    pub fn insert_duid_option1837_binding(&self, duid: &Duid, opt: &Option1837) {
+        self.insert_duid(duid, opt);
+        self.replicate(|| PeerMessage::DuidBinding {
+            duid: duid.clone(),
+            option1837: opt.clone(),
+        });
+    }
+
+    fn insert_duid(&self, duid: &Duid, opt: &Option1837) {
+        self.duid_to_opt1837
+            .entry(duid.clone())
+            .and_modify(|entry| {
+                if entry.opt1837 != *opt {
+                    debug!(%duid, old = ?entry.opt1837, new = ?opt, "updated duid -> option1837 binding");
+                    entry.opt1837 = opt.clone();
+                }
+                entry.last_seen = LeaseTime::now();
+            })
+            .or_insert_with(|| {
+                debug!(%duid, option1837 = ?opt, "added duid -> option1837 binding");
+                Opt1837Entry {
+                    opt1837: opt.clone(),
+                    last_seen: LeaseTime::now(),
+                }
+            });
+    }
+
+    pub fn get_opt1837_by_duid(&self, duid: &Duid) -> Option<Option1837> {
+        self.duid_to_opt1837
+            .get(duid)
+            .map(|entry| entry.opt1837.clone())
+    }
+
+    /// Record `mac` as holding `ip`, granted from the reservation tagged
+    /// `subscriber_id`. If a different client was leased `ip` less than
+    /// `hold` ago, that client is returned, and `mac` only takes over the
+    /// address when `take_over` is set.
+    pub fn claim_ipv4(
+        &self,
+        ip: Ipv4Addr,
+        mac: MacAddr6,
+        subscriber_id: Option<&CompactString>,
+        hold: Duration,
+        take_over: bool,
+    ) -> Option<MacAddr6> {
+        let previous = claim(
+            &self.ipv4_holders,
+            ip,
+            mac,
+            subscriber_id,
+            hold,
+            take_over,
+            LeaseTime::now(),
+        );
+        if previous.is_none() || take_over {
+            self.replicate(|| PeerMessage::HolderV4 {
+                ipv4: ip,
+                mac,
+                subscriber_id: subscriber_id.cloned(),
+                lease_secs: hold.as_secs(),
+            });
+        }
+        previous
+    }
+
+    /// DHCPv6 counterpart of [`Self::claim_ipv4`], keyed by the reserved
+    /// IA_NA address and the client's DUID
+    pub fn claim_ipv6(
+        &self,
+        addr: Ipv6Addr,
+        duid: &Duid,
+        subscriber_id: Option<&CompactString>,
+        hold: Duration,
+        take_over: bool,
+    ) -> Option<Duid> {
+        let previous = claim(
+            &self.ipv6_holders,
+            addr,
+            duid.clone(),
+            subscriber_id,
+            hold,
+            take_over,
+            LeaseTime::now(),
+        );
+        if previous.is_none() || take_over {
+            self.replicate(|| PeerMessage::HolderV6 {
+                ipv6_na: addr,
+                duid: duid.clone(),
+                subscriber_id: subscriber_id.cloned(),
+                lease_secs: hold.as_secs(),
+            });
+        }
+        previous
+    }
+
+    /// Recent transactions per client
+    pub fn history(&self) -> &LeaseHistory {
+        &self.history
+    }
+
+    /// Stop holding `addr` for `duid` after it sent a Release. An address
+    /// since claimed by another client is left alone.
+    pub fn release_ipv6(&self, addr: Ipv6Addr, duid: &Duid) {
+        self.release(addr, duid);
+        self.replicate(|| PeerMessage::ReleaseV6 {
+            ipv6_na: addr,
+            duid: duid.clone(),
+        });
+    }
+
+    fn release(&self, addr: Ipv6Addr, duid: &Duid) {
+        self.ipv6_holders
+            .remove_if(&addr, |_, entry| entry.client == *duid);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            option82_bindings: self.mac_to_opt82.len(),
+            option1837_bindings: self.duid_to_opt1837.len(),
+            v4_holders: self.ipv4_holders.len(),
+            v6_holders: self.ipv6_holders.len(),
+        }
+    }
+
+    /// The leases that haven't run out yet, with wall clock times so they can
+    /// be exported. Each runs for the lease time it was granted with. Only
+    /// addresses handed out from a reservation are tracked, so unanswered
+    /// clients never appear.
+    pub fn snapshot(&self) -> LeaseSnapshot {
+        self.snapshot_at(LeaseTime::now())
+    }
+
+    fn snapshot_at(&self, now: LeaseTime) -> LeaseSnapshot {
+        // Entries are copied out before the Option 82/18/37 lookups so no
+        // two shard locks are held at once
+        let holders: Vec<_> = self
+            .ipv4_holders
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        let mut v4: Vec<_> = holders
+            .into_iter()
+            .filter_map(|(ipv4, entry)| {
+                let (leased_at, expires_at) = lease_window(&now, &entry.last_seen, entry.lease)?;
+                Some(LeaseV4 {
+                    ipv4,
+                    mac: entry.client,
+                    option82: self.get_opt82_by_mac(&entry.client),
+                    subscriber_id: entry.subscriber_id,
+                    leased_at,
+                    expires_at,
+                })
+            })
+            .collect();
+        v4.sort_unstable_by_key(|lease| lease.ipv4);
+
+        let holders: Vec<_> = self
+            .ipv6_holders
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        let mut v6: Vec<_> = holders
+            .into_iter()
+            .filter_map(|(ipv6_na, entry)| {
+                let (leased_at, expires_at) = lease_window(&now, &entry.last_seen, entry.lease)?;
+                Some(LeaseV6 {
+                    ipv6_na,
+                    option1837: self.get_opt1837_by_duid(&entry.client),
+                    duid: entry.client,
+                    subscriber_id: entry.subscriber_id,
+                    leased_at,
+                    expires_at,
+                })
+            })
+            .collect();
+        v6.sort_unstable_by_key(|lease| lease.ipv6_na);
+
+        LeaseSnapshot { v4, v6 }
+    }
+
+    /// Remove expired and orphaned mac -> option82 and duid -> option1837
+    /// bindings.
     ///
     /// - `opt82_max_age`: maximum age before a binding is dropped (time-based).
     /// - `reservations`: current reservation database; bindings whose Option82
-    ///   no longer corresponds to any reservation are pruned.
+    ///   or Option1837 no longer corresponds to any reservation are pruned.
     pub fn evict_expired(&self, opt82_max_age: Duration, reservations: &ReservationDb) {
-        let now = Instant::now();
+        let now = LeaseTime::now();
 
+        // Workers keep inserting while this runs, so a map can grow between
+        // two `len` calls; the counts are only for the log line.
         let before = self.mac_to_opt82.len();
         self.mac_to_opt82
-            .retain(|_mac, entry| now.duration_since(entry.last_seen) < opt82_max_age);
-        let expired = before - self.mac_to_opt82.len();
+            .retain(|_mac, entry| entry.last_seen.age(&now) < opt82_max_age);
+        let expired = before.saturating_sub(self.mac_to_opt82.len());
 
         let after_expire = self.mac_to_opt82.len();
         self.mac_to_opt82
             .retain(|_mac, entry| reservations.has_opt82(&entry.opt82));
-        let orphaned = after_expire - self.mac_to_opt82.len();
+        let orphaned = after_expire.saturating_sub(self.mac_to_opt82.len());
 
         if expired > 0 || orphaned > 0 {
             info!(
@@ -80,7 +496,118 @@ impl Opt82Cache {
                 "evicted expired option82 bindings"
             );
         }
+
+        let before = self.duid_to_opt1837.len();
+        self.duid_to_opt1837.retain(|_duid, entry| {
+            entry.last_seen.age(&now) < opt82_max_age && reservations.has_opt1837(&entry.opt1837)
+        });
+        let evicted = before.saturating_sub(self.duid_to_opt1837.len());
+        if evicted > 0 {
+            info!(
+                evicted,
+                remaining = self.duid_to_opt1837.len(),
+                "evicted expired option1837 bindings"
+            );
+        }
+
+        self.history.evict_expired(opt82_max_age);
+    }
+
+    /// Stop holding the addresses whose lease has run out without being
+    /// renewed, returning an event for each with the client's Option
+    /// 82/18/37 as last seen. A released address is no longer held, so it
+    /// never shows up here.
+    pub fn expire_leases(&self) -> Vec<LeaseExpiredEvent> {
+        self.expire_leases_at(LeaseTime::now())
+    }
+
+    fn expire_leases_at(&self, now: LeaseTime) -> Vec<LeaseExpiredEvent> {
+        let mut v4 = Vec::new();
+        self.ipv4_holders.retain(|ip, entry| {
+            let expired = entry.expired(&now);
+            if expired {
+                v4.push((*ip, entry.clone()));
+            }
+            !expired
+        });
+        let mut v6 = Vec::new();
+        self.ipv6_holders.retain(|addr, entry| {
+            let expired = entry.expired(&now);
+            if expired {
+                v6.push((*addr, entry.clone()));
+            }
+            !expired
+        });
+
+        // Looked up once the holder maps are unlocked, so no two shard locks
+        // are held at once
+        let v4 = v4.into_iter().map(|(ip, entry)| {
+            LeaseExpiredEvent::v4(
+                ip,
+                entry.client,
+                self.get_opt82_by_mac(&entry.client).as_ref(),
+                entry.since.unix_millis(),
+                entry.last_seen.unix_millis(),
+                entry.lease,
+            )
+        });
+        let v6 = v6.into_iter().map(|(addr, entry)| {
+            LeaseExpiredEvent::v6(
+                addr,
+                &entry.client,
+                self.get_opt1837_by_duid(&entry.client).as_ref(),
+                entry.since.unix_millis(),
+                entry.last_seen.unix_millis(),
+                entry.lease,
+            )
+        });
+        v4.chain(v6).collect()
+    }
+}
+
+/// Unix milliseconds a lease last seen at `last_seen` started and ends, or
+/// None if it has already run out
+fn lease_window(now: &LeaseTime, last_seen: &LeaseTime, lifetime: Duration) -> Option<(u64, u64)> {
+    lifetime
+        .checked_sub(last_seen.age(now))
+        .filter(|remaining| !remaining.is_zero())?;
+    let leased_at = last_seen.unix_millis();
+    Some((leased_at, leased_at + lifetime.as_millis() as u64))
+}
+
+fn claim<K, T>(
+    holders: &DashMap<K, HolderEntry<T>>,
+    key: K,
+    client: T,
+    subscriber_id: Option<&CompactString>,
+    hold: Duration,
+    take_over: bool,
+    now: LeaseTime,
+) -> Option<T>
+where
+    K: std::hash::Hash + Eq,
+    T: Clone + PartialEq,
+{
+    let mut entry = holders.entry(key).or_insert_with(|| HolderEntry {
+        client: client.clone(),
+        last_seen: now,
+        since: now,
+        lease: hold,
+        subscriber_id: subscriber_id.cloned(),
+    });
+    let previous =
+        (entry.client != client && entry.last_seen.age(&now) < hold).then(|| entry.client.clone());
+    if previous.is_none() || take_over {
+        // A client back after its lease ran out starts over
+        if entry.client != client || entry.expired(&now) {
+            entry.since = now;
+        }
+        entry.client = client;
+        entry.last_seen = now;
+        entry.lease = hold;
+        entry.subscriber_id = subscriber_id.cloned();
     }
+    previous
 }
 
 #[cfg(test)]
@@ -107,14 +634,25 @@ mod tests {
     fn reservations_with_opt82(opt82: Option82) -> ReservationDb {
         let db = ReservationDb::new();
         db.load_reservations(vec![Reservation {
-            ipv4: Ipv4Addr::new(10, 0, 0, 1),
-            ipv6_na: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+            ipv4: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            ipv6_na: Some(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
             ipv6_pd: "2001:db8:1::/48".parse::<Ipv6Net>().unwrap(),
             mac: None,
             duid: None,
+            client_id: None,
             option82: Some(opt82),
             option1837: None,
-        }]);
+            svlan: None,
+            cvlan: None,
+            subscriber_id: None,
+            static_routes: vec![],
+            boot: None,
+            profile: None,
+            activate_at: None,
+            deactivate_at: None,
+            suspended: false,
+        }])
+        .unwrap();
         db
     }
 
@@ -209,4 +747,293 @@ mod tests {
             "binding without reservation should be pruned"
         );
     }
+
+    #[test]
+    fn evict_orphaned_opt1837_bindings() {
+        let cache = Opt82Cache::new();
+        let opt1837 = Option1837 {
+            interface: Some("eth0:100".into()),
+            remote: None,
+            enterprise_number: None,
+        };
+        let db = ReservationDb::new();
+        db.load_reservations(vec![Reservation {
+            ipv4: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            ipv6_na: Some(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+            ipv6_pd: "2001:db8:1::/48".parse::<Ipv6Net>().unwrap(),
+            mac: None,
+            duid: None,
+            client_id: None,
+            option82: None,
+            option1837: Some(opt1837.clone()),
+            svlan: None,
+            cvlan: None,
+            subscriber_id: None,
+            static_routes: vec![],
+            boot: None,
+            profile: None,
+            activate_at: None,
+            deactivate_at: None,
+            suspended: false,
+        }])
+        .unwrap();
+        let valid_duid = Duid::from(vec![0x00, 0x01]);
+        let orphan_duid = Duid::from(vec![0x00, 0x02]);
+
+        cache.insert_duid_option1837_binding(&valid_duid, &opt1837);
+        cache.insert_duid_option1837_binding(
+            &orphan_duid,
+            &Option1837 {
+                interface: Some("orphan".into()),
+                remote: None,
+                enterprise_number: None,
+            },
+        );
+
+        cache.evict_expired(Duration::from_secs(3600), &db);
+
+        assert_eq!(cache.get_opt1837_by_duid(&valid_duid), Some(opt1837));
+        assert!(cache.get_opt1837_by_duid(&orphan_duid).is_none());
+    }
+
+    #[test]
+    fn second_client_claiming_held_address_is_reported() {
+        let cache = Opt82Cache::new();
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let hold = Duration::from_secs(3600);
+
+        assert_eq!(
+            cache.claim_ipv4(ip, test_mac(0x01), None, hold, false),
+            None
+        );
+        assert_eq!(
+            cache.claim_ipv4(ip, test_mac(0x01), None, hold, false),
+            None
+        );
+        // Refused: the first client keeps the address
+        assert_eq!(
+            cache.claim_ipv4(ip, test_mac(0x02), None, hold, false),
+            Some(test_mac(0x01))
+        );
+        assert_eq!(
+            cache.claim_ipv4(ip, test_mac(0x02), None, hold, true),
+            Some(test_mac(0x01))
+        );
+        assert_eq!(
+            cache.claim_ipv4(ip, test_mac(0x01), None, hold, true),
+            Some(test_mac(0x02))
+        );
+
+        sleep(Duration::from_millis(10));
+        assert_eq!(
+            cache.claim_ipv4(ip, test_mac(0x02), None, Duration::from_millis(5), false),
+            None,
+            "holder whose lease ran out is not a conflict"
+        );
+    }
+
+    #[test]
+    fn released_address_is_not_a_conflict() {
+        let cache = Opt82Cache::new();
+        let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let first = Duid::from(vec![
+            0x00, 0x03, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ]);
+        let second = Duid::from(vec![
+            0x00, 0x03, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02,
+        ]);
+        let hold = Duration::from_secs(3600);
+
+        cache.claim_ipv6(addr, &first, None, hold, false);
+        // Only the holder can release
+        cache.release_ipv6(addr, &second);
+        assert_eq!(
+            cache.claim_ipv6(addr, &second, None, hold, false),
+            Some(first.clone())
+        );
+        cache.release_ipv6(addr, &first);
+        assert_eq!(cache.claim_ipv6(addr, &second, None, hold, false), None);
+    }
+
+    #[test]
+    fn peer_learns_bindings_and_holders() {
+        let (tx, rx) = mpsc::sync_channel(16);
+        let primary = Opt82Cache::with_replication(tx);
+        let standby = Opt82Cache::new();
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let duid = Duid::from(vec![0x00, 0x01]);
+        let opt1837 = Option1837 {
+            interface: Some("eth0".into()),
+            remote: None,
+            enterprise_number: None,
+        };
+        let hold = Duration::from_secs(3600);
+
+        primary.insert_mac_option82_binding(&test_mac(0x01), &test_option82());
+        primary.insert_duid_option1837_binding(&duid, &opt1837);
+        primary.claim_ipv4(ip, test_mac(0x01), None, hold, false);
+        primary.claim_ipv6(addr, &duid, None, hold, false);
+        // Refused, so the peer doesn't hear of it
+        primary.claim_ipv4(ip, test_mac(0x02), None, hold, false);
+        rx.try_iter().for_each(|msg| standby.apply_peer(msg));
+
+        assert_eq!(
+            standby.get_opt82_by_mac(&test_mac(0x01)),
+            Some(test_option82())
+        );
+        assert_eq!(standby.get_opt1837_by_duid(&duid), Some(opt1837));
+        assert_eq!(
+            standby.claim_ipv4(ip, test_mac(0x02), None, hold, false),
+            Some(test_mac(0x01))
+        );
+        let other = Duid::from(vec![0x00, 0x02]);
+        assert_eq!(
+            standby.claim_ipv6(addr, &other, None, hold, false),
+            Some(duid.clone())
+        );
+
+        primary.release_ipv6(addr, &duid);
+        rx.try_iter().for_each(|msg| standby.apply_peer(msg));
+        assert_eq!(standby.claim_ipv6(addr, &other, None, hold, false), None);
+    }
+
+    #[test]
+    fn expired_leases_reported_once_from_first_grant() {
+        let cache = Opt82Cache::new();
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let duid = Duid::from(vec![
+            0x00, 0x03, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ]);
+        let start = LeaseTime::now();
+        let at = |ms| {
+            let elapsed = Duration::from_millis(ms);
+            LeaseTime {
+                instant: start.instant + elapsed,
+                system: start.system + elapsed,
+            }
+        };
+        let hold = Duration::from_secs;
+        cache.insert_mac_option82_binding(&test_mac(0x01), &test_option82());
+        claim(
+            &cache.ipv4_holders,
+            ip,
+            test_mac(0x01),
+            None,
+            hold(5),
+            false,
+            at(0),
+        );
+        claim(
+            &cache.ipv6_holders,
+            addr,
+            duid.clone(),
+            None,
+            hold(5),
+            false,
+            at(0),
+        );
+        claim(
+            &cache.ipv4_holders,
+            Ipv4Addr::new(10, 0, 0, 2),
+            test_mac(0x02),
+            None,
+            hold(3600),
+            false,
+            at(0),
+        );
+        // A renewal keeps the session going
+        claim(
+            &cache.ipv4_holders,
+            ip,
+            test_mac(0x01),
+            None,
+            hold(10),
+            false,
+            at(3000),
+        );
+
+        assert!(cache.expire_leases_at(at(4999)).is_empty());
+        let expired = cache.expire_leases_at(at(5000));
+        assert_eq!(expired.len(), 1, "v6 lease ran out, v4 was renewed");
+        let v6 = &expired[0];
+        assert_eq!(v6.protocol, "v6");
+        assert_eq!(v6.ipv6_na, Some(addr));
+        assert_eq!(v6.client_id, Some(duid.to_string()));
+
+        assert!(cache.expire_leases_at(at(12_999)).is_empty());
+        let expired = cache.expire_leases_at(at(13_000));
+        assert_eq!(expired.len(), 1);
+        let v4 = &expired[0];
+        assert_eq!(v4.protocol, "v4");
+        assert_eq!(v4.ipv4, Some(ip));
+        assert_eq!(v4.mac_address, Some(test_mac(0x01)));
+        assert_eq!(v4.option82_circuit.as_deref(), Some("circuit1"));
+        assert_eq!(v4.leased_at, at(0).unix_millis());
+        assert_eq!(v4.renewed_at, at(3000).unix_millis());
+        assert_eq!(v4.expired_at, at(13_000).unix_millis());
+
+        assert!(cache.expire_leases_at(at(13_000)).is_empty());
+        assert_eq!(cache.stats().v4_holders, 1, "running lease still held");
+    }
+
+    #[test]
+    fn snapshot_lists_running_leases_with_wall_clock_times() {
+        let cache = Opt82Cache::new();
+        let hold = Duration::from_secs(3600);
+        cache.insert_mac_option82_binding(&test_mac(0x01), &test_option82());
+        cache.claim_ipv4(
+            Ipv4Addr::new(10, 0, 0, 2),
+            test_mac(0x02),
+            None,
+            hold,
+            false,
+        );
+        cache.claim_ipv4(
+            Ipv4Addr::new(10, 0, 0, 1),
+            test_mac(0x01),
+            None,
+            hold,
+            false,
+        );
+        let duid = Duid::from(vec![
+            0x00, 0x03, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ]);
+        cache.claim_ipv6(
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+            &duid,
+            None,
+            hold,
+            false,
+        );
+
+        let snapshot = cache.snapshot();
+        assert_eq!(snapshot.v4.len(), 2);
+        assert_eq!(snapshot.v4[0].ipv4, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(snapshot.v4[0].option82, Some(test_option82()));
+        assert_eq!(snapshot.v4[1].option82, None);
+        let lease = &snapshot.v4[0];
+        assert_eq!(lease.expires_at - lease.leased_at, 3_600_000);
+        assert_eq!(snapshot.v6.len(), 1);
+        assert_eq!(snapshot.v6[0].duid, duid);
+
+        // Leases that have run out are left out
+        let later = LeaseTime::now();
+        let later = LeaseTime {
+            instant: later.instant + hold,
+            system: later.system + hold,
+        };
+        let snapshot = cache.snapshot_at(later);
+        assert!(snapshot.v4.is_empty() && snapshot.v6.is_empty());
+    }
+
+    #[test]
+    fn lease_time_restored_from_wall_clock_keeps_its_age() {
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        let restored = LeaseTime::from_system(hour_ago);
+        assert_eq!(restored.system_time(), hour_ago);
+        let age = restored.age(&LeaseTime::now());
+        assert!(age >= Duration::from_secs(3600) && age < Duration::from_secs(3601));
+    }
 }
@@ -0,0 +1,86 @@
+//! Why the server stayed silent, shared by DHCPv4 and DHCPv6 so events and
+//! logs report one set of reasons for both protocols.
+
+use std::fmt;
+
+/// A message the server deliberately didn't answer.
+///
+/// [`NoResponse::as_str`] is the `failure_reason` written to events, so the
+/// strings are stable across releases. Any rename is a breaking change and is
+/// listed under "Renamed reasons" in `docs/events.md`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoResponse {
+    /// No reservation matched the client
+    NoReservation,
+    /// DHCPv4: no reservation matched and the relay isn't in an
+    /// `authoritative` subnet, so another server may own the client
+    NotAuthoritative,
+    /// The reservation's address isn't in any configured subnet
+    SubnetMismatch,
+    /// DHCPv4: a DISCOVER for a suspended reservation that can't be put in
+    /// a walled garden. Its REQUESTs are NAKed instead.
+    Suspended,
+    /// Dropped by the per-client or per-relay rate limit
+    RateLimited,
+    /// An option the reply depends on couldn't be parsed, such as a DHCPv6
+    /// Client Identifier that isn't a valid DUID
+    MalformedOption,
+    /// DHCPv4: chaddr isn't a 6-byte MAC
+    NoValidMac,
+    /// DHCPv4: no DHCP Message Type option
+    NoMessageType,
+    /// DHCPv4: giaddr unset, so the message never passed through a relay.
+    /// DHCPv6: a message sent straight to `v6_server_unicast` that has to
+    /// be multicast.
+    NotRelayed,
+    /// DHCPv6: no Client Identifier option
+    NoClientId,
+    /// DHCPv6: a Server Identifier in a message that mustn't carry one
+    UnexpectedServerId,
+    /// DHCPv6: no Server Identifier in a message that must carry one
+    NoServerId,
+    /// The message names another server
+    WrongServerId,
+    /// A message type or variant the server doesn't answer
+    Discarded,
+    /// Passed on to the `fallback` server to answer, see
+    /// [`crate::fallback`]
+    Forwarded,
+}
+
+impl NoResponse {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NoResponse::NoReservation => "NoReservation",
+            NoResponse::NotAuthoritative => "NotAuthoritative",
+            NoResponse::SubnetMismatch => "SubnetMismatch",
+            NoResponse::Suspended => "Suspended",
+            NoResponse::RateLimited => "RateLimited",
+            NoResponse::MalformedOption => "MalformedOption",
+            NoResponse::NoValidMac => "NoValidMac",
+            NoResponse::NoMessageType => "NoMessageType",
+            NoResponse::NotRelayed => "NotRelayed",
+            NoResponse::NoClientId => "NoClientId",
+            NoResponse::UnexpectedServerId => "UnexpectedServerId",
+            NoResponse::NoServerId => "NoServerId",
+            NoResponse::WrongServerId => "WrongServerId",
+            NoResponse::Discarded => "Discarded",
+            NoResponse::Forwarded => "Forwarded",
+        }
+    }
+
+    /// An unknown client rather than a problem with the packet. The workers
+    /// log these at `info` and everything else at `debug`.
+    pub fn is_unknown_client(&self) -> bool {
+        matches!(
+            self,
+            NoResponse::NoReservation | NoResponse::NotAuthoritative
+        )
+    }
+}
+
+impl fmt::Display for NoResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
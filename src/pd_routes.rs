@@ -0,0 +1,389 @@
+//! Routes for delegated prefixes, from the `pd_routes` config. The routers
+//! that forward to subscribers need a route for each delegated prefix
+//! towards the CPE it was delegated to; this keeps them in step with the
+//! leases instead of with the reservations file.
+//!
+//! The v6 workers pass every Reply that binds a prefix, and every Release
+//! of one, to the `pd-routes` thread. It keeps the live prefixes with their
+//! next hop, the CPE's IA_NA address from the same Reply, or the relay the
+//! request came through when there is none, and runs the configured actions
+//! when a prefix is first bound, moves to another next hop, or is released
+//! or outlives its valid lifetime. Renewals that change nothing run nothing.
+//!
+//! Like the lease cache the table is in memory. After a restart routes are
+//! added again as clients renew, and routes added before it are not deleted
+//! by this server.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use dhcproto::v6;
+use ipnet::Ipv6Net;
+use tracing::{debug, error, warn};
+
+use crate::config::PdRoutesConfig;
+use crate::types::Reservation;
+use crate::v6::extensions::ShadowMessageExtV6;
+
+/// Updates buffered between the workers and the route thread
+pub const PD_ROUTES_QUEUE: usize = 4096;
+/// How often prefixes past their valid lifetime are deleted
+const EXPIRY_CHECK: Duration = Duration::from_secs(10);
+
+/// What a DHCPv6 Reply did to a delegated prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PdUpdate {
+    Bound {
+        prefix: Ipv6Net,
+        next_hop: Ipv6Addr,
+        valid_secs: u32,
+    },
+    Released {
+        prefix: Ipv6Net,
+    },
+}
+
+impl PdUpdate {
+    /// The update for a Reply to `msg`, `None` if it neither binds a prefix
+    /// nor acknowledges the release of the reservation's one. `relay_addr`
+    /// is the next hop when the Reply carries no IA_NA address.
+    pub fn from_reply(
+        msg: &v6::Message,
+        reply: &v6::Message,
+        relay_addr: IpAddr,
+        reservation: Option<&Reservation>,
+    ) -> Option<Self> {
+        if reply.msg_type() != v6::MessageType::Reply {
+            return None;
+        }
+        if msg.msg_type() == v6::MessageType::Release {
+            // Only the reservation's own prefix, so a client can't take
+            // down a route it was never delegated
+            let prefix = reservation?.ipv6_pd;
+            let released = msg.ia_pd()?.opts.iter().any(|opt| {
+                matches!(opt, v6::DhcpOption::IAPrefix(ia)
+                    if Ipv6Net::new(ia.prefix_ip, ia.prefix_len).is_ok_and(|net| net == prefix))
+            });
+            return released.then_some(PdUpdate::Released { prefix });
+        }
+        // Zero lifetimes hand back a stale prefix; only the live one binds
+        let (prefix, valid_secs) = reply.ia_pd()?.opts.iter().find_map(|opt| match opt {
+            v6::DhcpOption::IAPrefix(ia) if ia.valid_lifetime != 0 => {
+                Ipv6Net::new(ia.prefix_ip, ia.prefix_len)
+                    .ok()
+                    .map(|net| (net, ia.valid_lifetime))
+            }
+            _ => None,
+        })?;
+        let na = reply.ia_na().and_then(|na| {
+            na.opts.iter().find_map(|opt| match opt {
+                v6::DhcpOption::IAAddr(ia) if ia.valid_life != 0 => Some(ia.addr),
+                _ => None,
+            })
+        });
+        let next_hop = match (na, relay_addr) {
+            (Some(na), _) => na,
+            (None, IpAddr::V6(relay)) => relay,
+            (None, IpAddr::V4(_)) => return None,
+        };
+        Some(PdUpdate::Bound {
+            prefix,
+            next_hop,
+            valid_secs,
+        })
+    }
+}
+
+/// Queue an update for the route thread. Never blocks a worker: an update
+/// with no room is dropped and the client's next renewal sends it again.
+pub fn send(routes: &Option<mpsc::SyncSender<PdUpdate>>, update: Option<PdUpdate>) {
+    if let (Some(tx), Some(update)) = (routes, update) {
+        match tx.try_send(update) {
+            Ok(()) => (),
+            Err(mpsc::TrySendError::Full(update)) => {
+                warn!(?update, "Prefix route queue is full, update dropped")
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                error!("Prefix route thread is gone, update dropped")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Add,
+    Change,
+    Delete,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Add => "add",
+            Action::Change => "change",
+            Action::Delete => "delete",
+        }
+    }
+}
+
+/// A route to run the configured actions for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteChange {
+    pub action: Action,
+    pub prefix: Ipv6Net,
+    /// For a delete, the next hop the route had
+    pub next_hop: Ipv6Addr,
+}
+
+#[derive(Debug)]
+struct Route {
+    next_hop: Ipv6Addr,
+    expires: Instant,
+}
+
+/// The prefixes currently bound
+#[derive(Debug, Default)]
+pub struct RouteTable {
+    routes: BTreeMap<Ipv6Net, Route>,
+}
+
+impl RouteTable {
+    /// Apply `update` at `now`, and the route change it makes, if any
+    pub fn update(&mut self, update: PdUpdate, now: Instant) -> Option<RouteChange> {
+        match update {
+            PdUpdate::Bound {
+                prefix,
+                next_hop,
+                valid_secs,
+            } => {
+                let expires = now + Duration::from_secs(valid_secs.into());
+                let previous = self
+                    .routes
+                    .insert(prefix, Route { next_hop, expires })
+                    .map(|route| route.next_hop);
+                let action = match previous {
+                    None => Action::Add,
+                    Some(previous) if previous != next_hop => Action::Change,
+                    Some(_) => return None,
+                };
+                Some(RouteChange {
+                    action,
+                    prefix,
+                    next_hop,
+                })
+            }
+            PdUpdate::Released { prefix } => self.routes.remove(&prefix).map(|route| RouteChange {
+                action: Action::Delete,
+                prefix,
+                next_hop: route.next_hop,
+            }),
+        }
+    }
+
+    /// Remove the prefixes whose valid lifetime ran out by `now`
+    pub fn expire(&mut self, now: Instant) -> Vec<RouteChange> {
+        let mut expired = Vec::new();
+        self.routes.retain(|prefix, route| {
+            if route.expires > now {
+                return true;
+            }
+            expired.push(RouteChange {
+                action: Action::Delete,
+                prefix: *prefix,
+                next_hop: route.next_hop,
+            });
+            false
+        });
+        expired
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// A BIRD `static` protocol route for each prefix, in prefix order
+    pub fn render(&self) -> String {
+        let mut file = String::from("# Generated by shadowdhcp from its DHCPv6 leases\n");
+        for (prefix, route) in &self.routes {
+            file.push_str(&format!("route {prefix} via {};\n", route.next_hop));
+        }
+        file
+    }
+}
+
+/// ExaBGP API command for `change`. A change is announced again with the
+/// new next hop, which replaces the old route.
+pub fn exabgp_command(change: &RouteChange) -> String {
+    let verb = match change.action {
+        Action::Add | Action::Change => "announce",
+        Action::Delete => "withdraw",
+    };
+    format!(
+        "{verb} route {} next-hop {}\n",
+        change.prefix, change.next_hop
+    )
+}
+
+/// Route thread. Runs until every worker has dropped its sender.
+pub fn run(config: PdRoutesConfig, rx: mpsc::Receiver<PdUpdate>) {
+    let mut table = RouteTable::default();
+    let mut last_expiry = Instant::now();
+    loop {
+        let mut changes = Vec::new();
+        match rx.recv_timeout(EXPIRY_CHECK) {
+            Ok(update) => {
+                let now = Instant::now();
+                changes.extend(table.update(update, now));
+                // Batch up whatever else is queued before running anything
+                while let Ok(update) = rx.try_recv() {
+                    changes.extend(table.update(update, now));
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        }
+        if last_expiry.elapsed() >= EXPIRY_CHECK {
+            last_expiry = Instant::now();
+            changes.extend(table.expire(last_expiry));
+        }
+        if !changes.is_empty() {
+            apply(&config, &table, &changes);
+        }
+    }
+}
+
+fn apply(config: &PdRoutesConfig, table: &RouteTable, changes: &[RouteChange]) {
+    for change in changes {
+        debug!(
+            action = change.action.as_str(),
+            prefix = %change.prefix,
+            next_hop = %change.next_hop,
+            "Prefix route"
+        );
+    }
+    if let Some(script) = &config.exec {
+        for change in changes {
+            run_script(script, change);
+        }
+    }
+    if let Some(pipe) = &config.exabgp_pipe {
+        let commands: String = changes.iter().map(exabgp_command).collect();
+        if let Err(e) = write_pipe(pipe, commands.as_bytes()) {
+            error!(
+                "Unable to send {} route updates to ExaBGP at {}: {e}",
+                changes.len(),
+                pipe.display()
+            );
+        }
+    }
+    if let Some(path) = &config.route_file {
+        match write_route_file(path, &table.render()) {
+            Ok(()) => debug!(routes = table.len(), "Wrote {}", path.display()),
+            Err(e) => error!("Unable to write {}: {e}", path.display()),
+        }
+    }
+}
+
+/// `script ACTION PREFIX NEXT_HOP`, waited for so the routes are changed in
+/// the order the leases were
+fn run_script(script: &Path, change: &RouteChange) {
+    let status = Command::new(script)
+        .arg(change.action.as_str())
+        .arg(change.prefix.to_string())
+        .arg(change.next_hop.to_string())
+        .status();
+    match status {
+        Ok(status) if status.success() => (),
+        Ok(status) => error!(
+            prefix = %change.prefix,
+            "{} {} exited with {status}",
+            script.display(),
+            change.action.as_str()
+        ),
+        Err(e) => error!("Unable to run {}: {e}", script.display()),
+    }
+}
+
+/// Write to a named pipe without waiting for a reader, so a stopped ExaBGP
+/// fails the write instead of stalling the thread
+fn write_pipe(path: &Path, commands: &[u8]) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NONBLOCK);
+    }
+    options.open(path)?.write_all(commands)
+}
+
+/// Replace `path` through a temporary file, so the routing daemon never
+/// reads half of it
+fn write_route_file(path: &Path, contents: &str) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_follow_bindings_releases_and_expiry() {
+        let prefix: Ipv6Net = "2001:db8:1::/56".parse().unwrap();
+        let cpe: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let moved: Ipv6Addr = "2001:db8::3".parse().unwrap();
+        let bound = |next_hop, valid_secs| PdUpdate::Bound {
+            prefix,
+            next_hop,
+            valid_secs,
+        };
+        let start = Instant::now();
+        let mut table = RouteTable::default();
+
+        let added = table.update(bound(cpe, 3600), start).unwrap();
+        assert_eq!(added.action, Action::Add);
+        assert_eq!(
+            exabgp_command(&added),
+            "announce route 2001:db8:1::/56 next-hop 2001:db8::2\n"
+        );
+        // A renewal only pushes the expiry out
+        assert_eq!(table.update(bound(cpe, 3600), start), None);
+        let changed = table.update(bound(moved, 60), start).unwrap();
+        assert_eq!((changed.action, changed.next_hop), (Action::Change, moved));
+        assert_eq!(
+            table.render(),
+            "# Generated by shadowdhcp from its DHCPv6 leases\n\
+             route 2001:db8:1::/56 via 2001:db8::3;\n"
+        );
+
+        assert!(table.expire(start + Duration::from_secs(59)).is_empty());
+        let expired = table.expire(start + Duration::from_secs(60));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(
+            exabgp_command(&expired[0]),
+            "withdraw route 2001:db8:1::/56 next-hop 2001:db8::3\n"
+        );
+        assert!(table.is_empty());
+
+        table.update(bound(cpe, 3600), start);
+        let released = table.update(PdUpdate::Released { prefix }, start).unwrap();
+        assert_eq!((released.action, released.next_hop), (Action::Delete, cpe));
+        assert_eq!(table.update(PdUpdate::Released { prefix }, start), None);
+    }
+}
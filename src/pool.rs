@@ -0,0 +1,348 @@
+//! Dynamic reservations for Option 82 keys that have none yet.
+//!
+//! Each configured pool claims keys by extractor and circuit/remote-id
+//! prefix. A key seen for the first time is hashed to a slot in the pool,
+//! moving on to the next free slot if that one is taken, and the slot's
+//! addresses become its reservation. Assignments are kept in
+//! `pool_assignments.json` next to `reservations.json` so a subscriber keeps
+//! its addresses across restarts even when the pool has filled up around it.
+//!
+//! Explicit reservations always win: pools are only consulted after every
+//! reservation lookup has failed. Pool ranges must not overlap addresses
+//! used by explicit reservations.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ipnet::Ipv6Net;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::config::{nth_64, Option82PoolConfig};
+use crate::shutdown::Shutdown;
+use crate::types::{Option82, Reservation};
+use crate::v4::extractors::{self, Option82ExtractorFn};
+
+/// How often new assignments are written to disk
+const PERSIST_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Pool {
+    cfg: Option82PoolConfig,
+    extractor_name: &'static str,
+    extractor: Option82ExtractorFn,
+    size: u64,
+}
+
+impl Pool {
+    fn new(cfg: &Option82PoolConfig) -> Self {
+        let (extractor_name, extractor) = extractors::get_all_extractors()
+            .get_key_value(cfg.extractor.as_str())
+            .map(|(&name, &extractor)| (name, extractor))
+            .expect("extractor checked when the config was loaded");
+        let v4_hosts = (1u64 << (32 - cfg.ipv4.prefix_len())) - 2;
+        let size = v4_hosts
+            .min(count(64 - cfg.ipv6_na.prefix_len()))
+            .min(count(cfg.pd_len - cfg.ipv6_pd.prefix_len()));
+        Self {
+            cfg: cfg.clone(),
+            extractor_name,
+            extractor,
+            size,
+        }
+    }
+
+    /// The pool's key for `option`, if it claims it
+    fn key(&self, option: &Option82) -> Option<Option82> {
+        let key = (self.extractor)(option)?;
+        let has_prefix = |field: &Option<compact_str::CompactString>, prefix: &Option<String>| {
+            prefix
+                .as_ref()
+                .is_none_or(|p| field.as_ref().is_some_and(|f| f.starts_with(p.as_str())))
+        };
+        (has_prefix(&key.circuit, &self.cfg.circuit_prefix)
+            && has_prefix(&key.remote, &self.cfg.remote_prefix))
+        .then_some(key)
+    }
+
+    fn reservation(&self, key: Option82, slot: u64) -> Reservation {
+        // Skip the network address
+        let ipv4 = Ipv4Addr::from(u32::from(self.cfg.ipv4.network()) + 1 + slot as u32);
+        let ipv6_na = nth_64(self.cfg.ipv6_na, slot, 1).expect("slot within pool size");
+        let pd_shift = 128 - u32::from(self.cfg.pd_len);
+        let pd_addr = u128::from(self.cfg.ipv6_pd.network()) | ((slot as u128) << pd_shift);
+        let ipv6_pd =
+            Ipv6Net::new(Ipv6Addr::from(pd_addr), self.cfg.pd_len).expect("pd_len validated");
+        Reservation {
+            ipv4: Some(ipv4),
+            ipv6_na: Some(ipv6_na),
+            ipv6_pd,
+            mac: None,
+            duid: None,
+            client_id: None,
+            option82: Some(key),
+            option1837: None,
+            svlan: None,
+            cvlan: None,
+            subscriber_id: None,
+            static_routes: vec![],
+            boot: None,
+            profile: None,
+            activate_at: None,
+            deactivate_at: None,
+            suspended: false,
+        }
+    }
+}
+
+/// Number of `bits`-bit slots, saturating for the huge IPv6 ranges
+fn count(bits: u8) -> u64 {
+    1u64.checked_shl(u32::from(bits)).unwrap_or(u64::MAX)
+}
+
+/// FNV-1a, so the same key lands on the same slot in every build
+fn slot_hash(key: &Option82) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for field in [&key.circuit, &key.remote, &key.subscriber] {
+        for byte in field.as_deref().unwrap_or("").bytes().chain([0]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// One line of `pool_assignments.json`
+#[derive(Clone, Serialize, Deserialize)]
+struct Assignment {
+    pool: String,
+    option82: Option82,
+    slot: u64,
+}
+
+#[derive(Default)]
+struct State {
+    by_key: HashMap<Option82, Arc<Reservation>>,
+    taken: HashSet<(usize, u64)>,
+    assignments: Vec<Assignment>,
+    dirty: bool,
+}
+
+pub struct PoolAllocator {
+    pools: Vec<Pool>,
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl PoolAllocator {
+    /// Set up `pools` and restore the assignments saved at `path`.
+    /// Assignments to pools that are gone or have shrunk are dropped.
+    pub fn open(pools: &[Option82PoolConfig], path: PathBuf) -> io::Result<Self> {
+        let pools: Vec<Pool> = pools.iter().map(Pool::new).collect();
+        let saved: Vec<Assignment> = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(file).map_err(io::Error::other)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut state = State::default();
+        for assignment in saved {
+            let found = pools
+                .iter()
+                .enumerate()
+                .find(|(_, pool)| pool.cfg.name == assignment.pool);
+            match found {
+                Some((i, pool))
+                    if assignment.slot < pool.size && state.taken.insert((i, assignment.slot)) =>
+                {
+                    let reservation =
+                        pool.reservation(assignment.option82.clone(), assignment.slot);
+                    state
+                        .by_key
+                        .insert(assignment.option82.clone(), Arc::new(reservation));
+                    state.assignments.push(assignment);
+                }
+                _ => {
+                    warn!(
+                        pool = %assignment.pool,
+                        slot = assignment.slot,
+                        "dropping saved pool assignment that no longer fits the configured pools"
+                    );
+                    state.dirty = true;
+                }
+            }
+        }
+        if !state.assignments.is_empty() {
+            info!(count = state.assignments.len(), "restored pool assignments");
+        }
+
+        Ok(Self {
+            pools,
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Reservation for `option` from the first pool that claims it,
+    /// assigning a slot if the key has none yet. Returns the extractor the
+    /// pool keys on, for the match metadata.
+    pub fn assign(&self, option: &Option82) -> Option<(Arc<Reservation>, &'static str)> {
+        let mut state = self.state.lock().expect("pool state poisoned");
+        for (i, pool) in self.pools.iter().enumerate() {
+            let Some(key) = pool.key(option) else {
+                continue;
+            };
+            if let Some(reservation) = state.by_key.get(&key) {
+                return Some((reservation.clone(), pool.extractor_name));
+            }
+            let start = slot_hash(&key) % pool.size;
+            let Some(slot) = (0..pool.size)
+                .map(|n| (start + n) % pool.size)
+                .find(|slot| !state.taken.contains(&(i, *slot)))
+            else {
+                warn!(pool = %pool.cfg.name, ?key, "pool exhausted");
+                continue;
+            };
+            let reservation = Arc::new(pool.reservation(key.clone(), slot));
+            info!(
+                pool = %pool.cfg.name,
+                ?key,
+                ipv4 = ?reservation.ipv4,
+                "assigned pool addresses to option82 key without a reservation"
+            );
+            state.taken.insert((i, slot));
+            state.by_key.insert(key.clone(), reservation.clone());
+            state.assignments.push(Assignment {
+                pool: pool.cfg.name.clone(),
+                option82: key,
+                slot,
+            });
+            state.dirty = true;
+            return Some((reservation, pool.extractor_name));
+        }
+        None
+    }
+
+    /// The pool that would claim `option`, the extractor it keys on and the
+    /// key's current assignment, without assigning anything
+    pub fn peek(
+        &self,
+        option: &Option82,
+    ) -> Option<(&str, &'static str, Option<Arc<Reservation>>)> {
+        let state = self.state.lock().expect("pool state poisoned");
+        self.pools.iter().find_map(|pool| {
+            let key = pool.key(option)?;
+            Some((
+                pool.cfg.name.as_str(),
+                pool.extractor_name,
+                state.by_key.get(&key).cloned(),
+            ))
+        })
+    }
+
+    /// Existing assignment for an already extracted key
+    pub fn get(&self, key: &Option82) -> Option<Arc<Reservation>> {
+        let state = self.state.lock().expect("pool state poisoned");
+        state.by_key.get(key).cloned()
+    }
+
+    /// Write the assignments out if any were made since the last call,
+    /// through a temp file so a crash never leaves a partial file
+    pub fn persist(&self) -> io::Result<()> {
+        let assignments = {
+            let mut state = self.state.lock().expect("pool state poisoned");
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            state.assignments.clone()
+        };
+        let result = write_atomic(&self.path, &assignments);
+        if result.is_err() {
+            self.state.lock().expect("pool state poisoned").dirty = true;
+        }
+        result
+    }
+}
+
+fn write_atomic(path: &Path, assignments: &[Assignment]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut file = File::create(&temp)?;
+    serde_json::to_writer_pretty(&mut file, assignments).map_err(io::Error::other)?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+/// Persister thread. Writes new assignments every few seconds and once
+/// more on shutdown.
+pub fn persister(pools: Arc<PoolAllocator>, shutdown: Shutdown) {
+    loop {
+        let stopping = shutdown.wait_timeout(PERSIST_INTERVAL);
+        if let Err(e) = pools.persist() {
+            error!(
+                "Unable to save pool assignments to {}: {e}",
+                pools.path.display()
+            );
+        }
+        if stopping {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_pool() -> Option82PoolConfig {
+        Option82PoolConfig {
+            name: "olt1".into(),
+            extractor: "remote_only".into(),
+            circuit_prefix: None,
+            remote_prefix: Some("olt1:".into()),
+            ipv4: "100.64.0.0/30".parse().unwrap(),
+            ipv6_na: "2001:db8:ff00::/48".parse().unwrap(),
+            ipv6_pd: "2001:db8:8000::/40".parse().unwrap(),
+            pd_len: 56,
+        }
+    }
+
+    fn remote(id: &str) -> Option82 {
+        Option82 {
+            circuit: None,
+            remote: Some(id.into()),
+            subscriber: None,
+        }
+    }
+
+    #[test]
+    fn assignments_are_unique_and_survive_restart() {
+        let dir = std::env::temp_dir().join(format!("shadowdhcp-pool-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pool_assignments.json");
+        let pools = PoolAllocator::open(&[test_pool()], path.clone()).unwrap();
+
+        // a /30 has two usable slots
+        let (a, extractor) = pools.assign(&remote("olt1:1")).unwrap();
+        assert_eq!(extractor, "remote_only");
+        let (b, _) = pools.assign(&remote("olt1:2")).unwrap();
+        assert_ne!(a.ipv4, b.ipv4);
+        assert_ne!(a.ipv6_pd, b.ipv6_pd);
+        assert!(pools.assign(&remote("olt1:3")).is_none());
+        assert!(pools.assign(&remote("olt2:1")).is_none());
+        assert_eq!(pools.assign(&remote("olt1:1")).unwrap().0, a);
+        pools.persist().unwrap();
+
+        let pools = PoolAllocator::open(&[test_pool()], path).unwrap();
+        assert_eq!(*pools.get(&remote("olt1:1")).unwrap(), *a);
+        assert_eq!(*pools.get(&remote("olt1:2")).unwrap(), *b);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,241 @@
+//! Per-key token-bucket rate limiting for the DHCP workers.
+//!
+//! Each worker owns its limiters outright (the workers are single threaded),
+//! so buckets live in a plain `HashMap` with no locking. A key that keeps
+//! hitting an empty bucket is banned for a while: its packets are dropped
+//! without being decoded further, so a CPE stuck in a Discover loop can't
+//! dominate the worker or flood the event sinks.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::config::{BucketConfig, RateLimitConfig};
+
+/// How often stale buckets are swept and drop counts reported.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Bucket empty, packet dropped
+    Limited,
+    /// Key is serving a temporary ban, packet dropped
+    Banned,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    /// Consecutive drops since the last allowed packet
+    drops: u32,
+    banned_until: Option<Instant>,
+}
+
+pub struct RateLimiter<K> {
+    /// Used in log lines, e.g. "DHCPv4 client"
+    name: &'static str,
+    rate: f64,
+    burst: f64,
+    ban_threshold: u32,
+    ban: Duration,
+    buckets: HashMap<K, Bucket>,
+    dropped: u64,
+    bans: u64,
+    last_sweep: Instant,
+}
+
+/// Counters accumulated since the previous sweep
+struct SweepReport {
+    dropped: u64,
+    bans: u64,
+}
+
+impl<K: Hash + Eq + Clone + Display> RateLimiter<K> {
+    pub fn new(
+        name: &'static str,
+        bucket: &BucketConfig,
+        ban_threshold: u32,
+        ban: Duration,
+    ) -> Self {
+        Self {
+            name,
+            rate: bucket.per_second,
+            burst: f64::from(bucket.burst),
+            ban_threshold,
+            ban,
+            buckets: HashMap::new(),
+            dropped: 0,
+            bans: 0,
+            last_sweep: Instant::now(),
+        }
+    }
+
+    /// Client limiter from the `rate_limit` config block; bans apply.
+    pub fn client(name: &'static str, cfg: &RateLimitConfig) -> Self {
+        Self::new(
+            name,
+            &cfg.client,
+            cfg.ban_threshold,
+            Duration::from_secs(cfg.ban_secs),
+        )
+    }
+
+    /// Relay limiter from the `rate_limit` config block, if configured.
+    /// Relays are never banned; one noisy subscriber behind a relay
+    /// shouldn't take out every other subscriber on it.
+    pub fn relay(name: &'static str, cfg: &RateLimitConfig) -> Option<Self> {
+        cfg.relay
+            .as_ref()
+            .map(|bucket| Self::new(name, bucket, 0, Duration::ZERO))
+    }
+
+    pub fn check(&mut self, key: &K) -> Verdict {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&mut self, key: &K, now: Instant) -> Verdict {
+        // Only clone the key for a new bucket; the common case is a hit.
+        if !self.buckets.contains_key(key) {
+            self.buckets.insert(
+                key.clone(),
+                Bucket {
+                    tokens: self.burst,
+                    last: now,
+                    drops: 0,
+                    banned_until: None,
+                },
+            );
+        }
+        let bucket = self.buckets.get_mut(key).expect("bucket inserted above");
+
+        if let Some(until) = bucket.banned_until {
+            if now < until {
+                self.dropped += 1;
+                return Verdict::Banned;
+            }
+            bucket.banned_until = None;
+            bucket.drops = 0;
+        }
+
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.drops = 0;
+            return Verdict::Allow;
+        }
+
+        self.dropped += 1;
+        bucket.drops = bucket.drops.saturating_add(1);
+        if self.ban_threshold > 0 && bucket.drops >= self.ban_threshold {
+            bucket.banned_until = Some(now + self.ban);
+            self.bans += 1;
+            warn!(
+                %key,
+                ban_secs = self.ban.as_secs(),
+                "{} banned after {} consecutive rate limited packets",
+                self.name,
+                bucket.drops
+            );
+        }
+        Verdict::Limited
+    }
+
+    /// Once per `SWEEP_INTERVAL`, forget buckets that have refilled and
+    /// aren't banned, and log the drops since the last sweep.
+    pub fn sweep(&mut self) {
+        if let Some(report) = self.sweep_at(Instant::now()) {
+            if report.dropped > 0 {
+                warn!(
+                    "{} rate limit dropped {} packets ({} new bans) in the last {}s",
+                    self.name,
+                    report.dropped,
+                    report.bans,
+                    SWEEP_INTERVAL.as_secs()
+                );
+            }
+        }
+    }
+
+    fn sweep_at(&mut self, now: Instant) -> Option<SweepReport> {
+        if now.saturating_duration_since(self.last_sweep) < SWEEP_INTERVAL {
+            return None;
+        }
+        self.last_sweep = now;
+        let (rate, burst) = (self.rate, self.burst);
+        self.buckets.retain(|_, b| {
+            let banned = b.banned_until.is_some_and(|until| now < until);
+            let refilled = b.tokens + now.saturating_duration_since(b.last).as_secs_f64() * rate;
+            banned || refilled < burst
+        });
+        Some(SweepReport {
+            dropped: std::mem::take(&mut self.dropped),
+            bans: std::mem::take(&mut self.bans),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_second: f64, burst: u32, ban_threshold: u32) -> RateLimiter<u8> {
+        RateLimiter::new(
+            "test",
+            &BucketConfig { per_second, burst },
+            ban_threshold,
+            Duration::from_secs(10),
+        )
+    }
+
+    #[test]
+    fn burst_then_refill() {
+        let mut rl = limiter(1.0, 3, 0);
+        let t0 = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(rl.check_at(&1, t0), Verdict::Allow);
+        }
+        assert_eq!(rl.check_at(&1, t0), Verdict::Limited);
+        // other keys have their own bucket
+        assert_eq!(rl.check_at(&2, t0), Verdict::Allow);
+        assert_eq!(rl.check_at(&1, t0 + Duration::from_secs(1)), Verdict::Allow);
+    }
+
+    #[test]
+    fn repeated_drops_ban_until_expiry() {
+        let mut rl = limiter(1.0, 1, 3);
+        let t0 = Instant::now();
+        assert_eq!(rl.check_at(&1, t0), Verdict::Allow);
+        for _ in 0..3 {
+            assert_eq!(rl.check_at(&1, t0), Verdict::Limited);
+        }
+        // refilled, but still banned
+        assert_eq!(
+            rl.check_at(&1, t0 + Duration::from_secs(5)),
+            Verdict::Banned
+        );
+        assert_eq!(
+            rl.check_at(&1, t0 + Duration::from_secs(11)),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn sweep_reports_and_forgets_idle_buckets() {
+        let mut rl = limiter(1.0, 1, 0);
+        let t0 = rl.last_sweep;
+        assert_eq!(rl.check_at(&1, t0), Verdict::Allow);
+        assert_eq!(rl.check_at(&1, t0), Verdict::Limited);
+        assert!(rl.sweep_at(t0 + Duration::from_secs(1)).is_none());
+
+        let report = rl.sweep_at(t0 + SWEEP_INTERVAL).unwrap();
+        assert_eq!(report.dropped, 1);
+        assert!(rl.buckets.is_empty());
+    }
+}
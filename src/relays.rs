@@ -0,0 +1,242 @@
+//! Every relay agent the workers have heard from, for auditing access
+//! devices and spotting new or rogue relays from the management port.
+//!
+//! DHCPv4 relays are keyed by giaddr. DHCPv6 relays are keyed by
+//! link-address, or by Interface-ID when the relay leaves the link-address
+//! unspecified. Each keeps when it was first and last heard from, how many
+//! messages it relayed, and which Option 82 or Option 18/37 layouts it used:
+//! which sub-options were present and whether each held a MAC address,
+//! text or binary. A relay switching layout, or a new relay appearing,
+//! usually means a reconfigured or unexpected access device.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use compact_str::CompactString;
+use dashmap::DashMap;
+use dhcproto::{v4, v6};
+use serde::Serialize;
+
+use crate::analytics::events::now;
+use crate::types::parse_mac;
+use crate::v4::extensions::{RelayAgentInformationExt, ShadowMessageExtV4};
+
+/// Relays beyond this many aren't tracked, so a flood of spoofed giaddrs
+/// can't grow the inventory without bound
+const MAX_RELAYS: usize = 10_000;
+/// Layouts kept per relay, later ones aren't recorded
+const MAX_FORMATS: usize = 16;
+
+/// Sub-option names in layout order, two bits each
+const OPTION82_FIELDS: [&str; 3] = ["circuit", "remote", "subscriber"];
+const OPTION1837_FIELDS: [&str; 2] = ["interface", "remote"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayId {
+    Giaddr(Ipv4Addr),
+    LinkAddr(Ipv6Addr),
+    InterfaceId(CompactString),
+}
+
+#[derive(Debug, Clone)]
+struct RelayRecord {
+    /// Address the relay last sent from
+    source: IpAddr,
+    first_seen: u64,
+    last_seen: u64,
+    messages: u64,
+    /// Packed layouts, see [`layout`]
+    formats: Vec<u8>,
+}
+
+impl RelayRecord {
+    fn seen(&mut self, source: IpAddr, now: u64, format: u8) {
+        self.source = source;
+        self.last_seen = now;
+        self.messages += 1;
+        if !self.formats.contains(&format) && self.formats.len() < MAX_FORMATS {
+            self.formats.push(format);
+        }
+    }
+}
+
+/// One relay as the `relays` management command reports it
+#[derive(Debug, Clone, Serialize)]
+pub struct RelaySummary {
+    #[serde(flatten)]
+    pub id: RelayId,
+    pub source: IpAddr,
+    /// Unix milliseconds
+    pub first_seen: u64,
+    /// Unix milliseconds
+    pub last_seen: u64,
+    pub messages: u64,
+    /// Option 82 or Option 18/37 layouts seen, such as
+    /// `circuit=text,remote=mac`, or `none` for messages without them
+    pub formats: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct RelayInventory {
+    relays: DashMap<RelayId, RelayRecord>,
+}
+
+impl RelayInventory {
+    /// Note a DHCPv4 message relayed from `source`. Messages with no giaddr
+    /// came straight from a client and are ignored.
+    pub fn record_v4(&self, msg: &v4::Message, source: IpAddr) {
+        let giaddr = msg.giaddr();
+        if giaddr.is_unspecified() {
+            return;
+        }
+        let format = msg.relay_agent_information().map_or(0, |info| {
+            layout(&[info.circuit_id(), info.remote_id(), info.subscriber_id()])
+        });
+        self.record(RelayId::Giaddr(giaddr), source, format);
+    }
+
+    /// Note a DHCPv6 Relay-forward received from `source`
+    pub fn record_v6(&self, relay_msg: &v6::RelayMessage, source: IpAddr) {
+        let mut interface = None;
+        let mut remote = None;
+        for opt in relay_msg.opts().iter() {
+            match opt {
+                v6::DhcpOption::InterfaceId(id) => interface = Some(id.clone()),
+                v6::DhcpOption::RemoteId(remote_id) => remote = Some(remote_id.id.clone()),
+                _ => (),
+            }
+        }
+        let id = match (relay_msg.link_addr(), &interface) {
+            (link, Some(interface)) if link.is_unspecified() => {
+                RelayId::InterfaceId(String::from_utf8_lossy(interface).into())
+            }
+            (link, _) => RelayId::LinkAddr(link),
+        };
+        self.record(id, source, layout(&[interface, remote]));
+    }
+
+    fn record(&self, id: RelayId, source: IpAddr, format: u8) {
+        let now = now();
+        if let Some(mut relay) = self.relays.get_mut(&id) {
+            relay.seen(source, now, format);
+            return;
+        }
+        if self.relays.len() >= MAX_RELAYS {
+            return;
+        }
+        self.relays
+            .entry(id)
+            .or_insert_with(|| RelayRecord {
+                source,
+                first_seen: now,
+                last_seen: now,
+                messages: 0,
+                formats: Vec::new(),
+            })
+            .seen(source, now, format);
+    }
+
+    /// Every relay, most recently first heard from first
+    pub fn snapshot(&self) -> Vec<RelaySummary> {
+        let mut relays: Vec<RelaySummary> = self
+            .relays
+            .iter()
+            .map(|entry| {
+                let fields: &[&str] = match entry.key() {
+                    RelayId::Giaddr(_) => &OPTION82_FIELDS,
+                    _ => &OPTION1837_FIELDS,
+                };
+                RelaySummary {
+                    id: entry.key().clone(),
+                    source: entry.source,
+                    first_seen: entry.first_seen,
+                    last_seen: entry.last_seen,
+                    messages: entry.messages,
+                    formats: entry
+                        .formats
+                        .iter()
+                        .map(|format| describe(*format, fields))
+                        .collect(),
+                }
+            })
+            .collect();
+        relays.sort_by(|a, b| b.first_seen.cmp(&a.first_seen));
+        relays
+    }
+}
+
+/// Pack which of `fields` are present and what each holds, two bits each:
+/// 0 absent, 1 MAC address, 2 text, 3 binary
+fn layout(fields: &[Option<Vec<u8>>]) -> u8 {
+    fields.iter().enumerate().fold(0, |packed, (i, field)| {
+        let kind = match field.as_deref().map(std::str::from_utf8) {
+            None => 0,
+            Some(Ok(text)) if parse_mac(text).is_some() => 1,
+            Some(Ok(text)) if text.chars().all(|c| c.is_ascii_graphic() || c == ' ') => 2,
+            Some(_) => 3,
+        };
+        packed | (kind << (2 * i))
+    })
+}
+
+fn describe(format: u8, fields: &[&str]) -> String {
+    let parts: Vec<String> = fields
+        .iter()
+        .enumerate()
+        .filter_map(|(i, name)| {
+            let kind = match (format >> (2 * i)) & 0b11 {
+                0 => return None,
+                1 => "mac",
+                2 => "text",
+                _ => "binary",
+            };
+            Some(format!("{name}={kind}"))
+        })
+        .collect();
+    if parts.is_empty() {
+        "none".into()
+    } else {
+        parts.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dhcproto::v4::relay::{RelayAgentInformation, RelayInfo};
+
+    #[test]
+    fn tracks_relays_and_their_option82_layouts() {
+        let inventory = RelayInventory::default();
+        let source = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let mut msg = v4::Message::new(
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::UNSPECIFIED,
+            Ipv4Addr::new(192, 0, 2, 1),
+            &[0, 1, 2, 3, 4, 5],
+        );
+        inventory.record_v4(&msg, source);
+
+        let mut relay_info = RelayAgentInformation::default();
+        relay_info.insert(RelayInfo::AgentCircuitId(b"eth 1/1/1:100".to_vec()));
+        relay_info.insert(RelayInfo::AgentRemoteId(b"00:11:22:33:44:55".to_vec()));
+        msg.opts_mut()
+            .insert(v4::DhcpOption::RelayAgentInformation(relay_info));
+        inventory.record_v4(&msg, source);
+        inventory.record_v4(&msg, source);
+
+        // Straight from a client, not a relay
+        msg.set_giaddr(Ipv4Addr::UNSPECIFIED);
+        inventory.record_v4(&msg, source);
+
+        let relays = inventory.snapshot();
+        assert_eq!(relays.len(), 1);
+        assert_eq!(relays[0].id, RelayId::Giaddr(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(relays[0].messages, 3);
+        assert_eq!(relays[0].formats, ["none", "circuit=text,remote=mac"]);
+        assert!(serde_json::to_string(&relays[0])
+            .unwrap()
+            .starts_with(r#"{"giaddr":"192.0.2.1","source":"192.0.2.1","#));
+    }
+}
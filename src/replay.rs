@@ -0,0 +1,371 @@
+//! `shadowdhcp replay`: run the DHCP requests in a packet capture through
+//! the handlers against the reservations on disk, to see how a config or
+//! reservation change would treat real subscriber traffic before it ships.
+//!
+//! Reads classic pcap files (not pcapng) with Ethernet, Linux cooked or raw
+//! IP framing. Only datagrams to the server ports are replayed: UDP 67 for
+//! DHCPv4 and 547 for DHCPv6. Replies in the capture, which go to the same
+//! ports on the relay, are skipped. Packets are replayed in capture order
+//! through one lease cache, so a Request sees the bindings its Discover
+//! made. Rate limiting, conflict detection and HA are worker features and
+//! play no part.
+
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use advmac::MacAddr6;
+use dhcproto::v4;
+use dhcproto::v6::{self, DhcpOption, RelayMessageData};
+use dhcproto::Decodable;
+
+use crate::analytics::events::{DhcpEventV4, DhcpEventV6, ReservationMatch};
+use crate::config::Config;
+use crate::opt82_cache::Opt82Cache;
+use crate::reservationdb::ReservationDb;
+use crate::types::Reservation;
+use crate::v4::extensions::ShadowMessageExtV4;
+use crate::v4::handlers::DhcpV4Response;
+use crate::v6::handlers::DhcpV6Response;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// Larger than any frame a capture of DHCP traffic should hold
+const MAX_RECORD_LEN: usize = 256 * 1024;
+
+const UDP: u8 = 17;
+const V4_SERVER_PORT: u16 = 67;
+const V6_SERVER_PORT: u16 = 547;
+
+/// A UDP datagram to one of the server ports
+pub struct Datagram {
+    /// 1-based packet number in the capture, as Wireshark shows it
+    pub number: usize,
+    pub src: IpAddr,
+    pub dst_port: u16,
+    pub payload: Vec<u8>,
+}
+
+/// Every datagram to a DHCP server port in the pcap read from `reader`
+pub fn read_pcap<R: Read>(mut reader: R) -> io::Result<Vec<Datagram>> {
+    let mut header = [0u8; 24];
+    reader.read_exact(&mut header)?;
+    let big_endian = match u32::from_le_bytes([header[0], header[1], header[2], header[3]]) {
+        0xa1b2_c3d4 | 0xa1b2_3c4d => false,
+        0xd4c3_b2a1 | 0x4d3c_b2a1 => true,
+        0x0a0d_0d0a => {
+            return Err(invalid(
+                "pcapng isn't supported, convert it with `editcap -F pcap`",
+            ))
+        }
+        _ => return Err(invalid("not a pcap file")),
+    };
+    let read_u32 = |b: &[u8]| {
+        let bytes = [b[0], b[1], b[2], b[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    // The top bits of the link type hold FCS information
+    let linktype = read_u32(&header[20..24]) & 0x0fff_ffff;
+
+    let mut datagrams = Vec::new();
+    let mut record = [0u8; 16];
+    let mut number = 0;
+    loop {
+        match reader.read_exact(&mut record) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        number += 1;
+        let len = read_u32(&record[8..12]) as usize;
+        if len > MAX_RECORD_LEN {
+            return Err(invalid("packet record is larger than any DHCP frame"));
+        }
+        let mut frame = vec![0u8; len];
+        reader.read_exact(&mut frame)?;
+        if let Some((src, dst_port, payload)) = udp_payload(linktype, &frame) {
+            if dst_port == V4_SERVER_PORT || dst_port == V6_SERVER_PORT {
+                datagrams.push(Datagram {
+                    number,
+                    src,
+                    dst_port,
+                    payload: payload.to_vec(),
+                });
+            }
+        }
+    }
+    Ok(datagrams)
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Source address, destination port and payload of a UDP frame
+fn udp_payload(linktype: u32, frame: &[u8]) -> Option<(IpAddr, u16, &[u8])> {
+    let packet = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16_at(frame, 12)?;
+            let mut offset = 14;
+            // 802.1Q and QinQ tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                ethertype = u16_at(frame, offset + 2)?;
+                offset += 4;
+            }
+            frame.get(offset..)?
+        }
+        LINKTYPE_LINUX_SLL => frame.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => frame.get(20..)?,
+        LINKTYPE_NULL => frame.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => frame,
+        _ => return None,
+    };
+    let (src, udp) = match packet.first()? >> 4 {
+        4 => ipv4_udp(packet)?,
+        6 => ipv6_udp(packet)?,
+        _ => return None,
+    };
+    let dst_port = u16_at(udp, 2)?;
+    let len = usize::from(u16_at(udp, 4)?).min(udp.len());
+    Some((src, dst_port, udp.get(8..len)?))
+}
+
+fn ipv4_udp(packet: &[u8]) -> Option<(IpAddr, &[u8])> {
+    let header_len = usize::from(packet[0] & 0x0f) * 4;
+    let total_len = usize::from(u16_at(packet, 2)?).min(packet.len());
+    // Fragments can't be decoded on their own
+    if *packet.get(9)? != UDP || u16_at(packet, 6)? & 0x3fff != 0 {
+        return None;
+    }
+    let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+    Some((
+        IpAddr::V4(Ipv4Addr::from(src)),
+        packet.get(header_len..total_len)?,
+    ))
+}
+
+fn ipv6_udp(packet: &[u8]) -> Option<(IpAddr, &[u8])> {
+    let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+    let mut next = *packet.get(6)?;
+    let mut offset = 40;
+    // Hop-by-hop, routing and destination options headers
+    while matches!(next, 0 | 43 | 60) {
+        next = *packet.get(offset)?;
+        offset += (usize::from(*packet.get(offset + 1)?) + 1) * 8;
+    }
+    if next != UDP {
+        return None;
+    }
+    Some((IpAddr::V6(Ipv6Addr::from(src)), packet.get(offset..)?))
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+/// What the server would have done with one datagram
+pub struct Replayed {
+    pub number: usize,
+    pub src: IpAddr,
+    /// The request, e.g. `Discover xid 1a2b3c4d mac 00-11-22-33-44-55`
+    pub request: String,
+    /// The reply message type, a `failure_reason`, or `ParseError` and
+    /// friends for datagrams the workers would reject before the handlers
+    pub outcome: &'static str,
+    /// The reservation and how it was found, when there is one
+    pub detail: Option<String>,
+}
+
+/// Replay `datagrams` in order. Replies found in the capture are left out.
+pub fn replay(
+    config: &Config,
+    reservations: &ReservationDb,
+    datagrams: &[Datagram],
+) -> Vec<Replayed> {
+    let leases = Opt82Cache::new();
+    datagrams
+        .iter()
+        .filter_map(|datagram| match datagram.dst_port {
+            V4_SERVER_PORT => replay_v4(config, reservations, &leases, datagram),
+            _ => replay_v6(config, reservations, &leases, datagram),
+        })
+        .collect()
+}
+
+fn replay_v4(
+    config: &Config,
+    reservations: &ReservationDb,
+    leases: &Opt82Cache,
+    datagram: &Datagram,
+) -> Option<Replayed> {
+    let replayed = |request, outcome, detail| Replayed {
+        number: datagram.number,
+        src: datagram.src,
+        request,
+        outcome,
+        detail,
+    };
+    let msg = match v4::Message::from_bytes(&datagram.payload) {
+        Ok(msg) => msg,
+        Err(_) => return Some(replayed(String::new(), "ParseError", None)),
+    };
+    if msg.opcode() == v4::Opcode::BootReply {
+        return None;
+    }
+    let msg_type = msg
+        .message_type()
+        .map_or("Unknown", DhcpEventV4::message_type_str);
+    let mut request = format!("{msg_type} xid {:08x}", msg.xid());
+    if let Ok(mac) = MacAddr6::try_from(msg.chaddr()) {
+        request.push_str(&format!(" mac {mac}"));
+    }
+    Some(
+        match crate::handle_v4_message(config, reservations, leases, &msg) {
+            DhcpV4Response::Message(resp) => replayed(
+                request,
+                resp.message
+                    .message_type()
+                    .map_or("Unknown", DhcpEventV4::message_type_str),
+                describe(resp.reservation.as_deref(), resp.reservation_match),
+            ),
+            DhcpV4Response::NoResponse(reason) => replayed(request, reason.as_str(), None),
+        },
+    )
+}
+
+fn replay_v6(
+    config: &Config,
+    reservations: &ReservationDb,
+    leases: &Opt82Cache,
+    datagram: &Datagram,
+) -> Option<Replayed> {
+    let replayed = |request, outcome, detail| Replayed {
+        number: datagram.number,
+        src: datagram.src,
+        request,
+        outcome,
+        detail,
+    };
+    let relay_msg = match v6::RelayMessage::from_bytes(&datagram.payload) {
+        Ok(msg) => msg,
+        Err(_) => return Some(replayed(String::new(), "ParseError", None)),
+    };
+    if relay_msg.msg_type != v6::MessageType::RelayForw {
+        return None;
+    }
+    let msg = match relay_msg.opts().iter().find_map(|opt| match opt {
+        DhcpOption::RelayMsg(msg) => Some(msg),
+        _ => None,
+    }) {
+        Some(RelayMessageData::Message(msg)) => msg,
+        Some(RelayMessageData::Relay(_)) => {
+            return Some(replayed(String::new(), "NestedRelay", None))
+        }
+        None => return Some(replayed(String::new(), "NoRelayMsg", None)),
+    };
+    let xid = msg.xid();
+    let request = format!(
+        "{} xid {:02x}{:02x}{:02x}",
+        DhcpEventV6::message_type_str(msg.msg_type()),
+        xid[0],
+        xid[1],
+        xid[2]
+    );
+    Some(
+        match crate::handle_v6_message(config, reservations, leases, msg, &relay_msg) {
+            DhcpV6Response::Message(resp) => replayed(
+                request,
+                DhcpEventV6::message_type_str(resp.message.msg_type()),
+                describe(resp.reservation.as_deref(), resp.reservation_match),
+            ),
+            DhcpV6Response::NoResponse(reason) => replayed(request, reason.as_str(), None),
+        },
+    )
+}
+
+fn describe(reservation: Option<&Reservation>, method: Option<ReservationMatch>) -> Option<String> {
+    let reservation = reservation?;
+    let mut detail = String::new();
+    if let Some(ipv4) = reservation.ipv4 {
+        detail.push_str(&format!("{ipv4} "));
+    }
+    if let Some(na) = reservation.ipv6_na {
+        detail.push_str(&format!("{na} "));
+    }
+    detail.push_str(&reservation.ipv6_pd.to_string());
+    if let Some(method) = method {
+        detail.push_str(&format!(" by {}", method.method));
+        if let Some(extractor) = method.extractor {
+            detail.push_str(&format!(" ({extractor})"));
+        }
+    }
+    Some(detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian pcap with one Ethernet frame carrying `payload` from
+    /// 10.0.0.1:67 to 10.0.0.2:`dst_port`
+    fn pcap(dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut udp = Vec::new();
+        udp.extend(67u16.to_be_bytes());
+        udp.extend(dst_port.to_be_bytes());
+        udp.extend((8 + payload.len() as u16).to_be_bytes());
+        udp.extend([0, 0]);
+        udp.extend(payload);
+
+        let mut ip = vec![0x45, 0];
+        ip.extend((20 + udp.len() as u16).to_be_bytes());
+        ip.extend([0, 0, 0, 0, 64, UDP, 0, 0]);
+        ip.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+        ip.extend(udp);
+
+        let mut frame = vec![0xff; 12];
+        frame.extend(0x0800u16.to_be_bytes());
+        frame.extend(ip);
+
+        let mut file = Vec::new();
+        file.extend(0xa1b2_c3d4u32.to_le_bytes());
+        file.extend([2, 0, 4, 0]);
+        file.extend([0; 8]);
+        file.extend(65535u32.to_le_bytes());
+        file.extend(LINKTYPE_ETHERNET.to_le_bytes());
+        file.extend([0; 8]);
+        file.extend((frame.len() as u32).to_le_bytes());
+        file.extend((frame.len() as u32).to_le_bytes());
+        file.extend(frame);
+        file
+    }
+
+    #[test]
+    fn reads_udp_to_server_ports_only() {
+        let datagrams = read_pcap(&pcap(67, b"dhcp")[..]).unwrap();
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].number, 1);
+        assert_eq!(datagrams[0].src, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(datagrams[0].payload, b"dhcp");
+
+        assert!(read_pcap(&pcap(53, b"dns")[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn pcapng_rejected() {
+        let err = read_pcap(&[0x0au8, 0x0d, 0x0d, 0x0a].repeat(6)[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,179 @@
+//! Sending replies from a fixed source address.
+//!
+//! The DHCP sockets are normally bound to the wildcard address, so the
+//! kernel picks each reply's source from the route back to the relay. Relays
+//! that only accept replies from their configured server address need that
+//! to be a particular one of the server's addresses, which
+//! `v4_reply_source`/`v6_reply_source` set per packet with `IP_PKTINFO` and
+//! `IPV6_PKTINFO`. Linux only; the config loader rejects the options
+//! elsewhere.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReplySource {
+    V4(Ipv4Addr),
+    V6 {
+        addr: Ipv6Addr,
+        /// Interface the address is on; needed for link-local addresses,
+        /// 0 leaves it to routing
+        ifindex: u32,
+    },
+}
+
+impl ReplySource {
+    /// `interface` names the link a link-local `addr` belongs to
+    pub fn v6(addr: Ipv6Addr, interface: Option<&str>) -> io::Result<Self> {
+        let ifindex = match interface {
+            Some(name) => interface_index(name)?,
+            None => 0,
+        };
+        Ok(ReplySource::V6 { addr, ifindex })
+    }
+}
+
+impl std::fmt::Display for ReplySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplySource::V4(addr) => write!(f, "{addr}"),
+            ReplySource::V6 { addr, .. } => write!(f, "{addr}"),
+        }
+    }
+}
+
+/// Send on `socket`, or from `source` when there is one
+pub fn send(
+    socket: &UdpSocket,
+    source: Option<&ReplySource>,
+    buf: &[u8],
+    dest: SocketAddr,
+) -> io::Result<usize> {
+    match source {
+        Some(source) => send_from(socket, buf, dest, source),
+        None => socket.send_to(buf, dest),
+    }
+}
+
+/// Only send and receive through `interface` (`SO_BINDTODEVICE`)
+#[cfg(target_os = "linux")]
+pub fn bind_interface(socket: &UdpSocket, interface: &str) -> io::Result<()> {
+    socket2::SockRef::from(socket).bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_interface(_socket: &UdpSocket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "interface binding needs Linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn interface_index(name: &str) -> io::Result<u32> {
+    let c_name = std::ffi::CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "interface name contains NUL"))?;
+    // SAFETY: c_name is a valid NUL-terminated string for the whole call
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interface_index(_name: &str) -> io::Result<u32> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "interface binding needs Linux",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn send_from(
+    socket: &UdpSocket,
+    buf: &[u8],
+    dest: SocketAddr,
+    source: &ReplySource,
+) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    let dest = socket2::SockAddr::from(dest);
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // u64 words keep the control buffer aligned for cmsghdr; 64 bytes fits
+    // either pktinfo struct with its header
+    let mut control = [0u64; 8];
+    let (level, kind, data_len) = match source {
+        ReplySource::V4(_) => (
+            libc::IPPROTO_IP,
+            libc::IP_PKTINFO,
+            std::mem::size_of::<libc::in_pktinfo>(),
+        ),
+        ReplySource::V6 { .. } => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_PKTINFO,
+            std::mem::size_of::<libc::in6_pktinfo>(),
+        ),
+    };
+
+    // SAFETY: msghdr is plain data for which all zeroes is valid. Every
+    // pointer stored in it (dest, iov, control) outlives the sendmsg call,
+    // control is aligned and larger than CMSG_SPACE(data_len), so
+    // CMSG_FIRSTHDR is non-null and the header and data writes stay inside
+    // it. The data pointer may be unaligned for the pktinfo struct, hence
+    // write_unaligned.
+    let sent = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_name = dest.as_ptr() as *mut libc::c_void;
+        msg.msg_namelen = dest.len();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = libc::CMSG_SPACE(data_len as u32) as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = level;
+        (*cmsg).cmsg_type = kind;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(data_len as u32) as _;
+        let data = libc::CMSG_DATA(cmsg);
+        match *source {
+            ReplySource::V4(addr) => std::ptr::write_unaligned(
+                data.cast(),
+                libc::in_pktinfo {
+                    ipi_ifindex: 0,
+                    ipi_spec_dst: libc::in_addr {
+                        s_addr: u32::from_ne_bytes(addr.octets()),
+                    },
+                    ipi_addr: libc::in_addr { s_addr: 0 },
+                },
+            ),
+            ReplySource::V6 { addr, ifindex } => std::ptr::write_unaligned(
+                data.cast(),
+                libc::in6_pktinfo {
+                    ipi6_addr: libc::in6_addr {
+                        s6_addr: addr.octets(),
+                    },
+                    ipi6_ifindex: ifindex,
+                },
+            ),
+        }
+        libc::sendmsg(socket.as_raw_fd(), &msg, 0)
+    };
+    if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(sent as usize)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_from(
+    socket: &UdpSocket,
+    buf: &[u8],
+    dest: SocketAddr,
+    _source: &ReplySource,
+) -> io::Result<usize> {
+    socket.send_to(buf, dest)
+}
@@ -0,0 +1,183 @@
+//! `shadowdhcp diff-reservations`: what a new reservations file would add,
+//! remove and change compared to the old one, for reviewing provisioning
+//! pushes before they reach the servers.
+//!
+//! Reservations are paired across the files by `ipv6_pd`, the one address
+//! every reservation has. A customer moved to another prefix therefore shows
+//! up as one reservation removed and another added.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use ipnet::Ipv6Net;
+use serde_json::Value;
+
+use crate::config::V6NaTemplate;
+use crate::reservationdb::ReservationDb;
+use crate::types::Reservation;
+
+/// Fields a client is matched on, as opposed to what it is handed
+const KEY_FIELDS: &[&str] = &[
+    "mac",
+    "duid",
+    "client_id",
+    "option82",
+    "option1837",
+    "svlan",
+    "cvlan",
+];
+
+#[derive(Debug, Default)]
+pub struct Diff {
+    pub added: Vec<Reservation>,
+    pub removed: Vec<Reservation>,
+    pub changed: Vec<Changed>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A reservation in both files, with different settings
+#[derive(Debug)]
+pub struct Changed {
+    pub old: Reservation,
+    pub new: Reservation,
+    /// The field, with its value in the old and the new file, `null` where
+    /// it is unset
+    pub fields: Vec<(String, Value, Value)>,
+}
+
+impl Changed {
+    /// The changed fields the reservation is looked up by
+    pub fn key_fields(&self) -> impl Iterator<Item = &str> {
+        self.fields
+            .iter()
+            .map(|(field, ..)| field.as_str())
+            .filter(|field| is_key_field(field))
+    }
+}
+
+pub fn is_key_field(field: &str) -> bool {
+    KEY_FIELDS.contains(&field)
+}
+
+/// Read a reservations file as the server does on startup or reload,
+/// deriving `"auto"` IA_NA addresses from `na_template`
+pub fn load(path: &Path, na_template: Option<V6NaTemplate>) -> Result<Vec<Reservation>, String> {
+    let file =
+        std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    let mut reservations: Vec<Reservation> = serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| format!("Failed to parse {}: {e}", path.display()))?;
+    let db = ReservationDb::with_na_template(na_template);
+    for reservation in reservations.iter_mut() {
+        db.derive_ipv6_na(reservation)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+    }
+    Ok(reservations)
+}
+
+/// Compare two reservation lists, each in `ipv6_pd` order. Fails if either
+/// delegates the same prefix twice, since the pairing would be ambiguous.
+pub fn diff(old: Vec<Reservation>, new: Vec<Reservation>) -> Result<Diff, String> {
+    let mut old = by_prefix(old, "old")?;
+    let new = by_prefix(new, "new")?;
+    let mut diff = Diff::default();
+    for (prefix, new) in new {
+        let Some(old) = old.remove(&prefix) else {
+            diff.added.push(new);
+            continue;
+        };
+        let fields = changed_fields(&old, &new);
+        if !fields.is_empty() {
+            diff.changed.push(Changed { old, new, fields });
+        }
+    }
+    diff.removed = old.into_values().collect();
+    Ok(diff)
+}
+
+fn by_prefix(
+    reservations: Vec<Reservation>,
+    which: &str,
+) -> Result<BTreeMap<Ipv6Net, Reservation>, String> {
+    let mut by_prefix = BTreeMap::new();
+    for reservation in reservations {
+        let prefix = reservation.ipv6_pd;
+        if by_prefix.insert(prefix, reservation).is_some() {
+            return Err(format!(
+                "ipv6_pd {prefix} is in more than one reservation in the {which} file"
+            ));
+        }
+    }
+    Ok(by_prefix)
+}
+
+/// Compared as JSON, so every field is covered and printed the way it is
+/// written in the file
+fn changed_fields(old: &Reservation, new: &Reservation) -> Vec<(String, Value, Value)> {
+    let as_object = |reservation: &Reservation| match serde_json::to_value(reservation) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Default::default(),
+    };
+    let (old, mut new) = (as_object(old), as_object(new));
+    let mut fields: Vec<_> = old
+        .into_iter()
+        .map(|(field, old)| {
+            let new = new.remove(&field).unwrap_or(Value::Null);
+            (field, old, new)
+        })
+        .chain(
+            new.into_iter()
+                .map(|(field, new)| (field, Value::Null, new)),
+        )
+        .filter(|(_, old, new)| old != new)
+        .collect();
+    fields.sort_by(|a, b| a.0.cmp(&b.0));
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reservations(json: &str) -> Vec<Reservation> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn added_removed_and_changed_keys() {
+        let old = reservations(
+            r#"[{"ipv4": "100.64.0.1", "ipv6_pd": "2001:db8:1::/56", "mac": "00-11-22-33-44-01"},
+                {"ipv4": "100.64.0.2", "ipv6_pd": "2001:db8:2::/56", "mac": "00-11-22-33-44-02"},
+                {"ipv4": "100.64.0.3", "ipv6_pd": "2001:db8:3::/56", "option82": {"remote": "olt1:1"}}]"#,
+        );
+        let new = reservations(
+            r#"[{"ipv4": "100.64.0.1", "ipv6_pd": "2001:db8:1::/56", "mac": "00-11-22-33-44-01"},
+                {"ipv4": "100.64.0.3", "ipv6_pd": "2001:db8:3::/56", "mac": "00:11:22:33:44:03", "subscriber_id": "cust-3"},
+                {"ipv4": "100.64.0.4", "ipv6_pd": "2001:db8:4::/56", "mac": "00-11-22-33-44-04"}]"#,
+        );
+        let diff = diff(old.clone(), new).unwrap();
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].name(), "100.64.0.4");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].name(), "100.64.0.2");
+        let [changed] = &diff.changed[..] else {
+            panic!("{:?}", diff.changed);
+        };
+        let names: Vec<_> = changed.fields.iter().map(|(f, ..)| f.as_str()).collect();
+        assert_eq!(names, ["mac", "option82", "subscriber_id"]);
+        assert_eq!(
+            changed.key_fields().collect::<Vec<_>>(),
+            ["mac", "option82"]
+        );
+        assert_eq!(changed.fields[2].1, Value::Null);
+
+        assert!(super::diff(old.clone(), old.clone()).unwrap().is_empty());
+        let twice = [old.clone(), old].concat();
+        assert!(super::diff(twice, Vec::new()).is_err());
+    }
+}
@@ -1,86 +1,422 @@
-use std::{hash::Hash, sync::Arc};
+use std::{
+    collections::HashSet,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use advmac::MacAddr6;
 use dashmap::DashMap;
+use serde::Serialize;
 
-use crate::types::{Duid, Option1837, Option82, Reservation};
+use crate::analytics::events::{now, ReservationMatch};
+use crate::config::V6NaTemplate;
+use crate::pool::PoolAllocator;
+use crate::types::{ClientId, Duid, Option1837, Option82, Reservation, Vlans};
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum ReservationKey {
     Mac(MacAddr6),
     Duid(Duid),
+    ClientId(ClientId),
     Opt82(Option82),
     Opt1837(Option1837),
+    Vlans(Vlans),
+}
+
+/// Lookup paths counted in [`LookupStats`]
+#[derive(Debug, Clone, Copy)]
+enum LookupPath {
+    ClientId,
+    Mac,
+    Vlans,
+    Option82,
+    Pool,
+    Duid,
+    Option1837,
+    RelayCache,
+}
+
+impl LookupPath {
+    /// In the order they are reported
+    const ALL: [LookupPath; 8] = [
+        LookupPath::ClientId,
+        LookupPath::Mac,
+        LookupPath::Vlans,
+        LookupPath::Option82,
+        LookupPath::Pool,
+        LookupPath::Duid,
+        LookupPath::Option1837,
+        LookupPath::RelayCache,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            LookupPath::ClientId => "client_id",
+            LookupPath::Mac => "mac",
+            LookupPath::Vlans => "vlans",
+            LookupPath::Option82 => "option82",
+            LookupPath::Pool => "pool",
+            LookupPath::Duid => "duid",
+            LookupPath::Option1837 => "option1837",
+            LookupPath::RelayCache => "relay_cache",
+        }
+    }
+}
+
+/// Hits and misses per lookup path, for telling which keys a deployment
+/// actually matches on and how well the relay cache does
+#[derive(Default)]
+pub struct LookupStats {
+    hits: [AtomicU64; LookupPath::ALL.len()],
+    misses: [AtomicU64; LookupPath::ALL.len()],
+}
+
+impl LookupStats {
+    fn record(&self, path: LookupPath, hit: bool) {
+        let counters = if hit { &self.hits } else { &self.misses };
+        counters[path as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Vec<LookupCount> {
+        LookupPath::ALL
+            .iter()
+            .map(|&path| LookupCount {
+                path: path.as_str(),
+                hits: self.hits[path as usize].load(Ordering::Relaxed),
+                misses: self.misses[path as usize].load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+/// Lookups of one path since startup, as health reports show them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LookupCount {
+    pub path: &'static str,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+type CachedMatch = (Arc<Reservation>, ReservationMatch);
+
+/// Reservations recently found from a subscriber's relay information, so
+/// its repeat packets skip the extractor chain. Two generations stand in for
+/// an LRU: matches are stamped with the current generation, a new one starts
+/// once it holds half the capacity, and a hit from the previous generation
+/// is stamped again. Anything not used for two generations is dropped.
+/// Sharded like the reservations themselves, so the workers don't queue on
+/// one lock for every relayed packet.
+struct RelayCache {
+    half: usize,
+    entries: DashMap<Option82, (CachedMatch, u64)>,
+    generation: AtomicU64,
+    /// Matches stamped with the current generation
+    fresh: AtomicUsize,
+}
+
+impl RelayCache {
+    fn new(capacity: usize) -> Option<Self> {
+        (capacity > 0).then(|| Self {
+            half: capacity.div_ceil(2),
+            entries: DashMap::new(),
+            generation: AtomicU64::new(0),
+            fresh: AtomicUsize::new(0),
+        })
+    }
+
+    fn get(&self, relay: &Option82) -> Option<CachedMatch> {
+        let (found, stamped) = self.entries.get(relay)?.clone();
+        if stamped != self.generation.load(Ordering::Relaxed) {
+            let generation = self.stamp(Some(relay));
+            // Unless a change emptied the cache meanwhile
+            if let Some(mut entry) = self.entries.get_mut(relay) {
+                entry.1 = generation;
+            }
+        }
+        Some(found)
+    }
+
+    fn insert(&self, relay: Option82, found: CachedMatch) {
+        let generation = self.stamp(None);
+        self.entries.insert(relay, (found, generation));
+    }
+
+    /// Generation to stamp a match with. The caller that finds the current
+    /// one full starts the next, dropping everything older than the one
+    /// being retired except `keep`.
+    fn stamp(&self, keep: Option<&Option82>) -> u64 {
+        if self.fresh.fetch_add(1, Ordering::Relaxed) != self.half {
+            return self.generation.load(Ordering::Relaxed);
+        }
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        self.fresh.store(1, Ordering::Relaxed);
+        self.entries
+            .retain(|relay, (_, stamped)| *stamped + 1 >= generation || Some(relay) == keep);
+        generation
+    }
+
+    fn clear(&self) {
+        self.entries.clear();
+        self.fresh.store(0, Ordering::Relaxed);
+    }
 }
 
 pub struct ReservationDb {
     inner: DashMap<ReservationKey, Arc<Reservation>>,
+    na_template: Option<V6NaTemplate>,
+    /// Outlives reloads: every replacement database shares the same one
+    pools: Option<Arc<PoolAllocator>>,
+    /// Outlives reloads like `pools`
+    stats: Arc<LookupStats>,
+    relay_cache_size: usize,
+    /// Emptied on every change, so it never returns a reservation that has
+    /// since been replaced or removed
+    relay_cache: Option<RelayCache>,
+}
+
+impl Default for ReservationDb {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ReservationDb {
     pub fn new() -> Self {
+        Self::with_na_template(None)
+    }
+
+    /// Database that fills in `"ipv6_na": "auto"` reservations from
+    /// `na_template` as they are loaded
+    pub fn with_na_template(na_template: Option<V6NaTemplate>) -> Self {
         Self {
             inner: DashMap::new(),
+            na_template,
+            pools: None,
+            stats: Arc::default(),
+            relay_cache_size: 0,
+            relay_cache: None,
         }
     }
 
-    pub fn insert(&self, reservation: Reservation) {
-        let stored = Arc::new(reservation);
+    /// Fall back to `pools` for Option 82 keys without a reservation
+    pub fn with_pools(mut self, pools: Option<Arc<PoolAllocator>>) -> Self {
+        self.pools = pools;
+        self
+    }
+
+    /// Remember up to `capacity` relay information matches, see
+    /// [`Self::cached_relay_match`]. 0 turns the cache off.
+    pub fn with_relay_cache(mut self, capacity: usize) -> Self {
+        self.relay_cache_size = capacity;
+        self.relay_cache = RelayCache::new(capacity);
+        self
+    }
 
-        if let Some(mac) = stored.mac {
-            self.inner.insert(ReservationKey::Mac(mac), stored.clone());
+    /// Empty database with the same template, pools, statistics and cache
+    /// size, to load a replacement set into
+    pub fn empty_like(&self) -> Self {
+        Self {
+            stats: self.stats.clone(),
+            ..Self::with_na_template(self.na_template.clone())
+                .with_pools(self.pools.clone())
+                .with_relay_cache(self.relay_cache_size)
         }
+    }
 
-        if let Some(ref duid) = stored.duid {
-            self.inner
-                .insert(ReservationKey::Duid(duid.clone()), stored.clone());
+    pub fn insert(&self, reservation: Reservation) {
+        if let Some(cache) = &self.relay_cache {
+            cache.clear();
         }
+        let stored = Arc::new(reservation);
+        for key in keys_of(&stored) {
+            self.inner.insert(key, stored.clone());
+        }
+    }
 
-        if let Some(ref opt82) = stored.option82 {
-            self.inner
-                .insert(ReservationKey::Opt82(opt82.clone()), stored.clone());
+    /// Insert `reservation`, then remove every existing reservation that
+    /// shared one of its keys so no stale entry is left reachable through
+    /// its other keys. Shared keys are overwritten in place, so a lookup
+    /// running alongside finds either the old or the new reservation, never
+    /// neither.
+    pub fn upsert(&self, reservation: Reservation) {
+        let replaced: Vec<Arc<Reservation>> = keys_of(&reservation)
+            .iter()
+            .filter_map(|key| self.stored(key))
+            .collect();
+        self.insert(reservation);
+        for existing in replaced {
+            for key in keys_of(&existing) {
+                self.inner
+                    .remove_if(&key, |_, stored| Arc::ptr_eq(stored, &existing));
+            }
+        }
+        // A lookup between the insert and the removals may have cached the
+        // old reservation through one of its other keys
+        if let Some(cache) = &self.relay_cache {
+            cache.clear();
         }
+    }
 
-        if let Some(ref opt1837) = stored.option1837 {
+    /// Remove every key pointing at `reservation`. Keys that have since been
+    /// taken over by a different reservation are left alone.
+    pub fn remove(&self, reservation: &Reservation) {
+        if let Some(cache) = &self.relay_cache {
+            cache.clear();
+        }
+        for key in keys_of(reservation) {
             self.inner
-                .insert(ReservationKey::Opt1837(opt1837.clone()), stored.clone());
+                .remove_if(&key, |_, stored| **stored == *reservation);
         }
     }
 
-    pub fn load_reservations(&self, reservations: Vec<Reservation>) {
+    /// Unique reservations in the database, sorted by IPv4 address so
+    /// persisted files are stable across writes.
+    pub fn reservations(&self) -> Vec<Reservation> {
+        let mut seen = HashSet::new();
+        let mut out: Vec<Reservation> = self
+            .inner
+            .iter()
+            .filter(|r| seen.insert(Arc::as_ptr(r.value())))
+            .map(|r| Reservation::clone(r.value()))
+            .collect();
+        out.sort_by_key(|r| (r.ipv4, r.ipv6_pd));
+        out
+    }
+
+    /// Derive every `auto` address, then insert. If any can't be derived,
+    /// nothing is inserted.
+    pub fn load_reservations(&self, mut reservations: Vec<Reservation>) -> Result<(), String> {
+        for reservation in reservations.iter_mut() {
+            self.derive_ipv6_na(reservation)?;
+        }
         for reservation in reservations.into_iter() {
             self.insert(reservation);
         }
+        Ok(())
+    }
+
+    /// Fill in `ipv6_na` if the reservation gave it as `"auto"`
+    pub fn derive_ipv6_na(&self, reservation: &mut Reservation) -> Result<(), String> {
+        if !reservation.ipv6_na_is_auto() {
+            return Ok(());
+        }
+        let Some(template) = &self.na_template else {
+            return Err(format!(
+                "Reservation for {} has \"ipv6_na\": \"auto\" but no v6_na_template is configured",
+                reservation.name()
+            ));
+        };
+        let derived = template
+            .derive(reservation)
+            .map_err(|e| format!("Cannot derive ipv6_na for {}: {e}", reservation.name()))?;
+        reservation.ipv6_na = Some(derived);
+        Ok(())
+    }
+
+    /// Reservation stored under `key`, unless it is outside its activation
+    /// window. Inactive reservations stay loaded so they are still persisted
+    /// and start matching without a reload.
+    fn active(&self, key: &ReservationKey) -> Option<Arc<Reservation>> {
+        let found = self.stored(key).filter(|r| r.is_active_at(now()));
+        let path = match key {
+            ReservationKey::Mac(_) => LookupPath::Mac,
+            ReservationKey::Duid(_) => LookupPath::Duid,
+            ReservationKey::ClientId(_) => LookupPath::ClientId,
+            ReservationKey::Opt82(_) => LookupPath::Option82,
+            ReservationKey::Opt1837(_) => LookupPath::Option1837,
+            ReservationKey::Vlans(_) => LookupPath::Vlans,
+        };
+        self.stats.record(path, found.is_some());
+        found
+    }
+
+    /// Reservation stored under `key`, active or not, for management
+    /// commands that change reservations rather than serve them
+    pub fn stored(&self, key: &ReservationKey) -> Option<Arc<Reservation>> {
+        self.inner.get(key).map(|r| Arc::clone(r.value()))
     }
 
     pub fn by_mac(&self, mac: MacAddr6) -> Option<Arc<Reservation>> {
-        self.inner
-            .get(&ReservationKey::Mac(mac))
-            .map(|r| Arc::clone(r.value()))
+        self.active(&ReservationKey::Mac(mac))
     }
 
     pub fn by_duid(&self, duid: &Duid) -> Option<Arc<Reservation>> {
-        self.inner
-            .get(&ReservationKey::Duid(duid.clone()))
-            .map(|r| Arc::clone(r.value()))
+        self.active(&ReservationKey::Duid(duid.clone()))
     }
 
+    pub fn by_client_id(&self, client_id: &ClientId) -> Option<Arc<Reservation>> {
+        self.active(&ReservationKey::ClientId(client_id.clone()))
+    }
+
+    /// A MAC-like Remote-ID or Circuit-ID matches in any format, so the
+    /// extractors that pass it through as sent still find the reservation
     pub fn by_opt82(&self, opt: &Option82) -> Option<Arc<Reservation>> {
-        self.inner
-            .get(&ReservationKey::Opt82(opt.clone()))
-            .map(|r| Arc::clone(r.value()))
+        self.active(&ReservationKey::Opt82(opt.clone().with_canonical_macs()))
     }
 
     pub fn by_opt1837(&self, opt: &Option1837) -> Option<Arc<Reservation>> {
-        self.inner
-            .get(&ReservationKey::Opt1837(opt.clone()))
-            .map(|r| Arc::clone(r.value()))
+        self.active(&ReservationKey::Opt1837(opt.clone()))
     }
 
-    /// Check if a reservation exists for the given Option82
+    pub fn by_vlans(&self, vlans: Vlans) -> Option<Arc<Reservation>> {
+        self.active(&ReservationKey::Vlans(vlans))
+    }
+
+    /// Pool reservation for relay info that matched no reservation,
+    /// assigned on first sight. Returns the extractor the pool keys on.
+    pub fn by_pool(&self, opt: &Option82) -> Option<(Arc<Reservation>, &'static str)> {
+        let found = self.pools.as_ref()?.assign(opt);
+        self.stats.record(LookupPath::Pool, found.is_some());
+        found
+    }
+
+    /// The reservation an earlier packet with the same relay information
+    /// was matched to through the Option 82 extractors, while it is still
+    /// active
+    pub fn cached_relay_match(
+        &self,
+        relay: &Option82,
+    ) -> Option<(Arc<Reservation>, ReservationMatch)> {
+        let cache = self.relay_cache.as_ref()?;
+        let found = cache.get(relay).filter(|(r, _)| r.is_active_at(now()));
+        self.stats.record(LookupPath::RelayCache, found.is_some());
+        found
+    }
+
+    /// Remember that `relay` matched `reservation` as `found`
+    pub fn cache_relay_match(
+        &self,
+        relay: Option82,
+        reservation: Arc<Reservation>,
+        found: ReservationMatch,
+    ) {
+        if let Some(cache) = &self.relay_cache {
+            cache.insert(relay, (reservation, found));
+        }
+    }
+
+    /// Lookups per path since startup, reloads included
+    pub fn lookup_stats(&self) -> Vec<LookupCount> {
+        self.stats.snapshot()
+    }
+
+    /// Pool reservation already assigned to an extracted key
+    pub fn by_pool_key(&self, opt: &Option82) -> Option<Arc<Reservation>> {
+        self.pools.as_ref()?.get(opt)
+    }
+
+    /// Check if an active reservation, or a pool assignment, exists for the
+    /// given Option82
     pub fn has_opt82(&self, opt82: &Option82) -> bool {
-        self.inner
-            .contains_key(&ReservationKey::Opt82(opt82.clone()))
+        self.by_opt82(opt82).is_some() || self.by_pool_key(opt82).is_some()
+    }
+
+    /// Check if an active reservation exists for the given Option1837
+    pub fn has_opt1837(&self, opt1837: &Option1837) -> bool {
+        self.by_opt1837(opt1837).is_some()
     }
 
     /// Returns the number of entries in the database.
@@ -88,11 +424,38 @@ impl ReservationDb {
     pub fn len(&self) -> usize {
         self.inner.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+pub(crate) fn keys_of(reservation: &Reservation) -> Vec<ReservationKey> {
+    let mut keys = Vec::with_capacity(6);
+    if let Some(mac) = reservation.mac {
+        keys.push(ReservationKey::Mac(mac));
+    }
+    if let Some(ref duid) = reservation.duid {
+        keys.push(ReservationKey::Duid(duid.clone()));
+    }
+    if let Some(ref client_id) = reservation.client_id {
+        keys.push(ReservationKey::ClientId(client_id.clone()));
+    }
+    if let Some(ref opt82) = reservation.option82 {
+        keys.push(ReservationKey::Opt82(opt82.clone().with_canonical_macs()));
+    }
+    if let Some(ref opt1837) = reservation.option1837 {
+        keys.push(ReservationKey::Opt1837(opt1837.clone()));
+    }
+    if let Some(vlans) = reservation.vlans() {
+        keys.push(ReservationKey::Vlans(vlans));
+    }
+    keys
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::Ipv4Addr;
+    use std::net::{Ipv4Addr, Ipv6Addr};
 
     use super::*;
     use advmac::MacAddr6;
@@ -167,20 +530,20 @@ mod tests {
         ]
         "#;
         let reservations: Vec<Reservation> = serde_json::from_str(json_str).unwrap();
-        db.load_reservations(reservations);
+        db.load_reservations(reservations).unwrap();
 
         assert_eq!(
             db.by_mac(MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]))
                 .unwrap()
                 .ipv4,
-            Ipv4Addr::new(192, 168, 1, 109)
+            Some(Ipv4Addr::new(192, 168, 1, 109))
         );
 
         assert_eq!(
             db.by_duid(&Duid::from(vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]))
                 .unwrap()
                 .ipv4,
-            Ipv4Addr::new(192, 168, 1, 112)
+            Some(Ipv4Addr::new(192, 168, 1, 112))
         );
 
         let opt82 = Option82 {
@@ -191,7 +554,206 @@ mod tests {
 
         assert_eq!(
             db.by_opt82(&opt82).unwrap().ipv4,
-            Ipv4Addr::new(192, 168, 1, 112)
+            Some(Ipv4Addr::new(192, 168, 1, 112))
+        );
+    }
+
+    #[test]
+    fn upsert_replaces_reservation_sharing_a_key() {
+        let db = ReservationDb::new();
+        let old: Reservation = serde_json::from_str(
+            r#"{"ipv4":"100.64.0.1","ipv6_na":"2001:db8::1","ipv6_pd":"2001:db8:1::/56","mac":"00-11-22-33-44-55","duid":"00:01:02:03"}"#,
+        )
+        .unwrap();
+        let new: Reservation = serde_json::from_str(
+            r#"{"ipv4":"100.64.0.2","ipv6_na":"2001:db8::2","ipv6_pd":"2001:db8:2::/56","mac":"00-11-22-33-44-55"}"#,
+        )
+        .unwrap();
+        db.insert(old);
+        db.upsert(new);
+
+        let mac = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        assert_eq!(
+            db.by_mac(mac).unwrap().ipv4,
+            Some(Ipv4Addr::new(100, 64, 0, 2))
         );
+        // the old reservation's DUID key must not survive the replacement
+        assert!(db.by_duid(&Duid::from(vec![0, 1, 2, 3])).is_none());
+        assert_eq!(db.reservations().len(), 1);
+
+        let current = db.by_mac(mac).unwrap();
+        db.remove(&current);
+        assert_eq!(db.len(), 0);
+    }
+
+    #[test]
+    fn upsert_never_leaves_a_key_unmatched() {
+        let db = ReservationDb::new();
+        let reservation: Reservation = serde_json::from_str(
+            r#"{"ipv4":"100.64.0.1","ipv6_na":"2001:db8::1","ipv6_pd":"2001:db8:1::/56","mac":"00-11-22-33-44-55","duid":"00:01:02:03"}"#,
+        )
+        .unwrap();
+        db.insert(reservation.clone());
+        let mac = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+        let done = std::sync::atomic::AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    assert!(db.by_mac(mac).is_some());
+                }
+            });
+            for _ in 0..1000 {
+                db.upsert(reservation.clone());
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        // Upserting an identical reservation keeps every key
+        assert_eq!(db.len(), 2);
+        assert!(db.by_duid(&Duid::from(vec![0, 1, 2, 3])).is_some());
+    }
+
+    #[test]
+    fn reservations_outside_their_window_not_matched() {
+        let db = ReservationDb::new();
+        let reservations: Vec<Reservation> = serde_json::from_str(
+            r#"[
+                {"ipv4": "100.64.0.1", "ipv6_na": "2001:db8::1", "ipv6_pd": "2001:db8:1::/56",
+                 "mac": "00-11-22-33-44-55", "activate_at": "2999-01-01T00:00:00Z"},
+                {"ipv4": "100.64.0.2", "ipv6_na": "2001:db8::2", "ipv6_pd": "2001:db8:2::/56",
+                 "option82": {"remote": "olt1:1"}, "deactivate_at": "2020-01-01T00:00:00Z"},
+                {"ipv4": "100.64.0.3", "ipv6_na": "2001:db8::3", "ipv6_pd": "2001:db8:3::/56",
+                 "mac": "00-11-22-33-44-57", "activate_at": "2020-01-01T00:00:00Z",
+                 "deactivate_at": "2999-01-01T00:00:00Z"}
+            ]"#,
+        )
+        .unwrap();
+        db.load_reservations(reservations).unwrap();
+
+        assert!(db
+            .by_mac(MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]))
+            .is_none());
+        let opt82 = Option82 {
+            circuit: None,
+            remote: Some("olt1:1".into()),
+            subscriber: None,
+        };
+        assert!(db.by_opt82(&opt82).is_none());
+        assert!(!db.has_opt82(&opt82));
+        assert!(db
+            .by_mac(MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x57]))
+            .is_some());
+        // still loaded, so still persisted
+        assert_eq!(db.reservations().len(), 3);
+    }
+
+    #[test]
+    fn auto_ipv6_na_derived_on_load() {
+        let json_str = r#"
+        [
+            {
+                "ipv4": "100.64.0.1",
+                "ipv6_na": "auto",
+                "ipv6_pd": "2001:db8:1:100::/56",
+                "mac": "00-11-22-33-44-55",
+                "subscriber_id": "258"
+            },
+            {
+                "ipv4": "100.64.0.2",
+                "ipv6_na": "2001:db8::2",
+                "ipv6_pd": "2001:db8:1:200::/56",
+                "mac": "00-11-22-33-44-56"
+            }
+        ]
+        "#;
+        let reservations: Vec<Reservation> = serde_json::from_str(json_str).unwrap();
+        let mac = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+
+        let template = serde_json::from_str(r#"{"from": "pd", "subnet": 255}"#).unwrap();
+        let db = ReservationDb::with_na_template(Some(template));
+        db.load_reservations(reservations.clone()).unwrap();
+        assert_eq!(
+            db.by_mac(mac).unwrap().ipv6_na,
+            Some("2001:db8:1:1ff::1".parse::<Ipv6Addr>().unwrap())
+        );
+
+        let template =
+            serde_json::from_str(r#"{"from": "subscriber_id", "prefix": "2001:db8:ff00::/40"}"#)
+                .unwrap();
+        let db = ReservationDb::with_na_template(Some(template));
+        db.load_reservations(reservations.clone()).unwrap();
+        assert_eq!(
+            db.by_mac(mac).unwrap().ipv6_na,
+            Some("2001:db8:ff00:102::1".parse::<Ipv6Addr>().unwrap())
+        );
+        // explicit addresses are left alone
+        assert_eq!(
+            db.by_mac(MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x56]))
+                .unwrap()
+                .ipv6_na,
+            Some("2001:db8::2".parse::<Ipv6Addr>().unwrap())
+        );
+
+        // nothing to derive from, so nothing is loaded
+        let db = ReservationDb::new();
+        assert!(db.load_reservations(reservations).is_err());
+        assert_eq!(db.len(), 0);
+    }
+
+    #[test]
+    fn relay_matches_cached_and_lookups_counted() {
+        let db = ReservationDb::new().with_relay_cache(2);
+        let reservations: Vec<Reservation> = serde_json::from_str(
+            r#"[
+                {"ipv4": "100.64.0.1", "ipv6_na": "2001:db8::1", "ipv6_pd": "2001:db8:1::/56",
+                 "option82": {"remote": "olt1:1"}},
+                {"ipv4": "100.64.0.2", "ipv6_na": "2001:db8::2", "ipv6_pd": "2001:db8:2::/56",
+                 "mac": "00-11-22-33-44-56"}
+            ]"#,
+        )
+        .unwrap();
+        db.load_reservations(reservations.clone()).unwrap();
+        let relay = |remote: &str| Option82 {
+            circuit: Some("eth 1/1/1:100".into()),
+            remote: Some(remote.into()),
+            subscriber: None,
+        };
+        let found = db.by_opt82(&reservations[0].option82.clone().unwrap());
+        assert!(db.cached_relay_match(&relay("olt1:1")).is_none());
+        db.cache_relay_match(
+            relay("olt1:1"),
+            found.unwrap(),
+            ReservationMatch::option82("remote_only"),
+        );
+        let (cached, method) = db.cached_relay_match(&relay("olt1:1")).unwrap();
+        assert_eq!(cached.ipv4, Some(Ipv4Addr::new(100, 64, 0, 1)));
+        assert_eq!(method.extractor, Some("remote_only"));
+
+        // a hit in the older generation survives two more inserts
+        let other = db.by_mac(MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x56]));
+        let other = other.unwrap();
+        let method = ReservationMatch::option82("remote_only");
+        db.cache_relay_match(relay("olt1:2"), Arc::clone(&other), method.clone());
+        assert!(db.cached_relay_match(&relay("olt1:1")).is_some());
+        db.cache_relay_match(relay("olt1:3"), Arc::clone(&other), method.clone());
+        db.cache_relay_match(relay("olt1:4"), Arc::clone(&other), method);
+        assert!(db.cached_relay_match(&relay("olt1:2")).is_none(), "evicted");
+
+        // any change to the reservations empties it
+        db.remove(&reservations[1]);
+        assert!(db.cached_relay_match(&relay("olt1:4")).is_none());
+
+        let count = |path: &str| {
+            let stats = db.lookup_stats();
+            let count = stats.iter().find(|c| c.path == path).unwrap();
+            (count.hits, count.misses)
+        };
+        assert_eq!(count("option82"), (1, 0));
+        assert_eq!(count("mac"), (1, 0));
+        assert_eq!(count("relay_cache"), (2, 3));
+        assert_eq!(count("pool"), (0, 0));
+        // shared with the database that replaces it
+        assert_eq!(db.empty_like().lookup_stats(), db.lookup_stats());
     }
 }
@@ -0,0 +1,86 @@
+//! Answering client retransmissions from the reply already sent.
+//!
+//! A client that hasn't heard back resends its Discover or Solicit with the
+//! same transaction id, often several times a second. Within
+//! `retransmit_window_ms` of the first reply, the worker resends the encoded
+//! reply it kept instead of handling the message again, and emits no event
+//! for it. Like the rate limiters, each worker owns its cache outright.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+struct CachedReply {
+    bytes: Vec<u8>,
+    dest: SocketAddr,
+    sent: Instant,
+}
+
+pub struct RetransmitCache<K> {
+    window: Duration,
+    replies: HashMap<K, CachedReply>,
+    last_sweep: Instant,
+}
+
+impl<K: Hash + Eq> RetransmitCache<K> {
+    /// `None` when `window_ms` is 0, which turns the cache off
+    pub fn new(window_ms: u64) -> Option<Self> {
+        (window_ms > 0).then(|| Self {
+            window: Duration::from_millis(window_ms),
+            replies: HashMap::new(),
+            last_sweep: Instant::now(),
+        })
+    }
+
+    /// The reply sent for `key` and where it went, if within the window
+    pub fn get(&self, key: &K) -> Option<(&[u8], SocketAddr)> {
+        self.replies
+            .get(key)
+            .filter(|reply| reply.sent.elapsed() < self.window)
+            .map(|reply| (reply.bytes.as_slice(), reply.dest))
+    }
+
+    pub fn insert(&mut self, key: K, bytes: &[u8], dest: SocketAddr) {
+        self.replies.insert(
+            key,
+            CachedReply {
+                bytes: bytes.to_vec(),
+                dest,
+                sent: Instant::now(),
+            },
+        );
+    }
+
+    /// Drop replies older than the window, at most once per window
+    pub fn sweep(&mut self) {
+        if self.last_sweep.elapsed() < self.window {
+            return;
+        }
+        let window = self.window;
+        self.replies
+            .retain(|_, reply| reply.sent.elapsed() < window);
+        self.last_sweep = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_served_within_window() {
+        assert!(RetransmitCache::<u32>::new(0).is_none());
+
+        let mut cache = RetransmitCache::new(20).unwrap();
+        let dest: SocketAddr = "192.0.2.1:67".parse().unwrap();
+        cache.insert(1u32, b"offer", dest);
+        assert_eq!(cache.get(&1), Some((&b"offer"[..], dest)));
+        assert_eq!(cache.get(&2), None);
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert_eq!(cache.get(&1), None, "expired");
+        cache.sweep();
+        assert!(cache.replies.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+//! Logs reservations whose `activate_at` or `deactivate_at` is coming up, and
+//! again once it has passed, so an install or churn date in
+//! reservations.json shows up in the logs before and when the server starts
+//! or stops answering that customer.
+//!
+//! The database itself skips reservations outside their window on every
+//! lookup; this thread only reports.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use tracing::info;
+
+use crate::analytics::events::now;
+use crate::reservationdb::ReservationDb;
+use crate::shutdown::Shutdown;
+use crate::types::{Reservation, Timestamp};
+
+/// How often the reservations are checked, and how far ahead changes are
+/// logged as upcoming
+const SCHEDULE_INTERVAL: Duration = Duration::from_hours(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Activate,
+    Deactivate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheduled {
+    /// [`Reservation::name`]
+    pub reservation: String,
+    pub change: Change,
+    pub at: Timestamp,
+}
+
+/// Activations and deactivations after `from` and up to `to`, both Unix
+/// milliseconds, earliest first
+pub fn between(reservations: &[Reservation], from: u64, to: u64) -> Vec<Scheduled> {
+    let mut changes: Vec<Scheduled> = reservations
+        .iter()
+        .flat_map(|reservation| {
+            [
+                (Change::Activate, reservation.activate_at),
+                (Change::Deactivate, reservation.deactivate_at),
+            ]
+            .into_iter()
+            .filter_map(|(change, at)| {
+                let at = at.filter(|at| (from + 1..=to).contains(&at.unix_millis()))?;
+                Some(Scheduled {
+                    reservation: reservation.name(),
+                    change,
+                    at,
+                })
+            })
+        })
+        .collect();
+    changes.sort_by_key(|scheduled| scheduled.at);
+    changes
+}
+
+/// Every [`SCHEDULE_INTERVAL`], log the changes since the last check and the
+/// ones due before the next
+pub fn logger(db: Arc<ArcSwap<ReservationDb>>, shutdown: Shutdown) {
+    let mut last = now();
+    loop {
+        let current = now();
+        let reservations = db.load().reservations();
+        for scheduled in between(&reservations, last, current) {
+            let verb = match scheduled.change {
+                Change::Activate => "activated",
+                Change::Deactivate => "deactivated",
+            };
+            info!(
+                "Reservation for {} {verb} at {}",
+                scheduled.reservation, scheduled.at
+            );
+        }
+        let ahead = current + SCHEDULE_INTERVAL.as_millis() as u64;
+        for scheduled in between(&reservations, current, ahead) {
+            let verb = match scheduled.change {
+                Change::Activate => "activates",
+                Change::Deactivate => "deactivates",
+            };
+            info!(
+                "Reservation for {} {verb} at {}",
+                scheduled.reservation, scheduled.at
+            );
+        }
+        last = current;
+        if shutdown.wait_timeout(SCHEDULE_INTERVAL) {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_within_the_window_earliest_first() {
+        let reservations: Vec<Reservation> = serde_json::from_str(
+            r#"[
+                {"ipv4": "100.64.0.1", "ipv6_na": "2001:db8::1", "ipv6_pd": "2001:db8:1::/56",
+                 "mac": "00-11-22-33-44-55", "deactivate_at": "2025-06-01T12:00:00Z"},
+                {"ipv4": "100.64.0.2", "ipv6_na": "2001:db8::2", "ipv6_pd": "2001:db8:2::/56",
+                 "mac": "00-11-22-33-44-56", "activate_at": "2025-06-01T06:00:00Z",
+                 "deactivate_at": "2025-07-01T00:00:00Z"},
+                {"ipv4": "100.64.0.3", "ipv6_na": "2001:db8::3", "ipv6_pd": "2001:db8:3::/56",
+                 "mac": "00-11-22-33-44-57"}
+            ]"#,
+        )
+        .unwrap();
+        let day = |at: &str| at.parse::<Timestamp>().unwrap();
+        let (from, to) = (day("2025-06-01T06:00:00Z"), day("2025-06-02T00:00:00Z"));
+
+        let changes = between(&reservations, from.unix_millis() - 1, to.unix_millis());
+        assert_eq!(
+            changes,
+            [
+                Scheduled {
+                    reservation: "100.64.0.2".into(),
+                    change: Change::Activate,
+                    at: from,
+                },
+                Scheduled {
+                    reservation: "100.64.0.1".into(),
+                    change: Change::Deactivate,
+                    at: day("2025-06-01T12:00:00Z"),
+                },
+            ]
+        );
+        // `from` itself was reported by the previous check
+        assert_eq!(
+            between(&reservations, from.unix_millis(), to.unix_millis()).len(),
+            1
+        );
+    }
+}
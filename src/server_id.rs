@@ -0,0 +1,134 @@
+//! Server identifiers for a config directory without ids.json. The DHCPv6
+//! DUID is a DUID-LLT from one of the machine's Ethernet addresses, or a
+//! random DUID-UUID where none can be read. The DHCPv4 server id has to be
+//! one of the server's addresses, so it is only filled in when the config
+//! names one. The server writes what it generated to ids.json at startup, so
+//! clients see the same ids after a restart.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use advmac::MacAddr6;
+
+use crate::types::{parse_mac, Duid};
+
+/// DUID-LLT time is counted from midnight UTC, January 1, 2000
+const DUID_EPOCH: u64 = 946_684_800;
+/// ARP hardware type of Ethernet
+const HTYPE_ETHERNET: u16 = 1;
+
+/// DUID-LLT (RFC 8415 §11.2) for an Ethernet address
+pub fn duid_llt(mac: MacAddr6, unix_secs: u64) -> Duid {
+    let time = unix_secs.saturating_sub(DUID_EPOCH) as u32;
+    let mut bytes = Vec::with_capacity(14);
+    bytes.extend(1u16.to_be_bytes());
+    bytes.extend(HTYPE_ETHERNET.to_be_bytes());
+    bytes.extend(time.to_be_bytes());
+    bytes.extend(mac.to_array());
+    Duid::from(bytes)
+}
+
+/// A DUID-LLT from `interface`, or the first Ethernet interface when none
+/// is given, falling back to a DUID-UUID
+pub fn generate_duid(interface: Option<&str>) -> Duid {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    match interface_mac(interface) {
+        Some(mac) => duid_llt(mac, now),
+        None => Duid::from_uuid(random_uuid()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn interface_mac(interface: Option<&str>) -> Option<MacAddr6> {
+    let read = |name: &str, file: &str| {
+        std::fs::read_to_string(Path::new("/sys/class/net").join(name).join(file)).ok()
+    };
+    let ethernet_mac = |name: &str| {
+        if read(name, "type")?.trim() != HTYPE_ETHERNET.to_string() {
+            return None;
+        }
+        parse_mac(&read(name, "address")?).filter(|mac| !mac.is_nil())
+    };
+    if let Some(interface) = interface {
+        return ethernet_mac(interface);
+    }
+    let mut names: Vec<String> = std::fs::read_dir("/sys/class/net")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    names.sort_unstable();
+    names.iter().find_map(|name| ethernet_mac(name))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interface_mac(_interface: Option<&str>) -> Option<MacAddr6> {
+    None
+}
+
+/// A version 4 UUID. std seeds each `RandomState` from the OS, which is
+/// all the randomness an identifier needs.
+fn random_uuid() -> [u8; 16] {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut uuid = [0u8; 16];
+    uuid[..8].copy_from_slice(&RandomState::new().hash_one(seed).to_be_bytes());
+    uuid[8..].copy_from_slice(&RandomState::new().hash_one(seed).to_be_bytes());
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
+
+/// Write `v4` and `v6` to ids.json in `config_dir`, through a temporary file
+/// so a crash can't leave it half written
+pub fn write_ids(config_dir: &Path, v4: Option<Ipv4Addr>, v6: &Duid) -> io::Result<()> {
+    let mut ids = serde_json::Map::new();
+    if let Some(v4) = v4 {
+        ids.insert("v4".into(), v4.to_string().into());
+    }
+    ids.insert("v6".into(), v6.to_string().into());
+
+    let target = config_dir.join("ids.json");
+    let temp = config_dir.join("ids.json.tmp");
+    let mut file = std::fs::File::create(&temp)?;
+    serde_json::to_writer_pretty(&mut file, &ids).map_err(io::Error::other)?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    std::fs::rename(&temp, &target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DuidType;
+
+    #[test]
+    fn generated_duids_round_trip_through_ids_json() {
+        let mac = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        // 2024-01-01, 757382400 seconds after the DUID epoch
+        let llt = duid_llt(mac, 1_704_067_200);
+        assert_eq!(llt.to_string(), "00:01:00:01:2d:24:bd:00:00:11:22:33:44:55");
+        assert_eq!(llt.link_layer_address(), Some(&mac.to_array()[..]));
+
+        let uuid = Duid::from_uuid(random_uuid());
+        assert_eq!(uuid.duid_type(), Some(DuidType::Uuid));
+        assert_eq!(uuid.uuid().unwrap()[6] >> 4, 4, "version 4");
+
+        let dir = std::env::temp_dir().join(format!("shadowdhcp-ids-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_ids(&dir, Some(Ipv4Addr::new(192, 0, 2, 1)), &llt).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("ids.json")).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(written["v4"], "192.0.2.1");
+        assert_eq!(written["v6"], llt.to_string());
+    }
+}
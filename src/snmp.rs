@@ -0,0 +1,282 @@
+//! The health report as an SNMP subtree, for monitoring that still polls
+//! SNMP. The server doesn't speak SNMP itself: the management listener
+//! serves the subtree as a text table at `/snmp`, and `shadowdhcp snmp`
+//! hands it to the system's snmpd through net-snmp's `pass_persist`
+//! protocol, so snmpd's own communities and access control apply.
+//!
+//! OIDs are relative to the base the subcommand is given:
+//!
+//! ```text
+//! .1 DHCPv4, .2 DHCPv6
+//!   .1.0            packets received        Counter32
+//!   .2.0            workers running         Gauge32
+//!   .3.0            receive errors          Counter32
+//!   .4.1.1.<name>   outcome name            STRING
+//!   .4.1.2.<name>   messages handled        Counter32
+//! .3.0              reservations            Gauge32
+//! .4 leases
+//!   .1.0            DHCPv4 lease holders    Gauge32
+//!   .2.0            DHCPv6 lease holders    Gauge32
+//!   .3.0            Option 82 bindings      Gauge32
+//!   .4.0            Option 18/37 bindings   Gauge32
+//! .5.0              events queued           Gauge32
+//! ```
+//!
+//! The outcome table is indexed by the outcome's name, the reply type or
+//! `failure_reason` of the events, encoded as its length followed by its
+//! bytes, so each keeps its OID as new ones appear.
+
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant};
+
+use crate::health::{ProtocolStatus, Report};
+
+/// net-snmp's experimental `netSnmpPlaypen` subtree, used when no base OID
+/// is given
+pub const DEFAULT_BASE: &str = ".1.3.6.1.4.1.8072.9999.9999.67";
+/// How long `pass_persist` answers from one fetch of the table. A walk is
+/// one request per object, and they should all see the same numbers.
+const CACHE_FOR: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub oid: Vec<u32>,
+    /// The `pass_persist` type: `counter`, `gauge` or `string`
+    pub kind: &'static str,
+    pub value: String,
+}
+
+/// The report's objects in OID order
+pub fn table(report: &Report) -> Vec<Object> {
+    let mut objects = Vec::new();
+    for (branch, status) in [(1, &report.v4), (2, &report.v6)] {
+        protocol(&mut objects, branch, status);
+    }
+    let gauges = [
+        (vec![3, 0], report.reservations),
+        (vec![4, 1, 0], report.leases.v4_holders),
+        (vec![4, 2, 0], report.leases.v6_holders),
+        (vec![4, 3, 0], report.leases.option82_bindings),
+        (vec![4, 4, 0], report.leases.option1837_bindings),
+        (vec![5, 0], report.events_queued),
+    ];
+    for (oid, value) in gauges {
+        objects.push(gauge(oid, value as u64));
+    }
+    objects.sort_by(|a, b| a.oid.cmp(&b.oid));
+    objects
+}
+
+fn protocol(objects: &mut Vec<Object>, branch: u32, status: &ProtocolStatus) {
+    objects.push(counter(vec![branch, 1, 0], status.packets_received));
+    objects.push(gauge(vec![branch, 2, 0], status.workers_running as u64));
+    objects.push(counter(vec![branch, 3, 0], status.recv_errors));
+    for (name, count) in &status.outcomes {
+        let mut index = vec![name.len() as u32];
+        index.extend(name.bytes().map(u32::from));
+        let column = |column: u32| [vec![branch, 4, 1, column], index.clone()].concat();
+        objects.push(Object {
+            oid: column(1),
+            kind: "string",
+            value: name.to_string(),
+        });
+        objects.push(counter(column(2), *count));
+    }
+}
+
+/// Counter32 wraps, as SNMP pollers expect of it
+fn counter(oid: Vec<u32>, value: u64) -> Object {
+    Object {
+        oid,
+        kind: "counter",
+        value: (value % (1 << 32)).to_string(),
+    }
+}
+
+/// Gauge32 stops at its maximum
+fn gauge(oid: Vec<u32>, value: u64) -> Object {
+    Object {
+        oid,
+        kind: "gauge",
+        value: value.min(u64::from(u32::MAX)).to_string(),
+    }
+}
+
+/// The `/snmp` body: one `OID TYPE VALUE` line per object
+pub fn render(objects: &[Object]) -> String {
+    objects
+        .iter()
+        .map(|object| {
+            format!(
+                "{} {} {}\n",
+                format_oid(&object.oid),
+                object.kind,
+                object.value
+            )
+        })
+        .collect()
+}
+
+/// Read back what [`render`] wrote
+pub fn parse(text: &str) -> Result<Vec<Object>, String> {
+    text.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let (Some(oid), Some(kind), Some(value)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(format!("Invalid line `{line}`"));
+            };
+            let kind = match kind {
+                "counter" => "counter",
+                "gauge" => "gauge",
+                "string" => "string",
+                _ => return Err(format!("Unknown type in `{line}`")),
+            };
+            Ok(Object {
+                oid: parse_oid(oid).ok_or_else(|| format!("Invalid OID in `{line}`"))?,
+                kind,
+                value: value.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn parse_oid(oid: &str) -> Option<Vec<u32>> {
+    oid.trim()
+        .trim_start_matches('.')
+        .split('.')
+        .map(|arc| arc.parse().ok())
+        .collect()
+}
+
+fn format_oid(oid: &[u32]) -> String {
+    oid.iter().map(|arc| format!(".{arc}")).collect()
+}
+
+/// Answer snmpd's `pass_persist` requests for the subtree at `base` until it
+/// closes `input`. `fetch` gets the table from the server; when it fails
+/// every object is reported missing until the next fetch.
+pub fn pass_persist(
+    base: &[u32],
+    input: impl BufRead,
+    mut output: impl Write,
+    mut fetch: impl FnMut() -> Result<Vec<Object>, String>,
+) -> io::Result<()> {
+    let mut cached: Option<(Instant, Vec<Object>)> = None;
+    let mut lines = input.lines();
+    while let Some(command) = lines.next() {
+        let command = command?;
+        let reply = match command.trim() {
+            "" => return Ok(()),
+            "PING" => "PONG\n".to_string(),
+            "set" => {
+                // The OID, then the type and value
+                lines.next().transpose()?;
+                lines.next().transpose()?;
+                "not-writable\n".to_string()
+            }
+            command @ ("get" | "getnext") => {
+                let Some(oid) = lines.next().transpose()? else {
+                    return Ok(());
+                };
+                if cached
+                    .as_ref()
+                    .is_none_or(|(fetched, _)| fetched.elapsed() >= CACHE_FOR)
+                {
+                    let objects = fetch().unwrap_or_else(|e| {
+                        eprintln!("shadowdhcp snmp: {e}");
+                        Vec::new()
+                    });
+                    cached = Some((Instant::now(), objects));
+                }
+                let objects = cached.as_ref().map_or(&[][..], |(_, objects)| objects);
+                match lookup(base, objects, &oid, command == "getnext") {
+                    Some((oid, object)) => {
+                        format!("{}\n{}\n{}\n", format_oid(&oid), object.kind, object.value)
+                    }
+                    None => "NONE\n".to_string(),
+                }
+            }
+            other => {
+                eprintln!("shadowdhcp snmp: unknown request `{other}`");
+                "NONE\n".to_string()
+            }
+        };
+        output.write_all(reply.as_bytes())?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// The object at `oid`, or with `next` the first one after it, and its full
+/// OID
+fn lookup<'a>(
+    base: &[u32],
+    objects: &'a [Object],
+    oid: &str,
+    next: bool,
+) -> Option<(Vec<u32>, &'a Object)> {
+    let oid = parse_oid(oid)?;
+    let full = |object: &Object| [base, &object.oid[..]].concat();
+    if next {
+        objects
+            .iter()
+            .map(|object| (full(object), object))
+            .find(|(full, _)| *full > oid)
+    } else {
+        objects
+            .iter()
+            .find(|object| full(object) == oid)
+            .map(|object| (oid.clone(), object))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::health::Health;
+    use crate::opt82_cache::CacheStats;
+
+    #[test]
+    fn report_walks_through_pass_persist() {
+        let health = Health::default();
+        health.v4.record_packet();
+        health.v4.record_outcome("Offer");
+        health.v4.record_outcome("NoReservation");
+        health.v4.record_outcome("NoReservation");
+        let report = health.report(&Config::default(), 7, CacheStats::default());
+
+        let objects = table(&report);
+        assert_eq!(parse(&render(&objects)).unwrap(), objects);
+        // Indexed by length first, so "Offer" comes before "NoReservation"
+        assert_eq!(objects[3].oid, [1, 4, 1, 1, 5, 79, 102, 102, 101, 114]);
+        assert_eq!(objects[4].value, "NoReservation");
+
+        let base = parse_oid(".1.3.6.1.4.1.8072.9999.9999.67").unwrap();
+        let input = "PING\ngetnext\n.1.3.6.1.4.1.8072.9999.9999.67\n\
+            get\n.1.3.6.1.4.1.8072.9999.9999.67.1.4.1.2.5.79.102.102.101.114\n\
+            get\n.1.3.6.1.4.1.8072.9999.9999.67.3\n\
+            getnext\n.1.3.6.1.4.1.8072.9999.9999.67.3\n\
+            set\n.1.3.6.1.4.1.8072.9999.9999.67.3.0\ngauge 1\n\n";
+        let mut output = Vec::new();
+        let mut fetches = 0;
+        pass_persist(&base, input.as_bytes(), &mut output, || {
+            fetches += 1;
+            Ok(objects.clone())
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "PONG\n\
+             .1.3.6.1.4.1.8072.9999.9999.67.1.1.0\ncounter\n1\n\
+             .1.3.6.1.4.1.8072.9999.9999.67.1.4.1.2.5.79.102.102.101.114\ncounter\n1\n\
+             NONE\n\
+             .1.3.6.1.4.1.8072.9999.9999.67.3.0\ngauge\n7\n\
+             not-writable\n"
+        );
+        assert_eq!(fetches, 1);
+    }
+}
@@ -0,0 +1,196 @@
+//! `subnets_v4`, indexed for looking up the subnet an address belongs to.
+//!
+//! Each entry's `net` and `ranges` go into a binary trie on the address
+//! bits, so a lookup is at most 32 steps however many subnets there are,
+//! and the longest matching prefix wins: a supernet can carry the defaults
+//! for a block while more specific entries inside it override them.
+
+use std::net::Ipv4Addr;
+
+use ipnet::Ipv4Net;
+
+use crate::types::V4Subnet;
+
+/// The configured subnets, in config order, and the trie over them
+#[derive(Debug, Clone, Default)]
+pub struct V4Subnets {
+    subnets: Vec<V4Subnet>,
+    trie: PrefixTrie,
+}
+
+impl V4Subnets {
+    /// Index `subnets`. A prefix listed more than once is matched to its
+    /// first entry; config loading rejects that before it gets here.
+    pub fn new(subnets: Vec<V4Subnet>) -> Self {
+        let mut trie = PrefixTrie::default();
+        for (index, subnet) in subnets.iter().enumerate() {
+            for prefix in subnet.prefixes() {
+                trie.insert(prefix, index);
+            }
+        }
+        Self { subnets, trie }
+    }
+
+    /// The entry with the longest prefix containing `ip`, and that prefix
+    pub fn find(&self, ip: Ipv4Addr) -> Option<(&V4Subnet, Ipv4Net)> {
+        self.find_index(ip)
+            .map(|(index, prefix)| (&self.subnets[index], prefix))
+    }
+
+    /// Like [`find`](Self::find), with the entry's position in config order
+    pub fn find_index(&self, ip: Ipv4Addr) -> Option<(usize, Ipv4Net)> {
+        self.trie.longest_match(ip)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, V4Subnet> {
+        self.subnets.iter()
+    }
+
+    pub fn as_slice(&self) -> &[V4Subnet] {
+        &self.subnets
+    }
+}
+
+impl From<Vec<V4Subnet>> for V4Subnets {
+    fn from(subnets: Vec<V4Subnet>) -> Self {
+        Self::new(subnets)
+    }
+}
+
+/// Prefixes listed in more than one place in `subnets`: in two entries, or
+/// twice in one. Returns each repeat with the entry it first appeared in.
+pub fn duplicates(subnets: &[V4Subnet]) -> Vec<(Ipv4Net, Ipv4Net)> {
+    let mut seen = std::collections::HashMap::new();
+    let mut duplicates = Vec::new();
+    for subnet in subnets {
+        for prefix in subnet.prefixes().map(|prefix| prefix.trunc()) {
+            if let Some(first) = seen.insert(prefix, subnet.net) {
+                duplicates.push((prefix, first));
+            }
+        }
+    }
+    duplicates
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    children: [Option<u32>; 2],
+    /// Entry index, for a node that ends a configured prefix
+    value: Option<usize>,
+}
+
+/// Binary trie from IPv4 prefixes to entry indexes, nodes kept in one `Vec`
+#[derive(Debug, Clone)]
+struct PrefixTrie {
+    nodes: Vec<Node>,
+}
+
+impl Default for PrefixTrie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::default()],
+        }
+    }
+}
+
+/// Bit `depth` of `addr`, counting from the most significant
+fn bit(addr: u32, depth: u8) -> usize {
+    ((addr >> (31 - depth)) & 1) as usize
+}
+
+impl PrefixTrie {
+    /// Keeps the value already there when `prefix` was inserted before
+    fn insert(&mut self, prefix: Ipv4Net, value: usize) {
+        let addr = u32::from(prefix.network());
+        let mut node = 0;
+        for depth in 0..prefix.prefix_len() {
+            let b = bit(addr, depth);
+            node = match self.nodes[node].children[b] {
+                Some(child) => child as usize,
+                None => {
+                    self.nodes.push(Node::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children[b] = Some(child as u32);
+                    child
+                }
+            };
+        }
+        self.nodes[node].value.get_or_insert(value);
+    }
+
+    fn longest_match(&self, ip: Ipv4Addr) -> Option<(usize, Ipv4Net)> {
+        let addr = u32::from(ip);
+        let mut node = &self.nodes[0];
+        let mut best = node.value.map(|value| (value, 0));
+        for depth in 0..32 {
+            let Some(child) = node.children[bit(addr, depth)] else {
+                break;
+            };
+            node = &self.nodes[child as usize];
+            if let Some(value) = node.value {
+                best = Some((value, depth + 1));
+            }
+        }
+        best.map(|(value, len)| {
+            let prefix = Ipv4Net::new(ip, len).expect("prefix length at most 32");
+            (value, prefix.trunc())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Vec<V4Subnet> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let subnets = V4Subnets::new(parse(
+            r#"[{"net": "100.64.0.0/16", "gateway": "100.64.0.1"},
+                {"net": "100.64.8.0/24", "gateway": "100.64.8.1", "ranges": ["100.65.0.0/24", "100.66.0.0/24"]},
+                {"net": "100.64.8.128/25", "gateway": "100.64.8.129"}]"#,
+        ));
+        let find = |ip: &str| {
+            subnets
+                .find_index(ip.parse().unwrap())
+                .map(|(index, prefix)| (index, prefix.to_string()))
+        };
+
+        assert_eq!(find("100.64.0.5"), Some((0, "100.64.0.0/16".into())));
+        assert_eq!(find("100.64.8.5"), Some((1, "100.64.8.0/24".into())));
+        assert_eq!(find("100.64.8.200"), Some((2, "100.64.8.128/25".into())));
+        assert_eq!(find("100.66.0.9"), Some((1, "100.66.0.0/24".into())));
+        assert_eq!(find("100.67.0.1"), None);
+
+        let everything = V4Subnets::new(parse(r#"[{"net": "0.0.0.0/0", "gateway": "0.0.0.1"}]"#));
+        assert_eq!(
+            everything.find_index(Ipv4Addr::BROADCAST),
+            Some((0, "0.0.0.0/0".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn repeated_prefixes_found() {
+        let repeated = parse(
+            r#"[{"net": "100.64.0.0/24", "gateway": "100.64.0.1", "ranges": ["100.65.0.0/24"]},
+                {"net": "100.65.0.0/24", "gateway": "100.65.0.1"}]"#,
+        );
+        assert_eq!(
+            duplicates(&repeated),
+            [(
+                "100.65.0.0/24".parse().unwrap(),
+                "100.64.0.0/24".parse().unwrap()
+            )]
+        );
+        // The first entry keeps it
+        assert_eq!(
+            V4Subnets::new(repeated)
+                .find_index("100.65.0.1".parse().unwrap())
+                .map(|(index, _)| index),
+            Some(0)
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Packet builders for tests and test tooling.
+//!
+//! Each builder returns a message as a client and relay agent in front of
+//! the server would send it: DHCPv4 messages are BOOTREQUESTs with the
+//! fields RFC 2131 Table 5 sets for that state, and DHCPv6 messages go in a
+//! Relay-forward. The unit tests, the integration tests, the benches,
+//! `shadowdhcp-client` and downstream integration tests use them so they
+//! all build packets the same way.
+//!
+//! Only built with the `testutil` feature:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! shadowdhcp = { git = "https://github.com/shadowinternet/shadowdhcp.git", default-features = false, features = ["testutil"] }
+//! ```
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use advmac::MacAddr6;
+use dhcproto::v4::{self, relay::RelayAgentInformation, relay::RelayInfo};
+use dhcproto::v6::{self, RelayMessage, RelayMessageData};
+
+/// A BOOTREQUEST of `msg_type` from `mac`
+fn v4_message(
+    mac: MacAddr6,
+    xid: u32,
+    ciaddr: Ipv4Addr,
+    giaddr: Ipv4Addr,
+    msg_type: v4::MessageType,
+) -> v4::Message {
+    let mut msg = v4::Message::new_with_id(
+        xid,
+        ciaddr,
+        Ipv4Addr::UNSPECIFIED,
+        Ipv4Addr::UNSPECIFIED,
+        giaddr,
+        &mac.to_array(),
+    );
+    msg.set_opcode(v4::Opcode::BootRequest);
+    msg.opts_mut().insert(v4::DhcpOption::MessageType(msg_type));
+    msg
+}
+
+/// DHCPDISCOVER relayed through `giaddr`
+pub fn discover(mac: MacAddr6, xid: u32, giaddr: Ipv4Addr) -> v4::Message {
+    v4_message(
+        mac,
+        xid,
+        Ipv4Addr::UNSPECIFIED,
+        giaddr,
+        v4::MessageType::Discover,
+    )
+}
+
+/// DHCPREQUEST in SELECTING state, answering an OFFER of `requested_ip`
+/// from `server_id`
+pub fn request_selecting(
+    mac: MacAddr6,
+    xid: u32,
+    giaddr: Ipv4Addr,
+    server_id: Ipv4Addr,
+    requested_ip: Ipv4Addr,
+) -> v4::Message {
+    let mut msg = v4_message(
+        mac,
+        xid,
+        Ipv4Addr::UNSPECIFIED,
+        giaddr,
+        v4::MessageType::Request,
+    );
+    let opts = msg.opts_mut();
+    opts.insert(v4::DhcpOption::ServerIdentifier(server_id));
+    opts.insert(v4::DhcpOption::RequestedIpAddress(requested_ip));
+    msg
+}
+
+/// DHCPREQUEST in INIT-REBOOT state: `requested_ip` and no server identifier
+pub fn request_init_reboot(
+    mac: MacAddr6,
+    xid: u32,
+    giaddr: Ipv4Addr,
+    requested_ip: Ipv4Addr,
+) -> v4::Message {
+    let mut msg = v4_message(
+        mac,
+        xid,
+        Ipv4Addr::UNSPECIFIED,
+        giaddr,
+        v4::MessageType::Request,
+    );
+    msg.opts_mut()
+        .insert(v4::DhcpOption::RequestedIpAddress(requested_ip));
+    msg
+}
+
+/// DHCPREQUEST in RENEWING state, unicast from `ciaddr` so giaddr is zero
+pub fn request_renew(mac: MacAddr6, xid: u32, ciaddr: Ipv4Addr) -> v4::Message {
+    v4_message(
+        mac,
+        xid,
+        ciaddr,
+        Ipv4Addr::UNSPECIFIED,
+        v4::MessageType::Request,
+    )
+}
+
+/// DHCPREQUEST in REBINDING state, broadcast from `ciaddr` and relayed
+/// through `giaddr`
+pub fn request_rebinding(
+    mac: MacAddr6,
+    xid: u32,
+    ciaddr: Ipv4Addr,
+    giaddr: Ipv4Addr,
+) -> v4::Message {
+    v4_message(mac, xid, ciaddr, giaddr, v4::MessageType::Request)
+}
+
+/// Option 82 with whichever of the circuit-id, remote-id and subscriber-id
+/// are given, `None` when none are
+pub fn relay_agent_information(
+    circuit_id: Option<&[u8]>,
+    remote_id: Option<&[u8]>,
+    subscriber_id: Option<&[u8]>,
+) -> Option<v4::DhcpOption> {
+    let mut info = RelayAgentInformation::default();
+    if let Some(circuit) = circuit_id {
+        info.insert(RelayInfo::AgentCircuitId(circuit.to_vec()));
+    }
+    if let Some(remote) = remote_id {
+        info.insert(RelayInfo::AgentRemoteId(remote.to_vec()));
+    }
+    if let Some(subscriber) = subscriber_id {
+        info.insert(RelayInfo::SubscriberId(subscriber.to_vec()));
+    }
+    (circuit_id.is_some() || remote_id.is_some() || subscriber_id.is_some())
+        .then_some(v4::DhcpOption::RelayAgentInformation(info))
+}
+
+/// Solicit from `client_id` asking for an address and a prefix
+pub fn solicit(client_id: &[u8]) -> v6::Message {
+    let mut msg = v6::Message::new(v6::MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(v6::DhcpOption::ClientId(client_id.to_vec()));
+    opts.insert(empty_ia_na());
+    opts.insert(empty_ia_pd());
+    msg
+}
+
+/// IA_NA with IAID 1 and no address, as in a Solicit
+pub fn empty_ia_na() -> v6::DhcpOption {
+    v6::DhcpOption::IANA(v6::IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: v6::DhcpOptions::new(),
+    })
+}
+
+/// IA_PD with IAID 1 and no prefix, as in a Solicit
+pub fn empty_ia_pd() -> v6::DhcpOption {
+    v6::DhcpOption::IAPD(v6::IAPD {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: v6::DhcpOptions::new(),
+    })
+}
+
+/// Relay-forward carrying `msg` and nothing else. Relay options such as
+/// Interface-ID can be added to its `opts`.
+pub fn relay_forw(msg: &v6::Message, link_addr: Ipv6Addr, peer_addr: Ipv6Addr) -> RelayMessage {
+    let mut opts = v6::DhcpOptions::new();
+    opts.insert(v6::DhcpOption::RelayMsg(RelayMessageData::Message(
+        msg.clone(),
+    )));
+    RelayMessage {
+        msg_type: v6::MessageType::RelayForw,
+        hop_count: 0,
+        link_addr,
+        peer_addr,
+        opts,
+    }
+}
+
+/// RFC 8415 §11.4 DUID-LL, hardware type 1 (Ethernet)
+pub fn duid_ll(mac: MacAddr6) -> Vec<u8> {
+    let mut duid = vec![0, 3, 0, 1];
+    duid.extend(mac.to_array());
+    duid
+}
+
+/// fe80:: address a CPE would source its Solicit from, so the server's
+/// `peer_addr_eui64` MAC extractor finds the same MAC
+pub fn link_local_eui64(mac: MacAddr6) -> Ipv6Addr {
+    let m = mac.to_array();
+    Ipv6Addr::from([
+        0xfe,
+        0x80,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        m[0] ^ 0x02,
+        m[1],
+        m[2],
+        0xff,
+        0xfe,
+        m[3],
+        m[4],
+        m[5],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v6::mac_extractors::MacExtractor;
+
+    #[test]
+    fn solicit_mac_found_by_extractors() {
+        let mac = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let msg = solicit(&duid_ll(mac));
+        let relay_msg = relay_forw(&msg, Ipv6Addr::UNSPECIFIED, link_local_eui64(mac));
+
+        for extractor in [MacExtractor::PeerAddrEui64, MacExtractor::Duid] {
+            assert_eq!(
+                extractor.extract(&relay_msg, &msg),
+                Some(mac),
+                "{extractor:?}"
+            );
+        }
+    }
+}
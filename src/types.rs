@@ -2,31 +2,388 @@ use core::fmt;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
 use advmac::MacAddr6;
-use compact_str::CompactString;
+use compact_str::{CompactString, ToCompactString};
 use ipnet::{Ipv4Net, Ipv6Net};
 use serde::{de::Visitor, Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct Reservation {
-    // customer WAN v4 address
-    pub ipv4: Ipv4Addr,
-    // customer WAN v6 address /64
-    pub ipv6_na: Ipv6Addr,
+    // customer WAN v4 address, or none for customers that only get IPv6.
+    // DHCPv4 treats a reservation without one as no reservation at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<Ipv4Addr>,
+    // customer WAN v6 address /64, or none for customers that only get
+    // prefix delegation. `"auto"` in JSON, held as `::` until the
+    // ReservationDb derives it from `v6_na_template` on load
+    #[serde(default, with = "auto_na", skip_serializing_if = "Option::is_none")]
+    pub ipv6_na: Option<Ipv6Addr>,
     // customer LAN prefix delegation /56
     pub ipv6_pd: Ipv6Net,
-    // customer router WAN mac address. Overrides option82 settings
-    #[serde(skip_serializing_if = "Option::is_none")]
+    // customer router WAN mac address. Overrides option82 settings. Any
+    // format `parse_mac` accepts, held canonical
+    #[serde(
+        default,
+        deserialize_with = "flexible_mac",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub mac: Option<MacAddr6>,
     // customer router duid. Overrides option82 settings, and mac setting for ipv6
     #[serde(skip_serializing_if = "Option::is_none")]
     pub duid: Option<Duid>,
+    // DHCPv4 client identifier (option 61). Overrides mac and option82 settings for ipv4
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<ClientId>,
     // option82 info used if mac is not specified
     #[serde(skip_serializing_if = "Option::is_none")]
     pub option82: Option<Option82>,
     // option1837 contains dhcpv6 option 18 and option 37, the v6 equivalent to option 82
     #[serde(skip_serializing_if = "Option::is_none")]
     pub option1837: Option<Option1837>,
+    // outer VLAN the relay reports in its Circuit-ID. With cvlan, the tag
+    // pair this customer is matched on after mac and before option82
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub svlan: Option<u16>,
+    // inner VLAN, only used together with svlan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cvlan: Option<u16>,
+    // stable customer identifier from billing/provisioning, copied into every
+    // event for this reservation so v4 and v6 activity can be joined on it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscriber_id: Option<CompactString>,
+    // extra DHCPv4 routes for this customer, on top of the config-level ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub static_routes: Vec<StaticRoute>,
+    // BOOTP header fields for this customer, overriding the config-level ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot: Option<BootParams>,
+    // name of the config `reservation_profiles` entry whose settings this
+    // customer shares
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<CompactString>,
+    // when service starts for this customer, such as the install date.
+    // Until then the reservation is loaded but never matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activate_at: Option<Timestamp>,
+    // when service ends for this customer, such as the churn date. From then
+    // on the reservation is loaded but never matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deactivate_at: Option<Timestamp>,
+    // walled off: the customer is refused with DHCPNAK and zero-lifetime
+    // NoBinding replies, while the reservation keeps its provisioning data
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub suspended: bool,
+}
+
+impl Reservation {
+    /// How logs and reports refer to this reservation: its IPv4 address, or
+    /// its delegated prefix when it has none
+    pub fn name(&self) -> String {
+        match self.ipv4 {
+            Some(ipv4) => ipv4.to_string(),
+            None => self.ipv6_pd.to_string(),
+        }
+    }
+
+    /// `ipv6_na` was given as `"auto"` and hasn't been derived yet
+    pub fn ipv6_na_is_auto(&self) -> bool {
+        self.ipv6_na.is_some_and(|na| na.is_unspecified())
+    }
+
+    /// The VLAN tags this reservation is keyed on, if it sets `svlan`
+    pub fn vlans(&self) -> Option<Vlans> {
+        Some(Vlans {
+            svlan: self.svlan?,
+            cvlan: self.cvlan,
+        })
+    }
+
+    /// Inside its activation window at `now`, in Unix milliseconds
+    pub fn is_active_at(&self, now: u64) -> bool {
+        self.activate_at.is_none_or(|at| at.unix_millis() <= now)
+            && self.deactivate_at.is_none_or(|at| now < at.unix_millis())
+    }
+}
+
+/// A point in time written as an RFC 3339 string, such as
+/// `2025-06-01T00:00:00Z` or `2025-06-01T09:00:00+02:00`. Held as Unix
+/// milliseconds and written back in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub fn from_unix_millis(millis: u64) -> Self {
+        Self(millis)
+    }
+
+    pub fn unix_millis(self) -> u64 {
+        self.0
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        const INVALID: &str = "expected an RFC 3339 timestamp such as 2025-06-01T00:00:00Z";
+        let bytes = value.as_bytes();
+        if bytes.len() < 20
+            || bytes[4] != b'-'
+            || bytes[7] != b'-'
+            || !matches!(bytes[10], b'T' | b't' | b' ')
+            || bytes[13] != b':'
+            || bytes[16] != b':'
+        {
+            return Err(INVALID);
+        }
+        let field = |start: usize, len: usize| {
+            value
+                .get(start..start + len)
+                .and_then(digits)
+                .ok_or(INVALID)
+        };
+        let (year, month, day) = (field(0, 4)?, field(5, 2)?, field(8, 2)?);
+        let (hour, minute, second) = (field(11, 2)?, field(14, 2)?, field(17, 2)?);
+        // 60 is a leap second, which lands on the next minute
+        if !(1..=12).contains(&month)
+            || day == 0
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(INVALID);
+        }
+
+        let mut rest = &value[19..];
+        let mut millis = 0;
+        if let Some(fraction) = rest.strip_prefix('.') {
+            let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+            if len == 0 {
+                return Err(INVALID);
+            }
+            millis = fraction[..len.min(3)]
+                .bytes()
+                .chain(std::iter::repeat(b'0'))
+                .take(3)
+                .fold(0, |acc, c| acc * 10 + u64::from(c - b'0'));
+            rest = &fraction[len..];
+        }
+        let offset = match rest.as_bytes() {
+            [b'Z' | b'z'] => 0,
+            [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+                let (hours, minutes) = (rest.get(1..3), rest.get(4..6));
+                let (hours, minutes) = (hours.and_then(digits), minutes.and_then(digits));
+                let (Some(hours @ 0..=23), Some(minutes @ 0..=59)) = (hours, minutes) else {
+                    return Err(INVALID);
+                };
+                let offset = i64::from(hours * 3600 + minutes * 60);
+                if *sign == b'-' {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+            _ => return Err(INVALID),
+        };
+
+        let seconds = days_from_civil(i64::from(year), month, day) * 86_400
+            + i64::from(hour * 3600 + minute * 60 + second)
+            - offset;
+        let seconds =
+            u64::try_from(seconds).map_err(|_| "timestamps before 1970 aren't supported")?;
+        Ok(Self(seconds * 1000 + millis))
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0 / 1000;
+        let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+        let time = seconds % 86_400;
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+            time / 3600,
+            time / 60 % 60,
+            time % 60
+        )?;
+        match self.0 % 1000 {
+            0 => f.write_str("Z"),
+            millis => write!(f, ".{millis:03}Z"),
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// All-digit `value` as a number
+fn digits(value: &str) -> Option<u32> {
+    if value.is_empty() || !value.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date, after
+/// <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * i64::from((month + 9) % 12) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Inverse of [`days_from_civil`]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Parse a MAC address as provisioning systems and relays write it: six
+/// colon or dash separated octets, Cisco dotted `xxxx.xxxx.xxxx`, or 12 bare
+/// hex digits (optionally `0x` prefixed), in either case.
+pub fn parse_mac(value: &str) -> Option<MacAddr6> {
+    let value = value.trim();
+    let groups: Vec<&str> = value.split([':', '-', '.']).collect();
+    let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    let hex = match lengths.as_slice() {
+        [2, 2, 2, 2, 2, 2] if !value.contains('.') => groups.concat(),
+        [4, 4, 4] if !value.contains([':', '-']) => groups.concat(),
+        [_] => value.strip_prefix("0x").unwrap_or(value).to_owned(),
+        _ => return None,
+    };
+    if hex.len() != 12 {
+        return None;
+    }
+    let bytes: [u8; 6] = parse_unseparated_hex(&hex)?.try_into().ok()?;
+    Some(MacAddr6::new(bytes))
+}
+
+fn flexible_mac<'de, D>(deserializer: D) -> Result<Option<MacAddr6>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let Some(value) = Option::<CompactString>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    parse_mac(&value)
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid MAC address `{value}`")))
+}
+
+mod auto_na {
+    use std::net::Ipv6Addr;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        addr: &Option<Ipv6Addr>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match addr {
+            Some(addr) if addr.is_unspecified() => serializer.serialize_str("auto"),
+            addr => addr.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Ipv6Addr>, D::Error> {
+        let Some(value) = Option::<String>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        if value == "auto" {
+            return Ok(Some(Ipv6Addr::UNSPECIFIED));
+        }
+        value.parse().map(Some).map_err(D::Error::custom)
+    }
+}
+
+/// An IPv4 route pushed to DHCPv4 clients in option 121 (RFC 3442), or
+/// option 33 for clients that don't ask for 121
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct StaticRoute {
+    pub destination: Ipv4Net,
+    pub gateway: Ipv4Addr,
+}
+
+/// BOOTP header fields used by CPEs that fetch their config over TFTP
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct BootParams {
+    /// Server host name (`sname`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sname: Option<CompactString>,
+    /// Next server to use in bootstrap (`siaddr`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_server: Option<Ipv4Addr>,
+    /// Boot file name (`file`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<CompactString>,
+    /// Leave `sname` empty even where another level sets it, for CPEs that
+    /// mishandle a server host name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub omit_sname: Option<bool>,
+}
+
+impl BootParams {
+    /// Fields set here, with the rest taken from `defaults`
+    pub fn or(&self, defaults: &BootParams) -> BootParams {
+        BootParams {
+            sname: self.sname.clone().or_else(|| defaults.sname.clone()),
+            next_server: self.next_server.or(defaults.next_server),
+            file: self.file.clone().or_else(|| defaults.file.clone()),
+            omit_sname: self.omit_sname.or(defaults.omit_sname),
+        }
+    }
+
+    /// The header has room for 64 and 128 bytes, including the terminating NUL
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.sname.as_ref().is_some_and(|s| s.len() > 63) {
+            return Err("sname must be at most 63 bytes");
+        }
+        if self.file.as_ref().is_some_and(|f| f.len() > 127) {
+            return Err("file must be at most 127 bytes");
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -36,13 +393,27 @@ pub struct V4Subnet {
     pub gateway: Ipv4Addr,
     /// Optional override for the subnet mask sent in DHCP replies
     pub reply_prefix_len: Option<u8>,
+    /// NAK clients without a reservation when relayed from this subnet
+    /// instead of staying silent
+    #[serde(default)]
+    pub authoritative: bool,
+    /// Further prefixes on the same link, behind the same relay, handed
+    /// out with this entry's gateway and settings
+    #[serde(default)]
+    pub ranges: Vec<Ipv4Net>,
 }
 
 impl V4Subnet {
     /// Returns the subnet mask to use in DHCP replies.
     /// Uses `reply_prefix_len` if set, otherwise uses the prefix from `net`.
     pub fn reply_netmask(&self) -> Ipv4Addr {
-        let prefix_len = self.reply_prefix_len.unwrap_or(self.net.prefix_len());
+        self.reply_netmask_for(self.net)
+    }
+
+    /// The subnet mask for an address in `prefix`, `net` or one of the
+    /// `ranges`
+    pub fn reply_netmask_for(&self, prefix: Ipv4Net) -> Ipv4Addr {
+        let prefix_len = self.reply_prefix_len.unwrap_or(prefix.prefix_len());
         // Convert prefix length to netmask by setting the high bits
         if prefix_len == 0 {
             Ipv4Addr::new(0, 0, 0, 0)
@@ -54,24 +425,88 @@ impl V4Subnet {
         }
     }
 
+    /// `net` followed by the `ranges`
+    pub fn prefixes(&self) -> impl Iterator<Item = Ipv4Net> + '_ {
+        std::iter::once(self.net).chain(self.ranges.iter().copied())
+    }
+
     pub fn validate(&self) -> Result<(), &'static str> {
+        if !self.net.contains(&self.gateway) {
+            return Err("gateway must be inside the subnet");
+        }
         if let Some(len) = self.reply_prefix_len {
             if len > 32 {
                 return Err("reply_prefix_len must be between 0 and 32");
             }
         }
+        let prefixes: Vec<_> = self.prefixes().map(|net| net.trunc()).collect();
+        for (i, a) in prefixes.iter().enumerate() {
+            if prefixes[i + 1..]
+                .iter()
+                .any(|b| a.contains(b) || b.contains(a))
+            {
+                return Err("net and ranges must not overlap each other");
+            }
+        }
         Ok(())
     }
 }
 
+/// A `circuit` or `remote` that parses as a MAC address is read in the
+/// dashed form the MAC extractors produce, so a reservation matches however
+/// its export wrote the MAC.
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct Option82 {
+    #[serde(default, deserialize_with = "mac_or_text")]
     pub circuit: Option<CompactString>,
+    #[serde(default, deserialize_with = "mac_or_text")]
     pub remote: Option<CompactString>,
     pub subscriber: Option<CompactString>,
 }
 
+impl Option82 {
+    /// `circuit` and `remote` rewritten as `00-11-22-33-44-AA` when they
+    /// parse as a MAC address, the form reservations are keyed by
+    pub fn with_canonical_macs(mut self) -> Self {
+        for field in [&mut self.circuit, &mut self.remote] {
+            if let Some(mac) = field.as_deref().and_then(parse_mac) {
+                *field = Some(mac.to_compact_string());
+            }
+        }
+        self
+    }
+}
+
+fn mac_or_text<'de, D>(deserializer: D) -> Result<Option<CompactString>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<CompactString>::deserialize(deserializer)?;
+    Ok(value.map(|text| match parse_mac(&text) {
+        Some(mac) => mac.to_compact_string(),
+        None => text,
+    }))
+}
+
+/// S-VLAN and, when double tagged, C-VLAN a client's traffic arrives on.
+/// Written `svlan.cvlan`, or `svlan` alone when single tagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Vlans {
+    pub svlan: u16,
+    pub cvlan: Option<u16>,
+}
+
+impl fmt::Display for Vlans {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.svlan)?;
+        if let Some(cvlan) = self.cvlan {
+            write!(f, ".{cvlan}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct Option1837 {
@@ -91,6 +526,32 @@ pub struct Duid {
     pub bytes: Vec<u8>,
 }
 
+/// DUID type, the first two bytes of a DUID (RFC 8415 §11.1, RFC 6355)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuidType {
+    /// 1: link-layer address plus time
+    Llt,
+    /// 2: enterprise number plus vendor identifier
+    En,
+    /// 3: link-layer address
+    Ll,
+    /// 4: UUID
+    Uuid,
+    Other(u16),
+}
+
+impl From<u16> for DuidType {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => DuidType::Llt,
+            2 => DuidType::En,
+            3 => DuidType::Ll,
+            4 => DuidType::Uuid,
+            other => DuidType::Other(other),
+        }
+    }
+}
+
 impl Duid {
     /// Create a new DUID with length validation.
     /// Returns None if the DUID exceeds MAX_DUID_LEN (130 bytes).
@@ -100,6 +561,60 @@ impl Duid {
         }
         Some(Duid { bytes })
     }
+
+    /// DUID-UUID (RFC 6355): type 4 followed by the 16 UUID bytes
+    pub fn from_uuid(uuid: [u8; 16]) -> Self {
+        let mut bytes = Vec::with_capacity(18);
+        bytes.extend_from_slice(&4u16.to_be_bytes());
+        bytes.extend_from_slice(&uuid);
+        Duid { bytes }
+    }
+
+    /// None for a DUID shorter than its type field
+    pub fn duid_type(&self) -> Option<DuidType> {
+        self.u16_at(0).map(DuidType::from)
+    }
+
+    /// IANA hardware type of a DUID-LLT or DUID-LL, 1 for Ethernet
+    pub fn hardware_type(&self) -> Option<u16> {
+        match self.duid_type()? {
+            DuidType::Llt | DuidType::Ll => self.u16_at(2),
+            _ => None,
+        }
+    }
+
+    /// Link-layer address of a DUID-LLT or DUID-LL, a MAC for Ethernet
+    pub fn link_layer_address(&self) -> Option<&[u8]> {
+        let start = match self.duid_type()? {
+            DuidType::Llt => 8,
+            DuidType::Ll => 4,
+            _ => return None,
+        };
+        self.bytes
+            .get(start..)
+            .filter(|address| !address.is_empty())
+    }
+
+    /// Enterprise number of a DUID-EN
+    pub fn enterprise_number(&self) -> Option<u32> {
+        match self.duid_type()? {
+            DuidType::En => Some(u32::from_be_bytes(self.bytes.get(2..6)?.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    /// The UUID of a DUID-UUID
+    pub fn uuid(&self) -> Option<[u8; 16]> {
+        match self.duid_type()? {
+            DuidType::Uuid if self.bytes.len() == 18 => self.bytes[2..].try_into().ok(),
+            _ => None,
+        }
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes = self.bytes.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
 }
 
 impl fmt::Display for Duid {
@@ -133,7 +648,9 @@ impl<'de> serde::Deserialize<'de> for Duid {
             type Value = Duid;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str(r#"colon or dash separated hex "00:11:22" or "00-11-22""#)
+                formatter.write_str(
+                    r#"hex "00:11:22", "00-11-22" or "001122", or a UUID for a DUID-UUID"#,
+                )
             }
 
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -194,27 +711,30 @@ impl Default for DuidParseError {
     }
 }
 
-// TODO: support UUID
-// TODO: support raw bytes, or other data
+/// Accepts colon or dash separated hex, unseparated hex (optionally `0x`
+/// prefixed), and for a DUID-UUID either a dashed UUID or `uuid:` followed by
+/// a UUID with or without dashes. Unseparated hex is always the DUID itself,
+/// so a UUID written without dashes needs the `uuid:` prefix.
 impl TryFrom<&str> for Duid {
     type Error = DuidParseError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let bytes = match value.trim().as_bytes().get(2) {
-            Some(&b':') => value
-                .trim()
-                .split(':')
-                .map(|hex| u8::from_str_radix(hex, 16))
-                .collect::<Result<Vec<u8>, _>>()
-                .map_err(|_| DuidParseError::default())?,
-            Some(&b'-') => value
-                .trim()
-                .split('-')
-                .map(|hex| u8::from_str_radix(hex, 16))
-                .collect::<Result<Vec<u8>, _>>()
-                .map_err(|_| DuidParseError::default())?,
-            _ => return Err(DuidParseError::default()),
-        };
+        let value = value.trim();
+        if let Some(uuid) = value
+            .get(..5)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("uuid:"))
+            .map(|_| &value[5..])
+        {
+            return parse_uuid(uuid).map(Duid::from_uuid).ok_or(DuidParseError {
+                message: "Invalid UUID after `uuid:`",
+            });
+        }
+        if let Some(uuid) = parse_dashed_uuid(value) {
+            return Ok(Duid::from_uuid(uuid));
+        }
+        let bytes = parse_hex(value)
+            .or_else(|| parse_unseparated_hex(value))
+            .ok_or_else(DuidParseError::default)?;
         if bytes.len() > MAX_DUID_LEN {
             return Err(DuidParseError {
                 message: "DUID exceeds maximum length of 130 bytes",
@@ -224,6 +744,120 @@ impl TryFrom<&str> for Duid {
     }
 }
 
+/// Colon or dash separated hex, as used for DUIDs and client identifiers
+fn parse_hex(value: &str) -> Option<Vec<u8>> {
+    let sep = match value.trim().as_bytes().get(2) {
+        Some(&b':') => ':',
+        Some(&b'-') => '-',
+        _ => return None,
+    };
+    value
+        .trim()
+        .split(sep)
+        .map(|hex| u8::from_str_radix(hex, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()
+}
+
+/// Hex digits without separators, e.g. `000300010011223344` or `0x0003...`
+fn parse_unseparated_hex(value: &str) -> Option<Vec<u8>> {
+    let hex = value.strip_prefix("0x").unwrap_or(value);
+    if hex.is_empty() || hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// The 8-4-4-4-12 textual form (RFC 9562)
+fn parse_dashed_uuid(value: &str) -> Option<[u8; 16]> {
+    let groups: Vec<&str> = value.split('-').collect();
+    let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    if lengths != [8, 4, 4, 4, 12] {
+        return None;
+    }
+    parse_unseparated_hex(&groups.concat())?.try_into().ok()
+}
+
+/// Dashed, or 32 hex digits
+fn parse_uuid(value: &str) -> Option<[u8; 16]> {
+    parse_dashed_uuid(value).or_else(|| {
+        if value.len() != 32 {
+            return None;
+        }
+        parse_unseparated_hex(value)?.try_into().ok()
+    })
+}
+
+/// DHCPv4 client identifier (option 61, RFC 2132 §9.14): a type byte
+/// followed by the identifier. Type 1 is an Ethernet MAC; RFC 4361 clients
+/// send 255, an IAID and their DHCPv6 DUID.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientId {
+    pub bytes: Vec<u8>,
+}
+
+impl ClientId {
+    /// Option 61 carries at least the type byte and one byte of identifier
+    pub fn new(bytes: Vec<u8>) -> Option<Self> {
+        (2..=255)
+            .contains(&bytes.len())
+            .then_some(ClientId { bytes })
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, b) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for ClientId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ClientId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct ClientIdVisitor;
+        impl<'de> Visitor<'de> for ClientIdVisitor {
+            type Value = ClientId;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str(
+                    r#"2 to 255 bytes of colon or dash separated hex "01:00:11:22:33:44:55""#,
+                )
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                parse_hex(v)
+                    .and_then(ClientId::new)
+                    .ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_str(ClientIdVisitor)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,12 +887,225 @@ mod tests {
         assert_eq!(parsed_json.duid, duid);
     }
 
+    #[test]
+    fn parse_mac_formats() {
+        let mac = MacAddr6::new([0x00, 0x11, 0x22, 0xaa, 0xbb, 0xcc]);
+        for text in [
+            "00-11-22-AA-BB-CC",
+            "00:11:22:aa:bb:cc",
+            "0011.22aa.bbcc",
+            "001122AABBCC",
+            "0x001122aabbcc",
+            " 00:11:22:AA:bb:CC ",
+        ] {
+            assert_eq!(parse_mac(text), Some(mac), "{text}");
+        }
+        for text in [
+            "",
+            "00:11:22:aa:bb",
+            "00:11:22:aa:bb:cc:dd",
+            "0011.22aa.bbc",
+            "00:11:22.aa:bb:cc",
+            "0011-22aa-bbcc",
+            "00112233445g",
+        ] {
+            assert_eq!(parse_mac(text), None, "{text}");
+        }
+
+        let json = r#"{"ipv4": "192.0.2.1", "ipv6_na": "2001:db8::1",
+            "ipv6_pd": "2001:db8:100::/56", "mac": "0011.22aa.bbcc"}"#;
+        let reservation: Reservation = serde_json::from_str(json).unwrap();
+        assert_eq!(reservation.mac, Some(mac));
+        assert!(serde_json::to_string(&reservation)
+            .unwrap()
+            .contains(r#""mac":"00-11-22-AA-BB-CC""#));
+
+        let json = r#"{"ipv4": "192.0.2.1", "ipv6_na": "2001:db8::1",
+            "ipv6_pd": "2001:db8:100::/56", "mac": "00:11:22"}"#;
+        assert!(serde_json::from_str::<Reservation>(json).is_err());
+
+        // Only the Option 82 fields that parse as a MAC are rewritten
+        let opt: Option82 =
+            serde_json::from_str(r#"{"circuit": "eth0/1", "remote": "0011.22aa.bbcc"}"#).unwrap();
+        assert_eq!(opt.circuit.as_deref(), Some("eth0/1"));
+        assert_eq!(opt.remote.as_deref(), Some("00-11-22-AA-BB-CC"));
+        assert_eq!(opt.subscriber, None);
+    }
+
+    #[test]
+    fn timestamps_parse_rfc3339() {
+        let utc: Timestamp = "2025-06-01T00:00:00Z".parse().unwrap();
+        assert_eq!(utc.unix_millis(), 1_748_736_000_000);
+        assert_eq!(utc.to_string(), "2025-06-01T00:00:00Z");
+        let offset: Timestamp = "2025-06-01T02:00:00+02:00".parse().unwrap();
+        assert_eq!(offset, utc);
+        let fraction: Timestamp = "2024-02-29t23:59:59.5-00:30".parse().unwrap();
+        assert_eq!(fraction.to_string(), "2024-03-01T00:29:59.500Z");
+        assert_eq!(
+            "1970-01-01T00:00:00Z".parse::<Timestamp>().unwrap(),
+            Timestamp::from_unix_millis(0)
+        );
+
+        for text in [
+            "",
+            "2025-06-01",
+            "2025-06-01T00:00:00",
+            "2025-06-01T00:00Z",
+            "2025-13-01T00:00:00Z",
+            "2025-02-29T00:00:00Z",
+            "2025-06-01T24:00:00Z",
+            "2025-06-01T00:00:00.Z",
+            "2025-06-01T00:00:00+2:00",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert!(text.parse::<Timestamp>().is_err(), "{text}");
+        }
+
+        let json = r#"{"ipv4": "192.0.2.1", "ipv6_na": "2001:db8::1",
+            "ipv6_pd": "2001:db8:100::/56", "mac": "00:11:22:aa:bb:cc",
+            "activate_at": "2025-06-01T00:00:00Z", "deactivate_at": "2025-07-01T00:00:00Z"}"#;
+        let reservation: Reservation = serde_json::from_str(json).unwrap();
+        assert!(!reservation.is_active_at(utc.unix_millis() - 1));
+        assert!(reservation.is_active_at(utc.unix_millis()));
+        assert!(!reservation.is_active_at(1_751_328_000_000));
+        assert!(serde_json::to_string(&reservation)
+            .unwrap()
+            .contains(r#""activate_at":"2025-06-01T00:00:00Z""#));
+    }
+
+    #[test]
+    fn parse_duid_formats() {
+        let ll = Duid::from(vec![
+            0x00, 0x03, 0x00, 0x01, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55,
+        ]);
+        assert_eq!(Duid::try_from("00030001001122334455").unwrap(), ll);
+        assert_eq!(Duid::try_from("0x00030001001122334455").unwrap(), ll);
+        assert!(Duid::try_from("0003000").is_err());
+        assert!(Duid::try_from("00030001zz").is_err());
+
+        let uuid = [
+            0x55, 0x0e, 0x84, 0x00, 0xe2, 0x9b, 0x41, 0xd4, 0xa7, 0x16, 0x44, 0x66, 0x55, 0x44,
+            0x00, 0x00,
+        ];
+        for text in [
+            "550e8400-e29b-41d4-a716-446655440000",
+            "uuid:550E8400-E29B-41D4-A716-446655440000",
+            "UUID:550e8400e29b41d4a716446655440000",
+        ] {
+            let duid = Duid::try_from(text).unwrap();
+            assert_eq!(duid.duid_type(), Some(DuidType::Uuid));
+            assert_eq!(duid.uuid(), Some(uuid));
+            assert_eq!(duid.bytes.len(), 18);
+        }
+        assert!(Duid::try_from("uuid:550e8400").is_err());
+        // Without the prefix, 32 hex digits are the DUID as is
+        assert_eq!(
+            Duid::try_from("550e8400e29b41d4a716446655440000")
+                .unwrap()
+                .bytes,
+            uuid
+        );
+    }
+
+    #[test]
+    fn duid_accessors() {
+        let llt = Duid::try_from("00:01:00:01:1c:39:cf:88:00:11:22:33:44:55").unwrap();
+        assert_eq!(llt.duid_type(), Some(DuidType::Llt));
+        assert_eq!(llt.hardware_type(), Some(1));
+        assert_eq!(
+            llt.link_layer_address(),
+            Some(&[0x00, 0x11, 0x22, 0x33, 0x44, 0x55][..])
+        );
+        assert_eq!(llt.enterprise_number(), None);
+
+        let en = Duid::try_from("00:02:00:00:0d:e9:01:02:03").unwrap();
+        assert_eq!(en.duid_type(), Some(DuidType::En));
+        assert_eq!(en.enterprise_number(), Some(3561));
+        assert_eq!(en.hardware_type(), None);
+        assert_eq!(en.link_layer_address(), None);
+
+        assert_eq!(
+            Duid::from(vec![0x00, 0x03, 0x00, 0x01]).link_layer_address(),
+            None
+        );
+        assert_eq!(Duid::from(vec![0x00]).duid_type(), None);
+        assert_eq!(
+            Duid::from(vec![0x00, 0x09]).duid_type(),
+            Some(DuidType::Other(9))
+        );
+    }
+
+    #[test]
+    fn parse_client_id() {
+        #[derive(Deserialize)]
+        struct ClientIdJson {
+            client_id: ClientId,
+        }
+        let json = r#"{"client_id": "01-00-11-22-33-44-55"}"#;
+        let parsed: ClientIdJson = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            parsed.client_id.bytes,
+            vec![0x01, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55]
+        );
+        assert_eq!(parsed.client_id.to_string(), "01:00:11:22:33:44:55");
+
+        assert!(serde_json::from_str::<ClientIdJson>(r#"{"client_id": "01"}"#).is_err());
+    }
+
+    #[test]
+    fn reservation_serializes_to_the_documented_format() {
+        let json = r#"{
+            "ipv4": "100.64.0.2",
+            "ipv6_na": "auto",
+            "ipv6_pd": "2001:db8:100::/56",
+            "duid": "00-03-00-01-00-11-22-33-44-55",
+            "client_id": "01-00-11-22-33-44-55",
+            "option82": {"circuit": "eth0/1", "remote": "olt1:1", "subscriber": null},
+            "option1837": {"interface": null, "remote": "olt1:1", "enterprise_number": 3561},
+            "subscriber_id": "cust-1",
+            "static_routes": [{"destination": "10.0.0.0/8", "gateway": "100.64.0.1"}],
+            "boot": {"file": "cpe.cfg"}
+        }"#;
+        let reservation: Reservation = serde_json::from_str(json).unwrap();
+        let value = serde_json::to_value(&reservation).unwrap();
+
+        // Identifiers come back colon separated, `auto` stays `auto`, and
+        // unset fields are left out so the output reads back as written
+        assert_eq!(value["ipv6_na"], "auto");
+        assert_eq!(value["duid"], "00:03:00:01:00:11:22:33:44:55");
+        assert_eq!(value["client_id"], "01:00:11:22:33:44:55");
+        assert!(value.get("mac").is_none());
+        assert_eq!(value["boot"], serde_json::json!({"file": "cpe.cfg"}));
+
+        let round_trip: Reservation = serde_json::from_value(value).unwrap();
+        assert_eq!(round_trip, reservation);
+
+        // Prefix delegation only: no address, and none written back
+        let json =
+            r#"{"ipv4": "100.64.0.3", "ipv6_pd": "2001:db8:300::/56", "mac": "00-11-22-33-44-56"}"#;
+        let reservation: Reservation = serde_json::from_str(json).unwrap();
+        assert_eq!(reservation.ipv6_na, None);
+        assert!(!reservation.ipv6_na_is_auto());
+        let value = serde_json::to_value(&reservation).unwrap();
+        assert!(value.get("ipv6_na").is_none());
+
+        // IPv6 only: no IPv4 address, and none written back
+        let json = r#"{"ipv6_na": "2001:db8::4", "ipv6_pd": "2001:db8:400::/56", "mac": "00-11-22-33-44-57"}"#;
+        let reservation: Reservation = serde_json::from_str(json).unwrap();
+        assert_eq!(reservation.ipv4, None);
+        assert_eq!(reservation.name(), "2001:db8:400::/56");
+        let value = serde_json::to_value(&reservation).unwrap();
+        assert!(value.get("ipv4").is_none());
+    }
+
     #[test]
     fn v4subnet_reply_netmask_uses_net_prefix_when_override_not_set() {
         let subnet = V4Subnet {
             net: "192.168.1.0/24".parse().unwrap(),
             gateway: Ipv4Addr::new(192, 168, 1, 1),
             reply_prefix_len: None,
+            authoritative: false,
+            ranges: vec![],
         };
         assert_eq!(subnet.reply_netmask(), Ipv4Addr::new(255, 255, 255, 0));
 
@@ -266,6 +1113,8 @@ mod tests {
             net: "10.0.0.0/16".parse().unwrap(),
             gateway: Ipv4Addr::new(10, 0, 0, 1),
             reply_prefix_len: None,
+            authoritative: false,
+            ranges: vec![],
         };
         assert_eq!(subnet_16.reply_netmask(), Ipv4Addr::new(255, 255, 0, 0));
     }
@@ -276,6 +1125,8 @@ mod tests {
             net: "192.168.1.0/24".parse().unwrap(),
             gateway: Ipv4Addr::new(192, 168, 1, 1),
             reply_prefix_len: Some(32),
+            authoritative: false,
+            ranges: vec![],
         };
         assert_eq!(subnet.reply_netmask(), Ipv4Addr::new(255, 255, 255, 255));
 
@@ -283,6 +1134,8 @@ mod tests {
             net: "192.168.1.0/24".parse().unwrap(),
             gateway: Ipv4Addr::new(192, 168, 1, 1),
             reply_prefix_len: Some(30),
+            authoritative: false,
+            ranges: vec![],
         };
         assert_eq!(subnet_30.reply_netmask(), Ipv4Addr::new(255, 255, 255, 252));
     }
@@ -294,6 +1147,8 @@ mod tests {
                 net: "192.168.1.0/24".parse().unwrap(),
                 gateway: Ipv4Addr::new(192, 168, 1, 1),
                 reply_prefix_len: Some(prefix),
+                authoritative: false,
+                ranges: vec![],
             };
             assert!(
                 subnet.validate().is_ok(),
@@ -303,6 +1158,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn v4subnet_validate_rejects_gateway_outside_subnet() {
+        let subnet = V4Subnet {
+            net: "192.168.1.0/24".parse().unwrap(),
+            gateway: Ipv4Addr::new(192, 168, 2, 1),
+            reply_prefix_len: None,
+            authoritative: false,
+            ranges: vec![],
+        };
+        assert!(subnet.validate().is_err());
+    }
+
+    #[test]
+    fn v4subnet_validate_rejects_overlapping_ranges() {
+        let subnet = |ranges: &[&str]| V4Subnet {
+            net: "100.64.0.0/24".parse().unwrap(),
+            gateway: Ipv4Addr::new(100, 64, 0, 1),
+            reply_prefix_len: None,
+            authoritative: false,
+            ranges: ranges.iter().map(|r| r.parse().unwrap()).collect(),
+        };
+        assert!(subnet(&["100.65.0.0/24", "100.66.0.0/23"])
+            .validate()
+            .is_ok());
+        assert!(subnet(&["100.64.0.128/25"]).validate().is_err());
+        assert!(subnet(&["100.65.0.0/16", "100.65.7.0/24"])
+            .validate()
+            .is_err());
+
+        let shared = subnet(&["100.65.0.0/26"]);
+        assert_eq!(
+            shared.reply_netmask_for("100.65.0.0/26".parse().unwrap()),
+            Ipv4Addr::new(255, 255, 255, 192)
+        );
+    }
+
     #[test]
     fn v4subnet_validate_rejects_invalid_prefix_lengths() {
         for prefix in [33, 64, 128, 255] {
@@ -310,6 +1201,8 @@ mod tests {
                 net: "192.168.1.0/24".parse().unwrap(),
                 gateway: Ipv4Addr::new(192, 168, 1, 1),
                 reply_prefix_len: Some(prefix),
+                authoritative: false,
+                ranges: vec![],
             };
             assert!(
                 subnet.validate().is_err(),
@@ -7,6 +7,14 @@ pub trait ShadowMessageExtV4 {
     fn server_id(&self) -> Option<&Ipv4Addr>;
     fn requested_ip_addr(&self) -> Option<&Ipv4Addr>;
     fn relay_agent_information(&self) -> Option<&v4::relay::RelayAgentInformation>;
+    fn requests_option(&self, code: v4::OptionCode) -> bool;
+    fn hostname(&self) -> Option<&str>;
+    fn vendor_class(&self) -> Option<&[u8]>;
+    fn client_identifier(&self) -> Option<&[u8]>;
+    fn max_message_size(&self) -> Option<u16>;
+    fn prl_fingerprint(&self) -> Option<String>;
+    fn link_address(&self) -> Ipv4Addr;
+    fn reply_server_id(&self, own: Ipv4Addr) -> Ipv4Addr;
 }
 
 impl ShadowMessageExtV4 for v4::Message {
@@ -37,12 +45,88 @@ impl ShadowMessageExtV4 for v4::Message {
             _ => None,
         })
     }
+
+    /// Whether `code` is in the client's Parameter Request List (option 55)
+    fn requests_option(&self, code: v4::OptionCode) -> bool {
+        self.opts().iter().any(|o| match o.1 {
+            DhcpOption::ParameterRequestList(list) => list.contains(&code),
+            _ => false,
+        })
+    }
+
+    /// Client hostname (option 12)
+    fn hostname(&self) -> Option<&str> {
+        self.opts().iter().find_map(|o| match o.1 {
+            DhcpOption::Hostname(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Vendor class identifier (option 60)
+    fn vendor_class(&self) -> Option<&[u8]> {
+        self.opts().iter().find_map(|o| match o.1 {
+            DhcpOption::ClassIdentifier(class) => Some(class.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Client identifier (option 61), type byte included
+    fn client_identifier(&self) -> Option<&[u8]> {
+        self.opts().iter().find_map(|o| match o.1 {
+            DhcpOption::ClientIdentifier(id) => Some(id.as_slice()),
+            _ => None,
+        })
+    }
+
+    /// Maximum DHCP Message Size (option 57)
+    fn max_message_size(&self) -> Option<u16> {
+        self.opts().iter().find_map(|o| match o.1 {
+            DhcpOption::MaxMessageSize(size) => Some(*size),
+            _ => None,
+        })
+    }
+
+    /// Parameter Request List as comma separated option codes in the order
+    /// the client sent them, e.g. `1,3,6,15`. The order is stable per
+    /// client OS/firmware, so it identifies CPE models the way fingerbank
+    /// style fingerprints do.
+    fn prl_fingerprint(&self) -> Option<String> {
+        self.opts().iter().find_map(|o| match o.1 {
+            DhcpOption::ParameterRequestList(list) => Some(
+                list.iter()
+                    .map(|code| u8::from(*code).to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            _ => None,
+        })
+    }
+
+    /// The link the client is on: the relay's Link Selection sub-option
+    /// (RFC 3527) when it sends one, because its giaddr is then only where
+    /// to send the reply, otherwise giaddr
+    fn link_address(&self) -> Ipv4Addr {
+        self.relay_agent_information()
+            .and_then(|relay| relay.link_selection())
+            .unwrap_or(self.giaddr())
+    }
+
+    /// The Server Identifier to put in replies: the relay's Server
+    /// Identifier Override sub-option (RFC 5107) when it sends one, so
+    /// renewals go through the relay, otherwise `own`
+    fn reply_server_id(&self, own: Ipv4Addr) -> Ipv4Addr {
+        self.relay_agent_information()
+            .and_then(|relay| relay.server_id_override())
+            .unwrap_or(own)
+    }
 }
 
 pub trait RelayAgentInformationExt {
     fn circuit_id(&self) -> Option<Vec<u8>>;
     fn remote_id(&self) -> Option<Vec<u8>>;
     fn subscriber_id(&self) -> Option<Vec<u8>>;
+    fn link_selection(&self) -> Option<Ipv4Addr>;
+    fn server_id_override(&self) -> Option<Ipv4Addr>;
 }
 
 impl RelayAgentInformationExt for RelayAgentInformation {
@@ -73,4 +157,26 @@ impl RelayAgentInformationExt for RelayAgentInformation {
                 _ => None,
             })
     }
+
+    fn link_selection(&self) -> Option<Ipv4Addr> {
+        self.get(dhcproto::v4::relay::RelayCode::LinkSelection)
+            .and_then(|ri| match ri {
+                dhcproto::v4::relay::RelayInfo::LinkSelection(addr) if !addr.is_unspecified() => {
+                    Some(*addr)
+                }
+                _ => None,
+            })
+    }
+
+    fn server_id_override(&self) -> Option<Ipv4Addr> {
+        self.get(dhcproto::v4::relay::RelayCode::ServerIdentifierOverride)
+            .and_then(|ri| match ri {
+                dhcproto::v4::relay::RelayInfo::ServerIdentifierOverride(addr)
+                    if !addr.is_unspecified() =>
+                {
+                    Some(*addr)
+                }
+                _ => None,
+            })
+    }
 }
@@ -1,10 +1,10 @@
-use std::{collections::HashMap, str::FromStr};
+use std::collections::HashMap;
+use std::fmt;
 
-use advmac::MacAddr6;
-use compact_str::ToCompactString;
+use compact_str::{format_compact, CompactString, ToCompactString};
 use tracing::debug;
 
-use crate::types::Option82;
+use crate::types::{parse_mac, Option82, Vlans};
 
 pub type Option82ExtractorFn = fn(opt: &Option82) -> Option<Option82>;
 
@@ -63,11 +63,10 @@ pub fn subscriber_only(opt: &Option82) -> Option<Option82> {
 /// Read the first 12 characters of Remote-ID and parse as a MacAddr6.
 /// Then format the MacAddr6 with dash format for lookup in reservations.
 pub fn remote_first_12(opt: &Option82) -> Option<Option82> {
-    let mac = opt.remote.as_ref().and_then(|remote| {
-        remote
-            .get(0..12)
-            .and_then(|substring| MacAddr6::from_str(substring).ok())
-    });
+    let mac = opt
+        .remote
+        .as_ref()
+        .and_then(|remote| remote.get(0..12).and_then(parse_mac));
 
     match mac {
         Some(mac) => {
@@ -82,12 +81,10 @@ pub fn remote_first_12(opt: &Option82) -> Option<Option82> {
     }
 }
 
-/// Parse the entire Remote-ID as a MAC address and re-encode to a MAC address formatted with dashes.
+/// Parse the entire Remote-ID as a MAC address in any format `parse_mac`
+/// accepts and re-encode to a MAC address formatted with dashes.
 pub fn normalize_remote_mac(opt: &Option82) -> Option<Option82> {
-    let mac = opt
-        .remote
-        .as_ref()
-        .and_then(|remote| MacAddr6::from_str(remote).ok());
+    let mac = opt.remote.as_deref().and_then(parse_mac);
 
     match mac {
         Some(mac) => {
@@ -105,6 +102,136 @@ pub fn normalize_remote_mac(opt: &Option82) -> Option<Option82> {
     }
 }
 
+/// A Circuit-ID broken into the access port and the VLAN tags it was
+/// received on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitId {
+    /// Slash-separated numbers, such as `0/0/1` or `1/1/4/3`
+    pub port: CompactString,
+    pub vlans: Option<Vlans>,
+}
+
+impl CircuitId {
+    /// Parse the structured Circuit-IDs BNGs and OLTs commonly send, all of
+    /// the form `[node] [type] <port>[:<svlan>[.<cvlan>]]`:
+    ///
+    /// * `eth 0/0/1:100.200` (Cisco, Nokia ISAM `ISAM1 eth 1/1/4/3:100`)
+    /// * `1/1/1:100.200` (Nokia SR SAP notation)
+    /// * `ge-1/0/0.32767:100-200` (Juniper, unit dropped, tags joined by `-`)
+    /// * `OLT1 xpon 0/1/0/5:100.200` (Huawei)
+    ///
+    /// The interface type prefix (`eth`, `ge-`, `xpon`...) is dropped so the
+    /// same port matches however the relay names it. `None` when the last
+    /// word has no numeric port or a tag isn't a VLAN ID.
+    pub fn parse(circuit: &str) -> Option<Self> {
+        let word = circuit
+            .trim_end_matches('\0')
+            .split_whitespace()
+            .next_back()?;
+        let (port, tags) = match word.split_once(':') {
+            Some((port, tags)) => (port, Some(tags)),
+            None => (word, None),
+        };
+        let port = port.trim_start_matches(|c: char| c.is_ascii_alphabetic() || c == '-');
+        // Juniper logical unit
+        let port = port.split_once('.').map_or(port, |(port, _unit)| port);
+        if !port.contains('/')
+            || !port
+                .split('/')
+                .all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return None;
+        }
+        let vlans = match tags {
+            None => None,
+            Some(tags) => {
+                let mut tags = tags.split(['.', '-']).map(vlan_id);
+                let svlan = tags.next()??;
+                let cvlan = match tags.next() {
+                    Some(cvlan) => Some(cvlan?),
+                    None => None,
+                };
+                if tags.next().is_some() {
+                    return None;
+                }
+                Some(Vlans { svlan, cvlan })
+            }
+        };
+        Some(Self {
+            port: port.into(),
+            vlans,
+        })
+    }
+
+    /// Also accept the binary Circuit-ID of Cisco's default `vlan-mod-port`
+    /// format: type 0, length 4, the VLAN as two bytes, then
+    /// module and port. Anything else is parsed as text with
+    /// [`Self::parse`].
+    pub fn from_bytes(circuit: &[u8]) -> Option<Self> {
+        match *circuit {
+            [0, 4, vlan_hi, vlan_lo, module, port] => {
+                let svlan = vlan_id_from(u16::from_be_bytes([vlan_hi, vlan_lo]))?;
+                Some(Self {
+                    port: format_compact!("{module}/{port}"),
+                    vlans: Some(Vlans { svlan, cvlan: None }),
+                })
+            }
+            _ => Self::parse(std::str::from_utf8(circuit).ok()?),
+        }
+    }
+}
+
+impl fmt::Display for CircuitId {
+    /// `port:svlan.cvlan`, the form reservations match against
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.port)?;
+        if let Some(vlans) = self.vlans {
+            write!(f, ":{vlans}")?;
+        }
+        Ok(())
+    }
+}
+
+fn vlan_id(tag: &str) -> Option<u16> {
+    vlan_id_from(tag.parse().ok()?)
+}
+
+fn vlan_id_from(id: u16) -> Option<u16> {
+    (id <= 4095).then_some(id)
+}
+
+fn circuit_key(circuit: CompactString) -> Option82 {
+    Option82 {
+        circuit: Some(circuit),
+        remote: None,
+        subscriber: None,
+    }
+}
+
+/// Parse the Circuit-ID with [`CircuitId::parse`] and keep only the port,
+/// e.g. `0/0/1`
+pub fn circuit_port(opt: &Option82) -> Option<Option82> {
+    let parsed = CircuitId::parse(opt.circuit.as_deref()?)?;
+    Some(circuit_key(parsed.port))
+}
+
+/// Parse the Circuit-ID and keep only the VLAN tags, e.g. `100.200`, for
+/// networks where the tag pair alone identifies the subscriber
+pub fn circuit_vlans(opt: &Option82) -> Option<Option82> {
+    let parsed = CircuitId::parse(opt.circuit.as_deref()?)?;
+    Some(circuit_key(parsed.vlans?.to_compact_string()))
+}
+
+/// Parse the Circuit-ID and re-encode the port and VLAN tags as
+/// `port:svlan.cvlan`, e.g. `0/0/1:100.200`
+pub fn circuit_port_vlans(opt: &Option82) -> Option<Option82> {
+    let parsed = CircuitId::parse(opt.circuit.as_deref()?)?;
+    if parsed.vlans.is_none() {
+        return None;
+    }
+    Some(circuit_key(parsed.to_compact_string()))
+}
+
 pub fn get_all_extractors() -> HashMap<&'static str, Option82ExtractorFn> {
     let mut extractors = HashMap::new();
     extractors.insert("remote_only", remote_only as Option82ExtractorFn);
@@ -120,6 +247,12 @@ pub fn get_all_extractors() -> HashMap<&'static str, Option82ExtractorFn> {
         "normalize_remote_mac",
         normalize_remote_mac as Option82ExtractorFn,
     );
+    extractors.insert("circuit_port", circuit_port as Option82ExtractorFn);
+    extractors.insert("circuit_vlans", circuit_vlans as Option82ExtractorFn);
+    extractors.insert(
+        "circuit_port_vlans",
+        circuit_port_vlans as Option82ExtractorFn,
+    );
 
     extractors
 }
@@ -266,5 +399,81 @@ mod tests {
                 subscriber: None
             })
         );
+
+        let cisco = Option82 {
+            circuit: None,
+            remote: Some("0011.2233.44aa".to_compact_string()),
+            subscriber: None,
+        };
+        assert_eq!(
+            normalize_remote_mac(&cisco).and_then(|opt| opt.remote),
+            Some("00-11-22-33-44-AA".to_compact_string())
+        );
+    }
+
+    #[test]
+    fn circuit_ids_parsed() {
+        let parsed = |s: &str| CircuitId::parse(s).map(|c| c.to_string());
+        assert_eq!(
+            parsed("eth 0/0/1:100.200").as_deref(),
+            Some("0/0/1:100.200")
+        );
+        assert_eq!(
+            parsed("ISAM1 eth 1/1/4/3:100").as_deref(),
+            Some("1/1/4/3:100")
+        );
+        assert_eq!(parsed("1/1/1:100.200").as_deref(), Some("1/1/1:100.200"));
+        assert_eq!(
+            parsed("ge-1/0/0.32767:100-200").as_deref(),
+            Some("1/0/0:100.200")
+        );
+        assert_eq!(
+            parsed("OLT1 xpon 0/1/0/5:100.200\0").as_deref(),
+            Some("0/1/0/5:100.200")
+        );
+        assert_eq!(parsed("xe-0/0/0").as_deref(), Some("0/0/0"));
+        assert_eq!(parsed("eth0"), None, "no slot/port");
+        assert_eq!(parsed("eth 0/0/1:5000"), None, "not a VLAN ID");
+        assert_eq!(parsed("eth 0/0/1:1.2.3"), None);
+        assert_eq!(parsed("b4fbe4501fda/1/ac8ba9e217f8"), None);
+
+        let binary = CircuitId::from_bytes(&[0, 4, 0, 200, 1, 7]).unwrap();
+        assert_eq!(binary.to_string(), "1/7:200");
+        assert_eq!(
+            CircuitId::from_bytes(b"eth 0/0/1:100.200").and_then(|c| c.vlans),
+            Some(Vlans {
+                svlan: 100,
+                cvlan: Some(200)
+            })
+        );
+    }
+
+    #[test]
+    fn test_circuit_component_extractors() {
+        let wire_opt = Option82 {
+            circuit: Some("eth 0/0/1:100.200".to_compact_string()),
+            remote: Some("001122334455".to_compact_string()),
+            subscriber: None,
+        };
+        let circuit = |opt: Option<Option82>| opt.and_then(|opt| opt.circuit);
+        assert_eq!(circuit(circuit_port(&wire_opt)).as_deref(), Some("0/0/1"));
+        assert_eq!(
+            circuit(circuit_vlans(&wire_opt)).as_deref(),
+            Some("100.200")
+        );
+        assert_eq!(
+            circuit(circuit_port_vlans(&wire_opt)).as_deref(),
+            Some("0/0/1:100.200")
+        );
+        assert_eq!(circuit_port_vlans(&wire_opt).unwrap().remote, None);
+
+        let untagged = Option82 {
+            circuit: Some("eth 0/0/1".to_compact_string()),
+            remote: None,
+            subscriber: None,
+        };
+        assert!(circuit_port(&untagged).is_some());
+        assert_eq!(circuit_vlans(&untagged), None);
+        assert_eq!(circuit_port_vlans(&untagged), None);
     }
 }
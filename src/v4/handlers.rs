@@ -1,15 +1,22 @@
 use advmac::MacAddr6;
 use dhcproto::v4::{self, DhcpOption, Flags};
-use std::{net::Ipv4Addr, sync::Arc};
+use ipnet::Ipv4Net;
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
+};
 use tracing::{debug, warn};
 
-use crate::types::Reservation;
+use crate::types::{ClientId, Reservation, StaticRoute};
 
 use crate::analytics::events::ReservationMatch;
-use crate::config::Config;
+use crate::config::{Config, Profile};
 use crate::opt82_cache::Opt82Cache;
 use crate::reservationdb::ReservationDb;
 
+pub use crate::outcome::NoResponse;
+
 use crate::v4::{extensions::ShadowMessageExtV4, reservation::find_reservation};
 
 /// A DHCPv4 response message produced by the server.
@@ -20,31 +27,8 @@ pub struct ResponseMessage {
     pub message: v4::Message,
     pub reservation: Option<Arc<Reservation>>,
     pub reservation_match: Option<ReservationMatch>,
-}
-
-#[derive(Debug, Copy, Clone)]
-pub enum NoResponse {
-    NoReservation,
-    NoValidMac,
-    NoServerSubnet,
-    Discarded,
-    WrongServerId,
-    NoMessageType,
-    NotRelayed,
-}
-
-impl NoResponse {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            NoResponse::NoReservation => "NoReservation",
-            NoResponse::NoValidMac => "NoValidMac",
-            NoResponse::NoServerSubnet => "NoServerSubnet",
-            NoResponse::Discarded => "Discarded",
-            NoResponse::WrongServerId => "WrongServerId",
-            NoResponse::NoMessageType => "NoMessageType",
-            NoResponse::NotRelayed => "NotRelayed",
-        }
-    }
+    /// How long the address is leased for, on a DHCPACK that grants one
+    pub lease: Option<Duration>,
 }
 
 /// Result of processing an incoming DHCPv6 message.
@@ -56,12 +40,50 @@ pub enum DhcpV4Response {
     NoResponse(NoResponse),
 }
 
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+/// Where to send `reply`, per RFC 2131 §4.1:
+/// * DHCPACK to a DHCPINFORM with ciaddr set: unicast to ciaddr:68, even when
+///   relayed, since the client already has that address (§4.3.5)
+/// * relayed (giaddr set): the relay's server port, giaddr:67
+/// * DHCPNAK not relayed: broadcast
+/// * ciaddr set, as in a RENEWING client's unicast DHCPREQUEST: unicast to
+///   ciaddr:68
+/// * broadcast flag set: broadcast
+/// * otherwise the RFC asks for a unicast to yiaddr at chaddr, which needs an
+///   ARP entry a plain UDP socket can't install, so broadcast instead
+///
+/// Only the relayed cases are reachable while the server is relay-only and
+/// doesn't answer DHCPINFORM; the rest keep the addressing correct once those
+/// handlers exist.
+///
+/// <https://datatracker.ietf.org/doc/html/rfc2131#section-4.1>
+pub fn reply_destination(request: &v4::Message, reply: &v4::Message) -> SocketAddrV4 {
+    let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, CLIENT_PORT);
+    if request.message_type() == Some(&v4::MessageType::Inform)
+        && request.ciaddr() != Ipv4Addr::UNSPECIFIED
+    {
+        SocketAddrV4::new(request.ciaddr(), CLIENT_PORT)
+    } else if request.giaddr() != Ipv4Addr::UNSPECIFIED {
+        SocketAddrV4::new(request.giaddr(), SERVER_PORT)
+    } else if reply.message_type() == Some(&v4::MessageType::Nak) {
+        broadcast
+    } else if request.ciaddr() != Ipv4Addr::UNSPECIFIED {
+        SocketAddrV4::new(request.ciaddr(), CLIENT_PORT)
+    } else {
+        broadcast
+    }
+}
+
 /// 4.3 A DHCP server can receive the following messages from a client:
 /// * DHCPDISCOVER
 /// * DHCPREQUEST
 /// * DHCPDECLINE
 /// * DHCPRELEASE
 /// * DHCPINFORM
+///
+/// and, with `bootp_enabled`, BOOTREQUESTs without a message type
 pub fn handle_message(
     reservations: &ReservationDb,
     leases: &Opt82Cache,
@@ -79,19 +101,24 @@ pub fn handle_message(
 
     // servers should only respond to BootRequest messages
     let message_type = match msg.opcode() {
-        v4::Opcode::BootRequest => match msg.message_type() {
-            Some(mt) => mt,
-            None => return DhcpV4Response::NoResponse(NoResponse::NoMessageType),
-        },
+        v4::Opcode::BootRequest => msg.message_type(),
         // Servers don't receive BootReply
         v4::Opcode::BootReply => return DhcpV4Response::NoResponse(NoResponse::Discarded),
         // Skip handling Unknown
         v4::Opcode::Unknown(_) => return DhcpV4Response::NoResponse(NoResponse::Discarded),
     };
 
+    let profile = config
+        .profile_v4(msg.link_address())
+        .with_device_class(config, crate::fingerprint::v4(msg).as_deref());
+    let message_type = match message_type {
+        Some(mt) => mt,
+        None if config.bootp_enabled => return handle_bootp(reservations, config, &profile, msg),
+        None => return DhcpV4Response::NoResponse(NoResponse::NoMessageType),
+    };
     match message_type {
-        v4::MessageType::Discover => handle_discover(reservations, config, msg),
-        v4::MessageType::Request => handle_request(reservations, leases, config, msg),
+        v4::MessageType::Discover => handle_discover(reservations, config, &profile, msg),
+        v4::MessageType::Request => handle_request(reservations, leases, config, &profile, msg),
         v4::MessageType::Decline => DhcpV4Response::NoResponse(NoResponse::Discarded),
         v4::MessageType::Release => DhcpV4Response::NoResponse(NoResponse::Discarded),
         // If a client has obtained a network address through some other means (e.g., manual configuration), it
@@ -112,6 +139,7 @@ pub fn handle_message(
 fn handle_discover(
     reservations: &ReservationDb,
     config: &Config,
+    profile: &Profile,
     msg: &v4::Message,
 ) -> DhcpV4Response {
     let mac_addr = match MacAddr6::try_from(msg.chaddr()).ok() {
@@ -119,34 +147,47 @@ fn handle_discover(
         None => return DhcpV4Response::NoResponse(NoResponse::NoValidMac),
     };
 
+    let client_id = msg
+        .client_identifier()
+        .and_then(|id| ClientId::new(id.to_vec()));
     let (reservation, match_info) = match find_reservation(
         reservations,
         &config.option82_extractors,
+        client_id.as_ref(),
         mac_addr,
         msg.relay_agent_information(),
     ) {
-        Some((res, match_info)) => (res, match_info),
-        None => return DhcpV4Response::NoResponse(NoResponse::NoReservation),
+        // A v6-only reservation is no reservation as far as DHCPv4 goes
+        Some((res, match_info)) if res.ipv4.is_some() => (res, match_info),
+        // Nothing to offer, so stay silent even on an authoritative subnet:
+        // a DHCPNAK only ever answers a DHCPREQUEST
+        _ if profile.answer_unknown_clients => {
+            return DhcpV4Response::NoResponse(NoResponse::NoReservation)
+        }
+        _ => return DhcpV4Response::NoResponse(NoResponse::NotAuthoritative),
     };
-
-    let (gateway, subnet_mask) = match config
-        .subnets_v4
-        .iter()
-        .find(|subnet| subnet.net.contains(&reservation.ipv4))
-        .map(|subnet| (subnet.gateway, subnet.reply_netmask()))
-    {
-        Some((gw, subnet)) => (gw, subnet),
-        None => {
-            warn!(mac = %mac_addr, "Couldn't find configured subnet for {}", &reservation.ipv4);
-            return DhcpV4Response::NoResponse(NoResponse::NoServerSubnet);
+    let assignment = match assignment(config, *profile, mac_addr, &reservation) {
+        Some(assignment) => assignment,
+        // RFC 2131 only NAKs a REQUEST, so there is no answer to give
+        None if reservation.suspended => {
+            debug!(reservation = %reservation.name(), "reservation suspended, not offering");
+            return DhcpV4Response::NoResponse(NoResponse::Suspended);
         }
+        None => return DhcpV4Response::NoResponse(NoResponse::SubnetMismatch),
     };
+    let Assignment {
+        address,
+        gateway,
+        subnet_mask,
+        ref profile,
+        walled,
+    } = assignment;
 
     let unspecified = Ipv4Addr::UNSPECIFIED;
     let mut reply = v4::Message::new_with_id(
         msg.xid(),
         unspecified,
-        reservation.ipv4,
+        address,
         unspecified,
         msg.giaddr(),
         msg.chaddr(),
@@ -154,22 +195,98 @@ fn handle_discover(
     reply.set_opcode(v4::Opcode::BootReply);
     reply.set_secs(0);
     reply.set_flags(msg.flags());
+    if !walled {
+        set_boot_params(&mut reply, msg, config, profile, &reservation);
+    }
 
     let opts = reply.opts_mut();
 
     opts.insert(DhcpOption::MessageType(v4::MessageType::Offer));
-    opts.insert(DhcpOption::ServerIdentifier(config.v4_server_id));
+    opts.insert(DhcpOption::ServerIdentifier(
+        msg.reply_server_id(config.v4_server_id),
+    ));
     opts.insert(DhcpOption::SubnetMask(subnet_mask));
     opts.insert(DhcpOption::Router(vec![gateway]));
-    opts.insert(DhcpOption::DomainNameServer(config.dns_v4.clone()));
-    opts.insert(DhcpOption::AddressLeaseTime(config.lease_times.v4_lease));
-    opts.insert(DhcpOption::Renewal(config.lease_times.v4_renewal));
-    opts.insert(DhcpOption::Rebinding(config.lease_times.v4_rebinding));
+    opts.insert(DhcpOption::DomainNameServer(profile.dns_v4.to_vec()));
+    insert_host_config(opts, profile);
+    opts.insert(DhcpOption::AddressLeaseTime(profile.lease_times.v4_lease));
+    opts.insert(DhcpOption::Renewal(profile.lease_times.v4_renewal));
+    opts.insert(DhcpOption::Rebinding(profile.lease_times.v4_rebinding));
+    if !walled {
+        insert_static_routes(opts, msg, profile, &reservation, gateway);
+    }
 
     DhcpV4Response::Message(ResponseMessage {
         message: reply,
         reservation: Some(reservation),
         reservation_match: Some(match_info),
+        lease: None,
+    })
+}
+
+/// BOOTREQUEST without a DHCP Message Type, from a BOOTP client (RFC 951)
+/// such as the metering devices still in the field. Only clients with a MAC
+/// reservation are answered, with a BOOTREPLY carrying the reserved address
+/// and the options a DHCPOFFER would have, minus those that only mean
+/// something to DHCP: no message type, server identifier or lease times,
+/// since a BOOTP client keeps its address for good. For the same reason a
+/// suspended reservation gets no answer rather than a walled garden address.
+///
+/// <https://datatracker.ietf.org/doc/html/rfc1534>
+fn handle_bootp(
+    reservations: &ReservationDb,
+    config: &Config,
+    profile: &Profile,
+    msg: &v4::Message,
+) -> DhcpV4Response {
+    let Ok(mac_addr) = MacAddr6::try_from(msg.chaddr()) else {
+        return DhcpV4Response::NoResponse(NoResponse::NoValidMac);
+    };
+    let reservation = match reservations.by_mac(mac_addr) {
+        Some(res) if res.ipv4.is_some() => res,
+        _ => return DhcpV4Response::NoResponse(NoResponse::NoReservation),
+    };
+    if reservation.suspended {
+        debug!(reservation = %reservation.name(), "reservation suspended, ignoring BOOTREQUEST");
+        return DhcpV4Response::NoResponse(NoResponse::Suspended);
+    }
+    let Some(Assignment {
+        address,
+        gateway,
+        subnet_mask,
+        ref profile,
+        ..
+    }) = assignment(config, *profile, mac_addr, &reservation)
+    else {
+        return DhcpV4Response::NoResponse(NoResponse::SubnetMismatch);
+    };
+
+    let unspecified = Ipv4Addr::UNSPECIFIED;
+    let mut reply = v4::Message::new_with_id(
+        msg.xid(),
+        unspecified,
+        address,
+        unspecified,
+        msg.giaddr(),
+        msg.chaddr(),
+    );
+    reply.set_opcode(v4::Opcode::BootReply);
+    reply.set_secs(0);
+    reply.set_flags(msg.flags());
+    set_boot_params(&mut reply, msg, config, profile, &reservation);
+
+    let opts = reply.opts_mut();
+    opts.insert(DhcpOption::SubnetMask(subnet_mask));
+    opts.insert(DhcpOption::Router(vec![gateway]));
+    opts.insert(DhcpOption::DomainNameServer(profile.dns_v4.to_vec()));
+    insert_host_config(opts, profile);
+    insert_static_routes(opts, msg, profile, &reservation, gateway);
+
+    DhcpV4Response::Message(ResponseMessage {
+        message: reply,
+        reservation: Some(reservation),
+        reservation_match: Some(ReservationMatch::mac("chaddr").with_key(mac_addr)),
+        lease: None,
     })
 }
 
@@ -182,6 +299,7 @@ fn handle_request(
     reservations: &ReservationDb,
     leases: &Opt82Cache,
     config: &Config,
+    profile: &Profile,
     msg: &v4::Message,
 ) -> DhcpV4Response {
     // Four variants of DHCPREQUEST
@@ -208,49 +326,16 @@ fn handle_request(
         None => return DhcpV4Response::NoResponse(NoResponse::NoValidMac),
     };
 
-    let (reservation, match_info) = match find_reservation(
-        reservations,
-        &config.option82_extractors,
-        mac_addr,
-        msg.relay_agent_information(),
-    ) {
-        Some((res, match_info)) => (res, match_info),
-        None => return DhcpV4Response::NoResponse(NoResponse::NoReservation),
-    };
-
-    let (gateway, subnet_mask) = match config
-        .subnets_v4
-        .iter()
-        .find(|subnet| subnet.net.contains(&reservation.ipv4))
-        .map(|subnet| (subnet.gateway, subnet.reply_netmask()))
-    {
-        Some((gw, subnet)) => (gw, subnet),
-        None => {
-            warn!(mac = %mac_addr, "Couldn't find configured subnet for {}", &reservation.ipv4);
-            return DhcpV4Response::NoResponse(NoResponse::NoServerSubnet);
-        }
-    };
-
-    let unspecified = Ipv4Addr::UNSPECIFIED;
-    let mut reply = v4::Message::new_with_id(
-        msg.xid(),
-        unspecified,
-        reservation.ipv4,
-        unspecified,
-        msg.giaddr(),
-        msg.chaddr(),
-    );
-    reply.set_opcode(v4::Opcode::BootReply);
-    reply.set_secs(0);
-    // TODO: check flags are correct
-    reply.set_flags(msg.flags());
-
-    // select one of the four variants:
+    // select one of the four variants. This comes before the reservation
+    // lookup, so a REQUEST selecting another server's offer is left to that
+    // server even when we would NAK the client:
     let variant_tuple = (msg.server_id(), &msg.ciaddr(), msg.requested_ip_addr());
     let client_requested_ip = match variant_tuple {
         (Some(server_id), &Ipv4Addr::UNSPECIFIED, Some(requested_ip)) => {
             debug!("variant: selecting");
-            if server_id != &config.v4_server_id {
+            // A client behind a relay that overrides the server id selects
+            // the override address
+            if server_id != &msg.reply_server_id(config.v4_server_id) {
                 debug!(%server_id, "SELECTING server id did not match");
                 return DhcpV4Response::NoResponse(NoResponse::WrongServerId);
             }
@@ -274,7 +359,49 @@ fn handle_request(
         }
     };
 
-    if client_requested_ip == &reservation.ipv4 {
+    let client_id = msg
+        .client_identifier()
+        .and_then(|id| ClientId::new(id.to_vec()));
+    let (reservation, match_info) = match find_reservation(
+        reservations,
+        &config.option82_extractors,
+        client_id.as_ref(),
+        mac_addr,
+        msg.relay_agent_information(),
+    ) {
+        // A v6-only reservation is no reservation as far as DHCPv4 goes
+        Some((res, match_info)) if res.ipv4.is_some() => (res, match_info),
+        _ => return no_reservation(config, profile, msg),
+    };
+    let assignment = match assignment(config, *profile, mac_addr, &reservation) {
+        Some(assignment) => assignment,
+        None if reservation.suspended => return suspended(config, msg, reservation, match_info),
+        None => return DhcpV4Response::NoResponse(NoResponse::SubnetMismatch),
+    };
+    let Assignment {
+        address,
+        gateway,
+        subnet_mask,
+        ref profile,
+        walled,
+    } = assignment;
+
+    let unspecified = Ipv4Addr::UNSPECIFIED;
+    let mut reply = v4::Message::new_with_id(
+        msg.xid(),
+        unspecified,
+        address,
+        unspecified,
+        msg.giaddr(),
+        msg.chaddr(),
+    );
+    reply.set_opcode(v4::Opcode::BootReply);
+    reply.set_secs(0);
+    // TODO: check flags are correct
+    reply.set_flags(msg.flags());
+
+    let mut lease = None;
+    if client_requested_ip == &address {
         // the server selected in the DHCPREQUEST message commits the binding, and responds with a DHCPACK message
         // containing the configuration parameters for the requesting client. The combination of 'client identifier'
         // or 'chaddr' and assigned network address constitute a unique identifier for the client's lease.
@@ -282,20 +409,28 @@ fn handle_request(
         // server should respond with a DHCPNAK message.
         let opts = reply.opts_mut();
         opts.insert(DhcpOption::MessageType(v4::MessageType::Ack));
-        opts.insert(DhcpOption::ServerIdentifier(config.v4_server_id));
+        opts.insert(DhcpOption::ServerIdentifier(
+            msg.reply_server_id(config.v4_server_id),
+        ));
         opts.insert(DhcpOption::SubnetMask(subnet_mask));
         opts.insert(DhcpOption::Router(vec![gateway]));
-        opts.insert(DhcpOption::DomainNameServer(config.dns_v4.clone()));
-        opts.insert(DhcpOption::AddressLeaseTime(config.lease_times.v4_lease));
-        opts.insert(DhcpOption::Renewal(config.lease_times.v4_renewal));
-        opts.insert(DhcpOption::Rebinding(config.lease_times.v4_rebinding));
+        opts.insert(DhcpOption::DomainNameServer(profile.dns_v4.to_vec()));
+        insert_host_config(opts, profile);
+        opts.insert(DhcpOption::AddressLeaseTime(profile.lease_times.v4_lease));
+        opts.insert(DhcpOption::Renewal(profile.lease_times.v4_renewal));
+        opts.insert(DhcpOption::Rebinding(profile.lease_times.v4_rebinding));
         // TODO: add support for parameter request list option
+        if !walled {
+            insert_static_routes(opts, msg, profile, &reservation, gateway);
+            set_boot_params(&mut reply, msg, config, profile, &reservation);
+        }
 
         if let Some(opt) = &reservation.option82 {
             leases.insert_mac_option82_binding(&mac_addr, opt);
         }
+        lease = Some(Duration::from_secs(profile.lease_times.v4_lease.into()));
     } else {
-        warn!(mac = %mac_addr, reservation_ipv4 = %reservation.ipv4, %client_requested_ip,
+        warn!(mac = %mac_addr, reservation_ipv4 = %address, %client_requested_ip,
             "client requested ip doesn't match reserved address, sending DHCPNAK",
         );
         // RFC 2131 Table 3: yiaddr in DHCPNAK MUST be 0
@@ -307,12 +442,252 @@ fn handle_request(
         }
         let opts = reply.opts_mut();
         opts.insert(DhcpOption::MessageType(v4::MessageType::Nak));
-        opts.insert(DhcpOption::ServerIdentifier(config.v4_server_id));
+        opts.insert(DhcpOption::ServerIdentifier(
+            msg.reply_server_id(config.v4_server_id),
+        ));
     }
 
     DhcpV4Response::Message(ResponseMessage {
         message: reply,
         reservation: Some(reservation),
         reservation_match: Some(match_info),
+        lease,
+    })
+}
+
+/// What a client with a reservation is given
+struct Assignment<'a> {
+    address: Ipv4Addr,
+    gateway: Ipv4Addr,
+    subnet_mask: Ipv4Addr,
+    profile: Profile<'a>,
+    /// Placed in the walled garden, so the reservation's routes and boot
+    /// parameters are left out
+    walled: bool,
+}
+
+/// The reserved address in its configured subnet, or for a suspended
+/// reservation the walled-garden address when one is configured. `None`
+/// when there is nothing to hand out, including for a v6-only reservation.
+fn assignment<'a>(
+    config: &'a Config,
+    profile: Profile<'a>,
+    mac_addr: MacAddr6,
+    reservation: &Reservation,
+) -> Option<Assignment<'a>> {
+    let ipv4 = reservation.ipv4?;
+    if reservation.suspended {
+        let garden = config.walled_garden.as_ref()?;
+        let Some(address) = garden.address_for(ipv4) else {
+            warn!(mac = %mac_addr, reservation_ipv4 = %ipv4,
+                "no walled garden address for reservation");
+            return None;
+        };
+        return Some(Assignment {
+            address,
+            gateway: garden.gateway,
+            subnet_mask: garden.net.netmask(),
+            profile: garden.profile(profile),
+            walled: true,
+        });
+    }
+    let Some((subnet, prefix)) = config.subnets_v4.find(ipv4) else {
+        warn!(mac = %mac_addr, "Couldn't find configured subnet for {ipv4}");
+        return None;
+    };
+    Some(Assignment {
+        address: ipv4,
+        gateway: subnet.gateway,
+        subnet_mask: subnet.reply_netmask_for(prefix),
+        profile: profile.with_reservation(config, reservation),
+        walled: false,
+    })
+}
+
+/// Answer for a DHCPREQUEST from a client without a reservation. Silent by
+/// default, so another DHCP server on the segment can still serve it. When
+/// the relay's giaddr is in a subnet marked `authoritative`, or its
+/// subscriber network says so, we own that network outright and NAK instead,
+/// which sends a client holding a stale lease back to INIT rather than
+/// letting it retry until it times out.
+fn no_reservation(config: &Config, profile: &Profile, msg: &v4::Message) -> DhcpV4Response {
+    if !profile.answer_unknown_clients {
+        return DhcpV4Response::NoResponse(NoResponse::NotAuthoritative);
+    }
+    DhcpV4Response::Message(ResponseMessage {
+        message: nak(config, msg),
+        reservation: None,
+        reservation_match: None,
+        lease: None,
+    })
+}
+
+/// Answer for a REQUEST from a client whose reservation is suspended and
+/// that can't be put in a walled garden: a DHCPNAK, so it never holds its
+/// address while walled off. Its DISCOVERs get no OFFER to begin with. The
+/// reservation is kept on the response so events show who it was.
+fn suspended(
+    config: &Config,
+    msg: &v4::Message,
+    reservation: Arc<Reservation>,
+    match_info: ReservationMatch,
+) -> DhcpV4Response {
+    debug!(reservation = %reservation.name(), "reservation suspended, sending DHCPNAK");
+    DhcpV4Response::Message(ResponseMessage {
+        message: nak(config, msg),
+        reservation: Some(reservation),
+        reservation_match: Some(match_info),
+        lease: None,
     })
 }
+
+fn nak(config: &Config, msg: &v4::Message) -> v4::Message {
+    let unspecified = Ipv4Addr::UNSPECIFIED;
+    // RFC 2131 Table 3: ciaddr and yiaddr in DHCPNAK MUST be 0
+    let mut reply = v4::Message::new_with_id(
+        msg.xid(),
+        unspecified,
+        unspecified,
+        unspecified,
+        msg.giaddr(),
+        msg.chaddr(),
+    );
+    reply.set_opcode(v4::Opcode::BootReply);
+    reply.set_secs(0);
+    // relayed NAK should set broadcast bit
+    reply.set_flags(Flags::set_broadcast(msg.flags()));
+    let opts = reply.opts_mut();
+    opts.insert(DhcpOption::MessageType(v4::MessageType::Nak));
+    opts.insert(DhcpOption::ServerIdentifier(
+        msg.reply_server_id(config.v4_server_id),
+    ));
+    reply
+}
+
+/// Fill the BOOTP `sname`, `siaddr` and `file` header fields, preferring the
+/// reservation's values over its profile's and those over the config-level
+/// ones. Reservations aren't validated on load, so values that don't fit the
+/// header are left out. `sname` stays empty when `omit_sname` is set or the
+/// client's vendor class is in `omit_sname_vendor_classes`.
+fn set_boot_params(
+    reply: &mut v4::Message,
+    msg: &v4::Message,
+    config: &Config,
+    profile: &Profile,
+    reservation: &Reservation,
+) {
+    let defaults = match profile.boot {
+        Some(shared) => shared.or(&config.boot),
+        None => config.boot.clone(),
+    };
+    let boot = match &reservation.boot {
+        Some(own) => own.or(&defaults),
+        None => defaults,
+    };
+    if let Err(reason) = boot.validate() {
+        warn!(reservation = %reservation.name(), "not sending boot parameters: {reason}");
+        return;
+    }
+    let omit_sname = boot.omit_sname.unwrap_or(false)
+        || msg.vendor_class().is_some_and(|class| {
+            config
+                .omit_sname_vendor_classes
+                .iter()
+                .any(|prefix| class.starts_with(prefix.as_bytes()))
+        });
+    if let Some(sname) = boot.sname.as_ref().filter(|_| !omit_sname) {
+        reply.set_sname(sname.as_bytes());
+    }
+    if let Some(next_server) = boot.next_server {
+        reply.set_siaddr(next_server);
+    }
+    if let Some(file) = &boot.file {
+        reply.set_fname(file.as_bytes());
+    }
+}
+
+/// NTP servers (option 42), interface MTU (option 26) and domain name
+/// (option 15), each only when configured
+fn insert_host_config(opts: &mut v4::DhcpOptions, profile: &Profile) {
+    if !profile.ntp_v4.is_empty() {
+        opts.insert(DhcpOption::NtpServers(profile.ntp_v4.to_vec()));
+    }
+    if let Some(mtu) = profile.interface_mtu {
+        opts.insert(DhcpOption::InterfaceMtu(mtu));
+    }
+    if let Some(domain) = profile.domain_name {
+        let domain = domain.strip_suffix('.').unwrap_or(domain);
+        opts.insert(DhcpOption::DomainName(domain.to_owned()));
+    }
+}
+
+/// Push the config and reservation static routes. Clients that list option
+/// 121 in their Parameter Request List get classless routes (RFC 3442);
+/// others get the subset option 33 can express. A reservation route to the
+/// same destination as a config route replaces it.
+fn insert_static_routes(
+    opts: &mut v4::DhcpOptions,
+    msg: &v4::Message,
+    profile: &Profile,
+    reservation: &Reservation,
+    gateway: Ipv4Addr,
+) {
+    let own: Vec<StaticRoute> = reservation
+        .static_routes
+        .iter()
+        .map(|r| StaticRoute {
+            destination: r.destination.trunc(),
+            gateway: r.gateway,
+        })
+        .collect();
+    let routes: Vec<StaticRoute> = profile
+        .static_routes
+        .iter()
+        .filter(|c| !own.iter().any(|r| r.destination == c.destination))
+        .chain(own.iter())
+        .copied()
+        .collect();
+    if routes.is_empty() {
+        return;
+    }
+
+    if msg.requests_option(v4::OptionCode::ClasslessStaticRoute) {
+        // A client that uses option 121 ignores the Router option, so the
+        // default route has to be carried here as well.
+        let mut classless = Vec::with_capacity(routes.len() + 1);
+        if !routes.iter().any(|r| r.destination.prefix_len() == 0) {
+            let default = Ipv4Net::new(Ipv4Addr::UNSPECIFIED, 0).expect("valid prefix length");
+            classless.push((default, gateway));
+        }
+        classless.extend(routes.iter().map(|r| (r.destination, r.gateway)));
+        opts.insert(DhcpOption::ClasslessStaticRoute(classless));
+    } else {
+        let classful: Vec<(Ipv4Addr, Ipv4Addr)> = routes
+            .iter()
+            .filter(|r| is_classful(r.destination))
+            .map(|r| (r.destination.network(), r.gateway))
+            .collect();
+        if classful.len() < routes.len() {
+            debug!(
+                xid = msg.xid(),
+                skipped = routes.len() - classful.len(),
+                "client did not request option 121, sending classful routes only"
+            );
+        }
+        if !classful.is_empty() {
+            opts.insert(DhcpOption::StaticRoutingTable(classful));
+        }
+    }
+}
+
+/// Option 33 carries no mask: the client derives it from the destination's
+/// address class, and the default route is not allowed (RFC 2132 §5.8).
+fn is_classful(destination: Ipv4Net) -> bool {
+    let class_len = match destination.network().octets()[0] {
+        1..=127 => 8,
+        128..=191 => 16,
+        192..=223 => 24,
+        _ => return false,
+    };
+    destination.prefix_len() == class_len
+}
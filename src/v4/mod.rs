@@ -2,6 +2,7 @@ pub mod extensions;
 pub mod extractors;
 pub mod handlers;
 mod reservation;
+pub(crate) mod size;
 pub mod worker;
 
 pub use worker::v4_worker;
@@ -1,19 +1,22 @@
 use std::sync::Arc;
 
-use crate::types::{Option82, Reservation};
+use crate::types::{ClientId, Option82, Reservation};
 use crate::v4::extensions::RelayAgentInformationExt;
 use advmac::MacAddr6;
 use compact_str::CompactString;
 use dhcproto::v4::relay::RelayAgentInformation;
 use tracing::debug;
 
-use super::extractors::NamedOption82Extractor;
+use super::extractors::{CircuitId, NamedOption82Extractor};
 use crate::analytics::events::ReservationMatch;
 use crate::reservationdb::ReservationDb;
 
 /// Attempt to find a reservation using Option 82 relay agent information.
 ///
-/// Tries each configured extractor in order until one finds a matching reservation.
+/// Tries each configured extractor in order until one finds a matching reservation,
+/// then falls back to the Option 82 pools. Extractor matches are remembered
+/// by the database, so the next packet with the same relay information
+/// skips the extractors.
 /// Returns the reservation along with match metadata (which extractor was used).
 pub fn find_reservation_by_relay_info(
     reservations: &ReservationDb,
@@ -38,37 +41,73 @@ pub fn find_reservation_by_relay_info(
 
     debug!("{option:?}");
 
-    extractors.iter().find_map(|(name, extractor)| {
+    if let Some(cached) = reservations.cached_relay_match(&option) {
+        return Some(cached);
+    }
+
+    let found = extractors.iter().find_map(|(name, extractor)| {
         extractor(&option).and_then(|extracted_opt| {
-            reservations
-                .by_opt82(&extracted_opt)
-                .map(|res| (res, ReservationMatch::option82(name)))
+            reservations.by_opt82(&extracted_opt).map(|res| {
+                let found = ReservationMatch::option82(name).with_relay_key(&extracted_opt);
+                (res, found)
+            })
         })
-    })
+    });
+    if let Some((res, found)) = found {
+        reservations.cache_relay_match(option, res.clone(), found.clone());
+        return Some((res, found));
+    }
+
+    reservations
+        .by_pool(&option)
+        .map(|(res, name)| (res, ReservationMatch::pool(name).with_relay_key(&option)))
 }
 
 /// Attempt to find a reservation using different lookup priorities:
 ///
-/// 1. By MAC address (from chaddr)
-/// 2. By Option 82 (relay agent information) using extractors
+/// 1. By client identifier (option 61)
+/// 2. By MAC address (from chaddr)
+/// 3. By the S-VLAN/C-VLAN pair in the Option 82 Circuit-ID
+/// 4. By Option 82 (relay agent information) using extractors
 ///
 /// Returns the reservation along with match metadata (method and extractor used).
 pub fn find_reservation(
     reservations: &ReservationDb,
     extractors: &[NamedOption82Extractor],
+    client_id: Option<&ClientId>,
     mac_addr: MacAddr6,
     relay: Option<&RelayAgentInformation>,
 ) -> Option<(Arc<Reservation>, ReservationMatch)> {
-    // Priority 1: MAC address (from chaddr field)
+    // Priority 1: client identifier, for CPEs whose client-id isn't chaddr
+    if let Some(id) = client_id {
+        if let Some(res) = reservations.by_client_id(id) {
+            let found = ReservationMatch::client_id().with_key(id);
+            return Some((res, found.with_priority(1)));
+        }
+    }
+
+    // Priority 2: MAC address (from chaddr field)
     if let Some(res) = reservations.by_mac(mac_addr) {
-        return Some((res, ReservationMatch::mac("chaddr")));
+        let found = ReservationMatch::mac("chaddr").with_key(mac_addr);
+        return Some((res, found.with_priority(2)));
+    }
+
+    // Priority 3: VLAN tags the relay reports in the Circuit-ID
+    if let Some(vlans) = relay
+        .and_then(|relay_info| relay_info.circuit_id())
+        .and_then(|circuit| CircuitId::from_bytes(&circuit)?.vlans)
+    {
+        if let Some(res) = reservations.by_vlans(vlans) {
+            let found = ReservationMatch::vlans().with_key(vlans);
+            return Some((res, found.with_priority(3)));
+        }
     }
 
-    // Priority 2: Option 82 with extractors
-    if let Some(result) = relay
+    // Priority 4: Option 82 with extractors
+    if let Some((res, found)) = relay
         .and_then(|relay_info| find_reservation_by_relay_info(reservations, extractors, relay_info))
     {
-        return Some(result);
+        return Some((res, found.with_priority(4)));
     }
 
     None
@@ -0,0 +1,308 @@
+//! Keeping replies within the size the client accepts.
+//!
+//! A client announces the largest message it takes in Maximum DHCP Message
+//! Size (option 57), and without it only has to take 576 bytes (RFC 2131
+//! §2). Large Option 121 route lists can take a reply past that. The reply
+//! is then fitted in two steps:
+//!
+//! 1. Option overload (RFC 2132 §9.3): options move into the `file` and then
+//!    `sname` header fields, largest first, when boot parameters haven't
+//!    claimed them.
+//! 2. Trimming: options the client can do without are dropped in
+//!    [`TRIM_ORDER`] until it fits, repeating step 1 each time.
+//!
+//! Message Type, Server Identifier, Subnet Mask, Router and the lease time
+//! are never dropped; a reply still too large with only those is sent as is.
+//!
+//! BOOTP replies go the other way: they are padded to the 300 bytes of a
+//! BOOTP message with its 64 byte vendor area (RFC 951), which some BOOTP
+//! clients and relays insist on.
+
+use dhcproto::error::EncodeError;
+use dhcproto::v4::{self, DhcpOption, OptionCode};
+use dhcproto::Encodable;
+use tracing::{debug, warn};
+
+use crate::v4::extensions::ShadowMessageExtV4;
+
+/// Every client accepts a 576 byte IP datagram (RFC 2131 §2)
+const MIN_MAX_MESSAGE_SIZE: u16 = 576;
+/// Option 57 counts the IP and UDP headers, the encoded message doesn't
+const IP_UDP_HEADERS: usize = 20 + 8;
+const FILE_LEN: usize = 128;
+const SNAME_LEN: usize = 64;
+const END: u8 = 255;
+/// Smallest BOOTP message (RFC 1542 §2.1)
+const BOOTP_MIN_LEN: usize = 300;
+
+/// Dropped first to last. NTP servers and the domain name are conveniences,
+/// clients derive T1 and T2 from the lease time, and option 33 is the
+/// fallback for clients that don't ask for option 121.
+pub const TRIM_ORDER: [OptionCode; 7] = [
+    OptionCode::NtpServers,
+    OptionCode::DomainName,
+    OptionCode::Rebinding,
+    OptionCode::Renewal,
+    OptionCode::StaticRoutingTable,
+    OptionCode::ClasslessStaticRoute,
+    OptionCode::DomainNameServer,
+];
+
+/// Stay in the options field when overloading
+const NOT_OVERLOADED: [OptionCode; 2] = [OptionCode::MessageType, OptionCode::ServerIdentifier];
+
+/// Largest encoded reply `request`'s sender accepts
+pub fn max_reply_len(request: &v4::Message) -> usize {
+    let size = request
+        .max_message_size()
+        .unwrap_or(MIN_MAX_MESSAGE_SIZE)
+        .max(MIN_MAX_MESSAGE_SIZE);
+    usize::from(size) - IP_UDP_HEADERS
+}
+
+/// Encode `reply` into `buf` within [`max_reply_len`] of `request`,
+/// overloading or trimming `reply` if needed, so what was sent can be read
+/// back from it
+pub fn encode_within_limit(
+    request: &v4::Message,
+    reply: &mut v4::Message,
+    buf: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let limit = max_reply_len(request);
+    crate::encode_into(reply, buf)?;
+    if buf.len() <= limit {
+        if reply.message_type().is_none() && buf.len() < BOOTP_MIN_LEN {
+            // Pad options after End
+            buf.resize(BOOTP_MIN_LEN, 0);
+        }
+        return Ok(());
+    }
+
+    let file_free = is_unused(reply.fname());
+    let sname_free = is_unused(reply.sname());
+    let mut opts = reply.opts().clone();
+    let mut dropped = Vec::new();
+    loop {
+        if layout(reply, &opts, file_free, sname_free, limit, buf)? {
+            if !dropped.is_empty() {
+                warn!(
+                    xid = request.xid(),
+                    limit,
+                    ?dropped,
+                    "reply too large for the client, dropped options"
+                );
+            }
+            return Ok(());
+        }
+        let Some(code) = TRIM_ORDER
+            .into_iter()
+            .find(|code| opts.get(*code).is_some())
+        else {
+            warn!(
+                xid = request.xid(),
+                limit,
+                len = buf.len(),
+                "reply too large for the client even without optional options, sending anyway"
+            );
+            return Ok(());
+        };
+        opts.remove(code);
+        dropped.push(code);
+    }
+}
+
+/// Put `opts` in `reply`, overloading into the free header fields if the
+/// options field alone doesn't fit, and encode it. Whether it fit.
+fn layout(
+    reply: &mut v4::Message,
+    opts: &v4::DhcpOptions,
+    file_free: bool,
+    sname_free: bool,
+    limit: usize,
+    buf: &mut Vec<u8>,
+) -> Result<bool, EncodeError> {
+    *reply.opts_mut() = opts.clone();
+    if file_free {
+        reply.set_fname(&[]);
+    }
+    if sname_free {
+        reply.set_sname(&[]);
+    }
+    crate::encode_into(reply, buf)?;
+    if buf.len() <= limit || !(file_free || sname_free) {
+        return Ok(buf.len() <= limit);
+    }
+
+    let mut movable = Vec::new();
+    for (code, opt) in opts.iter() {
+        if !NOT_OVERLOADED.contains(code) {
+            movable.push((*code, opt.to_vec()?));
+        }
+    }
+    movable.sort_by_key(|(_, encoded)| std::cmp::Reverse(encoded.len()));
+
+    // The Option Overload option itself takes three bytes
+    let mut excess = buf.len() + 3 - limit;
+    let (mut file, mut sname) = (Vec::new(), Vec::new());
+    for (code, encoded) in movable {
+        if excess == 0 {
+            break;
+        }
+        // Each field keeps a byte for its End option
+        let field = if file_free && file.len() + encoded.len() < FILE_LEN {
+            &mut file
+        } else if sname_free && sname.len() + encoded.len() < SNAME_LEN {
+            &mut sname
+        } else {
+            continue;
+        };
+        excess = excess.saturating_sub(encoded.len());
+        field.extend_from_slice(&encoded);
+        reply.opts_mut().remove(code);
+    }
+    let overload = match (file.is_empty(), sname.is_empty()) {
+        (true, true) => return Ok(false),
+        (false, true) => 1,
+        (true, false) => 2,
+        (false, false) => 3,
+    };
+    for field in [&mut file, &mut sname] {
+        if !field.is_empty() {
+            field.push(END);
+        }
+    }
+    reply.set_fname(&file);
+    reply.set_sname(&sname);
+    reply
+        .opts_mut()
+        .insert(DhcpOption::OptionOverload(overload));
+    debug!(xid = reply.xid(), overload, "overloading reply options");
+
+    crate::encode_into(reply, buf)?;
+    Ok(buf.len() <= limit)
+}
+
+fn is_unused(field: Option<&[u8]>) -> bool {
+    field.is_none_or(|bytes| bytes.iter().all(|b| *b == 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dhcproto::Decodable;
+    use ipnet::Ipv4Net;
+    use std::net::Ipv4Addr;
+
+    fn request(max_message_size: Option<u16>) -> v4::Message {
+        let mut msg = v4::Message::default();
+        if let Some(size) = max_message_size {
+            msg.opts_mut().insert(DhcpOption::MaxMessageSize(size));
+        }
+        msg
+    }
+
+    /// An ACK carrying `routes` /32 routes in option 121 and `dns` servers
+    fn ack(routes: u8, dns: u8) -> v4::Message {
+        let mut reply = v4::Message::default();
+        reply.set_opcode(v4::Opcode::BootReply);
+        let gateway = Ipv4Addr::new(100, 64, 0, 1);
+        let opts = reply.opts_mut();
+        opts.insert(DhcpOption::MessageType(v4::MessageType::Ack));
+        opts.insert(DhcpOption::ServerIdentifier(Ipv4Addr::new(10, 0, 0, 1)));
+        opts.insert(DhcpOption::SubnetMask(Ipv4Addr::new(255, 255, 255, 0)));
+        opts.insert(DhcpOption::Router(vec![gateway]));
+        opts.insert(DhcpOption::DomainNameServer(
+            (0..dns).map(|i| Ipv4Addr::new(198, 51, 100, i)).collect(),
+        ));
+        opts.insert(DhcpOption::AddressLeaseTime(3600));
+        opts.insert(DhcpOption::Renewal(1800));
+        opts.insert(DhcpOption::Rebinding(3150));
+        opts.insert(DhcpOption::ClasslessStaticRoute(
+            (0..routes)
+                .map(|i| {
+                    let dest = Ipv4Net::new(Ipv4Addr::new(192, 0, 2, i), 32).unwrap();
+                    (dest, gateway)
+                })
+                .collect(),
+        ));
+        reply
+    }
+
+    #[test]
+    fn max_reply_len_never_below_rfc_minimum() {
+        assert_eq!(max_reply_len(&request(None)), 548);
+        assert_eq!(max_reply_len(&request(Some(300))), 548);
+        assert_eq!(max_reply_len(&request(Some(1500))), 1472);
+    }
+
+    #[test]
+    fn small_reply_is_untouched() {
+        let (request, mut reply) = (request(None), ack(4, 2));
+        let original = reply.clone();
+        let mut buf = Vec::new();
+        encode_within_limit(&request, &mut reply, &mut buf).unwrap();
+        assert_eq!(reply, original);
+        assert_eq!(buf, original.to_vec().unwrap());
+    }
+
+    #[test]
+    fn large_reply_overloads_file() {
+        // 254 bytes of routes and 122 of DNS servers overflow 548 bytes;
+        // the DNS servers fit in `file`
+        let (request, mut reply) = (request(None), ack(28, 30));
+        let mut buf = Vec::new();
+        encode_within_limit(&request, &mut reply, &mut buf).unwrap();
+        assert!(buf.len() <= 548, "{}", buf.len());
+        assert!(matches!(
+            reply.opts().get(OptionCode::OptionOverload),
+            Some(DhcpOption::OptionOverload(1))
+        ));
+        let file = reply.fname().unwrap();
+        assert_eq!(file[..2], [u8::from(OptionCode::DomainNameServer), 120]);
+        assert_eq!(file[122], END);
+        assert!(reply.opts().get(OptionCode::DomainNameServer).is_none());
+        // Nothing was trimmed
+        assert!(reply.opts().get(OptionCode::ClasslessStaticRoute).is_some());
+        assert!(reply.opts().get(OptionCode::Rebinding).is_some());
+    }
+
+    #[test]
+    fn reply_trimmed_when_header_fields_are_taken() {
+        let (request, mut reply) = (request(None), ack(28, 30));
+        reply.set_fname(b"pxelinux.0");
+        reply.set_sname(b"boot.example.net");
+        let mut buf = Vec::new();
+        encode_within_limit(&request, &mut reply, &mut buf).unwrap();
+        assert!(buf.len() <= 548, "{}", buf.len());
+        assert_eq!(reply.fname(), Some(&b"pxelinux.0"[..]));
+        assert!(reply.opts().get(OptionCode::Rebinding).is_none());
+        assert!(reply.opts().get(OptionCode::Renewal).is_none());
+        assert!(reply.opts().get(OptionCode::ClasslessStaticRoute).is_none());
+        assert!(reply.opts().get(OptionCode::DomainNameServer).is_some());
+        assert!(reply.opts().get(OptionCode::AddressLeaseTime).is_some());
+        assert!(reply.opts().get(OptionCode::OptionOverload).is_none());
+    }
+
+    #[test]
+    fn larger_announced_size_avoids_overloading() {
+        let (request, mut reply) = (request(Some(1500)), ack(28, 30));
+        let mut buf = Vec::new();
+        encode_within_limit(&request, &mut reply, &mut buf).unwrap();
+        assert!(buf.len() > 548);
+        assert!(reply.opts().get(OptionCode::OptionOverload).is_none());
+    }
+
+    #[test]
+    fn bootp_reply_padded_to_minimum() {
+        let mut reply = v4::Message::default();
+        reply.set_opcode(v4::Opcode::BootReply);
+        reply
+            .opts_mut()
+            .insert(DhcpOption::SubnetMask(Ipv4Addr::new(255, 255, 255, 0)));
+        let mut buf = Vec::new();
+        encode_within_limit(&request(None), &mut reply, &mut buf).unwrap();
+        assert_eq!(buf.len(), BOOTP_MIN_LEN);
+        let decoded = v4::Message::from_bytes(&buf).unwrap();
+        assert!(decoded.opts().get(OptionCode::SubnetMask).is_some());
+    }
+}
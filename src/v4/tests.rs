@@ -1,17 +1,20 @@
-use crate::types::{Duid, Option82, Reservation, V4Subnet};
+use crate::types::{BootParams, ClientId, Duid, Option82, Reservation, StaticRoute, V4Subnet};
 use advmac::MacAddr6;
 use dhcproto::v4::{self, DhcpOption, Flags, Opcode};
 use ipnet::Ipv6Net;
 
-use crate::config::{Config, LeaseTimes};
+use crate::config::{
+    Config, DeviceClass, LeaseTimes, ReservationProfile, SubscriberNetwork, WalledGarden,
+};
 use crate::opt82_cache::Opt82Cache;
 use crate::reservationdb::ReservationDb;
+use crate::testutil;
 use crate::v4::extractors;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, SocketAddrV4};
 
 use crate::v4::{
     extensions::ShadowMessageExtV4,
-    handlers::{handle_message, DhcpV4Response},
+    handlers::{handle_message, reply_destination, DhcpV4Response, NoResponse},
 };
 
 const TEST_MAC: MacAddr6 = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
@@ -20,6 +23,13 @@ const TEST_MAC_2: MacAddr6 = MacAddr6::new([0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF])
 // messages carry a relay address by default.
 const TEST_RELAY_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 254);
 
+/// Change one of the `subnets_v4`, re-indexing them
+fn set_subnet(config: &mut Config, index: usize, change: impl FnOnce(&mut V4Subnet)) {
+    let mut subnets = config.subnets_v4.as_slice().to_vec();
+    change(&mut subnets[index]);
+    config.subnets_v4 = subnets.into();
+}
+
 fn create_test_env() -> (Config, ReservationDb, Opt82Cache) {
     let config = Config {
         v4_server_id: Ipv4Addr::new(10, 0, 0, 1),
@@ -29,13 +39,18 @@ fn create_test_env() -> (Config, ReservationDb, Opt82Cache) {
                 net: "192.168.1.0/24".parse().unwrap(),
                 gateway: Ipv4Addr::new(192, 168, 1, 1),
                 reply_prefix_len: None,
+                authoritative: false,
+                ranges: vec![],
             },
             V4Subnet {
                 net: "10.10.0.0/16".parse().unwrap(),
                 gateway: Ipv4Addr::new(10, 10, 0, 1),
                 reply_prefix_len: None,
+                authoritative: false,
+                ranges: vec![],
             },
-        ],
+        ]
+        .into(),
         v6_server_id: Duid::from(vec![0, 1, 2, 3]),
         option82_extractors: extractors::get_all_extractors().into_iter().collect(),
         ..Default::default()
@@ -45,45 +60,75 @@ fn create_test_env() -> (Config, ReservationDb, Opt82Cache) {
 
     // MAC-based reservation
     let reservation_mac = Reservation {
-        ipv4: Ipv4Addr::new(192, 168, 1, 100),
-        ipv6_na: "2001:db8::100".parse().unwrap(),
+        ipv4: Some(Ipv4Addr::new(192, 168, 1, 100)),
+        ipv6_na: Some("2001:db8::100".parse().unwrap()),
         ipv6_pd: "2001:db8:100::/56".parse::<Ipv6Net>().unwrap(),
         mac: Some(TEST_MAC),
         duid: None,
+        client_id: None,
         option82: None,
         option1837: None,
+        svlan: None,
+        cvlan: None,
+        subscriber_id: None,
+        static_routes: vec![],
+        boot: None,
+        profile: None,
+        activate_at: None,
+        deactivate_at: None,
+        suspended: false,
     };
     reservations.insert(reservation_mac);
 
     // Option82-based reservation (remote_id only)
     let reservation_opt82 = Reservation {
-        ipv4: Ipv4Addr::new(192, 168, 1, 200),
-        ipv6_na: "2001:db8::200".parse().unwrap(),
+        ipv4: Some(Ipv4Addr::new(192, 168, 1, 200)),
+        ipv6_na: Some("2001:db8::200".parse().unwrap()),
         ipv6_pd: "2001:db8:200::/56".parse::<Ipv6Net>().unwrap(),
         mac: None,
         duid: None,
+        client_id: None,
         option82: Some(Option82 {
             circuit: None,
             remote: Some("switch1:port1".into()),
             subscriber: None,
         }),
         option1837: None,
+        svlan: None,
+        cvlan: None,
+        subscriber_id: None,
+        static_routes: vec![],
+        boot: None,
+        profile: None,
+        activate_at: None,
+        deactivate_at: None,
+        suspended: false,
     };
     reservations.insert(reservation_opt82);
 
     // Reservation with both MAC and Option82 (MAC should take priority)
     let reservation_both = Reservation {
-        ipv4: Ipv4Addr::new(10, 10, 1, 50),
-        ipv6_na: "2001:db8::50".parse().unwrap(),
+        ipv4: Some(Ipv4Addr::new(10, 10, 1, 50)),
+        ipv6_na: Some("2001:db8::50".parse().unwrap()),
         ipv6_pd: "2001:db8:50::/56".parse::<Ipv6Net>().unwrap(),
         mac: Some(TEST_MAC_2),
         duid: None,
+        client_id: None,
         option82: Some(Option82 {
             circuit: None,
             remote: Some("fallback-remote".into()),
             subscriber: None,
         }),
         option1837: None,
+        svlan: None,
+        cvlan: None,
+        subscriber_id: None,
+        static_routes: vec![],
+        boot: None,
+        profile: None,
+        activate_at: None,
+        deactivate_at: None,
+        suspended: false,
     };
     reservations.insert(reservation_both);
 
@@ -93,18 +138,7 @@ fn create_test_env() -> (Config, ReservationDb, Opt82Cache) {
 }
 
 fn create_discover(mac: MacAddr6, xid: u32) -> v4::Message {
-    let mut msg = v4::Message::new_with_id(
-        xid,
-        Ipv4Addr::UNSPECIFIED, // ciaddr
-        Ipv4Addr::UNSPECIFIED, // yiaddr
-        Ipv4Addr::UNSPECIFIED, // siaddr
-        TEST_RELAY_IP,         // giaddr
-        &mac.to_array(),
-    );
-    msg.set_opcode(Opcode::BootRequest);
-    msg.opts_mut()
-        .insert(DhcpOption::MessageType(v4::MessageType::Discover));
-    msg
+    testutil::discover(mac, xid, TEST_RELAY_IP)
 }
 
 fn create_request_selecting(
@@ -113,74 +147,11 @@ fn create_request_selecting(
     server_id: Ipv4Addr,
     requested_ip: Ipv4Addr,
 ) -> v4::Message {
-    let mut msg = v4::Message::new_with_id(
-        xid,
-        Ipv4Addr::UNSPECIFIED, // ciaddr must be zero
-        Ipv4Addr::UNSPECIFIED,
-        Ipv4Addr::UNSPECIFIED,
-        TEST_RELAY_IP,
-        &mac.to_array(),
-    );
-    msg.set_opcode(Opcode::BootRequest);
-    let opts = msg.opts_mut();
-    opts.insert(DhcpOption::MessageType(v4::MessageType::Request));
-    opts.insert(DhcpOption::ServerIdentifier(server_id));
-    opts.insert(DhcpOption::RequestedIpAddress(requested_ip));
-    msg
+    testutil::request_selecting(mac, xid, TEST_RELAY_IP, server_id, requested_ip)
 }
 
 fn create_request_init_reboot(mac: MacAddr6, xid: u32, requested_ip: Ipv4Addr) -> v4::Message {
-    let mut msg = v4::Message::new_with_id(
-        xid,
-        Ipv4Addr::UNSPECIFIED, // ciaddr must be zero
-        Ipv4Addr::UNSPECIFIED,
-        Ipv4Addr::UNSPECIFIED,
-        TEST_RELAY_IP,
-        &mac.to_array(),
-    );
-    msg.set_opcode(Opcode::BootRequest);
-    let opts = msg.opts_mut();
-    opts.insert(DhcpOption::MessageType(v4::MessageType::Request));
-    // No server_id for INIT-REBOOT
-    opts.insert(DhcpOption::RequestedIpAddress(requested_ip));
-    msg
-}
-
-fn create_request_renew(mac: MacAddr6, xid: u32, ciaddr: Ipv4Addr) -> v4::Message {
-    let mut msg = v4::Message::new_with_id(
-        xid,
-        ciaddr, // ciaddr must be filled
-        Ipv4Addr::UNSPECIFIED,
-        Ipv4Addr::UNSPECIFIED,
-        Ipv4Addr::UNSPECIFIED, // giaddr is zero for RENEW (unicast)
-        &mac.to_array(),
-    );
-    msg.set_opcode(Opcode::BootRequest);
-    let opts = msg.opts_mut();
-    opts.insert(DhcpOption::MessageType(v4::MessageType::Request));
-    // No server_id, no requested_ip for RENEW
-    msg
-}
-
-fn create_request_rebinding(
-    mac: MacAddr6,
-    xid: u32,
-    ciaddr: Ipv4Addr,
-    giaddr: Ipv4Addr,
-) -> v4::Message {
-    let mut msg = v4::Message::new_with_id(
-        xid,
-        ciaddr, // ciaddr must be filled
-        Ipv4Addr::UNSPECIFIED,
-        Ipv4Addr::UNSPECIFIED,
-        giaddr, // giaddr is set for REBINDING (broadcast via relay)
-        &mac.to_array(),
-    );
-    msg.set_opcode(Opcode::BootRequest);
-    let opts = msg.opts_mut();
-    opts.insert(DhcpOption::MessageType(v4::MessageType::Request));
-    // No server_id, no requested_ip for REBINDING
-    msg
+    testutil::request_init_reboot(mac, xid, TEST_RELAY_IP, requested_ip)
 }
 
 // ============================================================================
@@ -203,6 +174,87 @@ fn discover_with_mac_reservation_returns_offer() {
     assert_eq!(reply.yiaddr(), Ipv4Addr::new(192, 168, 1, 100));
 }
 
+#[test]
+fn boot_params_reservation_overrides_config() {
+    let (mut config, reservations, leases) = create_test_env();
+    config.boot = BootParams {
+        sname: Some("tftp.example.net".into()),
+        next_server: Some(Ipv4Addr::new(10, 0, 0, 5)),
+        file: Some("cpe/default.cfg".into()),
+        omit_sname: None,
+    };
+    let mut reservation = Reservation::clone(&reservations.by_mac(TEST_MAC).unwrap());
+    reservation.boot = Some(BootParams {
+        file: Some("cpe/model-a.cfg".into()),
+        ..Default::default()
+    });
+    reservations.insert(reservation);
+
+    let msg = create_discover(TEST_MAC, 0x12345678);
+    let reply = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => {
+            panic!("Expected OFFER, got NoResponse({:?})", reason)
+        }
+    };
+
+    assert_eq!(reply.sname(), Some(&b"tftp.example.net"[..]));
+    assert_eq!(reply.siaddr(), Ipv4Addr::new(10, 0, 0, 5));
+    assert_eq!(reply.fname(), Some(&b"cpe/model-a.cfg"[..]));
+}
+
+#[test]
+fn sname_omitted_by_vendor_class_and_profile() {
+    let (mut config, reservations, leases) = create_test_env();
+    config.boot = BootParams {
+        sname: Some("tftp.example.net".into()),
+        file: Some("cpe/default.cfg".into()),
+        ..Default::default()
+    };
+    config.omit_sname_vendor_classes = vec!["udhcp 0.9".into()];
+    config.reservation_profiles.insert(
+        "no-sname".into(),
+        ReservationProfile {
+            boot: Some(BootParams {
+                omit_sname: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    let offer = |config: &Config, msg: &v4::Message| match handle_message(
+        &reservations,
+        &leases,
+        config,
+        msg,
+    ) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+    let is_empty = |field: Option<&[u8]>| field.unwrap_or_default().iter().all(|b| *b == 0);
+
+    let mut msg = create_discover(TEST_MAC, 1);
+    assert_eq!(offer(&config, &msg).sname(), Some(&b"tftp.example.net"[..]));
+    msg.opts_mut()
+        .insert(DhcpOption::ClassIdentifier(b"udhcp 0.9.8".to_vec()));
+    let reply = offer(&config, &msg);
+    assert!(is_empty(reply.sname()));
+    assert_eq!(reply.fname(), Some(&b"cpe/default.cfg"[..]));
+
+    let mut reservation = Reservation::clone(&reservations.by_mac(TEST_MAC_2).unwrap());
+    reservation.profile = Some("no-sname".into());
+    reservations.insert(reservation.clone());
+    let msg = create_discover(TEST_MAC_2, 2);
+    assert!(is_empty(offer(&config, &msg).sname()));
+    // The reservation's own setting comes last
+    reservation.boot = Some(BootParams {
+        omit_sname: Some(false),
+        ..Default::default()
+    });
+    reservations.insert(reservation);
+    assert_eq!(offer(&config, &msg).sname(), Some(&b"tftp.example.net"[..]));
+}
+
 #[test]
 fn discover_with_option82_reservation_returns_offer() {
     let (config, reservations, leases) = create_test_env();
@@ -350,19 +402,434 @@ fn discover_no_reservation_returns_none() {
     );
 }
 
+#[test]
+fn bootp_request_answered_only_when_enabled() {
+    let (mut config, reservations, leases) = create_test_env();
+    let bootrequest = |mac: MacAddr6| {
+        let mut msg = create_discover(mac, 0x0B00790B);
+        msg.opts_mut().remove(v4::OptionCode::MessageType);
+        msg
+    };
+    let msg = bootrequest(TEST_MAC);
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &msg),
+        DhcpV4Response::NoResponse(NoResponse::NoMessageType)
+    ));
+
+    config.bootp_enabled = true;
+    let reply = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected BOOTREPLY, got {reason:?}"),
+    };
+    assert_eq!(reply.opcode(), Opcode::BootReply);
+    assert_eq!(reply.xid(), 0x0B00790B);
+    assert_eq!(reply.yiaddr(), Ipv4Addr::new(192, 168, 1, 100));
+    assert_eq!(reply.message_type(), None);
+    assert!(reply.opts().get(v4::OptionCode::SubnetMask).is_some());
+    assert!(reply.opts().get(v4::OptionCode::Router).is_some());
+    assert!(reply.opts().get(v4::OptionCode::ServerIdentifier).is_none());
+    assert!(reply.opts().get(v4::OptionCode::AddressLeaseTime).is_none());
+
+    // Reserved by Option 82 only: BOOTP clients are matched by MAC
+    let mut msg = bootrequest(MacAddr6::new([0x99, 0x99, 0x99, 0x99, 0x99, 0x99]));
+    let mut relay_info = dhcproto::v4::relay::RelayAgentInformation::default();
+    relay_info.insert(dhcproto::v4::relay::RelayInfo::AgentRemoteId(
+        b"switch1:port1".to_vec(),
+    ));
+    msg.opts_mut()
+        .insert(DhcpOption::RelayAgentInformation(relay_info));
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &msg),
+        DhcpV4Response::NoResponse(NoResponse::NoReservation)
+    ));
+
+    let reservation = reservations.by_mac(TEST_MAC).unwrap();
+    reservations.upsert(Reservation {
+        suspended: true,
+        ..Reservation::clone(&reservation)
+    });
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &bootrequest(TEST_MAC)),
+        DhcpV4Response::NoResponse(NoResponse::Suspended)
+    ));
+}
+
+#[test]
+fn unknown_client_on_authoritative_subnet_gets_nak() {
+    let (mut config, reservations, leases) = create_test_env();
+    set_subnet(&mut config, 0, |subnet| subnet.authoritative = true);
+    let unknown_mac = MacAddr6::new([0x99, 0x99, 0x99, 0x99, 0x99, 0x99]);
+    let requested_ip = Ipv4Addr::new(192, 168, 1, 50);
+
+    for msg in [
+        create_request_init_reboot(unknown_mac, 0x55555556, requested_ip),
+        testutil::request_rebinding(unknown_mac, 0x55555557, requested_ip, TEST_RELAY_IP),
+    ] {
+        let resp = match handle_message(&reservations, &leases, &config, &msg) {
+            DhcpV4Response::Message(resp) => resp,
+            DhcpV4Response::NoResponse(reason) => {
+                panic!("Expected NAK, got NoResponse({:?})", reason)
+            }
+        };
+        assert!(resp.reservation.is_none());
+        let reply = resp.message;
+        assert_eq!(reply.message_type(), Some(&v4::MessageType::Nak));
+        assert_eq!(reply.xid(), msg.xid());
+        assert_eq!(reply.yiaddr(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(reply.giaddr(), TEST_RELAY_IP);
+        assert!(Flags::broadcast(&reply.flags()));
+        assert!(reply.opts().iter().any(|(_, opt)| {
+            matches!(opt, DhcpOption::ServerIdentifier(id) if *id == config.v4_server_id)
+        }));
+    }
+
+    // RFC 2131 only NAKs a REQUEST: a DISCOVER goes unanswered, and so does
+    // a REQUEST selecting another server's offer
+    let discover = create_discover(unknown_mac, 0x55555555);
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &discover),
+        DhcpV4Response::NoResponse(NoResponse::NoReservation)
+    ));
+    let other_server = create_request_selecting(
+        unknown_mac,
+        0x55555559,
+        Ipv4Addr::new(10, 0, 0, 99),
+        requested_ip,
+    );
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &other_server),
+        DhcpV4Response::NoResponse(NoResponse::WrongServerId)
+    ));
+}
+
+#[test]
+fn unknown_client_rebinding_is_ignored_when_not_authoritative() {
+    // No binding exists for this client; without authority over the subnet
+    // another server may hold its lease, so stay silent rather than NAK.
+    let (config, reservations, leases) = create_test_env();
+    let unknown_mac = MacAddr6::new([0x99, 0x99, 0x99, 0x99, 0x99, 0x99]);
+    let msg = testutil::request_rebinding(
+        unknown_mac,
+        0x55555558,
+        Ipv4Addr::new(192, 168, 1, 50),
+        TEST_RELAY_IP,
+    );
+
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &msg),
+        DhcpV4Response::NoResponse(NoResponse::NotAuthoritative)
+    ));
+}
+
+#[test]
+fn unknown_client_relayed_from_other_subnet_is_ignored() {
+    let (mut config, reservations, leases) = create_test_env();
+    // giaddr is in the first subnet, only the second is authoritative
+    set_subnet(&mut config, 1, |subnet| subnet.authoritative = true);
+    let unknown_mac = MacAddr6::new([0x99, 0x99, 0x99, 0x99, 0x99, 0x99]);
+    let msg = create_discover(unknown_mac, 0x55555555);
+
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &msg),
+        DhcpV4Response::NoResponse(NoResponse::NotAuthoritative)
+    ));
+}
+
+#[test]
+fn v6_only_reservation_is_no_reservation_for_dhcpv4() {
+    let (mut config, reservations, leases) = create_test_env();
+    let reservation = reservations.by_mac(TEST_MAC).unwrap();
+    reservations.upsert(Reservation {
+        ipv4: None,
+        ..Reservation::clone(&reservation)
+    });
+    let msg = create_discover(TEST_MAC, 0x55555559);
+
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &msg),
+        DhcpV4Response::NoResponse(NoResponse::NotAuthoritative)
+    ));
+
+    // Answered like any unknown client, so an authoritative subnet NAKs its
+    // REQUEST
+    set_subnet(&mut config, 0, |subnet| subnet.authoritative = true);
+    let msg = create_request_init_reboot(TEST_MAC, 0x5555555a, Ipv4Addr::new(192, 168, 1, 100));
+    let resp = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected NAK, got {reason:?}"),
+    };
+    assert_eq!(resp.message.message_type(), Some(&v4::MessageType::Nak));
+    assert!(resp.reservation.is_none());
+}
+
+#[test]
+fn link_selection_chooses_subnet_over_giaddr() {
+    let (mut config, reservations, leases) = create_test_env();
+    // giaddr is in the first subnet, link selection names the second
+    set_subnet(&mut config, 1, |subnet| subnet.authoritative = true);
+    let unknown_mac = MacAddr6::new([0x99, 0x99, 0x99, 0x99, 0x99, 0x99]);
+    let mut msg = create_request_init_reboot(unknown_mac, 0x55555559, Ipv4Addr::new(10, 10, 0, 10));
+    let mut relay_info = dhcproto::v4::relay::RelayAgentInformation::default();
+    relay_info.insert(dhcproto::v4::relay::RelayInfo::LinkSelection(
+        Ipv4Addr::new(10, 10, 0, 0),
+    ));
+    msg.opts_mut()
+        .insert(DhcpOption::RelayAgentInformation(relay_info));
+
+    let reply = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected NAK, got {reason:?}"),
+    };
+    assert_eq!(reply.message_type(), Some(&v4::MessageType::Nak));
+    // The reply still goes back through the relay
+    assert_eq!(reply.giaddr(), TEST_RELAY_IP);
+}
+
+#[test]
+fn server_identifier_override_echoed_and_accepted() {
+    let (config, reservations, leases) = create_test_env();
+    let reserved_ip = Ipv4Addr::new(192, 168, 1, 100);
+    let override_id = TEST_RELAY_IP;
+    let with_override = |mut msg: v4::Message| {
+        let mut relay_info = dhcproto::v4::relay::RelayAgentInformation::default();
+        relay_info.insert(dhcproto::v4::relay::RelayInfo::ServerIdentifierOverride(
+            override_id,
+        ));
+        msg.opts_mut()
+            .insert(DhcpOption::RelayAgentInformation(relay_info));
+        msg
+    };
+
+    let offer = match handle_message(
+        &reservations,
+        &leases,
+        &config,
+        &with_override(create_discover(TEST_MAC, 0x5107)),
+    ) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+    assert_eq!(offer.server_id(), Some(&override_id));
+
+    // The client selects the override, which is this server
+    let msg = with_override(create_request_selecting(
+        TEST_MAC,
+        0x5108,
+        override_id,
+        reserved_ip,
+    ));
+    let ack = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected ACK, got {reason:?}"),
+    };
+    assert_eq!(ack.message_type(), Some(&v4::MessageType::Ack));
+    assert_eq!(ack.server_id(), Some(&override_id));
+}
+
+#[test]
+fn subscriber_network_overrides_options_for_its_relays() {
+    let (mut config, reservations, leases) = create_test_env();
+    config.subscriber_networks = vec![SubscriberNetwork {
+        name: "business".into(),
+        relays_v4: vec!["192.168.1.254/32".parse().unwrap()],
+        relays_v6: vec![],
+        dns_v4: Some(vec![Ipv4Addr::new(192, 0, 2, 53)]),
+        dns_v6: None,
+        domain_search_v6: None,
+        sntp_v6: None,
+        ntp_v6: None,
+        ntp_v4: None,
+        interface_mtu: Some(1492),
+        domain_name: None,
+        static_routes: None,
+        v4_lease_time: Some(600),
+        v6_lease_time: None,
+        authoritative: Some(true),
+        v6_advertise_unknown_clients: None,
+    }];
+
+    let resp = match handle_message(
+        &reservations,
+        &leases,
+        &config,
+        &create_discover(TEST_MAC, 1),
+    ) {
+        DhcpV4Response::Message(resp) => resp,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+    let opts = resp.message.opts();
+    assert!(opts.iter().any(|(_, opt)| {
+        matches!(opt, DhcpOption::DomainNameServer(dns) if dns == &[Ipv4Addr::new(192, 0, 2, 53)])
+    }));
+    assert!(opts
+        .iter()
+        .any(|(_, opt)| matches!(opt, DhcpOption::AddressLeaseTime(600))));
+    assert!(opts
+        .iter()
+        .any(|(_, opt)| matches!(opt, DhcpOption::InterfaceMtu(1492))));
+
+    // The subnet isn't authoritative but the network is
+    let unknown_mac = MacAddr6::new([0x99, 0x99, 0x99, 0x99, 0x99, 0x99]);
+    match handle_message(
+        &reservations,
+        &leases,
+        &config,
+        &create_request_init_reboot(unknown_mac, 2, Ipv4Addr::new(192, 168, 1, 50)),
+    ) {
+        DhcpV4Response::Message(resp) => {
+            assert_eq!(resp.message.message_type(), Some(&v4::MessageType::Nak))
+        }
+        DhcpV4Response::NoResponse(reason) => panic!("Expected NAK, got {reason:?}"),
+    }
+
+    // Other relays keep the top-level settings
+    let mut msg = create_discover(TEST_MAC, 3);
+    msg.set_giaddr(Ipv4Addr::new(192, 168, 1, 253));
+    let resp = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+    assert!(resp.message.opts().iter().any(|(_, opt)| {
+        matches!(opt, DhcpOption::AddressLeaseTime(lease) if *lease == config.lease_times.v4_lease)
+    }));
+}
+
+#[test]
+fn reservation_profile_applies_between_network_and_reservation() {
+    let (mut config, reservations, leases) = create_test_env();
+    config.boot = BootParams {
+        sname: Some("config".into()),
+        next_server: None,
+        file: Some("config.cfg".into()),
+        omit_sname: None,
+    };
+    config.reservation_profiles.insert(
+        "residential-1g".into(),
+        ReservationProfile {
+            dns_v4: Some(vec![Ipv4Addr::new(192, 0, 2, 53)]),
+            v4_lease_time: Some(86400),
+            boot: Some(BootParams {
+                file: Some("residential.cfg".into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    let mut reservation = Reservation::clone(&reservations.by_mac(TEST_MAC).unwrap());
+    reservation.profile = Some("residential-1g".into());
+    reservation.boot = Some(BootParams {
+        sname: Some("own".into()),
+        ..Default::default()
+    });
+    reservations.insert(reservation);
+
+    let resp = match handle_message(
+        &reservations,
+        &leases,
+        &config,
+        &create_discover(TEST_MAC, 1),
+    ) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+    assert!(resp.opts().iter().any(|(_, opt)| {
+        matches!(opt, DhcpOption::DomainNameServer(dns) if dns == &[Ipv4Addr::new(192, 0, 2, 53)])
+    }));
+    assert!(resp
+        .opts()
+        .iter()
+        .any(|(_, opt)| matches!(opt, DhcpOption::AddressLeaseTime(86400))));
+    assert_eq!(resp.sname(), Some(&b"own"[..]));
+    assert_eq!(resp.fname(), Some(&b"residential.cfg"[..]));
+}
+
+#[test]
+fn device_class_profile_applies_after_reservation_profile() {
+    let (mut config, reservations, leases) = create_test_env();
+    config.reservation_profiles.insert(
+        "residential-1g".into(),
+        ReservationProfile {
+            dns_v4: Some(vec![Ipv4Addr::new(192, 0, 2, 53)]),
+            v4_lease_time: Some(86400),
+            ..Default::default()
+        },
+    );
+    config.reservation_profiles.insert(
+        "legacy-ont".into(),
+        ReservationProfile {
+            dns_v4: Some(vec![Ipv4Addr::new(198, 51, 100, 53)]),
+            ..Default::default()
+        },
+    );
+    let mut reservation = Reservation::clone(&reservations.by_mac(TEST_MAC).unwrap());
+    reservation.profile = Some("residential-1g".into());
+    reservations.insert(reservation);
+
+    let discover = |vendor: &[u8]| {
+        let mut msg = create_discover(TEST_MAC, 1);
+        let opts = msg.opts_mut();
+        opts.insert(DhcpOption::ClassIdentifier(vendor.to_vec()));
+        opts.insert(DhcpOption::ParameterRequestList(vec![
+            v4::OptionCode::SubnetMask,
+            v4::OptionCode::Router,
+            v4::OptionCode::DomainNameServer,
+        ]));
+        msg
+    };
+    let legacy = discover(b"acme-ont 1.0");
+    config.device_classes.insert(
+        "acme-ont-1.0".into(),
+        DeviceClass {
+            fingerprints: vec![crate::fingerprint::v4(&legacy).unwrap().to_string()],
+            profile: Some("legacy-ont".into()),
+        },
+    );
+
+    let offer = |msg: &v4::Message| match handle_message(&reservations, &leases, &config, msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+    let dns = |reply: &v4::Message| {
+        reply.opts().iter().find_map(|(_, opt)| match opt {
+            DhcpOption::DomainNameServer(dns) => Some(dns.clone()),
+            _ => None,
+        })
+    };
+    let reply = offer(&legacy);
+    assert_eq!(dns(&reply), Some(vec![Ipv4Addr::new(198, 51, 100, 53)]));
+    // What the class leaves alone still comes from the reservation's profile
+    assert!(reply
+        .opts()
+        .iter()
+        .any(|(_, opt)| matches!(opt, DhcpOption::AddressLeaseTime(86400))));
+
+    let reply = offer(&discover(b"acme-ont 2.0"));
+    assert_eq!(dns(&reply), Some(vec![Ipv4Addr::new(192, 0, 2, 53)]));
+}
+
 #[test]
 fn discover_reservation_not_in_subnet_returns_none() {
     let (config, reservations, leases) = create_test_env();
 
     // Add a reservation with an IP not in any configured subnet
     let bad_reservation = Reservation {
-        ipv4: Ipv4Addr::new(172, 16, 0, 1), // Not in 192.168.1.0/24 or 10.10.0.0/16
-        ipv6_na: "2001:db8::bad".parse().unwrap(),
+        ipv4: Some(Ipv4Addr::new(172, 16, 0, 1)), // Not in 192.168.1.0/24 or 10.10.0.0/16
+        ipv6_na: Some("2001:db8::bad".parse().unwrap()),
         ipv6_pd: "2001:db8:bad::/56".parse::<Ipv6Net>().unwrap(),
         mac: Some(MacAddr6::new([0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC])),
         duid: None,
+        client_id: None,
         option82: None,
         option1837: None,
+        svlan: None,
+        cvlan: None,
+        subscriber_id: None,
+        static_routes: vec![],
+        boot: None,
+        profile: None,
+        activate_at: None,
+        deactivate_at: None,
+        suspended: false,
     };
     reservations.insert(bad_reservation);
 
@@ -379,6 +846,94 @@ fn discover_reservation_not_in_subnet_returns_none() {
     );
 }
 
+#[test]
+fn suspended_reservation_is_nakked() {
+    let (config, reservations, leases) = create_test_env();
+    let reservation = reservations.by_mac(TEST_MAC).unwrap();
+    reservations.upsert(Reservation {
+        suspended: true,
+        ..Reservation::clone(&reservation)
+    });
+
+    let discover = create_discover(TEST_MAC, 0x57575757);
+    assert!(matches!(
+        handle_message(&reservations, &leases, &config, &discover),
+        DhcpV4Response::NoResponse(NoResponse::Suspended)
+    ));
+
+    let request = create_request_init_reboot(TEST_MAC, 0x57575758, reservation.ipv4.unwrap());
+    let resp = match handle_message(&reservations, &leases, &config, &request) {
+        DhcpV4Response::Message(resp) => resp,
+        DhcpV4Response::NoResponse(reason) => {
+            panic!("Expected NAK, got NoResponse({:?})", reason)
+        }
+    };
+    assert_eq!(resp.message.message_type(), Some(&v4::MessageType::Nak));
+    assert_eq!(resp.message.yiaddr(), Ipv4Addr::UNSPECIFIED);
+    assert!(resp.reservation.is_some_and(|r| r.suspended));
+}
+
+#[test]
+fn suspended_reservation_placed_in_walled_garden() {
+    let (mut config, reservations, leases) = create_test_env();
+    config.walled_garden = Some(WalledGarden {
+        net: "10.99.0.0/16".parse().unwrap(),
+        gateway: Ipv4Addr::new(10, 99, 0, 1),
+        dns_v4: vec![Ipv4Addr::new(10, 99, 0, 53)],
+        lease_time: 300,
+    });
+    let reservation = reservations.by_mac(TEST_MAC).unwrap();
+    reservations.upsert(Reservation {
+        suspended: true,
+        static_routes: vec![StaticRoute {
+            destination: "172.16.0.0/12".parse().unwrap(),
+            gateway: Ipv4Addr::new(192, 168, 1, 1),
+        }],
+        ..Reservation::clone(&reservation)
+    });
+    let garden_ip = Ipv4Addr::new(10, 99, 1, 100);
+
+    let offer = match handle_message(
+        &reservations,
+        &leases,
+        &config,
+        &create_discover(TEST_MAC, 0x58585858),
+    ) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+    assert_eq!(offer.message_type(), Some(&v4::MessageType::Offer));
+    assert_eq!(offer.yiaddr(), garden_ip);
+    let opts: Vec<_> = offer.opts().iter().map(|(_, opt)| opt).collect();
+    assert!(opts
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::Router(r) if r == &[Ipv4Addr::new(10, 99, 0, 1)])));
+    assert!(opts.iter().any(|opt| {
+        matches!(opt, DhcpOption::DomainNameServer(dns) if dns == &[Ipv4Addr::new(10, 99, 0, 53)])
+    }));
+    assert!(opts
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::AddressLeaseTime(300))));
+    assert!(!opts.iter().any(|opt| matches!(
+        opt,
+        DhcpOption::StaticRoutingTable(_) | DhcpOption::ClasslessStaticRoute(_)
+    )));
+
+    // The reserved address is refused while suspended, the garden one acked
+    for (requested, expected) in [
+        (reservation.ipv4.unwrap(), v4::MessageType::Nak),
+        (garden_ip, v4::MessageType::Ack),
+    ] {
+        let request = create_request_init_reboot(TEST_MAC, 0x58585859, requested);
+        match handle_message(&reservations, &leases, &config, &request) {
+            DhcpV4Response::Message(resp) => {
+                assert_eq!(resp.message.message_type(), Some(&expected))
+            }
+            DhcpV4Response::NoResponse(reason) => panic!("Expected reply, got {reason:?}"),
+        }
+    }
+}
+
 #[test]
 fn mac_has_priority_over_option82() {
     let (config, reservations, leases) = create_test_env();
@@ -404,6 +959,176 @@ fn mac_has_priority_over_option82() {
     assert_eq!(reply.yiaddr(), Ipv4Addr::new(10, 10, 1, 50));
 }
 
+#[test]
+fn match_records_key_and_priority() {
+    let (config, reservations, leases) = create_test_env();
+    let found = |msg: &v4::Message| match handle_message(&reservations, &leases, &config, msg) {
+        DhcpV4Response::Message(resp) => resp.reservation_match.unwrap(),
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+
+    let by_mac = found(&create_discover(TEST_MAC, 0x1));
+    assert_eq!(by_mac.key.as_deref(), Some("00-11-22-33-44-55"));
+    assert_eq!(by_mac.priority, Some(2));
+
+    let mut msg = create_discover(MacAddr6::new([0xFF, 0xEE, 0xDD, 0xCC, 0xBB, 0xAA]), 0x2);
+    let mut relay_info = dhcproto::v4::relay::RelayAgentInformation::default();
+    relay_info.insert(dhcproto::v4::relay::RelayInfo::AgentRemoteId(
+        b"switch1:port1".to_vec(),
+    ));
+    msg.opts_mut()
+        .insert(DhcpOption::RelayAgentInformation(relay_info));
+    let by_relay = found(&msg);
+    assert_eq!(by_relay.method, "option82");
+    assert_eq!(
+        by_relay.key.as_deref(),
+        Some(r#"{"circuit":null,"remote":"switch1:port1","subscriber":null}"#)
+    );
+    assert_eq!(by_relay.priority, Some(4));
+
+    let event =
+        crate::analytics::events::DhcpEventV4::success(&msg, TEST_RELAY_IP, None, Some(&by_relay));
+    assert_eq!(event.match_key, by_relay.key);
+    assert_eq!(event.match_priority, Some(4));
+}
+
+#[test]
+fn option82_mac_matches_whatever_format_either_side_uses() {
+    let (mut config, reservations, leases) = create_test_env();
+    let reservation: Reservation = serde_json::from_str(
+        r#"{"ipv4": "192.168.1.150", "ipv6_na": "2001:db8::150",
+            "ipv6_pd": "2001:db8:150::/56", "option82": {"remote": "00:11:22:33:44:aa"}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        reservation.option82.as_ref().unwrap().remote.as_deref(),
+        Some("00-11-22-33-44-AA")
+    );
+    reservations.insert(reservation);
+
+    let unknown_mac = MacAddr6::new([0x99, 0x99, 0x99, 0x99, 0x99, 0x99]);
+    for (extractor, remote) in [
+        ("normalize_remote_mac", "0011.2233.44aa"),
+        ("remote_only", "0011223344AA"),
+        ("remote_only", "00-11-22-33-44-aa"),
+    ] {
+        config.option82_extractors = extractors::get_all_extractors()
+            .into_iter()
+            .filter(|(name, _)| *name == extractor)
+            .collect();
+        let mut msg = create_discover(unknown_mac, 0x3);
+        let mut relay_info = dhcproto::v4::relay::RelayAgentInformation::default();
+        relay_info.insert(dhcproto::v4::relay::RelayInfo::AgentRemoteId(
+            remote.as_bytes().to_vec(),
+        ));
+        msg.opts_mut()
+            .insert(DhcpOption::RelayAgentInformation(relay_info));
+
+        let reply = match handle_message(&reservations, &leases, &config, &msg) {
+            DhcpV4Response::Message(resp) => resp.message,
+            DhcpV4Response::NoResponse(reason) => {
+                panic!("{extractor} {remote}: expected OFFER, got {reason:?}")
+            }
+        };
+        assert_eq!(reply.yiaddr(), Ipv4Addr::new(192, 168, 1, 150), "{remote}");
+    }
+}
+
+#[test]
+fn vlans_match_after_mac_before_option82() {
+    let (config, reservations, leases) = create_test_env();
+    let reservation = reservations.by_mac(TEST_MAC).unwrap();
+    reservations.insert(Reservation {
+        ipv4: Some(Ipv4Addr::new(10, 10, 2, 2)),
+        ipv6_na: Some("2001:db8::1002".parse().unwrap()),
+        ipv6_pd: "2001:db8:1002::/56".parse::<Ipv6Net>().unwrap(),
+        mac: None,
+        svlan: Some(100),
+        cvlan: Some(200),
+        ..Reservation::clone(&reservation)
+    });
+
+    let mut relay_info = dhcproto::v4::relay::RelayAgentInformation::default();
+    relay_info.insert(dhcproto::v4::relay::RelayInfo::AgentCircuitId(
+        b"eth 0/0/1:100.200".to_vec(),
+    ));
+    // Would match the 192.168.1.200 reservation
+    relay_info.insert(dhcproto::v4::relay::RelayInfo::AgentRemoteId(
+        b"switch1:port1".to_vec(),
+    ));
+    for (mac, expected) in [
+        (
+            MacAddr6::new([0x02, 0, 0, 0, 0x10, 0x02]),
+            Ipv4Addr::new(10, 10, 2, 2),
+        ),
+        (TEST_MAC, reservation.ipv4.unwrap()),
+    ] {
+        let mut msg = create_discover(mac, 0x88888888);
+        msg.opts_mut()
+            .insert(DhcpOption::RelayAgentInformation(relay_info.clone()));
+        let resp = match handle_message(&reservations, &leases, &config, &msg) {
+            DhcpV4Response::Message(resp) => resp,
+            DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+        };
+        assert_eq!(resp.message.yiaddr(), expected);
+        if expected != reservation.ipv4.unwrap() {
+            assert_eq!(resp.reservation_match.unwrap().method, "vlans");
+        }
+    }
+}
+
+#[test]
+fn client_id_has_priority_over_mac() {
+    let (config, reservations, leases) = create_test_env();
+    // CPE whose option 61 is an RFC 4361 DUID rather than its chaddr
+    let client_id = ClientId::new(vec![
+        0xff, 0, 0, 0, 1, 0, 3, 0, 1, 0xde, 0xad, 0xbe, 0xef, 0, 1,
+    ])
+    .unwrap();
+    reservations.insert(Reservation {
+        ipv4: Some(Ipv4Addr::new(192, 168, 1, 61)),
+        ipv6_na: Some("2001:db8::61".parse().unwrap()),
+        ipv6_pd: "2001:db8:61::/56".parse::<Ipv6Net>().unwrap(),
+        mac: None,
+        duid: None,
+        client_id: Some(client_id.clone()),
+        option82: None,
+        option1837: None,
+        svlan: None,
+        cvlan: None,
+        subscriber_id: None,
+        static_routes: vec![],
+        boot: None,
+        profile: None,
+        activate_at: None,
+        deactivate_at: None,
+        suspended: false,
+    });
+
+    // TEST_MAC has its own reservation, the client-id one should win
+    let mut msg = create_discover(TEST_MAC, 0x61616161);
+    msg.opts_mut()
+        .insert(DhcpOption::ClientIdentifier(client_id.bytes));
+
+    let reply = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => {
+            panic!("Expected OFFER, got NoResponse({:?})", reason)
+        }
+    };
+    assert_eq!(reply.yiaddr(), Ipv4Addr::new(192, 168, 1, 61));
+
+    // Without option 61 the MAC reservation is used as before
+    let msg = create_discover(TEST_MAC, 0x61616162);
+    let reply = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => {
+            panic!("Expected OFFER, got NoResponse({:?})", reason)
+        }
+    };
+    assert_eq!(reply.yiaddr(), Ipv4Addr::new(192, 168, 1, 100));
+}
+
 // ============================================================================
 // REQUEST Tests - SELECTING variant
 // ============================================================================
@@ -423,6 +1148,132 @@ fn request_selecting_returns_ack() {
     assert_eq!(reply.yiaddr(), reserved_ip);
 }
 
+#[test]
+fn subscriber_id_carried_by_event_and_lease() {
+    let (config, reservations, leases) = create_test_env();
+    let reservation = reservations.by_mac(TEST_MAC).unwrap();
+    reservations.upsert(Reservation {
+        subscriber_id: Some("acct-100234".into()),
+        ..Reservation::clone(&reservation)
+    });
+    let reserved_ip = Ipv4Addr::new(192, 168, 1, 100);
+    let msg = create_request_selecting(TEST_MAC, 0x1, config.v4_server_id, reserved_ip);
+
+    let DhcpV4Response::Message(resp) = handle_message(&reservations, &leases, &config, &msg)
+    else {
+        panic!("Expected ACK");
+    };
+    assert_eq!(
+        crate::v4::worker::lease_conflict(&leases, &config, &msg, &resp),
+        None
+    );
+    let event = crate::analytics::events::DhcpEventV4::success(
+        &msg,
+        TEST_RELAY_IP,
+        resp.reservation.as_deref(),
+        resp.reservation_match.as_ref(),
+    );
+    assert_eq!(
+        event.reservation_subscriber_id.as_deref(),
+        Some("acct-100234")
+    );
+
+    let snapshot = leases.snapshot();
+    assert_eq!(snapshot.v4.len(), 1);
+    assert_eq!(snapshot.v4[0].ipv4, reserved_ip);
+    assert_eq!(snapshot.v4[0].subscriber_id.as_deref(), Some("acct-100234"));
+}
+
+#[test]
+fn lease_listed_for_the_profile_lease_time() {
+    let (mut config, reservations, leases) = create_test_env();
+    let profile_lease = config.lease_times.v4_lease * 24;
+    config.reservation_profiles.insert(
+        "long-lease".into(),
+        ReservationProfile {
+            v4_lease_time: Some(profile_lease),
+            ..Default::default()
+        },
+    );
+    let mut reservation = Reservation::clone(&reservations.by_mac(TEST_MAC).unwrap());
+    reservation.profile = Some("long-lease".into());
+    reservations.insert(reservation);
+    let reserved_ip = Ipv4Addr::new(192, 168, 1, 100);
+    let msg = create_request_selecting(TEST_MAC, 0x1, config.v4_server_id, reserved_ip);
+
+    let DhcpV4Response::Message(resp) = handle_message(&reservations, &leases, &config, &msg)
+    else {
+        panic!("Expected ACK");
+    };
+    assert_eq!(
+        resp.lease,
+        Some(std::time::Duration::from_secs(profile_lease.into()))
+    );
+    crate::v4::worker::lease_conflict(&leases, &config, &msg, &resp);
+
+    let snapshot = leases.snapshot();
+    assert_eq!(snapshot.v4.len(), 1);
+    let lease = &snapshot.v4[0];
+    assert_eq!(
+        lease.expires_at - lease.leased_at,
+        u64::from(profile_lease) * 1000
+    );
+}
+
+#[test]
+fn offer_and_ack_include_configured_host_options() {
+    let (mut config, reservations, leases) = create_test_env();
+    let reserved_ip = Ipv4Addr::new(192, 168, 1, 100);
+    let discover = create_discover(TEST_MAC, 1);
+    let request = create_request_selecting(TEST_MAC, 2, config.v4_server_id, reserved_ip);
+
+    // Nothing configured, nothing sent
+    for msg in [&discover, &request] {
+        let DhcpV4Response::Message(resp) = handle_message(&reservations, &leases, &config, msg)
+        else {
+            panic!("Expected a reply");
+        };
+        assert!(resp
+            .message
+            .opts()
+            .get(v4::OptionCode::NtpServers)
+            .is_none());
+        assert!(resp
+            .message
+            .opts()
+            .get(v4::OptionCode::InterfaceMtu)
+            .is_none());
+        assert!(resp
+            .message
+            .opts()
+            .get(v4::OptionCode::DomainName)
+            .is_none());
+    }
+
+    config.ntp_v4 = vec![Ipv4Addr::new(192, 0, 2, 123)];
+    config.interface_mtu = Some(1500);
+    config.domain_name = Some("example.net.".into());
+    for msg in [&discover, &request] {
+        let DhcpV4Response::Message(resp) = handle_message(&reservations, &leases, &config, msg)
+        else {
+            panic!("Expected a reply");
+        };
+        let opts = resp.message.opts();
+        assert!(matches!(
+            opts.get(v4::OptionCode::NtpServers),
+            Some(DhcpOption::NtpServers(ntp)) if ntp == &[Ipv4Addr::new(192, 0, 2, 123)]
+        ));
+        assert!(matches!(
+            opts.get(v4::OptionCode::InterfaceMtu),
+            Some(DhcpOption::InterfaceMtu(1500))
+        ));
+        assert!(matches!(
+            opts.get(v4::OptionCode::DomainName),
+            Some(DhcpOption::DomainName(domain)) if domain == "example.net"
+        ));
+    }
+}
+
 #[test]
 fn request_selecting_wrong_server_id_ignored() {
     let (config, reservations, leases) = create_test_env();
@@ -494,7 +1345,7 @@ fn request_init_reboot_wrong_ip_returns_nak() {
 fn request_renew_unicast_is_rejected() {
     let (config, reservations, leases) = create_test_env();
     let reserved_ip = Ipv4Addr::new(192, 168, 1, 100);
-    let msg = create_request_renew(TEST_MAC, 0xDDDDDDDD, reserved_ip);
+    let msg = testutil::request_renew(TEST_MAC, 0xDDDDDDDD, reserved_ip);
 
     let reply = handle_message(&reservations, &leases, &config, &msg);
 
@@ -513,7 +1364,7 @@ fn request_rebinding_returns_ack() {
     let (config, reservations, leases) = create_test_env();
     let reserved_ip = Ipv4Addr::new(192, 168, 1, 100);
     let relay_ip = Ipv4Addr::new(192, 168, 1, 254);
-    let msg = create_request_rebinding(TEST_MAC, 0xF0F0F0F0, reserved_ip, relay_ip);
+    let msg = testutil::request_rebinding(TEST_MAC, 0xF0F0F0F0, reserved_ip, relay_ip);
 
     let reply = match handle_message(&reservations, &leases, &config, &msg) {
         DhcpV4Response::Message(resp) => resp.message,
@@ -725,7 +1576,7 @@ fn rebinding_ack_yiaddr_must_be_set() {
     let (config, reservations, leases) = create_test_env();
     let reserved_ip = Ipv4Addr::new(192, 168, 1, 100);
     let relay_ip = Ipv4Addr::new(192, 168, 1, 254);
-    let msg = create_request_rebinding(TEST_MAC, 0x55556666, reserved_ip, relay_ip);
+    let msg = testutil::request_rebinding(TEST_MAC, 0x55556666, reserved_ip, relay_ip);
 
     let reply = match handle_message(&reservations, &leases, &config, &msg) {
         DhcpV4Response::Message(resp) => resp.message,
@@ -830,7 +1681,7 @@ fn offer_should_include_t1_renewal_time() {
 #[test]
 fn discover_uses_reply_prefix_len_override() {
     let (mut config, reservations, leases) = create_test_env();
-    config.subnets_v4[0].reply_prefix_len = Some(32);
+    set_subnet(&mut config, 0, |subnet| subnet.reply_prefix_len = Some(32));
     let msg = create_discover(TEST_MAC, 0xABCD1234);
 
     let reply = match handle_message(&reservations, &leases, &config, &msg) {
@@ -852,7 +1703,7 @@ fn discover_uses_reply_prefix_len_override() {
 #[test]
 fn request_uses_reply_prefix_len_override() {
     let (mut config, reservations, leases) = create_test_env();
-    config.subnets_v4[0].reply_prefix_len = Some(32);
+    set_subnet(&mut config, 0, |subnet| subnet.reply_prefix_len = Some(32));
 
     let reserved_ip = Ipv4Addr::new(192, 168, 1, 100);
     let msg = create_request_selecting(TEST_MAC, 0xEFEF1234, config.v4_server_id, reserved_ip);
@@ -872,3 +1723,232 @@ fn request_uses_reply_prefix_len_override() {
         "SubnetMask should be /32 (255.255.255.255) when reply_prefix_len is set to 32"
     );
 }
+
+#[test]
+fn most_specific_range_gives_gateway_and_mask() {
+    let (mut config, reservations, leases) = create_test_env();
+    // 192.168.1.100 is in the first subnet's /24, and in this narrower range
+    // shared with the second
+    set_subnet(&mut config, 1, |subnet| {
+        subnet.ranges = vec!["192.168.1.64/26".parse().unwrap()]
+    });
+    let msg = create_discover(TEST_MAC, 0xABCD1235);
+
+    let reply = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => panic!("Expected OFFER, got {reason:?}"),
+    };
+    assert_eq!(reply.yiaddr(), Ipv4Addr::new(192, 168, 1, 100));
+    let opts: Vec<_> = reply.opts().iter().map(|(_, opt)| opt).collect();
+    assert!(opts.iter().any(|opt| {
+        matches!(opt, DhcpOption::SubnetMask(mask) if *mask == Ipv4Addr::new(255, 255, 255, 192))
+    }));
+    assert!(opts
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::Router(r) if r == &[Ipv4Addr::new(10, 10, 0, 1)])));
+}
+
+#[test]
+fn relayed_reply_goes_to_giaddr_server_port() {
+    let (config, reservations, leases) = create_test_env();
+    let msg = create_discover(TEST_MAC, 0x1234);
+
+    let response = handle_message(&reservations, &leases, &config, &msg);
+    let DhcpV4Response::Message(resp) = response else {
+        panic!("Expected Offer");
+    };
+    assert_eq!(
+        reply_destination(&msg, &resp.message),
+        SocketAddrV4::new(TEST_RELAY_IP, 67)
+    );
+}
+
+#[test]
+fn unrelayed_reply_destinations() {
+    let broadcast = SocketAddrV4::new(Ipv4Addr::BROADCAST, 68);
+    let mut request = create_discover(TEST_MAC, 0x1234);
+    request.set_giaddr(Ipv4Addr::UNSPECIFIED);
+    let mut reply = request.clone();
+    reply
+        .opts_mut()
+        .insert(DhcpOption::MessageType(v4::MessageType::Offer));
+
+    // no ciaddr, no broadcast flag: can't unicast without ARP, so broadcast
+    assert_eq!(reply_destination(&request, &reply), broadcast);
+
+    request.set_ciaddr(Ipv4Addr::new(192, 168, 1, 100));
+    assert_eq!(
+        reply_destination(&request, &reply),
+        SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 100), 68)
+    );
+
+    // NAKs are always broadcast when not relayed
+    reply
+        .opts_mut()
+        .insert(DhcpOption::MessageType(v4::MessageType::Nak));
+    assert_eq!(reply_destination(&request, &reply), broadcast);
+}
+
+/// RFC 2131 §4.3.5: the ACK to an INFORM goes straight to ciaddr even when
+/// relayed; a RENEWING client's unicast REQUEST is answered at ciaddr too
+#[test]
+fn inform_and_unicast_renew_answered_at_ciaddr() {
+    let ciaddr = Ipv4Addr::new(192, 168, 1, 100);
+    let mut ack = create_discover(TEST_MAC, 0x1234);
+    ack.opts_mut()
+        .insert(DhcpOption::MessageType(v4::MessageType::Ack));
+
+    let mut inform = create_discover(TEST_MAC, 0x1234);
+    inform
+        .opts_mut()
+        .insert(DhcpOption::MessageType(v4::MessageType::Inform));
+    inform.set_ciaddr(ciaddr);
+    assert_eq!(
+        reply_destination(&inform, &ack),
+        SocketAddrV4::new(ciaddr, 68)
+    );
+
+    let renew = testutil::request_renew(TEST_MAC, 0x1234, ciaddr);
+    assert_eq!(renew.giaddr(), Ipv4Addr::UNSPECIFIED);
+    assert_eq!(
+        reply_destination(&renew, &ack),
+        SocketAddrV4::new(ciaddr, 68)
+    );
+
+    // Relayed, the same REQUEST goes back through the relay
+    let mut renew = renew;
+    renew.set_giaddr(TEST_RELAY_IP);
+    assert_eq!(
+        reply_destination(&renew, &ack),
+        SocketAddrV4::new(TEST_RELAY_IP, 67)
+    );
+}
+
+fn route(destination: &str, gateway: Ipv4Addr) -> StaticRoute {
+    StaticRoute {
+        destination: destination.parse().unwrap(),
+        gateway,
+    }
+}
+
+#[test]
+fn classless_routes_sent_when_requested() {
+    let (mut config, reservations, leases) = create_test_env();
+    let gateway = Ipv4Addr::new(192, 168, 1, 1);
+    config.static_routes = vec![route("10.50.0.0/16", gateway)];
+    let mut msg = create_discover(TEST_MAC, 0x1234);
+    msg.opts_mut().insert(DhcpOption::ParameterRequestList(vec![
+        v4::OptionCode::SubnetMask,
+        v4::OptionCode::ClasslessStaticRoute,
+    ]));
+
+    let reply = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => {
+            panic!("Expected OFFER, got NoResponse({:?})", reason)
+        }
+    };
+
+    let routes = reply.opts().iter().find_map(|(_, opt)| match opt {
+        DhcpOption::ClasslessStaticRoute(routes) => Some(routes.clone()),
+        _ => None,
+    });
+    // the default route comes along, since the client ignores option 3
+    assert_eq!(
+        routes,
+        Some(vec![
+            ("0.0.0.0/0".parse().unwrap(), gateway),
+            ("10.50.0.0/16".parse().unwrap(), gateway),
+        ])
+    );
+    assert!(!reply
+        .opts()
+        .iter()
+        .any(|(_, opt)| matches!(opt, DhcpOption::StaticRoutingTable(_))));
+}
+
+#[test]
+fn reservation_routes_fall_back_to_option_33() {
+    let (mut config, reservations, leases) = create_test_env();
+    let gateway = Ipv4Addr::new(192, 168, 1, 1);
+    config.static_routes = vec![route("10.0.0.0/8", Ipv4Addr::new(192, 168, 1, 2))];
+    let mut reservation = (*reservations.by_mac(TEST_MAC).unwrap()).clone();
+    reservation.static_routes = vec![
+        // replaces the config route to the same destination
+        route("10.0.0.0/8", gateway),
+        // not classful, can't be expressed in option 33
+        route("172.16.0.0/12", gateway),
+    ];
+    reservations.upsert(reservation);
+    let msg = create_discover(TEST_MAC, 0x1234);
+
+    let reply = match handle_message(&reservations, &leases, &config, &msg) {
+        DhcpV4Response::Message(resp) => resp.message,
+        DhcpV4Response::NoResponse(reason) => {
+            panic!("Expected OFFER, got NoResponse({:?})", reason)
+        }
+    };
+
+    let routes = reply.opts().iter().find_map(|(_, opt)| match opt {
+        DhcpOption::StaticRoutingTable(routes) => Some(routes.clone()),
+        _ => None,
+    });
+    assert_eq!(routes, Some(vec![(Ipv4Addr::new(10, 0, 0, 0), gateway)]));
+    assert!(!reply
+        .opts()
+        .iter()
+        .any(|(_, opt)| matches!(opt, DhcpOption::ClasslessStaticRoute(_))));
+}
+
+#[test]
+fn event_records_client_fingerprint() {
+    let mac = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+    let mut msg = create_discover(mac, 0x1234abcd);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::Hostname("cpe-lab".to_string()));
+    opts.insert(DhcpOption::ClassIdentifier(b"MSFT 5.0".to_vec()));
+    opts.insert(DhcpOption::ParameterRequestList(vec![
+        v4::OptionCode::SubnetMask,
+        v4::OptionCode::Router,
+        v4::OptionCode::DomainNameServer,
+        v4::OptionCode::DomainName,
+    ]));
+
+    let event = crate::analytics::events::DhcpEventV4::failed(
+        &msg,
+        TEST_RELAY_IP,
+        NoResponse::NoReservation,
+    );
+    assert_eq!(event.xid, "1234abcd");
+    assert_eq!(event.client_hostname.as_deref(), Some("cpe-lab"));
+    assert_eq!(event.vendor_class.as_deref(), Some("MSFT 5.0"));
+    assert_eq!(event.prl_fingerprint.as_deref(), Some("1,3,6,15"));
+    assert_eq!(event.device_class, crate::fingerprint::v4(&msg));
+    assert!(event.device_class.is_some());
+}
+
+#[test]
+fn event_records_binary_option82_as_hex() {
+    let mut msg = create_discover(TEST_MAC, 0x1234abcd);
+    let mut relay_info = dhcproto::v4::relay::RelayAgentInformation::default();
+    // Cisco binary circuit-id: VLAN 100, module 1, port 255
+    relay_info.insert(dhcproto::v4::relay::RelayInfo::AgentCircuitId(vec![
+        0x00, 0x04, 0x00, 0x64, 0x01, 0xff,
+    ]));
+    relay_info.insert(dhcproto::v4::relay::RelayInfo::AgentRemoteId(
+        b"switch1".to_vec(),
+    ));
+    msg.opts_mut()
+        .insert(DhcpOption::RelayAgentInformation(relay_info));
+
+    let event = crate::analytics::events::DhcpEventV4::failed(
+        &msg,
+        TEST_RELAY_IP,
+        NoResponse::NoReservation,
+    );
+    assert_eq!(event.option82_circuit, None);
+    assert_eq!(event.option82_circuit_hex.as_deref(), Some("0004006401ff"));
+    assert_eq!(event.option82_remote.as_deref(), Some("switch1"));
+    assert_eq!(event.option82_remote_hex, None);
+    assert_eq!(event.option82_subscriber_hex, None);
+}
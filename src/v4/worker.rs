@@ -1,178 +1,612 @@
 use arc_swap::ArcSwap;
-use dhcproto::{v4, Decodable, Encodable};
+use dhcproto::error::{DecodeError, EncodeError};
+use dhcproto::{v4, Decodable};
 use std::{
     io,
-    net::{SocketAddr, UdpSocket},
-    sync::Arc,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::{mpsc, Arc},
     time::Duration,
 };
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
+use crate::audit::{self, AuditRecord};
+use crate::capture::{chaddr, Direction, PacketCapture};
 use crate::config::Config;
+use crate::conflict::{ConflictDetector, Probe};
+use crate::dump::V4Dump;
+use crate::ha::PeerMonitor;
+use crate::health::{Health, ProtocolHealth, RecvAction};
+use crate::history::Transaction;
 use crate::opt82_cache::Opt82Cache;
+use crate::rate_limit::{RateLimiter, Verdict};
+use crate::relays::RelayInventory;
+use crate::reply_source::ReplySource;
 use crate::reservationdb::ReservationDb;
+use crate::retransmit::RetransmitCache;
 use crate::shutdown::Shutdown;
 
 use advmac::MacAddr6;
 
 use crate::{
     analytics::{
-        events::{DhcpEvent, DhcpEventV4},
-        EventSenders,
+        events::{DhcpEvent, DhcpEventV4, PacketTiming, ReservationMatch},
+        socket_queue_bytes, EventSenders,
     },
     v4::extensions::{RelayAgentInformationExt, ShadowMessageExtV4},
-    v4::handlers::{handle_message, DhcpV4Response, NoResponse, ResponseMessage},
+    v4::handlers::{
+        handle_message, reply_destination, DhcpV4Response, NoResponse, ResponseMessage,
+    },
+    v4::size::encode_within_limit,
 };
 
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+/// Socket wait while packets are parked on conflict probes
+const PROBE_POLL: Duration = Duration::from_millis(10);
+
+#[allow(clippy::too_many_arguments)]
 pub fn v4_worker(
     socket: UdpSocket,
     reservations: Arc<ArcSwap<ReservationDb>>,
     leases: Arc<Opt82Cache>,
     config: Arc<ArcSwap<Config>>,
     event_channel: Option<EventSenders>,
+    audit_channel: Option<mpsc::SyncSender<AuditRecord>>,
+    capture: Arc<PacketCapture>,
+    ha: Option<Arc<PeerMonitor>>,
+    reply_source: Option<ReplySource>,
+    health: Arc<Health>,
     shutdown: Shutdown,
-) {
+) -> io::Result<()> {
+    let _running = health.v4.worker_running();
     let mut read_buf = [0u8; 2048];
+    // Replies are encoded into this so sending doesn't allocate
+    let mut write_buf = Vec::with_capacity(read_buf.len());
     let mut error_count: u32 = 0;
-    const MAX_BACKOFF_MS: u64 = 1000;
 
     // Wake once per second so the shutdown flag is noticed promptly.
     socket
-        .set_read_timeout(Some(Duration::from_secs(1)))
+        .set_read_timeout(Some(RECV_TIMEOUT))
         .expect("set v4 socket read timeout");
+    // Needed for the broadcast cases of RFC 2131 §4.1 reply addressing.
+    if let Err(e) = socket.set_broadcast(true) {
+        error!("Unable to enable SO_BROADCAST on v4 socket: {e}");
+    }
+
+    let (mut client_limiter, mut relay_limiter) = match &config.load().rate_limit {
+        Some(cfg) => (
+            Some(RateLimiter::<MacAddr6>::client("DHCPv4 client", cfg)),
+            RateLimiter::<IpAddr>::relay("DHCPv4 relay", cfg),
+        ),
+        None => (None, None),
+    };
+    let mut conflicts = config
+        .load()
+        .conflict_detection
+        .as_ref()
+        .map(ConflictDetector::new);
+    let mut retransmits = RetransmitCache::new(config.load().retransmit_window_ms);
+    let mut polling_probes = false;
 
     loop {
         if shutdown.is_signalled() {
             info!("v4 worker shutting down");
-            return;
+            return Ok(());
+        }
+        if let Some(limiter) = client_limiter.as_mut() {
+            limiter.sweep();
         }
-        let (amount, src) = match socket.recv_from(&mut read_buf) {
+        if let Some(limiter) = relay_limiter.as_mut() {
+            limiter.sweep();
+        }
+        if let Some(cache) = retransmits.as_mut() {
+            cache.sweep();
+        }
+        // Keep the wait on the socket short while packets are parked on
+        // conflict probes, so they go out soon after the probe finishes
+        let waiting = conflicts.as_ref().is_some_and(ConflictDetector::is_waiting);
+        if waiting != polling_probes {
+            let timeout = if waiting { PROBE_POLL } else { RECV_TIMEOUT };
+            if let Err(e) = socket.set_read_timeout(Some(timeout)) {
+                error!("Unable to set v4 socket read timeout: {e}");
+            }
+            polling_probes = waiting;
+        }
+        let parked = conflicts.as_mut().and_then(ConflictDetector::next_ready);
+        // A parked packet was already counted, captured and rate limited
+        // when it arrived
+        let replayed = parked.is_some();
+        let received = match parked {
+            Some((packet, src)) => {
+                read_buf[..packet.len()].copy_from_slice(&packet);
+                Ok((packet.len(), src))
+            }
+            None => socket.recv_from(&mut read_buf),
+        };
+        let (amount, src) = match received {
             Ok((amount, src)) => {
                 error_count = 0;
-                debug!("Received {amount} bytes from {src:?}");
-                trace!("Data: {:x?}", &read_buf[..amount]);
+                if !replayed {
+                    health.v4.record_packet();
+                    debug!("Received {amount} bytes from {src:?}");
+                    trace!("Data: {:x?}", &read_buf[..amount]);
+                }
                 (amount, src)
             }
             Err(err) => {
-                match err.kind() {
-                    // Read-timeout expiry: WouldBlock on Unix, TimedOut on Windows
-                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {}
-                    io::ErrorKind::ConnectionReset => {
-                        debug!("Sent response to host that responded with ICMP unreachable");
-                    }
-                    io::ErrorKind::Interrupted => {
-                        debug!("recv_from interrupted, retrying");
-                    }
-                    _ => {
+                match health.v4.recv_failed(&err, &mut error_count) {
+                    RecvAction::Retry => match err.kind() {
+                        io::ErrorKind::ConnectionReset => {
+                            debug!("Sent response to host that responded with ICMP unreachable");
+                        }
+                        io::ErrorKind::Interrupted => {
+                            debug!("recv_from interrupted, retrying");
+                        }
+                        _ => {}
+                    },
+                    RecvAction::Backoff(delay) => {
                         error!("Unexpected socket error: {err:?}");
-                        // Apply exponential backoff to prevent CPU spin on persistent errors
-                        error_count = error_count.saturating_add(1);
-                        let backoff_ms = std::cmp::min(
-                            10_u64.saturating_mul(2_u64.saturating_pow(error_count)),
-                            MAX_BACKOFF_MS,
-                        );
-                        std::thread::sleep(Duration::from_millis(backoff_ms));
+                        std::thread::sleep(delay);
+                    }
+                    RecvAction::Rebind => {
+                        error!("DHCPv4 socket unusable, rebinding: {err:?}");
+                        return Err(err);
                     }
                 }
                 continue;
             }
         };
+        let mut timing = PacketTiming::received(socket_queue_bytes(&socket));
+
+        if let Some(ha) = &ha {
+            if !ha.should_answer() {
+                trace!("HA standby, ignoring packet from {src}");
+                continue;
+            }
+        }
+
+        if let Some(limiter) = relay_limiter.as_mut().filter(|_| !replayed) {
+            if limiter.check(&src.ip()) != Verdict::Allow {
+                trace!(reason = %NoResponse::RateLimited, "Rate limited packet from relay {src}");
+                continue;
+            }
+        }
 
         let relay_addr = match src {
             SocketAddr::V4(v4) => Some(*v4.ip()),
             SocketAddr::V6(_) => None,
         };
 
-        match v4::Message::from_bytes(&read_buf[..amount]) {
-            Ok(msg) => match handle_message(&reservations.load(), &leases, &config.load(), &msg) {
-                DhcpV4Response::NoResponse(reason) => {
-                    if !matches!(reason, NoResponse::NoReservation) {
-                        debug!("Not responding {:?}", reason);
-                    } else if tracing::enabled!(tracing::Level::INFO) {
-                        let relay_info = msg.relay_agent_information();
-                        let circuit = relay_info
-                            .and_then(|r| r.circuit_id())
-                            .map(|b| String::from_utf8_lossy(&b).into_owned());
-                        let remote = relay_info
-                            .and_then(|r| r.remote_id())
-                            .map(|b| String::from_utf8_lossy(&b).into_owned());
-                        let subscriber = relay_info
-                            .and_then(|r| r.subscriber_id())
-                            .map(|b| String::from_utf8_lossy(&b).into_owned());
-                        let mac = MacAddr6::try_from(msg.chaddr()).ok().map(|m| m.to_string());
-                        info!(
-                            mac = mac.as_deref(),
-                            circuit = circuit.as_deref(),
-                            remote = remote.as_deref(),
-                            subscriber = subscriber.as_deref(),
-                            relay = %msg.giaddr(),
-                            xid = msg.xid(),
-                            "DHCPv4: no reservation found — not responding"
-                        );
-                    }
-                    if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
-                        let event = DhcpEventV4::failed(&msg, relay_addr, reason.as_str());
-                        sinks.send(DhcpEvent::V4(event));
+        let history_depth = config.load().lease_history;
+
+        // Before parsing, so packets dhcproto rejects are captured too
+        if !replayed {
+            capture.record(
+                chaddr(&read_buf[..amount]),
+                Direction::Rx,
+                src,
+                &read_buf[..amount],
+            );
+        }
+
+        let parsed = decode(
+            &read_buf[..amount],
+            src,
+            (!replayed).then_some(&health.relays),
+        );
+
+        match parsed {
+            Ok(msg) if !replayed && is_rate_limited(&mut client_limiter, &msg) => {
+                trace!(xid = msg.xid(), reason = %NoResponse::RateLimited, "Rate limited DHCPv4 client");
+                let outcome = NoResponse::RateLimited.as_str();
+                record_history(&health.v4, &leases, history_depth, &msg, src, outcome, None);
+            }
+            Ok(msg) => {
+                let cache_key = retransmit_key(&msg);
+                if let Some((reply, dest)) = retransmits
+                    .as_ref()
+                    .zip(cache_key.as_ref())
+                    .and_then(|(cache, key)| cache.get(key))
+                {
+                    match crate::reply_source::send(&socket, reply_source.as_ref(), reply, dest) {
+                        Ok(sent) => {
+                            debug!(
+                                xid = msg.xid(),
+                                "resent cached reply to {dest} with {sent} bytes"
+                            );
+                            capture.record(
+                                MacAddr6::try_from(msg.chaddr()).ok(),
+                                Direction::Tx,
+                                dest,
+                                reply,
+                            );
+                        }
+                        Err(e) => error!("Problem resending cached response message: {e}"),
                     }
+                    continue;
                 }
-                DhcpV4Response::Message(resp) => {
-                    let write_buf = match resp.message.to_vec() {
-                        Ok(buf) => buf,
-                        Err(e) => {
-                            error!("Failed to encode DHCPv4 response: {e}");
+
+                match timing
+                    .handle(|| handle_message(&reservations.load(), &leases, &config.load(), &msg))
+                {
+                    DhcpV4Response::NoResponse(reason) => {
+                        let reason = crate::fallback::forward_v4(
+                            &socket,
+                            &config.load(),
+                            reason,
+                            &read_buf[..amount],
+                        );
+                        record_history(
+                            &health.v4,
+                            &leases,
+                            history_depth,
+                            &msg,
+                            src,
+                            reason.as_str(),
+                            None,
+                        );
+                        if !reason.is_unknown_client() {
+                            debug!("Not responding {:?}", reason);
+                        } else if tracing::enabled!(tracing::Level::INFO) {
+                            let relay_info = msg.relay_agent_information();
+                            let circuit = relay_info
+                                .and_then(|r| r.circuit_id())
+                                .map(|b| String::from_utf8_lossy(&b).into_owned());
+                            let remote = relay_info
+                                .and_then(|r| r.remote_id())
+                                .map(|b| String::from_utf8_lossy(&b).into_owned());
+                            let subscriber = relay_info
+                                .and_then(|r| r.subscriber_id())
+                                .map(|b| String::from_utf8_lossy(&b).into_owned());
+                            let mac = MacAddr6::try_from(msg.chaddr()).ok().map(|m| m.to_string());
+                            info!(
+                                mac = mac.as_deref(),
+                                circuit = circuit.as_deref(),
+                                remote = remote.as_deref(),
+                                subscriber = subscriber.as_deref(),
+                                relay = %msg.giaddr(),
+                                xid = msg.xid(),
+                                "DHCPv4: no reservation found — not responding"
+                            );
+                        }
+                        if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
+                            let event = DhcpEventV4::failed(&msg, relay_addr, reason);
+                            sinks.send(DhcpEvent::V4(event.with_timing(&timing)));
+                        }
+                    }
+                    DhcpV4Response::Message(mut resp) => {
+                        let found = resp.reservation_match.as_ref();
+                        let probe =
+                            offer_conflict(&mut conflicts, &msg, &resp, &read_buf[..amount], src);
+                        if probe == Some(Probe::Pending) {
+                            // Parked; handled again once the probe finishes
+                            continue;
+                        }
+                        if let Some(probe) = probe {
+                            record_history(
+                                &health.v4,
+                                &leases,
+                                history_depth,
+                                &msg,
+                                src,
+                                probe.as_str(),
+                                found,
+                            );
                             if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
                                 let event = DhcpEventV4::send_failed(
                                     &msg,
                                     relay_addr,
                                     resp.reservation.as_deref(),
-                                    resp.reservation_match,
-                                    "EncodeFailed",
+                                    found,
+                                    probe.as_str(),
                                 );
-                                sinks.send(DhcpEvent::V4(event));
+                                sinks.send(DhcpEvent::V4(event.with_timing(&timing)));
                             }
                             continue;
                         }
-                    };
-                    match socket.send_to(&write_buf, src) {
-                        Ok(sent) => {
-                            debug!("responded to {src} with {sent} bytes");
-                            log_send_outcome(&msg, &resp);
+                        let config = config.load();
+                        let conflict_holder = lease_conflict(&leases, &config, &msg, &resp);
+                        if conflict_holder.is_some() && config.refuse_lease_conflicts {
+                            record_history(
+                                &health.v4,
+                                &leases,
+                                history_depth,
+                                &msg,
+                                src,
+                                "LeaseConflict",
+                                found,
+                            );
                             if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
-                                let event = DhcpEventV4::success(
+                                let mut event = DhcpEventV4::send_failed(
                                     &msg,
                                     relay_addr,
                                     resp.reservation.as_deref(),
-                                    resp.reservation_match,
+                                    found,
+                                    "LeaseConflict",
                                 );
-                                sinks.send(DhcpEvent::V4(event));
+                                event.lease_conflict_mac = conflict_holder;
+                                sinks.send(DhcpEvent::V4(event.with_timing(&timing)));
                             }
+                            continue;
                         }
-                        Err(e) => {
-                            error!("Problem sending response message: {e}");
-                            if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
-                                let event = DhcpEventV4::send_failed(
+                        let dest = match encode_reply(&msg, &mut resp.message, &mut write_buf) {
+                            Ok(dest) => dest,
+                            Err(e) => {
+                                error!("Failed to encode DHCPv4 response: {e}");
+                                record_history(
+                                    &health.v4,
+                                    &leases,
+                                    history_depth,
                                     &msg,
-                                    relay_addr,
-                                    resp.reservation.as_deref(),
-                                    resp.reservation_match,
+                                    src,
+                                    "EncodeFailed",
+                                    found,
+                                );
+                                if let (Some(sinks), Some(relay_addr)) =
+                                    (&event_channel, relay_addr)
+                                {
+                                    let event = DhcpEventV4::send_failed(
+                                        &msg,
+                                        relay_addr,
+                                        resp.reservation.as_deref(),
+                                        found,
+                                        "EncodeFailed",
+                                    );
+                                    sinks.send(DhcpEvent::V4(event.with_timing(&timing)));
+                                }
+                                continue;
+                            }
+                        };
+                        match crate::reply_source::send(
+                            &socket,
+                            reply_source.as_ref(),
+                            &write_buf,
+                            dest.into(),
+                        ) {
+                            Ok(sent) => {
+                                debug!("responded to {dest} with {sent} bytes");
+                                capture.record(
+                                    MacAddr6::try_from(msg.chaddr()).ok(),
+                                    Direction::Tx,
+                                    dest.into(),
+                                    &write_buf,
+                                );
+                                if let (Some(cache), Some(key)) = (retransmits.as_mut(), cache_key)
+                                {
+                                    cache.insert(key, &write_buf, dest.into());
+                                }
+                                log_send_outcome(&msg, &resp);
+                                // Only BOOTP replies go out without a message type
+                                let outcome = resp
+                                    .message
+                                    .message_type()
+                                    .map_or("BootReply", DhcpEventV4::message_type_str);
+                                record_history(
+                                    &health.v4,
+                                    &leases,
+                                    history_depth,
+                                    &msg,
+                                    src,
+                                    outcome,
+                                    found,
+                                );
+                                if let Some(found) = found {
+                                    health.v4.record_match(found);
+                                }
+                                let audit_record = audit_channel.as_ref().and_then(|_| {
+                                    AuditRecord::v4(
+                                        &msg,
+                                        &resp.message,
+                                        src.ip(),
+                                        resp.reservation.as_deref(),
+                                    )
+                                });
+                                audit::record(&audit_channel, audit_record);
+                                if let (Some(sinks), Some(relay_addr)) =
+                                    (&event_channel, relay_addr)
+                                {
+                                    let event =
+                                        reply_event(&msg, relay_addr, &resp, conflict_holder);
+                                    sinks.send(DhcpEvent::V4(event.with_timing(&timing)));
+                                }
+                            }
+                            Err(e) => {
+                                error!("Problem sending response message: {e}");
+                                record_history(
+                                    &health.v4,
+                                    &leases,
+                                    history_depth,
+                                    &msg,
+                                    src,
                                     "SendFailed",
+                                    found,
                                 );
-                                sinks.send(DhcpEvent::V4(event));
+                                if let (Some(sinks), Some(relay_addr)) =
+                                    (&event_channel, relay_addr)
+                                {
+                                    let event = DhcpEventV4::send_failed(
+                                        &msg,
+                                        relay_addr,
+                                        resp.reservation.as_deref(),
+                                        found,
+                                        "SendFailed",
+                                    );
+                                    sinks.send(DhcpEvent::V4(event.with_timing(&timing)));
+                                }
                             }
                         }
                     }
                 }
-            },
+            }
             Err(e) => {
                 error!("Unable to parse dhcpv4 message {}", e);
                 if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
-                    sinks.send(DhcpEvent::V4(DhcpEventV4::parse_error(relay_addr)));
+                    let event = DhcpEventV4::parse_error(relay_addr);
+                    sinks.send(DhcpEvent::V4(event.with_timing(&timing)));
                 }
             }
         }
     }
 }
 
+/// Decode a datagram from `src`, adding it to `relays` if given. A parked
+/// packet was already counted when it first arrived.
+pub(crate) fn decode(
+    packet: &[u8],
+    src: SocketAddr,
+    relays: Option<&RelayInventory>,
+) -> Result<v4::Message, DecodeError> {
+    let msg = v4::Message::from_bytes(packet)?;
+    if let Some(relays) = relays {
+        relays.record_v4(&msg, src.ip());
+    }
+    trace!("Received from {src}:\n{}", V4Dump(&msg));
+    Ok(msg)
+}
+
+/// Encode the reply to `msg` into `buf`; returns where to send it
+pub(crate) fn encode_reply(
+    msg: &v4::Message,
+    reply: &mut v4::Message,
+    buf: &mut Vec<u8>,
+) -> Result<SocketAddrV4, EncodeError> {
+    encode_within_limit(msg, reply, buf)?;
+    let dest = reply_destination(msg, reply);
+    trace!("Replying to {dest}:\n{}", V4Dump(reply));
+    Ok(dest)
+}
+
+/// The analytics event for a reply that was sent
+pub(crate) fn reply_event(
+    msg: &v4::Message,
+    relay_addr: Ipv4Addr,
+    resp: &ResponseMessage,
+    conflict_holder: Option<MacAddr6>,
+) -> DhcpEventV4 {
+    // A NAK without a reservation is the authoritative unknown-client
+    // answer; report it as NoReservation.
+    if resp.reservation.is_none() {
+        return DhcpEventV4::failed(msg, relay_addr, NoResponse::NoReservation);
+    }
+    let mut event = DhcpEventV4::success(
+        msg,
+        relay_addr,
+        resp.reservation.as_deref(),
+        resp.reservation_match.as_ref(),
+    );
+    event.lease_conflict_mac = conflict_holder;
+    event
+}
+
+/// Retransmissions carry the same xid and chaddr. Only Discovers are
+/// answered from the cache; a Request is handled again each time.
+fn retransmit_key(msg: &v4::Message) -> Option<(u32, MacAddr6)> {
+    if msg.message_type() != Some(&v4::MessageType::Discover) {
+        return None;
+    }
+    Some((msg.xid(), MacAddr6::try_from(msg.chaddr()).ok()?))
+}
+
+/// Count the outcome and keep the transaction in the history of the client
+/// with this chaddr
+fn record_history(
+    health: &ProtocolHealth,
+    leases: &Opt82Cache,
+    depth: usize,
+    msg: &v4::Message,
+    src: SocketAddr,
+    outcome: &'static str,
+    found: Option<&ReservationMatch>,
+) {
+    health.record_outcome(outcome);
+    if depth == 0 {
+        return;
+    }
+    if let Ok(mac) = MacAddr6::try_from(msg.chaddr()) {
+        let transaction = Transaction::v4(msg, src.ip(), outcome, found);
+        leases.history().record_v4(depth, mac, transaction);
+    }
+}
+
+/// Per-client limit keyed by chaddr. Messages without a usable MAC are
+/// left to the relay limit.
+fn is_rate_limited(limiter: &mut Option<RateLimiter<MacAddr6>>, msg: &v4::Message) -> bool {
+    let Some(limiter) = limiter.as_mut() else {
+        return false;
+    };
+    match MacAddr6::try_from(msg.chaddr()) {
+        Ok(mac) => limiter.check(&mac) != Verdict::Allow,
+        Err(_) => false,
+    }
+}
+
+/// Probe the address of an OFFER; returns the probe result if the offer
+/// must be withheld, or `Pending` if `packet` was parked until the probe
+/// finishes.
+fn offer_conflict(
+    detector: &mut Option<ConflictDetector>,
+    msg: &v4::Message,
+    resp: &ResponseMessage,
+    packet: &[u8],
+    src: SocketAddr,
+) -> Option<Probe> {
+    let detector = detector.as_mut()?;
+    if resp.message.message_type() != Some(&v4::MessageType::Offer) {
+        return None;
+    }
+    let ip = resp.message.yiaddr();
+    let probe = detector.check(ip, packet, src);
+    match probe {
+        Probe::Free => return None,
+        Probe::Pending => {
+            trace!(%ip, xid = msg.xid(), "DHCPv4: waiting on conflict probe before offering");
+            return Some(probe);
+        }
+        Probe::InUse | Probe::Quarantined => {}
+    }
+    let mac = MacAddr6::try_from(msg.chaddr()).ok().map(|m| m.to_string());
+    warn!(
+        mac = mac.as_deref(),
+        %ip,
+        relay = %msg.giaddr(),
+        xid = msg.xid(),
+        reason = probe.as_str(),
+        "DHCPv4: reserved address answered ping — not offering"
+    );
+    Some(probe)
+}
+
+/// Check the address of an ACK against the client last leased it. Returns
+/// that client if it differs and its lease hasn't run out yet, which
+/// usually means a cloned MAC or two CPEs behind one OLT port.
+pub(crate) fn lease_conflict(
+    leases: &Opt82Cache,
+    config: &Config,
+    msg: &v4::Message,
+    resp: &ResponseMessage,
+) -> Option<MacAddr6> {
+    let lease = resp.lease?;
+    let reservation = resp.reservation.as_deref()?;
+    let ip = resp.message.yiaddr();
+    let mac = MacAddr6::try_from(msg.chaddr()).ok()?;
+    let holder = leases.claim_ipv4(
+        ip,
+        mac,
+        reservation.subscriber_id.as_ref(),
+        lease,
+        !config.refuse_lease_conflicts,
+    )?;
+    warn!(
+        %mac,
+        %holder,
+        %ip,
+        relay = %msg.giaddr(),
+        xid = msg.xid(),
+        refused = config.refuse_lease_conflicts,
+        "DHCPv4: reserved address claimed by a second client before the holder's lease expired"
+    );
+    Some(holder)
+}
+
 /// One human-readable line per sent transaction, logged at the send path so
 /// it reflects what actually went out on the wire.
 fn log_send_outcome(msg: &v4::Message, resp: &ResponseMessage) {
@@ -184,7 +618,7 @@ fn log_send_outcome(msg: &v4::Message, resp: &ResponseMessage) {
         Some(v4::MessageType::Offer) => info!(
             mac = mac.as_deref(),
             ip = %resp.message.yiaddr(),
-            method = resp.reservation_match.map(|m| m.method),
+            method = resp.reservation_match.as_ref().map(|m| m.method),
             relay = %msg.giaddr(),
             xid = msg.xid(),
             "DHCPv4 lease offered"
@@ -192,17 +626,38 @@ fn log_send_outcome(msg: &v4::Message, resp: &ResponseMessage) {
         Some(v4::MessageType::Ack) => info!(
             mac = mac.as_deref(),
             ip = %resp.message.yiaddr(),
-            method = resp.reservation_match.map(|m| m.method),
+            method = resp.reservation_match.as_ref().map(|m| m.method),
             relay = %msg.giaddr(),
             xid = msg.xid(),
             "DHCPv4 lease acknowledged"
         ),
+        Some(v4::MessageType::Nak) if resp.reservation.is_none() => info!(
+            mac = mac.as_deref(),
+            relay = %msg.giaddr(),
+            xid = msg.xid(),
+            "DHCPv4 NAK sent — no reservation on authoritative subnet"
+        ),
+        Some(v4::MessageType::Nak) if resp.reservation.as_ref().is_some_and(|r| r.suspended) => {
+            info!(
+                mac = mac.as_deref(),
+                relay = %msg.giaddr(),
+                xid = msg.xid(),
+                "DHCPv4 NAK sent — reservation suspended"
+            )
+        }
         Some(v4::MessageType::Nak) => info!(
             mac = mac.as_deref(),
             relay = %msg.giaddr(),
             xid = msg.xid(),
             "DHCPv4 NAK sent — requested address does not match reservation"
         ),
+        None => info!(
+            mac = mac.as_deref(),
+            ip = %resp.message.yiaddr(),
+            relay = %msg.giaddr(),
+            xid = msg.xid(),
+            "BOOTP reply sent"
+        ),
         _ => {}
     }
 }
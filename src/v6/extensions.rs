@@ -3,10 +3,14 @@ use std::net::Ipv6Addr;
 use crate::types::Option1837;
 use advmac::MacAddr6;
 use compact_str::CompactString;
-use dhcproto::v6::{DhcpOption, Message, RelayMessage, IANA, IAPD};
+use dhcproto::v6::{DhcpOption, Message, OptionCode, RelayMessage, IANA, IAPD};
+use dhcproto::Encodable;
 use ipnet::Ipv6Net;
 use tracing::debug;
 
+/// Option Request (RFC 8415 §21.7)
+const OPTION_ORO: u16 = 6;
+
 /// Helpers for interacting with dhcproto::v6::Message
 pub trait ShadowMessageExtV6 {
     fn client_id(&self) -> Option<&[u8]>;
@@ -18,6 +22,14 @@ pub trait ShadowMessageExtV6 {
     fn ia_na_address(&self) -> Option<Ipv6Addr>;
     #[allow(unused)]
     fn ia_pd_prefix(&self) -> Option<Ipv6Net>;
+    /// Client hinted at an IA_NA address other than `reserved`
+    fn ia_na_hint_differs(&self, reserved: Ipv6Addr) -> bool;
+    /// Client hinted at an IA_PD prefix, or only a prefix length, other than
+    /// `reserved`
+    fn ia_pd_hint_differs(&self, reserved: Ipv6Net) -> bool;
+    /// Option codes listed in the client's Option Request (RFC 8415
+    /// §21.7), `None` when it sent none
+    fn requested_options(&self) -> Option<Vec<u16>>;
 }
 
 /// Helpers for interacting with dhcproto::v6::RelayMessage
@@ -79,6 +91,48 @@ impl ShadowMessageExtV6 for Message {
             })
         })
     }
+
+    fn ia_na_hint_differs(&self, reserved: Ipv6Addr) -> bool {
+        self.ia_na_address()
+            .is_some_and(|addr| !addr.is_unspecified() && addr != reserved)
+    }
+
+    fn ia_pd_hint_differs(&self, reserved: Ipv6Net) -> bool {
+        let Some(hint) = self.ia_pd().and_then(|pd| {
+            pd.opts.iter().find_map(|opt| match opt {
+                DhcpOption::IAPrefix(ia) => Some(ia),
+                _ => None,
+            })
+        }) else {
+            return false;
+        };
+        // RFC 8415 §18.2.1: a hint of :: with a length asks for a prefix of
+        // that size, anywhere
+        if hint.prefix_ip.is_unspecified() {
+            return hint.prefix_len != 0 && hint.prefix_len != reserved.prefix_len();
+        }
+        Ipv6Net::new(hint.prefix_ip, hint.prefix_len)
+            .ok()
+            .map(|n| n.trunc())
+            != Some(reserved.trunc())
+    }
+
+    fn requested_options(&self) -> Option<Vec<u16>> {
+        let oro = self
+            .opts()
+            .iter()
+            .find(|opt| u16::from(OptionCode::from(*opt)) == OPTION_ORO)?;
+        // Read off the wire form, two-byte code and length then the codes,
+        // so it doesn't matter how dhcproto decoded it
+        let encoded = oro.to_vec().ok()?;
+        Some(
+            encoded
+                .get(4..)?
+                .chunks_exact(2)
+                .map(|code| u16::from_be_bytes([code[0], code[1]]))
+                .collect(),
+        )
+    }
 }
 
 impl ShadowRelayMessageExtV6 for RelayMessage {
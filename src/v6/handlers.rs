@@ -1,17 +1,30 @@
+use std::net::{Ipv6Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::types::Reservation;
+use crate::types::{Duid, Reservation};
 use dhcproto::v6::{
-    DhcpOption, DhcpOptions, IAAddr, IAPrefix, Message, MessageType, RelayMessage, IANA, IAPD,
+    DhcpOption, DhcpOptions, Message, MessageType, OptionCode, RelayMessage, RelayMessageData,
+    Status, StatusCode,
 };
+use dhcproto::Encodable;
 
 use crate::analytics::events::ReservationMatch;
-use crate::config::Config;
+use crate::config::{Config, Profile};
 use crate::opt82_cache::Opt82Cache;
 use crate::reservationdb::ReservationDb;
-use tracing::debug;
+use tracing::{debug, info, warn};
 
-use crate::v6::{extensions::ShadowMessageExtV6, reservation::find_reservation};
+pub use crate::outcome::NoResponse;
+
+use crate::v6::{
+    extensions::ShadowMessageExtV6,
+    reservation::find_reservation,
+    response::{
+        build_no_addrs_ia_na, build_no_binding, build_no_prefix_ia_pd, insert_config_options,
+        insert_hint_notes, insert_inappropriate_leases, insert_reserved_ias, insert_unbound_ias,
+    },
+};
 
 /// A DHCPv6 response message produced by the server.
 ///
@@ -21,31 +34,9 @@ pub struct ResponseMessage {
     pub message: Message,
     pub reservation: Option<Arc<Reservation>>,
     pub reservation_match: Option<ReservationMatch>,
-}
-
-#[derive(Debug, Copy, Clone)]
-pub enum NoResponse {
-    NoClientId,
-    InvalidClientId,
-    UnexpectedServerId,
-    WrongServerId,
-    NoServerId,
-    NoReservation,
-    Discarded,
-}
-
-impl NoResponse {
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            NoResponse::NoClientId => "NoClientId",
-            NoResponse::InvalidClientId => "InvalidClientId",
-            NoResponse::UnexpectedServerId => "UnexpectedServerId",
-            NoResponse::WrongServerId => "WrongServerId",
-            NoResponse::NoServerId => "NoServerId",
-            NoResponse::NoReservation => "NoReservation",
-            NoResponse::Discarded => "Discarded",
-        }
-    }
+    /// Valid lifetime of the reserved IA_NA address, on a Reply that
+    /// leases it
+    pub lease: Option<Duration>,
 }
 
 /// Result of processing an incoming DHCPv6 message.
@@ -59,6 +50,7 @@ pub enum DhcpV6Response {
 
 fn handle_solicit(
     config: &Config,
+    profile: &Profile,
     reservations: &ReservationDb,
     leases: &Opt82Cache,
     msg: &Message,
@@ -69,7 +61,7 @@ fn handle_solicit(
     let client_id = match msg.client_id() {
         Some(bytes) => match crate::types::Duid::new(bytes.to_vec()) {
             Some(duid) => duid,
-            None => return DhcpV6Response::NoResponse(NoResponse::InvalidClientId),
+            None => return DhcpV6Response::NoResponse(NoResponse::MalformedOption),
         },
         None => return DhcpV6Response::NoResponse(NoResponse::NoClientId),
     };
@@ -80,10 +72,15 @@ fn handle_solicit(
     }
 
     // Rapid Commit option - The client may request the expedited two-message exchange
-    // by adding the Rapid Commit option to the first Solicit request
-    let msg_type = if msg.rapid_commit() {
+    // by adding the Rapid Commit option to the first Solicit request. Servers
+    // only honour it when configured to (RFC 8415 §18.3.1), since with several
+    // servers answering each would commit addresses to the client
+    let msg_type = if msg.rapid_commit() && config.rapid_commit_enabled {
         debug!("Solicit 2 message exchange, rapid commit");
         MessageType::Reply
+    } else if msg.rapid_commit() {
+        debug!("Solicit requested rapid commit but it is disabled, 4 message exchange");
+        MessageType::Advertise
     } else {
         debug!("Solicit 4 message exchange");
         MessageType::Advertise
@@ -98,7 +95,21 @@ fn handle_solicit(
         msg,
         &client_id,
     );
+    let profile = &reserved_address
+        .as_ref()
+        .map_or(*profile, |(reservation, _)| {
+            profile.with_reservation(config, reservation)
+        });
     match reserved_address {
+        Some((reservation, match_info)) if reservation.suspended => {
+            debug!(%client_id, "reservation suspended, advertising no addresses");
+            DhcpV6Response::Message(ResponseMessage {
+                message: no_addrs_advertise(config, msg, client_id),
+                reservation: Some(reservation),
+                reservation_match: Some(match_info),
+                lease: None,
+            })
+        }
         Some((reservation, match_info)) => {
             let mut reply = Message::new_with_id(msg_type, msg.xid());
             let opts = reply.opts_mut();
@@ -107,68 +118,94 @@ fn handle_solicit(
                 // client requested rapid commit
                 // https://datatracker.ietf.org/doc/html/rfc8415#section-21.14
                 opts.insert(DhcpOption::RapidCommit)
-            } else {
-                // RFC 8415 Section 21.8: Advertise messages should include a Preference option
-                // Value 255 is the maximum preference, causing client to use this server immediately
-                opts.insert(DhcpOption::Preference(255));
+            } else if let Some(preference) = config.v6_preference {
+                // RFC 8415 Section 21.8: 255 makes the client use this server
+                // immediately, anything lower lets it wait for other Advertises
+                opts.insert(DhcpOption::Preference(preference));
             }
 
-            // Reply contains IA_NA address and IA_PD prefix as options.
-            // These options contain nested options with the actual addresses/prefixes
-            // ReplyOptions [IAPD[IAPrefix], IANA[IAAddr]]
-
-            // construct IA_PD information
-            if let Some(iapd) = msg.ia_pd() {
-                let mut ia_pd_opts = DhcpOptions::new();
-                ia_pd_opts.insert(DhcpOption::IAPrefix(IAPrefix {
-                    preferred_lifetime: config.lease_times.v6_preferred,
-                    valid_lifetime: config.lease_times.v6_valid,
-                    prefix_len: reservation.ipv6_pd.prefix_len(),
-                    prefix_ip: reservation.ipv6_pd.addr(),
-                    opts: DhcpOptions::new(),
-                }));
-                // add IA_PD information to Reply message
-                opts.insert(DhcpOption::IAPD(IAPD {
-                    id: iapd.id,
-                    t1: config.lease_times.v6_renewal,
-                    t2: config.lease_times.v6_rebinding,
-                    opts: ia_pd_opts,
-                }));
-            }
-
-            // construct IA_NA information
-            if let Some(iana) = msg.ia_na() {
-                let mut ia_na_opts = DhcpOptions::new();
-                ia_na_opts.insert(DhcpOption::IAAddr(IAAddr {
-                    addr: reservation.ipv6_na,
-                    preferred_life: config.lease_times.v6_preferred,
-                    valid_life: config.lease_times.v6_valid,
-                    opts: DhcpOptions::new(),
-                }));
-                // add IA_NA information to Reply message
-                opts.insert(DhcpOption::IANA(IANA {
-                    id: iana.id,
-                    t1: config.lease_times.v6_renewal,
-                    t2: config.lease_times.v6_rebinding,
-                    opts: ia_na_opts,
-                }));
+            insert_reserved_ias(opts, msg, &reservation, &profile.lease_times);
+
+            // Hints are only ever advisory (RFC 8415 §18.2.1) and the
+            // reservation always wins, but a CPE that keeps asking for
+            // something else is usually provisioned for the wrong subscriber
+            if reservation
+                .ipv6_na
+                .is_some_and(|na| msg.ia_na_hint_differs(na))
+                || msg.ia_pd_hint_differs(reservation.ipv6_pd)
+            {
+                info!(
+                    requested_na = ?msg.ia_na_address(),
+                    reserved_na = ?reservation.ipv6_na,
+                    requested_pd = ?msg.ia_pd_prefix(),
+                    reserved_pd = %reservation.ipv6_pd,
+                    "Solicit IA hint differs from reservation, offering reserved values"
+                );
+                if config.v6_hint_status {
+                    insert_hint_notes(opts, msg, &reservation);
+                }
             }
 
             opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
             opts.insert(DhcpOption::ClientId(client_id.bytes));
-            opts.insert(DhcpOption::DomainNameServers(config.dns_v6.clone()));
+            insert_config_options(opts, config, profile, msg);
             DhcpV6Response::Message(ResponseMessage {
                 message: reply,
                 reservation: Some(reservation),
                 reservation_match: Some(match_info),
+                lease: matches!(msg_type, MessageType::Reply).then(|| valid_lifetime(profile)),
             })
         }
+        None if profile.answer_unknown_clients => DhcpV6Response::Message(ResponseMessage {
+            message: no_addrs_advertise(config, msg, client_id),
+            reservation: None,
+            reservation_match: None,
+            lease: None,
+        }),
         None => DhcpV6Response::NoResponse(NoResponse::NoReservation),
     }
 }
 
+/// Advertise for a client we have no reservation for, or whose reservation
+/// is suspended, per RFC 8415 §18.3.9:
+/// each IA comes back empty with NoAddrsAvail (IA_NA) or NoPrefixAvail
+/// (IA_PD) inside it. A client that gets no answer at all keeps multicasting
+/// Solicits; this tells it there is nothing for it here.
+///
+/// No Preference option is included, so a client hearing from another server
+/// that does have addresses picks that one. Always an Advertise, even for a
+/// Rapid Commit Solicit, since nothing is being committed.
+fn no_addrs_advertise(config: &Config, msg: &Message, client_id: Duid) -> Message {
+    let mut advertise = Message::new_with_id(MessageType::Advertise, msg.xid());
+    let opts = advertise.opts_mut();
+    let mut has_ia = false;
+    for opt in msg.opts().iter() {
+        match opt {
+            DhcpOption::IANA(iana) => {
+                has_ia = true;
+                opts.insert(build_no_addrs_ia_na(iana.id));
+            }
+            DhcpOption::IAPD(iapd) => {
+                has_ia = true;
+                opts.insert(build_no_prefix_ia_pd(iapd.id));
+            }
+            _ => (),
+        }
+    }
+    if !has_ia {
+        opts.insert(DhcpOption::StatusCode(StatusCode {
+            status: Status::NoAddrsAvail,
+            msg: "No addresses available".into(),
+        }));
+    }
+    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+    opts.insert(DhcpOption::ClientId(client_id.bytes));
+    advertise
+}
+
 fn handle_renew(
     config: &Config,
+    profile: &Profile,
     reservations: &ReservationDb,
     leases: &Opt82Cache,
     msg: &Message,
@@ -181,7 +218,7 @@ fn handle_renew(
     let client_id = match msg.client_id() {
         Some(bytes) => match crate::types::Duid::new(bytes.to_vec()) {
             Some(duid) => duid,
-            None => return DhcpV6Response::NoResponse(NoResponse::InvalidClientId),
+            None => return DhcpV6Response::NoResponse(NoResponse::MalformedOption),
         },
         None => return DhcpV6Response::NoResponse(NoResponse::NoClientId),
     };
@@ -205,109 +242,46 @@ fn handle_renew(
         msg,
         &client_id,
     );
+    let profile = &reserved_address
+        .as_ref()
+        .map_or(*profile, |(reservation, _)| {
+            profile.with_reservation(config, reservation)
+        });
 
     let (reservation, match_info) = match reserved_address {
+        Some((ref reservation, match_info)) if reservation.suspended => {
+            debug!(%client_id, "reservation suspended, returning NoBinding");
+            build_no_binding(reply_opts, msg);
+            (Some(reservation.clone()), Some(match_info))
+        }
         Some((ref reservation, match_info)) => {
-            // check if our server reservation matches what the client sent
-            // TODO: should this scan for multiple IANA options?
-            if let Some(iana) = msg.ia_na() {
-                let mut ia_na_opts = DhcpOptions::new();
-                ia_na_opts.insert(DhcpOption::IAAddr(IAAddr {
-                    addr: reservation.ipv6_na,
-                    preferred_life: config.lease_times.v6_preferred,
-                    valid_life: config.lease_times.v6_valid,
-                    opts: DhcpOptions::new(),
-                }));
-                // add IA_NA information to Reply message
-                reply_opts.insert(DhcpOption::IANA(IANA {
-                    id: iana.id,
-                    t1: config.lease_times.v6_renewal,
-                    t2: config.lease_times.v6_rebinding,
-                    opts: ia_na_opts,
-                }));
-            }
-
-            if let Some(iapd) = msg.ia_pd() {
-                let mut ia_pd_opts = DhcpOptions::new();
-                ia_pd_opts.insert(DhcpOption::IAPrefix(IAPrefix {
-                    preferred_lifetime: config.lease_times.v6_preferred,
-                    valid_lifetime: config.lease_times.v6_valid,
-                    prefix_len: reservation.ipv6_pd.prefix_len(),
-                    prefix_ip: reservation.ipv6_pd.addr(),
-                    opts: DhcpOptions::new(),
-                }));
-                // add IA_PD information to Reply message
-                reply_opts.insert(DhcpOption::IAPD(IAPD {
-                    id: iapd.id,
-                    t1: config.lease_times.v6_renewal,
-                    t2: config.lease_times.v6_rebinding,
-                    opts: ia_pd_opts,
-                }));
-            }
-
+            insert_reserved_ias(reply_opts, msg, reservation, &profile.lease_times);
             (Some(reservation.clone()), Some(match_info))
         }
         None => {
-            // RFC 8415 Section 18.4.2: If the server cannot find a client entry for the IA,
-            // the server returns the IA containing no addresses/prefixes with a Status Code
-            // option set to NoBinding in the Reply message.
-            for opt in msg.opts().iter() {
-                match opt {
-                    DhcpOption::IANA(iana) => {
-                        let mut iana_new = iana.clone();
-                        // Zero out lifetimes for any addresses
-                        for ia_opt in iana_new.opts.iter_mut() {
-                            if let DhcpOption::IAAddr(addr) = ia_opt {
-                                addr.valid_life = 0;
-                                addr.preferred_life = 0;
-                            }
-                        }
-                        // Add NoBinding status inside the IA option per RFC 8415
-                        iana_new
-                            .opts
-                            .insert(DhcpOption::StatusCode(dhcproto::v6::StatusCode {
-                                status: dhcproto::v6::Status::NoBinding,
-                                msg: "No binding for this IA".into(),
-                            }));
-                        reply_opts.insert(DhcpOption::IANA(iana_new));
-                    }
-                    DhcpOption::IAPD(iapd) => {
-                        let mut iapd_new = iapd.clone();
-                        // Zero out lifetimes for any prefixes
-                        for ia_opt in iapd_new.opts.iter_mut() {
-                            if let DhcpOption::IAPrefix(prefix) = ia_opt {
-                                prefix.valid_lifetime = 0;
-                                prefix.preferred_lifetime = 0;
-                            }
-                        }
-                        // Add NoBinding status inside the IA option per RFC 8415
-                        iapd_new
-                            .opts
-                            .insert(DhcpOption::StatusCode(dhcproto::v6::StatusCode {
-                                status: dhcproto::v6::Status::NoBinding,
-                                msg: "No binding for this IA".into(),
-                            }));
-                        reply_opts.insert(DhcpOption::IAPD(iapd_new));
-                    }
-                    _ => (),
-                }
-            }
+            build_no_binding(reply_opts, msg);
             (None, None)
         }
     };
 
     reply_opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
     reply_opts.insert(DhcpOption::ClientId(client_id.bytes));
-    reply_opts.insert(DhcpOption::DomainNameServers(config.dns_v6.clone()));
+    insert_config_options(reply_opts, config, profile, msg);
+    let lease = reservation
+        .as_ref()
+        .filter(|r| !r.suspended)
+        .map(|_| valid_lifetime(profile));
     DhcpV6Response::Message(ResponseMessage {
         message: reply,
         reservation,
         reservation_match: match_info,
+        lease,
     })
 }
 
 fn handle_request(
     config: &Config,
+    profile: &Profile,
     reservations: &ReservationDb,
     leases: &Opt82Cache,
     msg: &Message,
@@ -320,7 +294,7 @@ fn handle_request(
     let client_id = match msg.client_id() {
         Some(bytes) => match crate::types::Duid::new(bytes.to_vec()) {
             Some(duid) => duid,
-            None => return DhcpV6Response::NoResponse(NoResponse::InvalidClientId),
+            None => return DhcpV6Response::NoResponse(NoResponse::MalformedOption),
         },
         None => return DhcpV6Response::NoResponse(NoResponse::NoClientId),
     };
@@ -341,59 +315,32 @@ fn handle_request(
         msg,
         &client_id,
     );
+    let profile = &reserved_address
+        .as_ref()
+        .map_or(*profile, |(reservation, _)| {
+            profile.with_reservation(config, reservation)
+        });
     match reserved_address {
         Some((reservation, match_info)) => {
             let mut reply = Message::new_with_id(MessageType::Reply, msg.xid());
             let opts = reply.opts_mut();
 
-            // Reply contains IA_NA address and IA_PD prefix as options.
-            // These options contain nested options with the actual addresses/prefixes
-            // ReplyOptions [IAPD[IAPrefix], IANA[IAAddr]]
-
-            // construct IA_PD information
-            if let Some(iapd) = msg.ia_pd() {
-                let mut ia_pd_opts = DhcpOptions::new();
-                ia_pd_opts.insert(DhcpOption::IAPrefix(IAPrefix {
-                    preferred_lifetime: config.lease_times.v6_preferred,
-                    valid_lifetime: config.lease_times.v6_valid,
-                    prefix_len: reservation.ipv6_pd.prefix_len(),
-                    prefix_ip: reservation.ipv6_pd.addr(),
-                    opts: DhcpOptions::new(),
-                }));
-                // add IA_PD information to Reply message
-                opts.insert(DhcpOption::IAPD(IAPD {
-                    id: iapd.id,
-                    t1: config.lease_times.v6_renewal,
-                    t2: config.lease_times.v6_rebinding,
-                    opts: ia_pd_opts,
-                }));
-            }
-
-            // construct IA_NA information
-            if let Some(iana) = msg.ia_na() {
-                let mut ia_na_opts = DhcpOptions::new();
-                ia_na_opts.insert(DhcpOption::IAAddr(IAAddr {
-                    addr: reservation.ipv6_na,
-                    preferred_life: config.lease_times.v6_preferred,
-                    valid_life: config.lease_times.v6_valid,
-                    opts: DhcpOptions::new(),
-                }));
-                // add IA_NA information to Reply message
-                opts.insert(DhcpOption::IANA(IANA {
-                    id: iana.id,
-                    t1: config.lease_times.v6_renewal,
-                    t2: config.lease_times.v6_rebinding,
-                    opts: ia_na_opts,
-                }));
+            if reservation.suspended {
+                debug!(%client_id, "reservation suspended, returning NoBinding");
+                build_no_binding(opts, msg);
+            } else {
+                insert_reserved_ias(opts, msg, &reservation, &profile.lease_times);
             }
 
             opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
             opts.insert(DhcpOption::ClientId(client_id.bytes));
-            opts.insert(DhcpOption::DomainNameServers(config.dns_v6.clone()));
+            insert_config_options(opts, config, profile, msg);
+            let lease = (!reservation.suspended).then(|| valid_lifetime(profile));
             DhcpV6Response::Message(ResponseMessage {
                 message: reply,
                 reservation: Some(reservation),
                 reservation_match: Some(match_info),
+                lease,
             })
         }
         None => DhcpV6Response::NoResponse(NoResponse::NoReservation),
@@ -406,6 +353,7 @@ fn handle_request(
 /// (not specifically to the server that originally assigned the lease).
 fn handle_rebind(
     config: &Config,
+    profile: &Profile,
     reservations: &ReservationDb,
     leases: &Opt82Cache,
     msg: &Message,
@@ -415,7 +363,7 @@ fn handle_rebind(
     let client_id = match msg.client_id() {
         Some(bytes) => match crate::types::Duid::new(bytes.to_vec()) {
             Some(duid) => duid,
-            None => return DhcpV6Response::NoResponse(NoResponse::InvalidClientId),
+            None => return DhcpV6Response::NoResponse(NoResponse::MalformedOption),
         },
         None => return DhcpV6Response::NoResponse(NoResponse::NoClientId),
     };
@@ -438,97 +386,238 @@ fn handle_rebind(
         msg,
         &client_id,
     );
+    let profile = &reserved_address
+        .as_ref()
+        .map_or(*profile, |(reservation, _)| {
+            profile.with_reservation(config, reservation)
+        });
 
     let (reservation, match_info) = match reserved_address {
+        Some((ref reservation, match_info)) if reservation.suspended => {
+            debug!(%client_id, "reservation suspended, returning NoBinding");
+            build_no_binding(reply_opts, msg);
+            (Some(reservation.clone()), Some(match_info))
+        }
         Some((ref reservation, match_info)) => {
-            if let Some(iana) = msg.ia_na() {
-                let mut ia_na_opts = DhcpOptions::new();
-                ia_na_opts.insert(DhcpOption::IAAddr(IAAddr {
-                    addr: reservation.ipv6_na,
-                    preferred_life: config.lease_times.v6_preferred,
-                    valid_life: config.lease_times.v6_valid,
-                    opts: DhcpOptions::new(),
-                }));
-                reply_opts.insert(DhcpOption::IANA(IANA {
-                    id: iana.id,
-                    t1: config.lease_times.v6_renewal,
-                    t2: config.lease_times.v6_rebinding,
-                    opts: ia_na_opts,
-                }));
+            insert_reserved_ias(reply_opts, msg, reservation, &profile.lease_times);
+            let zeroed = insert_inappropriate_leases(reply_opts, msg, reservation);
+            if zeroed > 0 {
+                debug!(
+                    %client_id,
+                    "Rebind included {zeroed} addresses/prefixes outside the reservation, returning them with zero lifetimes"
+                );
             }
-
-            if let Some(iapd) = msg.ia_pd() {
-                let mut ia_pd_opts = DhcpOptions::new();
-                ia_pd_opts.insert(DhcpOption::IAPrefix(IAPrefix {
-                    preferred_lifetime: config.lease_times.v6_preferred,
-                    valid_lifetime: config.lease_times.v6_valid,
-                    prefix_len: reservation.ipv6_pd.prefix_len(),
-                    prefix_ip: reservation.ipv6_pd.addr(),
-                    opts: DhcpOptions::new(),
-                }));
-                reply_opts.insert(DhcpOption::IAPD(IAPD {
-                    id: iapd.id,
-                    t1: config.lease_times.v6_renewal,
-                    t2: config.lease_times.v6_rebinding,
-                    opts: ia_pd_opts,
-                }));
-            }
-
             (Some(reservation.clone()), Some(match_info))
         }
         None => {
             // RFC 8415 Section 18.4.5: Same as Renew - return IAs with NoBinding status
-            for opt in msg.opts().iter() {
-                match opt {
-                    DhcpOption::IANA(iana) => {
-                        let mut iana_new = iana.clone();
-                        for ia_opt in iana_new.opts.iter_mut() {
-                            if let DhcpOption::IAAddr(addr) = ia_opt {
-                                addr.valid_life = 0;
-                                addr.preferred_life = 0;
-                            }
-                        }
-                        iana_new
-                            .opts
-                            .insert(DhcpOption::StatusCode(dhcproto::v6::StatusCode {
-                                status: dhcproto::v6::Status::NoBinding,
-                                msg: "No binding for this IA".into(),
-                            }));
-                        reply_opts.insert(DhcpOption::IANA(iana_new));
-                    }
-                    DhcpOption::IAPD(iapd) => {
-                        let mut iapd_new = iapd.clone();
-                        for ia_opt in iapd_new.opts.iter_mut() {
-                            if let DhcpOption::IAPrefix(prefix) = ia_opt {
-                                prefix.valid_lifetime = 0;
-                                prefix.preferred_lifetime = 0;
-                            }
-                        }
-                        iapd_new
-                            .opts
-                            .insert(DhcpOption::StatusCode(dhcproto::v6::StatusCode {
-                                status: dhcproto::v6::Status::NoBinding,
-                                msg: "No binding for this IA".into(),
-                            }));
-                        reply_opts.insert(DhcpOption::IAPD(iapd_new));
-                    }
-                    _ => (),
-                }
-            }
+            build_no_binding(reply_opts, msg);
             (None, None)
         }
     };
 
     reply_opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
     reply_opts.insert(DhcpOption::ClientId(client_id.bytes));
-    reply_opts.insert(DhcpOption::DomainNameServers(config.dns_v6.clone()));
+    insert_config_options(reply_opts, config, profile, msg);
+    let lease = reservation
+        .as_ref()
+        .filter(|r| !r.suspended)
+        .map(|_| valid_lifetime(profile));
     DhcpV6Response::Message(ResponseMessage {
         message: reply,
         reservation,
         reservation_match: match_info,
+        lease,
     })
 }
 
+/// Handle Release and Decline per RFC 8415 Sections 18.3.7 and 18.3.8
+///
+/// Both are answered with a Reply carrying a Success status, whether or not
+/// the client has a reservation. IAs that aren't the client's reservation are
+/// echoed with NoBinding, so a client we know nothing about still hears back
+/// instead of retransmitting until it gives up. A released address is no
+/// longer held for the client, so another client may claim it without a
+/// conflict. A declined address stays reserved, since it is the only one the
+/// client can get, but is worth an operator's attention.
+fn handle_release_or_decline(
+    config: &Config,
+    reservations: &ReservationDb,
+    leases: &Opt82Cache,
+    msg: &Message,
+    relay_msg: &RelayMessage,
+) -> DhcpV6Response {
+    // Servers MUST discard any Release or Decline messages that:
+    // * does not include a Client Identifier
+    // * does not include a Server Identifier option
+    // * includes a Server Identifier option that does not match this server's DUID
+    let client_id = match msg.client_id() {
+        Some(bytes) => match crate::types::Duid::new(bytes.to_vec()) {
+            Some(duid) => duid,
+            None => return DhcpV6Response::NoResponse(NoResponse::MalformedOption),
+        },
+        None => return DhcpV6Response::NoResponse(NoResponse::NoClientId),
+    };
+
+    match msg.server_id() {
+        Some(bytes) if bytes == config.v6_server_id.bytes => (),
+        Some(_) => return DhcpV6Response::NoResponse(NoResponse::WrongServerId),
+        None => return DhcpV6Response::NoResponse(NoResponse::NoServerId),
+    }
+
+    let reserved_address = find_reservation(
+        reservations,
+        leases,
+        &config.option1837_extractors,
+        &config.mac_extractors,
+        relay_msg,
+        msg,
+        &client_id,
+    );
+
+    let mut reply = Message::new_with_id(MessageType::Reply, msg.xid());
+    let opts = reply.opts_mut();
+    let na_bound = insert_unbound_ias(
+        opts,
+        msg,
+        reserved_address
+            .as_ref()
+            .map(|(reservation, _)| &**reservation),
+    );
+    let reserved_na = reserved_address
+        .as_ref()
+        .and_then(|(reservation, _)| reservation.ipv6_na);
+    if let (true, Some(na)) = (na_bound, reserved_na) {
+        if matches!(msg.msg_type(), MessageType::Decline) {
+            warn!(
+                %client_id,
+                %na,
+                "DHCPv6 Decline: client reports its reserved address is in use on the link"
+            );
+        } else {
+            leases.release_ipv6(na, &client_id);
+        }
+    }
+
+    opts.insert(DhcpOption::StatusCode(StatusCode {
+        status: Status::Success,
+        msg: format!("{:?} received", msg.msg_type()),
+    }));
+    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+    opts.insert(DhcpOption::ClientId(client_id.bytes));
+    let (reservation, reservation_match) = reserved_address.unzip();
+    DhcpV6Response::Message(ResponseMessage {
+        message: reply,
+        reservation,
+        reservation_match,
+        lease: None,
+    })
+}
+
+/// How long a Reply leases the reserved IA_NA address for
+fn valid_lifetime(profile: &Profile) -> Duration {
+    Duration::from_secs(profile.lease_times.v6_valid.into())
+}
+
+const SERVER_PORT: u16 = 547;
+const OPTION_RELAY_MSG: u16 = 9;
+/// Echo Request Option, RFC 4994
+const OPTION_ERO: u16 = 43;
+/// Relay Source Port, RFC 8357
+const OPTION_RELAY_PORT: u16 = 135;
+
+/// Wrap `reply` in the Relay-reply for `relay_msg`. Interface-ID is echoed
+/// so the relay can find the client's link (RFC 8415 §19.3), and Relay
+/// Source Port so a relay listening on another port keeps receiving replies
+/// through any relays above it (RFC 8357 §5.2). Any other option the relay
+/// lists in an Echo Request Option is copied back too, so relays that keep
+/// their subscriber state in Relay-forward options, as BNGs do, get it
+/// back with the reply (RFC 4994 §4).
+pub fn relay_reply(relay_msg: &RelayMessage, reply: Message) -> RelayMessage {
+    let mut opts = DhcpOptions::new();
+    opts.insert(DhcpOption::RelayMsg(RelayMessageData::Message(reply)));
+    let echo = echo_requested(relay_msg);
+    for opt in relay_msg.opts().iter() {
+        let code = u16::from(OptionCode::from(opt));
+        let echoed = echo.contains(&code) && !matches!(code, OPTION_RELAY_MSG | OPTION_ERO);
+        if matches!(opt, DhcpOption::InterfaceId(_)) || is_relay_port(opt) || echoed {
+            opts.insert(opt.clone());
+        }
+    }
+    RelayMessage {
+        msg_type: MessageType::RelayRepl,
+        hop_count: relay_msg.hop_count(),
+        link_addr: relay_msg.link_addr(),
+        peer_addr: relay_msg.peer_addr(),
+        opts,
+    }
+}
+
+/// Where to send the Relay-reply for a Relay-forward received from `src`:
+/// the port the relay sent from if it included Relay Source Port, otherwise
+/// the server port, 547 (RFC 8357 §5.3).
+///
+/// <https://datatracker.ietf.org/doc/html/rfc8357#section-5.3>
+pub fn reply_destination(relay_msg: &RelayMessage, src: SocketAddr) -> SocketAddr {
+    if relay_msg.opts().iter().any(is_relay_port) {
+        src
+    } else {
+        SocketAddr::new(src.ip(), SERVER_PORT)
+    }
+}
+
+/// Whether a client may send `msg_type` straight to the server's unicast
+/// address. Solicit, Confirm, Rebind and Information-request must be
+/// multicast, so the server discards them when unicast (RFC 8415 §16).
+///
+/// <https://datatracker.ietf.org/doc/html/rfc8415#section-16>
+pub fn accepts_unicast(msg_type: MessageType) -> bool {
+    matches!(
+        msg_type,
+        MessageType::Request | MessageType::Renew | MessageType::Release | MessageType::Decline
+    )
+}
+
+/// Stand-in Relay-forward for a message `client` sent straight to
+/// `v6_server_unicast`, so it goes through the same handling as a relayed
+/// one. It carries no relay options, and its unspecified link-address
+/// matches no subscriber network; the reservation is found by DUID or by the
+/// Option 18/37 bound to the DUID when the client was relayed.
+pub fn unicast_relay_forw(msg: Message, client: Ipv6Addr) -> RelayMessage {
+    let mut opts = DhcpOptions::new();
+    opts.insert(DhcpOption::RelayMsg(RelayMessageData::Message(msg)));
+    RelayMessage {
+        msg_type: MessageType::RelayForw,
+        hop_count: 0,
+        link_addr: Ipv6Addr::UNSPECIFIED,
+        peer_addr: client,
+        opts,
+    }
+}
+
+fn is_relay_port(opt: &DhcpOption) -> bool {
+    u16::from(OptionCode::from(opt)) == OPTION_RELAY_PORT
+}
+
+/// Option codes listed in `relay_msg`'s Echo Request Options. Read from the
+/// encoded option, which is the same whichever way it was decoded.
+fn echo_requested(relay_msg: &RelayMessage) -> Vec<u16> {
+    relay_msg
+        .opts()
+        .iter()
+        .filter(|opt| u16::from(OptionCode::from(*opt)) == OPTION_ERO)
+        .filter_map(|opt| opt.to_vec().ok())
+        .flat_map(|encoded| {
+            // Past the option code and length
+            let codes = encoded.get(4..).unwrap_or_default();
+            codes
+                .chunks_exact(2)
+                .map(|code| u16::from_be_bytes([code[0], code[1]]))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
 pub fn handle_message(
     config: &Config,
     reservations: &ReservationDb,
@@ -536,17 +625,24 @@ pub fn handle_message(
     msg: &Message,
     relay_msg: &RelayMessage,
 ) -> DhcpV6Response {
-    match msg.msg_type() {
+    let profile = config
+        .profile_v6(relay_msg.link_addr())
+        .with_device_class(config, crate::fingerprint::v6(msg).as_deref());
+    let mut response = match msg.msg_type() {
         // A client sends a Solicit message to locate servers.
         // https://datatracker.ietf.org/doc/html/rfc8415#section-16.2
         // Four-message exchange - Solicit -> Advertisement -> Request -> Reply
         // Two-message exchange (rapid commit) - Solicit -> Reply
-        MessageType::Solicit => handle_solicit(config, reservations, leases, msg, relay_msg),
+        MessageType::Solicit => {
+            handle_solicit(config, &profile, reservations, leases, msg, relay_msg)
+        }
         // Servers always discard Advertise
         MessageType::Advertise => DhcpV6Response::NoResponse(NoResponse::Discarded),
         // A client sends a Request as part of the 4 message exchange to receive an initial address/prefix
         // https://datatracker.ietf.org/doc/html/rfc8415#section-16.4
-        MessageType::Request => handle_request(config, reservations, leases, msg, relay_msg),
+        MessageType::Request => {
+            handle_request(config, &profile, reservations, leases, msg, relay_msg)
+        }
         // 18.2.4.  Creation and Transmission of Renew Messages
         //
         //   To extend the preferred and valid lifetimes for the leases assigned
@@ -559,10 +655,17 @@ pub fn handle_message(
         //   the IAs.  The client includes IA Prefix options (see Section 21.22)
         //   within IA_PD options (see Section 21.21) for the delegated prefixes
         //   assigned to the IAs.
-        MessageType::Renew => handle_renew(config, reservations, leases, msg, relay_msg),
+        MessageType::Renew => handle_renew(config, &profile, reservations, leases, msg, relay_msg),
         // RFC 8415 Section 18.4.5: Rebind is like Renew but sent to any server
         // when the client can't reach the original server
-        MessageType::Rebind => handle_rebind(config, reservations, leases, msg, relay_msg),
+        MessageType::Rebind => {
+            handle_rebind(config, &profile, reservations, leases, msg, relay_msg)
+        }
+        // RFC 8415 Sections 18.3.7 and 18.3.8: the client is done with its
+        // leases, or found one of them in use on the link
+        MessageType::Release | MessageType::Decline => {
+            handle_release_or_decline(config, reservations, leases, msg, relay_msg)
+        }
         _ => {
             debug!(
                 "MessageType `{:?}` not implemented by shadowdhcp",
@@ -570,5 +673,27 @@ pub fn handle_message(
             );
             DhcpV6Response::NoResponse(NoResponse::Discarded)
         }
+    };
+
+    // Remember the relay options the client was leased under, so later
+    // messages relayed without them still find the reservation.
+    if let DhcpV6Response::Message(resp) = &response {
+        if matches!(resp.message.msg_type(), MessageType::Reply) {
+            if let (Some(opt), Some(duid)) = (
+                resp.reservation
+                    .as_ref()
+                    .and_then(|r| r.option1837.as_ref()),
+                msg.client_id().and_then(|id| Duid::new(id.to_vec())),
+            ) {
+                leases.insert_duid_option1837_binding(&duid, opt);
+            }
+        }
     }
+
+    // RFC 8415 §21.12: the client may then unicast to this address
+    if let (DhcpV6Response::Message(resp), Some(addr)) = (&mut response, config.v6_server_unicast) {
+        resp.message.opts_mut().insert(DhcpOption::Unicast(addr));
+    }
+
+    response
 }
@@ -8,6 +8,8 @@ use dhcproto::v6::{DhcpOption, Message, RelayMessage};
 use serde::Deserialize;
 use tracing::debug;
 
+use crate::types::Duid;
+
 /// MAC address extraction method.
 ///
 /// Each variant represents a different source from which a client's MAC address
@@ -96,10 +98,6 @@ fn extract_peer_addr_eui64(relay_msg: &RelayMessage) -> Option<MacAddr6> {
     Some(MacAddr6::new(mac))
 }
 
-/// DUID type codes from RFC 8415.
-const DUID_LLT: u16 = 1; // Link-layer address plus time
-const DUID_LL: u16 = 3; // Link-layer address
-
 /// Hardware type for Ethernet from IANA.
 const HTYPE_ETHERNET: u16 = 1;
 
@@ -112,15 +110,18 @@ fn extract_duid(msg: &Message) -> Option<MacAddr6> {
         DhcpOption::ClientId(id) => Some(id.as_slice()),
         _ => None,
     })?;
+    let duid = Duid::from(client_id);
 
-    if client_id.len() < 4 {
+    let Some(address) = duid.link_layer_address() else {
+        debug!(
+            "DUID type {:?} does not contain link-layer address",
+            duid.duid_type()
+        );
         return None;
-    }
-
-    let duid_type = u16::from_be_bytes([client_id[0], client_id[1]]);
-    let htype = u16::from_be_bytes([client_id[2], client_id[3]]);
+    };
 
     // Only handle Ethernet hardware type
+    let htype = duid.hardware_type()?;
     if htype != HTYPE_ETHERNET {
         debug!(
             "DUID hardware type {} is not Ethernet, skipping MAC extraction",
@@ -129,33 +130,8 @@ fn extract_duid(msg: &Message) -> Option<MacAddr6> {
         return None;
     }
 
-    match duid_type {
-        DUID_LLT => {
-            // DUID-LLT: type(2) + htype(2) + time(4) + MAC(6) = 14 bytes minimum
-            if client_id.len() < 14 {
-                return None;
-            }
-            let mut mac = [0u8; 6];
-            mac.copy_from_slice(&client_id[8..14]);
-            Some(MacAddr6::new(mac))
-        }
-        DUID_LL => {
-            // DUID-LL: type(2) + htype(2) + MAC(6) = 10 bytes minimum
-            if client_id.len() < 10 {
-                return None;
-            }
-            let mut mac = [0u8; 6];
-            mac.copy_from_slice(&client_id[4..10]);
-            Some(MacAddr6::new(mac))
-        }
-        _ => {
-            debug!(
-                "DUID type {} does not contain link-layer address",
-                duid_type
-            );
-            None
-        }
-    }
+    let mac: [u8; 6] = address.get(..6)?.try_into().ok()?;
+    Some(MacAddr6::new(mac))
 }
 
 #[cfg(test)]
@@ -3,6 +3,7 @@ pub mod extractors;
 pub mod handlers;
 pub mod mac_extractors;
 mod reservation;
+mod response;
 pub mod worker;
 
 pub use worker::v6_worker;
@@ -26,9 +26,10 @@ pub fn find_reservation_by_relay_info(
 
     extractors.iter().find_map(|(name, extractor)| {
         extractor(&option1837).and_then(|extracted_opt| {
-            reservations
-                .by_opt1837(&extracted_opt)
-                .map(|res| (res, ReservationMatch::option1837(name)))
+            reservations.by_opt1837(&extracted_opt).map(|res| {
+                let found = ReservationMatch::option1837(name).with_relay_key(&extracted_opt);
+                (res, found)
+            })
         })
     })
 }
@@ -37,8 +38,9 @@ pub fn find_reservation_by_relay_info(
 ///
 /// 1. By DUID
 /// 2. By Option 18/37 (relay agent options) using extractors
-/// 3. By MAC (using configured extractors in order)
-/// 4. By Option82 (via MAC lookup in lease database - fallback for each extracted MAC)
+/// 3. By Option 18/37 bound to the DUID on an earlier lease
+/// 4. By MAC (using configured extractors in order)
+/// 5. By Option82 (via MAC lookup in lease database - fallback for each extracted MAC)
 ///
 /// Returns the reservation along with match metadata (method and extractor used).
 pub fn find_reservation(
@@ -52,27 +54,42 @@ pub fn find_reservation(
 ) -> Option<(Arc<Reservation>, ReservationMatch)> {
     // Priority 1: DUID
     if let Some(res) = reservations.by_duid(client_id) {
-        return Some((res, ReservationMatch::duid()));
+        let found = ReservationMatch::duid().with_key(client_id);
+        return Some((res, found.with_priority(1)));
     }
 
     // Priority 2: Option 18/37 with extractors
-    if let Some(result) =
+    if let Some((res, found)) =
         find_reservation_by_relay_info(reservations, opt1837_extractors, relay_msg)
     {
-        return Some(result);
+        return Some((res, found.with_priority(2)));
     }
 
-    // Priority 3: Try MAC extractors in order
+    // Priority 3: Option 18/37 via DUID binding
+    if let Some(opt1837) = leases.get_opt1837_by_duid(client_id) {
+        if let Some(res) = reservations.by_opt1837(&opt1837) {
+            let found = ReservationMatch::option1837("duid_binding").with_relay_key(&opt1837);
+            return Some((res, found.with_priority(3)));
+        }
+    }
+
+    // Priority 4: Try MAC extractors in order
     for extractor in mac_extractors {
         if let Some(mac) = extractor.extract(relay_msg, msg) {
             // Try direct MAC reservation match
             if let Some(res) = reservations.by_mac(mac) {
-                return Some((res, ReservationMatch::mac(extractor.name())));
+                let found = ReservationMatch::mac(extractor.name()).with_key(mac);
+                return Some((res, found.with_priority(4)));
             }
-            // Priority 4: Option82 via lease fallback
+            // Priority 5: Option82 via lease fallback
             if let Some(opt82) = leases.get_opt82_by_mac(&mac) {
                 if let Some(res) = reservations.by_opt82(&opt82) {
-                    return Some((res, ReservationMatch::option82("lease_fallback")));
+                    let found = ReservationMatch::option82("lease_fallback");
+                    return Some((res, found.with_relay_key(&opt82).with_priority(5)));
+                }
+                if let Some(res) = reservations.by_pool_key(&opt82) {
+                    let found = ReservationMatch::pool("lease_fallback");
+                    return Some((res, found.with_relay_key(&opt82).with_priority(5)));
                 }
             }
         }
@@ -0,0 +1,419 @@
+//! Pieces shared by every Advertise and Reply. Lifetimes and T1/T2 are only
+//! ever read from the relay's `Profile`, so all four handlers hand out the
+//! same values to a client.
+
+use std::net::Ipv6Addr;
+
+use dhcproto::v6::{
+    DhcpOption, DhcpOptions, IAAddr, IAPrefix, Message, OptionCode, Status, StatusCode,
+    UnknownOption, IANA, IAPD,
+};
+use ipnet::Ipv6Net;
+
+use crate::config::{Config, LeaseTimes, Profile};
+use crate::types::Reservation;
+
+use super::extensions::ShadowMessageExtV6;
+
+/// RFC 3646 DNS recursive name servers
+const OPTION_DNS_SERVERS: u16 = 23;
+/// RFC 3646 domain search list
+const OPTION_DOMAIN_LIST: u16 = 24;
+/// RFC 4075 SNTP servers
+const OPTION_SNTP_SERVERS: u16 = 31;
+/// RFC 5908 NTP servers
+const OPTION_NTP_SERVER: u16 = 56;
+const NTP_SUBOPTION_SRV_ADDR: u16 = 1;
+
+/// IA_NA carrying the reserved address, under the client's IAID
+pub fn build_ia_na(iaid: u32, addr: Ipv6Addr, lease_times: &LeaseTimes) -> DhcpOption {
+    let mut ia_na_opts = DhcpOptions::new();
+    ia_na_opts.insert(DhcpOption::IAAddr(IAAddr {
+        addr,
+        preferred_life: lease_times.v6_preferred,
+        valid_life: lease_times.v6_valid,
+        opts: DhcpOptions::new(),
+    }));
+    DhcpOption::IANA(IANA {
+        id: iaid,
+        t1: lease_times.v6_renewal,
+        t2: lease_times.v6_rebinding,
+        opts: ia_na_opts,
+    })
+}
+
+/// IA_PD carrying the reserved prefix, under the client's IAID
+pub fn build_ia_pd(iaid: u32, prefix: Ipv6Net, lease_times: &LeaseTimes) -> DhcpOption {
+    let mut ia_pd_opts = DhcpOptions::new();
+    ia_pd_opts.insert(DhcpOption::IAPrefix(IAPrefix {
+        preferred_lifetime: lease_times.v6_preferred,
+        valid_lifetime: lease_times.v6_valid,
+        prefix_len: prefix.prefix_len(),
+        prefix_ip: prefix.addr(),
+        opts: DhcpOptions::new(),
+    }));
+    DhcpOption::IAPD(IAPD {
+        id: iaid,
+        t1: lease_times.v6_renewal,
+        t2: lease_times.v6_rebinding,
+        opts: ia_pd_opts,
+    })
+}
+
+/// Empty IA_NA holding a NoAddrsAvail status, for a client that asked for
+/// an address we don't have for it
+pub fn build_no_addrs_ia_na(iaid: u32) -> DhcpOption {
+    let mut ia_opts = DhcpOptions::new();
+    ia_opts.insert(DhcpOption::StatusCode(StatusCode {
+        status: Status::NoAddrsAvail,
+        msg: "No addresses available".into(),
+    }));
+    DhcpOption::IANA(IANA {
+        id: iaid,
+        t1: 0,
+        t2: 0,
+        opts: ia_opts,
+    })
+}
+
+/// Empty IA_PD holding a NoPrefixAvail status
+pub fn build_no_prefix_ia_pd(iaid: u32) -> DhcpOption {
+    let mut ia_opts = DhcpOptions::new();
+    ia_opts.insert(DhcpOption::StatusCode(StatusCode {
+        status: Status::NoPrefixAvail,
+        msg: "No prefixes available".into(),
+    }));
+    DhcpOption::IAPD(IAPD {
+        id: iaid,
+        t1: 0,
+        t2: 0,
+        opts: ia_opts,
+    })
+}
+
+/// Reply contains IA_NA address and IA_PD prefix as options, for whichever
+/// of the two the client asked for. These options contain nested options
+/// with the actual addresses/prefixes: ReplyOptions [IAPD[IAPrefix], IANA[IAAddr]].
+/// A reservation without an address answers IA_NA with NoAddrsAvail.
+pub fn insert_reserved_ias(
+    opts: &mut DhcpOptions,
+    msg: &Message,
+    reservation: &Reservation,
+    lease_times: &LeaseTimes,
+) {
+    // TODO: should this scan for multiple IANA options?
+    if let Some(iana) = msg.ia_na() {
+        opts.insert(match reservation.ipv6_na {
+            Some(addr) => build_ia_na(iana.id, addr, lease_times),
+            // Prefix delegation only: RFC 8415 §18.3.1/§18.3.2, the IA
+            // comes back empty with NoAddrsAvail and the IA_PD still holds
+            None => build_no_addrs_ia_na(iana.id),
+        });
+    }
+    if let Some(iapd) = msg.ia_pd() {
+        opts.insert(build_ia_pd(iapd.id, reservation.ipv6_pd, lease_times));
+    }
+}
+
+/// RFC 8415 Section 18.4.2: If the server cannot find a client entry for the
+/// IA, the server returns the IA containing no addresses/prefixes with a
+/// Status Code option set to NoBinding in the Reply message. Every IA the
+/// client sent is echoed back with its lifetimes zeroed.
+pub fn build_no_binding(opts: &mut DhcpOptions, msg: &Message) {
+    for opt in msg.opts().iter() {
+        match opt {
+            DhcpOption::IANA(iana) => {
+                let mut iana_new = iana.clone();
+                for ia_opt in iana_new.opts.iter_mut() {
+                    if let DhcpOption::IAAddr(addr) = ia_opt {
+                        addr.valid_life = 0;
+                        addr.preferred_life = 0;
+                    }
+                }
+                iana_new.opts.insert(no_binding_status());
+                opts.insert(DhcpOption::IANA(iana_new));
+            }
+            DhcpOption::IAPD(iapd) => {
+                let mut iapd_new = iapd.clone();
+                for ia_opt in iapd_new.opts.iter_mut() {
+                    if let DhcpOption::IAPrefix(prefix) = ia_opt {
+                        prefix.valid_lifetime = 0;
+                        prefix.preferred_lifetime = 0;
+                    }
+                }
+                iapd_new.opts.insert(no_binding_status());
+                opts.insert(DhcpOption::IAPD(iapd_new));
+            }
+            _ => (),
+        }
+    }
+}
+
+/// RFC 8415 §18.3.5: addresses and prefixes a client is rebinding that
+/// aren't its reservation, such as ones left over from another server or an
+/// older reservation, are returned with lifetimes of 0 so the client drops
+/// them instead of using them until they expire. They go in the reply IA
+/// with the same IAID, or in an IA of their own when the reservation isn't
+/// being handed out under that IAID. Returns how many were zeroed.
+pub fn insert_inappropriate_leases(
+    opts: &mut DhcpOptions,
+    msg: &Message,
+    reservation: &Reservation,
+) -> usize {
+    let mut zeroed = 0;
+    for opt in msg.opts().iter() {
+        match opt {
+            DhcpOption::IANA(iana) => {
+                let stale: Vec<_> = iana
+                    .opts
+                    .iter()
+                    .filter_map(|ia_opt| match ia_opt {
+                        DhcpOption::IAAddr(addr)
+                            if !addr.addr.is_unspecified()
+                                && Some(addr.addr) != reservation.ipv6_na =>
+                        {
+                            Some(DhcpOption::IAAddr(IAAddr {
+                                addr: addr.addr,
+                                preferred_life: 0,
+                                valid_life: 0,
+                                opts: DhcpOptions::new(),
+                            }))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                if stale.is_empty() {
+                    continue;
+                }
+                zeroed += stale.len();
+                let reply_iana = opts.iter_mut().find_map(|reply_opt| match reply_opt {
+                    DhcpOption::IANA(reply) if reply.id == iana.id => Some(reply),
+                    _ => None,
+                });
+                match reply_iana {
+                    Some(reply) => extend_ia_opts(&mut reply.opts, stale),
+                    None => {
+                        let mut ia_opts = DhcpOptions::new();
+                        extend_ia_opts(&mut ia_opts, stale);
+                        opts.insert(DhcpOption::IANA(IANA {
+                            id: iana.id,
+                            t1: 0,
+                            t2: 0,
+                            opts: ia_opts,
+                        }));
+                    }
+                }
+            }
+            DhcpOption::IAPD(iapd) => {
+                let stale: Vec<_> = iapd
+                    .opts
+                    .iter()
+                    .filter_map(|ia_opt| match ia_opt {
+                        DhcpOption::IAPrefix(prefix)
+                            if !prefix.prefix_ip.is_unspecified()
+                                && Ipv6Net::new(prefix.prefix_ip, prefix.prefix_len)
+                                    .ok()
+                                    .map(|net| net.trunc())
+                                    != Some(reservation.ipv6_pd.trunc()) =>
+                        {
+                            Some(DhcpOption::IAPrefix(IAPrefix {
+                                preferred_lifetime: 0,
+                                valid_lifetime: 0,
+                                prefix_len: prefix.prefix_len,
+                                prefix_ip: prefix.prefix_ip,
+                                opts: DhcpOptions::new(),
+                            }))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                if stale.is_empty() {
+                    continue;
+                }
+                zeroed += stale.len();
+                let reply_iapd = opts.iter_mut().find_map(|reply_opt| match reply_opt {
+                    DhcpOption::IAPD(reply) if reply.id == iapd.id => Some(reply),
+                    _ => None,
+                });
+                match reply_iapd {
+                    Some(reply) => extend_ia_opts(&mut reply.opts, stale),
+                    None => {
+                        let mut ia_opts = DhcpOptions::new();
+                        extend_ia_opts(&mut ia_opts, stale);
+                        opts.insert(DhcpOption::IAPD(IAPD {
+                            id: iapd.id,
+                            t1: 0,
+                            t2: 0,
+                            opts: ia_opts,
+                        }));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    zeroed
+}
+
+/// RFC 8415 §18.3.7/§18.3.8: each IA in a Release or Decline that isn't
+/// bound to the client comes back under its IAID holding only a NoBinding
+/// status. An IA is bound when it carries the reserved address (IA_NA) or
+/// prefix (IA_PD); without a reservation none are. Returns whether the
+/// reserved address was among the IAs, so the caller can act on it.
+pub fn insert_unbound_ias(
+    opts: &mut DhcpOptions,
+    msg: &Message,
+    reservation: Option<&Reservation>,
+) -> bool {
+    let mut na_bound = false;
+    for opt in msg.opts().iter() {
+        match opt {
+            DhcpOption::IANA(iana) => {
+                let bound = reservation.is_some_and(|reservation| {
+                    iana.opts.iter().any(|ia_opt| {
+                        matches!(ia_opt, DhcpOption::IAAddr(addr) if Some(addr.addr) == reservation.ipv6_na)
+                    })
+                });
+                na_bound |= bound;
+                if !bound {
+                    let mut ia_opts = DhcpOptions::new();
+                    ia_opts.insert(no_binding_status());
+                    opts.insert(DhcpOption::IANA(IANA {
+                        id: iana.id,
+                        t1: 0,
+                        t2: 0,
+                        opts: ia_opts,
+                    }));
+                }
+            }
+            DhcpOption::IAPD(iapd) => {
+                let bound = reservation.is_some_and(|reservation| {
+                    iapd.opts.iter().any(|ia_opt| match ia_opt {
+                        DhcpOption::IAPrefix(prefix) => {
+                            Ipv6Net::new(prefix.prefix_ip, prefix.prefix_len)
+                                .is_ok_and(|net| net.trunc() == reservation.ipv6_pd.trunc())
+                        }
+                        _ => false,
+                    })
+                });
+                if !bound {
+                    let mut ia_opts = DhcpOptions::new();
+                    ia_opts.insert(no_binding_status());
+                    opts.insert(DhcpOption::IAPD(IAPD {
+                        id: iapd.id,
+                        t1: 0,
+                        t2: 0,
+                        opts: ia_opts,
+                    }));
+                }
+            }
+            _ => (),
+        }
+    }
+    na_bound
+}
+
+fn extend_ia_opts(ia_opts: &mut DhcpOptions, stale: Vec<DhcpOption>) {
+    for opt in stale {
+        ia_opts.insert(opt);
+    }
+}
+
+/// RFC 8415 §21.13 allows a Success status with a message inside an IA.
+/// Puts one in each reserved IA whose hint the client won't get, so the
+/// reason shows up in the CPE's own logs.
+pub fn insert_hint_notes(opts: &mut DhcpOptions, msg: &Message, reservation: &Reservation) {
+    let na_differs = reservation
+        .ipv6_na
+        .is_some_and(|na| msg.ia_na_hint_differs(na));
+    let pd_differs = msg.ia_pd_hint_differs(reservation.ipv6_pd);
+    for opt in opts.iter_mut() {
+        match opt {
+            DhcpOption::IANA(iana) if na_differs => {
+                iana.opts
+                    .insert(hint_status("Reserved address differs from hint"));
+            }
+            DhcpOption::IAPD(iapd) if pd_differs => {
+                iapd.opts
+                    .insert(hint_status("Reserved prefix differs from hint"));
+            }
+            _ => (),
+        }
+    }
+}
+
+fn hint_status(msg: &str) -> DhcpOption {
+    DhcpOption::StatusCode(StatusCode {
+        status: Status::Success,
+        msg: msg.into(),
+    })
+}
+
+fn no_binding_status() -> DhcpOption {
+    DhcpOption::StatusCode(StatusCode {
+        status: Status::NoBinding,
+        msg: "No binding for this IA".into(),
+    })
+}
+
+/// Options that come straight from the config: DNS servers, domain search
+/// list, SNTP and NTP servers. dhcproto has no typed variants for the last
+/// three, so they are encoded here.
+///
+/// None of them is required in a reply, so each is only sent when `msg`'s
+/// Option Request lists it (RFC 8415 §18.3), unless `v6_always_send_options`
+/// is set. A client with no Option Request gets them all, as it did before
+/// the server read it.
+pub fn insert_config_options(
+    opts: &mut DhcpOptions,
+    config: &Config,
+    profile: &Profile,
+    msg: &Message,
+) {
+    let requested = (!config.v6_always_send_options)
+        .then(|| msg.requested_options())
+        .flatten();
+    let wanted = |code: u16| requested.as_ref().is_none_or(|oro| oro.contains(&code));
+
+    if wanted(OPTION_DNS_SERVERS) {
+        opts.insert(DhcpOption::DomainNameServers(profile.dns_v6.to_vec()));
+    }
+    if !profile.domain_search_v6.is_empty() && wanted(OPTION_DOMAIN_LIST) {
+        let mut data = Vec::new();
+        for domain in profile.domain_search_v6 {
+            encode_domain(&mut data, domain);
+        }
+        opts.insert(raw_option(OPTION_DOMAIN_LIST, data));
+    }
+    if !profile.sntp_v6.is_empty() && wanted(OPTION_SNTP_SERVERS) {
+        let data = profile
+            .sntp_v6
+            .iter()
+            .flat_map(|addr| addr.octets())
+            .collect();
+        opts.insert(raw_option(OPTION_SNTP_SERVERS, data));
+    }
+    if !profile.ntp_v6.is_empty() && wanted(OPTION_NTP_SERVER) {
+        let mut data = Vec::with_capacity(profile.ntp_v6.len() * 20);
+        for addr in profile.ntp_v6 {
+            data.extend(NTP_SUBOPTION_SRV_ADDR.to_be_bytes());
+            data.extend(16u16.to_be_bytes());
+            data.extend(addr.octets());
+        }
+        opts.insert(raw_option(OPTION_NTP_SERVER, data));
+    }
+}
+
+fn raw_option(code: u16, data: Vec<u8>) -> DhcpOption {
+    DhcpOption::Unknown(UnknownOption::new(OptionCode::from(code), data))
+}
+
+/// Uncompressed DNS wire format (RFC 1035 §3.1). Names are checked when the
+/// config is loaded.
+fn encode_domain(buf: &mut Vec<u8>, domain: &str) {
+    for label in domain.trim_end_matches('.').split('.') {
+        buf.push(label.len() as u8);
+        buf.extend(label.as_bytes());
+    }
+    buf.push(0);
+}
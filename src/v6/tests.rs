@@ -1,21 +1,26 @@
-use crate::types::{Duid, Option82, Reservation, V4Subnet};
+use crate::types::{Duid, Option1837, Option82, Reservation, V4Subnet};
 use advmac::MacAddr6;
 use dhcproto::{
     v6::{
         ClientLinklayerAddress, DhcpOption, DhcpOptions, IAAddr, IAPrefix, Message, MessageType,
-        RelayMessage, RelayMessageData, IANA, IAPD,
+        OptionCode, RelayMessage, RelayMessageData, Status, UnknownOption, IANA, IAPD,
     },
-    Decodable,
+    Decodable, Encodable,
 };
 use ipnet::Ipv6Net;
 
-use crate::config::{Config, LeaseTimes};
+use crate::analytics::events::DhcpEventV6;
+use crate::config::{Config, LeaseTimes, SubscriberNetwork};
 use crate::opt82_cache::Opt82Cache;
 use crate::reservationdb::ReservationDb;
+use crate::testutil;
 use crate::v6::extractors as v6_extractors;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 
-use crate::v6::{extensions::ShadowMessageExtV6, handlers::DhcpV6Response};
+use crate::v6::{
+    extensions::ShadowMessageExtV6,
+    handlers::{relay_reply, reply_destination, DhcpV6Response, NoResponse},
+};
 
 const RESERVATION_MAC: MacAddr6 = MacAddr6::new([0, 1, 2, 3, 4, 5]);
 
@@ -26,7 +31,10 @@ fn create_env() -> (Config, ReservationDb, Opt82Cache) {
             net: "192.168.0.0/24".parse().unwrap(),
             gateway: "192.168.0.1".parse().unwrap(),
             reply_prefix_len: None,
-        }],
+            authoritative: false,
+            ranges: vec![],
+        }]
+        .into(),
         v6_server_id: Duid::from(vec![0, 1, 2, 3]),
         dns_v6: vec!["2001:4860:4860::8888".parse().unwrap()],
         option1837_extractors: v6_extractors::get_all_extractors().into_iter().collect(),
@@ -34,13 +42,23 @@ fn create_env() -> (Config, ReservationDb, Opt82Cache) {
     };
 
     let reservation = Reservation {
-        ipv4: Ipv4Addr::new(192, 168, 0, 10),
-        ipv6_na: "2001:db8::1".parse().unwrap(),
+        ipv4: Some(Ipv4Addr::new(192, 168, 0, 10)),
+        ipv6_na: Some("2001:db8::1".parse().unwrap()),
         ipv6_pd: "2001:db8:100::/56".parse::<Ipv6Net>().unwrap(),
         mac: Some(RESERVATION_MAC),
         duid: Some(Duid::from(vec![0xaa, 0xbb, 0xcc])),
+        client_id: None,
         option82: None,
         option1837: None,
+        svlan: None,
+        cvlan: None,
+        subscriber_id: None,
+        static_routes: vec![],
+        boot: None,
+        profile: None,
+        activate_at: None,
+        deactivate_at: None,
+        suspended: false,
     };
 
     let reservations = ReservationDb::new();
@@ -52,16 +70,7 @@ fn create_env() -> (Config, ReservationDb, Opt82Cache) {
 }
 
 fn create_relay_forw(msg: &dhcproto::v6::Message) -> RelayMessage {
-    let mut relay_opts = DhcpOptions::new();
-    relay_opts.insert(DhcpOption::RelayMsg(RelayMessageData::Message(msg.clone())));
-
-    RelayMessage {
-        msg_type: MessageType::RelayForw,
-        hop_count: 0,
-        link_addr: Ipv6Addr::UNSPECIFIED,
-        peer_addr: Ipv6Addr::UNSPECIFIED,
-        opts: relay_opts,
-    }
+    testutil::relay_forw(msg, Ipv6Addr::UNSPECIFIED, Ipv6Addr::UNSPECIFIED)
 }
 
 // Generated with GPT-5.2 instant
@@ -89,7 +98,7 @@ fn request_echoes_iaid() {
         opts: {
             let mut o = DhcpOptions::new();
             o.insert(DhcpOption::IAAddr(IAAddr {
-                addr: reservation.ipv6_na,
+                addr: reservation.ipv6_na.unwrap(),
                 preferred_life: 100,
                 valid_life: 200,
                 opts: DhcpOptions::new(),
@@ -214,7 +223,7 @@ fn renew_echoes_iaid() {
         opts: {
             let mut o = DhcpOptions::new();
             o.insert(DhcpOption::IAAddr(IAAddr {
-                addr: reservation.ipv6_na,
+                addr: reservation.ipv6_na.unwrap(),
                 preferred_life: 100,
                 valid_life: 200,
                 opts: DhcpOptions::new(),
@@ -273,7 +282,8 @@ fn renew_with_incorrect_iana_address_returns_reserved_address() {
 
     let incorrect_addr: Ipv6Addr = "2001:db8::dead".parse().unwrap();
     assert_ne!(
-        incorrect_addr, reservation.ipv6_na,
+        incorrect_addr,
+        reservation.ipv6_na.unwrap(),
         "Test setup error: incorrect address matches reservation"
     );
 
@@ -323,7 +333,8 @@ fn renew_with_incorrect_iana_address_returns_reserved_address() {
 
     let returned_addr = resp.ia_na_address().expect("Returned IANA missing IAAddr");
     assert_eq!(
-        returned_addr, reservation.ipv6_na,
+        returned_addr,
+        reservation.ipv6_na.unwrap(),
         "Server must return reserved IPv6 address, not the incorrect one"
     );
 }
@@ -552,7 +563,7 @@ fn renew_with_incorrect_iana_and_iapd_returns_reserved_values() {
     let returned_na = resp.ia_na_address().unwrap();
     let returned_pd = resp.ia_pd_prefix().unwrap();
 
-    assert_eq!(returned_na, reservation.ipv6_na);
+    assert_eq!(returned_na, reservation.ipv6_na.unwrap());
     assert_eq!(returned_pd, reservation.ipv6_pd);
     assert_eq!(returned_iana.id, 10);
     assert_eq!(returned_iapd.id, 20);
@@ -614,21 +625,24 @@ fn kea_advertise() {
     assert_eq!(msg.xid(), [164, 207, 112]);
 }
 
+/// Request from a Mikrotik CPE, client DUID 00:03:00:01:08:55:31:8f:19:94,
+/// for IA_NA 2605:cb40:8020::2 and IA_PD 2605:cb40:8020:100::/56 (IAID 5)
+/// from a Kea server with DUID 00:01:00:01:2d:f2:39:c7:bc:24:11:a7:20:34
+const MIKROTIK_REQUEST: [u8; 137] = [
+    0x03, 0x2a, 0xcb, 0x85, 0x00, 0x01, 0x00, 0x0a, 0x00, 0x03, 0x00, 0x01, 0x08, 0x55, 0x31, 0x8f,
+    0x19, 0x94, 0x00, 0x02, 0x00, 0x0e, 0x00, 0x01, 0x00, 0x01, 0x2d, 0xf2, 0x39, 0xc7, 0xbc, 0x24,
+    0x11, 0xa7, 0x20, 0x34, 0x00, 0x03, 0x00, 0x28, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x03, 0xe8,
+    0x00, 0x00, 0x07, 0xd0, 0x00, 0x05, 0x00, 0x18, 0x26, 0x05, 0xcb, 0x40, 0x80, 0x20, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x0b, 0xb8, 0x00, 0x00, 0x0f, 0xa0,
+    0x00, 0x06, 0x00, 0x02, 0x00, 0x17, 0x00, 0x08, 0x00, 0x02, 0x00, 0x00, 0x00, 0x19, 0x00, 0x29,
+    0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x07, 0xd0, 0x00, 0x1a, 0x00, 0x19,
+    0x00, 0x00, 0x0b, 0xb8, 0x00, 0x00, 0x0f, 0xa0, 0x38, 0x26, 0x05, 0xcb, 0x40, 0x80, 0x20, 0x01,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
 #[test]
 fn mikrotik_request() {
-    let packet_bytes: [u8; 137] = [
-        0x03, 0x2a, 0xcb, 0x85, 0x00, 0x01, 0x00, 0x0a, 0x00, 0x03, 0x00, 0x01, 0x08, 0x55, 0x31,
-        0x8f, 0x19, 0x94, 0x00, 0x02, 0x00, 0x0e, 0x00, 0x01, 0x00, 0x01, 0x2d, 0xf2, 0x39, 0xc7,
-        0xbc, 0x24, 0x11, 0xa7, 0x20, 0x34, 0x00, 0x03, 0x00, 0x28, 0x00, 0x00, 0x00, 0x05, 0x00,
-        0x00, 0x03, 0xe8, 0x00, 0x00, 0x07, 0xd0, 0x00, 0x05, 0x00, 0x18, 0x26, 0x05, 0xcb, 0x40,
-        0x80, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x0b,
-        0xb8, 0x00, 0x00, 0x0f, 0xa0, 0x00, 0x06, 0x00, 0x02, 0x00, 0x17, 0x00, 0x08, 0x00, 0x02,
-        0x00, 0x00, 0x00, 0x19, 0x00, 0x29, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x03, 0xe8, 0x00,
-        0x00, 0x07, 0xd0, 0x00, 0x1a, 0x00, 0x19, 0x00, 0x00, 0x0b, 0xb8, 0x00, 0x00, 0x0f, 0xa0,
-        0x38, 0x26, 0x05, 0xcb, 0x40, 0x80, 0x20, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-        0x00, 0x00,
-    ];
-    let msg = Message::from_bytes(&packet_bytes).unwrap();
+    let msg = Message::from_bytes(&MIKROTIK_REQUEST).unwrap();
     assert!(matches!(msg.msg_type(), MessageType::Request));
     assert_eq!(msg.xid(), [42, 203, 133]);
 }
@@ -766,7 +780,7 @@ fn dynamic_opt82_binding() {
     "#;
     let reservations: Vec<Reservation> = serde_json::from_str(json_str).unwrap();
     let db = ReservationDb::new();
-    db.load_reservations(reservations);
+    db.load_reservations(reservations).unwrap();
     let leases = Opt82Cache::new();
     let opt82 = Option82 {
         circuit: Some("99-11-22-33-44-55".into()),
@@ -817,14 +831,104 @@ fn dynamic_opt82_binding() {
     assert!(matches!(resp.msg_type(), MessageType::Advertise));
 
     let reservation = db.by_opt82(&opt82).unwrap();
-    assert_eq!(resp.ia_na_address().unwrap(), reservation.ipv6_na);
+    assert_eq!(resp.ia_na_address(), reservation.ipv6_na);
     assert_eq!(resp.ia_pd_prefix().unwrap(), reservation.ipv6_pd);
 }
 
-/// RFC 8415 Section 21.8: Advertise messages should include Preference option
 #[test]
-fn advertise_includes_preference_option() {
+fn dynamic_opt1837_binding() {
     let (config, reservations, leases) = create_env();
+    let opt1837 = Option1837 {
+        interface: Some("eth0:100".into()),
+        remote: None,
+        enterprise_number: None,
+    };
+    reservations.insert(Reservation {
+        ipv4: Some(Ipv4Addr::new(192, 168, 0, 20)),
+        ipv6_na: Some("2001:db8::20".parse().unwrap()),
+        ipv6_pd: "2001:db8:200::/56".parse::<Ipv6Net>().unwrap(),
+        mac: None,
+        duid: None,
+        client_id: None,
+        option82: None,
+        option1837: Some(opt1837.clone()),
+        svlan: None,
+        cvlan: None,
+        subscriber_id: None,
+        static_routes: vec![],
+        boot: None,
+        profile: None,
+        activate_at: None,
+        deactivate_at: None,
+        suspended: false,
+    });
+
+    let client_id = vec![0x00, 0x03, 0x00, 0x01, 0xde, 0xad, 0xbe, 0xef, 0x00, 0x01];
+    let mut msg = Message::new(MessageType::Request);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(client_id.clone()));
+    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+
+    let mut relay_msg = create_relay_forw(&msg);
+    relay_msg
+        .opts
+        .insert(DhcpOption::InterfaceId(b"eth0:100".to_vec()));
+
+    match crate::v6::handlers::handle_message(&config, &reservations, &leases, &msg, &relay_msg) {
+        DhcpV6Response::Message(resp) => {
+            assert!(matches!(resp.message.msg_type(), MessageType::Reply));
+            assert_eq!(
+                resp.message.ia_na_address().unwrap(),
+                "2001:db8::20".parse::<Ipv6Addr>().unwrap()
+            );
+        }
+        DhcpV6Response::NoResponse(reason) => panic!("Expected Reply, got {reason:?}"),
+    }
+    assert_eq!(
+        leases.get_opt1837_by_duid(&Duid::from(client_id.clone())),
+        Some(opt1837),
+        "Option 18/37 binding should be created on Reply"
+    );
+
+    // Renew relayed without Option 18/37 still matches through the binding
+    let mut msg = Message::new(MessageType::Renew);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(client_id));
+    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+    let relay_msg = create_relay_forw(&msg);
+
+    match crate::v6::handlers::handle_message(&config, &reservations, &leases, &msg, &relay_msg) {
+        DhcpV6Response::Message(resp) => {
+            assert_eq!(
+                resp.message.ia_na_address().unwrap(),
+                "2001:db8::20".parse::<Ipv6Addr>().unwrap()
+            );
+            assert_eq!(
+                resp.reservation_match.unwrap().extractor,
+                Some("duid_binding")
+            );
+        }
+        DhcpV6Response::NoResponse(reason) => panic!("Expected Reply, got {reason:?}"),
+    }
+}
+
+/// RFC 8415 Section 21.8: Advertise messages carry the configured Preference
+#[test]
+fn advertise_includes_configured_preference_option() {
+    let (mut config, reservations, leases) = create_env();
+    config.v6_preference = Some(255);
 
     let mut msg = Message::new(MessageType::Solicit);
     let opts = msg.opts_mut();
@@ -859,9 +963,45 @@ fn advertise_includes_preference_option() {
             DhcpOption::Preference(pref) => Some(*pref),
             _ => None,
         })
-        .expect("Advertise should include Preference option per RFC 8415");
+        .expect("Advertise should include the configured Preference option");
+
+    assert_eq!(preference, 255, "Preference should be the configured value");
+}
+
+/// Without a configured preference the client is free to collect other
+/// servers' Advertises
+#[test]
+fn advertise_omits_preference_by_default() {
+    let (config, reservations, leases) = create_env();
+
+    let mut msg = Message::new(MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+
+    let relay_msg = create_relay_forw(&msg);
+
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Advertise response"),
+    };
 
-    assert_eq!(preference, 255, "Preference should be 255 (maximum)");
+    assert!(matches!(resp.msg_type(), MessageType::Advertise));
+    assert!(!resp
+        .opts()
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::Preference(_))));
 }
 
 /// RFC 8415: Reply messages (rapid commit) should NOT include Preference option
@@ -916,6 +1056,49 @@ fn rapid_commit_reply_does_not_include_preference() {
     );
 }
 
+/// RFC 8415 §18.3.1: with Rapid Commit disabled the Solicit is answered as
+/// if the option were absent
+#[test]
+fn rapid_commit_disabled_falls_back_to_advertise() {
+    let (mut config, reservations, leases) = create_env();
+    config.rapid_commit_enabled = false;
+    config.v6_preference = Some(255);
+
+    let mut msg = Message::new(MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::RapidCommit);
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+
+    let relay_msg = create_relay_forw(&msg);
+
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Advertise response"),
+    };
+
+    assert!(matches!(resp.msg_type(), MessageType::Advertise));
+    assert!(resp
+        .opts()
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::Preference(255))));
+    assert!(!resp
+        .opts()
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::RapidCommit)));
+}
+
 /// RFC 8415 §21.4, §21.21: T1 = 0.5·preferred, T2 = 0.8·preferred,
 /// preferred = 0.5·valid, and T1 < T2 < preferred < valid.
 #[test]
@@ -931,6 +1114,37 @@ fn lease_times_derivation_is_rfc_compliant() {
     }
 }
 
+#[test]
+fn ia_builders_use_configured_lease_times() {
+    let lt = LeaseTimes::from_base(3600, 7200);
+    let prefix: Ipv6Net = "2001:db8:100::/56".parse().unwrap();
+
+    let DhcpOption::IAPD(iapd) = crate::v6::response::build_ia_pd(7, prefix, &lt) else {
+        panic!("Expected IA_PD");
+    };
+    assert_eq!(
+        (iapd.id, iapd.t1, iapd.t2),
+        (7, lt.v6_renewal, lt.v6_rebinding)
+    );
+    let DhcpOption::IAPrefix(ia_prefix) = iapd.opts.iter().next().unwrap() else {
+        panic!("Expected IAPrefix");
+    };
+    assert_eq!(ia_prefix.prefix_ip, prefix.addr());
+    assert_eq!(ia_prefix.prefix_len, 56);
+    assert_eq!(ia_prefix.preferred_lifetime, lt.v6_preferred);
+    assert_eq!(ia_prefix.valid_lifetime, lt.v6_valid);
+
+    let DhcpOption::IANA(iana) =
+        crate::v6::response::build_ia_na(9, "2001:db8::1".parse().unwrap(), &lt)
+    else {
+        panic!("Expected IA_NA");
+    };
+    assert_eq!(
+        (iana.id, iana.t1, iana.t2),
+        (9, lt.v6_renewal, lt.v6_rebinding)
+    );
+}
+
 /// Verify that Solicit response contains correct T1/T2 values per RFC 8415
 #[test]
 fn solicit_response_includes_dns_servers() {
@@ -967,8 +1181,27 @@ fn solicit_response_includes_dns_servers() {
 }
 
 #[test]
-fn solicit_response_has_correct_t1_t2() {
-    let (config, reservations, leases) = create_env();
+fn subscriber_network_selected_by_link_address() {
+    let (mut config, reservations, leases) = create_env();
+    let business_dns: Ipv6Addr = "2001:db8:53::1".parse().unwrap();
+    config.subscriber_networks = vec![SubscriberNetwork {
+        name: "business".into(),
+        relays_v4: vec![],
+        relays_v6: vec!["2001:db8:b::/48".parse().unwrap()],
+        dns_v4: None,
+        dns_v6: Some(vec![business_dns]),
+        domain_search_v6: None,
+        sntp_v6: None,
+        ntp_v6: None,
+        ntp_v4: None,
+        interface_mtu: None,
+        domain_name: None,
+        static_routes: None,
+        v4_lease_time: None,
+        v6_lease_time: Some(1800),
+        authoritative: None,
+        v6_advertise_unknown_clients: None,
+    }];
 
     let mut msg = Message::new(MessageType::Solicit);
     let opts = msg.opts_mut();
@@ -979,15 +1212,9 @@ fn solicit_response_has_correct_t1_t2() {
         t2: 0,
         opts: DhcpOptions::new(),
     }));
-    opts.insert(DhcpOption::IAPD(IAPD {
-        id: 2,
-        t1: 0,
-        t2: 0,
-        opts: DhcpOptions::new(),
-    }));
-
-    let relay_msg = create_relay_forw(&msg);
 
+    let mut relay_msg = create_relay_forw(&msg);
+    relay_msg.link_addr = "2001:db8:b:1::1".parse().unwrap();
     let resp = match crate::v6::handlers::handle_message(
         &config,
         &reservations,
@@ -996,83 +1223,54 @@ fn solicit_response_has_correct_t1_t2() {
         &relay_msg,
     ) {
         DhcpV6Response::Message(resp) => resp.message,
-        _ => panic!("Expected Advertise response"),
+        _ => panic!("Expected response"),
     };
+    let dns = resp.opts().iter().find_map(|o| match o {
+        DhcpOption::DomainNameServers(addrs) => Some(addrs.clone()),
+        _ => None,
+    });
+    assert_eq!(dns, Some(vec![business_dns]));
+    let iana = resp.opts().iter().find_map(|o| match o {
+        DhcpOption::IANA(iana) => Some(iana.clone()),
+        _ => None,
+    });
+    let lt = LeaseTimes::from_base(config.lease_times.v4_lease, 1800);
+    assert_eq!(iana.map(|iana| iana.t1), Some(lt.v6_renewal));
 
-    let iana = resp.ia_na().expect("Response missing IA_NA");
-    let iapd = resp.ia_pd().expect("Response missing IA_PD");
-
-    // Verify T1 and T2 are set correctly per RFC 8415
-    assert_eq!(
-        iana.t1,
-        LeaseTimes::default().v6_renewal,
-        "IA_NA T1 should be LeaseTimes::default().v6_renewal"
-    );
-    assert_eq!(
-        iana.t2,
-        LeaseTimes::default().v6_rebinding,
-        "IA_NA T2 should be LeaseTimes::default().v6_rebinding"
-    );
-    assert_eq!(
-        iapd.t1,
-        LeaseTimes::default().v6_renewal,
-        "IA_PD T1 should be LeaseTimes::default().v6_renewal"
-    );
-    assert_eq!(
-        iapd.t2,
-        LeaseTimes::default().v6_rebinding,
-        "IA_PD T2 should be LeaseTimes::default().v6_rebinding"
-    );
-
-    // Verify preferred and valid lifetimes in nested options
-    let ia_addr = iana
-        .opts
-        .iter()
-        .find_map(|o| match o {
-            DhcpOption::IAAddr(addr) => Some(addr),
-            _ => None,
-        })
-        .expect("IA_NA missing IAAddr");
-    assert_eq!(ia_addr.preferred_life, LeaseTimes::default().v6_preferred);
-    assert_eq!(ia_addr.valid_life, LeaseTimes::default().v6_valid);
-
-    let ia_prefix = iapd
-        .opts
-        .iter()
-        .find_map(|o| match o {
-            DhcpOption::IAPrefix(prefix) => Some(prefix),
-            _ => None,
-        })
-        .expect("IA_PD missing IAPrefix");
-    assert_eq!(
-        ia_prefix.preferred_lifetime,
-        LeaseTimes::default().v6_preferred
-    );
-    assert_eq!(ia_prefix.valid_lifetime, LeaseTimes::default().v6_valid);
+    // An unspecified link-address matches no network
+    let relay_msg = create_relay_forw(&msg);
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected response"),
+    };
+    let dns = resp.opts().iter().find_map(|o| match o {
+        DhcpOption::DomainNameServers(addrs) => Some(addrs.clone()),
+        _ => None,
+    });
+    assert_eq!(dns.as_deref(), Some(config.dns_v6.as_slice()));
 }
 
-/// Verify that Request response contains correct T1/T2 values per RFC 8415
 #[test]
-fn request_response_has_correct_t1_t2() {
-    let (config, reservations, leases) = create_env();
+fn solicit_response_includes_search_list_and_ntp() {
+    let (mut config, reservations, leases) = create_env();
+    config.domain_search_v6 = vec!["example.net".into()];
+    config.ntp_v6 = vec!["2001:db8::123".parse().unwrap()];
 
-    let mut msg = Message::new(MessageType::Request);
+    let mut msg = Message::new(MessageType::Solicit);
     let opts = msg.opts_mut();
     opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
-    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
     opts.insert(DhcpOption::IANA(IANA {
         id: 1,
         t1: 0,
         t2: 0,
         opts: DhcpOptions::new(),
     }));
-    opts.insert(DhcpOption::IAPD(IAPD {
-        id: 2,
-        t1: 0,
-        t2: 0,
-        opts: DhcpOptions::new(),
-    }));
-
     let relay_msg = create_relay_forw(&msg);
 
     let resp = match crate::v6::handlers::handle_message(
@@ -1083,46 +1281,382 @@ fn request_response_has_correct_t1_t2() {
         &relay_msg,
     ) {
         DhcpV6Response::Message(resp) => resp.message,
-        _ => panic!("Expected Reply response"),
+        _ => panic!("Expected response"),
     };
+    let bytes = resp.to_vec().unwrap();
 
-    let iana = resp.ia_na().expect("Response missing IA_NA");
-    let iapd = resp.ia_pd().expect("Response missing IA_PD");
+    let search_list = [&[0, 24, 0, 13][..], &[7], b"example", &[3], b"net", &[0]].concat();
+    assert!(bytes.windows(search_list.len()).any(|w| w == search_list));
 
-    assert_eq!(
-        iana.t1,
-        LeaseTimes::default().v6_renewal,
-        "IA_NA T1 should be LeaseTimes::default().v6_renewal"
-    );
-    assert_eq!(
-        iana.t2,
-        LeaseTimes::default().v6_rebinding,
-        "IA_NA T2 should be LeaseTimes::default().v6_rebinding"
-    );
-    assert_eq!(
-        iapd.t1,
-        LeaseTimes::default().v6_renewal,
-        "IA_PD T1 should be LeaseTimes::default().v6_renewal"
-    );
-    assert_eq!(
-        iapd.t2,
-        LeaseTimes::default().v6_rebinding,
-        "IA_PD T2 should be LeaseTimes::default().v6_rebinding"
-    );
+    let ntp_addr: Ipv6Addr = "2001:db8::123".parse().unwrap();
+    let ntp = [&[0, 56, 0, 20, 0, 1, 0, 16][..], &ntp_addr.octets()].concat();
+    assert!(bytes.windows(ntp.len()).any(|w| w == ntp));
 }
 
-/// RFC 8415 Section 18.4.5: Rebind works without Server ID (unlike Renew)
 #[test]
-fn rebind_works_without_server_id() {
-    let (config, reservations, leases) = create_env();
-
-    let reservation = reservations
-        .by_mac(RESERVATION_MAC)
-        .expect("No reservation found");
+fn solicit_response_honors_option_request() {
+    let (mut config, reservations, leases) = create_env();
+    config.domain_search_v6 = vec!["example.net".into()];
+    config.ntp_v6 = vec!["2001:db8::123".parse().unwrap()];
 
-    let mut msg = Message::new(MessageType::Rebind);
+    let mut msg = Message::new(MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+    // Option Request for DNS servers only
+    opts.insert(DhcpOption::Unknown(UnknownOption::new(
+        OptionCode::from(6),
+        vec![0, 23],
+    )));
+    assert_eq!(msg.requested_options(), Some(vec![23]));
+    let relay_msg = create_relay_forw(&msg);
+
+    let codes = |config: &Config| match crate::v6::handlers::handle_message(
+        config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp
+            .message
+            .opts()
+            .iter()
+            .map(|opt| u16::from(OptionCode::from(opt)))
+            .collect::<Vec<_>>(),
+        _ => panic!("Expected response"),
+    };
+    let sent = codes(&config);
+    assert!(sent.contains(&23), "{sent:?}");
+    assert!(!sent.contains(&24) && !sent.contains(&56), "{sent:?}");
+    // Server and client identifiers and the IA are not optional
+    assert!(sent.contains(&1) && sent.contains(&2) && sent.contains(&3));
+
+    config.v6_always_send_options = true;
+    let sent = codes(&config);
+    assert!(sent.contains(&24) && sent.contains(&56), "{sent:?}");
+}
+
+#[test]
+fn solicit_hint_mismatch_offers_reservation_with_note() {
+    let (mut config, reservations, leases) = create_env();
+    config.v6_hint_status = true;
+    let reservation = reservations
+        .by_mac(RESERVATION_MAC)
+        .expect("No reservation found");
+
+    let mut msg = Message::new(MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: {
+            let mut o = DhcpOptions::new();
+            o.insert(DhcpOption::IAAddr(IAAddr {
+                addr: "2001:db8::beef".parse().unwrap(),
+                preferred_life: 0,
+                valid_life: 0,
+                opts: DhcpOptions::new(),
+            }));
+            o
+        },
+    }));
+    // Length-only hint that matches the reserved /56
+    opts.insert(DhcpOption::IAPD(IAPD {
+        id: 2,
+        t1: 0,
+        t2: 0,
+        opts: {
+            let mut o = DhcpOptions::new();
+            o.insert(DhcpOption::IAPrefix(IAPrefix {
+                prefix_ip: Ipv6Addr::UNSPECIFIED,
+                prefix_len: 56,
+                preferred_lifetime: 0,
+                valid_lifetime: 0,
+                opts: DhcpOptions::new(),
+            }));
+            o
+        },
+    }));
+    let relay_msg = create_relay_forw(&msg);
+
+    assert!(msg.ia_na_hint_differs(reservation.ipv6_na.unwrap()));
+    assert!(!msg.ia_pd_hint_differs(reservation.ipv6_pd));
+    assert!(msg.ia_pd_hint_differs("2001:db8:100::/48".parse().unwrap()));
+
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Advertise"),
+    };
+    assert_eq!(resp.ia_na_address(), reservation.ipv6_na);
+    assert_eq!(resp.ia_pd_prefix(), Some(reservation.ipv6_pd));
+
+    let has_note = |opts: &DhcpOptions| {
+        opts.iter()
+            .any(|o| matches!(o, DhcpOption::StatusCode(sc) if sc.status == Status::Success))
+    };
+    assert!(has_note(&resp.ia_na().unwrap().opts));
+    assert!(!has_note(&resp.ia_pd().unwrap().opts));
+
+    let event = DhcpEventV6::success(
+        &msg,
+        &relay_msg,
+        Ipv6Addr::LOCALHOST,
+        Some(reservation.as_ref()),
+        None,
+    );
+    assert!(event.ipv6_na_hint_mismatch);
+    assert!(!event.ipv6_pd_hint_mismatch);
+}
+
+#[test]
+fn solicit_response_has_correct_t1_t2() {
+    let (config, reservations, leases) = create_env();
+
+    let mut msg = Message::new(MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+    opts.insert(DhcpOption::IAPD(IAPD {
+        id: 2,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+
+    let relay_msg = create_relay_forw(&msg);
+
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Advertise response"),
+    };
+
+    let iana = resp.ia_na().expect("Response missing IA_NA");
+    let iapd = resp.ia_pd().expect("Response missing IA_PD");
+
+    // Verify T1 and T2 are set correctly per RFC 8415
+    assert_eq!(
+        iana.t1,
+        LeaseTimes::default().v6_renewal,
+        "IA_NA T1 should be LeaseTimes::default().v6_renewal"
+    );
+    assert_eq!(
+        iana.t2,
+        LeaseTimes::default().v6_rebinding,
+        "IA_NA T2 should be LeaseTimes::default().v6_rebinding"
+    );
+    assert_eq!(
+        iapd.t1,
+        LeaseTimes::default().v6_renewal,
+        "IA_PD T1 should be LeaseTimes::default().v6_renewal"
+    );
+    assert_eq!(
+        iapd.t2,
+        LeaseTimes::default().v6_rebinding,
+        "IA_PD T2 should be LeaseTimes::default().v6_rebinding"
+    );
+
+    // Verify preferred and valid lifetimes in nested options
+    let ia_addr = iana
+        .opts
+        .iter()
+        .find_map(|o| match o {
+            DhcpOption::IAAddr(addr) => Some(addr),
+            _ => None,
+        })
+        .expect("IA_NA missing IAAddr");
+    assert_eq!(ia_addr.preferred_life, LeaseTimes::default().v6_preferred);
+    assert_eq!(ia_addr.valid_life, LeaseTimes::default().v6_valid);
+
+    let ia_prefix = iapd
+        .opts
+        .iter()
+        .find_map(|o| match o {
+            DhcpOption::IAPrefix(prefix) => Some(prefix),
+            _ => None,
+        })
+        .expect("IA_PD missing IAPrefix");
+    assert_eq!(
+        ia_prefix.preferred_lifetime,
+        LeaseTimes::default().v6_preferred
+    );
+    assert_eq!(ia_prefix.valid_lifetime, LeaseTimes::default().v6_valid);
+}
+
+/// Verify that Request response contains correct T1/T2 values per RFC 8415
+#[test]
+fn request_response_has_correct_t1_t2() {
+    let (config, reservations, leases) = create_env();
+
+    let mut msg = Message::new(MessageType::Request);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+    opts.insert(DhcpOption::IAPD(IAPD {
+        id: 2,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+
+    let relay_msg = create_relay_forw(&msg);
+
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Reply response"),
+    };
+
+    let iana = resp.ia_na().expect("Response missing IA_NA");
+    let iapd = resp.ia_pd().expect("Response missing IA_PD");
+
+    assert_eq!(
+        iana.t1,
+        LeaseTimes::default().v6_renewal,
+        "IA_NA T1 should be LeaseTimes::default().v6_renewal"
+    );
+    assert_eq!(
+        iana.t2,
+        LeaseTimes::default().v6_rebinding,
+        "IA_NA T2 should be LeaseTimes::default().v6_rebinding"
+    );
+    assert_eq!(
+        iapd.t1,
+        LeaseTimes::default().v6_renewal,
+        "IA_PD T1 should be LeaseTimes::default().v6_renewal"
+    );
+    assert_eq!(
+        iapd.t2,
+        LeaseTimes::default().v6_rebinding,
+        "IA_PD T2 should be LeaseTimes::default().v6_rebinding"
+    );
+}
+
+/// RFC 8415 Section 18.4.5: Rebind works without Server ID (unlike Renew)
+#[test]
+fn rebind_works_without_server_id() {
+    let (config, reservations, leases) = create_env();
+
+    let reservation = reservations
+        .by_mac(RESERVATION_MAC)
+        .expect("No reservation found");
+
+    let mut msg = Message::new(MessageType::Rebind);
+    let opts = msg.opts_mut();
+    // Rebind only requires Client ID, NOT Server ID
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: {
+            let mut o = DhcpOptions::new();
+            o.insert(DhcpOption::IAAddr(IAAddr {
+                addr: reservation.ipv6_na.unwrap(),
+                preferred_life: 100,
+                valid_life: 200,
+                opts: DhcpOptions::new(),
+            }));
+            o
+        },
+    }));
+    opts.insert(DhcpOption::IAPD(IAPD {
+        id: 2,
+        t1: 0,
+        t2: 0,
+        opts: {
+            let mut o = DhcpOptions::new();
+            o.insert(DhcpOption::IAPrefix(IAPrefix {
+                prefix_ip: reservation.ipv6_pd.addr(),
+                prefix_len: reservation.ipv6_pd.prefix_len(),
+                preferred_lifetime: 100,
+                valid_lifetime: 200,
+                opts: DhcpOptions::new(),
+            }));
+            o
+        },
+    }));
+
+    let relay_msg = create_relay_forw(&msg);
+
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Reply response for Rebind"),
+    };
+
+    assert!(matches!(resp.msg_type(), MessageType::Reply));
+
+    // Verify IA_NA and IA_PD are returned with correct values
+    let iana = resp.ia_na().expect("Response missing IA_NA");
+    let iapd = resp.ia_pd().expect("Response missing IA_PD");
+
+    assert_eq!(iana.t1, LeaseTimes::default().v6_renewal);
+    assert_eq!(iana.t2, LeaseTimes::default().v6_rebinding);
+    assert_eq!(iapd.t1, LeaseTimes::default().v6_renewal);
+    assert_eq!(iapd.t2, LeaseTimes::default().v6_rebinding);
+
+    // Verify addresses/prefixes
+    let returned_na = resp.ia_na_address().unwrap();
+    let returned_pd = resp.ia_pd_prefix().unwrap();
+    assert_eq!(returned_na, reservation.ipv6_na.unwrap());
+    assert_eq!(returned_pd, reservation.ipv6_pd);
+}
+
+/// RFC 8415 Section 18.3.5: addresses and prefixes the client is rebinding
+/// that aren't its reservation come back with zero lifetimes
+#[test]
+fn rebind_zeroes_lifetimes_outside_reservation() {
+    let (config, reservations, leases) = create_env();
+
+    let reservation = reservations
+        .by_mac(RESERVATION_MAC)
+        .expect("No reservation found");
+    let old_na: Ipv6Addr = "2001:db8:ffff::1".parse().unwrap();
+    let old_pd: Ipv6Net = "2001:db8:ff00::/56".parse().unwrap();
+
+    let mut msg = Message::new(MessageType::Rebind);
     let opts = msg.opts_mut();
-    // Rebind only requires Client ID, NOT Server ID
     opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
     opts.insert(DhcpOption::IANA(IANA {
         id: 1,
@@ -1131,7 +1665,7 @@ fn rebind_works_without_server_id() {
         opts: {
             let mut o = DhcpOptions::new();
             o.insert(DhcpOption::IAAddr(IAAddr {
-                addr: reservation.ipv6_na,
+                addr: old_na,
                 preferred_life: 100,
                 valid_life: 200,
                 opts: DhcpOptions::new(),
@@ -1143,19 +1677,191 @@ fn rebind_works_without_server_id() {
         id: 2,
         t1: 0,
         t2: 0,
-        opts: {
-            let mut o = DhcpOptions::new();
-            o.insert(DhcpOption::IAPrefix(IAPrefix {
-                prefix_ip: reservation.ipv6_pd.addr(),
-                prefix_len: reservation.ipv6_pd.prefix_len(),
-                preferred_lifetime: 100,
-                valid_lifetime: 200,
-                opts: DhcpOptions::new(),
-            }));
-            o
-        },
+        opts: {
+            let mut o = DhcpOptions::new();
+            o.insert(DhcpOption::IAPrefix(IAPrefix {
+                prefix_ip: old_pd.addr(),
+                prefix_len: old_pd.prefix_len(),
+                preferred_lifetime: 100,
+                valid_lifetime: 200,
+                opts: DhcpOptions::new(),
+            }));
+            o
+        },
+    }));
+
+    let relay_msg = create_relay_forw(&msg);
+
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Reply response for Rebind"),
+    };
+
+    let lease_times = LeaseTimes::default();
+    let iana = resp.ia_na().expect("Response missing IA_NA");
+    let addrs: Vec<_> = iana
+        .opts
+        .iter()
+        .filter_map(|opt| match opt {
+            DhcpOption::IAAddr(addr) => Some((addr.addr, addr.valid_life, addr.preferred_life)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(addrs.len(), 2);
+    assert!(addrs.contains(&(
+        reservation.ipv6_na.unwrap(),
+        lease_times.v6_valid,
+        lease_times.v6_preferred
+    )));
+    assert!(addrs.contains(&(old_na, 0, 0)));
+
+    let iapd = resp.ia_pd().expect("Response missing IA_PD");
+    let prefixes: Vec<_> = iapd
+        .opts
+        .iter()
+        .filter_map(|opt| match opt {
+            DhcpOption::IAPrefix(prefix) => Some((
+                prefix.prefix_ip,
+                prefix.valid_lifetime,
+                prefix.preferred_lifetime,
+            )),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(prefixes.len(), 2);
+    assert!(prefixes.contains(&(
+        reservation.ipv6_pd.addr(),
+        lease_times.v6_valid,
+        lease_times.v6_preferred
+    )));
+    assert!(prefixes.contains(&(old_pd.addr(), 0, 0)));
+}
+
+/// The Mikrotik Request capture re-typed as a Release or Decline, sent to
+/// the server it was leased from
+fn mikrotik_release_or_decline(msg_type: u8) -> (Config, Message) {
+    let (mut config, _, _) = create_env();
+    let mut bytes = MIKROTIK_REQUEST;
+    bytes[0] = msg_type;
+    let msg = Message::from_bytes(&bytes).unwrap();
+    config.v6_server_id = Duid::new(msg.server_id().unwrap().to_vec()).unwrap();
+    (config, msg)
+}
+
+fn top_level_success(msg: &Message) -> bool {
+    msg.opts()
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::StatusCode(sc) if sc.status == Status::Success))
+}
+
+/// RFC 8415 §18.3.7/§18.3.8: a Release or Decline from a client without a
+/// reservation is still answered, with Success and a bare NoBinding IA for
+/// each IA it sent
+#[test]
+fn release_and_decline_without_reservation_reply_no_binding() {
+    for (msg_type, expected) in [(8, MessageType::Release), (9, MessageType::Decline)] {
+        let (config, msg) = mikrotik_release_or_decline(msg_type);
+        let (_, reservations, leases) = create_env();
+        assert_eq!(msg.msg_type(), expected);
+        let relay_msg = create_relay_forw(&msg);
+
+        let resp = match crate::v6::handlers::handle_message(
+            &config,
+            &reservations,
+            &leases,
+            &msg,
+            &relay_msg,
+        ) {
+            DhcpV6Response::Message(resp) => resp,
+            DhcpV6Response::NoResponse(reason) => panic!("{expected:?} not answered: {reason:?}"),
+        };
+        assert!(resp.reservation.is_none());
+        let reply = resp.message;
+        assert_eq!(reply.msg_type(), MessageType::Reply);
+        assert_eq!(reply.xid(), msg.xid());
+        assert!(top_level_success(&reply));
+        assert_eq!(reply.client_id(), msg.client_id());
+
+        let iana = reply.ia_na().expect("IA_NA echoed");
+        assert_eq!(iana.id, 5);
+        assert_eq!(iana.opts.iter().count(), 1, "only the status in the IA");
+        assert!(matches!(
+            iana.opts.iter().next(),
+            Some(DhcpOption::StatusCode(sc)) if sc.status == Status::NoBinding
+        ));
+        let iapd = reply.ia_pd().expect("IA_PD echoed");
+        assert_eq!(iapd.id, 5);
+        assert_eq!(iapd.opts.iter().count(), 1, "only the status in the IA");
+        assert!(matches!(
+            iapd.opts.iter().next(),
+            Some(DhcpOption::StatusCode(sc)) if sc.status == Status::NoBinding
+        ));
+
+        // Encodes as a Relay-reply the CPE can parse
+        let bytes = relay_reply(&relay_msg, reply).to_vec().unwrap();
+        assert!(RelayMessage::from_bytes(&bytes).is_ok());
+    }
+}
+
+/// Release is, like Request, only for the server the client chose
+#[test]
+fn release_for_another_server_discarded() {
+    let (_, msg) = mikrotik_release_or_decline(8);
+    let (config, reservations, leases) = create_env();
+    let relay_msg = create_relay_forw(&msg);
+    assert!(matches!(
+        crate::v6::handlers::handle_message(&config, &reservations, &leases, &msg, &relay_msg),
+        DhcpV6Response::NoResponse(NoResponse::WrongServerId)
+    ));
+}
+
+/// A reserved client releasing its leases gets Success with no IAs, and its
+/// address is no longer held against other clients
+#[test]
+fn release_of_reserved_leases_frees_address() {
+    let (config, reservations, leases) = create_env();
+    let client = Duid::from(vec![0xaa, 0xbb, 0xcc]);
+    let na: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let hold = std::time::Duration::from_secs(3600);
+    leases.claim_ipv6(na, &client, None, hold, false);
+
+    let mut msg = Message::new(MessageType::Release);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(client.bytes.clone()));
+    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+    let mut ia_na_opts = DhcpOptions::new();
+    ia_na_opts.insert(DhcpOption::IAAddr(IAAddr {
+        addr: na,
+        preferred_life: 0,
+        valid_life: 0,
+        opts: DhcpOptions::new(),
+    }));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: ia_na_opts,
+    }));
+    let mut ia_pd_opts = DhcpOptions::new();
+    ia_pd_opts.insert(DhcpOption::IAPrefix(IAPrefix {
+        preferred_lifetime: 0,
+        valid_lifetime: 0,
+        prefix_len: 56,
+        prefix_ip: "2001:db8:100::".parse().unwrap(),
+        opts: DhcpOptions::new(),
+    }));
+    opts.insert(DhcpOption::IAPD(IAPD {
+        id: 2,
+        t1: 0,
+        t2: 0,
+        opts: ia_pd_opts,
     }));
-
     let relay_msg = create_relay_forw(&msg);
 
     let resp = match crate::v6::handlers::handle_message(
@@ -1165,26 +1871,17 @@ fn rebind_works_without_server_id() {
         &msg,
         &relay_msg,
     ) {
-        DhcpV6Response::Message(resp) => resp.message,
-        _ => panic!("Expected Reply response for Rebind"),
+        DhcpV6Response::Message(resp) => resp,
+        DhcpV6Response::NoResponse(reason) => panic!("Release not answered: {reason:?}"),
     };
-
-    assert!(matches!(resp.msg_type(), MessageType::Reply));
-
-    // Verify IA_NA and IA_PD are returned with correct values
-    let iana = resp.ia_na().expect("Response missing IA_NA");
-    let iapd = resp.ia_pd().expect("Response missing IA_PD");
-
-    assert_eq!(iana.t1, LeaseTimes::default().v6_renewal);
-    assert_eq!(iana.t2, LeaseTimes::default().v6_rebinding);
-    assert_eq!(iapd.t1, LeaseTimes::default().v6_renewal);
-    assert_eq!(iapd.t2, LeaseTimes::default().v6_rebinding);
-
-    // Verify addresses/prefixes
-    let returned_na = resp.ia_na_address().unwrap();
-    let returned_pd = resp.ia_pd_prefix().unwrap();
-    assert_eq!(returned_na, reservation.ipv6_na);
-    assert_eq!(returned_pd, reservation.ipv6_pd);
+    assert!(resp.reservation.is_some());
+    assert_eq!(resp.message.msg_type(), MessageType::Reply);
+    assert!(top_level_success(&resp.message));
+    assert!(resp.message.ia_na().is_none());
+    assert!(resp.message.ia_pd().is_none());
+
+    let other = Duid::from(vec![0xaa, 0xbb, 0xcd]);
+    assert_eq!(leases.claim_ipv6(na, &other, None, hold, false), None);
 }
 
 /// RFC 8415: Rebind with no reservation returns NoBinding in IA options
@@ -1250,6 +1947,84 @@ fn rebind_no_reservation_returns_no_binding() {
     assert_eq!(returned_addr.valid_life, 0);
 }
 
+/// RFC 8415 Section 18.3.9: unknown clients get an Advertise with
+/// NoAddrsAvail/NoPrefixAvail inside each IA when enabled
+#[test]
+fn solicit_unknown_client_advertises_no_addrs_when_enabled() {
+    let (mut config, reservations, leases) = create_env();
+
+    let mut msg = Message::new(MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xde, 0xad, 0xbe, 0xef]));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+    opts.insert(DhcpOption::IAPD(IAPD {
+        id: 2,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+
+    let relay_msg = create_relay_forw(&msg);
+
+    // Default stays silent so the client keeps trying other servers
+    assert!(matches!(
+        crate::v6::handlers::handle_message(&config, &reservations, &leases, &msg, &relay_msg),
+        DhcpV6Response::NoResponse(NoResponse::NoReservation)
+    ));
+
+    config.v6_advertise_unknown_clients = true;
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Advertise"),
+    };
+    assert_eq!(resp.msg_type(), MessageType::Advertise);
+
+    let returned_iana = resp.ia_na().expect("Advertise missing IA_NA");
+    assert_eq!(returned_iana.id, 1);
+    let ia_status = returned_iana
+        .opts
+        .iter()
+        .find_map(|opt| match opt {
+            DhcpOption::StatusCode(code) => Some(code),
+            _ => None,
+        })
+        .expect("IA_NA missing StatusCode");
+    assert_eq!(ia_status.status, dhcproto::v6::Status::NoAddrsAvail);
+    assert!(!returned_iana
+        .opts
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::IAAddr(_))));
+
+    let returned_iapd = resp.ia_pd().expect("Advertise missing IA_PD");
+    assert_eq!(returned_iapd.id, 2);
+    let pd_status = returned_iapd
+        .opts
+        .iter()
+        .find_map(|opt| match opt {
+            DhcpOption::StatusCode(code) => Some(code),
+            _ => None,
+        })
+        .expect("IA_PD missing StatusCode");
+    assert_eq!(pd_status.status, dhcproto::v6::Status::NoPrefixAvail);
+
+    // No Preference option, so the client doesn't stop at this server
+    assert!(!resp
+        .opts()
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::Preference(_))));
+}
+
 /// Verify that Renew response contains correct T1/T2 values per RFC 8415
 #[test]
 fn renew_response_has_correct_t1_t2() {
@@ -1270,7 +2045,7 @@ fn renew_response_has_correct_t1_t2() {
         opts: {
             let mut o = DhcpOptions::new();
             o.insert(DhcpOption::IAAddr(IAAddr {
-                addr: reservation.ipv6_na,
+                addr: reservation.ipv6_na.unwrap(),
                 preferred_life: 100,
                 valid_life: 200,
                 opts: DhcpOptions::new(),
@@ -1332,3 +2107,345 @@ fn renew_response_has_correct_t1_t2() {
         "IA_PD T2 should be LeaseTimes::default().v6_rebinding"
     );
 }
+
+#[test]
+fn relay_source_port_echoed_and_used_for_destination() {
+    let msg = Message::new(MessageType::Solicit);
+    let mut relay_msg = create_relay_forw(&msg);
+    relay_msg
+        .opts
+        .insert(DhcpOption::InterfaceId(b"eth0:100".to_vec()));
+    let src: SocketAddr = "[2001:db8::1]:1547".parse().unwrap();
+
+    // Without Relay Source Port the relay is answered on the server port
+    assert_eq!(
+        reply_destination(&relay_msg, src),
+        "[2001:db8::1]:547".parse::<SocketAddr>().unwrap()
+    );
+
+    let relay_port = DhcpOption::Unknown(UnknownOption::new(OptionCode::from(135), vec![0, 0]));
+    relay_msg.opts.insert(relay_port.clone());
+    assert_eq!(reply_destination(&relay_msg, src), src);
+
+    let reply = relay_reply(&relay_msg, Message::new(MessageType::Advertise));
+    assert!(matches!(reply.msg_type, MessageType::RelayRepl));
+    assert!(reply.opts().iter().any(|opt| *opt == relay_port));
+    assert!(reply
+        .opts()
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::InterfaceId(id) if id == b"eth0:100")));
+    // Relay-reply must be decodable by the relay
+    let bytes = reply.to_vec().unwrap();
+    let decoded = RelayMessage::from_bytes(&bytes).unwrap();
+    assert!(decoded.opts().iter().any(|opt| {
+        matches!(opt, DhcpOption::RelayMsg(RelayMessageData::Message(m)) if m.msg_type() == MessageType::Advertise)
+    }));
+}
+
+/// RFC 4994: options the relay lists in an Echo Request Option come back in
+/// the Relay-reply, except the Relay Message, which is the server's own
+#[test]
+fn echo_request_option_copies_relay_options() {
+    let msg = Message::new(MessageType::Request);
+    let mut relay_msg = create_relay_forw(&msg);
+    let subscriber_id = DhcpOption::Unknown(UnknownOption::new(
+        OptionCode::from(38),
+        b"bng1-sub-42".to_vec(),
+    ));
+    let vendor_state = DhcpOption::Unknown(UnknownOption::new(
+        OptionCode::from(65001),
+        vec![0xde, 0xad],
+    ));
+    relay_msg.opts.insert(subscriber_id.clone());
+    relay_msg.opts.insert(vendor_state.clone());
+
+    // Nothing extra is echoed without an ERO
+    let reply = relay_reply(&relay_msg, Message::new(MessageType::Reply));
+    assert!(!reply.opts().iter().any(|opt| *opt == subscriber_id));
+
+    // ERO asking for Subscriber-ID, Relay Message and an option the relay
+    // didn't send
+    let ero = DhcpOption::Unknown(UnknownOption::new(
+        OptionCode::from(43),
+        vec![0, 38, 0, 9, 0, 18],
+    ));
+    relay_msg.opts.insert(ero.clone());
+    let reply = relay_reply(&relay_msg, Message::new(MessageType::Reply));
+    assert!(reply.opts().iter().any(|opt| *opt == subscriber_id));
+    assert!(!reply.opts().iter().any(|opt| *opt == vendor_state));
+    assert!(!reply.opts().iter().any(|opt| *opt == ero));
+    let relay_msgs = reply
+        .opts()
+        .iter()
+        .filter(|opt| matches!(opt, DhcpOption::RelayMsg(_)))
+        .count();
+    assert_eq!(relay_msgs, 1);
+
+    let decoded = RelayMessage::from_bytes(&reply.to_vec().unwrap()).unwrap();
+    assert!(decoded
+        .opts()
+        .iter()
+        .any(|opt| u16::from(OptionCode::from(opt)) == 38));
+}
+
+/// RFC 8415 §21.12: with `v6_server_unicast` set, replies carry the Server
+/// Unicast option
+#[test]
+fn server_unicast_option_sent_when_configured() {
+    let (mut config, reservations, leases) = create_env();
+    let server_addr: Ipv6Addr = "2001:db8::547".parse().unwrap();
+
+    let mut msg = Message::new(MessageType::Solicit);
+    msg.opts_mut()
+        .insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    let relay_msg = create_relay_forw(&msg);
+
+    for (unicast, expected) in [(None, None), (Some(server_addr), Some(server_addr))] {
+        config.v6_server_unicast = unicast;
+        let resp = match crate::v6::handlers::handle_message(
+            &config,
+            &reservations,
+            &leases,
+            &msg,
+            &relay_msg,
+        ) {
+            DhcpV6Response::Message(resp) => resp.message,
+            DhcpV6Response::NoResponse(reason) => panic!("Expected Advertise, got {reason:?}"),
+        };
+        let sent = resp.opts().iter().find_map(|opt| match opt {
+            DhcpOption::Unicast(addr) => Some(*addr),
+            _ => None,
+        });
+        assert_eq!(sent, expected);
+    }
+}
+
+/// A Renew sent straight to the server is handled like a relayed one and
+/// still finds the reservation; a unicast Solicit isn't accepted
+#[test]
+fn unicast_renew_handled_without_relay() {
+    let (mut config, reservations, leases) = create_env();
+    config.v6_server_unicast = Some("2001:db8::547".parse().unwrap());
+    // The client unicasts from the address it was leased
+    let client = reservations
+        .by_mac(RESERVATION_MAC)
+        .unwrap()
+        .ipv6_na
+        .unwrap();
+
+    let mut msg = Message::new(MessageType::Renew);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+
+    assert!(crate::v6::handlers::accepts_unicast(msg.msg_type()));
+    assert!(!crate::v6::handlers::accepts_unicast(MessageType::Solicit));
+    assert!(!crate::v6::handlers::accepts_unicast(MessageType::Rebind));
+
+    let relay_msg = crate::v6::handlers::unicast_relay_forw(msg.clone(), client);
+    assert_eq!(relay_msg.peer_addr(), client);
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp,
+        DhcpV6Response::NoResponse(reason) => panic!("Expected Reply, got {reason:?}"),
+    };
+    assert_eq!(resp.message.msg_type(), MessageType::Reply);
+    assert!(resp.reservation.is_some());
+    let iana = resp.message.ia_na().expect("IA_NA in Reply");
+    assert!(iana
+        .opts
+        .iter()
+        .any(|opt| matches!(opt, DhcpOption::IAAddr(addr) if addr.addr == client)));
+}
+
+#[test]
+fn suspended_reservation_gets_no_leases() {
+    let (config, reservations, leases) = create_env();
+    let reservation = reservations.by_mac(RESERVATION_MAC).unwrap();
+    reservations.upsert(Reservation {
+        suspended: true,
+        ..Reservation::clone(&reservation)
+    });
+    let has_status = |ia_opts: &DhcpOptions, status: Status| {
+        ia_opts
+            .iter()
+            .any(|opt| matches!(opt, DhcpOption::StatusCode(code) if code.status == status))
+    };
+
+    let mut msg = Message::new(MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+    let relay_msg = create_relay_forw(&msg);
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp,
+        _ => panic!("Expected Advertise"),
+    };
+    assert!(resp.reservation.is_some_and(|r| r.suspended));
+    let returned_iana = resp.message.ia_na().expect("Advertise missing IA_NA");
+    assert!(has_status(&returned_iana.opts, Status::NoAddrsAvail));
+
+    let mut msg = Message::new(MessageType::Renew);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: {
+            let mut o = DhcpOptions::new();
+            o.insert(DhcpOption::IAAddr(IAAddr {
+                addr: reservation.ipv6_na.unwrap(),
+                preferred_life: 100,
+                valid_life: 200,
+                opts: DhcpOptions::new(),
+            }));
+            o
+        },
+    }));
+    let relay_msg = create_relay_forw(&msg);
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Reply"),
+    };
+    let returned_iana = resp.ia_na().expect("Reply missing IA_NA");
+    assert!(has_status(&returned_iana.opts, Status::NoBinding));
+    assert!(returned_iana.opts.iter().all(|opt| match opt {
+        DhcpOption::IAAddr(addr) => addr.valid_life == 0 && addr.preferred_life == 0,
+        _ => true,
+    }));
+}
+
+/// A prefix-only reservation still gets its IA_PD. An IA_NA the client asks
+/// for comes back empty with NoAddrsAvail (RFC 8415 §18.3.1, §18.3.2).
+#[test]
+fn prefix_only_reservation_answers_ia_na_with_no_addrs_avail() {
+    let (config, reservations, leases) = create_env();
+    let reservation = reservations.by_mac(RESERVATION_MAC).unwrap();
+    reservations.upsert(Reservation {
+        ipv6_na: None,
+        ..Reservation::clone(&reservation)
+    });
+
+    for msg_type in [MessageType::Solicit, MessageType::Request] {
+        let mut msg = Message::new(msg_type);
+        let opts = msg.opts_mut();
+        opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+        if msg_type == MessageType::Request {
+            opts.insert(DhcpOption::ServerId(config.v6_server_id.bytes.clone()));
+        }
+        opts.insert(DhcpOption::IAPD(IAPD {
+            id: 2,
+            t1: 0,
+            t2: 0,
+            opts: DhcpOptions::new(),
+        }));
+        let relay_msg = create_relay_forw(&msg);
+        let resp = match crate::v6::handlers::handle_message(
+            &config,
+            &reservations,
+            &leases,
+            &msg,
+            &relay_msg,
+        ) {
+            DhcpV6Response::Message(resp) => resp.message,
+            _ => panic!("Expected a response to the IA_PD-only {msg_type:?}"),
+        };
+        assert_eq!(resp.ia_pd_prefix(), Some(reservation.ipv6_pd));
+        assert!(resp.ia_na().is_none());
+
+        msg.opts_mut().insert(DhcpOption::IANA(IANA {
+            id: 1,
+            t1: 0,
+            t2: 0,
+            opts: DhcpOptions::new(),
+        }));
+        let relay_msg = create_relay_forw(&msg);
+        let resp = match crate::v6::handlers::handle_message(
+            &config,
+            &reservations,
+            &leases,
+            &msg,
+            &relay_msg,
+        ) {
+            DhcpV6Response::Message(resp) => resp.message,
+            _ => panic!("Expected a response to the {msg_type:?}"),
+        };
+        assert_eq!(resp.ia_pd_prefix(), Some(reservation.ipv6_pd));
+        let iana = resp.ia_na().expect("IA_NA echoed");
+        assert_eq!(iana.id, 1);
+        assert!(iana.opts.iter().any(
+            |opt| matches!(opt, DhcpOption::StatusCode(code) if code.status == Status::NoAddrsAvail)
+        ));
+        assert!(resp.ia_na_address().is_none());
+    }
+}
+
+#[test]
+fn v6_only_reservation_is_answered() {
+    let (config, reservations, leases) = create_env();
+    let reservation = reservations.by_mac(RESERVATION_MAC).unwrap();
+    reservations.upsert(Reservation {
+        ipv4: None,
+        ..Reservation::clone(&reservation)
+    });
+
+    let mut msg = Message::new(MessageType::Solicit);
+    let opts = msg.opts_mut();
+    opts.insert(DhcpOption::ClientId(vec![0xaa, 0xbb, 0xcc]));
+    opts.insert(DhcpOption::IANA(IANA {
+        id: 1,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+    opts.insert(DhcpOption::IAPD(IAPD {
+        id: 2,
+        t1: 0,
+        t2: 0,
+        opts: DhcpOptions::new(),
+    }));
+    let relay_msg = create_relay_forw(&msg);
+
+    let resp = match crate::v6::handlers::handle_message(
+        &config,
+        &reservations,
+        &leases,
+        &msg,
+        &relay_msg,
+    ) {
+        DhcpV6Response::Message(resp) => resp.message,
+        _ => panic!("Expected Advertise"),
+    };
+    assert_eq!(resp.ia_na_address(), reservation.ipv6_na);
+    assert_eq!(resp.ia_pd_prefix(), Some(reservation.ipv6_pd));
+}
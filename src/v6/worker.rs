@@ -1,130 +1,274 @@
 use arc_swap::ArcSwap;
 
 use dhcproto::{
-    v6::{self, DhcpOption, DhcpOptions, RelayMessage},
-    Decodable, Encodable,
+    error::{DecodeError, EncodeError},
+    v6::{self, DhcpOption},
+    Decodable,
 };
 
+use crate::audit::{self, AuditRecord};
+use crate::capture::{Direction, PacketCapture};
 use crate::config::Config;
+use crate::dump::{V6Dump, V6RelayDump};
+use crate::ha::PeerMonitor;
+use crate::health::{Health, ProtocolHealth, RecvAction};
+use crate::history::Transaction;
 use crate::opt82_cache::Opt82Cache;
+use crate::pd_routes::{self, PdUpdate};
+use crate::rate_limit::{RateLimiter, Verdict};
+use crate::relays::RelayInventory;
+use crate::reply_source::ReplySource;
 use crate::reservationdb::ReservationDb;
+use crate::retransmit::RetransmitCache;
 use crate::shutdown::Shutdown;
 use std::{
     fmt::Write,
     io,
-    net::{SocketAddr, UdpSocket},
-    sync::Arc,
+    net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{mpsc, Arc},
     time::Duration,
 };
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
 use crate::{
     analytics::{
-        events::{DhcpEvent, DhcpEventV6},
-        EventSenders,
+        events::{DhcpEvent, DhcpEventV6, PacketTiming, ReservationMatch},
+        socket_queue_bytes, EventSenders,
     },
-    types::Duid,
+    types::{Duid, Reservation},
     v6::extensions::{ShadowMessageExtV6, ShadowRelayMessageExtV6},
-    v6::handlers::{DhcpV6Response, NoResponse},
+    v6::handlers::{
+        accepts_unicast, relay_reply, reply_destination, unicast_relay_forw, DhcpV6Response,
+        NoResponse, ResponseMessage,
+    },
 };
 
+/// The IPv6 minimum MTU (RFC 8200 §5) less the IPv6 and UDP headers. DHCPv6
+/// has no Maximum Message Size option; fragments are often dropped on the way
+/// to the relay, so larger replies are logged.
+const MAX_UNFRAGMENTED_REPLY: usize = 1280 - 40 - 8;
+
+#[allow(clippy::too_many_arguments)]
 pub fn v6_worker(
     socket: UdpSocket,
     reservations: Arc<ArcSwap<ReservationDb>>,
     leases: Arc<Opt82Cache>,
     config: Arc<ArcSwap<Config>>,
     event_channel: Option<EventSenders>,
+    audit_channel: Option<mpsc::SyncSender<AuditRecord>>,
+    pd_routes_channel: Option<mpsc::SyncSender<PdUpdate>>,
+    capture: Arc<PacketCapture>,
+    ha: Option<Arc<PeerMonitor>>,
+    reply_source: Option<ReplySource>,
+    health: Arc<Health>,
     shutdown: Shutdown,
-) {
+) -> io::Result<()> {
+    let _running = health.v6.worker_running();
     let mut read_buf = [0u8; 2048];
+    // Replies are encoded into this so sending doesn't allocate
+    let mut write_buf = Vec::with_capacity(read_buf.len());
     let mut error_count: u32 = 0;
-    const MAX_BACKOFF_MS: u64 = 1000;
 
     // Wake once per second so the shutdown flag is noticed promptly.
     socket
         .set_read_timeout(Some(Duration::from_secs(1)))
         .expect("set v6 socket read timeout");
 
+    let (mut client_limiter, mut relay_limiter) = match &config.load().rate_limit {
+        Some(cfg) => (
+            Some(RateLimiter::<Duid>::client("DHCPv6 client", cfg)),
+            RateLimiter::<IpAddr>::relay("DHCPv6 relay", cfg),
+        ),
+        None => (None, None),
+    };
+    let mut retransmits = RetransmitCache::new(config.load().retransmit_window_ms);
+
     // listen for messages
     loop {
         if shutdown.is_signalled() {
             info!("v6 worker shutting down");
-            return;
+            return Ok(());
+        }
+        if let Some(limiter) = client_limiter.as_mut() {
+            limiter.sweep();
+        }
+        if let Some(limiter) = relay_limiter.as_mut() {
+            limiter.sweep();
+        }
+        if let Some(cache) = retransmits.as_mut() {
+            cache.sweep();
         }
         // if the src is not listening on response, it may send a ICMP host unreachable
         let (amount, src) = match socket.recv_from(&mut read_buf) {
             Ok((amount, src)) => {
                 error_count = 0;
+                health.v6.record_packet();
                 debug!("Received {amount} bytes from {src:?}");
                 trace!("Data: {}", hex_for_text2pcap(&read_buf[..amount]));
                 (amount, src)
             }
             Err(err) => {
-                match err.kind() {
-                    // Read-timeout expiry: WouldBlock on Unix, TimedOut on Windows
-                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {}
-                    io::ErrorKind::ConnectionReset => {
-                        debug!("Sent response to host that responded with ICMP unreachable");
-                    }
-                    io::ErrorKind::Interrupted => {
-                        debug!("recv_from interrupted, retrying");
-                    }
-                    _ => {
+                match health.v6.recv_failed(&err, &mut error_count) {
+                    RecvAction::Retry => match err.kind() {
+                        io::ErrorKind::ConnectionReset => {
+                            debug!("Sent response to host that responded with ICMP unreachable");
+                        }
+                        io::ErrorKind::Interrupted => {
+                            debug!("recv_from interrupted, retrying");
+                        }
+                        _ => {}
+                    },
+                    RecvAction::Backoff(delay) => {
                         error!("Unexpected socket error: {err:?}");
-                        // Apply exponential backoff to prevent CPU spin on persistent errors
-                        error_count = error_count.saturating_add(1);
-                        let backoff_ms = std::cmp::min(
-                            10_u64.saturating_mul(2_u64.saturating_pow(error_count)),
-                            MAX_BACKOFF_MS,
-                        );
-                        std::thread::sleep(Duration::from_millis(backoff_ms));
+                        std::thread::sleep(delay);
+                    }
+                    RecvAction::Rebind => {
+                        error!("DHCPv6 socket unusable, rebinding: {err:?}");
+                        return Err(err);
                     }
                 }
                 continue;
             }
         };
+        let mut timing = PacketTiming::received(socket_queue_bytes(&socket));
+
+        if let Some(ha) = &ha {
+            if !ha.should_answer() {
+                trace!("HA standby, ignoring packet from {src}");
+                continue;
+            }
+        }
+
+        if crate::fallback::is_reply_v6(&config.load(), src, &read_buf[..amount]) {
+            crate::fallback::return_v6(&socket, &read_buf[..amount], &mut write_buf);
+            continue;
+        }
+
+        if let Some(limiter) = relay_limiter.as_mut() {
+            if limiter.check(&src.ip()) != Verdict::Allow {
+                trace!(reason = %NoResponse::RateLimited, "Rate limited packet from relay {src}");
+                continue;
+            }
+        }
 
         let relay_addr = match src {
             SocketAddr::V6(v6) => Some(*v6.ip()),
             SocketAddr::V4(_) => None,
         };
 
-        match v6::RelayMessage::from_bytes(&read_buf[..amount]) {
+        // With `v6_server_unicast` set, clients may skip the relay and send
+        // some messages straight to the server
+        let unicast = config.load().v6_server_unicast.is_some();
+        let (direct, parsed) = decode(&read_buf[..amount], src, unicast, &health.relays);
+
+        match parsed {
             Ok(msg) => {
-                trace!("RelayMessage: {:#?}", msg);
-                // get the inner msg from the option
-                let inner_msg = match msg.opts().iter().find_map(|opt| match opt {
-                    DhcpOption::RelayMsg(msg) => Some(msg),
-                    _ => None,
-                }) {
-                    Some(v6::RelayMessageData::Message(m)) => m,
-                    Some(v6::RelayMessageData::Relay(_rm)) => {
-                        debug!("Ignoring nested relay message from {src}");
+                let inner_msg = match relayed_message(&msg) {
+                    Ok(inner_msg) => inner_msg,
+                    Err(reason) => {
+                        debug!(reason, "Ignoring relay message from {src}");
                         if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
-                            let event = DhcpEventV6::relay_failed(&msg, relay_addr, "NestedRelay");
-                            sinks.send(DhcpEvent::V6(event));
+                            let event = DhcpEventV6::relay_failed(&msg, relay_addr, reason);
+                            sinks.send(DhcpEvent::V6(event.with_timing(&timing)));
                         }
                         continue;
                     }
-                    None => {
-                        debug!("Relay message from {src} carries no RelayMsg option");
-                        if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
-                            let event = DhcpEventV6::relay_failed(&msg, relay_addr, "NoRelayMsg");
-                            sinks.send(DhcpEvent::V6(event));
+                };
+
+                let capture_mac = if capture.is_active() {
+                    config
+                        .load()
+                        .mac_extractors
+                        .iter()
+                        .find_map(|extractor| extractor.extract(&msg, inner_msg))
+                } else {
+                    None
+                };
+                capture.record(capture_mac, Direction::Rx, src, &read_buf[..amount]);
+
+                let history_depth = config.load().lease_history;
+                let record = |outcome, found| {
+                    record_history(
+                        &health.v6,
+                        &leases,
+                        history_depth,
+                        inner_msg,
+                        &msg,
+                        src,
+                        outcome,
+                        found,
+                    )
+                };
+
+                if direct && !accepts_unicast(inner_msg.msg_type()) {
+                    debug!(
+                        "Discarding {:?} unicast by {src}, it must be multicast",
+                        inner_msg.msg_type()
+                    );
+                    record(NoResponse::NotRelayed.as_str(), None);
+                    if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
+                        let event = DhcpEventV6::failed(
+                            inner_msg,
+                            &msg,
+                            relay_addr,
+                            NoResponse::NotRelayed,
+                        );
+                        sinks.send(DhcpEvent::V6(event.with_timing(&timing)));
+                    }
+                    continue;
+                }
+
+                // Messages without a usable client DUID are left to the relay limit.
+                if let Some(limiter) = client_limiter.as_mut() {
+                    if let Some(duid) = inner_msg.client_id().and_then(|b| Duid::new(b.to_vec())) {
+                        if limiter.check(&duid) != Verdict::Allow {
+                            trace!(%duid, reason = %NoResponse::RateLimited, "Rate limited DHCPv6 client");
+                            record(NoResponse::RateLimited.as_str(), None);
+                            continue;
                         }
-                        continue;
                     }
-                };
+                }
 
-                match crate::v6::handlers::handle_message(
-                    &config.load(),
-                    &reservations.load(),
-                    &leases,
-                    inner_msg,
-                    &msg,
-                ) {
+                let cache_key = retransmit_key(inner_msg);
+                if let Some((reply, dest)) = retransmits
+                    .as_ref()
+                    .zip(cache_key.as_ref())
+                    .and_then(|(cache, key)| cache.get(key))
+                {
+                    match crate::reply_source::send(&socket, reply_source.as_ref(), reply, dest) {
+                        Ok(sent) => {
+                            debug!(xid = ?inner_msg.xid(), "resent cached reply to {dest} with {sent} bytes");
+                            capture.record(capture_mac, Direction::Tx, dest, reply);
+                        }
+                        Err(e) => error!("Problem resending cached response message: {e}"),
+                    }
+                    continue;
+                }
+
+                match timing.handle(|| {
+                    crate::v6::handlers::handle_message(
+                        &config.load(),
+                        &reservations.load(),
+                        &leases,
+                        inner_msg,
+                        &msg,
+                    )
+                }) {
                     DhcpV6Response::NoResponse(reason) => {
-                        if !matches!(reason, NoResponse::NoReservation) {
+                        let reason = if direct {
+                            reason
+                        } else {
+                            crate::fallback::forward_v6(
+                                &socket,
+                                &config.load(),
+                                reason,
+                                &msg,
+                                src,
+                                &mut write_buf,
+                            )
+                        };
+                        record(reason.as_str(), None);
+                        if !reason.is_unknown_client() {
                             debug!("Not responding {:?}", reason);
                         } else if tracing::enabled!(tracing::Level::INFO) {
                             let duid = inner_msg
@@ -147,41 +291,55 @@ pub fn v6_worker(
                             );
                         }
                         if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
-                            let event =
-                                DhcpEventV6::failed(inner_msg, &msg, relay_addr, reason.as_str());
-                            sinks.send(DhcpEvent::V6(event));
+                            let event = DhcpEventV6::failed(inner_msg, &msg, relay_addr, reason);
+                            sinks.send(DhcpEvent::V6(event.with_timing(&timing)));
                         }
                     }
                     DhcpV6Response::Message(resp) => {
+                        let config = config.load();
+                        let conflict_holder = lease_conflict(&leases, &config, inner_msg, &resp);
+                        if conflict_holder.is_some() && config.refuse_lease_conflicts {
+                            record("LeaseConflict", resp.reservation_match.as_ref());
+                            if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
+                                let mut event = DhcpEventV6::send_failed(
+                                    inner_msg,
+                                    &msg,
+                                    relay_addr,
+                                    resp.reservation.as_deref(),
+                                    resp.reservation_match.as_ref(),
+                                    "LeaseConflict",
+                                );
+                                event.lease_conflict_duid =
+                                    conflict_holder.as_ref().map(Duid::to_string);
+                                sinks.send(DhcpEvent::V6(event.with_timing(&timing)));
+                            }
+                            continue;
+                        }
                         // Capture before resp.message moves into the relay wrapper.
                         let reply_type = resp.message.msg_type();
-                        // wrap the message in a RelayRepl
-                        let mut relay_reply_opts = DhcpOptions::new();
-                        relay_reply_opts.insert(DhcpOption::RelayMsg(
-                            v6::RelayMessageData::Message(resp.message),
-                        ));
-
-                        // reply with InterfaceId if it was included in the original RelayForw message
-                        if let Some(interface_id) = msg
-                            .opts
-                            .iter()
-                            .find(|opt| matches!(opt, v6::DhcpOption::InterfaceId(_)))
-                        {
-                            relay_reply_opts.insert(interface_id.clone());
-                        }
-
-                        let relay_msg = RelayMessage {
-                            msg_type: v6::MessageType::RelayRepl,
-                            hop_count: msg.hop_count(),
-                            link_addr: msg.link_addr(),
-                            peer_addr: msg.peer_addr(),
-                            opts: relay_reply_opts,
-                        };
-
-                        let write_buf = match relay_msg.to_vec() {
-                            Ok(buf) => buf,
+                        let audit_record = audit_channel.as_ref().and_then(|_| {
+                            AuditRecord::v6(
+                                inner_msg,
+                                &msg,
+                                &resp.message,
+                                src.ip(),
+                                resp.reservation.as_deref(),
+                            )
+                        });
+                        let pd_update = pd_routes_channel.as_ref().and_then(|_| {
+                            PdUpdate::from_reply(
+                                inner_msg,
+                                &resp.message,
+                                src.ip(),
+                                resp.reservation.as_deref(),
+                            )
+                        });
+                        let encoded = encode_reply(&msg, resp.message, direct, src, &mut write_buf);
+                        let dest = match encoded {
+                            Ok(dest) => dest,
                             Err(e) => {
                                 error!("Failed to encode DHCPv6 response: {e}");
+                                record("EncodeFailed", resp.reservation_match.as_ref());
                                 if let (Some(sinks), Some(relay_addr)) =
                                     (&event_channel, relay_addr)
                                 {
@@ -190,17 +348,42 @@ pub fn v6_worker(
                                         &msg,
                                         relay_addr,
                                         resp.reservation.as_deref(),
-                                        resp.reservation_match,
+                                        resp.reservation_match.as_ref(),
                                         "EncodeFailed",
                                     );
-                                    sinks.send(DhcpEvent::V6(event));
+                                    sinks.send(DhcpEvent::V6(event.with_timing(&timing)));
                                 }
                                 continue;
                             }
                         };
-                        match socket.send_to(&write_buf, src) {
+                        if write_buf.len() > MAX_UNFRAGMENTED_REPLY {
+                            warn!(
+                                len = write_buf.len(),
+                                "DHCPv6 reply exceeds the IPv6 minimum MTU and will be fragmented"
+                            );
+                        }
+                        match crate::reply_source::send(
+                            &socket,
+                            reply_source.as_ref(),
+                            &write_buf,
+                            dest,
+                        ) {
                             Ok(sent) => {
-                                debug!("responded to {src} with {sent} bytes");
+                                debug!("responded to {dest} with {sent} bytes");
+                                capture.record(capture_mac, Direction::Tx, dest, &write_buf);
+                                if let (Some(cache), Some(key)) = (retransmits.as_mut(), cache_key)
+                                {
+                                    cache.insert(key, &write_buf, dest);
+                                }
+                                audit::record(&audit_channel, audit_record);
+                                pd_routes::send(&pd_routes_channel, pd_update);
+                                record(
+                                    DhcpEventV6::message_type_str(reply_type),
+                                    resp.reservation_match.as_ref(),
+                                );
+                                if let Some(found) = resp.reservation_match.as_ref() {
+                                    health.v6.record_match(found);
+                                }
                                 if tracing::enabled!(tracing::Level::INFO) {
                                     let duid = inner_msg
                                         .client_id()
@@ -208,17 +391,48 @@ pub fn v6_worker(
                                         .map(|d| d.to_string());
                                     let mac = msg.hw_addr().map(|m| m.to_string());
                                     match resp.reservation.as_deref() {
+                                        _ if matches!(
+                                            inner_msg.msg_type(),
+                                            v6::MessageType::Release | v6::MessageType::Decline
+                                        ) =>
+                                        {
+                                            info!(
+                                                message_type = ?inner_msg.msg_type(),
+                                                mac = mac.as_deref(),
+                                                duid = duid.as_deref(),
+                                                reserved = resp.reservation.is_some(),
+                                                relay = %src,
+                                                xid = ?inner_msg.xid(),
+                                                "DHCPv6 Release/Decline acknowledged"
+                                            )
+                                        }
+                                        Some(reservation) if reservation.suspended => info!(
+                                            message_type = ?reply_type,
+                                            mac = mac.as_deref(),
+                                            duid = duid.as_deref(),
+                                            na = ?reservation.ipv6_na,
+                                            relay = %src,
+                                            xid = ?inner_msg.xid(),
+                                            "DHCPv6 reply sent without leases — reservation suspended"
+                                        ),
                                         Some(reservation) => info!(
                                             message_type = ?reply_type,
                                             mac = mac.as_deref(),
                                             duid = duid.as_deref(),
-                                            na = %reservation.ipv6_na,
+                                            na = ?reservation.ipv6_na,
                                             pd = %reservation.ipv6_pd,
-                                            method = resp.reservation_match.map(|m| m.method),
+                                            method = resp.reservation_match.as_ref().map(|m| m.method),
                                             relay = %src,
                                             xid = ?inner_msg.xid(),
                                             "DHCPv6 lease granted"
                                         ),
+                                        None if reply_type == v6::MessageType::Advertise => info!(
+                                            mac = mac.as_deref(),
+                                            duid = duid.as_deref(),
+                                            relay = %src,
+                                            xid = ?inner_msg.xid(),
+                                            "DHCPv6 Advertise sent with NoAddrsAvail — no reservation found"
+                                        ),
                                         None => info!(
                                             mac = mac.as_deref(),
                                             duid = duid.as_deref(),
@@ -231,18 +445,21 @@ pub fn v6_worker(
                                 if let (Some(sinks), Some(relay_addr)) =
                                     (&event_channel, relay_addr)
                                 {
-                                    let event = DhcpEventV6::success(
+                                    let event = reply_event(
                                         inner_msg,
                                         &msg,
                                         relay_addr,
+                                        reply_type,
                                         resp.reservation.as_deref(),
-                                        resp.reservation_match,
+                                        resp.reservation_match.as_ref(),
+                                        conflict_holder.as_ref(),
                                     );
-                                    sinks.send(DhcpEvent::V6(event));
+                                    sinks.send(DhcpEvent::V6(event.with_timing(&timing)));
                                 }
                             }
                             Err(e) => {
                                 error!("Problem sending response message: {e}");
+                                record("SendFailed", resp.reservation_match.as_ref());
                                 if let (Some(sinks), Some(relay_addr)) =
                                     (&event_channel, relay_addr)
                                 {
@@ -251,10 +468,10 @@ pub fn v6_worker(
                                         &msg,
                                         relay_addr,
                                         resp.reservation.as_deref(),
-                                        resp.reservation_match,
+                                        resp.reservation_match.as_ref(),
                                         "SendFailed",
                                     );
-                                    sinks.send(DhcpEvent::V6(event));
+                                    sinks.send(DhcpEvent::V6(event.with_timing(&timing)));
                                 }
                             }
                         }
@@ -264,13 +481,164 @@ pub fn v6_worker(
             Err(e) => {
                 error!("Unable to parse dhcp message {}", e);
                 if let (Some(sinks), Some(relay_addr)) = (&event_channel, relay_addr) {
-                    sinks.send(DhcpEvent::V6(DhcpEventV6::parse_error(relay_addr)));
+                    let event = DhcpEventV6::parse_error(relay_addr);
+                    sinks.send(DhcpEvent::V6(event.with_timing(&timing)));
                 }
             }
         };
     }
 }
 
+/// Decode a datagram from `src`. When `unicast` is allowed, anything but a
+/// Relay-forward is a client sending straight to the server; it's wrapped
+/// in a Relay-forward from `src` and the returned flag is set. Only
+/// messages that came through a relay are added to `relays`.
+pub(crate) fn decode(
+    packet: &[u8],
+    src: SocketAddr,
+    unicast: bool,
+    relays: &RelayInventory,
+) -> (bool, Result<v6::RelayMessage, DecodeError>) {
+    let direct = unicast
+        && packet
+            .first()
+            .is_some_and(|t| *t != u8::from(v6::MessageType::RelayForw));
+    let parsed = if direct {
+        let peer = match src.ip() {
+            IpAddr::V6(ip) => ip,
+            IpAddr::V4(_) => Ipv6Addr::UNSPECIFIED,
+        };
+        v6::Message::from_bytes(packet).map(|m| unicast_relay_forw(m, peer))
+    } else {
+        v6::RelayMessage::from_bytes(packet)
+    };
+    if let Ok(msg) = &parsed {
+        trace!("Received from {src}:\n{}", V6RelayDump(msg));
+        if !direct {
+            relays.record_v6(msg, src.ip());
+        }
+    }
+    (direct, parsed)
+}
+
+/// The client message a Relay-forward carries, or the failure reason when
+/// it carries another relay's message or none at all
+pub(crate) fn relayed_message(relay_msg: &v6::RelayMessage) -> Result<&v6::Message, &'static str> {
+    match relay_msg.opts().iter().find_map(|opt| match opt {
+        DhcpOption::RelayMsg(msg) => Some(msg),
+        _ => None,
+    }) {
+        Some(v6::RelayMessageData::Message(msg)) => Ok(msg),
+        Some(v6::RelayMessageData::Relay(_)) => Err("NestedRelay"),
+        None => Err("NoRelayMsg"),
+    }
+}
+
+/// Encode `reply` into `buf`; returns where to send it. A client that
+/// unicast is answered directly, anything else through its relay.
+pub(crate) fn encode_reply(
+    relay_msg: &v6::RelayMessage,
+    reply: v6::Message,
+    direct: bool,
+    src: SocketAddr,
+    buf: &mut Vec<u8>,
+) -> Result<SocketAddr, EncodeError> {
+    if direct {
+        trace!("Reply:\n{}", V6Dump(&reply));
+        crate::encode_into(&reply, buf)?;
+        return Ok(src);
+    }
+    let reply = relay_reply(relay_msg, reply);
+    trace!("Reply:\n{}", V6RelayDump(&reply));
+    crate::encode_into(&reply, buf)?;
+    Ok(reply_destination(relay_msg, src))
+}
+
+/// The analytics event for a reply of `reply_type` that was sent
+pub(crate) fn reply_event(
+    msg: &v6::Message,
+    relay_msg: &v6::RelayMessage,
+    relay_addr: Ipv6Addr,
+    reply_type: v6::MessageType,
+    reservation: Option<&Reservation>,
+    found: Option<&ReservationMatch>,
+    conflict_holder: Option<&Duid>,
+) -> DhcpEventV6 {
+    // An Advertise without a reservation is the unknown-client answer;
+    // report it like the silent drop so dashboards count it the same.
+    if reservation.is_none() && reply_type == v6::MessageType::Advertise {
+        return DhcpEventV6::failed(msg, relay_msg, relay_addr, NoResponse::NoReservation);
+    }
+    let mut event = DhcpEventV6::success(msg, relay_msg, relay_addr, reservation, found);
+    event.lease_conflict_duid = conflict_holder.map(Duid::to_string);
+    event
+}
+
+/// Retransmissions carry the same transaction id and client DUID. Only
+/// Solicits are answered from the cache; other messages are handled again
+/// each time.
+fn retransmit_key(msg: &v6::Message) -> Option<([u8; 3], Duid)> {
+    if msg.msg_type() != v6::MessageType::Solicit {
+        return None;
+    }
+    Some((msg.xid(), Duid::new(msg.client_id()?.to_vec())?))
+}
+
+/// Count the outcome and keep the transaction in the history of the client
+/// with this DUID
+#[allow(clippy::too_many_arguments)]
+fn record_history(
+    health: &ProtocolHealth,
+    leases: &Opt82Cache,
+    depth: usize,
+    msg: &v6::Message,
+    relay_msg: &v6::RelayMessage,
+    src: SocketAddr,
+    outcome: &'static str,
+    found: Option<&ReservationMatch>,
+) {
+    health.record_outcome(outcome);
+    if depth == 0 {
+        return;
+    }
+    if let Some(duid) = msg.client_id().and_then(|b| Duid::new(b.to_vec())) {
+        let transaction = Transaction::v6(msg, relay_msg, src.ip(), outcome, found);
+        leases.history().record_v6(depth, &duid, transaction);
+    }
+}
+
+/// Check a Reply that leases a reservation against the client last leased
+/// its address. Returns that client's DUID if it differs and its lease
+/// hasn't run out yet.
+pub(crate) fn lease_conflict(
+    leases: &Opt82Cache,
+    config: &Config,
+    msg: &v6::Message,
+    resp: &ResponseMessage,
+) -> Option<Duid> {
+    let lease = resp.lease?;
+    let reservation = resp.reservation.as_deref()?;
+    // Conflicts are tracked by address, which prefix-only reservations lack
+    let na = reservation.ipv6_na?;
+    let duid = msg.client_id().and_then(|b| Duid::new(b.to_vec()))?;
+    let holder = leases.claim_ipv6(
+        na,
+        &duid,
+        reservation.subscriber_id.as_ref(),
+        lease,
+        !config.refuse_lease_conflicts,
+    )?;
+    warn!(
+        %duid,
+        %holder,
+        %na,
+        xid = ?msg.xid(),
+        refused = config.refuse_lease_conflicts,
+        "DHCPv6: reserved address claimed by a second client before the holder's lease expired"
+    );
+    Some(holder)
+}
+
 fn hex_for_text2pcap(bytes: &[u8]) -> String {
     let mut s = String::new();
     s.push_str("0000 ");
@@ -0,0 +1,123 @@
+//! Just enough of RFC 6455 for the management listener to push events to a
+//! browser: the opening handshake, unfragmented frames out, and the small
+//! control frames a client sends in.
+
+use std::io::{self, Read, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+pub const TEXT: u8 = 0x1;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xa;
+
+/// Appended to the client's key before hashing, RFC 6455 section 1.3
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Clients only send control frames here, which RFC 6455 caps at 125
+/// bytes; anything much larger isn't a client we know how to talk to
+const MAX_CLIENT_FRAME: u64 = 4096;
+
+pub struct Frame {
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// The `101 Switching Protocols` answer to a request carrying
+/// `Sec-WebSocket-Key: key`
+pub fn handshake_response(key: &str) -> String {
+    format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )
+}
+
+fn accept_key(key: &str) -> String {
+    let digest = sha1_smol::Sha1::from(format!("{}{GUID}", key.trim())).digest();
+    STANDARD.encode(digest.bytes())
+}
+
+/// Write one unmasked frame, as servers must
+pub fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+/// Read one frame, unmasking its payload
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Frame> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head)?;
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0u8; 2];
+            reader.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    if len > MAX_CLIENT_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{len} byte WebSocket frame"),
+        ));
+    }
+    let mut mask = [0u8; 4];
+    if head[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Frame {
+        opcode: head[0] & 0x0f,
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_and_frames() {
+        // The example in RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let mut out = Vec::new();
+        write_frame(&mut out, TEXT, b"Hello").unwrap();
+        assert_eq!(out, b"\x81\x05Hello");
+        out.clear();
+        write_frame(&mut out, TEXT, &[b'a'; 300]).unwrap();
+        assert_eq!(out[..4], [0x81, 126, 0x01, 0x2c]);
+
+        // A masked "Hello" from a client, RFC 6455 section 5.7
+        let masked = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let frame = read_frame(&mut &masked[..]).unwrap();
+        assert_eq!(frame.opcode, TEXT);
+        assert_eq!(frame.payload, b"Hello");
+    }
+}
@@ -0,0 +1,514 @@
+//! RFC conformance matrix for the DHCPv4 and DHCPv6 handlers.
+//!
+//! Each case crafts a message, runs it through [`handle_v4_message`] or
+//! [`handle_v6_message`] exactly as a worker would, and checks the answer
+//! against RFC 2131 Table 3 or RFC 8415 §16 and §18. Every case runs even
+//! when an earlier one fails, a conformance report is printed, and the test
+//! fails if any case did, so it can gate a release:
+//!
+//! ```sh
+//! cargo test --features compliance --test compliance -- --nocapture
+//! ```
+
+#![cfg(feature = "compliance")]
+
+use std::net::Ipv4Addr;
+
+use advmac::MacAddr6;
+use shadowdhcp::dhcproto::{v4, v6};
+use shadowdhcp::testutil;
+use shadowdhcp::types::{Duid, V4Subnet};
+use shadowdhcp::{
+    handle_v4_message, handle_v6_message, Config, DhcpV4Response, DhcpV6Response, NoResponse,
+    Opt82Cache, ReservationDb,
+};
+
+const SERVER_ID_V4: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const RELAY_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 254);
+const RESERVED_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 100);
+const CLIENT_MAC: MacAddr6 = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+const SERVER_DUID: [u8; 4] = [0, 1, 2, 3];
+
+struct Env {
+    config: Config,
+    reservations: ReservationDb,
+}
+
+fn env() -> Env {
+    let config = Config {
+        v4_server_id: SERVER_ID_V4,
+        subnets_v4: vec![V4Subnet {
+            net: "192.0.2.0/24".parse().unwrap(),
+            gateway: Ipv4Addr::new(192, 0, 2, 1),
+            reply_prefix_len: None,
+            authoritative: false,
+            ranges: vec![],
+        }]
+        .into(),
+        v6_server_id: Duid::from(SERVER_DUID.to_vec()),
+        ..Default::default()
+    };
+    let reservations = ReservationDb::new();
+    reservations.insert(
+        serde_json::from_str(
+            r#"{"ipv4": "192.0.2.100", "ipv6_na": "2001:db8::100", "ipv6_pd": "2001:db8:100::/56",
+                "mac": "00-11-22-33-44-55", "duid": "00:03:00:01:00:11:22:33:44:55"}"#,
+        )
+        .unwrap(),
+    );
+    Env {
+        config,
+        reservations,
+    }
+}
+
+struct Case {
+    rfc: &'static str,
+    name: &'static str,
+    check: fn(&Env) -> Result<(), String>,
+}
+
+/// Fail the case with `msg` unless `cond` holds
+fn ensure(cond: bool, msg: impl Into<String>) -> Result<(), String> {
+    if cond {
+        Ok(())
+    } else {
+        Err(msg.into())
+    }
+}
+
+// ============================================================================
+// DHCPv4
+// ============================================================================
+
+fn v4_answer(env: &Env, msg: &v4::Message) -> Result<v4::Message, String> {
+    match handle_v4_message(&env.config, &env.reservations, &Opt82Cache::new(), msg) {
+        DhcpV4Response::Message(resp) => Ok(resp.message),
+        DhcpV4Response::NoResponse(reason) => Err(format!("no response: {reason:?}")),
+    }
+}
+
+fn v4_ignored(env: &Env, msg: &v4::Message) -> Result<(), String> {
+    match handle_v4_message(&env.config, &env.reservations, &Opt82Cache::new(), msg) {
+        DhcpV4Response::Message(resp) => Err(format!(
+            "answered with {:?}, should be ignored",
+            resp.message.message_type()
+        )),
+        DhcpV4Response::NoResponse(_) => Ok(()),
+    }
+}
+
+/// Fields RFC 2131 Table 3 requires in every reply to be copied from the
+/// request
+fn v4_header_echoed(request: &v4::Message, reply: &v4::Message) -> Result<(), String> {
+    ensure(
+        reply.opcode() == v4::Opcode::BootReply,
+        "op must be BOOTREPLY",
+    )?;
+    ensure(reply.xid() == request.xid(), "xid must be echoed")?;
+    ensure(reply.giaddr() == request.giaddr(), "giaddr must be echoed")?;
+    ensure(
+        reply.chaddr()[..6] == request.chaddr()[..6],
+        "chaddr must be echoed",
+    )
+}
+
+fn v4_expect_type(reply: &v4::Message, expected: v4::MessageType) -> Result<(), String> {
+    ensure(
+        reply.message_type() == Some(&expected),
+        format!("expected {expected:?}, got {:?}", reply.message_type()),
+    )
+}
+
+fn v4_has(reply: &v4::Message, code: v4::OptionCode) -> bool {
+    reply.opts().get(code).is_some()
+}
+
+fn v4_offer(env: &Env) -> Result<(), String> {
+    let msg = testutil::discover(CLIENT_MAC, 0x0000_0001, RELAY_V4);
+    let reply = v4_answer(env, &msg)?;
+    v4_expect_type(&reply, v4::MessageType::Offer)?;
+    v4_header_echoed(&msg, &reply)?;
+    ensure(
+        reply.yiaddr() == RESERVED_V4,
+        "yiaddr must be the offered address",
+    )?;
+    ensure(reply.ciaddr().is_unspecified(), "ciaddr must be 0")?;
+    ensure(
+        v4_has(&reply, v4::OptionCode::ServerIdentifier),
+        "Server Identifier (54) is required",
+    )?;
+    ensure(
+        v4_has(&reply, v4::OptionCode::AddressLeaseTime),
+        "Lease Time (51) is required",
+    )?;
+    ensure(
+        !v4_has(&reply, v4::OptionCode::RequestedIpAddress),
+        "Requested IP Address (50) must not be sent",
+    )?;
+    ensure(
+        !v4_has(&reply, v4::OptionCode::ParameterRequestList),
+        "Parameter Request List (55) must not be sent",
+    )
+}
+
+fn v4_selecting(server_id: Ipv4Addr, requested: Ipv4Addr) -> v4::Message {
+    testutil::request_selecting(CLIENT_MAC, 0x0000_0002, RELAY_V4, server_id, requested)
+}
+
+fn v4_ack(env: &Env) -> Result<(), String> {
+    let msg = v4_selecting(SERVER_ID_V4, RESERVED_V4);
+    let reply = v4_answer(env, &msg)?;
+    v4_expect_type(&reply, v4::MessageType::Ack)?;
+    v4_header_echoed(&msg, &reply)?;
+    ensure(
+        reply.yiaddr() == RESERVED_V4,
+        "yiaddr must be the leased address",
+    )?;
+    ensure(
+        v4_has(&reply, v4::OptionCode::ServerIdentifier),
+        "Server Identifier (54) is required",
+    )?;
+    ensure(
+        v4_has(&reply, v4::OptionCode::AddressLeaseTime),
+        "Lease Time (51) is required",
+    )?;
+    ensure(
+        v4_has(&reply, v4::OptionCode::Renewal) && v4_has(&reply, v4::OptionCode::Rebinding),
+        "T1 (58) and T2 (59) should be sent",
+    )
+}
+
+fn v4_other_server(env: &Env) -> Result<(), String> {
+    v4_ignored(env, &v4_selecting(Ipv4Addr::new(10, 0, 0, 99), RESERVED_V4))
+}
+
+fn v4_nak(env: &Env) -> Result<(), String> {
+    let msg = v4_selecting(SERVER_ID_V4, Ipv4Addr::new(192, 0, 2, 99));
+    let reply = v4_answer(env, &msg)?;
+    v4_expect_type(&reply, v4::MessageType::Nak)?;
+    v4_header_echoed(&msg, &reply)?;
+    ensure(reply.yiaddr().is_unspecified(), "yiaddr must be 0")?;
+    ensure(reply.ciaddr().is_unspecified(), "ciaddr must be 0")?;
+    ensure(
+        v4_has(&reply, v4::OptionCode::ServerIdentifier),
+        "Server Identifier (54) is required",
+    )?;
+    ensure(
+        !v4_has(&reply, v4::OptionCode::AddressLeaseTime),
+        "Lease Time (51) must not be sent",
+    )
+}
+
+fn v4_init_reboot(env: &Env) -> Result<(), String> {
+    let msg = testutil::request_init_reboot(CLIENT_MAC, 0x0000_0003, RELAY_V4, RESERVED_V4);
+    let reply = v4_answer(env, &msg)?;
+    v4_expect_type(&reply, v4::MessageType::Ack)?;
+    ensure(
+        reply.yiaddr() == RESERVED_V4,
+        "yiaddr must be the leased address",
+    )
+}
+
+fn v4_rebinding(env: &Env) -> Result<(), String> {
+    let msg = testutil::request_rebinding(CLIENT_MAC, 0x0000_0004, RESERVED_V4, RELAY_V4);
+    let reply = v4_answer(env, &msg)?;
+    v4_expect_type(&reply, v4::MessageType::Ack)?;
+    ensure(
+        reply.yiaddr() == RESERVED_V4,
+        "yiaddr must be the leased address",
+    )
+}
+
+// ============================================================================
+// DHCPv6
+// ============================================================================
+
+fn v6_client(msg_type: v6::MessageType) -> v6::Message {
+    let mut msg = v6::Message::new(msg_type);
+    let opts = msg.opts_mut();
+    opts.insert(v6::DhcpOption::ClientId(testutil::duid_ll(CLIENT_MAC)));
+    opts.insert(testutil::empty_ia_na());
+    opts.insert(testutil::empty_ia_pd());
+    msg
+}
+
+fn v6_with_server_id(mut msg: v6::Message, server_id: &[u8]) -> v6::Message {
+    msg.opts_mut()
+        .insert(v6::DhcpOption::ServerId(server_id.to_vec()));
+    msg
+}
+
+fn v6_handle(env: &Env, msg: &v6::Message) -> DhcpV6Response {
+    let relay_msg = testutil::relay_forw(
+        msg,
+        "2001:db8::1".parse().unwrap(),
+        "fe80::1".parse().unwrap(),
+    );
+    handle_v6_message(
+        &env.config,
+        &env.reservations,
+        &Opt82Cache::new(),
+        msg,
+        &relay_msg,
+    )
+}
+
+fn v6_answer(env: &Env, msg: &v6::Message) -> Result<v6::Message, String> {
+    match v6_handle(env, msg) {
+        DhcpV6Response::Message(resp) => Ok(resp.message),
+        DhcpV6Response::NoResponse(reason) => Err(format!("no response: {reason:?}")),
+    }
+}
+
+fn v6_discarded(env: &Env, msg: &v6::Message, expected: NoResponse) -> Result<(), String> {
+    match v6_handle(env, msg) {
+        DhcpV6Response::Message(resp) => Err(format!(
+            "answered with {:?}, should be discarded",
+            resp.message.msg_type()
+        )),
+        DhcpV6Response::NoResponse(reason) => ensure(
+            reason == expected,
+            format!("discarded as {reason:?}, expected {expected:?}"),
+        ),
+    }
+}
+
+/// Checks every RFC 8415 §18.3 reply to a client carrying IA_NA and IA_PD
+/// must pass: matching transaction id, both identifiers, both IAs with
+/// T1 <= T2 and preferred <= valid lifetimes
+fn v6_reply_valid(
+    request: &v6::Message,
+    reply: &v6::Message,
+    expected: v6::MessageType,
+) -> Result<(), String> {
+    ensure(
+        reply.msg_type() == expected,
+        format!("expected {expected:?}, got {:?}", reply.msg_type()),
+    )?;
+    ensure(
+        reply.xid() == request.xid(),
+        "transaction-id must be echoed",
+    )?;
+
+    let mut client_id = None;
+    let mut server_id = None;
+    let mut ia_na = None;
+    let mut ia_pd = None;
+    for opt in reply.opts().iter() {
+        match opt {
+            v6::DhcpOption::ClientId(id) => client_id = Some(id),
+            v6::DhcpOption::ServerId(id) => server_id = Some(id),
+            v6::DhcpOption::IANA(ia) => ia_na = Some(ia),
+            v6::DhcpOption::IAPD(ia) => ia_pd = Some(ia),
+            _ => (),
+        }
+    }
+    ensure(
+        client_id.is_some_and(|id| *id == testutil::duid_ll(CLIENT_MAC)),
+        "Client Identifier must be echoed",
+    )?;
+    ensure(
+        server_id.is_some_and(|id| id[..] == SERVER_DUID),
+        "Server Identifier must be this server's DUID",
+    )?;
+
+    let ia_na = ia_na.ok_or("IA_NA missing")?;
+    ensure(ia_na.id == 1, "IA_NA IAID must be echoed")?;
+    ensure(ia_na.t1 <= ia_na.t2, "IA_NA T1 must not exceed T2")?;
+    for opt in ia_na.opts.iter() {
+        if let v6::DhcpOption::IAAddr(addr) = opt {
+            ensure(
+                addr.preferred_life <= addr.valid_life,
+                "IA Address preferred lifetime must not exceed valid",
+            )?;
+        }
+    }
+
+    let ia_pd = ia_pd.ok_or("IA_PD missing")?;
+    ensure(ia_pd.id == 1, "IA_PD IAID must be echoed")?;
+    ensure(ia_pd.t1 <= ia_pd.t2, "IA_PD T1 must not exceed T2")?;
+    for opt in ia_pd.opts.iter() {
+        if let v6::DhcpOption::IAPrefix(prefix) = opt {
+            ensure(
+                prefix.preferred_lifetime <= prefix.valid_lifetime,
+                "IA Prefix preferred lifetime must not exceed valid",
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn v6_advertise(env: &Env) -> Result<(), String> {
+    let msg = v6_client(v6::MessageType::Solicit);
+    let reply = v6_answer(env, &msg)?;
+    v6_reply_valid(&msg, &reply, v6::MessageType::Advertise)
+}
+
+fn v6_rapid_commit(env: &Env) -> Result<(), String> {
+    let mut msg = v6_client(v6::MessageType::Solicit);
+    msg.opts_mut().insert(v6::DhcpOption::RapidCommit);
+    let reply = v6_answer(env, &msg)?;
+    v6_reply_valid(&msg, &reply, v6::MessageType::Reply)?;
+    ensure(
+        reply
+            .opts()
+            .iter()
+            .any(|opt| matches!(opt, v6::DhcpOption::RapidCommit)),
+        "Rapid Commit must be included in the Reply",
+    )
+}
+
+fn v6_solicit_with_server_id(env: &Env) -> Result<(), String> {
+    let msg = v6_with_server_id(v6_client(v6::MessageType::Solicit), &SERVER_DUID);
+    v6_discarded(env, &msg, NoResponse::UnexpectedServerId)
+}
+
+fn v6_no_client_id(env: &Env) -> Result<(), String> {
+    let msg = v6::Message::new(v6::MessageType::Solicit);
+    v6_discarded(env, &msg, NoResponse::NoClientId)
+}
+
+fn v6_request(env: &Env) -> Result<(), String> {
+    let msg = v6_with_server_id(v6_client(v6::MessageType::Request), &SERVER_DUID);
+    let reply = v6_answer(env, &msg)?;
+    v6_reply_valid(&msg, &reply, v6::MessageType::Reply)
+}
+
+fn v6_request_without_server_id(env: &Env) -> Result<(), String> {
+    let msg = v6_client(v6::MessageType::Request);
+    v6_discarded(env, &msg, NoResponse::NoServerId)
+}
+
+fn v6_request_other_server(env: &Env) -> Result<(), String> {
+    let msg = v6_with_server_id(v6_client(v6::MessageType::Request), &[9, 9, 9, 9]);
+    v6_discarded(env, &msg, NoResponse::WrongServerId)
+}
+
+fn v6_renew(env: &Env) -> Result<(), String> {
+    let msg = v6_with_server_id(v6_client(v6::MessageType::Renew), &SERVER_DUID);
+    let reply = v6_answer(env, &msg)?;
+    v6_reply_valid(&msg, &reply, v6::MessageType::Reply)
+}
+
+fn v6_renew_other_server(env: &Env) -> Result<(), String> {
+    let msg = v6_with_server_id(v6_client(v6::MessageType::Renew), &[9, 9, 9, 9]);
+    v6_discarded(env, &msg, NoResponse::WrongServerId)
+}
+
+fn v6_rebind(env: &Env) -> Result<(), String> {
+    let msg = v6_client(v6::MessageType::Rebind);
+    let reply = v6_answer(env, &msg)?;
+    v6_reply_valid(&msg, &reply, v6::MessageType::Reply)
+}
+
+fn v6_advertise_from_client(env: &Env) -> Result<(), String> {
+    let msg = v6_with_server_id(v6_client(v6::MessageType::Advertise), &SERVER_DUID);
+    v6_discarded(env, &msg, NoResponse::Discarded)
+}
+
+const CASES: &[Case] = &[
+    Case {
+        rfc: "RFC 2131 §4.3.1, Table 3",
+        name: "DHCPDISCOVER answered with a well-formed DHCPOFFER",
+        check: v4_offer,
+    },
+    Case {
+        rfc: "RFC 2131 §4.3.2, Table 3",
+        name: "SELECTING DHCPREQUEST answered with a well-formed DHCPACK",
+        check: v4_ack,
+    },
+    Case {
+        rfc: "RFC 2131 §4.3.2",
+        name: "SELECTING DHCPREQUEST for another server ignored",
+        check: v4_other_server,
+    },
+    Case {
+        rfc: "RFC 2131 §4.3.2, Table 3",
+        name: "DHCPREQUEST for the wrong address answered with DHCPNAK",
+        check: v4_nak,
+    },
+    Case {
+        rfc: "RFC 2131 §4.3.2",
+        name: "INIT-REBOOT DHCPREQUEST answered with DHCPACK",
+        check: v4_init_reboot,
+    },
+    Case {
+        rfc: "RFC 2131 §4.3.2, Table 3",
+        name: "REBINDING DHCPREQUEST answered with DHCPACK",
+        check: v4_rebinding,
+    },
+    Case {
+        rfc: "RFC 8415 §16.2, §18.3.1",
+        name: "Solicit answered with a well-formed Advertise",
+        check: v6_advertise,
+    },
+    Case {
+        rfc: "RFC 8415 §18.3.1",
+        name: "Solicit with Rapid Commit answered with Reply",
+        check: v6_rapid_commit,
+    },
+    Case {
+        rfc: "RFC 8415 §16.2",
+        name: "Solicit carrying a Server Identifier discarded",
+        check: v6_solicit_with_server_id,
+    },
+    Case {
+        rfc: "RFC 8415 §16.2",
+        name: "Solicit without a Client Identifier discarded",
+        check: v6_no_client_id,
+    },
+    Case {
+        rfc: "RFC 8415 §16.4, §18.3.2",
+        name: "Request answered with a well-formed Reply",
+        check: v6_request,
+    },
+    Case {
+        rfc: "RFC 8415 §16.4",
+        name: "Request without a Server Identifier discarded",
+        check: v6_request_without_server_id,
+    },
+    Case {
+        rfc: "RFC 8415 §16.4",
+        name: "Request for another server discarded",
+        check: v6_request_other_server,
+    },
+    Case {
+        rfc: "RFC 8415 §16.6, §18.3.4",
+        name: "Renew answered with a well-formed Reply",
+        check: v6_renew,
+    },
+    Case {
+        rfc: "RFC 8415 §16.6",
+        name: "Renew for another server discarded",
+        check: v6_renew_other_server,
+    },
+    Case {
+        rfc: "RFC 8415 §16.7, §18.3.5",
+        name: "Rebind without a Server Identifier answered with Reply",
+        check: v6_rebind,
+    },
+    Case {
+        rfc: "RFC 8415 §16.3",
+        name: "Advertise sent to the server discarded",
+        check: v6_advertise_from_client,
+    },
+];
+
+#[test]
+fn rfc_conformance() {
+    let env = env();
+    let mut failed = 0;
+    println!("\nshadowdhcp RFC conformance report");
+    for case in CASES {
+        match (case.check)(&env) {
+            Ok(()) => println!("  PASS  {:<26} {}", case.rfc, case.name),
+            Err(reason) => {
+                failed += 1;
+                println!("  FAIL  {:<26} {}: {reason}", case.rfc, case.name);
+            }
+        }
+    }
+    println!("{} of {} cases passed\n", CASES.len() - failed, CASES.len());
+    assert_eq!(failed, 0, "{failed} conformance case(s) failed");
+}
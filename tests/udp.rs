@@ -0,0 +1,504 @@
+//! The workers over real UDP sockets on loopback.
+//!
+//! The unit tests and the compliance matrix call the handlers directly, so
+//! they can't see what the workers do with the answer: where it is sent,
+//! from which socket, or whether a message left unanswered really stays
+//! unanswered. Each test here starts a worker on an ephemeral port, plays
+//! the relay from a socket of its own, and decodes what comes back.
+//!
+//! DHCPv4 replies and DHCPv6 replies to relays without a Relay Source Port
+//! go to the well known server port, which needs privileges to bind. Tests
+//! that need it are skipped, with a note, where it can't be bound.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use advmac::MacAddr6;
+use arc_swap::ArcSwap;
+use shadowdhcp::capture::PacketCapture;
+use shadowdhcp::config::ReservationProfile;
+use shadowdhcp::dhcproto::{v4, v6, Decodable, Encodable};
+use shadowdhcp::health::Health;
+use shadowdhcp::shutdown::Shutdown;
+use shadowdhcp::testutil;
+use shadowdhcp::types::{Duid, V4Subnet};
+use shadowdhcp::{Config, Opt82Cache, ReservationDb};
+
+const SERVER_ID_V4: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const RESERVED_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 100);
+const RESERVED_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x100);
+const CLIENT_MAC: MacAddr6 = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+/// DUID-LL of `CLIENT_MAC`
+const CLIENT_DUID: [u8; 10] = [0x00, 0x03, 0x00, 0x01, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+/// Reserved with the `oversized` profile, whose options don't fit a reply
+const OVERSIZED_MAC: MacAddr6 = MacAddr6::new([0x00, 0x11, 0x22, 0x33, 0x44, 0x66]);
+const OVERSIZED_DUID: [u8; 10] = [0x00, 0x03, 0x00, 0x01, 0x00, 0x11, 0x22, 0x33, 0x44, 0x66];
+const SERVER_DUID: [u8; 4] = [0, 1, 2, 3];
+/// Relay Source Port (RFC 8357)
+const OPTION_RELAY_PORT: u16 = 135;
+/// Long enough for a worker on a loaded CI machine
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Held by the tests that bind port 67 or 547, which run one at a time
+static SERVER_PORTS: Mutex<()> = Mutex::new(());
+
+fn server_ports() -> MutexGuard<'static, ()> {
+    SERVER_PORTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn config() -> Config {
+    Config {
+        v4_server_id: SERVER_ID_V4,
+        dns_v4: vec![Ipv4Addr::new(192, 0, 2, 53)],
+        subnets_v4: vec![V4Subnet {
+            net: "192.0.2.0/24".parse().unwrap(),
+            gateway: Ipv4Addr::new(192, 0, 2, 1),
+            reply_prefix_len: None,
+            authoritative: false,
+            ranges: vec![],
+        }]
+        .into(),
+        v6_server_id: Duid::from(SERVER_DUID.to_vec()),
+        dns_v6: vec!["2001:db8::53".parse().unwrap()],
+        reservation_profiles: [("oversized".to_string(), oversized_profile())].into(),
+        ..Default::default()
+    }
+}
+
+/// DNS server lists far past what fits: 1200 bytes of DHCPv4 option 6, and
+/// an option 23 longer than a DHCPv6 option length can describe
+fn oversized_profile() -> ReservationProfile {
+    ReservationProfile {
+        dns_v4: Some((0..300).map(|i| Ipv4Addr::from(0xc633_6400 + i)).collect()),
+        dns_v6: Some(
+            (0..5000)
+                .map(|i| Ipv6Addr::from((0x2001_0db8_0053_u128 << 80) | i))
+                .collect(),
+        ),
+        ..Default::default()
+    }
+}
+
+fn reservations() -> ReservationDb {
+    let reservations = ReservationDb::new();
+    reservations.insert(
+        serde_json::from_str(
+            r#"{"ipv4": "192.0.2.100", "ipv6_na": "2001:db8::100", "ipv6_pd": "2001:db8:100::/56",
+                "mac": "00-11-22-33-44-55", "duid": "00:03:00:01:00:11:22:33:44:55"}"#,
+        )
+        .unwrap(),
+    );
+    reservations.insert(
+        serde_json::from_str(
+            r#"{"ipv4": "192.0.2.101", "ipv6_na": "2001:db8::101", "ipv6_pd": "2001:db8:101::/56",
+                "mac": "00-11-22-33-44-66", "duid": "00:03:00:01:00:11:22:33:44:66",
+                "profile": "oversized"}"#,
+        )
+        .unwrap(),
+    );
+    reservations
+}
+
+/// A worker thread, stopped and joined on drop
+struct Server {
+    addr: SocketAddr,
+    shutdown: Shutdown,
+    worker: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Server {
+    fn start(bind: SocketAddr, config: Config, v6: bool) -> io::Result<Self> {
+        let socket = UdpSocket::bind(bind)?;
+        let addr = socket.local_addr()?;
+        let shutdown = Shutdown::new();
+        let (reservations, leases, config, capture, health, stop) = (
+            Arc::new(ArcSwap::from_pointee(reservations())),
+            Arc::new(Opt82Cache::new()),
+            Arc::new(ArcSwap::from_pointee(config)),
+            Arc::new(PacketCapture::new(64, &[])),
+            Arc::new(Health::default()),
+            shutdown.clone(),
+        );
+        let worker = thread::spawn(move || {
+            if v6 {
+                shadowdhcp::v6::v6_worker(
+                    socket,
+                    reservations,
+                    leases,
+                    config,
+                    None,
+                    None,
+                    None,
+                    capture,
+                    None,
+                    None,
+                    health,
+                    stop,
+                )
+            } else {
+                shadowdhcp::v4::v4_worker(
+                    socket,
+                    reservations,
+                    leases,
+                    config,
+                    None,
+                    None,
+                    capture,
+                    None,
+                    None,
+                    health,
+                    stop,
+                )
+            }
+        });
+        Ok(Self {
+            addr,
+            shutdown,
+            worker: Some(worker),
+        })
+    }
+
+    fn v4(config: Config) -> Self {
+        Self::start((Ipv4Addr::LOCALHOST, 0).into(), config, false).expect("bind DHCPv4 worker")
+    }
+
+    /// `None` where the host has no IPv6 loopback
+    fn v6(config: Config) -> Option<Self> {
+        match Self::start((Ipv6Addr::LOCALHOST, 0).into(), config, true) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                eprintln!("skipped: no IPv6 loopback: {e}");
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.shutdown.signal();
+        if let Some(worker) = self.worker.take() {
+            let result = worker.join();
+            if !thread::panicking() {
+                result.expect("worker panicked").expect("worker failed");
+            }
+        }
+    }
+}
+
+/// A socket standing in for the relay, or `None` with a note where `addr`
+/// can't be bound
+fn relay_socket(addr: SocketAddr) -> Option<UdpSocket> {
+    match UdpSocket::bind(addr) {
+        Ok(socket) => {
+            socket.set_read_timeout(Some(REPLY_TIMEOUT)).unwrap();
+            Some(socket)
+        }
+        Err(e) => {
+            eprintln!("skipped: can't bind the relay to {addr}: {e}");
+            None
+        }
+    }
+}
+
+/// The next datagram, and who sent it. `None` when nothing arrives in time.
+fn receive(socket: &UdpSocket) -> Option<(Vec<u8>, SocketAddr)> {
+    let mut buf = [0u8; 2048];
+    match socket.recv_from(&mut buf) {
+        Ok((amount, src)) => Some((buf[..amount].to_vec(), src)),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            None
+        }
+        Err(e) => panic!("recv failed: {e}"),
+    }
+}
+
+// ============================================================================
+// DHCPv4
+// ============================================================================
+
+/// RFC 2131 §4.1: a relayed message is answered to the relay's giaddr on
+/// the server port, whatever port it was sent from
+#[test]
+fn v4_discover_and_request_answered_to_giaddr_server_port() {
+    let _ports = server_ports();
+    let server = Server::v4(config());
+    let Some(relay) = relay_socket((Ipv4Addr::LOCALHOST, 67).into()) else {
+        return;
+    };
+    // Relays often send from an ephemeral port
+    let sender = relay_socket((Ipv4Addr::LOCALHOST, 0).into()).unwrap();
+
+    let discover = testutil::discover(CLIENT_MAC, 0x1001, Ipv4Addr::LOCALHOST);
+    sender
+        .send_to(&discover.to_vec().unwrap(), server.addr)
+        .unwrap();
+    let (bytes, from) = receive(&relay).expect("no OFFER");
+    assert_eq!(from, server.addr, "answered from the socket it was sent to");
+    let offer = v4::Message::from_bytes(&bytes).unwrap();
+    assert_eq!(offer.message_type(), Some(&v4::MessageType::Offer));
+    assert_eq!(offer.opcode(), v4::Opcode::BootReply);
+    assert_eq!(offer.xid(), 0x1001);
+    assert_eq!(offer.giaddr(), Ipv4Addr::LOCALHOST);
+    assert_eq!(offer.yiaddr(), RESERVED_V4);
+
+    let request = testutil::request_selecting(
+        CLIENT_MAC,
+        0x1002,
+        Ipv4Addr::LOCALHOST,
+        SERVER_ID_V4,
+        RESERVED_V4,
+    );
+    sender
+        .send_to(&request.to_vec().unwrap(), server.addr)
+        .unwrap();
+    let (bytes, _) = receive(&relay).expect("no ACK");
+    let ack = v4::Message::from_bytes(&bytes).unwrap();
+    assert_eq!(ack.message_type(), Some(&v4::MessageType::Ack));
+    assert_eq!(ack.xid(), 0x1002);
+    assert_eq!(ack.yiaddr(), RESERVED_V4);
+}
+
+/// Nothing goes out for a client without a reservation, or for a message
+/// that never passed a relay
+#[test]
+fn v4_unanswered_messages_send_nothing() {
+    let _ports = server_ports();
+    let server = Server::v4(config());
+    let Some(relay) = relay_socket((Ipv4Addr::LOCALHOST, 67).into()) else {
+        return;
+    };
+
+    let unknown = MacAddr6::new([0x02, 0, 0, 0, 0, 0x99]);
+    let discover = testutil::discover(unknown, 0x2001, Ipv4Addr::LOCALHOST);
+    relay
+        .send_to(&discover.to_vec().unwrap(), server.addr)
+        .unwrap();
+    let not_relayed = testutil::discover(CLIENT_MAC, 0x2002, Ipv4Addr::UNSPECIFIED);
+    relay
+        .send_to(&not_relayed.to_vec().unwrap(), server.addr)
+        .unwrap();
+    relay.send_to(b"not a DHCP message", server.addr).unwrap();
+
+    // The worker handles datagrams in order, so once this is answered the
+    // ones before it were dropped
+    let known = testutil::discover(CLIENT_MAC, 0x2003, Ipv4Addr::LOCALHOST);
+    relay
+        .send_to(&known.to_vec().unwrap(), server.addr)
+        .unwrap();
+    let (bytes, _) = receive(&relay).expect("no OFFER");
+    assert_eq!(v4::Message::from_bytes(&bytes).unwrap().xid(), 0x2003);
+}
+
+// ============================================================================
+// DHCPv6
+// ============================================================================
+
+/// `msg` in a Relay-forward with an Interface-ID, and Relay Source Port
+/// set to `port` if given
+fn relay_forw(msg: &v6::Message, port: Option<u16>) -> v6::RelayMessage {
+    let mut relay_msg = testutil::relay_forw(
+        msg,
+        "2001:db8::1".parse().unwrap(),
+        testutil::link_local_eui64(CLIENT_MAC),
+    );
+    let opts = &mut relay_msg.opts;
+    opts.insert(v6::DhcpOption::InterfaceId(b"ge-0/0/1.100".to_vec()));
+    if let Some(port) = port {
+        opts.insert(v6::DhcpOption::Unknown(v6::UnknownOption::new(
+            v6::OptionCode::from(OPTION_RELAY_PORT),
+            port.to_be_bytes().to_vec(),
+        )));
+    }
+    relay_msg
+}
+
+/// The client message inside a Relay-reply, after checking the relay's
+/// fields came back
+fn unwrap_relay_repl(bytes: &[u8], forwarded: &v6::RelayMessage) -> v6::Message {
+    let reply = v6::RelayMessage::from_bytes(bytes).unwrap();
+    assert_eq!(reply.msg_type(), v6::MessageType::RelayRepl);
+    assert_eq!(reply.link_addr(), forwarded.link_addr());
+    assert_eq!(reply.peer_addr(), forwarded.peer_addr());
+    assert!(reply
+        .opts()
+        .iter()
+        .any(|opt| matches!(opt, v6::DhcpOption::InterfaceId(id) if id == b"ge-0/0/1.100")));
+    reply
+        .opts()
+        .iter()
+        .find_map(|opt| match opt {
+            v6::DhcpOption::RelayMsg(v6::RelayMessageData::Message(msg)) => Some(msg.clone()),
+            _ => None,
+        })
+        .expect("Relay-reply carries no client message")
+}
+
+fn ia_na_address(msg: &v6::Message) -> Option<Ipv6Addr> {
+    msg.opts().iter().find_map(|opt| match opt {
+        v6::DhcpOption::IANA(ia) => ia.opts.iter().find_map(|opt| match opt {
+            v6::DhcpOption::IAAddr(addr) => Some(addr.addr),
+            _ => None,
+        }),
+        _ => None,
+    })
+}
+
+/// RFC 8357: a relay that sends from another port says so, and is
+/// answered on that port
+#[test]
+fn v6_solicit_answered_to_relay_source_port() {
+    let Some(server) = Server::v6(config()) else {
+        return;
+    };
+    let Some(relay) = relay_socket((Ipv6Addr::LOCALHOST, 0).into()) else {
+        return;
+    };
+    let port = relay.local_addr().unwrap().port();
+
+    let solicit = testutil::solicit(&CLIENT_DUID);
+    let forwarded = relay_forw(&solicit, Some(port));
+    relay
+        .send_to(&forwarded.to_vec().unwrap(), server.addr)
+        .unwrap();
+    let (bytes, from) = receive(&relay).expect("no Relay-reply");
+    assert_eq!(from, server.addr, "answered from the socket it was sent to");
+    let advertise = unwrap_relay_repl(&bytes, &forwarded);
+    assert_eq!(advertise.msg_type(), v6::MessageType::Advertise);
+    assert_eq!(advertise.xid(), solicit.xid());
+    assert_eq!(ia_na_address(&advertise), Some(RESERVED_V6));
+}
+
+/// Without Relay Source Port the relay is answered on the server port,
+/// not the port the Relay-forward came from
+#[test]
+fn v6_relay_without_source_port_answered_on_server_port() {
+    let _ports = server_ports();
+    let Some(server) = Server::v6(config()) else {
+        return;
+    };
+    let Some(listener) = relay_socket((Ipv6Addr::LOCALHOST, 547).into()) else {
+        return;
+    };
+    let sender = relay_socket((Ipv6Addr::LOCALHOST, 0).into()).unwrap();
+
+    let forwarded = relay_forw(&testutil::solicit(&CLIENT_DUID), None);
+    sender
+        .send_to(&forwarded.to_vec().unwrap(), server.addr)
+        .unwrap();
+    let (bytes, _) = receive(&listener).expect("no Relay-reply on the server port");
+    let advertise = unwrap_relay_repl(&bytes, &forwarded);
+    assert_eq!(ia_na_address(&advertise), Some(RESERVED_V6));
+    sender
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    assert!(
+        receive(&sender).is_none(),
+        "answered to the source port too"
+    );
+}
+
+/// A Solicit retransmitted within `retransmit_window_ms` gets the reply
+/// already sent, byte for byte, and an unknown client gets nothing
+#[test]
+fn v6_retransmission_resent_and_unknown_client_ignored() {
+    let config = Config {
+        retransmit_window_ms: 5000,
+        ..config()
+    };
+    let Some(server) = Server::v6(config) else {
+        return;
+    };
+    let Some(relay) = relay_socket((Ipv6Addr::LOCALHOST, 0).into()) else {
+        return;
+    };
+    let port = Some(relay.local_addr().unwrap().port());
+
+    let unknown = [0x00, 0x03, 0x00, 0x01, 0x02, 0, 0, 0, 0, 0x99];
+    let ignored = relay_forw(&testutil::solicit(&unknown), port);
+    relay
+        .send_to(&ignored.to_vec().unwrap(), server.addr)
+        .unwrap();
+
+    let forwarded = relay_forw(&testutil::solicit(&CLIENT_DUID), port)
+        .to_vec()
+        .unwrap();
+    relay.send_to(&forwarded, server.addr).unwrap();
+    let (first, _) = receive(&relay).expect("no Relay-reply");
+    relay.send_to(&forwarded, server.addr).unwrap();
+    let (second, _) = receive(&relay).expect("no Relay-reply to the retransmission");
+    assert_eq!(first, second);
+
+    relay
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    assert!(receive(&relay).is_none(), "the unknown client was answered");
+}
+
+/// A reply whose options can't all be sent is trimmed to the client's size
+/// or dropped, and the worker goes on to answer the next client
+#[test]
+fn v4_oversized_options_do_not_stop_the_worker() {
+    let _ports = server_ports();
+    let server = Server::v4(config());
+    let Some(relay) = relay_socket((Ipv4Addr::LOCALHOST, 67).into()) else {
+        return;
+    };
+
+    let oversized = testutil::discover(OVERSIZED_MAC, 0x3001, Ipv4Addr::LOCALHOST);
+    relay
+        .send_to(&oversized.to_vec().unwrap(), server.addr)
+        .unwrap();
+    let known = testutil::discover(CLIENT_MAC, 0x3002, Ipv4Addr::LOCALHOST);
+    relay
+        .send_to(&known.to_vec().unwrap(), server.addr)
+        .unwrap();
+
+    let (mut bytes, _) = receive(&relay).expect("no OFFER");
+    if v4::Message::from_bytes(&bytes).unwrap().xid() == 0x3001 {
+        // Without option 57 the client only has to take 576 bytes
+        assert!(bytes.len() <= 576 - 28, "{} byte OFFER", bytes.len());
+        bytes = receive(&relay).expect("no OFFER for the next client").0;
+    }
+    let offer = v4::Message::from_bytes(&bytes).unwrap();
+    assert_eq!(offer.xid(), 0x3002);
+    assert_eq!(offer.yiaddr(), RESERVED_V4);
+}
+
+/// A Reply too large to encode or send is dropped, and the worker goes on
+/// to answer the next client
+#[test]
+fn v6_oversized_options_dropped_and_next_client_answered() {
+    let Some(server) = Server::v6(config()) else {
+        return;
+    };
+    let Some(relay) = relay_socket((Ipv6Addr::LOCALHOST, 0).into()) else {
+        return;
+    };
+    let port = Some(relay.local_addr().unwrap().port());
+
+    let oversized = relay_forw(&testutil::solicit(&OVERSIZED_DUID), port);
+    relay
+        .send_to(&oversized.to_vec().unwrap(), server.addr)
+        .unwrap();
+    let solicit = testutil::solicit(&CLIENT_DUID);
+    let forwarded = relay_forw(&solicit, port);
+    relay
+        .send_to(&forwarded.to_vec().unwrap(), server.addr)
+        .unwrap();
+
+    // Answered in order, so the first reply is the oversized client's if
+    // it was sent at all
+    let (bytes, _) = receive(&relay).expect("no Relay-reply for the next client");
+    let advertise = unwrap_relay_repl(&bytes, &forwarded);
+    assert_eq!(advertise.xid(), solicit.xid());
+    assert_eq!(ia_na_address(&advertise), Some(RESERVED_V6));
+}